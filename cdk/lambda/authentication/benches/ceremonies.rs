@@ -0,0 +1,136 @@
+//! Benchmarks of the hot paths of the registration and authentication
+//! handlers.
+//!
+//! Measures challenge generation, serialization of ceremony states into
+//! session items, and verification of attestations and assertions produced
+//! by the software authenticator, so that performance regressions across
+//! upgrades of webauthn-rs are visible.
+//!
+//! ```sh
+//! cargo bench --features test-support --bench ceremonies
+//! ```
+
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use webauthn_rs::prelude::{PasskeyAuthentication, PasskeyRegistration};
+
+use authentication::authenticator::VirtualAuthenticator;
+use authentication::authenticator::testing::{
+    ORIGIN,
+    USER_HANDLE,
+    create_credential,
+    register,
+    webauthn,
+};
+use authentication::client_binding::ClientBinding;
+use authentication::items::{
+    RegistrationContents,
+    RegistrationSessionItem,
+    RegistrationUserInfo,
+    SessionKey,
+    StepUpSessionItem,
+};
+
+fn challenges(c: &mut Criterion) {
+    let webauthn = webauthn();
+    let passkey = register(&webauthn, &mut VirtualAuthenticator::new(ORIGIN));
+
+    let mut group = c.benchmark_group("challenge");
+    group.bench_function("registration", |b| b.iter(|| {
+        webauthn
+            .start_passkey_registration(USER_HANDLE, black_box("alice"), "Alice", None)
+            .unwrap()
+    }));
+    group.bench_function("authentication", |b| b.iter(|| {
+        webauthn.start_passkey_authentication(black_box(&[passkey.clone()])).unwrap()
+    }));
+    group.bench_function("discoverable", |b| b.iter(|| {
+        webauthn.start_discoverable_authentication().unwrap()
+    }));
+    group.finish();
+}
+
+fn states(c: &mut Criterion) {
+    let webauthn = webauthn();
+    let mut authenticator = VirtualAuthenticator::new(ORIGIN);
+    let (credential, registration) =
+        create_credential(&webauthn, &mut authenticator, USER_HANDLE, "alice", "Alice");
+    let passkey = webauthn.finish_passkey_registration(&credential, &registration).unwrap();
+    let (_, authentication) = webauthn.start_passkey_authentication(&[passkey]).unwrap();
+
+    let mut group = c.benchmark_group("state");
+    group.bench_function("registration", |b| b.iter(|| {
+        let item = RegistrationSessionItem {
+            ttl: 0,
+            user_id: "AAAA".into(),
+            authenticator_attachment: None,
+            client_binding: ClientBinding::default(),
+            contents: RegistrationContents::Plain {
+                user_info: RegistrationUserInfo {
+                    username: "alice".into(),
+                    display_name: "Alice".into(),
+                },
+                state: serde_json::to_string(black_box(&registration)).unwrap(),
+            },
+        };
+        let item = RegistrationSessionItem::from_item(
+            &item.into_item(SessionKey::Registration("AAAA")),
+        ).unwrap();
+        match item.contents {
+            RegistrationContents::Plain { state, .. } => {
+                serde_json::from_str::<PasskeyRegistration>(&state).unwrap()
+            }
+            RegistrationContents::Sealed { .. } => unreachable!(),
+        }
+    }));
+    group.bench_function("authentication", |b| b.iter(|| {
+        let item = StepUpSessionItem {
+            ttl: 0,
+            user_handle: "AAAA".into(),
+            state: serde_json::to_string(black_box(&authentication)).unwrap(),
+        };
+        let item = StepUpSessionItem::from_item(
+            &item.into_item(SessionKey::StepUp("AAAA")),
+        ).unwrap();
+        serde_json::from_str::<PasskeyAuthentication>(&item.state).unwrap()
+    }));
+    group.finish();
+}
+
+fn verification(c: &mut Criterion) {
+    let webauthn = webauthn();
+    let mut authenticator = VirtualAuthenticator::new(ORIGIN);
+    let (attestation, registration) =
+        create_credential(&webauthn, &mut authenticator, USER_HANDLE, "alice", "Alice");
+    let passkey = webauthn.finish_passkey_registration(&attestation, &registration).unwrap();
+    let (options, authentication) = webauthn
+        .start_passkey_authentication(&[passkey.clone()])
+        .unwrap();
+    let assertion = authenticator.get(&options).unwrap();
+    let (options, discoverable) = webauthn.start_discoverable_authentication().unwrap();
+    let discoverable_assertion = authenticator.get(&options).unwrap();
+
+    let mut group = c.benchmark_group("verification");
+    group.bench_function("attestation", |b| b.iter(|| {
+        webauthn
+            .finish_passkey_registration(black_box(&attestation), &registration)
+            .unwrap()
+    }));
+    group.bench_function("assertion", |b| b.iter(|| {
+        webauthn
+            .finish_passkey_authentication(black_box(&assertion), &authentication)
+            .unwrap()
+    }));
+    group.bench_function("discoverable_assertion", |b| b.iter(|| {
+        webauthn
+            .finish_discoverable_authentication(
+                black_box(&discoverable_assertion),
+                discoverable.clone(),
+                &[(&passkey).into()],
+            )
+            .unwrap()
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, challenges, states, verification);
+criterion_main!(benches);
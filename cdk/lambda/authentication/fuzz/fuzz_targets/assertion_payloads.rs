@@ -0,0 +1,14 @@
+//! Fuzzes the request bodies that carry assertions.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use authentication::payload::{DEFAULT_MAX_BODY_SIZE, parse_json_payload};
+use authentication::step_up::FinishStepUpSession;
+use authentication::token::FinishTokenSession;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_json_payload::<FinishTokenSession>(data, DEFAULT_MAX_BODY_SIZE);
+    let _ = parse_json_payload::<FinishStepUpSession>(data, DEFAULT_MAX_BODY_SIZE);
+});
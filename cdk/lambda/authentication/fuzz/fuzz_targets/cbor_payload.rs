@@ -0,0 +1,16 @@
+//! Fuzzes the transcoding of a CBOR request body into JSON.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use authentication::content::cbor_to_json;
+use authentication::payload::DEFAULT_MAX_BODY_SIZE;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(json) = cbor_to_json(data, DEFAULT_MAX_BODY_SIZE) {
+        // the transcoded body must be valid JSON
+        serde_json::from_slice::<serde_json::Value>(&json)
+            .expect("transcoded CBOR must be JSON");
+    }
+});
@@ -0,0 +1,12 @@
+//! Fuzzes the request body to finish registration.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use authentication::payload::{DEFAULT_MAX_BODY_SIZE, parse_json_payload};
+use authentication::registration::FinishRegistrationSession;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = parse_json_payload::<FinishRegistrationSession>(data, DEFAULT_MAX_BODY_SIZE);
+});
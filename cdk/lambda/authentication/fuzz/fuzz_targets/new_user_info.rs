@@ -0,0 +1,15 @@
+//! Fuzzes the request body to start registration.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use authentication::payload::{DEFAULT_MAX_BODY_SIZE, parse_json_payload};
+use authentication::registration::NewUserInfo;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(user_info) = parse_json_payload::<NewUserInfo>(data, DEFAULT_MAX_BODY_SIZE) {
+        // `Debug` redacts the username and display name
+        let _ = format!("{:?}", user_info);
+    }
+});
@@ -0,0 +1,66 @@
+//! Fuzzes the parsing of items in the session table.
+//!
+//! The input is a JSON object that maps attribute names to attribute values:
+//! strings become `S`, numbers `N`, arrays of numbers `B`, and objects `M`.
+
+#![no_main]
+
+use aws_sdk_dynamodb::{primitives::Blob, types::AttributeValue};
+use libfuzzer_sys::fuzz_target;
+use serde_json::Value;
+use std::collections::HashMap;
+
+use authentication::items::{
+    DiscoverableSessionItem,
+    Item,
+    PasskeyAuthenticationItem,
+    RecoveryLinkItem,
+    RefreshTokenFamilyItem,
+    RefreshTokenItem,
+    RegistrationResultItem,
+    RegistrationSessionItem,
+    StepUpSessionItem,
+    StepUpTokenItem,
+};
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(Value::Object(attributes)) = serde_json::from_slice::<Value>(data) else {
+        return;
+    };
+    let item = to_item(attributes);
+    let _ = RegistrationSessionItem::from_item(&item);
+    let _ = RegistrationResultItem::from_item(&item);
+    let _ = RecoveryLinkItem::from_item(&item);
+    let _ = StepUpSessionItem::from_item(&item);
+    let _ = StepUpTokenItem::from_item(&item);
+    let _ = RefreshTokenItem::from_item(&item);
+    let _ = RefreshTokenFamilyItem::from_item(&item);
+    let _ = PasskeyAuthenticationItem::from_item(&item);
+    let _ = DiscoverableSessionItem::from_item(&item);
+});
+
+fn to_item(attributes: serde_json::Map<String, Value>) -> Item {
+    attributes.into_iter()
+        .filter_map(|(name, value)| to_attribute(value).map(|value| (name, value)))
+        .collect()
+}
+
+fn to_attribute(value: Value) -> Option<AttributeValue> {
+    match value {
+        Value::String(s) => Some(AttributeValue::S(s)),
+        Value::Number(n) => Some(AttributeValue::N(n.to_string())),
+        Value::Bool(b) => Some(AttributeValue::Bool(b)),
+        Value::Array(bytes) => Some(AttributeValue::B(Blob::new(
+            bytes.iter()
+                .filter_map(Value::as_u64)
+                .map(|b| b as u8)
+                .collect::<Vec<_>>(),
+        ))),
+        Value::Object(attributes) => Some(AttributeValue::M(
+            attributes.into_iter()
+                .filter_map(|(name, value)| to_attribute(value).map(|value| (name, value)))
+                .collect::<HashMap<_, _>>(),
+        )),
+        Value::Null => None,
+    }
+}
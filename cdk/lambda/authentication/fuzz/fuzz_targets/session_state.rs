@@ -0,0 +1,16 @@
+//! Fuzzes the deserialization of the ceremony states in sessions.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use webauthn_rs::prelude::{
+    DiscoverableAuthentication,
+    PasskeyAuthentication,
+    PasskeyRegistration,
+};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = serde_json::from_slice::<PasskeyRegistration>(data);
+    let _ = serde_json::from_slice::<PasskeyAuthentication>(data);
+    let _ = serde_json::from_slice::<DiscoverableAuthentication>(data);
+});
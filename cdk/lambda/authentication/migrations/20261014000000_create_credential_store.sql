@@ -0,0 +1,41 @@
+-- Users, credentials, and sessions of the relational credential store.
+-- Timestamps are ISO 8601 strings as in the DynamoDB tables.
+
+CREATE TABLE users (
+    user_handle TEXT PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    display_name TEXT NOT NULL,
+    cognito_sub TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE TABLE credentials (
+    user_handle TEXT NOT NULL REFERENCES users (user_handle) ON DELETE CASCADE,
+    credential_id TEXT NOT NULL,
+    username TEXT,
+    credential TEXT NOT NULL,
+    credential_type TEXT,
+    backup_eligible BOOLEAN,
+    backup_state BOOLEAN,
+    discoverable BOOLEAN,
+    prf_enabled BOOLEAN,
+    cognito_sub TEXT,
+    authenticator_attachment TEXT,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    last_used_at TEXT,
+    disabled_at TEXT,
+    version BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (user_handle, credential_id)
+);
+
+CREATE INDEX credentials_username_idx ON credentials (username);
+
+-- items of the session table in the DynamoDB JSON form
+CREATE TABLE sessions (
+    pk TEXT PRIMARY KEY,
+    ttl BIGINT,
+    item JSONB NOT NULL
+);
+
+CREATE INDEX sessions_ttl_idx ON sessions (ttl);
@@ -0,0 +1,5 @@
+-- Authenticators of credentials looked up in the FIDO Metadata Service.
+
+ALTER TABLE credentials
+    ADD COLUMN aaguid TEXT,
+    ADD COLUMN authenticator_name TEXT;
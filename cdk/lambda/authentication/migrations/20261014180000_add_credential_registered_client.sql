@@ -0,0 +1,5 @@
+-- Clients that registered credentials.
+
+ALTER TABLE credentials
+    ADD COLUMN registered_ip TEXT,
+    ADD COLUMN registered_user_agent TEXT;
@@ -0,0 +1,5 @@
+-- Attestation statements of credentials given at registration.
+
+ALTER TABLE credentials
+    ADD COLUMN attestation_format TEXT,
+    ADD COLUMN attestation_certificates TEXT[];
@@ -0,0 +1,4 @@
+-- Legacy RP IDs of credentials verified in the migration mode of the RP ID.
+
+ALTER TABLE credentials
+    ADD COLUMN legacy_rp_id TEXT;
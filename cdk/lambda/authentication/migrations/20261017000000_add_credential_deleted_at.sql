@@ -0,0 +1,4 @@
+-- When credentials were soft-deleted by users.
+
+ALTER TABLE credentials
+    ADD COLUMN deleted_at TEXT;
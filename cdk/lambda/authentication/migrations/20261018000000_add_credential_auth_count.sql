@@ -0,0 +1,4 @@
+-- Number of successful authentications with credentials.
+
+ALTER TABLE credentials
+    ADD COLUMN auth_count BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,7 @@
+-- Hashes of the unused recovery codes of users.
+
+CREATE TABLE recovery_codes (
+    user_handle TEXT PRIMARY KEY REFERENCES users (user_handle) ON DELETE CASCADE,
+    code_hashes TEXT[] NOT NULL,
+    created_at TEXT NOT NULL
+);
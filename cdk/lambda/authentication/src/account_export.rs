@@ -0,0 +1,386 @@
+//! Export of the personal data of a user.
+//!
+//! `GET account/export` of the credential management gathers everything
+//! stored about the authenticated user into an [`AccountExport`]:
+//! - the user in the credential table and the attributes of the user in the
+//!   Cognito user pool
+//! - the metadata of the credentials; public keys are not included
+//! - the audit events concerning the user
+//! - the sessions of the user in the session table; see [`SessionSummary`]
+//!
+//! An export larger than the inline limit is uploaded to an S3 bucket and
+//! delivered as a pre-signed URL if [`ExportDelivery`] is configured; see
+//! [`load_export_delivery`].
+
+use aws_sdk_dynamodb::types::AttributeValue;
+use aws_sdk_s3::{presigning::PresigningConfig, primitives::ByteStream};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::time::{Duration, SystemTime};
+use tracing::error;
+
+use crate::audit::{AuditLog, AuditQuery, AuditRecord};
+use crate::config;
+use crate::credentials::CredentialInfo;
+use crate::error::Error;
+use crate::items::{Item, SessionKey, UserItem, ttl_of};
+
+/// Default maximum size of an export in bytes returned in a response body.
+pub const DEFAULT_INLINE_LIMIT: usize = 1024 * 1024;
+
+/// Default lifetime of a pre-signed URL of an export.
+pub const DEFAULT_DOWNLOAD_URL_TTL: Duration = Duration::from_secs(15 * 60);
+
+// Number of audit events read at once.
+const AUDIT_PAGE_SIZE: i32 = 100;
+
+/// Personal data of a user.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountExport {
+    /// When the export was made.
+    pub exported_at: String,
+
+    /// Profile of the user.
+    pub profile: AccountProfile,
+
+    /// Credentials of the user.
+    pub credentials: Vec<CredentialInfo>,
+
+    /// Audit events concerning the user, newest first.
+    ///
+    /// Empty if the audit log is not configured.
+    pub audit_events: Vec<AuditRecord>,
+
+    /// Sessions of the user that have not expired yet.
+    pub sessions: Vec<SessionSummary>,
+}
+
+/// Profile of a user.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountProfile {
+    /// "base64url"-encoded user handle.
+    pub user_handle: String,
+
+    /// User in the credential table.
+    ///
+    /// Omitted if the user has no passkey; e.g., a password user who has not
+    /// upgraded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user: Option<UserItem>,
+
+    /// Attributes of the user in the Cognito user pool; e.g., `email`.
+    pub attributes: BTreeMap<String, String>,
+}
+
+/// Session of a user in the session table.
+///
+/// Tells only what the session is and when it expires; tokens, challenges,
+/// and states are never exported.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionSummary {
+    /// Kind of the session; "refreshTokenFamily", "stepUp", "stepUpToken",
+    /// "recoveryLink", or "passkeyAuthentication".
+    pub kind: &'static str,
+
+    /// ID of the tenant that the session belongs to.
+    ///
+    /// Omitted for the default relying party.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+
+    /// Expiration time in seconds since the epoch.
+    pub expires_at: i64,
+
+    /// When a refresh token family was revoked in seconds since the epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<i64>,
+}
+
+// prefixes of the partition keys of the sessions that are exported.
+const SESSION_KINDS: &[(&str, &str)] = &[
+    ("refresh-family#", "refreshTokenFamily"),
+    ("stepup#", "stepUp"),
+    ("stepup-token#", "stepUpToken"),
+    ("recovery-link#", "recoveryLink"),
+    ("passkey-authentication#", "passkeyAuthentication"),
+];
+
+impl SessionSummary {
+    /// Summarizes an item in the session table.
+    ///
+    /// Returns `None` if the item is not a kind of session to export; e.g.,
+    /// each refresh token of a family.
+    pub fn from_item(item: &Item) -> Result<Option<Self>, Error> {
+        let pk = item.get("pk")
+            .and_then(|pk| pk.as_s().ok())
+            .ok_or(Error::BadItemAttribute("pk"))?;
+        let (tenant_id, pk) = match pk.strip_prefix("tenant#") {
+            Some(scoped) => match scoped.split_once('#') {
+                Some((tenant_id, pk)) => (Some(tenant_id.to_string()), pk),
+                None => return Ok(None),
+            },
+            None => (None, pk.as_str()),
+        };
+        let Some((_, kind)) = SESSION_KINDS.iter()
+            .find(|(prefix, _)| pk.starts_with(prefix)) else
+        {
+            return Ok(None);
+        };
+        let revoked_at = item.get("revokedAt")
+            .map(|v| v.as_n()
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .ok_or(Error::BadItemAttribute("revokedAt")))
+            .transpose()?;
+        Ok(Some(Self {
+            kind,
+            tenant_id,
+            expires_at: ttl_of(item)?,
+            revoked_at,
+        }))
+    }
+}
+
+/// Lists the sessions of a user in the session table.
+///
+/// Scans the whole table, because sessions are keyed by session IDs or
+/// tokens; exports are rare enough to afford it. Expired sessions that
+/// DynamoDB has not deleted yet are omitted.
+pub async fn list_user_sessions(
+    dynamodb: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    user_handle: &str,
+    now: i64,
+) -> Result<Vec<SessionSummary>, Error> {
+    let mut sessions = Vec::new();
+    let mut exclusive_start_key = None;
+    loop {
+        let res = dynamodb
+            .scan()
+            .table_name(table_name)
+            // the latest passkey authentication is keyed by the user handle
+            .filter_expression("userHandle = :userHandle OR pk = :passkeyAuthentication")
+            .expression_attribute_values(":userHandle", AttributeValue::S(user_handle.into()))
+            .expression_attribute_values(
+                ":passkeyAuthentication",
+                SessionKey::PasskeyAuthentication(user_handle).attribute(),
+            )
+            .set_exclusive_start_key(exclusive_start_key)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "scanning sessions");
+                Error::Storage("failed to scan sessions")
+            })?;
+        for item in res.items() {
+            if let Some(session) = SessionSummary::from_item(item)? {
+                if session.expires_at >= now {
+                    sessions.push(session);
+                }
+            }
+        }
+        exclusive_start_key = res.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+            return Ok(sessions);
+        }
+    }
+}
+
+/// Reads every audit event concerning a user, newest first.
+pub async fn list_user_audit_events(
+    audit_log: &AuditLog,
+    user_handle: &str,
+) -> Result<Vec<AuditRecord>, Error> {
+    let mut records = Vec::new();
+    let mut exclusive_start_key = None;
+    loop {
+        let page = audit_log
+            .query(
+                AuditQuery::User(user_handle.into()),
+                AUDIT_PAGE_SIZE,
+                exclusive_start_key,
+            )
+            .await?;
+        records.extend(page.records);
+        exclusive_start_key = page.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+            return Ok(records);
+        }
+    }
+}
+
+/// Delivery of large exports via an S3 bucket.
+#[derive(Clone, Debug)]
+pub struct ExportDelivery {
+    s3: aws_sdk_s3::Client,
+    bucket_name: String,
+    inline_limit: usize,
+    url_ttl: Duration,
+}
+
+/// Export delivered via a pre-signed URL.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportDownload {
+    /// Pre-signed URL to download the [`AccountExport`] as JSON.
+    pub download_url: String,
+
+    /// Expiration time of the URL in seconds since the epoch.
+    pub expires_at: i64,
+}
+
+/// Loads the delivery of large exports.
+///
+/// You can specify the following environment variables:
+/// - `EXPORT_BUCKET_NAME`: name of the S3 bucket to which large exports are
+///   uploaded. The bucket should expire objects shortly.
+/// - `EXPORT_INLINE_LIMIT`: maximum size of an export in bytes returned in a
+///   response body; 1 MiB by default. A larger export is uploaded.
+/// - `EXPORT_URL_TTL`: lifetime of a pre-signed URL in seconds; 900 (15
+///   minutes) by default. 7 days at most.
+///
+/// Returns `None` if `EXPORT_BUCKET_NAME` is not set, which means every
+/// export is returned in a response body.
+pub fn load_export_delivery(s3: aws_sdk_s3::Client) -> Result<Option<ExportDelivery>, Error> {
+    let bucket_name = match config::var("EXPORT_BUCKET_NAME") {
+        Ok(name) if !name.is_empty() => name,
+        Ok(_) | Err(env::VarError::NotPresent) => return Ok(None),
+        Err(env::VarError::NotUnicode(name)) => return Err(
+            Error::BadEnvironmentVariable("EXPORT_BUCKET_NAME", name.to_string_lossy().into()),
+        ),
+    };
+    let inline_limit = match config::var("EXPORT_INLINE_LIMIT") {
+        Ok(limit) => limit.parse()
+            .or(Err(Error::BadEnvironmentVariable("EXPORT_INLINE_LIMIT", limit)))?,
+        Err(env::VarError::NotPresent) => DEFAULT_INLINE_LIMIT,
+        Err(env::VarError::NotUnicode(limit)) => return Err(
+            Error::BadEnvironmentVariable("EXPORT_INLINE_LIMIT", limit.to_string_lossy().into()),
+        ),
+    };
+    let url_ttl = match config::var("EXPORT_URL_TTL") {
+        Ok(ttl) => ttl.parse()
+            .ok()
+            .filter(|ttl| (1..=7 * 24 * 60 * 60).contains(ttl))
+            .map(Duration::from_secs)
+            .ok_or(Error::BadEnvironmentVariable("EXPORT_URL_TTL", ttl))?,
+        Err(env::VarError::NotPresent) => DEFAULT_DOWNLOAD_URL_TTL,
+        Err(env::VarError::NotUnicode(ttl)) => return Err(
+            Error::BadEnvironmentVariable("EXPORT_URL_TTL", ttl.to_string_lossy().into()),
+        ),
+    };
+    Ok(Some(ExportDelivery { s3, bucket_name, inline_limit, url_ttl }))
+}
+
+impl ExportDelivery {
+    /// Returns whether an export of a given size has to be uploaded.
+    pub fn requires_upload(&self, size: usize) -> bool {
+        size > self.inline_limit
+    }
+
+    /// Uploads an export of a user and pre-signs the URL to download it.
+    pub async fn upload(
+        &self,
+        user_handle: &str,
+        body: String,
+        now: SystemTime,
+    ) -> Result<ExportDownload, Error> {
+        let now_secs = now.duration_since(SystemTime::UNIX_EPOCH)
+            .or(Err(Error::Inconvertible("time before the epoch")))?
+            .as_secs() as i64;
+        let key = export_object_key(user_handle, now_secs);
+        self.s3
+            .put_object()
+            .bucket(self.bucket_name.clone())
+            .key(key.clone())
+            .content_type("application/json")
+            .body(ByteStream::from(body.into_bytes()))
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "uploading export");
+                Error::Storage("failed to upload export")
+            })?;
+        let presigning = PresigningConfig::builder()
+            .start_time(now)
+            .expires_in(self.url_ttl)
+            .build()
+            .or(Err(Error::Storage("failed to configure pre-signing")))?;
+        let request = self.s3
+            .get_object()
+            .bucket(self.bucket_name.clone())
+            .key(key)
+            .presigned(presigning)
+            .await
+            .map_err(|e| {
+                error!(?e, "pre-signing export");
+                Error::Storage("failed to pre-sign export")
+            })?;
+        Ok(ExportDownload {
+            download_url: request.uri().to_string(),
+            expires_at: now_secs + self.url_ttl.as_secs() as i64,
+        })
+    }
+}
+
+// exports of a user are grouped under the user handle so that a lifecycle
+// rule or an operator can find them.
+fn export_object_key(user_handle: &str, now: i64) -> String {
+    format!("exports/{}/{}.json", user_handle, now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn session_item(pk: &str, ttl: i64) -> Item {
+        HashMap::from([
+            ("pk".to_string(), AttributeValue::S(pk.into())),
+            ("ttl".into(), AttributeValue::N(format!("{}", ttl))),
+            ("userHandle".into(), AttributeValue::S("AAAA".into())),
+        ])
+    }
+
+    #[test]
+    fn session_summary_should_tell_kind_and_tenant_of_session() {
+        let mut item = session_item("refresh-family#family", 120);
+        item.insert("revokedAt".into(), AttributeValue::N("60".into()));
+        assert_eq!(
+            SessionSummary::from_item(&item).unwrap(),
+            Some(SessionSummary {
+                kind: "refreshTokenFamily",
+                tenant_id: None,
+                expires_at: 120,
+                revoked_at: Some(60),
+            }),
+        );
+
+        let item = session_item("tenant#acme#stepup-token#hash", 180);
+        assert_eq!(
+            SessionSummary::from_item(&item).unwrap(),
+            Some(SessionSummary {
+                kind: "stepUpToken",
+                tenant_id: Some("acme".into()),
+                expires_at: 180,
+                revoked_at: None,
+            }),
+        );
+    }
+
+    #[test]
+    fn session_summary_should_skip_refresh_tokens_and_unknown_items() {
+        let item = session_item("refresh-token#hash", 120);
+        assert_eq!(SessionSummary::from_item(&item).unwrap(), None);
+        let item = session_item("ratelimit#ip#hash#0", 120);
+        assert_eq!(SessionSummary::from_item(&item).unwrap(), None);
+    }
+
+    #[test]
+    fn export_object_key_should_be_grouped_by_user() {
+        assert_eq!(export_object_key("AAAA", 60), "exports/AAAA/60.json");
+    }
+}
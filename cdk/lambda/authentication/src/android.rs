@@ -0,0 +1,138 @@
+//! Native Android apps.
+//!
+//! Android apps using Credential Manager do not have a web origin. The client
+//! data of their credentials instead has the origin
+//! `android:apk-key-hash:<hash>`, where `<hash>` is the "base64url"-encoded
+//! SHA-256 hash of the certificate that signs the app. The relying party has
+//! to allow the origin to serve Android apps alongside web clients.
+
+use base64::{
+    Engine as _,
+    engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
+};
+use std::env;
+use tracing::error;
+use webauthn_rs::prelude::Url;
+
+use crate::config;
+use crate::error::Error;
+
+/// Prefix of the origins of Android apps.
+pub const APK_KEY_HASH_ORIGIN_PREFIX: &str = "android:apk-key-hash:";
+
+/// SHA-256 hash of the certificate that signs an Android app.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ApkKeyHash([u8; 32]);
+
+impl ApkKeyHash {
+    /// Parses a hash.
+    ///
+    /// Accepts either the colon-separated hex form printed by `keytool` and
+    /// `apksigner`, e.g., "14:6D:E9:...", or the "base64url"-encoded form in
+    /// the origin.
+    ///
+    /// Returns `None` if the hash is malformed.
+    pub fn parse(hash: &str) -> Option<Self> {
+        let bytes = if hash.contains(':') {
+            hash.split(':')
+                .map(|b| if b.len() == 2 {
+                    u8::from_str_radix(b, 16).ok()
+                } else {
+                    None
+                })
+                .collect::<Option<Vec<_>>>()?
+        } else {
+            base64url.decode(hash).ok()?
+        };
+        bytes.try_into().ok().map(Self)
+    }
+
+    /// Origin of the apps signed with the certificate.
+    pub fn origin(&self) -> Url {
+        Url::parse(&format!("{}{}", APK_KEY_HASH_ORIGIN_PREFIX, base64url.encode(self.0)))
+            .expect("APK key hash origin must be a valid URL")
+    }
+
+    /// Colon-separated uppercase hex form; e.g., "14:6D:E9:...".
+    ///
+    /// The form used in the Digital Asset Links.
+    pub fn fingerprint(&self) -> String {
+        self.0.iter()
+            .map(|b| format!("{:02X}", b))
+            .collect::<Vec<_>>()
+            .join(":")
+    }
+}
+
+/// Loads the hashes of the certificates that sign the Android apps served by
+/// the relying party.
+///
+/// You can specify to `ANDROID_APK_KEY_HASHES` environment variable a
+/// comma-separated list of SHA-256 hashes of the signing certificates. See
+/// [`ApkKeyHash::parse`] for the accepted forms.
+///
+/// Returns an empty list if `ANDROID_APK_KEY_HASHES` is not set, which means
+/// no Android apps are allowed.
+pub fn load_apk_key_hashes() -> Result<Vec<ApkKeyHash>, Error> {
+    match config::var("ANDROID_APK_KEY_HASHES") {
+        Ok(hashes) => parse_apk_key_hashes(&hashes),
+        Err(env::VarError::NotPresent) => Ok(Vec::new()),
+        Err(env::VarError::NotUnicode(hashes)) => Err(
+            Error::BadEnvironmentVariable(
+                "ANDROID_APK_KEY_HASHES",
+                hashes.to_string_lossy().into(),
+            ),
+        ),
+    }
+}
+
+fn parse_apk_key_hashes(hashes: &str) -> Result<Vec<ApkKeyHash>, Error> {
+    hashes.split(',')
+        .map(str::trim)
+        .filter(|hash| !hash.is_empty())
+        .map(|hash| ApkKeyHash::parse(hash).ok_or_else(|| {
+            error!("malformed APK key hash: {}", hash);
+            Error::BadEnvironmentVariable("ANDROID_APK_KEY_HASHES", hash.into())
+        }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEX: &str = "14:6D:E9:83:C5:73:06:50:D8:EE:B9:95:2F:34:FC:64:16:A0:83:42:E6:1D:BE:A8:8A:04:96:B2:3F:CF:44:E5";
+
+    #[test]
+    fn apk_key_hash_should_parse_hex_and_base64url_forms() {
+        let hash = ApkKeyHash::parse(HEX).unwrap();
+        assert_eq!(
+            hash.origin().as_str(),
+            "android:apk-key-hash:FG3pg8VzBlDY7rmVLzT8ZBagg0LmHb6oigSWsj_PROU",
+        );
+        assert_eq!(
+            ApkKeyHash::parse("FG3pg8VzBlDY7rmVLzT8ZBagg0LmHb6oigSWsj_PROU"),
+            Some(hash),
+        );
+        assert_eq!(hash.fingerprint(), HEX);
+    }
+
+    #[test]
+    fn apk_key_hash_should_reject_malformed_hash() {
+        assert_eq!(ApkKeyHash::parse("14:6D:E9"), None);
+        assert_eq!(ApkKeyHash::parse(&HEX.replace("14:", "1:4")), None);
+        assert_eq!(ApkKeyHash::parse("not a hash"), None);
+    }
+
+    #[test]
+    fn parse_apk_key_hashes_should_split_comma_separated_hashes() {
+        let hashes = parse_apk_key_hashes(&format!(
+            "{}, FG3pg8VzBlDY7rmVLzT8ZBagg0LmHb6oigSWsj_PROU,",
+            HEX,
+        )).unwrap();
+        assert_eq!(hashes.len(), 2);
+        assert_eq!(hashes[0], hashes[1]);
+        assert!(parse_apk_key_hashes("").unwrap().is_empty());
+        assert!(parse_apk_key_hashes("abc").is_err());
+    }
+}
@@ -0,0 +1,357 @@
+//! Errors of the HTTP APIs.
+//!
+//! Handlers fail with an [`ApiError`] instead of a string, so that a failure
+//! can be matched and mapped to a status code. [`handle_api_errors`] turns a
+//! client error into a JSON response with an [`ErrorResponseBody`], while a
+//! server error still fails the invocation so that it is counted as an error
+//! of the function. A [`crate::error::Error`] that reaches the handler
+//! boundary is converted into an [`ApiError`] likewise.
+
+use lambda_http::{
+    Body,
+    Response,
+    http::{Method, StatusCode, header::ALLOW},
+};
+use std::future::Future;
+use thiserror::{Error as ThisError};
+use tracing::error;
+
+use crate::error::Error;
+use crate::payload::{ErrorResponseBody, PayloadError};
+
+/// Error of an HTTP API.
+#[derive(Clone, Debug, Eq, PartialEq, ThisError)]
+pub enum ApiError {
+    /// Invalid request.
+    #[error("bad request: {0}")]
+    BadRequest(String),
+    /// Bad request payload.
+    ///
+    /// The response tells the path to the offending field.
+    #[error(transparent)]
+    Payload(#[from] PayloadError),
+    /// Path that no endpoint serves.
+    #[error("not found: {0}")]
+    NotFound(String),
+    /// Method that the endpoint does not accept.
+    ///
+    /// Holds the methods that the endpoint accepts.
+    #[error("method not allowed; use {}", allow_header(.0))]
+    MethodNotAllowed(Vec<Method>),
+    /// Body of an unsupported media type.
+    #[error("unsupported media type; use {0}")]
+    UnsupportedMediaType(&'static str),
+    /// Request without an authenticated user.
+    #[error("unauthenticated request")]
+    Unauthenticated,
+    /// Session that has expired, has been used, or belongs to another user.
+    #[error("session expired: {0}")]
+    SessionExpired(&'static str),
+    /// Failed verification of a credential.
+    #[error("verification failed: {0}")]
+    VerificationFailed(&'static str),
+    /// Credential verified without user verification where a policy requires
+    /// it.
+    #[error("user verification required")]
+    UserVerificationRequired,
+    /// Operation that a policy does not allow.
+    #[error("not allowed: {0}")]
+    NotAllowed(&'static str),
+    /// Registration of a credential beyond the maximum number of credentials
+    /// per user.
+    ///
+    /// Holds the maximum number.
+    #[error("too many credentials; up to {0} per user")]
+    CredentialLimitExceeded(usize),
+    /// Feature that is not configured.
+    #[error("not configured: {0}")]
+    NotConfigured(&'static str),
+    /// Storage failure, including a malformed item in the database.
+    #[error("storage: {0}")]
+    Storage(&'static str),
+    /// Missing or bad configuration.
+    #[error("configuration: {0}")]
+    Config(String),
+    /// Other server failure.
+    #[error("internal: {0}")]
+    Internal(String),
+}
+
+impl ApiError {
+    /// Creates a configuration error.
+    pub fn config(message: impl Into<String>) -> Self {
+        Self::Config(message.into())
+    }
+
+    /// Creates an internal error.
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::Internal(message.into())
+    }
+
+    /// Status code of the response.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Payload(e) => e.status_code(),
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::MethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            Self::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::Unauthenticated
+            | Self::SessionExpired(_)
+            | Self::VerificationFailed(_)
+            | Self::UserVerificationRequired => StatusCode::UNAUTHORIZED,
+            Self::NotAllowed(_) => StatusCode::FORBIDDEN,
+            Self::CredentialLimitExceeded(_) => StatusCode::CONFLICT,
+            Self::NotConfigured(_) => StatusCode::NOT_FOUND,
+            Self::Storage(_)
+            | Self::Config(_)
+            | Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Returns whether this is an error of the client.
+    pub fn is_client_error(&self) -> bool {
+        self.status_code().is_client_error()
+    }
+
+    /// Body of the response.
+    ///
+    /// The details of a server error are not exposed.
+    pub fn response_body(&self) -> ErrorResponseBody {
+        let (error, message) = match self {
+            Self::BadRequest(message) => ("bad_request", message.clone()),
+            Self::Payload(e) => return e.response_body(),
+            Self::NotFound(_) => ("not_found", self.to_string()),
+            Self::MethodNotAllowed(_) => ("method_not_allowed", self.to_string()),
+            Self::UnsupportedMediaType(_) =>
+                ("unsupported_media_type", self.to_string()),
+            Self::Unauthenticated => ("unauthenticated", self.to_string()),
+            Self::SessionExpired(message) => ("session_expired", (*message).into()),
+            Self::VerificationFailed(message) =>
+                ("verification_failed", (*message).into()),
+            Self::UserVerificationRequired =>
+                ("user_verification_required", self.to_string()),
+            Self::NotAllowed(message) => ("not_allowed", (*message).into()),
+            Self::CredentialLimitExceeded(_) =>
+                ("credential_limit_exceeded", self.to_string()),
+            Self::NotConfigured(message) => ("not_configured", (*message).into()),
+            Self::Storage(_) | Self::Config(_) | Self::Internal(_) =>
+                ("internal_error", "internal server error".into()),
+        };
+        ErrorResponseBody {
+            error,
+            message,
+            field: None,
+            localized_message: None,
+            request_id: None,
+        }
+    }
+
+    /// Converts into a JSON response.
+    ///
+    /// The response to [`ApiError::MethodNotAllowed`] has the `Allow` header.
+    pub fn into_response(self) -> Result<Response<Body>, lambda_http::Error> {
+        let body = serde_json::to_string(&self.response_body())?;
+        let mut builder = Response::builder()
+            .status(self.status_code())
+            .header("Content-Type", "application/json");
+        if let Self::MethodNotAllowed(methods) = &self {
+            builder = builder.header(ALLOW, allow_header(methods));
+        }
+        Ok(builder.body(body.into())?)
+    }
+}
+
+// value of the `Allow` header; e.g., "GET, DELETE".
+fn allow_header(methods: &[Method]) -> String {
+    methods.iter().map(Method::as_str).collect::<Vec<_>>().join(", ")
+}
+
+impl From<Error> for ApiError {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::PolicyViolation(message) => Self::NotAllowed(message),
+            Error::BadRecord(message) => Self::BadRequest(message.into()),
+            Error::Storage(message) => Self::Storage(message),
+            Error::BadItemAttribute(_) =>
+                Self::Storage("malformed item in the database"),
+            Error::ParameterNotFound(_)
+            | Error::BadRelyingPartyOrigin(_)
+            | Error::BadEnvironmentVariable(_, _)
+            | Error::BadConfiguration(_)
+            | Error::Secret(_) => Self::Config(e.to_string()),
+            _ => Self::Internal(e.to_string()),
+        }
+    }
+}
+
+/// Recovers from the [`ApiError`] of a handler.
+///
+/// A failure whose source is an [`ApiError`], a [`PayloadError`], or a
+/// [`crate::error::Error`] ends with the response of the [`ApiError`] if it is
+/// a client error. Any other failure is passed through.
+pub fn recover_api_error(
+    res: Result<Response<Body>, lambda_http::Error>,
+) -> Result<Response<Body>, lambda_http::Error> {
+    let e = match res {
+        Ok(res) => return Ok(res),
+        Err(e) => e,
+    };
+    let api_error = if let Some(e) = e.downcast_ref::<ApiError>() {
+        e.clone()
+    } else if let Some(e) = e.downcast_ref::<PayloadError>() {
+        ApiError::Payload(e.clone())
+    } else {
+        match e.downcast::<Error>() {
+            Ok(e) => ApiError::from(*e),
+            Err(e) => return Err(e),
+        }
+    };
+    if api_error.is_client_error() {
+        error!("client error: {}", api_error);
+        api_error.into_response()
+    } else {
+        Err(api_error.into())
+    }
+}
+
+/// Runs a handler and recovers from its [`ApiError`].
+///
+/// See [`recover_api_error`].
+pub async fn handle_api_errors<Fut>(
+    handler: Fut,
+) -> Result<Response<Body>, lambda_http::Error>
+where
+    Fut: Future<Output = Result<Response<Body>, lambda_http::Error>>,
+{
+    recover_api_error(handler.await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_error_should_map_to_status_codes() {
+        assert_eq!(
+            ApiError::BadRequest("bad".into()).status_code(),
+            StatusCode::BAD_REQUEST,
+        );
+        assert_eq!(
+            ApiError::SessionExpired("expired").status_code(),
+            StatusCode::UNAUTHORIZED,
+        );
+        assert_eq!(
+            ApiError::VerificationFailed("user not verified").status_code(),
+            StatusCode::UNAUTHORIZED,
+        );
+        assert_eq!(
+            ApiError::NotConfigured("self-issued tokens").status_code(),
+            StatusCode::NOT_FOUND,
+        );
+        assert_eq!(
+            ApiError::CredentialLimitExceeded(10).status_code(),
+            StatusCode::CONFLICT,
+        );
+        assert!(!ApiError::Storage("failed").is_client_error());
+        assert!(!ApiError::config("BASE_PATH env must be set").is_client_error());
+    }
+
+    #[test]
+    fn api_error_should_hide_details_of_server_error() {
+        let body = ApiError::Storage("failed to get session item").response_body();
+        assert_eq!(body.error, "internal_error");
+        assert_eq!(body.message, "internal server error");
+        let body = ApiError::SessionExpired("expired registration session")
+            .response_body();
+        assert_eq!(body.error, "session_expired");
+        assert_eq!(body.message, "expired registration session");
+    }
+
+    #[test]
+    fn api_error_should_tell_missing_user_verification() {
+        let e = ApiError::UserVerificationRequired;
+        assert_eq!(e.status_code(), StatusCode::UNAUTHORIZED);
+        assert_eq!(e.response_body().error, "user_verification_required");
+    }
+
+    #[test]
+    fn api_error_should_convert_common_error() {
+        assert_eq!(
+            ApiError::from(Error::PolicyViolation("authenticator attachment not allowed")),
+            ApiError::NotAllowed("authenticator attachment not allowed"),
+        );
+        assert_eq!(
+            ApiError::from(Error::BadItemAttribute("ttl")),
+            ApiError::Storage("malformed item in the database"),
+        );
+        assert!(matches!(
+            ApiError::from(Error::BadEnvironmentVariable("MAX_BODY_SIZE", "0".into())),
+            ApiError::Config(_),
+        ));
+    }
+
+    #[test]
+    fn recover_api_error_should_respond_to_client_error() {
+        let res = recover_api_error(Err(ApiError::VerificationFailed("user not verified").into()))
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = recover_api_error(Err(Error::PolicyViolation("not allowed").into()))
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn api_error_should_tell_offending_field_of_payload() {
+        let e = ApiError::from(PayloadError::Malformed {
+            field: Some("userInfo.displayName".into()),
+            message: "invalid type: integer `1`, expected a string".into(),
+        });
+        assert_eq!(e.status_code(), StatusCode::BAD_REQUEST);
+        let body = e.response_body();
+        assert_eq!(body.error, "malformed_payload");
+        assert_eq!(body.field.as_deref(), Some("userInfo.displayName"));
+        let res = recover_api_error(Err(PayloadError::Missing.into())).unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn api_error_should_tell_allowed_method() {
+        let res = ApiError::MethodNotAllowed(vec![Method::GET, Method::DELETE])
+            .into_response()
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers()[ALLOW], "GET, DELETE");
+    }
+
+    #[test]
+    fn api_error_codes_should_be_exported() {
+        use crate::payload::ERROR_CODES;
+        let errors = [
+            ApiError::BadRequest("bad".into()),
+            ApiError::Payload(PayloadError::Missing),
+            ApiError::Payload(PayloadError::TooLarge { size: 2, limit: 1 }),
+            ApiError::Payload(PayloadError::Malformed { field: None, message: "bad".into() }),
+            ApiError::NotFound("/unknown".into()),
+            ApiError::MethodNotAllowed(vec![Method::POST]),
+            ApiError::UnsupportedMediaType("application/json"),
+            ApiError::Unauthenticated,
+            ApiError::SessionExpired("expired"),
+            ApiError::VerificationFailed("failed"),
+            ApiError::NotAllowed("not allowed"),
+            ApiError::CredentialLimitExceeded(10),
+            ApiError::NotConfigured("not configured"),
+            ApiError::internal("failed"),
+        ];
+        for e in errors {
+            let code = e.response_body().error;
+            assert!(ERROR_CODES.contains(&code), "{} is not exported", code);
+        }
+    }
+
+    #[test]
+    fn recover_api_error_should_pass_through_server_error() {
+        assert!(recover_api_error(Err(ApiError::Storage("failed").into())).is_err());
+        assert!(recover_api_error(Err("unknown failure".into())).is_err());
+    }
+}
@@ -0,0 +1,537 @@
+//! Audit log.
+//!
+//! Security-relevant events are appended to a DynamoDB table dedicated to the
+//! audit log. Writers are only granted `PutItem`, and events are put under
+//! random IDs on condition that they do not exist, so recorded events are
+//! never overwritten by the functions. IAM does not enforce it, though.
+//!
+//! Ceremonies record events on a best-effort basis; see
+//! [`AuditLog::record_best_effort`].
+
+use aws_sdk_dynamodb::{
+    primitives::{DateTime, DateTimeFormat},
+    types::AttributeValue,
+};
+use base64::{
+    Engine as _,
+    engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
+};
+use lambda_http::Request;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::env;
+use std::time::SystemTime;
+use tracing::{error, warn};
+
+use crate::config;
+use crate::error::Error;
+use crate::rate_limit::source_ip;
+
+/// Name of the index to query events by date.
+pub const EVENT_DATE_INDEX_NAME: &str = "EventDateIndex";
+
+/// HTTP header that labels synthetic traffic; e.g., red-team scenarios.
+pub const SYNTHETIC_LABEL_HEADER: &str = "x-red-team-scenario";
+
+/// Client metadata key that labels synthetic traffic sent to Cognito.
+pub const SYNTHETIC_LABEL_CLIENT_METADATA_KEY: &str = "redTeamScenario";
+
+// maximum length of a synthetic traffic label.
+const MAX_SYNTHETIC_LABEL_LENGTH: usize = 64;
+
+/// Type of an audit event.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AuditEventType {
+    /// A credential has been registered.
+    CredentialRegistered,
+    /// A credential has been deleted.
+    CredentialDeleted,
+    /// A credential has been disabled.
+    CredentialDisabled,
+    /// A deleted credential has been restored.
+    CredentialRestored,
+    /// Authentication has failed.
+    AuthenticationFailed,
+    /// A recovery code has been used.
+    RecoveryCodeUsed,
+    /// A recovery link has been emailed.
+    RecoveryLinkSent,
+    /// A recovery link has been used.
+    RecoveryLinkUsed,
+    /// An account has been deleted.
+    AccountDeleted,
+    /// A username has been changed.
+    UsernameChanged,
+    /// A locked credential has been unlocked by an administrator.
+    CredentialUnlocked,
+    /// Authentication has succeeded.
+    AuthenticationSucceeded,
+}
+
+impl AuditEventType {
+    /// Returns the name of the event type.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuditEventType::CredentialRegistered => "credential_registered",
+            AuditEventType::CredentialDeleted => "credential_deleted",
+            AuditEventType::CredentialDisabled => "credential_disabled",
+            AuditEventType::CredentialRestored => "credential_restored",
+            AuditEventType::AuthenticationFailed => "authentication_failed",
+            AuditEventType::RecoveryCodeUsed => "recovery_code_used",
+            AuditEventType::RecoveryLinkSent => "recovery_link_sent",
+            AuditEventType::RecoveryLinkUsed => "recovery_link_used",
+            AuditEventType::AccountDeleted => "account_deleted",
+            AuditEventType::UsernameChanged => "username_changed",
+            AuditEventType::CredentialUnlocked => "credential_unlocked",
+            AuditEventType::AuthenticationSucceeded => "authentication_succeeded",
+        }
+    }
+}
+
+/// Client that caused an audit event.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ClientInfo {
+    /// Source IP address.
+    pub source_ip: Option<String>,
+
+    /// User agent.
+    pub user_agent: Option<String>,
+
+    /// Label of synthetic traffic; e.g., the name of a red-team scenario.
+    ///
+    /// Anyone can send a label, so it only tells synthetic events apart and
+    /// must never affect a decision.
+    pub synthetic_label: Option<String>,
+}
+
+impl ClientInfo {
+    /// Extracts the client information from a given request.
+    pub fn of(request: &Request) -> Self {
+        Self {
+            source_ip: source_ip(request),
+            user_agent: request.headers()
+                .get("User-Agent")
+                .and_then(|v| v.to_str().ok())
+                .map(Into::into),
+            synthetic_label: request.headers()
+                .get(SYNTHETIC_LABEL_HEADER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(synthetic_label),
+        }
+    }
+
+    /// Extracts the client information from the client metadata of a
+    /// Cognito trigger.
+    ///
+    /// Cognito triggers do not tell the source IP or user agent.
+    pub fn of_client_metadata(client_metadata: &HashMap<String, String>) -> Self {
+        Self {
+            synthetic_label: client_metadata
+                .get(SYNTHETIC_LABEL_CLIENT_METADATA_KEY)
+                .and_then(|v| synthetic_label(v)),
+            ..Self::default()
+        }
+    }
+}
+
+// accepts a synthetic traffic label if it is short and printable.
+fn synthetic_label(value: &str) -> Option<String> {
+    let valid = !value.is_empty()
+        && value.len() <= MAX_SYNTHETIC_LABEL_LENGTH
+        && value.bytes().all(|b| b.is_ascii_graphic());
+    valid.then(|| value.into())
+}
+
+/// Audit event.
+#[derive(Clone, Debug)]
+pub struct AuditEvent {
+    /// Type of the event.
+    pub event_type: AuditEventType,
+
+    /// User handle of the user concerned.
+    pub user_handle: String,
+
+    /// "base64url"-encoded ID of the credential concerned.
+    pub credential_id: Option<String>,
+
+    /// Client that caused the event.
+    pub client: ClientInfo,
+
+    /// Additional detail; e.g., the reason of an authentication failure.
+    pub detail: Option<String>,
+}
+
+/// Recorded audit event.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditRecord {
+    /// Unique ID of the event.
+    pub event_id: String,
+
+    /// Type of the event.
+    pub event_type: String,
+
+    /// User handle of the user concerned.
+    pub user_handle: String,
+
+    /// "base64url"-encoded ID of the credential concerned.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credential_id: Option<String>,
+
+    /// Source IP address.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_ip: Option<String>,
+
+    /// User agent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+
+    /// Additional detail.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+
+    /// Label of synthetic traffic.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub synthetic_label: Option<String>,
+
+    /// When the event occurred.
+    pub timestamp: String,
+}
+
+impl AuditRecord {
+    /// Extracts an audit record from an item in the audit table.
+    pub fn from_item(item: &HashMap<String, AttributeValue>) -> Result<Self, Error> {
+        let get_s = |name: &'static str| -> Result<Option<String>, Error> {
+            item.get(name)
+                .map(|v| v.as_s()
+                    .map(String::clone)
+                    .or(Err(Error::Storage("malformed audit record"))))
+                .transpose()
+        };
+        Ok(Self {
+            event_id: get_s("eventId")?
+                .ok_or(Error::Storage("missing eventId in audit record"))?,
+            event_type: get_s("eventType")?
+                .ok_or(Error::Storage("missing eventType in audit record"))?,
+            user_handle: get_s("userHandle")?
+                .ok_or(Error::Storage("missing userHandle in audit record"))?,
+            credential_id: get_s("credentialId")?,
+            source_ip: get_s("sourceIp")?,
+            user_agent: get_s("userAgent")?,
+            detail: get_s("detail")?,
+            synthetic_label: get_s("syntheticLabel")?,
+            timestamp: get_s("timestamp")?
+                .ok_or(Error::Storage("missing timestamp in audit record"))?,
+        })
+    }
+}
+
+/// Condition to query audit events.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AuditQuery {
+    /// Events concerning a user specified by the user handle.
+    User(String),
+    /// Events that occurred on a date in the form of "yyyy-mm-dd".
+    Date(String),
+}
+
+/// Page of audit events.
+#[derive(Clone, Debug)]
+pub struct AuditPage {
+    /// Events in the page, newest first.
+    pub records: Vec<AuditRecord>,
+
+    /// Key to start the next page.
+    ///
+    /// `None` if this is the last page.
+    pub last_evaluated_key: Option<HashMap<String, AttributeValue>>,
+}
+
+/// Audit log.
+#[derive(Clone, Debug)]
+pub struct AuditLog {
+    dynamodb: aws_sdk_dynamodb::Client,
+    table_name: String,
+}
+
+/// Loads the audit log configuration.
+///
+/// You can specify to `AUDIT_TABLE_NAME` environment variable the name of the
+/// DynamoDB table for the audit log.
+///
+/// Returns `None` if `AUDIT_TABLE_NAME` is not set, which means no events are
+/// recorded.
+pub fn load_audit_log(
+    dynamodb: aws_sdk_dynamodb::Client,
+) -> Result<Option<AuditLog>, Error> {
+    match config::var("AUDIT_TABLE_NAME") {
+        Ok(table_name) if !table_name.is_empty() => Ok(Some(AuditLog {
+            dynamodb,
+            table_name,
+        })),
+        Ok(table_name) => Err(
+            Error::BadEnvironmentVariable("AUDIT_TABLE_NAME", table_name),
+        ),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(table_name)) => Err(
+            Error::BadEnvironmentVariable(
+                "AUDIT_TABLE_NAME",
+                table_name.to_string_lossy().into(),
+            ),
+        ),
+    }
+}
+
+impl AuditLog {
+    /// Name of the DynamoDB table.
+    pub fn table_name(&self) -> &str {
+        &self.table_name
+    }
+
+    /// Appends an event to the audit log.
+    pub async fn record(&self, event: AuditEvent) -> Result<(), Error> {
+        let mut event_id = [0u8; 16];
+        SystemRandom::new().fill(&mut event_id)
+            .or(Err(Error::Storage("failed to generate event ID")))?;
+        let timestamp = DateTime::from(SystemTime::now())
+            .fmt(DateTimeFormat::DateTime)
+            .or(Err(Error::Storage("failed to format timestamp")))?;
+        let item = audit_item(&event, &base64url.encode(event_id), &timestamp);
+        self.dynamodb
+            .put_item()
+            .table_name(self.table_name.clone())
+            .set_item(Some(item))
+            // never overwrites recorded events
+            .condition_expression("attribute_not_exists(pk)")
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "recording audit event");
+                Error::Storage("failed to record audit event")
+            })?;
+        Ok(())
+    }
+
+    /// Appends an event to the audit log, logging instead of failing.
+    ///
+    /// A ceremony records its events with this, so that an unavailable or
+    /// throttled audit table never fails an authentication or a registration
+    /// that has already taken effect.
+    pub async fn record_best_effort(&self, event: AuditEvent) {
+        let event_type = event.event_type;
+        if let Err(e) = self.record(event).await {
+            warn!("proceeding without {:?} audit event: {}", event_type, e);
+        }
+    }
+
+    /// Queries audit events, newest first.
+    pub async fn query(
+        &self,
+        query: AuditQuery,
+        limit: i32,
+        exclusive_start_key: Option<HashMap<String, AttributeValue>>,
+    ) -> Result<AuditPage, Error> {
+        let request = self.dynamodb
+            .query()
+            .table_name(self.table_name.clone())
+            .scan_index_forward(false)
+            .limit(limit)
+            .set_exclusive_start_key(exclusive_start_key);
+        let request = match query {
+            AuditQuery::User(user_handle) => request
+                .key_condition_expression("pk = :pk")
+                .expression_attribute_values(
+                    ":pk",
+                    AttributeValue::S(format!("user#{}", user_handle)),
+                ),
+            AuditQuery::Date(date) => request
+                .index_name(EVENT_DATE_INDEX_NAME)
+                .key_condition_expression("eventDate = :date")
+                .expression_attribute_values(":date", AttributeValue::S(date)),
+        };
+        let res = request
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "querying audit events");
+                Error::Storage("failed to query audit events")
+            })?;
+        Ok(AuditPage {
+            records: res.items
+                .unwrap_or_default()
+                .iter()
+                .map(AuditRecord::from_item)
+                .collect::<Result<_, _>>()?,
+            last_evaluated_key: res.last_evaluated_key,
+        })
+    }
+
+    /// Scans audit events that occurred before a given timestamp.
+    ///
+    /// `cutoff` must be in the format of `DateTimeFormat::DateTime`. Events
+    /// in a page are not sorted.
+    pub async fn scan_before(
+        &self,
+        cutoff: String,
+        limit: i32,
+        exclusive_start_key: Option<HashMap<String, AttributeValue>>,
+    ) -> Result<AuditPage, Error> {
+        let res = self.dynamodb
+            .scan()
+            .table_name(self.table_name.clone())
+            .filter_expression("#timestamp < :cutoff")
+            .expression_attribute_names("#timestamp", "timestamp")
+            .expression_attribute_values(":cutoff", AttributeValue::S(cutoff))
+            .limit(limit)
+            .set_exclusive_start_key(exclusive_start_key)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "scanning audit events");
+                Error::Storage("failed to scan audit events")
+            })?;
+        Ok(AuditPage {
+            records: res.items
+                .unwrap_or_default()
+                .iter()
+                .map(AuditRecord::from_item)
+                .collect::<Result<_, _>>()?,
+            last_evaluated_key: res.last_evaluated_key,
+        })
+    }
+
+    /// Deletes a recorded event whose retention period has elapsed.
+    ///
+    /// Only the retention job is supposed to delete events; writers are
+    /// granted no more than appending.
+    pub async fn purge(&self, record: &AuditRecord) -> Result<(), Error> {
+        self.dynamodb
+            .delete_item()
+            .table_name(self.table_name.clone())
+            .set_key(Some(audit_key(&record.user_handle, &record.timestamp, &record.event_id)))
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "purging audit event");
+                Error::Storage("failed to purge audit event")
+            })?;
+        Ok(())
+    }
+}
+
+// returns the primary key attributes of an event in the audit table.
+fn audit_key(
+    user_handle: &str,
+    timestamp: &str,
+    event_id: &str,
+) -> HashMap<String, AttributeValue> {
+    HashMap::from([
+        ("pk".into(), AttributeValue::S(format!("user#{}", user_handle))),
+        ("sk".into(), AttributeValue::S(format!("event#{}#{}", timestamp, event_id))),
+    ])
+}
+
+// builds an item in the audit table.
+//
+// `timestamp` must be in the format of `DateTimeFormat::DateTime`.
+fn audit_item(
+    event: &AuditEvent,
+    event_id: &str,
+    timestamp: &str,
+) -> HashMap<String, AttributeValue> {
+    let mut item = audit_key(&event.user_handle, timestamp, event_id);
+    item.extend([
+        ("eventId".to_string(), AttributeValue::S(event_id.into())),
+        ("eventType".into(), AttributeValue::S(event.event_type.as_str().into())),
+        ("eventDate".into(), AttributeValue::S(timestamp.chars().take(10).collect())),
+        ("userHandle".into(), AttributeValue::S(event.user_handle.clone())),
+        ("timestamp".into(), AttributeValue::S(timestamp.into())),
+    ]);
+    let optional = [
+        ("credentialId", event.credential_id.as_ref()),
+        ("sourceIp", event.client.source_ip.as_ref()),
+        ("userAgent", event.client.user_agent.as_ref()),
+        ("detail", event.detail.as_ref()),
+        ("syntheticLabel", event.client.synthetic_label.as_ref()),
+    ];
+    for (name, value) in optional {
+        if let Some(value) = value {
+            item.insert(name.into(), AttributeValue::S(value.clone()));
+        }
+    }
+    item
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event() -> AuditEvent {
+        AuditEvent {
+            event_type: AuditEventType::CredentialRegistered,
+            user_handle: "user-handle".into(),
+            credential_id: Some("credential-id".into()),
+            client: ClientInfo {
+                source_ip: Some("192.0.2.1".into()),
+                user_agent: None,
+                synthetic_label: Some("wrong-origin".into()),
+            },
+            detail: None,
+        }
+    }
+
+    #[test]
+    fn audit_item_should_key_events_by_user_and_time() {
+        let item = audit_item(&event(), "event-id", "2024-01-02T03:04:05Z");
+        assert_eq!(item["pk"], AttributeValue::S("user#user-handle".into()));
+        assert_eq!(
+            item["sk"],
+            AttributeValue::S("event#2024-01-02T03:04:05Z#event-id".into()),
+        );
+        assert_eq!(item["eventDate"], AttributeValue::S("2024-01-02".into()));
+        assert_eq!(
+            item["eventType"],
+            AttributeValue::S("credential_registered".into()),
+        );
+        assert!(!item.contains_key("userAgent"));
+        assert!(!item.contains_key("detail"));
+    }
+
+    #[test]
+    fn audit_record_should_round_trip_audit_item() {
+        let item = audit_item(&event(), "event-id", "2024-01-02T03:04:05Z");
+        assert_eq!(
+            AuditRecord::from_item(&item).unwrap(),
+            AuditRecord {
+                event_id: "event-id".into(),
+                event_type: "credential_registered".into(),
+                user_handle: "user-handle".into(),
+                credential_id: Some("credential-id".into()),
+                source_ip: Some("192.0.2.1".into()),
+                user_agent: None,
+                detail: None,
+                synthetic_label: Some("wrong-origin".into()),
+                timestamp: "2024-01-02T03:04:05Z".into(),
+            },
+        );
+    }
+
+    #[test]
+    fn client_info_of_client_metadata_should_accept_only_printable_labels() {
+        let client = ClientInfo::of_client_metadata(&HashMap::from([(
+            SYNTHETIC_LABEL_CLIENT_METADATA_KEY.to_string(),
+            "replayed-assertion".to_string(),
+        )]));
+        assert_eq!(client.synthetic_label.as_deref(), Some("replayed-assertion"));
+        assert_eq!(client.source_ip, None);
+        for label in ["", "with space", "line\nbreak", "a".repeat(65).as_str()] {
+            let client = ClientInfo::of_client_metadata(&HashMap::from([(
+                SYNTHETIC_LABEL_CLIENT_METADATA_KEY.to_string(),
+                label.to_string(),
+            )]));
+            assert_eq!(client.synthetic_label, None, "{:?}", label);
+        }
+        assert_eq!(ClientInfo::of_client_metadata(&HashMap::new()), ClientInfo::default());
+    }
+}
@@ -0,0 +1,497 @@
+//! Software authenticator.
+//!
+//! Emulates a minimal FIDO2 authenticator that holds ES256 (P-256) credentials
+//! and produces "none" attestations and assertions.
+//! It is intended to drive the relying party without a browser, and must never
+//! be used to protect real accounts.
+//!
+//! [`VirtualAuthenticator`] plays the part of a browser and an authenticator
+//! together: it answers the options that the relying party returns with the
+//! credentials that the relying party expects, so that registration and
+//! authentication ceremonies can be run end to end in local tests.
+
+use base64::{
+    Engine as _,
+    engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
+};
+use ring::{
+    digest,
+    rand::SystemRandom,
+    signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_ASN1_SIGNING},
+};
+
+use serde_json::json;
+use webauthn_rs::prelude::{CreationChallengeResponse, RequestChallengeResponse};
+use webauthn_rs_proto::{PublicKeyCredential, RegisterPublicKeyCredential};
+
+use crate::error::Error;
+
+/// User present flag in authenticator data.
+pub const FLAG_UP: u8 = 0x01;
+/// User verified flag in authenticator data.
+pub const FLAG_UV: u8 = 0x04;
+/// Backup eligible flag in authenticator data.
+pub const FLAG_BE: u8 = 0x08;
+/// Backup state flag in authenticator data.
+pub const FLAG_BS: u8 = 0x10;
+/// Attested credential data included flag in authenticator data.
+pub const FLAG_AT: u8 = 0x40;
+
+// COSE algorithm identifier of ES256.
+const COSE_ALG_ES256: i64 = -7;
+
+/// Credential held by the software authenticator.
+pub struct SoftwareCredential {
+    /// Credential ID.
+    pub id: Vec<u8>,
+
+    /// User handle.
+    pub user_handle: Vec<u8>,
+
+    /// Relying party ID.
+    pub rp_id: String,
+
+    /// Signature counter.
+    pub counter: u32,
+
+    /// Flags set in every authenticator data besides UP and AT.
+    ///
+    /// [`FLAG_UV`] by default.
+    pub flags: u8,
+
+    key_pair: EcdsaKeyPair,
+    rng: SystemRandom,
+}
+
+impl SoftwareCredential {
+    /// Generates a new credential for a given relying party and user.
+    pub fn generate(
+        rp_id: impl Into<String>,
+        user_handle: impl Into<Vec<u8>>,
+    ) -> Result<Self, Error> {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(
+            &ECDSA_P256_SHA256_ASN1_SIGNING,
+            &rng,
+        ).or(Err(Error::SoftwareAuthenticator("failed to generate key pair")))?;
+        let key_pair = EcdsaKeyPair::from_pkcs8(
+            &ECDSA_P256_SHA256_ASN1_SIGNING,
+            pkcs8.as_ref(),
+            &rng,
+        ).or(Err(Error::SoftwareAuthenticator("failed to load key pair")))?;
+        let mut id = vec![0u8; 16];
+        getrandom::getrandom(&mut id)
+            .or(Err(Error::SoftwareAuthenticator("failed to generate credential ID")))?;
+        Ok(Self {
+            id,
+            user_handle: user_handle.into(),
+            rp_id: rp_id.into(),
+            counter: 0,
+            flags: FLAG_UV,
+            key_pair,
+            rng,
+        })
+    }
+
+    /// Produces a "none" attestation object.
+    ///
+    /// Client data is not signed in "none" attestation, so the relying party
+    /// checks it separately.
+    pub fn attest(&self) -> Vec<u8> {
+        let mut auth_data = self.authenticator_data_header(FLAG_AT);
+        auth_data.extend_from_slice(&[0u8; 16]); // AAGUID
+        auth_data.extend_from_slice(&(self.id.len() as u16).to_be_bytes());
+        auth_data.extend_from_slice(&self.id);
+        auth_data.extend_from_slice(&self.cose_public_key());
+        let mut attestation_object = Vec::new();
+        cbor::map(3, &mut attestation_object);
+        cbor::text("fmt", &mut attestation_object);
+        cbor::text("none", &mut attestation_object);
+        cbor::text("attStmt", &mut attestation_object);
+        cbor::map(0, &mut attestation_object);
+        cbor::text("authData", &mut attestation_object);
+        cbor::bytes(&auth_data, &mut attestation_object);
+        attestation_object
+    }
+
+    /// Produces an assertion for given client data.
+    ///
+    /// Increments the signature counter before signing.
+    ///
+    /// Returns the authenticator data and the signature.
+    pub fn assert(
+        &mut self,
+        client_data_json: &[u8],
+    ) -> Result<(Vec<u8>, Vec<u8>), Error> {
+        self.counter = self.counter.wrapping_add(1);
+        let auth_data = self.authenticator_data_header(0);
+        let client_data_hash = digest::digest(&digest::SHA256, client_data_json);
+        let mut message = auth_data.clone();
+        message.extend_from_slice(client_data_hash.as_ref());
+        let signature = self.key_pair.sign(&self.rng, &message)
+            .or(Err(Error::SoftwareAuthenticator("failed to sign")))?;
+        Ok((auth_data, signature.as_ref().to_vec()))
+    }
+
+    /// Produces a `PublicKeyCredential` of a registration in the JSON form
+    /// for given client data.
+    pub fn registration_credential(&self, client_data_json: &[u8]) -> serde_json::Value {
+        json!({
+            "id": base64url.encode(&self.id),
+            "rawId": base64url.encode(&self.id),
+            "type": "public-key",
+            "response": {
+                "attestationObject": base64url.encode(self.attest()),
+                "clientDataJSON": base64url.encode(client_data_json),
+            },
+            "extensions": {},
+        })
+    }
+
+    /// Produces a `PublicKeyCredential` of an assertion in the JSON form for
+    /// given client data.
+    ///
+    /// Increments the signature counter like [`SoftwareCredential::assert`].
+    pub fn assertion_credential(
+        &mut self,
+        client_data_json: &[u8],
+    ) -> Result<serde_json::Value, Error> {
+        let (authenticator_data, signature) = self.assert(client_data_json)?;
+        Ok(json!({
+            "id": base64url.encode(&self.id),
+            "rawId": base64url.encode(&self.id),
+            "type": "public-key",
+            "response": {
+                "authenticatorData": base64url.encode(authenticator_data),
+                "clientDataJSON": base64url.encode(client_data_json),
+                "signature": base64url.encode(signature),
+                "userHandle": base64url.encode(&self.user_handle),
+            },
+            "extensions": {},
+        }))
+    }
+
+    // rpIdHash || flags || signCount
+    fn authenticator_data_header(&self, extra_flags: u8) -> Vec<u8> {
+        let rp_id_hash = digest::digest(&digest::SHA256, self.rp_id.as_bytes());
+        let mut auth_data = Vec::with_capacity(37);
+        auth_data.extend_from_slice(rp_id_hash.as_ref());
+        auth_data.push(FLAG_UP | self.flags | extra_flags);
+        auth_data.extend_from_slice(&self.counter.to_be_bytes());
+        auth_data
+    }
+
+    fn cose_public_key(&self) -> Vec<u8> {
+        // uncompressed point: 0x04 || x || y
+        let point = self.key_pair.public_key().as_ref();
+        let mut key = Vec::new();
+        cbor::map(5, &mut key);
+        cbor::int(1, &mut key); // kty
+        cbor::int(2, &mut key); // EC2
+        cbor::int(3, &mut key); // alg
+        cbor::int(COSE_ALG_ES256, &mut key);
+        cbor::int(-1, &mut key); // crv
+        cbor::int(1, &mut key); // P-256
+        cbor::int(-2, &mut key); // x
+        cbor::bytes(&point[1..33], &mut key);
+        cbor::int(-3, &mut key); // y
+        cbor::bytes(&point[33..65], &mut key);
+        key
+    }
+}
+
+/// Virtual authenticator that answers the options of the relying party.
+///
+/// Holds every credential it has created, and acts as if the user always
+/// consents and verifies themselves.
+pub struct VirtualAuthenticator {
+    /// Origin that the virtual browser claims in client data.
+    pub origin: String,
+
+    /// Credentials created so far.
+    pub credentials: Vec<SoftwareCredential>,
+}
+
+impl VirtualAuthenticator {
+    /// Creates a virtual authenticator on a given origin without credentials.
+    pub fn new(origin: impl Into<String>) -> Self {
+        Self {
+            origin: origin.into(),
+            credentials: Vec::new(),
+        }
+    }
+
+    /// Creates a credential for given credential creation options.
+    ///
+    /// Fails if the authenticator already holds one of the credentials that
+    /// the options exclude, as an authenticator does with
+    /// `InvalidStateError`.
+    pub fn create(
+        &mut self,
+        options: &CreationChallengeResponse,
+    ) -> Result<RegisterPublicKeyCredential, Error> {
+        let options = serde_json::to_value(options)
+            .or(Err(Error::SoftwareAuthenticator("failed to serialize creation options")))?;
+        let options = &options["publicKey"];
+        let rp_id = options["rp"]["id"].as_str()
+            .ok_or(Error::SoftwareAuthenticator("missing rp.id"))?;
+        let user_handle = options["user"]["id"].as_str()
+            .and_then(|id| base64url.decode(id).ok())
+            .ok_or(Error::SoftwareAuthenticator("missing user.id"))?;
+        let challenge = options["challenge"].as_str()
+            .ok_or(Error::SoftwareAuthenticator("missing challenge"))?;
+        let excluded = credential_ids(&options["excludeCredentials"]);
+        if self.credentials.iter().any(|c| c.rp_id == rp_id && excluded.contains(&c.id)) {
+            return Err(Error::SoftwareAuthenticator("credential excluded"));
+        }
+        let credential = SoftwareCredential::generate(rp_id, user_handle)?;
+        let client_data = client_data_json("webauthn.create", challenge, &self.origin);
+        let response = serde_json::from_value(credential.registration_credential(&client_data))
+            .or(Err(Error::SoftwareAuthenticator("failed to compose registration credential")))?;
+        self.credentials.push(credential);
+        Ok(response)
+    }
+
+    /// Produces an assertion for given credential request options.
+    ///
+    /// Signs with the first credential of the relying party that the options
+    /// allow, or any credential of the relying party if the options allow
+    /// none as in discoverable authentication.
+    pub fn get(
+        &mut self,
+        options: &RequestChallengeResponse,
+    ) -> Result<PublicKeyCredential, Error> {
+        let options = serde_json::to_value(options)
+            .or(Err(Error::SoftwareAuthenticator("failed to serialize request options")))?;
+        let options = &options["publicKey"];
+        let rp_id = options["rpId"].as_str()
+            .ok_or(Error::SoftwareAuthenticator("missing rpId"))?;
+        let challenge = options["challenge"].as_str()
+            .ok_or(Error::SoftwareAuthenticator("missing challenge"))?;
+        let allowed = credential_ids(&options["allowCredentials"]);
+        let credential = self.credentials.iter_mut()
+            .find(|c| c.rp_id == rp_id && (allowed.is_empty() || allowed.contains(&c.id)))
+            .ok_or(Error::SoftwareAuthenticator("no credential available"))?;
+        let client_data = client_data_json("webauthn.get", challenge, &self.origin);
+        serde_json::from_value(credential.assertion_credential(&client_data)?)
+            .or(Err(Error::SoftwareAuthenticator("failed to compose assertion credential")))
+    }
+}
+
+// decodes the IDs in a list of credential descriptors.
+fn credential_ids(descriptors: &serde_json::Value) -> Vec<Vec<u8>> {
+    descriptors.as_array()
+        .map(|descriptors| descriptors.iter()
+            .filter_map(|d| d["id"].as_str())
+            .filter_map(|id| base64url.decode(id).ok())
+            .collect())
+        .unwrap_or_default()
+}
+
+/// Serializes collected client data.
+pub fn client_data_json(type_: &str, challenge: &str, origin: &str) -> Vec<u8> {
+    serde_json::to_vec(&serde_json::json!({
+        "type": type_,
+        "challenge": challenge,
+        "origin": origin,
+        "crossOrigin": false,
+    })).expect("client data must be serializable")
+}
+
+// Minimal CBOR encoder that covers attestation objects and COSE keys.
+mod cbor {
+    fn header(major: u8, value: u64, out: &mut Vec<u8>) {
+        let major = major << 5;
+        if value < 24 {
+            out.push(major | value as u8);
+        } else if value <= u8::MAX as u64 {
+            out.push(major | 24);
+            out.push(value as u8);
+        } else if value <= u16::MAX as u64 {
+            out.push(major | 25);
+            out.extend_from_slice(&(value as u16).to_be_bytes());
+        } else if value <= u32::MAX as u64 {
+            out.push(major | 26);
+            out.extend_from_slice(&(value as u32).to_be_bytes());
+        } else {
+            out.push(major | 27);
+            out.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+
+    pub(super) fn int(value: i64, out: &mut Vec<u8>) {
+        if value >= 0 {
+            header(0, value as u64, out);
+        } else {
+            header(1, (-1 - value) as u64, out);
+        }
+    }
+
+    pub(super) fn bytes(value: &[u8], out: &mut Vec<u8>) {
+        header(2, value.len() as u64, out);
+        out.extend_from_slice(value);
+    }
+
+    pub(super) fn text(value: &str, out: &mut Vec<u8>) {
+        header(3, value.len() as u64, out);
+        out.extend_from_slice(value.as_bytes());
+    }
+
+    pub(super) fn map(len: usize, out: &mut Vec<u8>) {
+        header(5, len as u64, out);
+    }
+}
+
+/// Fixtures of ceremonies run with [`VirtualAuthenticator`].
+///
+/// Shared by unit tests, property tests, and benchmarks.
+pub mod testing {
+    use webauthn_rs::prelude::{Passkey, PasskeyRegistration, Url, Uuid, Webauthn};
+    use webauthn_rs_proto::RegisterPublicKeyCredential;
+
+    use super::VirtualAuthenticator;
+    use crate::parameters::build_webauthn;
+
+    /// Origin of the relying party.
+    pub const ORIGIN: &str = "http://localhost:5173";
+
+    /// User handle of the user "alice".
+    pub const USER_HANDLE: Uuid = Uuid::from_u128(0x6f0c2a51_2f4e_4b8e_9d43_4d1b1e1c9a07);
+
+    /// Builds the relying party on "localhost".
+    pub fn webauthn() -> Webauthn {
+        build_webauthn("localhost", &Url::parse(ORIGIN).unwrap(), "Passkey Test", &[]).unwrap()
+    }
+
+    /// Starts a registration, and has a given authenticator create a
+    /// credential for it.
+    ///
+    /// Returns the created credential and the registration state, so that the
+    /// state can go through storage before the registration is finished.
+    pub fn create_credential(
+        webauthn: &Webauthn,
+        authenticator: &mut VirtualAuthenticator,
+        user_handle: Uuid,
+        username: &str,
+        display_name: &str,
+    ) -> (RegisterPublicKeyCredential, PasskeyRegistration) {
+        let (options, state) = webauthn
+            .start_passkey_registration(user_handle, username, display_name, None)
+            .unwrap();
+        (authenticator.create(&options).unwrap(), state)
+    }
+
+    /// Registers a passkey of "alice" with a given authenticator.
+    pub fn register(webauthn: &Webauthn, authenticator: &mut VirtualAuthenticator) -> Passkey {
+        let (credential, state) =
+            create_credential(webauthn, authenticator, USER_HANDLE, "alice", "Alice");
+        webauthn.finish_passkey_registration(&credential, &state).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use super::testing::{ORIGIN, USER_HANDLE, register, webauthn};
+
+    #[test]
+    fn cbor_int_should_encode_small_and_negative_values() {
+        let mut out = Vec::new();
+        cbor::int(1, &mut out);
+        cbor::int(-7, &mut out);
+        cbor::int(24, &mut out);
+        cbor::int(-300, &mut out);
+        assert_eq!(out, vec![0x01, 0x26, 0x18, 0x18, 0x39, 0x01, 0x2B]);
+    }
+
+    #[test]
+    fn software_credential_assert_should_increment_counter() {
+        let mut credential =
+            SoftwareCredential::generate("localhost", b"user".to_vec()).unwrap();
+        let (auth_data, _) = credential.assert(b"{}").unwrap();
+        assert_eq!(credential.counter, 1);
+        assert_eq!(auth_data.len(), 37);
+        assert_eq!(auth_data[32], FLAG_UP | FLAG_UV);
+        assert_eq!(&auth_data[33..37], &1u32.to_be_bytes());
+    }
+
+    #[test]
+    fn software_credential_attest_should_include_attested_credential_data() {
+        let credential =
+            SoftwareCredential::generate("localhost", b"user".to_vec()).unwrap();
+        let attestation_object = credential.attest();
+        // map(3), text(3) "fmt", text(4) "none"
+        assert_eq!(&attestation_object[0..2], &[0xA3, 0x63]);
+        assert_eq!(&attestation_object[2..5], b"fmt");
+        assert_eq!(&attestation_object[5..10], b"\x64none");
+    }
+
+    #[test]
+    fn software_credential_assertion_credential_should_tell_user_handle() {
+        let mut credential =
+            SoftwareCredential::generate("localhost", b"user".to_vec()).unwrap();
+        let assertion = credential.assertion_credential(b"{}").unwrap();
+        assert_eq!(assertion["id"], base64url.encode(&credential.id));
+        assert_eq!(assertion["response"]["userHandle"], "dXNlcg");
+        assert_eq!(assertion["response"]["clientDataJSON"], "e30");
+        assert_eq!(credential.counter, 1);
+    }
+
+    #[test]
+    fn virtual_authenticator_should_register_and_authenticate() {
+        let webauthn = webauthn();
+        let mut authenticator = VirtualAuthenticator::new(ORIGIN);
+        let passkey = register(&webauthn, &mut authenticator);
+        assert_eq!(authenticator.credentials[0].user_handle, USER_HANDLE.as_bytes());
+
+        let (options, state) = webauthn
+            .start_passkey_authentication(&[passkey.clone()])
+            .unwrap();
+        let credential = authenticator.get(&options).unwrap();
+        let result = webauthn.finish_passkey_authentication(&credential, &state).unwrap();
+        assert_eq!(result.cred_id(), passkey.cred_id());
+        assert_eq!(result.counter(), 1);
+    }
+
+    #[test]
+    fn virtual_authenticator_should_refuse_excluded_credential() {
+        let webauthn = webauthn();
+        let mut authenticator = VirtualAuthenticator::new(ORIGIN);
+        let passkey = register(&webauthn, &mut authenticator);
+        let (options, _) = webauthn
+            .start_passkey_registration(
+                USER_HANDLE,
+                "alice",
+                "Alice",
+                Some(vec![passkey.cred_id().clone()]),
+            )
+            .unwrap();
+        assert!(authenticator.create(&options).is_err());
+    }
+
+    #[test]
+    fn virtual_authenticator_should_answer_discoverable_authentication() {
+        let webauthn = webauthn();
+        let mut authenticator = VirtualAuthenticator::new(ORIGIN);
+        let passkey = register(&webauthn, &mut authenticator);
+
+        let (options, state) = webauthn.start_discoverable_authentication().unwrap();
+        let credential = authenticator.get(&options).unwrap();
+        let (identified, _) = webauthn
+            .identify_discoverable_authentication(&credential)
+            .unwrap();
+        assert_eq!(identified, USER_HANDLE);
+        webauthn
+            .finish_discoverable_authentication(&credential, state, &[(&passkey).into()])
+            .unwrap();
+    }
+
+    #[test]
+    fn virtual_authenticator_should_fail_without_credential() {
+        let webauthn = webauthn();
+        let mut authenticator = VirtualAuthenticator::new(ORIGIN);
+        let (options, _) = webauthn.start_discoverable_authentication().unwrap();
+        assert!(authenticator.get(&options).is_err());
+    }
+}
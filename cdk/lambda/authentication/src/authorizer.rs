@@ -0,0 +1,224 @@
+//! Events of a Lambda authorizer of API Gateway.
+//!
+//! Covers the `TOKEN` and `REQUEST` authorizers of REST APIs, and the
+//! `REQUEST` authorizer of HTTP APIs with the payload format version 1.0;
+//! all of them expect an IAM policy in the response. The `authorizer` binary
+//! verifies tokens with [`crate::jwt`] and responds with these types.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// Event that invokes a Lambda authorizer.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorizerRequest {
+    /// Value of the identity source of a `TOKEN` authorizer; e.g., the
+    /// `Authorization` header.
+    #[serde(default)]
+    pub authorization_token: Option<String>,
+
+    /// ARN of the method being invoked.
+    ///
+    /// `routeArn` of an HTTP API.
+    #[serde(alias = "routeArn")]
+    pub method_arn: String,
+
+    /// Headers of a `REQUEST` authorizer.
+    #[serde(default)]
+    pub headers: Option<HashMap<String, String>>,
+}
+
+impl AuthorizerRequest {
+    /// Returns the bearer token of the request.
+    ///
+    /// Takes the token from `authorizationToken`, or the `Authorization`
+    /// header of any case. The "Bearer" scheme is optional, because some
+    /// clients send a bare token.
+    pub fn bearer_token(&self) -> Option<&str> {
+        let value = self.authorization_token.as_deref().or_else(|| {
+            self.headers.as_ref()?
+                .iter()
+                .find(|(name, _)| name.eq_ignore_ascii_case("authorization"))
+                .map(|(_, value)| value.as_str())
+        })?;
+        let token = match value.split_once(' ') {
+            Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => token,
+            Some(_) => return None,
+            None => value,
+        }.trim();
+        (!token.is_empty()).then_some(token)
+    }
+
+    /// Returns the ARN that covers every method of the API stage.
+    ///
+    /// API Gateway caches the policy for a token, so the policy has to cover
+    /// the other methods the token may invoke while cached. Returns the
+    /// method ARN as it is if it is malformed.
+    pub fn api_wildcard_arn(&self) -> String {
+        // arn:aws:execute-api:{region}:{account}:{api-id}/{stage}/{method}/{path}
+        let mut parts = self.method_arn.splitn(3, '/');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(api), Some(stage), Some(_)) => format!("{}/{}/*/*", api, stage),
+            _ => self.method_arn.clone(),
+        }
+    }
+}
+
+/// Response of a Lambda authorizer.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthorizerResponse {
+    /// Principal that the policy applies to; the user handle.
+    pub principal_id: String,
+
+    /// IAM policy.
+    pub policy_document: PolicyDocument,
+
+    /// Context passed to the integration.
+    ///
+    /// Values must be strings, numbers, or booleans.
+    pub context: Map<String, Value>,
+}
+
+impl AuthorizerResponse {
+    /// Allows a principal to invoke a resource.
+    pub fn allow(principal_id: impl Into<String>, resource: impl Into<String>) -> Self {
+        Self::new(principal_id.into(), Effect::Allow, resource.into())
+    }
+
+    /// Denies a principal to invoke a resource.
+    pub fn deny(principal_id: impl Into<String>, resource: impl Into<String>) -> Self {
+        Self::new(principal_id.into(), Effect::Deny, resource.into())
+    }
+
+    fn new(principal_id: String, effect: Effect, resource: String) -> Self {
+        Self {
+            principal_id,
+            policy_document: PolicyDocument {
+                version: POLICY_VERSION,
+                statement: vec![PolicyStatement {
+                    action: INVOKE_ACTION,
+                    effect,
+                    resource,
+                }],
+            },
+            context: Map::new(),
+        }
+    }
+
+    /// Adds a value to the context.
+    pub fn with_context(mut self, key: &str, value: impl Into<Value>) -> Self {
+        self.context.insert(key.into(), value.into());
+        self
+    }
+}
+
+const POLICY_VERSION: &str = "2012-10-17";
+
+const INVOKE_ACTION: &str = "execute-api:Invoke";
+
+/// IAM policy document.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PolicyDocument {
+    /// Version of the policy language.
+    pub version: &'static str,
+
+    /// Statements.
+    pub statement: Vec<PolicyStatement>,
+}
+
+/// Statement of an IAM policy.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PolicyStatement {
+    /// Action.
+    pub action: &'static str,
+
+    /// Effect.
+    pub effect: Effect,
+
+    /// ARN of the resource.
+    pub resource: String,
+}
+
+/// Effect of a policy statement.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum Effect {
+    /// Allows the action.
+    Allow,
+    /// Denies the action.
+    Deny,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METHOD_ARN: &str =
+        "arn:aws:execute-api:ap-northeast-1:123456789012:abcdef/prod/GET/credentials/AAAA";
+
+    fn request(event: Value) -> AuthorizerRequest {
+        serde_json::from_value(event).unwrap()
+    }
+
+    #[test]
+    fn bearer_token_should_take_token_of_token_or_request_authorizer() {
+        let req = request(serde_json::json!({
+            "type": "TOKEN",
+            "authorizationToken": "Bearer abc.def.ghi",
+            "methodArn": METHOD_ARN,
+        }));
+        assert_eq!(req.bearer_token(), Some("abc.def.ghi"));
+
+        let req = request(serde_json::json!({
+            "type": "REQUEST",
+            "routeArn": METHOD_ARN,
+            "headers": { "authorization": "bearer abc.def.ghi" },
+        }));
+        assert_eq!(req.bearer_token(), Some("abc.def.ghi"));
+        assert_eq!(req.method_arn, METHOD_ARN);
+
+        let req = request(serde_json::json!({
+            "methodArn": METHOD_ARN,
+            "authorizationToken": "Basic dXNlcjpwYXNz",
+        }));
+        assert_eq!(req.bearer_token(), None);
+
+        let req = request(serde_json::json!({ "methodArn": METHOD_ARN }));
+        assert_eq!(req.bearer_token(), None);
+    }
+
+    #[test]
+    fn api_wildcard_arn_should_cover_every_method_of_stage() {
+        let req = request(serde_json::json!({ "methodArn": METHOD_ARN }));
+        assert_eq!(
+            req.api_wildcard_arn(),
+            "arn:aws:execute-api:ap-northeast-1:123456789012:abcdef/prod/*/*",
+        );
+        let req = request(serde_json::json!({ "methodArn": "malformed" }));
+        assert_eq!(req.api_wildcard_arn(), "malformed");
+    }
+
+    #[test]
+    fn authorizer_response_should_serialize_to_iam_policy() {
+        let res = AuthorizerResponse::allow("AAAA", "arn:resource")
+            .with_context("userHandle", "AAAA");
+        assert_eq!(
+            serde_json::to_value(res).unwrap(),
+            serde_json::json!({
+                "principalId": "AAAA",
+                "policyDocument": {
+                    "Version": "2012-10-17",
+                    "Statement": [{
+                        "Action": "execute-api:Invoke",
+                        "Effect": "Allow",
+                        "Resource": "arn:resource",
+                    }],
+                },
+                "context": { "userHandle": "AAAA" },
+            }),
+        );
+    }
+}
@@ -0,0 +1,940 @@
+//! Administration.
+//!
+//! You have to configure the following environment variables:
+//! - `BASE_PATH`: base path to provide the service; e.g., `/auth/credentials/admin/`
+//! - `AUDIT_TABLE_NAME`: name of the DynamoDB table for the audit log
+//! - `EVENT_BUS_NAME`: (optional) name of the EventBridge event bus. Revoked
+//!   credentials are published as `CredentialRevoked`; see
+//!   [`authentication::domain_events`].
+//! - `WEBHOOK_URL`, `WEBHOOK_SECRET_ID`: (optional) URL to which deleted
+//!   credentials are posted and the ID of the secret that signs the requests;
+//!   see [`authentication::webhooks`].
+//! - `USER_POOL_ID`: ID of the Cognito user pool
+//! - `CREDENTIAL_TABLE_NAME`: name of the DynamoDB table that manages
+//!   credentials
+//!
+//! You can optionally configure the following environment variables:
+//! - `CONFIG_PARAMETER_PATH`: path to the parameters in Parameter Store on
+//!   AWS Systems Manager that override the other environment variables. See
+//!   [`authentication::config`] for details.
+//! - `ADMIN_GROUP_NAME`: name of the group in the Cognito user pool whose
+//!   members are administrators; "admin" by default
+//! - `MAX_BODY_SIZE`: maximum size of a CBOR request body in bytes; 32 KiB
+//!   by default. Larger requests are rejected with 413.
+//! - `CORS_ALLOWED_ORIGINS`, `CORS_MAX_AGE`: origins allowed to call the API
+//!   and the lifetime of a preflight response. No CORS headers are added
+//!   unless specified; e.g., when API Gateway handles CORS. See
+//!   [`load_cors_policy`] for details.
+//! - `PII_KMS_KEY_ARN`, `PII_INDEX_SECRET_ID`: ARN of the KMS key and ID of
+//!   the secret in Secrets Manager that protect the usernames and display
+//!   names of users. Stored in plaintext unless specified. See
+//!   [`authentication::pii`].
+//! - `LOG_LEVEL`, `LOG_REDACTION`: log level or `RUST_LOG`-style directives,
+//!   and whether identifiers are redacted in logs; "info" and redacted by
+//!   default. See [`authentication::telemetry`] for details.
+//! - `METRICS_NAMESPACE`: namespace of the CloudWatch metrics; "PasskeyTest"
+//!   by default. The cold start of the function is reported as metrics; see
+//!   [`ColdStart`].
+//!
+//! The function fails at cold start if any required variable is missing or
+//! any variable is invalid, and the error lists all of them; see
+//! [`authentication::config`].
+//!
+//! Every endpoint must be protected by a JWT authorizer that verifies tokens
+//! issued by the Cognito user pool.
+//! Requests from users who are not administrators are rejected with 403.
+//! Requests with bad query parameters are rejected with 400 and
+//! [`ErrorResponseBody`] as `application/json`.
+//!
+//! ## Endpoints
+//!
+//! Provides the following endpoints under the base path.
+//! Every endpoint is versioned under `${BASE_PATH}v1/`; e.g.,
+//! `${BASE_PATH}v1/audit-events`. Paths without a version are routed to v1
+//! for backward compatibility, and unsupported versions end with 404.
+//! Request and response bodies may be CBOR instead of JSON; see
+//! [`authentication::content`]. A request body is `application/cbor` if so
+//! specified in `Content-Type`, and a response body is `application/cbor` if
+//! `Accept` prefers it.
+//! Large response bodies are compressed with Brotli or gzip if
+//! `Accept-Encoding` allows it.
+//! Error responses carry a `localizedMessage` in English or Japanese as
+//! `Accept-Language` prefers; see [`authentication::i18n`].
+//! `GET ${BASE_PATH}health` serves the health check, which requires no
+//! authentication and is not versioned; see [`authentication::health`].
+//! A scheduled warm-up event is answered with 200 without serving a request;
+//! see [`authentication::warmer`].
+//! A client error like an expired session or a failed verification ends with
+//! the status code of the [`ApiError`] and an [`ErrorResponseBody`]; see
+//! [`authentication::api_error`].
+//! A path that no endpoint serves ends with 404, and a method that the
+//! endpoint does not accept ends with 405; see [`Router`].
+//!
+//! ### `GET ${BASE_PATH}audit-events`
+//!
+//! Lists audit events, newest first.
+//! Exactly one of the following query parameters must be specified:
+//! - `user`: user handle of the user concerned
+//! - `date`: date when events occurred in the form of "yyyy-mm-dd"
+//!
+//! The following query parameters are optional:
+//! - `limit`: maximum number of events in a page; 1–100. 50 by default.
+//! - `nextToken`: token to obtain the next page
+//!
+//! The response body is [`AuditEventList`] as `application/json`.
+//!
+//! ### `GET ${BASE_PATH}users`
+//!
+//! Lists users in the Cognito user pool.
+//! The following query parameters are optional:
+//! - `limit`: maximum number of users in a page; 1–60. 50 by default.
+//! - `nextToken`: token to obtain the next page
+//!
+//! The response body is [`UserList`] as `application/json`.
+//!
+//! ### `GET ${BASE_PATH}users/{userHandle}/credentials`
+//!
+//! Lists the credentials of a user including the last-used timestamps.
+//! Each credential also carries the attestation statement given at
+//! registration, if stored, for compliance review.
+//! The response body is [`UserCredentialList`] as `application/json`.
+//!
+//! ### `GET ${BASE_PATH}legacy-credentials`
+//!
+//! Lists credentials that still need re-registration in the migration mode of
+//! the RP ID; i.e., flagged with a legacy RP ID. See
+//! [`authentication::rp_migration`].
+//! The following query parameters are optional:
+//! - `limit`: maximum number of credentials evaluated in a page; 1–100. 50
+//!   by default. A page may contain fewer credentials even if it is not the
+//!   last page.
+//! - `nextToken`: token to obtain the next page
+//!
+//! The response body is [`LegacyCredentialList`] as `application/json`.
+//!
+//! ### `DELETE ${BASE_PATH}users/{userHandle}/credentials/{credentialId}`
+//!
+//! Revokes a credential of a user. Ends with 404 if the credential does not
+//! exist.
+//!
+//! ### `DELETE ${BASE_PATH}users/{userHandle}/credentials`
+//!
+//! Revokes all the credentials of a user.
+//!
+//! Both revoking endpoints accept the following optional query parameters:
+//! - `action`: "delete" to delete credentials, or "disable" to keep but
+//!   disable them; "delete" by default.
+//! - `signOut`: "true" to invalidate the refresh tokens of the user in the
+//!   Cognito user pool; "false" by default. Access tokens issued before
+//!   remain valid until they expire.
+//!
+//! Every revoked credential is recorded in the audit log.
+//! The response body is [`RevokedCredentials`] as `application/json`.
+//!
+//! ### `DELETE ${BASE_PATH}users/{userHandle}/credentials/{credentialId}/lockout`
+//!
+//! Unlocks a credential locked after consecutive failed authentications, and
+//! resets the duration of the next lock; see [`authentication::lockout`].
+//! Ends with 404 if the credential is not locked and has no failures.
+//! The unlock is recorded in the audit log.
+//! Responds with 204 and no body.
+
+use aws_config::SdkConfig;
+use aws_sdk_dynamodb::primitives::{DateTime, DateTimeFormat};
+use lambda_http::{
+    Body,
+    Error,
+    Request,
+    RequestExt,
+    Response,
+    http::StatusCode,
+};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+use tracing::{Instrument, error, info, instrument};
+
+use authentication::api_error::{ApiError, handle_api_errors};
+use authentication::audit::{
+    AuditEvent,
+    AuditEventType,
+    AuditLog,
+    AuditQuery,
+    AuditRecord,
+    ClientInfo,
+    load_audit_log,
+};
+use authentication::config::{self, ConfigCheck, load_config_parameters};
+use authentication::content::negotiate_content;
+use authentication::correlation::load_sdk_config;
+use authentication::credentials::{CredentialAttestation, CredentialInfo};
+use authentication::domain_events::{
+    CredentialRevoked,
+    DomainEvent,
+    EventPublisher,
+    load_event_publisher,
+    publish_event,
+};
+use authentication::health::{HEALTH_PATH, health_check};
+use authentication::identity::{authenticated_user_handle, is_member_of};
+use authentication::items::CredentialKey;
+use authentication::lockout::unlock_credential;
+use authentication::metrics::{ColdStart, load_metrics};
+use authentication::pagination::{decode_page_token, encode_page_token};
+use authentication::payload::{ErrorResponseBody, load_max_body_size};
+use authentication::pii::load_pii_protection;
+use authentication::routing::{
+    ApiVersion,
+    CorsPolicy,
+    RouteParams,
+    Router,
+    job_path,
+    load_cors_policy,
+    resolve_version,
+    unsupported_version,
+};
+use authentication::telemetry::{init_tracing, redact, request_span, truncate};
+use authentication::users::UserDirectory;
+use authentication::warmer::run_with_warmer;
+use authentication::webhooks::{WebhookNotifier, load_webhook_notifier, notify_webhook};
+
+// Default number of events in a page.
+const DEFAULT_PAGE_LIMIT: i32 = 50;
+
+// Maximum number of events in a page.
+const MAX_PAGE_LIMIT: i32 = 100;
+
+// Maximum number of users in a page, which Cognito allows at most.
+const MAX_USER_PAGE_LIMIT: i32 = 60;
+
+// State shared among Lambda invocations.
+struct SharedState {
+    cognito: aws_sdk_cognitoidentityprovider::Client,
+    dynamodb: aws_sdk_dynamodb::Client,
+    base_path: String,
+    user_pool_id: String,
+    audit_log: AuditLog,
+    event_publisher: Option<EventPublisher>,
+    webhooks: Option<WebhookNotifier>,
+    users: UserDirectory,
+    admin_group_name: String,
+}
+
+// Configuration validated at cold start.
+struct Config {
+    base_path: String,
+    user_pool_id: String,
+    credential_table_name: String,
+    admin_group_name: String,
+    max_body_size: usize,
+    cors: Option<CorsPolicy>,
+}
+
+impl Config {
+    fn from_env() -> Result<Self, Error> {
+        let mut check = ConfigCheck::new();
+        // the audit log is loaded with the DynamoDB client
+        check.required("AUDIT_TABLE_NAME");
+        let config = Self {
+            base_path: check.required("BASE_PATH"),
+            user_pool_id: check.required("USER_POOL_ID"),
+            credential_table_name: check.required("CREDENTIAL_TABLE_NAME"),
+            admin_group_name: config::var("ADMIN_GROUP_NAME")
+                .unwrap_or_else(|_| "admin".into()),
+            max_body_size: check.load(load_max_body_size()),
+            cors: check.load(load_cors_policy()),
+        };
+        Ok(check.finish(config)?)
+    }
+}
+
+impl SharedState {
+    #[instrument(name = "cold_start", skip_all)]
+    async fn new(sdk_config: &SdkConfig, config: Config) -> Result<Self, Error> {
+        let dynamodb = aws_sdk_dynamodb::Client::new(sdk_config);
+        Ok(Self {
+            cognito: aws_sdk_cognitoidentityprovider::Client::new(sdk_config),
+            dynamodb: dynamodb.clone(),
+            base_path: config.base_path.trim_end_matches('/').into(),
+            user_pool_id: config.user_pool_id,
+            audit_log: load_audit_log(dynamodb.clone())?
+                .ok_or(ApiError::config("AUDIT_TABLE_NAME env must be set"))?,
+            event_publisher: load_event_publisher(
+                aws_sdk_eventbridge::Client::new(sdk_config),
+            )?,
+            webhooks: load_webhook_notifier(
+                aws_sdk_secretsmanager::Client::new(sdk_config),
+            )?,
+            users: UserDirectory::new(dynamodb, config.credential_table_name)
+                .with_pii_protection(load_pii_protection(
+                    aws_sdk_kms::Client::new(sdk_config),
+                    aws_sdk_secretsmanager::Client::new(sdk_config),
+                )?),
+            admin_group_name: config.admin_group_name,
+        })
+    }
+}
+
+/// Page of audit events.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditEventList {
+    /// Audit events.
+    pub events: Vec<AuditRecord>,
+
+    /// Token to obtain the next page.
+    ///
+    /// Omitted if this is the last page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_token: Option<String>,
+}
+
+/// Page of users.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserList {
+    /// Users.
+    pub users: Vec<UserSummary>,
+
+    /// Token to obtain the next page.
+    ///
+    /// Omitted if this is the last page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_token: Option<String>,
+}
+
+/// Summary of a user.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserSummary {
+    /// User handle, which is the username in the Cognito user pool.
+    pub user_handle: String,
+
+    /// Username; `preferred_username` in the Cognito user pool.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+
+    /// Display name; `name` in the Cognito user pool.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+
+    /// Whether the user is enabled.
+    pub enabled: bool,
+
+    /// When the user was created.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<String>,
+}
+
+/// Credentials of a user.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserCredentialList {
+    /// User handle.
+    pub user_handle: String,
+
+    /// Credentials.
+    pub credentials: Vec<AdminCredentialInfo>,
+}
+
+/// Information on a credential shown to administrators.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AdminCredentialInfo {
+    /// Information on the credential.
+    #[serde(flatten)]
+    pub info: CredentialInfo,
+
+    /// Attestation statement given at registration.
+    ///
+    /// Omitted unless attestation was requested and stored.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attestation: Option<CredentialAttestation>,
+}
+
+/// Page of credentials flagged with a legacy RP ID.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LegacyCredentialList {
+    /// Credentials.
+    pub credentials: Vec<LegacyCredential>,
+
+    /// Token to obtain the next page.
+    ///
+    /// Omitted if this is the last page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_token: Option<String>,
+}
+
+/// Credential that needs re-registration.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LegacyCredential {
+    /// User handle.
+    pub user_handle: String,
+
+    /// Credential ID.
+    pub credential_id: String,
+
+    /// Legacy RP ID with which the credential was last verified.
+    pub legacy_rp_id: String,
+
+    /// When the credential was last used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<String>,
+}
+
+/// Credentials revoked by an administrator.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RevokedCredentials {
+    /// User handle.
+    pub user_handle: String,
+
+    /// How the credentials were revoked; "delete" or "disable".
+    pub action: &'static str,
+
+    /// IDs of the revoked credentials.
+    pub credential_ids: Vec<String>,
+
+    /// Whether the user has been signed out of the Cognito user pool.
+    pub signed_out: bool,
+}
+
+// how credentials are revoked.
+#[derive(Clone, Copy, Debug)]
+enum RevokeAction {
+    Delete,
+    Disable,
+}
+
+impl RevokeAction {
+    fn as_str(self) -> &'static str {
+        match self {
+            RevokeAction::Delete => "delete",
+            RevokeAction::Disable => "disable",
+        }
+    }
+
+    fn past_tense(self) -> &'static str {
+        match self {
+            RevokeAction::Delete => "deleted",
+            RevokeAction::Disable => "disabled",
+        }
+    }
+
+    fn event_type(self) -> AuditEventType {
+        match self {
+            RevokeAction::Delete => AuditEventType::CredentialDeleted,
+            RevokeAction::Disable => AuditEventType::CredentialDisabled,
+        }
+    }
+}
+
+// Context of a job of an administrator.
+#[derive(Clone)]
+struct Job {
+    shared_state: Arc<SharedState>,
+    admin_handle: String,
+}
+
+// routes of the jobs.
+fn router(cors: Option<CorsPolicy>) -> Router<Job> {
+    Router::new()
+        .get("/audit-events", |job: Job, event, _| {
+            list_audit_events(job.shared_state, event)
+        })
+        .get("/users", |job: Job, event, _| list_users(job.shared_state, event))
+        .get("/legacy-credentials", |job: Job, event, _| {
+            list_legacy_credentials(job.shared_state, event)
+        })
+        .get("/users/{userHandle}/credentials", |job: Job, _, params: RouteParams| async move {
+            list_user_credentials(job.shared_state, params.require("userHandle")?).await
+        })
+        .delete("/users/{userHandle}/credentials", |job: Job, event, params: RouteParams| async move {
+            revoke_credentials(
+                job.shared_state,
+                params.require("userHandle")?,
+                None,
+                job.admin_handle,
+                event,
+            ).await
+        })
+        .delete(
+            "/users/{userHandle}/credentials/{credentialId}",
+            |job: Job, event, params: RouteParams| async move {
+                revoke_credentials(
+                    job.shared_state,
+                    params.require("userHandle")?,
+                    Some(params.require("credentialId")?),
+                    job.admin_handle,
+                    event,
+                ).await
+            },
+        )
+        .delete(
+            "/users/{userHandle}/credentials/{credentialId}/lockout",
+            |job: Job, event, params: RouteParams| async move {
+                unlock(
+                    job.shared_state,
+                    params.require("userHandle")?,
+                    params.require("credentialId")?,
+                    job.admin_handle,
+                    event,
+                ).await
+            },
+        )
+        .with_cors(cors)
+}
+
+async fn function_handler(
+    shared_state: Arc<SharedState>,
+    router: &Router<Job>,
+    event: Request,
+) -> Result<Response<Body>, Error> {
+    let job_path = job_path(&event, &shared_state.base_path)?;
+    if job_path == HEALTH_PATH {
+        let tables = [
+            ("auditTable", shared_state.audit_log.table_name()),
+            ("credentialTable", shared_state.users.table_name()),
+        ];
+        return health_check("admin", &event, &shared_state.dynamodb, &tables).await;
+    }
+    let user_handle = authenticated_user_handle(&event)
+        .ok_or(ApiError::Unauthenticated)?;
+    if !is_member_of(&event, &shared_state.admin_group_name) {
+        error!("not an administrator: {}", redact(&user_handle));
+        return error_response(
+            StatusCode::FORBIDDEN,
+            "forbidden",
+            "administrator only",
+            None,
+        );
+    }
+    let route = match resolve_version(job_path) {
+        Some((ApiVersion::V1, route)) => route.to_string(),
+        None => return unsupported_version(job_path),
+    };
+    let job = Job {
+        shared_state,
+        admin_handle: user_handle,
+    };
+    router.handle(job, event, &route).await
+}
+
+#[instrument(skip_all)]
+async fn list_audit_events(
+    shared_state: Arc<SharedState>,
+    event: Request,
+) -> Result<Response<Body>, Error> {
+    let params = event.query_string_parameters_ref();
+    let param = |name: &str| params.and_then(|p| p.first(name));
+    info!("list_audit_events: {:?}", params);
+
+    let query = match (param("user"), param("date")) {
+        (Some(user_handle), None) => AuditQuery::User(user_handle.into()),
+        (None, Some(date)) if is_date(date) => AuditQuery::Date(date.into()),
+        (None, Some(_)) => return error_response(
+            StatusCode::BAD_REQUEST,
+            "bad_query",
+            "date must be in the form of yyyy-mm-dd",
+            Some("date"),
+        ),
+        _ => return error_response(
+            StatusCode::BAD_REQUEST,
+            "bad_query",
+            "exactly one of user or date must be specified",
+            None,
+        ),
+    };
+    let limit = match param("limit").map(str::parse::<i32>) {
+        None => DEFAULT_PAGE_LIMIT,
+        Some(Ok(limit)) if (1..=MAX_PAGE_LIMIT).contains(&limit) => limit,
+        Some(_) => return error_response(
+            StatusCode::BAD_REQUEST,
+            "bad_query",
+            &format!("limit must be between 1 and {}", MAX_PAGE_LIMIT),
+            Some("limit"),
+        ),
+    };
+    let exclusive_start_key = match param("nextToken").map(decode_page_token) {
+        None => None,
+        Some(Some(key)) => Some(key),
+        Some(None) => return error_response(
+            StatusCode::BAD_REQUEST,
+            "bad_query",
+            "malformed nextToken",
+            Some("nextToken"),
+        ),
+    };
+
+    let page = shared_state.audit_log
+        .query(query, limit, exclusive_start_key)
+        .await?;
+    let next_token = page.last_evaluated_key.as_ref()
+        .map(encode_page_token)
+        .transpose()?;
+    let body = serde_json::to_string(&AuditEventList {
+        events: page.records,
+        next_token,
+    })?;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(body.into())?)
+}
+
+#[instrument(skip_all)]
+async fn list_users(
+    shared_state: Arc<SharedState>,
+    event: Request,
+) -> Result<Response<Body>, Error> {
+    let params = event.query_string_parameters_ref();
+    let param = |name: &str| params.and_then(|p| p.first(name));
+    info!("list_users: {:?}", params);
+
+    let limit = match param("limit").map(str::parse::<i32>) {
+        None => DEFAULT_PAGE_LIMIT,
+        Some(Ok(limit)) if (1..=MAX_USER_PAGE_LIMIT).contains(&limit) => limit,
+        Some(_) => return error_response(
+            StatusCode::BAD_REQUEST,
+            "bad_query",
+            &format!("limit must be between 1 and {}", MAX_USER_PAGE_LIMIT),
+            Some("limit"),
+        ),
+    };
+
+    let res = shared_state.cognito
+        .list_users()
+        .user_pool_id(shared_state.user_pool_id.clone())
+        .limit(limit)
+        .set_pagination_token(param("nextToken").map(Into::into))
+        .send()
+        .await?;
+    let users = res.users()
+        .iter()
+        .map(|user| {
+            let attribute = |name: &str| user.attributes()
+                .iter()
+                .find(|a| a.name() == name)
+                .and_then(|a| a.value())
+                .map(String::from);
+            Ok(UserSummary {
+                user_handle: user.username()
+                    .ok_or(ApiError::internal("missing username in user pool"))?
+                    .into(),
+                username: attribute("preferred_username"),
+                display_name: attribute("name"),
+                enabled: user.enabled(),
+                created_at: user.user_create_date()
+                    .map(|d| d.fmt(DateTimeFormat::DateTime))
+                    .transpose()?,
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    let body = serde_json::to_string(&UserList {
+        users,
+        next_token: res.pagination_token().map(Into::into),
+    })?;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(body.into())?)
+}
+
+#[instrument(skip_all)]
+async fn list_user_credentials(
+    shared_state: Arc<SharedState>,
+    user_handle: String,
+) -> Result<Response<Body>, Error> {
+    info!("list_user_credentials: {}", redact(&user_handle));
+
+    let credentials = shared_state.users
+        .list_credentials(&user_handle)
+        .await?
+        .into_iter()
+        .map(|item| {
+            let attestation = CredentialAttestation::of(&item);
+            CredentialInfo::from_credential(item)
+                .map(|info| AdminCredentialInfo { info, attestation })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let body = serde_json::to_string(&UserCredentialList {
+        user_handle,
+        credentials,
+    })?;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(body.into())?)
+}
+
+#[instrument(skip_all)]
+async fn list_legacy_credentials(
+    shared_state: Arc<SharedState>,
+    event: Request,
+) -> Result<Response<Body>, Error> {
+    let params = event.query_string_parameters_ref();
+    let param = |name: &str| params.and_then(|p| p.first(name));
+    info!("list_legacy_credentials: {:?}", params);
+
+    let limit = match param("limit").map(str::parse::<i32>) {
+        None => DEFAULT_PAGE_LIMIT,
+        Some(Ok(limit)) if (1..=MAX_PAGE_LIMIT).contains(&limit) => limit,
+        Some(_) => return error_response(
+            StatusCode::BAD_REQUEST,
+            "bad_query",
+            &format!("limit must be between 1 and {}", MAX_PAGE_LIMIT),
+            Some("limit"),
+        ),
+    };
+    let exclusive_start_key = match param("nextToken").map(decode_page_token) {
+        None => None,
+        Some(Some(key)) => Some(key),
+        Some(None) => return error_response(
+            StatusCode::BAD_REQUEST,
+            "bad_query",
+            "malformed nextToken",
+            Some("nextToken"),
+        ),
+    };
+
+    let page = shared_state.users
+        .scan_legacy_credentials(limit, exclusive_start_key)
+        .await?;
+    let next_token = page.last_evaluated_key.as_ref()
+        .map(encode_page_token)
+        .transpose()?;
+    let credentials = page.credentials
+        .into_iter()
+        .filter_map(|item| Some(LegacyCredential {
+            legacy_rp_id: item.legacy_rp_id?,
+            user_handle: item.user_handle,
+            credential_id: item.credential_id,
+            last_used_at: item.last_used_at,
+        }))
+        .collect();
+    let body = serde_json::to_string(&LegacyCredentialList {
+        credentials,
+        next_token,
+    })?;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(body.into())?)
+}
+
+#[instrument(skip_all)]
+async fn revoke_credentials(
+    shared_state: Arc<SharedState>,
+    target: String,
+    credential_id: Option<String>,
+    admin_handle: String,
+    event: Request,
+) -> Result<Response<Body>, Error> {
+    let params = event.query_string_parameters_ref();
+    let param = |name: &str| params.and_then(|p| p.first(name));
+    info!(
+        "revoke_credentials: {} {:?} {:?}",
+        redact(&target),
+        credential_id.as_deref().map(redact),
+        params,
+    );
+
+    let action = match param("action") {
+        None | Some("delete") => RevokeAction::Delete,
+        Some("disable") => RevokeAction::Disable,
+        Some(_) => return error_response(
+            StatusCode::BAD_REQUEST,
+            "bad_query",
+            "action must be delete or disable",
+            Some("action"),
+        ),
+    };
+    let sign_out = match param("signOut").map(str::parse::<bool>) {
+        None => false,
+        Some(Ok(sign_out)) => sign_out,
+        Some(Err(_)) => return error_response(
+            StatusCode::BAD_REQUEST,
+            "bad_query",
+            "signOut must be true or false",
+            Some("signOut"),
+        ),
+    };
+
+    let single = credential_id.is_some();
+    let credential_ids = match credential_id {
+        Some(credential_id) => vec![credential_id],
+        None => shared_state.users
+            .list_credentials(&target)
+            .await?
+            .into_iter()
+            .map(|c| c.credential_id)
+            .collect(),
+    };
+    let now = DateTime::from(SystemTime::now())
+        .fmt(DateTimeFormat::DateTime)?;
+    let client = ClientInfo::of(&event);
+    let mut revoked = Vec::with_capacity(credential_ids.len());
+    for credential_id in credential_ids {
+        let key = CredentialKey {
+            user_handle: &target,
+            credential_id: &credential_id,
+        };
+        let found = match action {
+            RevokeAction::Delete =>
+                shared_state.users.delete_credential(key).await?,
+            RevokeAction::Disable =>
+                shared_state.users.disable_credential(key, now.clone()).await?,
+        };
+        if !found {
+            info!("credential not found: {}", truncate(&credential_id));
+            continue;
+        }
+        shared_state.audit_log.record(AuditEvent {
+            event_type: action.event_type(),
+            user_handle: target.clone(),
+            credential_id: Some(credential_id.clone()),
+            client: client.clone(),
+            detail: Some(format!("revoked by administrator {}", admin_handle)),
+        }).await?;
+        let revocation = DomainEvent::CredentialRevoked(CredentialRevoked {
+            user_handle: target.clone(),
+            credential_id: credential_id.clone(),
+            tenant_id: None,
+            action: action.past_tense(),
+            revoked_by: "administrator",
+        });
+        if matches!(action, RevokeAction::Delete) {
+            notify_webhook(shared_state.webhooks.as_ref(), &revocation).await;
+        }
+        publish_event(shared_state.event_publisher.as_ref(), revocation).await;
+        revoked.push(credential_id);
+    }
+    if single && revoked.is_empty() {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            "credential_not_found",
+            "no such credential",
+            None,
+        );
+    }
+
+    if sign_out {
+        shared_state.cognito
+            .admin_user_global_sign_out()
+            .user_pool_id(shared_state.user_pool_id.clone())
+            .username(target.clone())
+            .send()
+            .await?;
+    }
+
+    let body = serde_json::to_string(&RevokedCredentials {
+        user_handle: target,
+        action: action.as_str(),
+        credential_ids: revoked,
+        signed_out: sign_out,
+    })?;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(body.into())?)
+}
+
+#[instrument(skip_all)]
+async fn unlock(
+    shared_state: Arc<SharedState>,
+    target: String,
+    credential_id: String,
+    admin_handle: String,
+    event: Request,
+) -> Result<Response<Body>, Error> {
+    info!("unlock: {} {}", redact(&target), truncate(&credential_id));
+
+    let key = CredentialKey {
+        user_handle: &target,
+        credential_id: &credential_id,
+    };
+    if !unlock_credential(&shared_state.dynamodb, shared_state.users.table_name(), key).await? {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            "lockout_not_found",
+            "credential not locked",
+            None,
+        );
+    }
+    shared_state.audit_log.record(AuditEvent {
+        event_type: AuditEventType::CredentialUnlocked,
+        user_handle: target.clone(),
+        credential_id: Some(credential_id.clone()),
+        client: ClientInfo::of(&event),
+        detail: Some(format!("unlocked by administrator {}", admin_handle)),
+    }).await?;
+
+    Ok(Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::Empty)?)
+}
+
+// returns whether a given string is a date in the form of "yyyy-mm-dd".
+fn is_date(date: &str) -> bool {
+    let bytes = date.as_bytes();
+    bytes.len() == 10
+        && bytes.iter().enumerate().all(|(i, b)| match i {
+            4 | 7 => *b == b'-',
+            _ => b.is_ascii_digit(),
+        })
+}
+
+fn error_response(
+    status: StatusCode,
+    error: &'static str,
+    message: &str,
+    field: Option<&str>,
+) -> Result<Response<Body>, Error> {
+    let body = serde_json::to_string(&ErrorResponseBody {
+        error,
+        message: message.into(),
+        field: field.map(Into::into),
+        localized_message: None,
+        request_id: None,
+    })?;
+    Ok(Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(body.into())?)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let started_at = Instant::now();
+    let telemetry = init_tracing("admin")?;
+
+    let sdk_config = load_sdk_config().await?;
+    load_config_parameters(&aws_sdk_ssm::Client::new(&sdk_config)).await?;
+    let config = Config::from_env()?;
+    let router = router(config.cors.clone());
+    let max_body_size = config.max_body_size;
+    let shared_state = Arc::new(SharedState::new(&sdk_config, config).await?);
+    let metrics = load_metrics("admin")?;
+    let cold_start = ColdStart::initialized_since(started_at);
+    run_with_warmer(&cold_start, &metrics, |req: Request| async {
+        let span = request_span(&req);
+        let handler_started_at = Instant::now();
+        let res = negotiate_content(
+            req,
+            max_body_size,
+            |req| handle_api_errors(function_handler(shared_state.clone(), &router, req)),
+        )
+            .instrument(span)
+            .await;
+        cold_start.report(&metrics, handler_started_at.elapsed());
+        telemetry.flush().await;
+        res
+    }).await
+}
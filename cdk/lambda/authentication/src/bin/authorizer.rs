@@ -0,0 +1,154 @@
+//! Lambda authorizer of API Gateway that verifies tokens.
+//!
+//! Verifies the bearer token of a request, and responds with an IAM policy
+//! that allows the user to invoke every method of the API stage. Access
+//! tokens of the app clients of the Cognito user pool, or ID tokens if
+//! `USER_POOL_TOKEN_USE` is "id", and tokens self-issued after passkey
+//! authentication are accepted; see [`authentication::jwt`] for details. See
+//! [`authentication::authorizer`] for the supported events.
+//!
+//! The context of the policy carries:
+//! - `userHandle`: user handle of the authenticated user
+//! - `issuer`: issuer of the token
+//! - `groups`: comma-separated groups in the Cognito user pool that the user
+//!   belongs to
+//!
+//! You have to configure at least one of the following environment
+//! variables:
+//! - `USER_POOL_ID`: ID of the Cognito user pool whose tokens are trusted.
+//!   Requires `USER_POOL_CLIENT_IDS`.
+//! - `TOKEN_ISSUER`: issuer of self-issued tokens that are trusted. Requires
+//!   `TOKEN_JWKS_URL`.
+//!
+//! You can optionally configure the following environment variables:
+//! - `CONFIG_PARAMETER_PATH`: path to the parameters in Parameter Store on
+//!   AWS Systems Manager that override the other environment variables. See
+//!   [`authentication::config`] for details.
+//! - `USER_POOL_CLIENT_IDS`, `USER_POOL_TOKEN_USE`, `TOKEN_JWKS_URL`,
+//!   `TOKEN_AUDIENCE`, `JWKS_CACHE_TTL`, `JWT_CLOCK_SKEW`: see
+//!   [`authentication::jwt::load_jwt_verifier`].
+//! - `REQUIRED_GROUP`: group in the Cognito user pool that users must belong
+//!   to; e.g., "admin". Users outside the group are denied.
+//! - `LOG_LEVEL`, `LOG_REDACTION`: log level or `RUST_LOG`-style directives,
+//!   and whether identifiers are redacted in logs; "info" and redacted by
+//!   default. See [`authentication::telemetry`] for details.
+//! - `METRICS_NAMESPACE`: namespace of the CloudWatch metrics; "PasskeyTest"
+//!   by default. The following metrics are reported:
+//!     - `authorizer_allowed`: number of allowed requests
+//!     - `authorizer_denied`: number of requests denied for the group
+//!     - `authorizer_unauthorized`: number of requests without a valid token
+//!
+//! A request without a valid token fails with "Unauthorized", which API
+//! Gateway responds with 401. Enable the authorizer cache of API Gateway to
+//! avoid verifying the same token for every request; the policy covers the
+//! whole stage for that reason.
+//!
+//! The function fails at cold start if any required variable is missing or
+//! any variable is invalid, and the error lists all of them; see
+//! [`authentication::config`].
+
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{info, instrument};
+
+use authentication::authorizer::{AuthorizerRequest, AuthorizerResponse};
+use authentication::config::{self, ConfigCheck, load_config_parameters};
+use authentication::jwt::{JwtVerifier, load_jwt_verifier};
+use authentication::metrics::{ColdStart, Metrics, load_metrics};
+use authentication::telemetry::{init_tracing, redact};
+
+// Error message that API Gateway turns into 401.
+const UNAUTHORIZED: &str = "Unauthorized";
+
+// State shared among Lambda invocations.
+struct SharedState {
+    verifier: JwtVerifier,
+    required_group: Option<String>,
+}
+
+// Configuration validated at cold start.
+struct Config {
+    verifier: JwtVerifier,
+    required_group: Option<String>,
+}
+
+impl Config {
+    fn from_env() -> Result<Self, Error> {
+        let mut check = ConfigCheck::new();
+        check.required_any(&["USER_POOL_ID", "TOKEN_ISSUER"]);
+        let verifier = check.load(load_jwt_verifier());
+        let required_group = config::var("REQUIRED_GROUP").ok()
+            .filter(|group| !group.is_empty());
+        // `required_any` has reported a missing issuer with the other problems
+        let verifier = check.finish(verifier)?
+            .ok_or("USER_POOL_ID or TOKEN_ISSUER must be set")?;
+        Ok(Self { verifier, required_group })
+    }
+}
+
+impl SharedState {
+    #[instrument(name = "cold_start", skip_all)]
+    fn new(config: Config) -> Self {
+        Self {
+            verifier: config.verifier,
+            required_group: config.required_group,
+        }
+    }
+}
+
+#[instrument(skip_all)]
+async fn function_handler(
+    shared_state: Arc<SharedState>,
+    metrics: &Metrics,
+    event: LambdaEvent<AuthorizerRequest>,
+) -> Result<AuthorizerResponse, Error> {
+    let request = event.payload;
+    let Some(token) = request.bearer_token() else {
+        info!("no bearer token");
+        metrics.count("authorizer_unauthorized");
+        return Err(UNAUTHORIZED.into());
+    };
+    let verified = match shared_state.verifier.verify(token).await {
+        Ok(verified) => verified,
+        Err(e) => {
+            info!("invalid token: {}", e);
+            metrics.count("authorizer_unauthorized");
+            return Err(UNAUTHORIZED.into());
+        }
+    };
+    let resource = request.api_wildcard_arn();
+    if let Some(group) = shared_state.required_group.as_ref() {
+        if !verified.groups.contains(group) {
+            info!("user not in group {}: {}", group, redact(&verified.user_handle));
+            metrics.count("authorizer_denied");
+            return Ok(AuthorizerResponse::deny(verified.user_handle, resource));
+        }
+    }
+    info!("authorized: {}", redact(&verified.user_handle));
+    metrics.count("authorizer_allowed");
+    Ok(AuthorizerResponse::allow(verified.user_handle.clone(), resource)
+        .with_context("userHandle", verified.user_handle)
+        .with_context("issuer", verified.issuer)
+        .with_context("groups", verified.groups.join(",")))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let started_at = Instant::now();
+    let telemetry = init_tracing("authorizer")?;
+
+    let sdk_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+    load_config_parameters(&aws_sdk_ssm::Client::new(&sdk_config)).await?;
+    let config = Config::from_env()?;
+    let shared_state = Arc::new(SharedState::new(config));
+    let metrics = load_metrics("authorizer")?;
+    let cold_start = ColdStart::initialized_since(started_at);
+    run(service_fn(|event| async {
+        let handler_started_at = Instant::now();
+        let res = function_handler(shared_state.clone(), &metrics, event).await;
+        cold_start.report(&metrics, handler_started_at.elapsed());
+        telemetry.flush().await;
+        res
+    })).await
+}
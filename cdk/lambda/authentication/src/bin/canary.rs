@@ -0,0 +1,314 @@
+//! Scheduled Lambda function that runs a synthetic canary against a stage.
+//!
+//! Registers a new user with the software authenticator, authenticates the
+//! user through Cognito, and deletes the account after a step-up, through the
+//! deployed Credentials API, so that a regression in the configuration or the
+//! tables of the stage shows up in the metrics. See
+//! [`authentication::canary`] for the steps and metrics.
+//!
+//! This binary is available only if the `canary` feature is enabled.
+//!
+//! You have to configure the following environment variables:
+//! - `CREDENTIALS_API_URL`: URL of the Credentials API; e.g.,
+//!   `https://xxxxxxxxxx.execute-api.ap-northeast-1.amazonaws.com/auth/credentials/`
+//! - `USER_POOL_CLIENT_ID`: ID of the Cognito user pool client
+//! - `RP_ORIGIN_PARAMETER_PATH`: path to the parameter that stores the origin
+//!   (URL) of the relying party in Parameter Store on AWS Systems Manager
+//!
+//! You can optionally configure the following environment variables:
+//! - `CONFIG_PARAMETER_PATH`: path to the parameters in Parameter Store on
+//!   AWS Systems Manager that override the other environment variables. See
+//!   [`authentication::config`] for details.
+//! - `RP_ORIGIN`: origin (URL) of the relying party that takes precedence over
+//!   `RP_ORIGIN_PARAMETER_PATH`
+//! - `LOG_LEVEL`, `LOG_REDACTION`: log level or `RUST_LOG`-style directives,
+//!   and whether identifiers are redacted in logs; "info" and redacted by
+//!   default. See [`authentication::telemetry`] for details.
+//! - `METRICS_NAMESPACE`: namespace of the CloudWatch metrics; "PasskeyTest"
+//!   by default.
+//!
+//! Any event invokes a run; e.g., a scheduled event of Amazon EventBridge.
+//! The function fails if any step fails, so that the errors of the function
+//! also tell the failure.
+
+use aws_sdk_cognitoidentityprovider::{
+    error::DisplayErrorContext,
+    types::{AuthFlowType, ChallengeNameType},
+};
+use base64::{
+    Engine as _,
+    engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
+};
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{error, info, instrument};
+use webauthn_rs::prelude::{Url, Uuid};
+
+use authentication::authenticator::{SoftwareCredential, client_data_json};
+use authentication::canary::{CanaryStep, USERNAME_PREFIX, report_run};
+use authentication::config;
+use authentication::metrics::{ColdStart, Metrics, load_metrics};
+use authentication::parameters::load_relying_party_origin;
+use authentication::step_up::STEP_UP_TOKEN_HEADER;
+use authentication::telemetry::{init_tracing, redact};
+
+// Stage under test.
+struct Stage {
+    http: reqwest::Client,
+    cognito: aws_sdk_cognitoidentityprovider::Client,
+    credentials_api_url: String,
+    rp_id: String,
+    rp_origin: String,
+    user_pool_client_id: String,
+}
+
+// Subset of `StartRegistrationSession`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StartRegistrationSession {
+    session_id: String,
+    credential_creation_options: ChallengeOptions,
+}
+
+// Subset of `StartStepUpSession`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StartStepUpSession {
+    session_id: String,
+    credential_request_options: ChallengeOptions,
+}
+
+// Subset of credential creation and request options.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChallengeOptions {
+    public_key: PublicKeyOptions,
+}
+
+#[derive(Deserialize)]
+struct PublicKeyOptions {
+    challenge: String,
+    user: Option<UserEntity>,
+}
+
+#[derive(Deserialize)]
+struct UserEntity {
+    id: String,
+}
+
+// Subset of `StepUpResult`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StepUpResult {
+    step_up_token: String,
+}
+
+impl Stage {
+    #[instrument(name = "cold_start")]
+    async fn new() -> Result<Self, Error> {
+        let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+        let (rp_id, rp_origin) =
+            load_relying_party_origin(aws_sdk_ssm::Client::new(&config)).await?;
+        let credentials_api_url = config::var("CREDENTIALS_API_URL")
+            .or(Err("CREDENTIALS_API_URL env must be set"))?;
+        Url::parse(&credentials_api_url)
+            .or(Err("CREDENTIALS_API_URL must be a URL"))?;
+        Ok(Self {
+            http: reqwest::Client::new(),
+            cognito: aws_sdk_cognitoidentityprovider::Client::new(&config),
+            credentials_api_url: credentials_api_url.trim_end_matches('/').into(),
+            rp_id,
+            rp_origin: rp_origin.origin().ascii_serialization(),
+            user_pool_client_id: config::var("USER_POOL_CLIENT_ID")
+                .or(Err("USER_POOL_CLIENT_ID env must be set"))?,
+        })
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.credentials_api_url, path)
+    }
+
+    // registers a new user and returns the credential.
+    async fn register(&self) -> Result<SoftwareCredential, Error> {
+        let session: StartRegistrationSession = self.http
+            .post(self.url("registration/start"))
+            .json(&json!({
+                "username": format!("{}{}", USERNAME_PREFIX, Uuid::new_v4()),
+                "displayName": "Canary",
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let options = session.credential_creation_options.public_key;
+        let user_handle = options.user
+            .ok_or("missing user in credential creation options")?;
+        let credential = SoftwareCredential::generate(
+            &self.rp_id,
+            base64url.decode(&user_handle.id)?,
+        )?;
+        info!("registering: {}", redact(&user_handle.id));
+        let client_data = client_data_json(
+            "webauthn.create",
+            &options.challenge,
+            &self.rp_origin,
+        );
+        self.http
+            .post(self.url("registration/finish"))
+            .json(&json!({
+                "sessionId": session.session_id,
+                "publicKeyCredential": credential.registration_credential(&client_data),
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(credential)
+    }
+
+    // authenticates with a given credential through Cognito and returns the
+    // ID token.
+    async fn authenticate(&self, credential: &mut SoftwareCredential) -> Result<String, Error> {
+        let options: ChallengeOptions = self.http
+            .post(self.url("discoverable/start"))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let assertion = self.assert(credential, &options.public_key.challenge)?;
+        let user_handle = base64url.encode(&credential.user_handle);
+        let challenge = self.cognito
+            .initiate_auth()
+            .client_id(self.user_pool_client_id.clone())
+            .auth_flow(AuthFlowType::CustomAuth)
+            .auth_parameters("USERNAME", &user_handle)
+            .send()
+            .await
+            .map_err(|e| format!("{}", DisplayErrorContext(&e)))?;
+        let res = self.cognito
+            .respond_to_auth_challenge()
+            .client_id(self.user_pool_client_id.clone())
+            .challenge_name(ChallengeNameType::CustomChallenge)
+            .set_session(challenge.session)
+            .challenge_responses("USERNAME", &user_handle)
+            .challenge_responses("ANSWER", serde_json::to_string(&assertion)?)
+            .send()
+            .await
+            .map_err(|e| format!("{}", DisplayErrorContext(&e)))?;
+        match res.authentication_result.and_then(|r| r.id_token) {
+            Some(id_token) => Ok(id_token),
+            None => Err(format!("further challenge: {:?}", res.challenge_name).into()),
+        }
+    }
+
+    // deletes the account of an authenticated user after a step-up.
+    async fn clean_up(
+        &self,
+        credential: &mut SoftwareCredential,
+        id_token: &str,
+    ) -> Result<(), Error> {
+        let session: StartStepUpSession = self.http
+            .post(self.url("user/step-up/start"))
+            .bearer_auth(id_token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let assertion = self.assert(
+            credential,
+            &session.credential_request_options.public_key.challenge,
+        )?;
+        let step_up: StepUpResult = self.http
+            .post(self.url("user/step-up/finish"))
+            .bearer_auth(id_token)
+            .json(&json!({
+                "sessionId": session.session_id,
+                "publicKeyCredential": assertion,
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        self.http
+            .delete(self.url("user/account"))
+            .bearer_auth(id_token)
+            .header(STEP_UP_TOKEN_HEADER, step_up.step_up_token)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    fn assert(
+        &self,
+        credential: &mut SoftwareCredential,
+        challenge: &str,
+    ) -> Result<Value, Error> {
+        let client_data = client_data_json("webauthn.get", challenge, &self.rp_origin);
+        Ok(credential.assertion_credential(&client_data)?)
+    }
+}
+
+// runs a step and reports the result.
+async fn run_step<T>(
+    metrics: &Metrics,
+    step: CanaryStep,
+    task: impl Future<Output = Result<T, Error>>,
+) -> Result<T, Error> {
+    let started_at = Instant::now();
+    let res = task.await;
+    step.report(metrics, res.is_ok(), started_at.elapsed());
+    match &res {
+        Ok(_) => info!("PASS {}", step),
+        Err(e) => error!("FAIL {}: {}", step, e),
+    }
+    res
+}
+
+#[instrument(skip_all)]
+async fn function_handler(
+    stage: Arc<Stage>,
+    metrics: &Metrics,
+    _event: LambdaEvent<Value>,
+) -> Result<Value, Error> {
+    let res = async {
+        let mut credential =
+            run_step(metrics, CanaryStep::Registration, stage.register()).await?;
+        let id_token = run_step(
+            metrics,
+            CanaryStep::Authentication,
+            stage.authenticate(&mut credential),
+        ).await?;
+        run_step(
+            metrics,
+            CanaryStep::Cleanup,
+            stage.clean_up(&mut credential, &id_token),
+        ).await
+    }.await;
+    report_run(metrics, res.is_ok());
+    res.map(|_| json!({ "passed": true }))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let started_at = Instant::now();
+    let telemetry = init_tracing("canary")?;
+
+    let stage = Arc::new(Stage::new().await?);
+    let metrics = load_metrics("canary")?;
+    let cold_start = ColdStart::initialized_since(started_at);
+    run(service_fn(|event| async {
+        let handler_started_at = Instant::now();
+        let res = function_handler(stage.clone(), &metrics, event).await;
+        cold_start.report(&metrics, handler_started_at.elapsed());
+        telemetry.flush().await;
+        res
+    })).await
+}
@@ -0,0 +1,130 @@
+//! Scheduled Lambda function that purges soft-deleted credentials.
+//!
+//! Deletes the credentials whose retention window has elapsed since they
+//! were deleted by users. See [`authentication::deletion`] for details. The
+//! `data-retention` function also purges them together with audit events and
+//! sessions.
+//!
+//! You have to configure the following environment variables:
+//! - `CREDENTIAL_TABLE_NAME`: name of the DynamoDB table that manages
+//!   credentials
+//!
+//! You can optionally configure the following environment variables:
+//! - `CONFIG_PARAMETER_PATH`: path to the parameters in Parameter Store on
+//!   AWS Systems Manager that override the other environment variables. See
+//!   [`authentication::config`] for details.
+//! - `DELETION_RETENTION`: retention window of deleted credentials in
+//!   seconds; 30 days by default. Must be the same as the credentials
+//!   function. "0" purges every deleted credential.
+//! - `PII_KMS_KEY_ARN`, `PII_INDEX_SECRET_ID`: ARN of the KMS key and ID of
+//!   the secret in Secrets Manager that protect the usernames and display
+//!   names of users. Stored in plaintext unless specified. See
+//!   [`authentication::pii`].
+//! - `LOG_LEVEL`, `LOG_REDACTION`: log level or `RUST_LOG`-style directives,
+//!   and whether identifiers are redacted in logs; "info" and redacted by
+//!   default. See [`authentication::telemetry`] for details.
+//! - `METRICS_NAMESPACE`: namespace of the CloudWatch metrics; "PasskeyTest"
+//!   by default. The number of purged credentials is reported as
+//!   `credentials_purged`.
+//!
+//! Any event invokes a cleanup; e.g., a scheduled event of Amazon
+//! EventBridge. A credential restored or deleted again during the cleanup is
+//! not purged.
+//!
+//! The function fails at cold start if any required variable is missing or
+//! any variable is invalid, and the error lists all of them; see
+//! [`authentication::config`].
+
+use aws_config::SdkConfig;
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, instrument};
+
+use authentication::config::{ConfigCheck, load_config_parameters};
+use authentication::deletion::{
+    load_deletion_retention,
+    purge_deleted_credentials,
+    retention_cutoff,
+};
+use authentication::metrics::{ColdStart, Metrics, Unit, load_metrics};
+use authentication::pii::load_pii_protection;
+use authentication::telemetry::init_tracing;
+use authentication::users::UserDirectory;
+
+// State shared among Lambda invocations.
+struct SharedState {
+    users: UserDirectory,
+    deletion_retention: Option<Duration>,
+}
+
+// Configuration validated at cold start.
+struct Config {
+    credential_table_name: String,
+    deletion_retention: Option<Duration>,
+}
+
+impl Config {
+    fn from_env() -> Result<Self, Error> {
+        let mut check = ConfigCheck::new();
+        let config = Self {
+            credential_table_name: check.required("CREDENTIAL_TABLE_NAME"),
+            deletion_retention: check.load(load_deletion_retention()),
+        };
+        Ok(check.finish(config)?)
+    }
+}
+
+impl SharedState {
+    #[instrument(name = "cold_start", skip_all)]
+    fn new(sdk_config: &SdkConfig, config: Config) -> Result<Self, Error> {
+        Ok(Self {
+            users: UserDirectory::new(
+                aws_sdk_dynamodb::Client::new(sdk_config),
+                config.credential_table_name,
+            ).with_pii_protection(load_pii_protection(
+                aws_sdk_kms::Client::new(sdk_config),
+                aws_sdk_secretsmanager::Client::new(sdk_config),
+            )?),
+            deletion_retention: config.deletion_retention,
+        })
+    }
+}
+
+#[instrument(skip_all)]
+async fn function_handler(
+    shared_state: Arc<SharedState>,
+    metrics: &Metrics,
+    _event: LambdaEvent<Value>,
+) -> Result<Value, Error> {
+    let cutoff = retention_cutoff(
+        SystemTime::now(),
+        shared_state.deletion_retention.unwrap_or(Duration::ZERO),
+    )?;
+    info!("purging credentials deleted before {}", cutoff);
+    let purged = purge_deleted_credentials(&shared_state.users, cutoff).await?;
+    info!("purged {} credentials", purged);
+    metrics.put("credentials_purged", purged as f64, Unit::Count);
+    Ok(json!({ "purged": purged }))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let started_at = Instant::now();
+    let telemetry = init_tracing("credential-cleanup")?;
+
+    let sdk_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+    load_config_parameters(&aws_sdk_ssm::Client::new(&sdk_config)).await?;
+    let config = Config::from_env()?;
+    let shared_state = Arc::new(SharedState::new(&sdk_config, config)?);
+    let metrics = load_metrics("credential-cleanup")?;
+    let cold_start = ColdStart::initialized_since(started_at);
+    run(service_fn(|event| async {
+        let handler_started_at = Instant::now();
+        let res = function_handler(shared_state.clone(), &metrics, event).await;
+        cold_start.report(&metrics, handler_started_at.elapsed());
+        telemetry.flush().await;
+        res
+    })).await
+}
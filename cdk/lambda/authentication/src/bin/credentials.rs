@@ -0,0 +1,1380 @@
+//! Credential management of authenticated users.
+//!
+//! You have to configure the following environment variables:
+//! - `BASE_PATH`: base path to provide the service; e.g., `/auth/credentials/user/`
+//! - `CREDENTIAL_TABLE_NAME`: name of the DynamoDB table that manages
+//!   credentials
+//! - `SESSION_TABLE_NAME`: name of the DynamoDB table to store step-up
+//!   sessions and tokens
+//! - `USER_POOL_ID`: ID of the Cognito user pool
+//! - `RP_ORIGIN_PARAMETER_PATH`: path to the parameter that stores the origin
+//!   (URL) of the relying party in the Parameter Store on AWS Systems Manager
+//!
+//! You can optionally configure the following environment variables:
+//! - `CONFIG_PARAMETER_PATH`: path to the parameters in Parameter Store on
+//!   AWS Systems Manager that override the other environment variables. See
+//!   [`authentication::config`] for details.
+//! - `RP_ORIGIN`: origin (URL) of the relying party that takes precedence over
+//!   `RP_ORIGIN_PARAMETER_PATH`
+//! - `RP_ID`: ID of the relying party; the domain of the origin by default
+//! - `RP_ALLOWED_ORIGINS`: comma-separated origins (URLs) allowed in addition
+//!   to the origin of the relying party; e.g., `https://www.example.com`
+//! - `ANDROID_APK_KEY_HASHES`: comma-separated SHA-256 hashes of the
+//!   certificates that sign Android apps allowed to use the relying party.
+//!   See [`authentication::android`] for details.
+//! - `CHALLENGE_TIMEOUT`: timeout of a step-up in seconds; 60 by default.
+//!   Given to the client as the `timeout` of the request options, and step-up
+//!   sessions expire after it.
+//! - `MAX_BODY_SIZE`: maximum size of a request body in bytes; 32 KiB by
+//!   default. Larger requests are rejected with 413.
+//! - `USERNAME_MIN_LENGTH`, `USERNAME_MAX_LENGTH`, `USERNAME_CHARSET`,
+//!   `USERNAME_LOWERCASE`, `USERNAME_EMAIL`: username validation policy
+//!   applied to a new username. Must be the same as the registration. See
+//!   [`load_username_policy`] for details.
+//! - `AUDIT_TABLE_NAME`: name of the DynamoDB table for the audit log.
+//!   Deleted and restored credentials and failed step-ups are recorded if
+//!   specified.
+//! - `EVENT_BUS_NAME`: name of the EventBridge event bus. Deleted credentials
+//!   are published as `CredentialRevoked` if specified; see
+//!   [`authentication::domain_events`].
+//! - `WEBHOOK_URL`, `WEBHOOK_SECRET_ID`: URL to which deleted credentials are
+//!   posted and the ID of the secret that signs the requests. Disabled unless
+//!   specified; see [`authentication::webhooks`].
+//! - `DELETION_RETENTION`: retention window of deleted credentials in
+//!   seconds; 30 days by default. Deleted credentials can be restored within
+//!   the window, and "0" deletes credentials immediately. See
+//!   [`authentication::deletion`].
+//! - `MAX_CREDENTIALS_PER_USER`: maximum number of credentials per user.
+//!   Restoring a deleted credential beyond the limit is rejected with 409
+//!   and `credential_limit_exceeded`. Must be the same as the registration.
+//!   Unlimited unless specified.
+//! - `SESSION_ID_SECRET_ID`: ID of the secret in Secrets Manager that signs
+//!   session IDs of step-ups. Step-ups with a forged or truncated session ID
+//!   fail without looking up the session table. Session IDs are not signed
+//!   unless specified. See [`authentication::session_id`].
+//! - `LARGE_BLOB`: support of the `largeBlob` extension; "required" or
+//!   "preferred". Step-ups request to read the large blob, and the outputs
+//!   are passed through in [`StepUpResult`] if specified. See
+//!   [`load_extension_policy`] for details.
+//! - `PRF`, `PRF_SALT`: whether the `prf` extension is evaluated and its
+//!   salt. The outputs of step-ups are passed through in [`StepUpResult`].
+//!   Disabled unless specified.
+//! - `TENANT_TABLE_NAME`, `TENANT_RESOLUTION`: table of tenants and how a
+//!   tenant is resolved from a request. Step-ups are verified by the default
+//!   relying party unless specified. See [`authentication::tenant`] for
+//!   details.
+//! - `CORS_ALLOWED_ORIGINS`, `CORS_MAX_AGE`: origins allowed to call the API
+//!   and the lifetime of a preflight response. No CORS headers are added
+//!   unless specified; e.g., when API Gateway handles CORS. See
+//!   [`load_cors_policy`] for details.
+//! - `EXPORT_BUCKET_NAME`, `EXPORT_INLINE_LIMIT`, `EXPORT_URL_TTL`: S3 bucket
+//!   to which large exports of `account/export` are uploaded, the maximum
+//!   size of an export returned in a response body, and the lifetime of
+//!   pre-signed URLs. Every export is returned in a response body unless
+//!   specified. See [`load_export_delivery`] for details.
+//! - `BEARER_AUTH`: "true" to verify bearer tokens in the function instead of
+//!   a JWT authorizer of API Gateway. Access tokens of the app clients in
+//!   `USER_POOL_CLIENT_IDS` of the user pool in `USER_POOL_ID` are trusted;
+//!   see [`load_bearer_auth`] for details.
+//! - `PII_KMS_KEY_ARN`, `PII_INDEX_SECRET_ID`: ARN of the KMS key and ID of
+//!   the secret in Secrets Manager that protect the usernames and display
+//!   names of users. Stored in plaintext unless specified. See
+//!   [`authentication::pii`].
+//! - `LOG_LEVEL`, `LOG_REDACTION`: log level or `RUST_LOG`-style directives,
+//!   and whether identifiers are redacted in logs; "info" and redacted by
+//!   default. See [`authentication::telemetry`] for details.
+//! - `METRICS_NAMESPACE`: namespace of the CloudWatch metrics; "PasskeyTest"
+//!   by default. The cold start of the function is reported as metrics; see
+//!   [`ColdStart`].
+//!
+//! The function fails at cold start if any required variable is missing or
+//! any variable is invalid, and the error lists all of them; see
+//! [`authentication::config`].
+//!
+//! Every endpoint must be protected by a JWT authorizer that verifies tokens
+//! issued by the Cognito user pool, unless `BEARER_AUTH` is enabled. A
+//! request without a valid token ends with 401.
+//!
+//! ## Endpoints
+//!
+//! Provides the following endpoints under the base path.
+//! Every endpoint is versioned under `${BASE_PATH}v1/`; e.g.,
+//! `${BASE_PATH}v1/credentials`. Paths without a version are routed to v1
+//! for backward compatibility, and unsupported versions end with 404.
+//! If tenants are configured and resolved by path, every path is prefixed
+//! with the tenant key; e.g., `${BASE_PATH}acme/v1/credentials`. Requests for
+//! an unknown tenant end with 404.
+//! Request and response bodies may be CBOR instead of JSON; see
+//! [`authentication::content`]. A request body is `application/cbor` if so
+//! specified in `Content-Type`, and a response body is `application/cbor` if
+//! `Accept` prefers it.
+//! Large response bodies are compressed with Brotli or gzip if
+//! `Accept-Encoding` allows it.
+//! Error responses carry a `localizedMessage` in English or Japanese as
+//! `Accept-Language` prefers; see [`authentication::i18n`].
+//! `GET ${BASE_PATH}health` serves the health check, which requires no
+//! authentication and is neither versioned nor scoped to a tenant; see
+//! [`authentication::health`].
+//! A scheduled warm-up event is answered with 200 without serving a request;
+//! see [`authentication::warmer`].
+//! A client error like an expired session or a failed verification ends with
+//! the status code of the [`ApiError`] and an [`ErrorResponseBody`]; see
+//! [`authentication::api_error`].
+//! A path that no endpoint serves ends with 404, and a method that the
+//! endpoint does not accept ends with 405; see [`Router`].
+//!
+//! ### `GET ${BASE_PATH}credentials`
+//!
+//! Lists the credentials of the authenticated user.
+//! The following query parameters are optional:
+//! - `limit`: maximum number of credentials evaluated in a page; 1–100. 50 by
+//!   default. A page may contain fewer credentials if filtered.
+//! - `nextToken`: token to obtain the next page
+//! - `credentialType`: lists only credentials of the type; "passkey" or
+//!   "securityKey"
+//! - `backupState`: lists only credentials that are ("true") or are not
+//!   ("false") backed up
+//!
+//! Each credential tells when it was last used (`lastUsedAt`) and how many
+//! times it has been used for authentication (`authCount`), so that stale
+//! credentials can be identified.
+//! The response body is [`CredentialList`] as `application/json`.
+//! Requests with bad query parameters are rejected with 400 and
+//! [`ErrorResponseBody`] as `application/json`.
+//!
+//! ### `GET ${BASE_PATH}public-keys`
+//!
+//! Lists the public keys of the enabled credentials of the authenticated user
+//! as a JSON Web Key Set, so that other backends can verify signatures made
+//! with the same passkeys; e.g., assertions over a transaction.
+//! Each key is identified by the credential ID (`kid`) and carries the JWS
+//! (`alg`) and COSE (`coseAlg`) algorithms.
+//! Credentials whose keys have no JWK representation are omitted.
+//! The response body is [`CredentialPublicKeys`] as `application/json`.
+//!
+//! ### `POST ${BASE_PATH}recovery-codes`
+//!
+//! Regenerates the recovery codes of the authenticated user. The previous
+//! codes are invalidated.
+//! The response body is [`RecoveryCodes`] as `application/json`.
+//!
+//! ### `POST ${BASE_PATH}username`
+//!
+//! Changes the username of the authenticated user.
+//! The request body must be [`UsernameChange`] as `application/json`.
+//! The username is normalized and validated according to the username policy;
+//! a bad username is rejected with 400 and [`ErrorResponseBody`].
+//! The user handle and the registered credentials stay intact, and the
+//! `preferred_username` attribute of the user in the Cognito user pool is
+//! also updated. The old username is restored if the update of the Cognito
+//! user pool fails. The audit log does not record the new username.
+//! Ends with 409 and [`ErrorResponseBody`] if another user has the username,
+//! or a concurrent update conflicted; the latter case may be retried.
+//! Ends with 204 on success.
+//!
+//! ### `POST ${BASE_PATH}step-up/start`
+//!
+//! Starts step-up re-authentication of the authenticated user.
+//! Only the enabled passkeys of the user are allowed, and user verification is
+//! required.
+//! The response body is [`StartStepUpSession`] as `application/json`.
+//!
+//! ### `POST ${BASE_PATH}step-up/finish`
+//!
+//! Verifies the assertion and issues a step-up token valid for 5 minutes.
+//! The request body must be [`FinishStepUpSession`] as `application/json`.
+//! A failed verification, an expired session, or a session of another user
+//! is rejected with 401 and [`ErrorResponseBody`].
+//! An assertion without the user verified (UV) flag in the authenticator data
+//! is rejected with 401 and `user_verification_required`.
+//! The response body is [`StepUpResult`] as `application/json`.
+//!
+//! ### `DELETE ${BASE_PATH}credentials/{credentialId}`
+//!
+//! Deletes a credential of the authenticated user.
+//! Requires a step-up token in the `X-Step-Up-Token` header; requests without
+//! a valid token are rejected with 403 and [`ErrorResponseBody`].
+//! Unless soft deletion is off, the credential is disabled and listed with
+//! `deletedAt` until the retention window elapses, and then purged by the
+//! `data-retention` or `credential-cleanup` job.
+//! Ends with 404 if the credential does not exist or has already been
+//! deleted, and with 204 on success.
+//!
+//! ### `POST ${BASE_PATH}credentials/{credentialId}/restore`
+//!
+//! Restores a deleted credential of the authenticated user within the
+//! retention window, so that it can be used for authentication again.
+//! Requires a step-up token in the `X-Step-Up-Token` header; requests without
+//! a valid token are rejected with 403 and [`ErrorResponseBody`].
+//! A credential disabled by an administrator before it was deleted cannot be
+//! restored.
+//! Ends with 409 and `credential_limit_exceeded` if the user already has as
+//! many credentials as the limit.
+//! Ends with 404 if the credential is not restorable, and with 204 on
+//! success; a `credential_restored` event is recorded in the audit log.
+//!
+//! ### `GET ${BASE_PATH}account/export`
+//!
+//! Exports the personal data of the authenticated user; i.e., the profile,
+//! the metadata of the credentials, the audit events, and the sessions.
+//! The response body is [`AccountExport`] as `application/json`.
+//! If the export is larger than `EXPORT_INLINE_LIMIT` and
+//! `EXPORT_BUCKET_NAME` is configured, the export is uploaded to the bucket,
+//! and the response body is [`ExportDownload`] as `application/json`, whose
+//! `downloadUrl` is valid for `EXPORT_URL_TTL`.
+//!
+//! ### `DELETE ${BASE_PATH}account`
+//!
+//! Deletes the account of the authenticated user; i.e., every credential,
+//! the recovery codes, and the user in the credential table, and the user in
+//! the Cognito user pool.
+//! Requires a step-up token in the `X-Step-Up-Token` header; requests without
+//! a valid token are rejected with 403 and [`ErrorResponseBody`].
+//! Idempotent; a retry with the same step-up token succeeds while the token
+//! is valid, even if the previous request failed halfway. Sessions and tokens
+//! of the user in the session table are left to expire, and refresh tokens
+//! of the deleted user are rejected.
+//! Ends with 204, and an `account_deleted` event is recorded in the audit
+//! log.
+
+use aws_config::SdkConfig;
+use aws_sdk_cognitoidentityprovider::types::AttributeType as UserAttributeType;
+use aws_sdk_dynamodb::primitives::{DateTime, DateTimeFormat};
+use base64::{
+    Engine as _,
+    engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
+};
+use lambda_http::{
+    Body,
+    Error,
+    Request,
+    RequestExt,
+    Response,
+    http::StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{Instrument, Span, error, field, info, info_span, instrument, warn};
+use webauthn_rs::{
+    prelude::{AuthenticationResult, Passkey, PasskeyAuthentication},
+};
+use webauthn_rs_proto::options::UserVerificationPolicy;
+
+use authentication::account_export::{
+    AccountExport,
+    AccountProfile,
+    ExportDelivery,
+    ExportDownload,
+    list_user_audit_events,
+    list_user_sessions,
+    load_export_delivery,
+};
+use authentication::api_error::{ApiError, handle_api_errors};
+use authentication::audit::{
+    AuditEvent,
+    AuditEventType,
+    AuditLog,
+    ClientInfo,
+    load_audit_log,
+};
+use authentication::config::{ConfigCheck, load_config_parameters};
+use authentication::content::negotiate_content;
+use authentication::correlation::load_sdk_config;
+use authentication::credentials::{CredentialInfo, CredentialPublicKeys};
+use authentication::deletion::{
+    deletion_timestamp,
+    load_deletion_retention,
+    retention_cutoff,
+};
+use authentication::domain_events::{
+    CredentialRevoked,
+    DomainEvent,
+    EventPublisher,
+    load_event_publisher,
+    publish_event,
+};
+use authentication::extensions::{
+    ExtensionOutputs,
+    ExtensionPolicy,
+    load_extension_policy,
+};
+use authentication::health::{HEALTH_PATH, health_check};
+use authentication::identity::authenticated_user_handle;
+use authentication::items::{
+    CredentialItem,
+    CredentialKey,
+    StepUpSessionItem,
+    user_handle_of,
+};
+use authentication::metrics::{ColdStart, load_metrics};
+use authentication::pagination::{decode_page_token, encode_page_token};
+use authentication::parameters::load_webauthn;
+use authentication::passkey::{PasskeyProperties, is_user_verified_in};
+use authentication::payload::{
+    ErrorResponseBody,
+    PayloadError,
+    load_max_body_size,
+    parse_json_payload,
+};
+use authentication::pii::load_pii_protection;
+use authentication::policy::{
+    ChallengeTimeout,
+    CredentialLimit,
+    load_challenge_timeout,
+    load_credential_limit,
+};
+use authentication::recovery::new_recovery_codes;
+use authentication::routing::{
+    ApiVersion,
+    BearerAuth,
+    CorsPolicy,
+    RouteParams,
+    Router,
+    job_path,
+    load_bearer_auth,
+    load_cors_policy,
+    require_json_body,
+    resolve_version,
+    unsupported_version,
+};
+use authentication::session_id::{SessionIds, load_session_ids};
+use authentication::step_up::{
+    FinishStepUpSession,
+    StartStepUpSession,
+    StepUpResult,
+    issue_step_up_token,
+    save_step_up_session,
+    step_up_token,
+    take_step_up_session,
+    verify_step_up_token,
+};
+use authentication::store::DynamoDbSessionStore;
+use authentication::telemetry::{init_tracing, redact, request_span, truncate};
+use authentication::tenant::{
+    Tenant,
+    TenantDirectory,
+    load_tenant_directory,
+    unknown_tenant,
+};
+use authentication::username::{UsernamePolicy, load_username_policy};
+use authentication::users::{CredentialFilter, RenameUserError, UserDirectory};
+use authentication::warmer::run_with_warmer;
+use authentication::webhooks::{WebhookNotifier, load_webhook_notifier, notify_webhook};
+
+// Default number of credentials in a page.
+const DEFAULT_PAGE_LIMIT: i32 = 50;
+
+// Maximum number of credentials in a page.
+const MAX_PAGE_LIMIT: i32 = 100;
+
+// State shared among Lambda invocations.
+struct SharedState {
+    default_tenant: Arc<Tenant>,
+    tenants: Option<TenantDirectory>,
+    dynamodb: aws_sdk_dynamodb::Client,
+    cognito: aws_sdk_cognitoidentityprovider::Client,
+    base_path: String,
+    user_pool_id: String,
+    session_table_name: String,
+    sessions: DynamoDbSessionStore,
+    challenge_timeout: ChallengeTimeout,
+    max_body_size: usize,
+    username_policy: UsernamePolicy,
+    users: UserDirectory,
+    audit_log: Option<AuditLog>,
+    event_publisher: Option<EventPublisher>,
+    webhooks: Option<WebhookNotifier>,
+    export_delivery: Option<ExportDelivery>,
+    session_ids: SessionIds,
+    extension_policy: ExtensionPolicy,
+    deletion_retention: Option<Duration>,
+    credential_limit: Option<CredentialLimit>,
+}
+
+// Configuration validated at cold start.
+struct Config {
+    base_path: String,
+    user_pool_id: String,
+    session_table_name: String,
+    credential_table_name: String,
+    challenge_timeout: ChallengeTimeout,
+    max_body_size: usize,
+    username_policy: UsernamePolicy,
+    extension_policy: ExtensionPolicy,
+    deletion_retention: Option<Duration>,
+    credential_limit: Option<CredentialLimit>,
+    cors: Option<CorsPolicy>,
+    bearer_auth: Option<BearerAuth>,
+}
+
+impl Config {
+    fn from_env() -> Result<Self, Error> {
+        let mut check = ConfigCheck::new();
+        check.required_any(&["RP_ORIGIN", "RP_ORIGIN_PARAMETER_PATH"]);
+        let config = Self {
+            base_path: check.required("BASE_PATH"),
+            user_pool_id: check.required("USER_POOL_ID"),
+            session_table_name: check.required("SESSION_TABLE_NAME"),
+            credential_table_name: check.required("CREDENTIAL_TABLE_NAME"),
+            challenge_timeout: check.load(load_challenge_timeout()),
+            max_body_size: check.load(load_max_body_size()),
+            username_policy: check.load(load_username_policy()),
+            extension_policy: check.load(load_extension_policy()),
+            deletion_retention: check.load(load_deletion_retention()),
+            credential_limit: check.load(load_credential_limit()),
+            cors: check.load(load_cors_policy()),
+            bearer_auth: check.load(load_bearer_auth()),
+        };
+        Ok(check.finish(config)?)
+    }
+}
+
+impl SharedState {
+    #[instrument(name = "cold_start", skip_all)]
+    async fn new(sdk_config: &SdkConfig, config: Config) -> Result<Self, Error> {
+        let webauthn = load_webauthn(aws_sdk_ssm::Client::new(sdk_config)).await?;
+        let dynamodb = aws_sdk_dynamodb::Client::new(sdk_config);
+        Ok(Self {
+            default_tenant: Arc::new(Tenant::default_tenant(webauthn)),
+            tenants: load_tenant_directory(dynamodb.clone())?,
+            dynamodb: dynamodb.clone(),
+            cognito: aws_sdk_cognitoidentityprovider::Client::new(sdk_config),
+            base_path: config.base_path.trim_end_matches('/').into(),
+            user_pool_id: config.user_pool_id,
+            sessions: DynamoDbSessionStore::new(
+                dynamodb.clone(),
+                config.session_table_name.clone(),
+            ),
+            session_table_name: config.session_table_name,
+            challenge_timeout: config.challenge_timeout,
+            max_body_size: config.max_body_size,
+            username_policy: config.username_policy,
+            users: UserDirectory::new(dynamodb.clone(), config.credential_table_name)
+                .with_pii_protection(load_pii_protection(
+                    aws_sdk_kms::Client::new(sdk_config),
+                    aws_sdk_secretsmanager::Client::new(sdk_config),
+                )?),
+            audit_log: load_audit_log(dynamodb)?,
+            event_publisher: load_event_publisher(
+                aws_sdk_eventbridge::Client::new(sdk_config),
+            )?,
+            webhooks: load_webhook_notifier(
+                aws_sdk_secretsmanager::Client::new(sdk_config),
+            )?,
+            export_delivery: load_export_delivery(
+                aws_sdk_s3::Client::new(sdk_config),
+            )?,
+            session_ids: load_session_ids(
+                aws_sdk_secretsmanager::Client::new(sdk_config),
+            )?,
+            extension_policy: config.extension_policy,
+            deletion_retention: config.deletion_retention,
+            credential_limit: config.credential_limit,
+        })
+    }
+
+    // parses a username change and normalizes the username.
+    fn parse_username_change(&self, body: &[u8]) -> Result<UsernameChange, PayloadError> {
+        let mut request: UsernameChange =
+            parse_json_payload(body, self.max_body_size)?;
+        request.username = self.username_policy.apply(&request.username)
+            .map_err(|e| PayloadError::Malformed {
+                field: Some("username".into()),
+                message: e.to_string(),
+            })?;
+        Ok(request)
+    }
+}
+
+/// Page of credentials of a user.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialList {
+    /// Credentials.
+    pub credentials: Vec<CredentialInfo>,
+
+    /// Token to obtain the next page.
+    ///
+    /// Omitted if this is the last page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_token: Option<String>,
+}
+
+/// Request to change the username.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UsernameChange {
+    /// New username.
+    pub username: String,
+}
+
+/// New recovery codes of a user.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RecoveryCodes {
+    /// One-time recovery codes. Shown only once.
+    pub recovery_codes: Vec<String>,
+}
+
+// Context of a job of the authenticated user.
+#[derive(Clone)]
+struct Job {
+    shared_state: Arc<SharedState>,
+    tenant: Arc<Tenant>,
+    user_handle: String,
+}
+
+// routes of the jobs.
+fn router(cors: Option<CorsPolicy>, bearer_auth: Option<BearerAuth>) -> Router<Job> {
+    Router::new()
+        .get("/credentials", |job: Job, event, _| {
+            list_credentials(job.shared_state, event, job.user_handle)
+        })
+        .get("/public-keys", |job: Job, _, _| {
+            list_public_keys(job.shared_state, job.user_handle)
+        })
+        .post("/recovery-codes", |job: Job, _, _| {
+            regenerate_recovery_codes(job.shared_state, job.user_handle)
+        })
+        .post("/username", |job: Job, event: Request, _| async move {
+            require_json_body(&event)?;
+            change_username(job.shared_state, job.tenant, event, job.user_handle).await
+        })
+        .post("/step-up/start", |job: Job, _, _| {
+            start_step_up(job.shared_state, job.tenant, job.user_handle)
+        })
+        .post("/step-up/finish", |job: Job, event: Request, _| async move {
+            require_json_body(&event)?;
+            finish_step_up(job.shared_state, job.tenant, event, job.user_handle).await
+        })
+        .get("/account/export", |job: Job, _, _| {
+            export_account(job.shared_state, job.user_handle)
+        })
+        .delete("/account", |job: Job, event, _| {
+            delete_account(job.shared_state, job.tenant, event, job.user_handle)
+        })
+        .delete("/credentials/{credentialId}", |job: Job, event, params: RouteParams| async move {
+            let credential_id = params.require("credentialId")?;
+            delete_credential(
+                job.shared_state,
+                job.tenant,
+                event,
+                job.user_handle,
+                credential_id,
+            ).await
+        })
+        .post("/credentials/{credentialId}/restore", |job: Job, event, params: RouteParams| async move {
+            let credential_id = params.require("credentialId")?;
+            restore_credential(
+                job.shared_state,
+                job.tenant,
+                event,
+                job.user_handle,
+                credential_id,
+            ).await
+        })
+        .with_cors(cors)
+        .with_bearer_auth(bearer_auth)
+}
+
+async fn function_handler(
+    shared_state: Arc<SharedState>,
+    router: &Router<Job>,
+    mut event: Request,
+) -> Result<Response<Body>, Error> {
+    // the job needs the user handle before routing
+    router.authenticate(&mut event).await;
+    let job_path = job_path(&event, &shared_state.base_path)?;
+    if job_path == HEALTH_PATH {
+        let mut tables = vec![
+            ("sessionTable", shared_state.session_table_name.as_str()),
+            ("credentialTable", shared_state.users.table_name()),
+        ];
+        if let Some(audit_log) = shared_state.audit_log.as_ref() {
+            tables.push(("auditTable", audit_log.table_name()));
+        }
+        return health_check("credentials", &event, &shared_state.dynamodb, &tables).await;
+    }
+    let user_handle = authenticated_user_handle(&event)
+        .ok_or(ApiError::Unauthenticated)?;
+    let (tenant, job_path) = match shared_state.tenants.as_ref() {
+        Some(tenants) => match tenants.resolve_request(&event, job_path).await? {
+            Some(resolved) => resolved,
+            None => return unknown_tenant(),
+        },
+        None => (shared_state.default_tenant.clone(), job_path),
+    };
+    let route = match resolve_version(job_path) {
+        Some((ApiVersion::V1, route)) => route.to_string(),
+        None => return unsupported_version(job_path),
+    };
+    let job = Job {
+        shared_state,
+        tenant,
+        user_handle,
+    };
+    router.handle(job, event, &route).await
+}
+
+#[instrument(skip_all)]
+async fn list_credentials(
+    shared_state: Arc<SharedState>,
+    event: Request,
+    user_handle: String,
+) -> Result<Response<Body>, Error> {
+    let params = event.query_string_parameters_ref();
+    let param = |name: &str| params.and_then(|p| p.first(name));
+    info!("list_credentials: {} {:?}", redact(&user_handle), params);
+
+    let limit = match param("limit").map(str::parse::<i32>) {
+        None => DEFAULT_PAGE_LIMIT,
+        Some(Ok(limit)) if (1..=MAX_PAGE_LIMIT).contains(&limit) => limit,
+        Some(_) => return bad_query(
+            &format!("limit must be between 1 and {}", MAX_PAGE_LIMIT),
+            "limit",
+        ),
+    };
+    // a token must not start a page of another user
+    let exclusive_start_key = match param("nextToken").map(decode_page_token) {
+        None => None,
+        Some(Some(key)) if user_handle_of(&key) == Some(user_handle.as_str()) =>
+            Some(key),
+        Some(_) => return bad_query("malformed nextToken", "nextToken"),
+    };
+    let credential_type = match param("credentialType") {
+        None => None,
+        Some(t @ ("passkey" | "securityKey")) => Some(t.to_string()),
+        Some(_) => return bad_query(
+            "credentialType must be passkey or securityKey",
+            "credentialType",
+        ),
+    };
+    let backup_state = match param("backupState").map(str::parse::<bool>) {
+        None => None,
+        Some(Ok(backup_state)) => Some(backup_state),
+        Some(Err(_)) => return bad_query(
+            "backupState must be true or false",
+            "backupState",
+        ),
+    };
+
+    let page = shared_state.users
+        .query_credentials(
+            &user_handle,
+            limit,
+            exclusive_start_key,
+            &CredentialFilter { credential_type, backup_state },
+        )
+        .await?;
+    let next_token = page.last_evaluated_key.as_ref()
+        .map(encode_page_token)
+        .transpose()?;
+    let credentials = page.credentials.into_iter()
+        .map(CredentialInfo::from_credential)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| {
+            error!("failed to list credentials: {}", e);
+            e
+        })?;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(serde_json::to_string(&CredentialList {
+            credentials,
+            next_token,
+        })?.into())?)
+}
+
+#[instrument(skip_all)]
+async fn list_public_keys(
+    shared_state: Arc<SharedState>,
+    user_handle: String,
+) -> Result<Response<Body>, Error> {
+    info!("list_public_keys: {}", redact(&user_handle));
+
+    let credentials = shared_state.users
+        .list_credentials(&user_handle)
+        .await?;
+    let keys = CredentialPublicKeys::of_credentials(&credentials)
+        .map_err(|e| {
+            error!("failed to list public keys: {}", e);
+            e
+        })?;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(serde_json::to_string(&keys)?.into())?)
+}
+
+#[instrument(skip_all)]
+async fn regenerate_recovery_codes(
+    shared_state: Arc<SharedState>,
+    user_handle: String,
+) -> Result<Response<Body>, Error> {
+    info!("regenerate_recovery_codes: {}", redact(&user_handle));
+
+    let (recovery_codes, item) = new_recovery_codes(
+        &user_handle,
+        DateTime::from(SystemTime::now()).fmt(DateTimeFormat::DateTime)?,
+    )?;
+    shared_state.users.put_recovery_codes(item).await?;
+    let body = serde_json::to_string(&RecoveryCodes { recovery_codes })?;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(body.into())?)
+}
+
+#[instrument(skip_all, fields(session_id))]
+async fn start_step_up(
+    shared_state: Arc<SharedState>,
+    tenant: Arc<Tenant>,
+    user_handle: String,
+) -> Result<Response<Body>, Error> {
+    info!("start_step_up: {}", redact(&user_handle));
+
+    let passkeys: Vec<Passkey> = shared_state.users
+        .list_credentials(&user_handle)
+        .await?
+        .iter()
+        .filter(|c| c.disabled_at.is_none())
+        .map(|c| serde_json::from_str(&c.credential)
+            .or(Err(ApiError::Storage("malformed credential in the database"))))
+        .collect::<Result<Vec<_>, _>>()?;
+    if passkeys.is_empty() {
+        error!("no enabled credentials for step-up");
+        return error_response(
+            StatusCode::BAD_REQUEST,
+            "no_credentials",
+            "no credentials to step up with",
+        );
+    }
+    let (mut rcr, auth_state) = tenant.webauthn()
+        .start_passkey_authentication(&passkeys)
+        .map_err(|e| {
+            error!("failed to start step-up: {}", e);
+            Error::from(ApiError::internal("failed to start step-up"))
+        })?;
+    rcr.public_key.user_verification = UserVerificationPolicy::Required;
+    rcr.public_key.timeout = Some(shared_state.challenge_timeout.as_millis());
+
+    let session_id = shared_state.session_ids.generate().await?;
+    Span::current().record("session_id", field::display(truncate(&session_id)));
+    let session = StepUpSessionItem {
+        ttl: shared_state.challenge_timeout
+            .session_ttl(DateTime::from(SystemTime::now()).secs()),
+        user_handle,
+        state: serde_json::to_string(&auth_state)?,
+    };
+    save_step_up_session(&shared_state.sessions, &tenant, &session_id, session)
+        .await?;
+    let mut body = serde_json::to_value(&StartStepUpSession {
+        session_id,
+        credential_request_options: rcr,
+    })?;
+    shared_state.extension_policy
+        .authentication_inputs()
+        .merge_into(&mut body["credentialRequestOptions"]);
+    let body = serde_json::to_string(&body)?;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(body.into())?)
+}
+
+#[instrument(skip_all)]
+async fn finish_step_up(
+    shared_state: Arc<SharedState>,
+    tenant: Arc<Tenant>,
+    event: Request,
+    user_handle: String,
+) -> Result<Response<Body>, Error> {
+    let session: FinishStepUpSession = match parse_json_payload(
+        event.body().as_ref(),
+        shared_state.max_body_size,
+    ) {
+        Ok(session) => session,
+        Err(e) => {
+            error!("bad payload: {:?}", e);
+            return e.into_response();
+        }
+    };
+    info!("finish_step_up: {} {}", redact(&user_handle), truncate(&session.session_id));
+    let client = ClientInfo::of(&event);
+    if !shared_state.session_ids.verify(&session.session_id).await? {
+        error!("forged step-up session ID");
+        return error_response(StatusCode::UNAUTHORIZED, "step_up_failed", "step-up failed");
+    }
+
+    let now = DateTime::from(SystemTime::now()).secs();
+    let item = take_step_up_session(
+        &shared_state.sessions,
+        &tenant,
+        &session.session_id,
+        &user_handle,
+        now,
+    ).await?;
+    let Some(item) = item else {
+        error!("expired or wrong step-up session");
+        return step_up_failed(
+            &shared_state,
+            &user_handle,
+            None,
+            client,
+            "expired or wrong session",
+        ).await;
+    };
+    let auth_state: PasskeyAuthentication = serde_json::from_str(&item.state)?;
+    let credential_id = session.public_key_credential.id.clone();
+    let verified = info_span!("verify_step_up").in_scope(|| {
+        tenant.webauthn().finish_passkey_authentication(
+            &session.public_key_credential,
+            &auth_state,
+        )
+    });
+    // the UV flag in the authenticator data is also checked by itself
+    let user_verified = is_user_verified_in(
+        session.public_key_credential.response.authenticator_data.as_ref(),
+    );
+    let auth_result = match verified {
+        Ok(auth_result) if auth_result.user_verified() && user_verified => auth_result,
+        Ok(_) => {
+            error!("user verification required but not performed");
+            record_step_up_failure(
+                &shared_state,
+                &user_handle,
+                Some(credential_id),
+                client,
+                "user not verified",
+            ).await?;
+            return error_response(
+                StatusCode::UNAUTHORIZED,
+                "user_verification_required",
+                "user verification required",
+            );
+        }
+        Err(e) => {
+            error!("step-up failed: {}", e);
+            return step_up_failed(
+                &shared_state,
+                &user_handle,
+                Some(credential_id),
+                client,
+                "verification failed",
+            ).await;
+        }
+    };
+    let credential_item = shared_state.users
+        .get_credential(CredentialKey {
+            user_handle: &user_handle,
+            credential_id: &base64url.encode(auth_result.cred_id()),
+        })
+        .await?
+        .ok_or(ApiError::Storage("missing credential in the database"))?;
+    if credential_item.disabled_at.is_some() {
+        error!("credential disabled");
+        return step_up_failed(
+            &shared_state,
+            &user_handle,
+            Some(credential_id),
+            client,
+            "credential disabled",
+        ).await;
+    }
+    record_credential_use(&shared_state, credential_item, &auth_result).await?;
+
+    let (step_up_token, expires_at) = issue_step_up_token(
+        &shared_state.sessions,
+        &tenant,
+        &user_handle,
+        now,
+    ).await?;
+    let extensions = ExtensionOutputs::of_payload(event.body().as_ref());
+    let body = serde_json::to_string(&StepUpResult {
+        step_up_token,
+        expires_at,
+        large_blob: extensions.large_blob,
+        prf: extensions.prf,
+    })?;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(body.into())?)
+}
+
+#[instrument(skip_all)]
+async fn delete_credential(
+    shared_state: Arc<SharedState>,
+    tenant: Arc<Tenant>,
+    event: Request,
+    user_handle: String,
+    credential_id: String,
+) -> Result<Response<Body>, Error> {
+    info!("delete_credential: {} {}", redact(&user_handle), truncate(&credential_id));
+
+    if !has_stepped_up(&shared_state, &tenant, &event, &user_handle).await? {
+        error!("step-up required");
+        return error_response(
+            StatusCode::FORBIDDEN,
+            "step_up_required",
+            "recent authentication is required",
+        );
+    }
+    let key = CredentialKey {
+        user_handle: &user_handle,
+        credential_id: &credential_id,
+    };
+    let deleted = match shared_state.deletion_retention {
+        Some(_) => {
+            let deleted_at = deletion_timestamp(SystemTime::now())?;
+            shared_state.users.soft_delete_credential(key, deleted_at).await?
+        }
+        None => shared_state.users.delete_credential(key).await?,
+    };
+    if !deleted {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            "credential_not_found",
+            "no such credential",
+        );
+    }
+    if let Some(audit_log) = shared_state.audit_log.as_ref() {
+        audit_log.record(AuditEvent {
+            event_type: AuditEventType::CredentialDeleted,
+            user_handle: user_handle.clone(),
+            credential_id: Some(credential_id.clone()),
+            client: ClientInfo::of(&event),
+            detail: Some("deleted by user".into()),
+        }).await?;
+    }
+    let revocation = DomainEvent::CredentialRevoked(CredentialRevoked {
+        user_handle,
+        credential_id,
+        tenant_id: tenant.id().map(Into::into),
+        action: "deleted",
+        revoked_by: "user",
+    });
+    notify_webhook(shared_state.webhooks.as_ref(), &revocation).await;
+    publish_event(shared_state.event_publisher.as_ref(), revocation).await;
+
+    Ok(Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::Empty)?)
+}
+
+#[instrument(skip_all)]
+async fn restore_credential(
+    shared_state: Arc<SharedState>,
+    tenant: Arc<Tenant>,
+    event: Request,
+    user_handle: String,
+    credential_id: String,
+) -> Result<Response<Body>, Error> {
+    info!("restore_credential: {} {}", redact(&user_handle), truncate(&credential_id));
+
+    if !has_stepped_up(&shared_state, &tenant, &event, &user_handle).await? {
+        error!("step-up required");
+        return error_response(
+            StatusCode::FORBIDDEN,
+            "step_up_required",
+            "recent authentication is required",
+        );
+    }
+    if let Some(limit) = shared_state.credential_limit {
+        limit.check(&shared_state.users.list_credentials(&user_handle).await?)?;
+    }
+    let restored = match shared_state.deletion_retention {
+        Some(retention) => shared_state.users
+            .restore_credential(
+                CredentialKey {
+                    user_handle: &user_handle,
+                    credential_id: &credential_id,
+                },
+                retention_cutoff(SystemTime::now(), retention)?,
+            )
+            .await?,
+        None => false,
+    };
+    if !restored {
+        return error_response(
+            StatusCode::NOT_FOUND,
+            "credential_not_found",
+            "no such deleted credential",
+        );
+    }
+    if let Some(audit_log) = shared_state.audit_log.as_ref() {
+        audit_log.record(AuditEvent {
+            event_type: AuditEventType::CredentialRestored,
+            user_handle,
+            credential_id: Some(credential_id),
+            client: ClientInfo::of(&event),
+            detail: Some("restored by user".into()),
+        }).await?;
+    }
+
+    Ok(Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::Empty)?)
+}
+
+#[instrument(skip_all)]
+async fn change_username(
+    shared_state: Arc<SharedState>,
+    tenant: Arc<Tenant>,
+    event: Request,
+    user_handle: String,
+) -> Result<Response<Body>, Error> {
+    info!("change_username: {}", redact(&user_handle));
+
+    let username = match shared_state.parse_username_change(event.body().as_ref()) {
+        Ok(request) => tenant.qualify_username(&request.username),
+        Err(e) => {
+            error!("bad payload: {:?}", e);
+            return e.into_response();
+        }
+    };
+    // the old username restores the user if Cognito rejects the new one
+    let old_username = shared_state.users
+        .get_user(&user_handle)
+        .await?
+        .ok_or(ApiError::Storage("missing user in the database"))?
+        .username;
+    match shared_state.users.rename_user(&user_handle, &username).await {
+        Ok(()) => {}
+        Err(RenameUserError::UsernameTaken) => return error_response(
+            StatusCode::CONFLICT,
+            "username_taken",
+            "username already taken",
+        ),
+        Err(RenameUserError::Conflict) => return error_response(
+            StatusCode::CONFLICT,
+            "conflict",
+            "conflicting update; try again",
+        ),
+        Err(RenameUserError::UserNotFound) =>
+            return Err(ApiError::Storage("missing user in the database").into()),
+        Err(RenameUserError::Other(e)) => return Err(e.into()),
+    }
+    // the user handle is the username in the Cognito user pool
+    if let Err(e) = shared_state.cognito
+        .admin_update_user_attributes()
+        .user_pool_id(shared_state.user_pool_id.clone())
+        .username(user_handle.clone())
+        .user_attributes(UserAttributeType::builder()
+            .name("preferred_username")
+            .value(username.clone())
+            .build()
+            .unwrap())
+        .send()
+        .await
+    {
+        error!("failed to update preferred_username: {:?}", e);
+        if let Err(e) = shared_state.users.rename_user(&user_handle, &old_username).await {
+            error!(
+                "failed to restore the old username of {}: {:?}",
+                redact(&user_handle),
+                e,
+            );
+        }
+        return Err(e.into());
+    }
+    // the new username is personal data and kept out of the audit log
+    if let Some(audit_log) = shared_state.audit_log.as_ref() {
+        audit_log.record(AuditEvent {
+            event_type: AuditEventType::UsernameChanged,
+            user_handle,
+            credential_id: None,
+            client: ClientInfo::of(&event),
+            detail: Some("changed by user".into()),
+        }).await?;
+    }
+
+    Ok(Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::Empty)?)
+}
+
+#[instrument(skip_all)]
+async fn export_account(
+    shared_state: Arc<SharedState>,
+    user_handle: String,
+) -> Result<Response<Body>, Error> {
+    info!("export_account: {}", redact(&user_handle));
+
+    let now = SystemTime::now();
+    let user = shared_state.users.get_user(&user_handle).await?;
+    // the user handle is the username in the Cognito user pool
+    let attributes = match shared_state.cognito
+        .admin_get_user()
+        .user_pool_id(shared_state.user_pool_id.clone())
+        .username(user_handle.clone())
+        .send()
+        .await
+    {
+        Ok(res) => res.user_attributes
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|a| a.value.map(|value| (a.name, value)))
+            .collect(),
+        Err(e) if e.as_service_error()
+            .is_some_and(|e| e.is_user_not_found_exception()) =>
+        {
+            info!("no Cognito user: {}", redact(&user_handle));
+            Default::default()
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let credentials = shared_state.users
+        .list_credentials(&user_handle)
+        .await?
+        .into_iter()
+        .map(CredentialInfo::from_credential)
+        .collect::<Result<Vec<_>, _>>()?;
+    let audit_events = match shared_state.audit_log.as_ref() {
+        Some(audit_log) => list_user_audit_events(audit_log, &user_handle).await?,
+        None => Vec::new(),
+    };
+    let sessions = list_user_sessions(
+        &shared_state.dynamodb,
+        &shared_state.session_table_name,
+        &user_handle,
+        DateTime::from(now).secs(),
+    ).await?;
+    info!(
+        "exporting {} credentials, {} audit events, and {} sessions",
+        credentials.len(),
+        audit_events.len(),
+        sessions.len(),
+    );
+    let body = serde_json::to_string(&AccountExport {
+        exported_at: DateTime::from(now).fmt(DateTimeFormat::DateTime)?,
+        profile: AccountProfile {
+            user_handle: user_handle.clone(),
+            user,
+            attributes,
+        },
+        credentials,
+        audit_events,
+        sessions,
+    })?;
+    let body = match shared_state.export_delivery.as_ref() {
+        Some(delivery) if delivery.requires_upload(body.len()) => {
+            let download: ExportDownload = delivery.upload(&user_handle, body, now).await?;
+            info!("uploaded export: {}", redact(&user_handle));
+            serde_json::to_string(&download)?
+        }
+        _ => body,
+    };
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(body.into())?)
+}
+
+#[instrument(skip_all)]
+async fn delete_account(
+    shared_state: Arc<SharedState>,
+    tenant: Arc<Tenant>,
+    event: Request,
+    user_handle: String,
+) -> Result<Response<Body>, Error> {
+    info!("delete_account: {}", redact(&user_handle));
+
+    if !has_stepped_up(&shared_state, &tenant, &event, &user_handle).await? {
+        error!("step-up required");
+        return error_response(
+            StatusCode::FORBIDDEN,
+            "step_up_required",
+            "recent authentication is required",
+        );
+    }
+    // the step-up token stays valid so that a failed deletion can be retried
+    let deleted = shared_state.users.delete_user(&user_handle).await?;
+    info!("deleted {} items of {}", deleted, redact(&user_handle));
+    // the user handle is the username in the Cognito user pool
+    let res = shared_state.cognito
+        .admin_delete_user()
+        .user_pool_id(shared_state.user_pool_id.clone())
+        .username(user_handle.clone())
+        .send()
+        .await;
+    match res {
+        Ok(_) => info!("deleted Cognito user: {}", redact(&user_handle)),
+        Err(e) if e.as_service_error()
+            .is_some_and(|e| e.is_user_not_found_exception()) =>
+        {
+            info!("Cognito user already deleted: {}", redact(&user_handle));
+        }
+        Err(e) => return Err(e.into()),
+    }
+    if let Some(audit_log) = shared_state.audit_log.as_ref() {
+        audit_log.record(AuditEvent {
+            event_type: AuditEventType::AccountDeleted,
+            user_handle,
+            credential_id: None,
+            client: ClientInfo::of(&event),
+            detail: Some("deleted by user".into()),
+        }).await?;
+    }
+
+    Ok(Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Body::Empty)?)
+}
+
+// returns whether a given request carries a valid step-up token of a given
+// user.
+#[instrument(skip_all)]
+async fn has_stepped_up(
+    shared_state: &SharedState,
+    tenant: &Tenant,
+    event: &Request,
+    user_handle: &str,
+) -> Result<bool, Error> {
+    let Some(token) = step_up_token(event) else {
+        return Ok(false);
+    };
+    Ok(verify_step_up_token(
+        &shared_state.sessions,
+        tenant,
+        token,
+        user_handle,
+        DateTime::from(SystemTime::now()).secs(),
+    ).await?)
+}
+
+// records the use of a credential in a step-up.
+//
+// a concurrent update is not retried; the sign count is updated again at the
+// next authentication.
+async fn record_credential_use(
+    shared_state: &SharedState,
+    credential_item: CredentialItem,
+    auth_result: &AuthenticationResult,
+) -> Result<(), Error> {
+    let mut passkey: Passkey = serde_json::from_str(&credential_item.credential)
+        .or(Err(ApiError::Storage("malformed credential in the database")))?;
+    let used_at = DateTime::from(SystemTime::now())
+        .fmt(DateTimeFormat::DateTime)?;
+    if !passkey.update_credential(auth_result).is_some_and(|b| b) {
+        shared_state.users
+            .touch_credential(credential_item.key(), used_at)
+            .await?;
+        return Ok(());
+    }
+    let properties = PasskeyProperties::of(&passkey)?;
+    if !shared_state.users.update_credential(
+        &credential_item,
+        serde_json::to_string(&passkey)?,
+        properties.backup_eligible,
+        properties.backup_state,
+        used_at,
+    ).await? {
+        warn!(
+            "credential updated concurrently: {}",
+            truncate(&credential_item.credential_id),
+        );
+    }
+    Ok(())
+}
+
+// records a failed step-up and creates a 401 response.
+async fn step_up_failed(
+    shared_state: &SharedState,
+    user_handle: &str,
+    credential_id: Option<String>,
+    client: ClientInfo,
+    reason: &str,
+) -> Result<Response<Body>, Error> {
+    record_step_up_failure(shared_state, user_handle, credential_id, client, reason).await?;
+    error_response(StatusCode::UNAUTHORIZED, "step_up_failed", "step-up failed")
+}
+
+// records a step-up failure in the audit log.
+async fn record_step_up_failure(
+    shared_state: &SharedState,
+    user_handle: &str,
+    credential_id: Option<String>,
+    client: ClientInfo,
+    reason: &str,
+) -> Result<(), Error> {
+    if let Some(audit_log) = shared_state.audit_log.as_ref() {
+        audit_log.record(AuditEvent {
+            event_type: AuditEventType::AuthenticationFailed,
+            user_handle: user_handle.into(),
+            credential_id,
+            client,
+            detail: Some(format!("step-up: {}", reason)),
+        }).await?;
+    }
+    Ok(())
+}
+
+// creates an error response.
+fn error_response(
+    status: StatusCode,
+    error: &'static str,
+    message: &str,
+) -> Result<Response<Body>, Error> {
+    let body = serde_json::to_string(&ErrorResponseBody {
+        error,
+        message: message.into(),
+        field: None,
+        localized_message: None,
+        request_id: None,
+    })?;
+    Ok(Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(body.into())?)
+}
+
+// creates a 400 response for a bad query parameter.
+fn bad_query(message: &str, field: &str) -> Result<Response<Body>, Error> {
+    let body = serde_json::to_string(&ErrorResponseBody {
+        error: "bad_query",
+        message: message.into(),
+        field: Some(field.into()),
+        localized_message: None,
+        request_id: None,
+    })?;
+    Ok(Response::builder()
+        .status(StatusCode::BAD_REQUEST)
+        .header("Content-Type", "application/json")
+        .body(body.into())?)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let started_at = Instant::now();
+    let telemetry = init_tracing("credentials")?;
+
+    let sdk_config = load_sdk_config().await?;
+    load_config_parameters(&aws_sdk_ssm::Client::new(&sdk_config)).await?;
+    let mut config = Config::from_env()?;
+    let router = router(config.cors.clone(), config.bearer_auth.take());
+    let shared_state = Arc::new(SharedState::new(&sdk_config, config).await?);
+    let metrics = load_metrics("credentials")?;
+    let cold_start = ColdStart::initialized_since(started_at);
+    run_with_warmer(&cold_start, &metrics, |req: Request| async {
+        let span = request_span(&req);
+        let handler_started_at = Instant::now();
+        let res = negotiate_content(
+            req,
+            shared_state.max_body_size,
+            |req| handle_api_errors(function_handler(shared_state.clone(), &router, req)),
+        )
+            .instrument(span)
+            .await;
+        cold_start.report(&metrics, handler_started_at.elapsed());
+        telemetry.flush().await;
+        res
+    }).await
+}
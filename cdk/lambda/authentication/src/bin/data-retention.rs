@@ -0,0 +1,264 @@
+//! Scheduled Lambda function that enforces data retention.
+//!
+//! Purges audit events, expired or orphaned sessions, and soft-deleted
+//! credentials that have outlived their retention periods. See
+//! [`authentication::retention`] for details.
+//!
+//! You have to configure the following environment variables:
+//! - `CREDENTIAL_TABLE_NAME`: name of the DynamoDB table that manages
+//!   credentials
+//! - `SESSION_TABLE_NAME`: name of the DynamoDB table that manages sessions
+//!
+//! You can optionally configure the following environment variables:
+//! - `CONFIG_PARAMETER_PATH`: path to the parameters in Parameter Store on
+//!   AWS Systems Manager that override the other environment variables. See
+//!   [`authentication::config`] for details.
+//! - `AUDIT_TABLE_NAME`: name of the DynamoDB table for the audit log. Audit
+//!   events are not purged unless specified.
+//! - `AUDIT_RETENTION`: retention period of audit events in seconds. Audit
+//!   events are kept forever unless specified, or if "0".
+//! - `SESSION_EXPIRY_GRACE`: time in seconds that an expired session is left
+//!   to DynamoDB TTL before it is purged; 1 day by default.
+//! - `DELETION_RETENTION`: retention window of deleted credentials in
+//!   seconds; 30 days by default. Must be the same as the credentials
+//!   function. "0" purges every deleted credential.
+//! - `PII_KMS_KEY_ARN`, `PII_INDEX_SECRET_ID`: ARN of the KMS key and ID of
+//!   the secret in Secrets Manager that protect the usernames and display
+//!   names of users. Stored in plaintext unless specified. See
+//!   [`authentication::pii`].
+//! - `LOG_LEVEL`, `LOG_REDACTION`: log level or `RUST_LOG`-style directives,
+//!   and whether identifiers are redacted in logs; "info" and redacted by
+//!   default. See [`authentication::telemetry`] for details.
+//! - `METRICS_NAMESPACE`: namespace of the CloudWatch metrics; "PasskeyTest"
+//!   by default. The following metrics are reported:
+//!     - `audit_events_purged`: number of purged audit events
+//!     - `expired_sessions_purged`: number of purged sessions that had
+//!       expired
+//!     - `orphaned_sessions_purged`: number of purged sessions of users who
+//!       no longer exist
+//!     - `credentials_purged`: number of purged credentials
+//!
+//! Any event invokes a purge; e.g., a scheduled event of Amazon EventBridge.
+//! The response tells the numbers of purged items.
+//!
+//! The function fails at cold start if any required variable is missing or
+//! any variable is invalid, and the error lists all of them; see
+//! [`authentication::config`].
+
+use aws_config::SdkConfig;
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, instrument};
+
+use authentication::audit::{AuditLog, load_audit_log};
+use authentication::config::{ConfigCheck, load_config_parameters};
+use authentication::deletion::{
+    load_deletion_retention,
+    purge_deleted_credentials,
+    retention_cutoff,
+};
+use authentication::metrics::{ColdStart, Metrics, Unit, load_metrics};
+use authentication::pii::load_pii_protection;
+use authentication::retention::{
+    SessionDisposition,
+    SessionSweeper,
+    load_audit_retention,
+    load_session_expiry_grace,
+    session_disposition,
+};
+use authentication::telemetry::{init_tracing, redact};
+use authentication::users::UserDirectory;
+
+// Maximum number of items evaluated in a page of a scan.
+const SCAN_PAGE_LIMIT: i32 = 100;
+
+// State shared among Lambda invocations.
+struct SharedState {
+    users: UserDirectory,
+    sessions: SessionSweeper,
+    audit_log: Option<AuditLog>,
+    audit_retention: Option<Duration>,
+    session_expiry_grace: Duration,
+    deletion_retention: Option<Duration>,
+}
+
+// Configuration validated at cold start.
+struct Config {
+    credential_table_name: String,
+    session_table_name: String,
+    audit_retention: Option<Duration>,
+    session_expiry_grace: Duration,
+    deletion_retention: Option<Duration>,
+}
+
+impl Config {
+    fn from_env() -> Result<Self, Error> {
+        let mut check = ConfigCheck::new();
+        let config = Self {
+            credential_table_name: check.required("CREDENTIAL_TABLE_NAME"),
+            session_table_name: check.required("SESSION_TABLE_NAME"),
+            audit_retention: check.load(load_audit_retention()),
+            session_expiry_grace: check.load(load_session_expiry_grace()),
+            deletion_retention: check.load(load_deletion_retention()),
+        };
+        Ok(check.finish(config)?)
+    }
+}
+
+impl SharedState {
+    #[instrument(name = "cold_start", skip_all)]
+    fn new(sdk_config: &SdkConfig, config: Config) -> Result<Self, Error> {
+        let dynamodb = aws_sdk_dynamodb::Client::new(sdk_config);
+        Ok(Self {
+            users: UserDirectory::new(dynamodb.clone(), config.credential_table_name)
+                .with_pii_protection(load_pii_protection(
+                    aws_sdk_kms::Client::new(sdk_config),
+                    aws_sdk_secretsmanager::Client::new(sdk_config),
+                )?),
+            sessions: SessionSweeper::new(dynamodb.clone(), config.session_table_name),
+            audit_log: load_audit_log(dynamodb)?,
+            audit_retention: config.audit_retention,
+            session_expiry_grace: config.session_expiry_grace,
+            deletion_retention: config.deletion_retention,
+        })
+    }
+}
+
+#[instrument(skip_all)]
+async fn function_handler(
+    shared_state: Arc<SharedState>,
+    metrics: &Metrics,
+    _event: LambdaEvent<Value>,
+) -> Result<Value, Error> {
+    let now = SystemTime::now();
+
+    let audit_events = match (shared_state.audit_log.as_ref(), shared_state.audit_retention) {
+        (Some(audit_log), Some(retention)) => {
+            purge_audit_events(audit_log, retention_cutoff(now, retention)?).await?
+        }
+        _ => {
+            info!("audit events are kept");
+            0
+        }
+    };
+    metrics.put("audit_events_purged", audit_events as f64, Unit::Count);
+
+    let (expired_sessions, orphaned_sessions) = purge_sessions(&shared_state, now).await?;
+    metrics.put("expired_sessions_purged", expired_sessions as f64, Unit::Count);
+    metrics.put("orphaned_sessions_purged", orphaned_sessions as f64, Unit::Count);
+
+    let cutoff = retention_cutoff(
+        now,
+        shared_state.deletion_retention.unwrap_or(Duration::ZERO),
+    )?;
+    info!("purging credentials deleted before {}", cutoff);
+    let credentials = purge_deleted_credentials(&shared_state.users, cutoff).await?;
+    info!("purged {} credentials", credentials);
+    metrics.put("credentials_purged", credentials as f64, Unit::Count);
+
+    Ok(json!({
+        "auditEvents": audit_events,
+        "expiredSessions": expired_sessions,
+        "orphanedSessions": orphaned_sessions,
+        "credentials": credentials,
+    }))
+}
+
+// purges the audit events that occurred before a given cutoff.
+#[instrument(skip_all)]
+async fn purge_audit_events(audit_log: &AuditLog, cutoff: String) -> Result<usize, Error> {
+    info!("purging audit events before {}", cutoff);
+    let mut purged = 0;
+    let mut exclusive_start_key = None;
+    loop {
+        let page = audit_log
+            .scan_before(cutoff.clone(), SCAN_PAGE_LIMIT, exclusive_start_key)
+            .await?;
+        for record in page.records {
+            audit_log.purge(&record).await?;
+            purged += 1;
+        }
+        exclusive_start_key = page.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+            break;
+        }
+    }
+    info!("purged {} audit events", purged);
+    Ok(purged)
+}
+
+// purges expired and orphaned sessions, and returns the numbers of them.
+#[instrument(skip_all)]
+async fn purge_sessions(
+    shared_state: &SharedState,
+    now: SystemTime,
+) -> Result<(usize, usize), Error> {
+    let now_secs = now.duration_since(SystemTime::UNIX_EPOCH)?.as_secs() as i64;
+    info!("purging expired and orphaned sessions");
+    // users looked up during this invocation
+    let mut user_exists: HashMap<String, bool> = HashMap::new();
+    let mut expired = 0;
+    let mut orphaned = 0;
+    let mut exclusive_start_key = None;
+    loop {
+        let page = shared_state.sessions
+            .scan(SCAN_PAGE_LIMIT, exclusive_start_key)
+            .await?;
+        for item in page.items {
+            match session_disposition(&item, now_secs, shared_state.session_expiry_grace)? {
+                SessionDisposition::Keep => {}
+                SessionDisposition::Expired => {
+                    shared_state.sessions.purge(&item).await?;
+                    expired += 1;
+                }
+                SessionDisposition::OwnedBy(user_handle) => {
+                    let exists = match user_exists.get(&user_handle) {
+                        Some(exists) => *exists,
+                        None => {
+                            let exists = shared_state.users
+                                .get_user(&user_handle)
+                                .await?
+                                .is_some();
+                            user_exists.insert(user_handle.clone(), exists);
+                            exists
+                        }
+                    };
+                    if !exists {
+                        info!("purging session of missing user: {}", redact(&user_handle));
+                        shared_state.sessions.purge(&item).await?;
+                        orphaned += 1;
+                    }
+                }
+            }
+        }
+        exclusive_start_key = page.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+            break;
+        }
+    }
+    info!("purged {} expired and {} orphaned sessions", expired, orphaned);
+    Ok((expired, orphaned))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let started_at = Instant::now();
+    let telemetry = init_tracing("data-retention")?;
+
+    let sdk_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+    load_config_parameters(&aws_sdk_ssm::Client::new(&sdk_config)).await?;
+    let config = Config::from_env()?;
+    let shared_state = Arc::new(SharedState::new(&sdk_config, config)?);
+    let metrics = load_metrics("data-retention")?;
+    let cold_start = ColdStart::initialized_since(started_at);
+    run(service_fn(|event| async {
+        let handler_started_at = Instant::now();
+        let res = function_handler(shared_state.clone(), &metrics, event).await;
+        cold_start.report(&metrics, handler_started_at.elapsed());
+        telemetry.flush().await;
+        res
+    })).await
+}
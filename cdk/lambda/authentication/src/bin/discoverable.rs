@@ -6,22 +6,165 @@
 //! - `RP_ORIGIN_PARAMETER_PATH`: path to the parameter that stores the origin
 //!   (URL) of the relying party in the Parameter Store on AWS Systems Manager
 //!
+//! You can optionally configure the following environment variables:
+//! - `CONFIG_PARAMETER_PATH`: path to the parameters in Parameter Store on
+//!   AWS Systems Manager that override the other environment variables. See
+//!   [`authentication::config`] for details.
+//! - `DATABASE_URL`: URL of the PostgreSQL database that stores users,
+//!   credentials, and sessions in place of the DynamoDB tables; needs the
+//!   `sql` feature. See [`authentication::store::load_database`]. The other
+//!   items in the session table, like rate limits, stay on DynamoDB.
+//! - `RP_ORIGIN`: origin (URL) of the relying party that takes precedence over
+//!   `RP_ORIGIN_PARAMETER_PATH`
+//! - `RP_ID`: ID of the relying party; the domain of the origin by default
+//! - `RP_ALLOWED_ORIGINS`: comma-separated origins (URLs) allowed in addition
+//!   to the origin of the relying party; e.g., `https://www.example.com`
+//! - `ANDROID_APK_KEY_HASHES`: comma-separated SHA-256 hashes of the
+//!   certificates that sign Android apps allowed to use the relying party.
+//!   See [`authentication::android`] for details.
+//! - `LEGACY_RP_ID`, `LEGACY_RP_ORIGIN`: legacy ID and origin of the relying
+//!   party, which enable the migration mode of the RP ID. An assertion that
+//!   fails with the current RP ID is verified with the legacy RP ID, and the
+//!   credential is flagged for re-registration. See
+//!   [`authentication::rp_migration`] for details.
+//! - `CHALLENGE_TIMEOUT`: timeout of an authentication in seconds; 60 by
+//!   default. Given to the client as the `timeout` of the request options,
+//!   and authentication sessions expire after it.
+//! - `HINTS`: comma-separated "security-key", "client-device", and "hybrid"
+//!   given as the `hints` of the request options in the order of
+//!   preference. The `hints` query parameter of `start` takes precedence. No
+//!   hints unless specified. See [`authentication::hints`] for details.
+//! - `MAX_BODY_SIZE`: maximum size of a request body in bytes; 32 KiB by
+//!   default. Larger requests are rejected with 413.
+//! - `CLIENT_BINDING`: comma-separated "ip" and "user-agent". Authentication
+//!   sessions are bound to the source IP and user agent of the client that
+//!   starts them, and the `finish` endpoint fails with 401 if it is called by
+//!   another client. Not bound unless specified. The custom authentication
+//!   flow of the Cognito user pool does not check the binding, because
+//!   Cognito does not forward the client. See
+//!   [`authentication::client_binding`] for details.
+//! - `LARGE_BLOB`: support of the `largeBlob` extension; "required" or
+//!   "preferred". Authentication requests to read the large blob if
+//!   specified. See [`load_extension_policy`] for details.
+//! - `PRF`, `PRF_SALT`: whether the `prf` extension is evaluated and its
+//!   salt. Disabled unless specified. See [`load_extension_policy`] for
+//!   details.
+//! - `TENANT_TABLE_NAME`, `TENANT_RESOLUTION`: table of tenants and how a
+//!   tenant is resolved from a request. Every request is served by the
+//!   default relying party unless specified. See
+//!   [`authentication::tenant`] for details.
+//! - `TOKEN_ISSUER`, `TOKEN_AUDIENCE`, `TOKEN_TTL`, `TOKEN_KMS_KEY_ID`,
+//!   `TOKEN_SIGNING_KEY_SECRET_ID`: settings and signing key of self-issued
+//!   tokens. The `finish`, `token/refresh`, and `jwks` endpoints are enabled
+//!   only if specified, and `CREDENTIAL_TABLE_NAME` must also be set. See
+//!   [`load_token_issuer`] for details.
+//! - `REFRESH_TOKEN_TTL`: time to live of a family of refresh tokens in
+//!   seconds; 30 days by default. See [`authentication::refresh`] for
+//!   details.
+//! - `LOCKOUT_THRESHOLD`, `LOCKOUT_DURATION`, `LOCKOUT_MAX_DURATION`: lockout
+//!   of credentials after consecutive failed authentications at the `finish`
+//!   endpoint. Disabled unless specified. See
+//!   [`authentication::lockout`] for details.
+//! - `CAPTCHA_PROVIDER`, `CAPTCHA_SECRET_ID`, `CAPTCHA_HEADER`: CAPTCHA token
+//!   required to start authentication; Turnstile or AWS WAF. Not required
+//!   unless specified. See [`authentication::captcha`] for details.
+//! - `RISK_HOOK_URL`, `RISK_HOOK_FUNCTION_NAME`, `RISK_HOOK_SECRET_ID`,
+//!   `RISK_HOOK_ON_ERROR`: HTTP endpoint or Lambda function that assesses
+//!   the risk of an authentication at the `finish` endpoint. Every verified
+//!   authentication is allowed unless specified. See
+//!   [`authentication::risk`] for details.
+//! - `AUDIT_TABLE_NAME`: name of the DynamoDB table for the audit log.
+//!   Authentications at the `finish` endpoint are recorded with the source IP
+//!   and user agent of the client if specified.
+//! - `EVENT_BUS_NAME`: name of the EventBridge event bus. Successful
+//!   authentications are published as `AuthenticationSucceeded` if specified;
+//!   see [`authentication::domain_events`].
+//! - `PII_KMS_KEY_ARN`, `PII_INDEX_SECRET_ID`: ARN of the KMS key and ID of
+//!   the secret in Secrets Manager that protect the usernames and display
+//!   names of users. Stored in plaintext unless specified. See
+//!   [`authentication::pii`].
+//! - `LOG_LEVEL`, `LOG_REDACTION`: log level or `RUST_LOG`-style directives,
+//!   and whether identifiers are redacted in logs; "info" and redacted by
+//!   default. See [`authentication::telemetry`] for details.
+//! - `METRICS_NAMESPACE`: namespace of the CloudWatch metrics; "PasskeyTest"
+//!   by default. The cold start of the function is reported as metrics; see
+//!   [`ColdStart`].
+//!
+//! The function fails at cold start if any required variable is missing or
+//! any variable is invalid, and the error lists all of them; see
+//! [`authentication::config`].
+//!
 //! ## Endpoint
 //!
 //! Provides the following endpoint under the base path.
+//! Every endpoint is versioned under `${BASE_PATH}v1/`; e.g.,
+//! `${BASE_PATH}v1/start`. Paths without a version are routed to v1
+//! for backward compatibility, and unsupported versions end with 404.
+//! If tenants are configured and resolved by path, the path is prefixed with
+//! the tenant key; e.g., `${BASE_PATH}acme/v1/start`. Requests for an unknown
+//! tenant end with 404. Authentication starts beyond the quota of the tenant
+//! are rejected with 429 and `Retry-After`.
+//! Request and response bodies may be CBOR instead of JSON; see
+//! [`authentication::content`]. A request body is `application/cbor` if so
+//! specified in `Content-Type`, and a response body is `application/cbor` if
+//! `Accept` prefers it.
+//! Large response bodies are compressed with Brotli or gzip if
+//! `Accept-Encoding` allows it.
+//! Error responses carry a `localizedMessage` in English or Japanese as
+//! `Accept-Language` prefers; see [`authentication::i18n`].
+//! `GET ${BASE_PATH}health` serves the health check, which is neither
+//! versioned nor scoped to a tenant; see [`authentication::health`].
+//! A scheduled warm-up event is answered with 200 without serving a request;
+//! see [`authentication::warmer`].
+//! A client error like an expired session or a failed verification ends with
+//! the status code of the [`ApiError`] and an [`ErrorResponseBody`]; see
+//! [`authentication::api_error`].
+//! A path that no endpoint serves ends with 404, and a method that the
+//! endpoint does not accept ends with 405; see [`Router`].
 //!
 //! ### `POST ${BASE_PATH}start`
 //!
 //! Starts authentication of a client-side discoverable credential.
 //! No request body is required.
+//! The optional `hints` query parameter is a comma-separated list of
+//! "security-key", "client-device", and "hybrid"; e.g., `?hints=hybrid`.
+//! Unknown hints are rejected with 400.
+//! If CAPTCHA is configured, a request without a valid token is rejected with
+//! 403 and `captcha_required` before a session is written.
 //! The response body is [`RequestChallengeResponse`] as `application/json`.
 //!
-//! There is not endpoint to finish the authentication, because subsequent steps are processed by Cognito triggers.
+//! Subsequent steps are processed by Cognito triggers unless self-issued
+//! tokens are enabled.
+//!
+//! ### `POST ${BASE_PATH}finish`
+//!
+//! Finishes authentication of a client-side discoverable credential and
+//! issues a token without Cognito.
+//! Available only if self-issued tokens are enabled.
+//! The request body is [`FinishTokenSession`] as `application/json`.
+//! The response body is [`TokenResult`] as `application/json`.
+//! Fails with 401 if the authentication fails, or with 423 and
+//! `credential_locked` if the credential is locked after consecutive failures.
+//! If a risk hook is configured, a verified authentication may fail with 403
+//! and `step_up_required` or `authentication_denied`.
+//!
+//! ### `POST ${BASE_PATH}token/refresh`
+//!
+//! Issues a new access token and the next refresh token with a refresh token.
+//! Available only if self-issued tokens are enabled.
+//! The request body is [`RefreshTokenRequest`] as `application/json`.
+//! The response body is [`TokenResult`] as `application/json`.
+//! A refresh token is usable only once. Fails with 401 if the refresh token
+//! is unknown, expired, or has already been used; reusing a refresh token
+//! revokes every refresh token issued since the authentication.
+//!
+//! ### `GET ${BASE_PATH}jwks`
+//!
+//! Returns the JWKS to verify tokens as `application/json`.
+//! Available only if self-issued tokens are enabled.
 
-use aws_sdk_dynamodb::{
-    primitives::DateTime,
-    types::AttributeValue,
-};
+use aws_config::SdkConfig;
+use aws_sdk_dynamodb::primitives::DateTime;
 use base64::{
     Engine as _,
     engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
@@ -33,106 +176,750 @@ use lambda_http::{
     RequestExt,
     Response,
     http::StatusCode,
-    run,
-    service_fn,
 };
-use std::env;
 use std::sync::Arc;
-use std::time::SystemTime;
-use tracing::{error, info};
-use webauthn_rs::{Webauthn, WebauthnBuilder};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{Instrument, error, info, info_span, instrument};
+use webauthn_rs::prelude::{DiscoverableAuthentication, DiscoverableKey, Passkey};
+use webauthn_rs_proto::CollectedClientData;
 
-use authentication::parameters::load_relying_party_origin;
+use authentication::api_error::{ApiError, handle_api_errors};
+use authentication::audit::{
+    AuditEvent,
+    AuditEventType,
+    AuditLog,
+    ClientInfo,
+    load_audit_log,
+};
+use authentication::captcha::{CaptchaVerifier, load_captcha_verifier, require_captcha};
+use authentication::config::{self, ConfigCheck, load_config_parameters};
+use authentication::content::negotiate_content;
+use authentication::correlation::load_sdk_config;
+use authentication::credential_store::{
+    CredentialBackend,
+    CredentialStore,
+    record_authentication,
+};
+use authentication::client_binding::{
+    ClientBindingPolicy,
+    load_client_binding_policy,
+};
+use authentication::domain_events::{
+    DomainEvent,
+    EventPublisher,
+    AuthenticationSucceeded,
+    load_event_publisher,
+    publish_event,
+};
+use authentication::extensions::{ExtensionPolicy, load_extension_policy};
+use authentication::hints::{
+    PublicKeyCredentialHint,
+    load_hints,
+    merge_hints_into,
+    parse_hints,
+    resolve_hints,
+};
+use authentication::health::{HEALTH_PATH, health_check};
+use authentication::items::{
+    CredentialItem,
+    CredentialKey,
+    DiscoverableSessionItem,
+};
+use authentication::lockout::{
+    CredentialLockout,
+    credential_locked,
+    load_credential_lockout,
+};
+use authentication::metrics::{ColdStart, load_metrics};
+use authentication::parameters::load_webauthn;
+use authentication::passkey::is_user_verified_in;
+use authentication::payload::{
+    ErrorResponseBody,
+    load_max_body_size,
+    parse_json_payload,
+};
+use authentication::pii::load_pii_protection;
+use authentication::policy::{
+    ChallengeTimeout,
+    load_challenge_timeout,
+};
+use authentication::routing::{
+    ApiVersion,
+    Router,
+    job_path,
+    require_json_body,
+    resolve_version,
+    unsupported_version,
+};
+use authentication::telemetry::{init_tracing, redact, request_span, truncate};
+use authentication::rate_limit::too_many_requests;
+use authentication::tenant::{
+    QuotaKind,
+    Tenant,
+    TenantDirectory,
+    load_tenant_directory,
+    unknown_tenant,
+};
+use authentication::refresh::{
+    RefreshOutcome,
+    RefreshTokenRequest,
+    RefreshTokenStore,
+};
+use authentication::rp_migration::{load_legacy_relying_party, verify_with_fallback};
+use authentication::risk::{
+    RemoteRiskHook,
+    RiskContext,
+    assess_risk,
+    load_risk_hook,
+    risk_rejected,
+};
+use authentication::secrets::{SecretCache, load_secret_cache_ttl};
+use authentication::sessions::{
+    TakenSession,
+    put_discoverable_session,
+    take_discoverable_session,
+};
+use authentication::store::{DynamoDbSessionStore, SessionBackend, load_database};
+use authentication::token::{
+    FinishTokenSession,
+    TokenIssuer,
+    TokenResult,
+    load_token_issuer,
+};
+use authentication::users::UserDirectory;
+use authentication::warmer::run_with_warmer;
+
+// Maximum number of attempts to generate a unique challenge.
+const MAX_CHALLENGE_ATTEMPTS: usize = 3;
 
 // State shared among Lambda invocations.
 struct SharedState {
-    webauthn: Webauthn,
+    default_tenant: Arc<Tenant>,
+    tenants: Option<TenantDirectory>,
     dynamodb: aws_sdk_dynamodb::Client,
     base_path: String,
     session_table_name: String,
+    sessions: SessionBackend,
+    challenge_timeout: ChallengeTimeout,
+    extension_policy: ExtensionPolicy,
+    hints: Option<Vec<PublicKeyCredentialHint>>,
+    max_body_size: usize,
+    client_binding: ClientBindingPolicy,
+    captcha: Option<CaptchaVerifier>,
+    risk_hook: Option<RemoteRiskHook>,
+    // only if self-issued tokens are enabled
+    token_issuer: Option<TokenIssuer>,
+    refresh_tokens: Option<RefreshTokenStore>,
+    users: Option<CredentialBackend>,
+    lockout: Option<CredentialLockout>,
+    audit_log: Option<AuditLog>,
+    event_publisher: Option<EventPublisher>,
+}
+
+// Configuration validated at cold start.
+struct Config {
+    base_path: String,
+    session_table_name: String,
+    challenge_timeout: ChallengeTimeout,
+    extension_policy: ExtensionPolicy,
+    hints: Option<Vec<PublicKeyCredentialHint>>,
+    max_body_size: usize,
+    client_binding: ClientBindingPolicy,
+    secret_cache_ttl: Duration,
+}
+
+impl Config {
+    fn from_env() -> Result<Self, Error> {
+        let mut check = ConfigCheck::new();
+        check.required_any(&["RP_ORIGIN", "RP_ORIGIN_PARAMETER_PATH"]);
+        let config = Self {
+            base_path: check.required("BASE_PATH"),
+            session_table_name: check.required("SESSION_TABLE_NAME"),
+            challenge_timeout: check.load(load_challenge_timeout()),
+            extension_policy: check.load(load_extension_policy()),
+            hints: check.load(load_hints()),
+            max_body_size: check.load(load_max_body_size()),
+            client_binding: check.load(load_client_binding_policy()),
+            secret_cache_ttl: check.load(load_secret_cache_ttl()),
+        };
+        Ok(check.finish(config)?)
+    }
 }
 
 impl SharedState {
-    async fn new() -> Result<Self, Error> {
-        let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
-        let (rp_id, rp_origin) =
-            load_relying_party_origin(aws_sdk_ssm::Client::new(&config)).await?;
-        let webauthn = WebauthnBuilder::new(&rp_id, &rp_origin)?
-            .rp_name("Passkey Test")
-            .build()?;
-        let base_path = env::var("BASE_PATH")
-            .or(Err("BASE_PATH env must be set"))?;
+    #[instrument(name = "cold_start", skip_all)]
+    async fn new(sdk_config: &SdkConfig, config: Config) -> Result<Self, Error> {
+        let ssm = aws_sdk_ssm::Client::new(sdk_config);
+        let webauthn = load_webauthn(ssm.clone()).await?;
+        let legacy_relying_party = load_legacy_relying_party(ssm).await?;
+        let dynamodb = aws_sdk_dynamodb::Client::new(sdk_config);
+        let secretsmanager = aws_sdk_secretsmanager::Client::new(sdk_config);
+        let secrets = SecretCache::new(secretsmanager.clone(), config.secret_cache_ttl);
+        let risk_hook = load_risk_hook(
+            aws_sdk_lambda::Client::new(sdk_config),
+            secretsmanager.clone(),
+        )?;
+        let token_issuer =
+            load_token_issuer(aws_sdk_kms::Client::new(sdk_config), &secrets).await?;
+        let users = match token_issuer {
+            Some(_) => Some(UserDirectory::new(
+                dynamodb.clone(),
+                config::var("CREDENTIAL_TABLE_NAME")
+                    .or(Err(ApiError::config(
+                        "CREDENTIAL_TABLE_NAME env must be set to issue tokens",
+                    )))?,
+            ).with_pii_protection(load_pii_protection(
+                aws_sdk_kms::Client::new(sdk_config),
+                secretsmanager.clone(),
+            )?)),
+            None => None,
+        };
+        // the lockout stays on the DynamoDB credential table
+        let lockout = users.as_ref()
+            .map(|users| load_credential_lockout(
+                dynamodb.clone(),
+                users.table_name().into(),
+            ))
+            .transpose()?
+            .flatten();
+        let database = load_database().await?;
+        let users = users.map(|users| CredentialBackend::new(users, database.as_ref()));
+        let session_table_name = config.session_table_name;
+        let refresh_tokens = token_issuer.as_ref().map(|issuer| RefreshTokenStore::new(
+            dynamodb.clone(),
+            session_table_name.clone(),
+            issuer.settings().refresh_ttl,
+        ));
         Ok(Self {
-            webauthn,
-            dynamodb: aws_sdk_dynamodb::Client::new(&config),
-            base_path: base_path.trim_end_matches('/').into(),
-            session_table_name: env::var("SESSION_TABLE_NAME")
-                .or(Err("SESSION_TABLE_NAME env must be set"))?,
+            default_tenant: Arc::new(
+                Tenant::default_tenant(webauthn)
+                    .with_legacy_relying_party(legacy_relying_party),
+            ),
+            tenants: load_tenant_directory(dynamodb.clone())?,
+            dynamodb: dynamodb.clone(),
+            base_path: config.base_path.trim_end_matches('/').into(),
+            sessions: SessionBackend::new(
+                DynamoDbSessionStore::new(dynamodb.clone(), session_table_name.clone()),
+                database.as_ref(),
+            ),
+            session_table_name,
+            challenge_timeout: config.challenge_timeout,
+            extension_policy: config.extension_policy,
+            hints: config.hints,
+            max_body_size: config.max_body_size,
+            client_binding: config.client_binding,
+            captcha: load_captcha_verifier(secretsmanager)?,
+            risk_hook,
+            token_issuer,
+            refresh_tokens,
+            lockout,
+            users,
+            audit_log: load_audit_log(dynamodb)?,
+            event_publisher: load_event_publisher(
+                aws_sdk_eventbridge::Client::new(sdk_config),
+            )?,
         })
     }
+
+    // returns whether a credential item is enabled.
+    //
+    // the authenticator attachment of a credential is not checked, because
+    // it is reported by the client without a signature.
+    fn is_allowed_credential(&self, credential: &CredentialItem) -> bool {
+        credential.disabled_at.is_none()
+    }
+
+    // records an authentication in the audit log.
+    async fn audit_authentication(
+        &self,
+        event_type: AuditEventType,
+        key: CredentialKey<'_>,
+        client: &ClientInfo,
+        detail: Option<String>,
+    ) {
+        if let Some(audit_log) = self.audit_log.as_ref() {
+            audit_log.record_best_effort(AuditEvent {
+                event_type,
+                user_handle: key.user_handle.into(),
+                credential_id: Some(key.credential_id.into()),
+                client: client.clone(),
+                detail,
+            }).await;
+        }
+    }
+
+    // records a failed authentication with a registered credential.
+    //
+    // returns the duration of the lock if the failure locks the credential.
+    async fn record_failure(
+        &self,
+        key: CredentialKey<'_>,
+        registered: bool,
+        now: i64,
+    ) -> Result<Option<u64>, Error> {
+        match self.lockout.as_ref() {
+            Some(lockout) if registered => Ok(lockout.record_failure(key, now).await?),
+            _ => Ok(None),
+        }
+    }
+}
+
+// Context of a job.
+#[derive(Clone)]
+struct Job {
+    shared_state: Arc<SharedState>,
+    tenant: Arc<Tenant>,
+}
+
+// routes of the jobs.
+//
+// the `finish`, `token/refresh`, and `jwks` endpoints are served only if
+// self-issued tokens are enabled.
+fn router(token_issuer_enabled: bool) -> Router<Job> {
+    let router = Router::new()
+        .post("/start", |job: Job, event: Request, _| async move {
+            if let Some(res) = require_captcha(job.shared_state.captcha.as_ref(), &event).await? {
+                return Ok(res);
+            }
+            let client = ClientInfo::of(&event);
+            let hints = requested_hints(&event)?;
+            start_authentication(job.shared_state, job.tenant, client, hints).await
+        });
+    if !token_issuer_enabled {
+        return router;
+    }
+    router
+        .post("/finish", |job: Job, event: Request, _| async move {
+            require_json_body(&event)?;
+            finish_authentication(job.shared_state, job.tenant, event).await
+        })
+        .post("/token/refresh", |job: Job, event: Request, _| async move {
+            require_json_body(&event)?;
+            refresh_token(job.shared_state, job.tenant, event).await
+        })
+        .get("/jwks", |job: Job, _, _| async move { get_jwks(job.shared_state) })
 }
 
 async fn function_handler(
     shared_state: Arc<SharedState>,
+    router: &Router<Job>,
     event: Request,
 ) -> Result<Response<Body>, Error> {
-    let job_path = event.raw_http_path().strip_prefix(&shared_state.base_path)
-        .ok_or(format!("path must start with {}", shared_state.base_path))?;
-    match job_path {
-        "/start" => start_authentication(shared_state).await,
-        _ => Err(format!("unsupported job path: {}", job_path).into()),
+    let job_path = job_path(&event, &shared_state.base_path)?;
+    if job_path == HEALTH_PATH {
+        let mut tables = vec![
+            ("sessionTable", shared_state.session_table_name.as_str()),
+        ];
+        if let Some(table_name) = shared_state.users.as_ref()
+            .and_then(CredentialBackend::table_name)
+        {
+            tables.push(("credentialTable", table_name));
+        }
+        return health_check("discoverable", &event, &shared_state.dynamodb, &tables).await;
     }
+    let (tenant, job_path) = match shared_state.tenants.as_ref() {
+        Some(tenants) => match tenants.resolve_request(&event, job_path).await? {
+            Some(resolved) => resolved,
+            None => return unknown_tenant(),
+        },
+        None => (shared_state.default_tenant.clone(), job_path),
+    };
+    let route = match resolve_version(job_path) {
+        Some((ApiVersion::V1, route)) => route.to_string(),
+        None => return unsupported_version(job_path),
+    };
+    let job = Job {
+        shared_state,
+        tenant,
+    };
+    router.handle(job, event, &route).await
 }
 
+#[instrument(skip_all)]
 async fn start_authentication(
     shared_state: Arc<SharedState>,
+    tenant: Arc<Tenant>,
+    client: ClientInfo,
+    hints: Option<Vec<PublicKeyCredentialHint>>,
 ) -> Result<Response<Body>, Error> {
-    info!("start_authentication");
-    let res = match shared_state.webauthn.start_discoverable_authentication() {
-        Ok((rcr, auth_state)) => {
-            let ttl = DateTime::from(SystemTime::now()).secs() + 60;
-            info!("putting authentication session: {}", base64url.encode(&rcr.public_key.challenge));
-            shared_state.dynamodb
-                .put_item()
-                .table_name(shared_state.session_table_name.clone())
-                .item(
-                    "pk",
-                    AttributeValue::S(
-                        format!("discoverable#{}", base64url.encode(&rcr.public_key.challenge)),
-                    ),
-                )
-                .item("ttl", AttributeValue::N(ttl.to_string()))
-                .item(
-                    "state",
-                    AttributeValue::S(serde_json::to_string(&auth_state)?),
-                )
-                .send()
-                .await?;
-            serde_json::to_string(&rcr)?
+    info!("start_authentication: {:?}", hints);
+    let hints = resolve_hints(shared_state.hints.as_deref(), hints.as_deref());
+    if let Some(retry_after) = tenant.hit_quota(
+        &shared_state.dynamodb,
+        &shared_state.session_table_name,
+        QuotaKind::Authentication,
+        DateTime::from(SystemTime::now()).secs(),
+    ).await? {
+        return Ok(too_many_requests(retry_after)?);
+    }
+    let client_binding = shared_state.client_binding.bind(&client);
+    // never overwrites an existing session; starts over with a new challenge
+    // upon collision
+    for _ in 0..MAX_CHALLENGE_ATTEMPTS {
+        let (mut rcr, auth_state) =
+            match tenant.webauthn().start_discoverable_authentication() {
+                Ok(res) => res,
+                Err(e) => {
+                    error!("failed to start authentication: {}", e);
+                    return Err(ApiError::internal("failed to start authentication").into());
+                }
+            };
+        rcr.public_key.timeout = Some(shared_state.challenge_timeout.as_millis());
+        let challenge = base64url.encode(&rcr.public_key.challenge);
+        let ttl = shared_state.challenge_timeout
+            .session_ttl(DateTime::from(SystemTime::now()).secs());
+        info!("putting authentication session: {}", truncate(&challenge));
+        let session = DiscoverableSessionItem {
+            ttl,
+            state: serde_json::to_string(&auth_state)?,
+            client_binding: client_binding.clone(),
+        };
+        if !put_discoverable_session(&shared_state.sessions, &tenant, &challenge, session).await? {
+            error!("challenge collision: {}", truncate(&challenge));
+            continue;
+        }
+        let mut options = shared_state.extension_policy
+            .authentication_inputs()
+            .add_to(&rcr)?;
+        if let Some(hints) = hints.as_deref() {
+            merge_hints_into(hints, &mut options);
         }
+        return Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&options)?.into())?);
+    }
+    Err(ApiError::internal("failed to generate a unique challenge").into())
+}
+
+// extracts the hints requested by the `hints` query parameter.
+fn requested_hints(
+    event: &Request,
+) -> Result<Option<Vec<PublicKeyCredentialHint>>, Error> {
+    let Some(hints) = event.query_string_parameters_ref()
+        .and_then(|params| params.first("hints")) else
+    {
+        return Ok(None);
+    };
+    match parse_hints(hints) {
+        Ok(hints) => Ok(Some(hints)),
+        Err(_) => Err(ApiError::BadRequest(format!("unknown hints: {}", hints)).into()),
+    }
+}
+
+#[instrument(skip_all)]
+async fn finish_authentication(
+    shared_state: Arc<SharedState>,
+    tenant: Arc<Tenant>,
+    event: Request,
+) -> Result<Response<Body>, Error> {
+    info!("finish_authentication");
+    let token_issuer = shared_state.token_issuer.as_ref()
+        .ok_or(ApiError::NotConfigured("self-issued tokens not enabled"))?;
+    let users = shared_state.users.as_ref()
+        .ok_or(ApiError::NotConfigured("self-issued tokens not enabled"))?;
+    let session: FinishTokenSession = match parse_json_payload(
+        event.body().as_ref(),
+        shared_state.max_body_size,
+    ) {
+        Ok(session) => session,
+        Err(e) => {
+            error!("bad payload: {:?}", e);
+            return e.into_response();
+        }
+    };
+    let client = ClientInfo::of(&event);
+    let credential = session.public_key_credential;
+    let Some(user_handle) = credential.response.user_handle.as_ref()
+        .map(|h| base64url.encode(h)) else
+    {
+        error!("missing user handle in credential");
+        return authentication_failed();
+    };
+
+    // extracts the challenge from `credential`
+    let client_data: CollectedClientData = match serde_json::from_slice(
+        credential.response.client_data_json.as_ref(),
+    ) {
+        Ok(client_data) => client_data,
+        Err(e) => {
+            error!("malformed client data: {}", e);
+            return authentication_failed();
+        }
+    };
+    if client_data.type_ != "webauthn.get" {
+        error!("invalid client data type: {}", client_data.type_);
+        return authentication_failed();
+    }
+
+    // a session is used only once
+    let challenge = base64url.encode(client_data.challenge);
+    let now = DateTime::from(SystemTime::now()).secs();
+    let item = take_discoverable_session(&shared_state.sessions, &tenant, &challenge, now)
+        .await?
+        .and_then(TakenSession::valid);
+    let Some(item) = item else {
+        error!("expired or unknown session: {}", truncate(&challenge));
+        return authentication_failed();
+    };
+    // the challenge may have been relayed to another client
+    if !item.client_binding.matches(&client) {
+        error!("session bound to another client: {}", truncate(&challenge));
+        return authentication_failed();
+    }
+    let auth_state: DiscoverableAuthentication = serde_json::from_str(&item.state)?;
+
+    // obtains the credentials (passkeys) associated with the user
+    let credentials: Vec<CredentialItem> = users
+        .list_credentials(&user_handle)
+        .await?
+        .into_iter()
+        .filter(|c| shared_state.is_allowed_credential(c))
+        .collect();
+    let passkeys: Vec<Passkey> = credentials.iter()
+        .map(|c| serde_json::from_str::<Passkey>(&c.credential)
+            .or(Err(ApiError::Storage("malformed credential in the database"))))
+        .collect::<Result<Vec<_>, _>>()?;
+    let discoverable_keys: Vec<DiscoverableKey> = passkeys.iter()
+        .map(|c| c.into())
+        .collect();
+
+    // a locked credential is rejected before verification
+    let credential_id = base64url.encode(&credential.raw_id);
+    let credential_key = CredentialKey {
+        user_handle: &user_handle,
+        credential_id: &credential_id,
+    };
+    let registered = credentials.iter().any(|c| c.credential_id == credential_id);
+    let lockout_state = match shared_state.lockout.as_ref() {
+        Some(lockout) if registered => lockout.get(credential_key).await?,
+        _ => None,
+    };
+    if let Some(retry_after) = lockout_state.and_then(|s| s.retry_after(now)) {
+        error!("credential locked: {}", truncate(&credential_id));
+        return credential_locked(retry_after);
+    }
+
+    let verified = info_span!("verify_authentication").in_scope(|| {
+        verify_with_fallback(&tenant, |webauthn| {
+            webauthn.finish_discoverable_authentication(
+                &credential,
+                auth_state.clone(),
+                &discoverable_keys,
+            )
+        })
+    });
+    // the UV flag in the authenticator data is also checked by itself
+    let outcome = match verified {
+        Ok((auth_result, legacy_rp_id)) if auth_result.user_verified()
+            && is_user_verified_in(credential.response.authenticator_data.as_ref())
+            => Ok((auth_result, legacy_rp_id)),
+        Ok(_) => Err(("user verification required but not performed".to_string(), true)),
+        Err(e) => Err((format!("authentication failed: {}", e), false)),
+    };
+    let (auth_result, legacy_rp_id) = match outcome {
+        Ok(res) => res,
+        Err((message, user_unverified)) => {
+            error!("{}", message);
+            shared_state.audit_authentication(
+                AuditEventType::AuthenticationFailed,
+                credential_key,
+                &client,
+                Some(message),
+            ).await;
+            return match shared_state.record_failure(credential_key, registered, now).await? {
+                Some(duration) => credential_locked(duration),
+                None if user_unverified => user_verification_required(),
+                None => authentication_failed(),
+            };
+        }
+    };
+
+    // the risk assessment may reject a verified authentication
+    if let Some(credential_item) = credentials.iter()
+        .find(|c| c.credential_id == credential_id)
+    {
+        let user_verified = auth_result.user_verified();
+        let assessment = assess_risk(
+            shared_state.risk_hook.as_ref(),
+            &RiskContext::new(credential_item, tenant.id(), &client, user_verified),
+        ).await?;
+        if let Some(rejection) = assessment.rejection(user_verified) {
+            error!("rejected by risk assessment: {} {:?}", rejection, assessment.reason);
+            shared_state.audit_authentication(
+                AuditEventType::AuthenticationFailed,
+                credential_key,
+                &client,
+                Some(match assessment.reason {
+                    Some(reason) => format!("{}: {}", rejection, reason),
+                    None => rejection.into(),
+                }),
+            ).await;
+            return risk_rejected(rejection);
+        }
+    }
+
+    if let (Some(lockout), Some(_)) = (shared_state.lockout.as_ref(), lockout_state) {
+        lockout.reset(credential_key).await?;
+    }
+    shared_state.audit_authentication(
+        AuditEventType::AuthenticationSucceeded,
+        credential_key,
+        &client,
+        None,
+    ).await;
+
+    // updates the stored credential if necessary
+    if let Some(credential_item) = credentials.into_iter()
+        .find(|c| c.credential_id == credential_id)
+    {
+        if let Some(legacy_rp_id) = legacy_rp_id {
+            users.flag_legacy_credential(&credential_item, legacy_rp_id).await?;
+        }
+        record_authentication(users, credential_item, &auth_result).await?;
+    }
+    publish_event(
+        shared_state.event_publisher.as_ref(),
+        DomainEvent::AuthenticationSucceeded(AuthenticationSucceeded {
+            user_handle: user_handle.clone(),
+            credential_id,
+            tenant_id: tenant.id().map(Into::into),
+        }),
+    ).await;
+
+    info!("issuing token: {}", redact(&user_handle));
+    let access_token = token_issuer.issue(&user_handle, tenant.id()).await?;
+    let refresh_token = shared_state.refresh_tokens.as_ref()
+        .ok_or(ApiError::NotConfigured("self-issued tokens not enabled"))?
+        .start_family(&tenant, &user_handle)
+        .await?;
+    let body = serde_json::to_string(&TokenResult::new(access_token, refresh_token))?;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(body.into())?)
+}
+
+#[instrument(skip_all)]
+async fn refresh_token(
+    shared_state: Arc<SharedState>,
+    tenant: Arc<Tenant>,
+    event: Request,
+) -> Result<Response<Body>, Error> {
+    info!("refresh_token");
+    let token_issuer = shared_state.token_issuer.as_ref()
+        .ok_or(ApiError::NotConfigured("self-issued tokens not enabled"))?;
+    let refresh_tokens = shared_state.refresh_tokens.as_ref()
+        .ok_or(ApiError::NotConfigured("self-issued tokens not enabled"))?;
+    let request: RefreshTokenRequest = match parse_json_payload(
+        event.body().as_ref(),
+        shared_state.max_body_size,
+    ) {
+        Ok(request) => request,
         Err(e) => {
-            error!("failed to start authentication: {}", e);
-            return Err("failed to start authentication".into());
+            error!("bad payload: {:?}", e);
+            return e.into_response();
+        }
+    };
+    let (user_handle, refresh_token) = match refresh_tokens
+        .rotate(&tenant, &request.refresh_token)
+        .await?
+    {
+        RefreshOutcome::Rotated { user_handle, refresh_token } => {
+            (user_handle, refresh_token)
+        }
+        RefreshOutcome::Invalid => {
+            error!("invalid refresh token");
+            return invalid_refresh_token();
+        }
+        RefreshOutcome::Reused => {
+            error!("refresh token reused");
+            return invalid_refresh_token();
         }
     };
+    // the user may have deleted the account
+    let users = shared_state.users.as_ref()
+        .ok_or(ApiError::NotConfigured("self-issued tokens not enabled"))?;
+    if users.get_user(&user_handle).await?.is_none() {
+        error!("refresh token of deleted user: {}", redact(&user_handle));
+        return invalid_refresh_token();
+    }
+    info!("refreshing token: {}", redact(&user_handle));
+    let access_token = token_issuer.issue(&user_handle, tenant.id()).await?;
+    let body = serde_json::to_string(&TokenResult::new(access_token, refresh_token))?;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(body.into())?)
+}
+
+fn get_jwks(shared_state: Arc<SharedState>) -> Result<Response<Body>, Error> {
+    let token_issuer = shared_state.token_issuer.as_ref()
+        .ok_or(ApiError::NotConfigured("self-issued tokens not enabled"))?;
     Ok(Response::builder()
         .status(StatusCode::OK)
-        .header("Content-Type", "text/plain")
-        .body(res.into())?)
+        .header("Content-Type", "application/json")
+        .header("Cache-Control", "public, max-age=300")
+        .body(serde_json::to_string(&token_issuer.jwks())?.into())?)
+}
+
+// creates a 401 response for a failed authentication.
+fn authentication_failed() -> Result<Response<Body>, Error> {
+    unauthorized("authentication_failed", "authentication failed")
+}
+
+// creates a 401 response for an assertion without user verification where
+// the policy requires it.
+fn user_verification_required() -> Result<Response<Body>, Error> {
+    unauthorized("user_verification_required", "user verification required")
+}
+
+// creates a 401 response for an unusable refresh token.
+//
+// does not tell whether the token has been reused.
+fn invalid_refresh_token() -> Result<Response<Body>, Error> {
+    unauthorized("invalid_refresh_token", "refresh token is invalid or expired")
+}
+
+// creates a 401 response.
+fn unauthorized(error: &'static str, message: &str) -> Result<Response<Body>, Error> {
+    let body = serde_json::to_string(&ErrorResponseBody {
+        error,
+        message: message.into(),
+        field: None,
+        localized_message: None,
+        request_id: None,
+    })?;
+    Ok(Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .header("Content-Type", "application/json")
+        .body(body.into())?)
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::INFO)
-        // disable printing the name of the module in every log line.
-        .with_target(false)
-        // disabling time is handy because CloudWatch will add the ingestion time.
-        .without_time()
-        .init();
-
-    let shared_state = Arc::new(SharedState::new().await?);
-    run(service_fn(|req| async {
-        function_handler(shared_state.clone(), req).await
-    })).await
+    let started_at = Instant::now();
+    let telemetry = init_tracing("discoverable")?;
+
+    let sdk_config = load_sdk_config().await?;
+    load_config_parameters(&aws_sdk_ssm::Client::new(&sdk_config)).await?;
+    let config = Config::from_env()?;
+    let shared_state = Arc::new(SharedState::new(&sdk_config, config).await?);
+    let router = router(shared_state.token_issuer.is_some());
+    let metrics = load_metrics("discoverable")?;
+    let cold_start = ColdStart::initialized_since(started_at);
+    run_with_warmer(&cold_start, &metrics, |req: Request| async {
+        let span = request_span(&req);
+        let handler_started_at = Instant::now();
+        let res = negotiate_content(
+            req,
+            shared_state.max_body_size,
+            |req| handle_api_errors(function_handler(shared_state.clone(), &router, req)),
+        )
+            .instrument(span)
+            .await;
+        cold_start.report(&metrics, handler_started_at.elapsed());
+        telemetry.flush().await;
+        res
+    }).await
 }
@@ -0,0 +1,327 @@
+//! GraphQL facade of the credential management of authenticated users.
+//!
+//! Available if the `graphql` feature is enabled. Serves the schema of
+//! [`authentication::graphql`] on the same tables as the credentials
+//! function.
+//!
+//! You have to configure the following environment variables:
+//! - `BASE_PATH`: base path to provide the service; e.g., `/auth/credentials/graphql/`
+//! - `CREDENTIAL_TABLE_NAME`: name of the DynamoDB table that manages
+//!   credentials
+//! - `SESSION_TABLE_NAME`: name of the DynamoDB table to store step-up
+//!   sessions and tokens
+//! - `RP_ORIGIN_PARAMETER_PATH`: path to the parameter that stores the origin
+//!   (URL) of the relying party in the Parameter Store on AWS Systems Manager
+//!
+//! You can optionally configure the following environment variables:
+//! - `CONFIG_PARAMETER_PATH`: path to the parameters in Parameter Store on
+//!   AWS Systems Manager that override the other environment variables. See
+//!   [`authentication::config`] for details.
+//! - `RP_ORIGIN`: origin (URL) of the relying party that takes precedence over
+//!   `RP_ORIGIN_PARAMETER_PATH`
+//! - `RP_ID`: ID of the relying party; the domain of the origin by default
+//! - `RP_ALLOWED_ORIGINS`: comma-separated origins (URLs) allowed in addition
+//!   to the origin of the relying party; e.g., `https://www.example.com`
+//! - `ANDROID_APK_KEY_HASHES`: comma-separated SHA-256 hashes of the
+//!   certificates that sign Android apps allowed to use the relying party.
+//!   See [`authentication::android`] for details.
+//! - `CHALLENGE_TIMEOUT`: timeout of a step-up in seconds; 60 by default.
+//! - `MAX_BODY_SIZE`: maximum size of a request body in bytes; 32 KiB by
+//!   default. Larger requests are rejected with 413.
+//! - `AUDIT_TABLE_NAME`: name of the DynamoDB table for the audit log.
+//!   Deleted and restored credentials and failed step-ups are recorded if
+//!   specified.
+//! - `EVENT_BUS_NAME`: name of the EventBridge event bus. Deleted credentials
+//!   are published as `CredentialRevoked` if specified; see
+//!   [`authentication::domain_events`].
+//! - `WEBHOOK_URL`, `WEBHOOK_SECRET_ID`: URL to which deleted credentials are
+//!   posted and the ID of the secret that signs the requests. Disabled unless
+//!   specified; see [`authentication::webhooks`].
+//! - `DELETION_RETENTION`: retention window of deleted credentials in
+//!   seconds; 30 days by default. "0" deletes credentials immediately. Must
+//!   be the same as the credentials function. See
+//!   [`authentication::deletion`].
+//! - `MAX_CREDENTIALS_PER_USER`: maximum number of credentials per user.
+//!   `restoreCredential` beyond the limit fails with
+//!   `credential_limit_exceeded`. Must be the same as the registration.
+//!   Unlimited unless specified.
+//! - `SESSION_ID_SECRET_ID`: ID of the secret in Secrets Manager that signs
+//!   session IDs of step-ups. Step-ups with a forged or truncated session ID
+//!   fail without looking up the session table. Session IDs are not signed
+//!   unless specified. See [`authentication::session_id`].
+//! - `TENANT_TABLE_NAME`, `TENANT_RESOLUTION`: table of tenants and how a
+//!   tenant is resolved from a request. Step-ups are verified by the default
+//!   relying party unless specified. See [`authentication::tenant`] for
+//!   details.
+//! - `PII_KMS_KEY_ARN`, `PII_INDEX_SECRET_ID`: ARN of the KMS key and ID of
+//!   the secret in Secrets Manager that protect the usernames and display
+//!   names of users. Stored in plaintext unless specified. See
+//!   [`authentication::pii`].
+//! - `LOG_LEVEL`, `LOG_REDACTION`: log level or `RUST_LOG`-style directives,
+//!   and whether identifiers are redacted in logs; "info" and redacted by
+//!   default. See [`authentication::telemetry`] for details.
+//! - `METRICS_NAMESPACE`: namespace of the CloudWatch metrics; "PasskeyTest"
+//!   by default. The cold start of the function is reported as metrics; see
+//!   [`ColdStart`].
+//!
+//! The function fails at cold start if any required variable is missing or
+//! any variable is invalid, and the error lists all of them; see
+//! [`authentication::config`].
+//!
+//! The endpoint must be protected by a JWT authorizer that verifies tokens
+//! issued by the Cognito user pool.
+//!
+//! ## Endpoint
+//!
+//! ### `POST ${BASE_PATH}`
+//!
+//! Executes a GraphQL request as `application/json`; e.g.,
+//! `{"query": "{ credentials { credentials { credentialId } } }"}`.
+//! The response body is the GraphQL response as `application/json`, which
+//! ends with 200 even if the operation fails; see the `errors`.
+//! The schema evolves without versions, so the path is not versioned. If
+//! tenants are configured and resolved by path, the path is prefixed with
+//! the tenant key; e.g., `${BASE_PATH}acme/`.
+//! `GET ${BASE_PATH}health` serves the health check, which requires no
+//! authentication; see [`authentication::health`].
+//! Another path ends with 404, and a method other than POST ends with 405;
+//! see [`Router`].
+//! A scheduled warm-up event is answered with 200 without serving a request;
+//! see [`authentication::warmer`].
+
+use aws_config::SdkConfig;
+use lambda_http::{
+    Body,
+    Error,
+    Request,
+    Response,
+    http::StatusCode,
+};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{Instrument, info, instrument};
+
+use authentication::api_error::{ApiError, handle_api_errors};
+use authentication::audit::{ClientInfo, load_audit_log};
+use authentication::config::{ConfigCheck, load_config_parameters};
+use authentication::correlation::load_sdk_config;
+use authentication::deletion::load_deletion_retention;
+use authentication::domain_events::load_event_publisher;
+use authentication::graphql::{
+    CredentialSchema,
+    GraphQlServices,
+    Viewer,
+    build_schema,
+};
+use authentication::health::{HEALTH_PATH, health_check};
+use authentication::identity::authenticated_user_handle;
+use authentication::metrics::{ColdStart, load_metrics};
+use authentication::parameters::load_webauthn;
+use authentication::payload::{load_max_body_size, parse_json_payload};
+use authentication::pii::load_pii_protection;
+use authentication::policy::{
+    ChallengeTimeout,
+    CredentialLimit,
+    load_challenge_timeout,
+    load_credential_limit,
+};
+use authentication::routing::{Router, job_path, require_json_body};
+use authentication::session_id::load_session_ids;
+use authentication::store::DynamoDbSessionStore;
+use authentication::telemetry::{init_tracing, redact, request_span};
+use authentication::tenant::{
+    Tenant,
+    TenantDirectory,
+    load_tenant_directory,
+    unknown_tenant,
+};
+use authentication::users::UserDirectory;
+use authentication::warmer::run_with_warmer;
+use authentication::webhooks::load_webhook_notifier;
+
+// State shared among Lambda invocations.
+struct SharedState {
+    schema: CredentialSchema,
+    default_tenant: Arc<Tenant>,
+    tenants: Option<TenantDirectory>,
+    dynamodb: aws_sdk_dynamodb::Client,
+    base_path: String,
+    session_table_name: String,
+    credential_table_name: String,
+    audit_table_name: Option<String>,
+    max_body_size: usize,
+}
+
+// Configuration validated at cold start.
+struct Config {
+    base_path: String,
+    session_table_name: String,
+    credential_table_name: String,
+    challenge_timeout: ChallengeTimeout,
+    max_body_size: usize,
+    deletion_retention: Option<Duration>,
+    credential_limit: Option<CredentialLimit>,
+}
+
+impl Config {
+    fn from_env() -> Result<Self, Error> {
+        let mut check = ConfigCheck::new();
+        check.required_any(&["RP_ORIGIN", "RP_ORIGIN_PARAMETER_PATH"]);
+        let config = Self {
+            base_path: check.required("BASE_PATH"),
+            session_table_name: check.required("SESSION_TABLE_NAME"),
+            credential_table_name: check.required("CREDENTIAL_TABLE_NAME"),
+            challenge_timeout: check.load(load_challenge_timeout()),
+            max_body_size: check.load(load_max_body_size()),
+            deletion_retention: check.load(load_deletion_retention()),
+            credential_limit: check.load(load_credential_limit()),
+        };
+        Ok(check.finish(config)?)
+    }
+}
+
+impl SharedState {
+    #[instrument(name = "cold_start", skip_all)]
+    async fn new(sdk_config: &SdkConfig, config: Config) -> Result<Self, Error> {
+        let webauthn = load_webauthn(aws_sdk_ssm::Client::new(sdk_config)).await?;
+        let dynamodb = aws_sdk_dynamodb::Client::new(sdk_config);
+        let audit_log = load_audit_log(dynamodb.clone())?;
+        let audit_table_name = audit_log.as_ref().map(|l| l.table_name().to_string());
+        let schema = build_schema(GraphQlServices {
+            users: UserDirectory::new(dynamodb.clone(), config.credential_table_name.clone())
+                .with_pii_protection(load_pii_protection(
+                    aws_sdk_kms::Client::new(sdk_config),
+                    aws_sdk_secretsmanager::Client::new(sdk_config),
+                )?),
+            sessions: DynamoDbSessionStore::new(
+                dynamodb.clone(),
+                config.session_table_name.clone(),
+            ),
+            challenge_timeout: config.challenge_timeout,
+            audit_log,
+            event_publisher: load_event_publisher(
+                aws_sdk_eventbridge::Client::new(sdk_config),
+            )?,
+            webhooks: load_webhook_notifier(
+                aws_sdk_secretsmanager::Client::new(sdk_config),
+            )?,
+            session_ids: load_session_ids(
+                aws_sdk_secretsmanager::Client::new(sdk_config),
+            )?,
+            deletion_retention: config.deletion_retention,
+            credential_limit: config.credential_limit,
+        });
+        Ok(Self {
+            schema,
+            default_tenant: Arc::new(Tenant::default_tenant(webauthn)),
+            tenants: load_tenant_directory(dynamodb.clone())?,
+            dynamodb,
+            base_path: config.base_path.trim_end_matches('/').into(),
+            session_table_name: config.session_table_name,
+            credential_table_name: config.credential_table_name,
+            audit_table_name,
+            max_body_size: config.max_body_size,
+        })
+    }
+}
+
+// Context of a job of the authenticated user.
+#[derive(Clone)]
+struct Job {
+    shared_state: Arc<SharedState>,
+    tenant: Arc<Tenant>,
+    user_handle: String,
+}
+
+// routes of the jobs.
+fn router() -> Router<Job> {
+    Router::new()
+        .post("/", |job: Job, event: Request, _| async move {
+            require_json_body(&event)?;
+            execute(job.shared_state, job.tenant, event, job.user_handle).await
+        })
+}
+
+async fn function_handler(
+    shared_state: Arc<SharedState>,
+    router: &Router<Job>,
+    event: Request,
+) -> Result<Response<Body>, Error> {
+    let job_path = job_path(&event, &shared_state.base_path)?;
+    if job_path == HEALTH_PATH {
+        let mut tables = vec![
+            ("sessionTable", shared_state.session_table_name.as_str()),
+            ("credentialTable", shared_state.credential_table_name.as_str()),
+        ];
+        if let Some(audit_table_name) = shared_state.audit_table_name.as_ref() {
+            tables.push(("auditTable", audit_table_name.as_str()));
+        }
+        return health_check("graphql", &event, &shared_state.dynamodb, &tables).await;
+    }
+    let user_handle = authenticated_user_handle(&event)
+        .ok_or(ApiError::Unauthenticated)?;
+    let (tenant, job_path) = match shared_state.tenants.as_ref() {
+        Some(tenants) => match tenants.resolve_request(&event, job_path).await? {
+            Some(resolved) => resolved,
+            None => return unknown_tenant(),
+        },
+        None => (shared_state.default_tenant.clone(), job_path),
+    };
+    // the base path without a trailing slash is the root
+    let route = if job_path.is_empty() { "/" } else { job_path }.to_string();
+    let job = Job {
+        shared_state,
+        tenant,
+        user_handle,
+    };
+    router.handle(job, event, &route).await
+}
+
+#[instrument(skip_all)]
+async fn execute(
+    shared_state: Arc<SharedState>,
+    tenant: Arc<Tenant>,
+    event: Request,
+    user_handle: String,
+) -> Result<Response<Body>, Error> {
+    let request: async_graphql::Request = parse_json_payload(
+        event.body().as_ref(),
+        shared_state.max_body_size,
+    )?;
+    info!("execute: {} {:?}", redact(&user_handle), request.operation_name);
+    let viewer = Viewer {
+        user_handle,
+        tenant,
+        client: ClientInfo::of(&event),
+    };
+    let res = shared_state.schema.execute(request.data(viewer)).await;
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(serde_json::to_string(&res)?.into())?)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let started_at = Instant::now();
+    let telemetry = init_tracing("graphql")?;
+
+    let sdk_config = load_sdk_config().await?;
+    load_config_parameters(&aws_sdk_ssm::Client::new(&sdk_config)).await?;
+    let config = Config::from_env()?;
+    let shared_state = Arc::new(SharedState::new(&sdk_config, config).await?);
+    let router = router();
+    let metrics = load_metrics("graphql")?;
+    let cold_start = ColdStart::initialized_since(started_at);
+    run_with_warmer(&cold_start, &metrics, |req: Request| async {
+        let span = request_span(&req);
+        let handler_started_at = Instant::now();
+        let res = handle_api_errors(function_handler(shared_state.clone(), &router, req))
+            .instrument(span)
+            .await;
+        cold_start.report(&metrics, handler_started_at.elapsed());
+        telemetry.flush().await;
+        res
+    }).await
+}
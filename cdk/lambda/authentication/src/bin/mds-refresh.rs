@@ -0,0 +1,142 @@
+//! Scheduled Lambda function that refreshes the FIDO Metadata Service BLOB.
+//!
+//! Downloads the BLOB, verifies it, and stores the entries in the metadata
+//! table unless the BLOB has already been stored. See
+//! [`authentication::mds`] for details.
+//!
+//! You have to configure the following environment variables:
+//! - `METADATA_TABLE_NAME`: name of the DynamoDB table that stores the
+//!   entries of the BLOB
+//! - `MDS_ROOT_CERTIFICATE_PARAMETER_PATH`: path to the parameter that stores
+//!   the root certificate of the FIDO Metadata Service in PEM in Parameter
+//!   Store on AWS Systems Manager
+//!
+//! You can optionally configure the following environment variables:
+//! - `CONFIG_PARAMETER_PATH`: path to the parameters in Parameter Store on
+//!   AWS Systems Manager that override the other environment variables. See
+//!   [`authentication::config`] for details.
+//! - `MDS_URL`: URL of the BLOB; [`DEFAULT_MDS_URL`] by default.
+//! - `LOG_LEVEL`, `LOG_REDACTION`: log level or `RUST_LOG`-style directives,
+//!   and whether identifiers are redacted in logs; "info" and redacted by
+//!   default. See [`authentication::telemetry`] for details.
+//! - `METRICS_NAMESPACE`: namespace of the CloudWatch metrics; "PasskeyTest"
+//!   by default. The number of stored entries is reported as
+//!   `mds_entries_stored`.
+//!
+//! Any event invokes a refresh; e.g., a scheduled event of Amazon
+//! EventBridge. The function fails if the BLOB cannot be verified, so the
+//! previous entries remain.
+//!
+//! The function fails at cold start if any required variable is missing or
+//! any variable is invalid, and the error lists all of them; see
+//! [`authentication::config`].
+
+use aws_config::SdkConfig;
+use aws_sdk_dynamodb::primitives::{DateTime, DateTimeFormat};
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+use tracing::{info, instrument, warn};
+
+use authentication::config::{self, ConfigCheck, load_config_parameters};
+use authentication::mds::{
+    DEFAULT_MDS_URL,
+    MetadataDirectory,
+    load_metadata_directory,
+    verify_blob,
+};
+use authentication::metrics::{ColdStart, Metrics, Unit, load_metrics};
+use authentication::parameters::load_mds_root_certificate;
+use authentication::telemetry::init_tracing;
+
+// State shared among Lambda invocations.
+struct SharedState {
+    http: reqwest::Client,
+    metadata: MetadataDirectory,
+    root_certificate: Vec<u8>,
+    mds_url: String,
+}
+
+// Configuration validated at cold start.
+struct Config {
+    mds_url: String,
+}
+
+impl Config {
+    fn from_env() -> Result<Self, Error> {
+        let mut check = ConfigCheck::new();
+        // the table and the certificate are loaded with the clients
+        check.required("METADATA_TABLE_NAME");
+        check.required("MDS_ROOT_CERTIFICATE_PARAMETER_PATH");
+        let config = Self {
+            mds_url: config::var("MDS_URL").unwrap_or_else(|_| DEFAULT_MDS_URL.into()),
+        };
+        Ok(check.finish(config)?)
+    }
+}
+
+impl SharedState {
+    #[instrument(name = "cold_start", skip_all)]
+    async fn new(sdk_config: &SdkConfig, config: Config) -> Result<Self, Error> {
+        Ok(Self {
+            http: reqwest::Client::new(),
+            metadata: load_metadata_directory(aws_sdk_dynamodb::Client::new(sdk_config))?
+                .ok_or("METADATA_TABLE_NAME env must be set")?,
+            root_certificate: load_mds_root_certificate(
+                aws_sdk_ssm::Client::new(sdk_config),
+            ).await?,
+            mds_url: config.mds_url,
+        })
+    }
+}
+
+#[instrument(skip_all)]
+async fn function_handler(
+    shared_state: Arc<SharedState>,
+    metrics: &Metrics,
+    _event: LambdaEvent<Value>,
+) -> Result<Value, Error> {
+    info!("downloading MDS BLOB: {}", shared_state.mds_url);
+    let blob = shared_state.http
+        .get(&shared_state.mds_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let now = SystemTime::now();
+    let blob = verify_blob(&blob, &shared_state.root_certificate, now)?;
+    let updated_at = DateTime::from(now).fmt(DateTimeFormat::DateTime)?;
+    if updated_at.get(..10).is_some_and(|today| today > blob.next_update.as_str()) {
+        warn!("MDS BLOB is overdue: next update was {}", blob.next_update);
+    }
+    if shared_state.metadata.blob_number().await?.is_some_and(|no| no >= blob.no) {
+        info!("MDS BLOB already stored: {}", blob.no);
+        return Ok(json!({ "blobNumber": blob.no, "stored": 0 }));
+    }
+    let stored = shared_state.metadata.replace_entries(&blob, updated_at).await?;
+    info!("stored {} entries of MDS BLOB {}", stored, blob.no);
+    metrics.put("mds_entries_stored", stored as f64, Unit::Count);
+    Ok(json!({ "blobNumber": blob.no, "stored": stored }))
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let started_at = Instant::now();
+    let telemetry = init_tracing("mds-refresh")?;
+
+    let sdk_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+    load_config_parameters(&aws_sdk_ssm::Client::new(&sdk_config)).await?;
+    let config = Config::from_env()?;
+    let shared_state = Arc::new(SharedState::new(&sdk_config, config).await?);
+    let metrics = load_metrics("mds-refresh")?;
+    let cold_start = ColdStart::initialized_since(started_at);
+    run(service_fn(|event| async {
+        let handler_started_at = Instant::now();
+        let res = function_handler(shared_state.clone(), &metrics, event).await;
+        cold_start.report(&metrics, handler_started_at.elapsed());
+        telemetry.flush().await;
+        res
+    })).await
+}
@@ -0,0 +1,12 @@
+//! Prints the OpenAPI specification of the registration API.
+//!
+//! See [`authentication::openapi`] for details.
+
+use utoipa::OpenApi as _;
+
+use authentication::openapi::ApiDoc;
+
+fn main() -> Result<(), serde_json::Error> {
+    println!("{}", ApiDoc::openapi().to_pretty_json()?);
+    Ok(())
+}
@@ -0,0 +1,517 @@
+//! Command line tool for operational tasks.
+//!
+//! Talks directly to the DynamoDB tables and the Cognito user pool, so it
+//! works even if the admin API is not deployed. Uses the AWS credentials of
+//! the local environment.
+//!
+//! This binary is available only if the `admin-cli` feature is enabled.
+//!
+//! ```sh
+//! cargo run --features admin-cli --bin passkey-admin -- <COMMAND>
+//! ```
+//!
+//! Arguments for the resources can also be specified with the following
+//! environment variables:
+//! - `USER_POOL_ID`: ID of the Cognito user pool
+//! - `CREDENTIAL_TABLE_NAME`: name of the DynamoDB table that manages
+//!   credentials
+//! - `SESSION_TABLE_NAME`: name of the DynamoDB table that manages sessions
+//!
+//! Revoked credentials are recorded in the audit log if the
+//! `AUDIT_TABLE_NAME` environment variable is set.
+//!
+//! Users and credentials are sealed and opened if the `PII_KMS_KEY_ARN` and
+//! `PII_INDEX_SECRET_ID` environment variables are set; see
+//! [`authentication::pii`]. `export` from a plaintext table and `import`
+//! with them set seal existing users.
+//!
+//! Every command prints results as JSON, one object per line.
+//!
+//! `export` writes the users and credentials in the credential table as JSON
+//! Lines of [`ExportRecord`], and `import` reads them back into another
+//! credential table. `import` validates every record and rejects duplicates
+//! in the input before writing anything. Records that already exist in the
+//! table are skipped, and so are users whose usernames belong to other users.
+//! See [`authentication::migration`] for details.
+
+use aws_config::SdkConfig;
+use aws_sdk_dynamodb::{
+    primitives::{DateTime, DateTimeFormat},
+    types::AttributeValue,
+};
+use clap::{Args, Parser, Subcommand};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use authentication::audit::{
+    AuditEvent,
+    AuditEventType,
+    AuditLog,
+    ClientInfo,
+    load_audit_log,
+};
+use authentication::credentials::CredentialInfo;
+use authentication::items::{CredentialKey, Item, ttl_of};
+use authentication::migration::ExportRecord;
+use authentication::pii::load_pii_protection;
+use authentication::users::UserDirectory;
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+/// Operational tasks of the passkey service.
+#[derive(Parser)]
+#[command(name = "passkey-admin")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Lists users in the Cognito user pool.
+    ListUsers {
+        #[command(flatten)]
+        user_pool: UserPoolArgs,
+    },
+    /// Lists credentials of a user.
+    ListCredentials {
+        #[command(flatten)]
+        credential_table: CredentialTableArgs,
+        /// User handle of the user.
+        user_handle: String,
+    },
+    /// Lists items in the session table.
+    ListSessions {
+        #[command(flatten)]
+        session_table: SessionTableArgs,
+        /// Lists expired items only.
+        #[arg(long)]
+        expired: bool,
+    },
+    /// Deletes expired items in the session table.
+    ///
+    /// DynamoDB may take days to delete items after they expire.
+    PurgeExpired {
+        #[command(flatten)]
+        session_table: SessionTableArgs,
+        /// Prints the items to delete without deleting them.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Revokes a credential or all the credentials of a user.
+    Revoke {
+        #[command(flatten)]
+        credential_table: CredentialTableArgs,
+        /// User handle of the user.
+        user_handle: String,
+        /// Credential ID to revoke; all the credentials if omitted.
+        #[arg(long)]
+        credential_id: Option<String>,
+        /// Disables credentials instead of deleting them.
+        #[arg(long)]
+        disable: bool,
+        /// Invalidates the refresh tokens of the user; requires the user pool.
+        #[arg(long, requires = "user_pool_id")]
+        sign_out: bool,
+        /// ID of the Cognito user pool.
+        #[arg(long, env = "USER_POOL_ID")]
+        user_pool_id: Option<String>,
+    },
+    /// Exports users and credentials as JSON Lines.
+    Export {
+        #[command(flatten)]
+        credential_table: CredentialTableArgs,
+        /// File to write; the standard output if omitted.
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+    /// Imports users and credentials exported by `export`.
+    Import {
+        #[command(flatten)]
+        credential_table: CredentialTableArgs,
+        /// File to read; the standard input if omitted.
+        input: Option<PathBuf>,
+        /// Validates the records without writing them.
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Args)]
+struct UserPoolArgs {
+    /// ID of the Cognito user pool.
+    #[arg(long, env = "USER_POOL_ID")]
+    user_pool_id: String,
+}
+
+#[derive(Args)]
+struct CredentialTableArgs {
+    /// Name of the DynamoDB table that manages credentials.
+    #[arg(long, env = "CREDENTIAL_TABLE_NAME")]
+    credential_table_name: String,
+}
+
+#[derive(Args)]
+struct SessionTableArgs {
+    /// Name of the DynamoDB table that manages sessions.
+    #[arg(long, env = "SESSION_TABLE_NAME")]
+    session_table_name: String,
+}
+
+// User in the Cognito user pool.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UserSummary {
+    user_handle: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    username: Option<String>,
+    enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<String>,
+}
+
+// Item in the session table.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionSummary {
+    pk: String,
+    ttl: i64,
+    expired: bool,
+}
+
+// Result of importing a record.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Imported {
+    line: usize,
+    pk: String,
+    sk: String,
+    status: &'static str,
+}
+
+// Revoked credential.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Revoked<'a> {
+    user_handle: &'a str,
+    credential_id: &'a str,
+    action: &'static str,
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let cli = Cli::parse();
+    let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+    match cli.command {
+        Command::ListUsers { user_pool } => {
+            let cognito = aws_sdk_cognitoidentityprovider::Client::new(&config);
+            list_users(&cognito, &user_pool.user_pool_id).await
+        }
+        Command::ListCredentials { credential_table, user_handle } => {
+            let users = user_directory(&config, credential_table.credential_table_name)?;
+            for credential in users.list_credentials(&user_handle).await? {
+                print_json(&CredentialInfo::from_credential(credential)?)?;
+            }
+            Ok(())
+        }
+        Command::ListSessions { session_table, expired } => {
+            let dynamodb = aws_sdk_dynamodb::Client::new(&config);
+            for session in scan_sessions(
+                &dynamodb,
+                &session_table.session_table_name,
+                expired,
+            ).await? {
+                print_json(&session)?;
+            }
+            Ok(())
+        }
+        Command::PurgeExpired { session_table, dry_run } => {
+            let dynamodb = aws_sdk_dynamodb::Client::new(&config);
+            purge_expired(&dynamodb, &session_table.session_table_name, dry_run)
+                .await
+        }
+        Command::Revoke {
+            credential_table,
+            user_handle,
+            credential_id,
+            disable,
+            sign_out,
+            user_pool_id,
+        } => {
+            let audit_log = load_audit_log(aws_sdk_dynamodb::Client::new(&config))?;
+            let users = user_directory(&config, credential_table.credential_table_name)?;
+            revoke(&users, audit_log.as_ref(), &user_handle, credential_id, disable)
+                .await?;
+            if sign_out {
+                aws_sdk_cognitoidentityprovider::Client::new(&config)
+                    .admin_user_global_sign_out()
+                    .set_user_pool_id(user_pool_id)
+                    .username(user_handle)
+                    .send()
+                    .await?;
+            }
+            Ok(())
+        }
+        Command::Export { credential_table, output } => {
+            let users = user_directory(&config, credential_table.credential_table_name)?;
+            let output: Box<dyn Write> = match output {
+                Some(path) => Box::new(File::create(path)?),
+                None => Box::new(io::stdout()),
+            };
+            export(&users, BufWriter::new(output)).await
+        }
+        Command::Import { credential_table, input, dry_run } => {
+            let users = user_directory(&config, credential_table.credential_table_name)?;
+            let input: Box<dyn BufRead> = match input {
+                Some(path) => Box::new(BufReader::new(File::open(path)?)),
+                None => Box::new(BufReader::new(io::stdin())),
+            };
+            import(&users, input, dry_run).await
+        }
+    }
+}
+
+// opens the user directory on a given credential table, which protects PII
+// if configured.
+fn user_directory(config: &SdkConfig, table_name: String) -> Result<UserDirectory, Error> {
+    let pii = load_pii_protection(
+        aws_sdk_kms::Client::new(config),
+        aws_sdk_secretsmanager::Client::new(config),
+    )?;
+    Ok(UserDirectory::new(aws_sdk_dynamodb::Client::new(config), table_name)
+        .with_pii_protection(pii))
+}
+
+async fn list_users(
+    cognito: &aws_sdk_cognitoidentityprovider::Client,
+    user_pool_id: &str,
+) -> Result<(), Error> {
+    let mut pagination_token: Option<String> = None;
+    loop {
+        let res = cognito
+            .list_users()
+            .user_pool_id(user_pool_id)
+            .set_pagination_token(pagination_token)
+            .send()
+            .await?;
+        for user in res.users() {
+            print_json(&UserSummary {
+                user_handle: user.username()
+                    .ok_or("missing username in user pool")?
+                    .into(),
+                username: user.attributes()
+                    .iter()
+                    .find(|a| a.name() == "preferred_username")
+                    .and_then(|a| a.value())
+                    .map(Into::into),
+                enabled: user.enabled(),
+                status: user.user_status().map(|s| s.as_str().into()),
+            })?;
+        }
+        pagination_token = res.pagination_token().map(Into::into);
+        if pagination_token.is_none() {
+            return Ok(());
+        }
+    }
+}
+
+// scans the session table.
+//
+// Returns expired items only if `expired_only` is `true`.
+async fn scan_sessions(
+    dynamodb: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    expired_only: bool,
+) -> Result<Vec<SessionSummary>, Error> {
+    let now = DateTime::from(SystemTime::now()).secs();
+    let mut sessions = Vec::new();
+    let mut exclusive_start_key: Option<Item> = None;
+    loop {
+        let mut request = dynamodb
+            .scan()
+            .table_name(table_name)
+            .projection_expression("pk, #ttl")
+            .expression_attribute_names("#ttl", "ttl")
+            .set_exclusive_start_key(exclusive_start_key);
+        if expired_only {
+            request = request
+                .filter_expression("#ttl < :now")
+                .expression_attribute_values(
+                    ":now",
+                    AttributeValue::N(format!("{}", now)),
+                );
+        }
+        let res = request.send().await?;
+        for item in res.items() {
+            let pk = item.get("pk")
+                .and_then(|pk| pk.as_s().ok())
+                .ok_or("malformed pk in session table")?;
+            let ttl = ttl_of(item)?;
+            sessions.push(SessionSummary {
+                pk: pk.clone(),
+                ttl,
+                expired: ttl < now,
+            });
+        }
+        exclusive_start_key = res.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+            return Ok(sessions);
+        }
+    }
+}
+
+async fn purge_expired(
+    dynamodb: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    dry_run: bool,
+) -> Result<(), Error> {
+    for session in scan_sessions(dynamodb, table_name, true).await? {
+        if !dry_run {
+            dynamodb
+                .delete_item()
+                .table_name(table_name)
+                .key(
+                    "pk",
+                    AttributeValue::S(session.pk.clone()),
+                )
+                .send()
+                .await?;
+        }
+        print_json(&session)?;
+    }
+    Ok(())
+}
+
+async fn revoke(
+    users: &UserDirectory,
+    audit_log: Option<&AuditLog>,
+    user_handle: &str,
+    credential_id: Option<String>,
+    disable: bool,
+) -> Result<(), Error> {
+    let credential_ids = match credential_id {
+        Some(credential_id) => vec![credential_id],
+        None => users.list_credentials(user_handle)
+            .await?
+            .into_iter()
+            .map(|c| c.credential_id)
+            .collect(),
+    };
+    let now = DateTime::from(SystemTime::now())
+        .fmt(DateTimeFormat::DateTime)?;
+    for credential_id in credential_ids {
+        let key = CredentialKey {
+            user_handle,
+            credential_id: &credential_id,
+        };
+        let (found, action, event_type) = if disable {
+            (
+                users.disable_credential(key, now.clone()).await?,
+                "disable",
+                AuditEventType::CredentialDisabled,
+            )
+        } else {
+            (
+                users.delete_credential(key).await?,
+                "delete",
+                AuditEventType::CredentialDeleted,
+            )
+        };
+        if !found {
+            return Err(format!("no such credential: {}", credential_id).into());
+        }
+        if let Some(audit_log) = audit_log {
+            audit_log.record(AuditEvent {
+                event_type,
+                user_handle: user_handle.into(),
+                credential_id: Some(credential_id.clone()),
+                client: ClientInfo::default(),
+                detail: Some("revoked by passkey-admin".into()),
+            }).await?;
+        }
+        print_json(&Revoked {
+            user_handle,
+            credential_id: &credential_id,
+            action,
+        })?;
+    }
+    Ok(())
+}
+
+async fn export(users: &UserDirectory, mut output: impl Write) -> Result<(), Error> {
+    let mut exclusive_start_key: Option<Item> = None;
+    loop {
+        let page = users.scan_records(exclusive_start_key).await?;
+        for record in page.records {
+            writeln!(output, "{}", serde_json::to_string(&record)?)?;
+        }
+        exclusive_start_key = page.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+            output.flush()?;
+            return Ok(());
+        }
+    }
+}
+
+async fn import(
+    users: &UserDirectory,
+    input: impl BufRead,
+    dry_run: bool,
+) -> Result<(), Error> {
+    // validates all the records before writing any of them
+    let mut records = Vec::new();
+    let mut keys = HashSet::new();
+    for (i, line) in input.lines().enumerate() {
+        let line_number = i + 1;
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: ExportRecord = serde_json::from_str(&line)
+            .map_err(|e| format!("line {}: {}", line_number, e))?;
+        record.validate()
+            .map_err(|e| format!("line {}: {}", line_number, e))?;
+        if !keys.insert(record.key()) {
+            return Err(format!("line {}: duplicate record", line_number).into());
+        }
+        records.push((line_number, record));
+    }
+
+    for (line, record) in records {
+        let (pk, sk) = record.key();
+        let status = if dry_run {
+            "valid"
+        } else if is_username_taken(users, &record).await? {
+            "usernameTaken"
+        } else if users.import_record(record).await? {
+            "imported"
+        } else {
+            "skipped"
+        };
+        print_json(&Imported { line, pk, sk, status })?;
+    }
+    Ok(())
+}
+
+// returns whether the username of a user record belongs to another user in
+// the table.
+async fn is_username_taken(
+    users: &UserDirectory,
+    record: &ExportRecord,
+) -> Result<bool, Error> {
+    match record {
+        ExportRecord::User(user) => Ok(users.find_user_handle(&user.username)
+            .await?
+            .is_some_and(|user_handle| user_handle != user.user_handle)),
+        ExportRecord::Credential(_) => Ok(false),
+    }
+}
+
+fn print_json(value: &impl Serialize) -> Result<(), Error> {
+    println!("{}", serde_json::to_string(value)?);
+    Ok(())
+}
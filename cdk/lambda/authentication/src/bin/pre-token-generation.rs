@@ -0,0 +1,148 @@
+//! Cognito pre token generation trigger that adds the claims of passkeys.
+//!
+//! Adds the claims of the passkey that the user has just authenticated with
+//! to the ID token; e.g., `amr: ["webauthn"]`, the AAGUID of the
+//! authenticator, and whether the user was verified. See
+//! [`authentication::passkey_claims`] for the claims. The verify auth
+//! challenge trigger has to record authentications with `PASSKEY_CLAIMS`.
+//!
+//! You have to configure the following environment variables:
+//! - `SESSION_TABLE_NAME`: name of the DynamoDB table that manages sessions.
+//!   Must be the same as the `user-pool-triggers` function.
+//!
+//! You can optionally configure the following environment variables:
+//! - `CONFIG_PARAMETER_PATH`: path to the parameters in Parameter Store on
+//!   AWS Systems Manager that override the other environment variables. See
+//!   [`authentication::config`] for details.
+//! - `AMR_CLAIM`: name of the claim of authentication methods; "amr" by
+//!   default. Cognito may not let a trigger override the standard claim.
+//! - `LOG_LEVEL`, `LOG_REDACTION`: log level or `RUST_LOG`-style directives,
+//!   and whether identifiers are redacted in logs; "info" and redacted by
+//!   default. See [`authentication::telemetry`] for details.
+//! - `METRICS_NAMESPACE`: namespace of the CloudWatch metrics; "PasskeyTest"
+//!   by default. Tokens with the claims are counted as
+//!   `passkey_claims_added`.
+//!
+//! Only the `TokenGeneration_Authentication` event adds the claims; the
+//! other events, including token refreshes, are returned as they are.
+//!
+//! The function fails at cold start if any required variable is missing or
+//! any variable is invalid, and the error lists all of them; see
+//! [`authentication::config`].
+
+use aws_config::SdkConfig;
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+use tracing::{info, instrument};
+
+use authentication::config::{self, ConfigCheck, load_config_parameters};
+use authentication::metrics::{ColdStart, Metrics, load_metrics};
+use authentication::passkey_claims::{
+    DEFAULT_AMR_CLAIM,
+    PasskeyAuthentications,
+    add_id_token_claims,
+    passkey_claims,
+};
+use authentication::telemetry::{init_tracing, redact, truncate};
+
+// Trigger source of sign-ins.
+const AUTHENTICATION_TRIGGER_SOURCE: &str = "TokenGeneration_Authentication";
+
+// State shared among Lambda invocations.
+struct SharedState {
+    authentications: PasskeyAuthentications,
+    amr_claim: String,
+}
+
+// Configuration validated at cold start.
+struct Config {
+    session_table_name: String,
+    amr_claim: String,
+}
+
+impl Config {
+    fn from_env() -> Result<Self, Error> {
+        let mut check = ConfigCheck::new();
+        let config = Self {
+            session_table_name: check.required("SESSION_TABLE_NAME"),
+            amr_claim: config::var("AMR_CLAIM").ok()
+                .filter(|claim| !claim.is_empty())
+                .unwrap_or_else(|| DEFAULT_AMR_CLAIM.into()),
+        };
+        Ok(check.finish(config)?)
+    }
+}
+
+impl SharedState {
+    #[instrument(name = "cold_start", skip_all)]
+    fn new(sdk_config: &SdkConfig, config: Config) -> Self {
+        Self {
+            authentications: PasskeyAuthentications::new(
+                aws_sdk_dynamodb::Client::new(sdk_config),
+                config.session_table_name,
+            ),
+            amr_claim: config.amr_claim,
+        }
+    }
+}
+
+#[instrument(skip_all)]
+async fn function_handler(
+    shared_state: Arc<SharedState>,
+    metrics: &Metrics,
+    event: LambdaEvent<Value>,
+) -> Result<Value, Error> {
+    let mut event = event.payload;
+    let trigger_source = event.get("triggerSource").and_then(Value::as_str);
+    if trigger_source != Some(AUTHENTICATION_TRIGGER_SOURCE) {
+        info!("no passkey claims for {:?}", trigger_source);
+        return Ok(event);
+    }
+    let user_handle = event.get("userName")
+        .and_then(Value::as_str)
+        .ok_or("missing username in request")?
+        .to_string();
+    let now = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_secs() as i64;
+    let Some(authentication) = shared_state.authentications
+        .take(&user_handle, now)
+        .await? else
+    {
+        info!("no recent passkey authentication: {}", redact(&user_handle));
+        return Ok(event);
+    };
+    info!(
+        "adding passkey claims: {} {}",
+        redact(&user_handle),
+        truncate(&authentication.credential_id),
+    );
+    add_id_token_claims(
+        &mut event,
+        passkey_claims(&authentication, &shared_state.amr_claim),
+    )?;
+    metrics.count("passkey_claims_added");
+    Ok(event)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let started_at = Instant::now();
+    let telemetry = init_tracing("pre-token-generation")?;
+
+    let sdk_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+    load_config_parameters(&aws_sdk_ssm::Client::new(&sdk_config)).await?;
+    let config = Config::from_env()?;
+    let shared_state = Arc::new(SharedState::new(&sdk_config, config));
+    let metrics = load_metrics("pre-token-generation")?;
+    let cold_start = ColdStart::initialized_since(started_at);
+    run(service_fn(|event| async {
+        let handler_started_at = Instant::now();
+        let res = function_handler(shared_state.clone(), &metrics, event).await;
+        cold_start.report(&metrics, handler_started_at.elapsed());
+        telemetry.flush().await;
+        res
+    })).await
+}
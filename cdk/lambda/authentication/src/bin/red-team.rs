@@ -0,0 +1,423 @@
+//! Red-team simulation.
+//!
+//! Emits labeled synthetic attack traffic against a non-production stage and
+//! verifies that the relying party rejects every attack.
+//! After each scenario, queries the audit log for the events of the user the
+//! scenario registered, and verifies that they are labeled with the scenario
+//! and that the rejection is audited as expected.
+//! Attack requests carry the `x-red-team-scenario` header, or the
+//! `redTeamScenario` client metadata for Cognito, and register users whose
+//! usernames start with `red-team-`.
+//!
+//! This binary is available only if the `red-team` feature is enabled.
+//!
+//! ```sh
+//! cargo run --features red-team --bin red-team [SCENARIO...]
+//! ```
+//!
+//! Runs all the scenarios if no `SCENARIO` is specified.
+//! Exits with a failure status if any attack is accepted or not audited as
+//! expected.
+//!
+//! You have to configure the following environment variables:
+//! - `RED_TEAM_STAGE`: name of the target stage; production-like names are
+//!   refused
+//! - `CREDENTIALS_API_URL`: URL of the Credentials API; e.g.,
+//!   `https://xxxxxxxxxx.execute-api.ap-northeast-1.amazonaws.com/auth/credentials/`
+//! - `USER_POOL_CLIENT_ID`: ID of the Cognito user pool client
+//! - `RP_ORIGIN_PARAMETER_PATH`: path to the parameter that stores the origin
+//!   (URL) of the relying party in Parameter Store on AWS Systems Manager
+//! - `AUDIT_TABLE_NAME`: name of the DynamoDB table for the audit log
+
+use aws_sdk_cognitoidentityprovider::{
+    error::DisplayErrorContext,
+    types::{AuthFlowType, ChallengeNameType},
+};
+use base64::{
+    Engine as _,
+    engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::env;
+use std::process::ExitCode;
+use std::time::Duration;
+use tracing::{error, info};
+use webauthn_rs::prelude::{Url, Uuid};
+
+use authentication::audit::{AuditLog, AuditQuery, load_audit_log};
+use authentication::authenticator::{SoftwareCredential, client_data_json};
+use authentication::parameters::load_relying_party_origin;
+use authentication::red_team::{
+    Outcome,
+    SCENARIO_CLIENT_METADATA_KEY,
+    SCENARIO_HEADER,
+    Scenario,
+    ScenarioReport,
+    USERNAME_PREFIX,
+    is_production_stage,
+};
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+// Origin no relying party should accept.
+const ATTACKER_ORIGIN: &str = "https://attacker.invalid";
+
+// Number of times to query audit events until they are visible.
+const AUDIT_QUERY_ATTEMPTS: usize = 5;
+
+// Interval between queries of audit events.
+const AUDIT_QUERY_INTERVAL: Duration = Duration::from_secs(1);
+
+// Maximum number of audit events of a user a scenario may leave.
+const AUDIT_QUERY_LIMIT: i32 = 100;
+
+// Stage under attack.
+struct Target {
+    http: reqwest::Client,
+    cognito: aws_sdk_cognitoidentityprovider::Client,
+    audit_log: AuditLog,
+    credentials_api_url: String,
+    rp_id: String,
+    rp_origin: String,
+    user_pool_client_id: String,
+}
+
+// Subset of `StartRegistrationSession`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StartRegistrationSession {
+    session_id: String,
+    credential_creation_options: ChallengeOptions,
+}
+
+// Subset of credential creation and request options.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChallengeOptions {
+    public_key: PublicKeyOptions,
+}
+
+#[derive(Deserialize)]
+struct PublicKeyOptions {
+    challenge: String,
+    user: Option<UserEntity>,
+}
+
+#[derive(Deserialize)]
+struct UserEntity {
+    id: String,
+}
+
+// Registered user.
+struct RegisteredUser {
+    user_handle: String,
+    credential: SoftwareCredential,
+    registration: serde_json::Value,
+}
+
+impl Target {
+    async fn new() -> Result<Self, Error> {
+        let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+        let (rp_id, rp_origin) =
+            load_relying_party_origin(aws_sdk_ssm::Client::new(&config)).await?;
+        let credentials_api_url = env::var("CREDENTIALS_API_URL")
+            .or(Err("CREDENTIALS_API_URL env must be set"))?;
+        Url::parse(&credentials_api_url)
+            .or(Err("CREDENTIALS_API_URL must be a URL"))?;
+        Ok(Self {
+            http: reqwest::Client::new(),
+            cognito: aws_sdk_cognitoidentityprovider::Client::new(&config),
+            audit_log: load_audit_log(aws_sdk_dynamodb::Client::new(&config))?
+                .ok_or("AUDIT_TABLE_NAME env must be set")?,
+            credentials_api_url: credentials_api_url.trim_end_matches('/').into(),
+            rp_id,
+            rp_origin: rp_origin.origin().ascii_serialization(),
+            user_pool_client_id: env::var("USER_POOL_CLIENT_ID")
+                .or(Err("USER_POOL_CLIENT_ID env must be set"))?,
+        })
+    }
+
+    async fn post(
+        &self,
+        scenario: Scenario,
+        path: &str,
+        body: Option<&serde_json::Value>,
+    ) -> Result<reqwest::Response, Error> {
+        let mut req = self.http
+            .post(format!("{}/{}", self.credentials_api_url, path))
+            .header(SCENARIO_HEADER, scenario.name());
+        if let Some(body) = body {
+            req = req.json(body);
+        }
+        Ok(req.send().await?)
+    }
+
+    async fn start_registration(
+        &self,
+        scenario: Scenario,
+    ) -> Result<StartRegistrationSession, Error> {
+        let user_info = json!({
+            "username": format!("{}{}", USERNAME_PREFIX, Uuid::new_v4()),
+            "displayName": "Red Team",
+        });
+        let res = self.post(scenario, "registration/start", Some(&user_info))
+            .await?
+            .error_for_status()?;
+        Ok(res.json().await?)
+    }
+
+    fn new_credential(
+        &self,
+        session: &StartRegistrationSession,
+    ) -> Result<SoftwareCredential, Error> {
+        let user_handle = session.credential_creation_options.public_key.user
+            .as_ref()
+            .ok_or("missing user in credential creation options")?;
+        let user_handle = base64url.decode(&user_handle.id)?;
+        Ok(SoftwareCredential::generate(&self.rp_id, user_handle)?)
+    }
+
+    async fn finish_registration(
+        &self,
+        scenario: Scenario,
+        registration: &serde_json::Value,
+    ) -> Result<Outcome, Error> {
+        let res = self.post(scenario, "registration/finish", Some(registration))
+            .await?;
+        outcome_of(res).await
+    }
+
+    // registers a legitimate user.
+    async fn register(&self, scenario: Scenario) -> Result<RegisteredUser, Error> {
+        let session = self.start_registration(scenario).await?;
+        let credential = self.new_credential(&session)?;
+        let client_data = client_data_json(
+            "webauthn.create",
+            &session.credential_creation_options.public_key.challenge,
+            &self.rp_origin,
+        );
+        let registration =
+            registration_body(&session.session_id, &credential, &client_data);
+        expect_accepted(
+            self.finish_registration(scenario, &registration).await?,
+            "legitimate registration",
+        )?;
+        Ok(RegisteredUser {
+            user_handle: base64url.encode(&credential.user_handle),
+            credential,
+            registration,
+        })
+    }
+
+    // makes a discoverable credential assertion.
+    async fn assert(
+        &self,
+        scenario: Scenario,
+        credential: &mut SoftwareCredential,
+    ) -> Result<serde_json::Value, Error> {
+        let res = self.post(scenario, "discoverable/start", None)
+            .await?
+            .error_for_status()?;
+        let options: ChallengeOptions = res.json().await?;
+        let client_data = client_data_json(
+            "webauthn.get",
+            &options.public_key.challenge,
+            &self.rp_origin,
+        );
+        Ok(credential.assertion_credential(&client_data)?)
+    }
+
+    // answers the Cognito custom challenge with a given assertion.
+    async fn authenticate(
+        &self,
+        scenario: Scenario,
+        user_handle: &str,
+        assertion: &serde_json::Value,
+    ) -> Result<Outcome, Error> {
+        let challenge = self.cognito
+            .initiate_auth()
+            .client_id(self.user_pool_client_id.clone())
+            .auth_flow(AuthFlowType::CustomAuth)
+            .auth_parameters("USERNAME", user_handle)
+            .client_metadata(SCENARIO_CLIENT_METADATA_KEY, scenario.name())
+            .send()
+            .await?;
+        let res = self.cognito
+            .respond_to_auth_challenge()
+            .client_id(self.user_pool_client_id.clone())
+            .challenge_name(ChallengeNameType::CustomChallenge)
+            .set_session(challenge.session)
+            .challenge_responses("USERNAME", user_handle)
+            .challenge_responses("ANSWER", serde_json::to_string(assertion)?)
+            .client_metadata(SCENARIO_CLIENT_METADATA_KEY, scenario.name())
+            .send()
+            .await;
+        Ok(match res {
+            Ok(res) if res.authentication_result.is_some() => Outcome::Accepted,
+            Ok(res) => Outcome::Rejected(
+                format!("further challenge: {:?}", res.challenge_name),
+            ),
+            Err(e) => Outcome::Rejected(format!("{}", DisplayErrorContext(&e))),
+        })
+    }
+}
+
+fn registration_body(
+    session_id: &str,
+    credential: &SoftwareCredential,
+    client_data: &[u8],
+) -> serde_json::Value {
+    json!({
+        "sessionId": session_id,
+        "publicKeyCredential": credential.registration_credential(client_data),
+    })
+}
+
+async fn outcome_of(res: reqwest::Response) -> Result<Outcome, Error> {
+    let status = res.status();
+    if status.is_success() {
+        Ok(Outcome::Accepted)
+    } else {
+        Ok(Outcome::Rejected(format!("{}: {}", status, res.text().await?)))
+    }
+}
+
+fn expect_accepted(outcome: Outcome, what: &str) -> Result<(), Error> {
+    match outcome {
+        Outcome::Accepted => Ok(()),
+        Outcome::Rejected(reason) =>
+            Err(format!("{} was rejected: {}", what, reason).into()),
+    }
+}
+
+// runs the attack in a given scenario and returns the user handle of the
+// attacked user and the outcome of the attack request.
+async fn attack(target: &Target, scenario: Scenario) -> Result<(String, Outcome), Error> {
+    match scenario {
+        Scenario::WrongOrigin => {
+            let session = target.start_registration(scenario).await?;
+            let credential = target.new_credential(&session)?;
+            let client_data = client_data_json(
+                "webauthn.create",
+                &session.credential_creation_options.public_key.challenge,
+                ATTACKER_ORIGIN,
+            );
+            let outcome = target.finish_registration(
+                scenario,
+                &registration_body(&session.session_id, &credential, &client_data),
+            ).await?;
+            Ok((base64url.encode(&credential.user_handle), outcome))
+        }
+        Scenario::TamperedClientData => {
+            let session = target.start_registration(scenario).await?;
+            let credential = target.new_credential(&session)?;
+            let mut challenge = [0u8; 32];
+            getrandom::getrandom(&mut challenge)?;
+            let client_data = client_data_json(
+                "webauthn.create",
+                &base64url.encode(challenge),
+                &target.rp_origin,
+            );
+            let outcome = target.finish_registration(
+                scenario,
+                &registration_body(&session.session_id, &credential, &client_data),
+            ).await?;
+            Ok((base64url.encode(&credential.user_handle), outcome))
+        }
+        Scenario::ReplayedRegistration => {
+            let user = target.register(scenario).await?;
+            let outcome = target.finish_registration(scenario, &user.registration).await?;
+            Ok((user.user_handle, outcome))
+        }
+        Scenario::ReplayedAssertion => {
+            let mut user = target.register(scenario).await?;
+            let assertion = target.assert(scenario, &mut user.credential).await?;
+            expect_accepted(
+                target.authenticate(scenario, &user.user_handle, &assertion).await?,
+                "legitimate assertion",
+            )?;
+            let outcome = target.authenticate(scenario, &user.user_handle, &assertion).await?;
+            Ok((user.user_handle, outcome))
+        }
+        Scenario::CounterRegression => {
+            let mut user = target.register(scenario).await?;
+            user.credential.counter = 9;
+            let assertion = target.assert(scenario, &mut user.credential).await?;
+            expect_accepted(
+                target.authenticate(scenario, &user.user_handle, &assertion).await?,
+                "legitimate assertion",
+            )?;
+            user.credential.counter = 4;
+            let assertion = target.assert(scenario, &mut user.credential).await?;
+            let outcome = target.authenticate(scenario, &user.user_handle, &assertion).await?;
+            Ok((user.user_handle, outcome))
+        }
+    }
+}
+
+// checks the audit events of the user attacked in a given scenario.
+//
+// queries again while the events do not meet the expectation, because the
+// query is eventually consistent.
+async fn check_audit_events(
+    target: &Target,
+    scenario: Scenario,
+    user_handle: &str,
+) -> Result<(), String> {
+    let mut result = Ok(());
+    for attempt in 0..AUDIT_QUERY_ATTEMPTS {
+        if attempt > 0 {
+            tokio::time::sleep(AUDIT_QUERY_INTERVAL).await;
+        }
+        let page = target.audit_log
+            .query(AuditQuery::User(user_handle.into()), AUDIT_QUERY_LIMIT, None)
+            .await
+            .map_err(|e| format!("failed to query audit events: {}", e))?;
+        result = scenario.check_audit_events(&page.records);
+        if result.is_ok() {
+            break;
+        }
+    }
+    result
+}
+
+#[tokio::main]
+async fn main() -> Result<ExitCode, Error> {
+    tracing_subscriber::fmt()
+        .with_max_level(tracing::Level::INFO)
+        .with_target(false)
+        .init();
+
+    let stage = env::var("RED_TEAM_STAGE")
+        .or(Err("RED_TEAM_STAGE env must be set"))?;
+    if is_production_stage(&stage) {
+        return Err(format!("refusing to attack stage \"{}\"", stage).into());
+    }
+    let mut scenarios = env::args()
+        .skip(1)
+        .map(|name| Scenario::from_name(&name)
+            .ok_or(format!("unknown scenario: {}", name)))
+        .collect::<Result<Vec<_>, _>>()?;
+    if scenarios.is_empty() {
+        scenarios = Scenario::ALL.to_vec();
+    }
+
+    let target = Target::new().await?;
+    info!("attacking stage: {}", stage);
+    let mut failed = false;
+    for scenario in scenarios {
+        let report = match attack(&target, scenario).await {
+            Ok((user_handle, outcome)) => ScenarioReport::evaluate(scenario, outcome)
+                .audited(check_audit_events(&target, scenario, &user_handle).await),
+            Err(e) => ScenarioReport::aborted(scenario, e),
+        };
+        if report.passed {
+            info!("PASS {}: {}", report.scenario, report.detail);
+        } else {
+            error!("FAIL {}: {}", report.scenario, report.detail);
+            failed = true;
+        }
+    }
+    Ok(if failed { ExitCode::FAILURE } else { ExitCode::SUCCESS })
+}
@@ -8,14 +8,215 @@
 //! - `RP_ORIGIN_PARAMETER_PATH`: path to the parameter that stores the origin
 //!   (URL) of the relying party in the Parameter Store on AWS Systems Manager
 //!
+//! You can optionally configure the following environment variables:
+//! - `CONFIG_PARAMETER_PATH`: path to the parameters in Parameter Store on
+//!   AWS Systems Manager that override the other environment variables. See
+//!   [`authentication::config`] for details.
+//! - `DATABASE_URL`: URL of the PostgreSQL database that stores users,
+//!   credentials, and sessions in place of the DynamoDB tables; needs the
+//!   `sql` feature. See [`authentication::store::load_database`]. The other
+//!   items in the session table, like rate limits, stay on DynamoDB.
+//! - `RP_ORIGIN`: origin (URL) of the relying party that takes precedence over
+//!   `RP_ORIGIN_PARAMETER_PATH`
+//! - `RP_ID`: ID of the relying party; the domain of the origin by default
+//! - `RP_ALLOWED_ORIGINS`: comma-separated origins (URLs) allowed in addition
+//!   to the origin of the relying party; e.g., `https://www.example.com`
+//! - `ANDROID_APK_KEY_HASHES`: comma-separated SHA-256 hashes of the
+//!   certificates that sign Android apps allowed to use the relying party.
+//!   See [`authentication::android`] for details.
+//! - `CHALLENGE_TIMEOUT`: timeout of a registration in seconds; 60 by
+//!   default. Given to the client as the `timeout` of the creation options,
+//!   and registration sessions expire after it.
+//! - `AUTHENTICATOR_ATTACHMENT`: authenticator attachment requested in the
+//!   creation options; "platform" or "cross-platform". Requests for the other
+//!   attachment are rejected if specified. The policy is advisory; the
+//!   attachment a client reports is unsigned, so a registration with another
+//!   attachment is only logged. See [`load_authenticator_attachment_policy`].
+//! - `RESIDENT_KEY`: resident key requirement; "required" (default),
+//!   "preferred", or "discouraged". Registration fails if "required" and the
+//!   `credProps` extension reports a non-resident key.
+//! - `ATTESTATION`: attestation conveyance preference of passkeys; "none"
+//!   (default), "indirect", or "direct". Registration fails without an
+//!   attestation statement if "direct". Security keys always request direct
+//!   attestation. The format and certificate chain of the attestation
+//!   statement are stored with the credential unless "none", and shown to
+//!   administrators for compliance review.
+//! - `ALLOWED_ALGORITHMS`: comma-separated COSE algorithms of public keys;
+//!   e.g., "ES256,EdDSA". Other algorithms are removed from the
+//!   `pubKeyCredParams` of the creation options, and registrations of
+//!   credentials with them fail with 403 and `not_allowed`. Every algorithm
+//!   of the Webauthn library is allowed unless specified. See
+//!   [`load_algorithm_allowlist`] for details.
+//! - `HINTS`: comma-separated "security-key", "client-device", and "hybrid"
+//!   given as the `hints` of the creation options in the order of
+//!   preference. Hints in a request body take precedence. No hints unless
+//!   specified, except for "security-key" at `security-key/start`. See
+//!   [`authentication::hints`] for details.
+//! - `ATTESTATION_CA_LIST_PARAMETER_PATH`: path to the parameter that stores
+//!   the attestation CA list in Parameter Store on AWS Systems Manager.
+//!   Security key registration is disabled unless the parameter exists.
+//! - `METADATA_TABLE_NAME`: name of the DynamoDB table of authenticators in
+//!   the FIDO Metadata Service. Credentials are recorded with the names of
+//!   their authenticators, and authenticators listed with compromised
+//!   statuses are rejected with 403 if specified; see
+//!   [`authentication::mds`].
+//! - `MAX_BODY_SIZE`: maximum size of a request body in bytes; 32 KiB by
+//!   default. Larger requests are rejected with 413.
+//! - `ENUMERATION_PROTECTION`, `ENUMERATION_MIN_DURATION`,
+//!   `ENUMERATION_SALT_SECRET_ID`: protection mode against user enumeration.
+//!   Registration for an existing username starts like one for a new
+//!   username, and the `start`, `security-key/start`, `recovery/start`, and
+//!   `recovery/email` endpoints respond no earlier than the minimum duration.
+//!   Disabled unless specified. `ENUMERATION_SALT_SECRET_ID` is required in
+//!   the protection mode. See [`authentication::enumeration`] for details.
+//! - `CLIENT_BINDING`: comma-separated "ip" and "user-agent". Registration
+//!   sessions are bound to the source IP and user agent of the client that
+//!   starts them, and `finish` endpoints fail with 401 and `session_expired`
+//!   if they are called by another client. Not bound unless specified. See
+//!   [`authentication::client_binding`] for details.
+//! - `USERNAME_MIN_LENGTH`, `USERNAME_MAX_LENGTH`, `USERNAME_CHARSET`,
+//!   `USERNAME_LOWERCASE`, `USERNAME_EMAIL`: username validation policy. See
+//!   [`load_username_policy`] for details.
+//! - `MAX_CREDENTIALS_PER_USER`: maximum number of credentials per user.
+//!   Registration of another credential for a user who has as many
+//!   credentials is rejected at `start`, `security-key/start`, and
+//!   `passkeys/start` with 409 and `credential_limit_exceeded`. Recovery is
+//!   not limited so that a user who lost an authenticator can sign in again
+//!   and delete it. Deleted credentials do not count. Unlimited unless
+//!   specified.
+//! - `DISPLAY_NAME_MAX_LENGTH`: maximum length of a display name in
+//!   characters; 64 by default. Longer display names are truncated.
+//! - `RATE_LIMIT_PER_IP`: rate limit of registration starts per source IP;
+//!   "<limit>/<window seconds>" or "off". "30/60" by default.
+//! - `RATE_LIMIT_PER_USERNAME`: rate limit of registration starts per
+//!   username; "<limit>/<window seconds>" or "off". "10/60" by default.
+//! - `CAPTCHA_PROVIDER`, `CAPTCHA_SECRET_ID`, `CAPTCHA_HEADER`: CAPTCHA token
+//!   required to start registration; Turnstile or AWS WAF. Not required
+//!   unless specified. See [`authentication::captcha`] for details.
+//! - `SESSION_KMS_KEY_ARN`: ARN of the KMS key for envelope encryption of the
+//!   registration state and user information in sessions. Sessions are
+//!   stored in plaintext unless specified, or `PII_KMS_KEY_ARN` is specified.
+//! - `PII_KMS_KEY_ARN`, `PII_INDEX_SECRET_ID`: ARN of the KMS key and ID of
+//!   the secret in Secrets Manager that protect the usernames and display
+//!   names of users. Stored in plaintext unless specified. See
+//!   [`authentication::pii`].
+//! - `SESSION_ID_SECRET_ID`: ID of the secret in Secrets Manager that signs
+//!   session IDs. Finishes with a forged or truncated session ID end with 401
+//!   without looking up the session table. Session IDs are not signed unless
+//!   specified. See [`authentication::session_id`].
+//! - `BEARER_AUTH`: "true" to verify bearer tokens at `passkeys/*` and
+//!   `upgrade/*` in the function instead of a JWT authorizer of API Gateway.
+//!   Access tokens of the app clients in `USER_POOL_CLIENT_IDS` of the user
+//!   pool in `USER_POOL_ID` are trusted; see [`load_bearer_auth`] for
+//!   details.
+//! - `LOG_LEVEL`, `LOG_REDACTION`: log level or `RUST_LOG`-style directives,
+//!   and whether identifiers are redacted in logs; "info" and redacted by
+//!   default. See [`authentication::telemetry`] for details.
+//! - `METRICS_NAMESPACE`: namespace of the CloudWatch metrics; "PasskeyTest"
+//!   by default.
+//! - `AUDIT_TABLE_NAME`: name of the DynamoDB table for the audit log.
+//!   Registered credentials are recorded with the source IP and user agent
+//!   if specified.
+//! - `EVENT_BUS_NAME`: name of the EventBridge event bus. Registered
+//!   credentials are published as `PasskeyRegistered` if specified; see
+//!   [`authentication::domain_events`].
+//! - `WEBHOOK_URL`, `WEBHOOK_SECRET_ID`: URL to which registered credentials
+//!   are posted and the ID of the secret that signs the requests. Disabled
+//!   unless specified; see [`authentication::webhooks`].
+//! - `RECOVERY_EMAIL_SENDER`, `RECOVERY_LINK_URL`, `RECOVERY_LINK_TTL`:
+//!   sender and destination of recovery links emailed through SES. Email
+//!   recovery is disabled unless specified. See [`load_recovery_mailer`] for
+//!   details.
+//! - `LARGE_BLOB`: support of the `largeBlob` extension requested at
+//!   registration; "required" or "preferred". Disabled unless specified. See
+//!   [`load_extension_policy`] for details.
+//! - `PRF`, `PRF_SALT`: whether the `prf` extension is evaluated and its
+//!   salt. Disabled unless specified. See [`load_extension_policy`] for
+//!   details.
+//! - `TENANT_TABLE_NAME`, `TENANT_RESOLUTION`: table of tenants and how a
+//!   tenant is resolved from a request. Every request is served by the
+//!   default relying party unless specified. See
+//!   [`authentication::tenant`] for details.
+//!
+//! The function fails at cold start if any required variable is missing or
+//! any variable is invalid, and the error lists all of them; see
+//! [`authentication::config`].
+//!
+//! ## Metrics
+//!
+//! Emits the following metrics in the CloudWatch embedded metric format with
+//! the `Service` dimension of "registration":
+//! - `cold_start`, `init_duration`, `cold_start_handler_duration`: cold start
+//!   of the function; see [`ColdStart`]
+//! - `registration_started`: count of started registrations
+//! - `registration_succeeded`: count of stored credentials
+//! - `verification_failed`: count of registrations failed to be verified
+//! - `session_expired`: count of registrations finished after the session
+//!   expired
+//! - `session_not_found`: count of registrations finished with a missing
+//!   session, which may have been deleted by the TTL
+//! - `client_mismatch`: count of registrations finished by another client
+//!   than the one to which the session is bound
+//! - `algorithm_rejected`: count of registrations rejected for a disallowed
+//!   public key algorithm
+//! - `recovery_code_rejected`: count of recoveries rejected with a wrong
+//!   username or recovery code
+//! - `recovery_link_sent`: count of emailed recovery links
+//! - `recovery_link_rejected`: count of recoveries rejected with an unknown,
+//!   used, or expired recovery link
+//! - `password_disabled`: count of passwords disabled after an upgrade to a
+//!   passkey
+//! - `start_registration_latency`, `finish_registration_latency`,
+//!   `start_security_key_registration_latency`,
+//!   `finish_security_key_registration_latency`, `start_recovery_latency`,
+//!   `finish_recovery_latency`, `request_recovery_link_latency`,
+//!   `start_recovery_link_latency`, `start_additional_registration_latency`,
+//!   `finish_additional_registration_latency`,
+//!   `start_password_upgrade_latency`, `finish_password_upgrade_latency`:
+//!   latency of each endpoint in milliseconds
+//!
 //! ## Endpoints
 //!
 //! Provides the following endpoints under the base path.
+//! Every endpoint is versioned under `${BASE_PATH}v1/`; e.g.,
+//! `${BASE_PATH}v1/start`. Paths without a version are routed to v1
+//! for backward compatibility, and unsupported versions end with 404.
+//! If tenants are configured and resolved by path, every path is prefixed
+//! with the tenant key; e.g., `${BASE_PATH}acme/v1/start`. Requests for an
+//! unknown tenant are rejected with 404 and [`ErrorResponseBody`].
+//! Registration starts exceeding the rate limits or the quota of the tenant
+//! are rejected with 429 and `Retry-After`.
+//! Requests with a malformed body are rejected with 400 and
+//! [`ErrorResponseBody`] as `application/json`.
+//! Request and response bodies may be CBOR instead of JSON; see
+//! [`authentication::content`]. A request body is `application/cbor` if so
+//! specified in `Content-Type`, and a response body is `application/cbor` if
+//! `Accept` prefers it.
+//! Large response bodies are compressed with Brotli or gzip if
+//! `Accept-Encoding` allows it.
+//! Error responses carry a `localizedMessage` in English or Japanese as
+//! `Accept-Language` prefers; see [`authentication::i18n`].
+//! `GET ${BASE_PATH}health` serves the health check, which is neither
+//! versioned nor scoped to a tenant; see [`authentication::health`].
+//! A scheduled warm-up event is answered with 200 without serving a request;
+//! see [`authentication::warmer`].
+//! A client error like an expired session or a failed verification ends with
+//! the status code of the [`ApiError`] and an [`ErrorResponseBody`]; see
+//! [`authentication::api_error`].
+//! A path that no endpoint serves ends with 404, and a method other than POST
+//! ends with 405; see [`Router`].
+//! Finish requests end with 409 and [`ErrorResponseBody`] if the user or the
+//! credential already exists, or a concurrent registration conflicted; the
+//! last case may be retried.
 //!
 //! ### `POST ${BASE_PATH}start`
 //!
 //! Starts registration of a new user.
 //! The request body must be [`NewUserInfo`] as `application/json`.
+//! The username is normalized and validated according to the username policy,
+//! and control characters are stripped from the display name.
+//! If CAPTCHA is configured, a request without a valid token is rejected with
+//! 403 and `captcha_required` before the rate limits are counted.
 //! The response body is [`StartRegistrationSession`] as `application/json`.
 //!
 //! ### `POST ${BASE_PATH}finish`
@@ -23,16 +224,144 @@
 //! Verifies the new user and finishes registration.
 //! The request body must be [`FinishRegistrationSession`] as
 //! `application/json`.
-//! The response body is an empty text.
+//! The response body is [`FinishRegistrationResult`] as `application/json`,
+//! which includes the recovery codes of the new user and the `credProps`,
+//! `largeBlob`, and `prf` extension outputs reported by the client.
+//! Whether the credential is discoverable and supports the `prf` extension is
+//! stored with the credential.
+//! Retries are idempotent; see [Retries](#retries).
+//!
+//! ### `POST ${BASE_PATH}security-key/start`
+//!
+//! Starts registration of a new user with a security key.
+//! Attestation is enforced and the attestation certificate must chain to a CA
+//! in the attestation CA list.
+//! A CAPTCHA token is required like `start`.
+//! The request body must be [`NewUserInfo`] as `application/json`.
+//! The response body is [`StartRegistrationSession`] as `application/json`.
+//!
+//! ### `POST ${BASE_PATH}security-key/finish`
+//!
+//! Verifies the attestation of the security key and finishes registration.
+//! The request body must be [`FinishRegistrationSession`] as
+//! `application/json`.
+//! The response body is [`FinishRegistrationResult`] as `application/json`,
+//! which includes the recovery codes of the new user.
+//! Retries are idempotent; see [Retries](#retries).
+//!
+//! ### `POST ${BASE_PATH}recovery/start`
+//!
+//! Consumes a recovery code of an existing user and starts registration of a
+//! new passkey for the user.
+//! The request body must be [`RecoveryRequest`] as `application/json`.
+//! A wrong username or recovery code is rejected with 401 and
+//! [`ErrorResponseBody`]. The code is consumed even if the registration is
+//! not finished.
+//! Subject to the same rate limits as registration starts.
+//! The response body is [`StartRegistrationSession`] as `application/json`.
+//!
+//! ### `POST ${BASE_PATH}recovery/finish`
+//!
+//! Verifies the new passkey and adds it to the recovering user.
+//! The request body must be [`FinishRegistrationSession`] as
+//! `application/json`.
+//! The response body is [`FinishRegistrationResult`] as `application/json`
+//! without recovery codes; the remaining codes stay valid.
+//! Also finishes recovery started with a recovery link.
+//! Retries are idempotent; see [Retries](#retries).
+//!
+//! ### `POST ${BASE_PATH}recovery/email`
+//!
+//! Emails a recovery link to an existing user whose username is an email
+//! address.
+//! The request body must be [`RecoveryLinkRequest`] as `application/json`.
+//! Subject to the same rate limits as registration starts.
+//! Always responds with 202 and an empty body so that the response does not
+//! reveal whether the user exists. Fails if email recovery is not
+//! configured.
+//!
+//! ### `POST ${BASE_PATH}recovery/email/start`
+//!
+//! Consumes the token of a recovery link and starts registration of a new
+//! passkey for the user, which is finished with `recovery/finish`.
+//! The request body must be [`RecoveryLinkSession`] as `application/json`.
+//! An unknown, used, or expired token is rejected with 401 and
+//! [`ErrorResponseBody`]. The token is consumed even if the registration is
+//! not finished.
+//! The response body is [`StartRegistrationSession`] as `application/json`.
+//!
+//! ### `POST ${BASE_PATH}passkeys/start`
+//!
+//! Starts registration of an additional passkey for the authenticated user.
+//! Must be protected by a JWT authorizer that verifies tokens issued by the
+//! Cognito user pool, unless `BEARER_AUTH` is enabled; the user handle of the
+//! caller is taken from the verified claims, and the existing credentials of
+//! the caller are excluded. A request without a valid token ends with 401.
+//! The request body must be [`AdditionalPasskeyRequest`] as
+//! `application/json`; e.g., `{}`.
+//! Ends with 409 and `credential_limit_exceeded` if the caller already has
+//! as many credentials as `MAX_CREDENTIALS_PER_USER`.
+//! The response body is [`StartRegistrationSession`] as `application/json`.
+//!
+//! ### `POST ${BASE_PATH}passkeys/finish`
+//!
+//! Verifies the additional passkey and adds it to the authenticated user.
+//! Must be protected by the same JWT authorizer as `passkeys/start`, and a
+//! session started by another user is rejected.
+//! The request body must be [`FinishRegistrationSession`] as
+//! `application/json`.
+//! The response body is [`FinishRegistrationResult`] as `application/json`
+//! without recovery codes; the existing codes stay valid.
+//! Retries are idempotent; see [Retries](#retries).
+//!
+//! ### `POST ${BASE_PATH}upgrade/start`
+//!
+//! Starts registration of the first passkey for the authenticated user who
+//! has signed in with a password, so that an existing user base can migrate
+//! to passkeys gradually.
+//! Must be protected by the same JWT authorizer as `passkeys/start`.
+//! The username of the Cognito user must be a user handle
+//! ("base64url"-encoded UUID) as for users registered with a passkey;
+//! otherwise the request is rejected with 403. A user who already has a
+//! passkey is rejected with 409 and has to use `passkeys/start` instead.
+//! The username and display name are taken from the `preferred_username`
+//! (or `email`) and `name` attributes of the Cognito user.
+//! The request body must be [`AdditionalPasskeyRequest`] as
+//! `application/json`; e.g., `{}`.
+//! The response body is [`StartRegistrationSession`] as `application/json`.
+//!
+//! ### `POST ${BASE_PATH}upgrade/finish`
+//!
+//! Verifies the passkey and stores the user and the credential without
+//! creating a Cognito user. If `disablePassword` of
+//! [`PasswordUpgradeOptions`] is `true`, the password of the user is replaced
+//! with a random one that is never disclosed once the passkey is stored, and
+//! the user signs in only with passkeys afterwards.
+//! Must be protected by the same JWT authorizer as `upgrade/start`, and a
+//! session started by another user is rejected.
+//! The request body must be [`FinishRegistrationSession`] and
+//! [`PasswordUpgradeOptions`] as `application/json`.
+//! The response body is [`FinishRegistrationResult`] as `application/json`
+//! with new recovery codes.
+//! Retries are idempotent; see [Retries](#retries).
+//!
+//! ## Retries
+//!
+//! A client may retry a finish request after a timeout even though the
+//! registration has succeeded and the session has been deleted.
+//! The outcome of a finished registration is recorded for an hour under the
+//! `Idempotency-Key` header, or under the session ID if the header is
+//! omitted, and a retry with the same key, session ID, and credential ID
+//! succeeds again without registering the credential twice.
+//! Recovery codes are not included in the response to a retry; the user may
+//! regenerate them through the Credentials API.
 
+use aws_config::SdkConfig;
 use aws_sdk_cognitoidentityprovider::types::{
     AttributeType as UserAttributeType,
     MessageActionType,
 };
-use aws_sdk_dynamodb::{
-    primitives::{DateTime, DateTimeFormat},
-    types::{AttributeValue, ReturnValue},
-};
+use aws_sdk_dynamodb::primitives::{DateTime, DateTimeFormat};
 use base64::{
     Engine as _,
     engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
@@ -41,243 +370,996 @@ use lambda_http::{
     Body,
     Error,
     Request,
-    RequestExt,
-    RequestPayloadExt,
     Response,
     http::StatusCode,
-    run,
-    service_fn,
 };
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::env;
+use ring::digest;
+use serde::{Serialize, de::DeserializeOwned};
+use std::future::Future;
 use std::sync::Arc;
-use std::time::SystemTime;
-use tracing::{error, info};
+use std::time::{Instant, SystemTime};
+use tracing::{Instrument, Span, error, field, info, info_span, instrument, warn};
 use webauthn_rs::{
-    Webauthn,
-    WebauthnBuilder,
     prelude::{
+        AttestationCaList,
         CreationChallengeResponse,
         CredentialID,
         PasskeyRegistration,
+        SecurityKeyRegistration,
         Uuid,
     },
 };
-use webauthn_rs_proto::RegisterPublicKeyCredential;
+use webauthn_rs_proto::options::{
+    AttestationConveyancePreference,
+    AuthenticatorAttachment,
+    ResidentKeyRequirement,
+};
 
-use authentication::parameters::load_relying_party_origin;
+use authentication::api_error::{ApiError, handle_api_errors};
+use authentication::audit::{
+    AuditEvent,
+    AuditEventType,
+    AuditLog,
+    ClientInfo,
+    load_audit_log,
+};
+use authentication::captcha::{CaptchaVerifier, load_captcha_verifier, require_captcha};
+use authentication::client_binding::{
+    ClientBinding,
+    ClientBindingPolicy,
+    load_client_binding_policy,
+};
+use authentication::config::{ConfigCheck, load_config_parameters};
+use authentication::enumeration::{
+    EnumerationProtection,
+    load_enumeration_protection,
+};
+use authentication::content::negotiate_content;
+use authentication::correlation::load_sdk_config;
+use authentication::credential_store::{CredentialBackend, CredentialStore};
+use authentication::display_name::{
+    load_max_display_name_length,
+    sanitize_display_name,
+};
+use authentication::domain_events::{
+    DomainEvent,
+    EventPublisher,
+    PasskeyRegistered,
+    load_event_publisher,
+    publish_event,
+};
+use authentication::email::{RecoveryMailer, load_recovery_mailer};
+use authentication::extensions::{
+    ExtensionOutputs,
+    ExtensionPolicy,
+    load_extension_policy,
+};
+use authentication::health::{HEALTH_PATH, health_check};
+use authentication::identity::authenticated_user_handle;
+use authentication::items::{
+    CredentialItem,
+    RecoveryLinkItem,
+    RegistrationContents,
+    RegistrationResultItem,
+    RegistrationSessionItem,
+    RegistrationUserInfo,
+    SessionKey,
+    UserItem,
+};
+use authentication::mds::{
+    AuthenticatorMetadata,
+    MetadataDirectory,
+    aaguid_of_attestation_object,
+    load_metadata_directory,
+};
+use authentication::hints::{
+    PublicKeyCredentialHint,
+    load_hints,
+    merge_hints_into,
+    resolve_hints,
+};
+use authentication::metrics::{ColdStart, Metrics, load_metrics};
+use authentication::parameters::{
+    load_attestation_ca_list,
+    load_webauthn,
+};
+use authentication::passkey::{
+    Attestation,
+    PasskeyProperties,
+    algorithm_of,
+    attestation_of,
+    authenticator_data_of,
+    is_attested,
+    is_user_verified_in,
+};
+use authentication::payload::{
+    ErrorResponseBody,
+    PayloadError,
+    load_max_body_size,
+    parse_json_payload,
+};
+use authentication::pii::{PiiProtection, load_pii_protection};
+use authentication::policy::{
+    AlgorithmAllowlist,
+    ChallengeTimeout,
+    CredentialLimit,
+    authenticator_attachment_name,
+    load_algorithm_allowlist,
+    load_attestation_conveyance_preference,
+    load_authenticator_attachment_policy,
+    load_challenge_timeout,
+    load_credential_limit,
+    load_resident_key_requirement,
+    parse_authenticator_attachment,
+    resolve_authenticator_attachment,
+    satisfies_attestation_conveyance,
+    satisfies_authenticator_attachment,
+    satisfies_resident_key_requirement,
+};
+use authentication::rate_limit::{
+    RateLimit,
+    hit,
+    load_rate_limit,
+    source_ip,
+    too_many_requests,
+};
+use authentication::recovery::{
+    generate_recovery_token,
+    hash_recovery_code,
+    hash_recovery_token,
+    new_recovery_codes,
+};
+use authentication::registration::{
+    AdditionalPasskeyRequest,
+    CredentialProperties,
+    FinishRegistrationResult,
+    FinishRegistrationSession,
+    NewUserInfo,
+    PasswordUpgradeOptions,
+    RecoveryLinkRequest,
+    RecoveryLinkSession,
+    RecoveryRequest,
+    StartRegistrationSession,
+};
+use authentication::routing::{
+    ApiVersion,
+    BearerAuth,
+    Router,
+    job_path,
+    load_bearer_auth,
+    require_json_body,
+    resolve_version,
+    unsupported_version,
+};
+use authentication::session_crypto::{
+    SessionEncryption,
+    load_session_encryption,
+};
+use authentication::session_id::{SessionIds, load_session_ids};
+use authentication::sessions::{
+    TakenSession,
+    get_registration_result,
+    open_registration_contents,
+    put_recovery_link,
+    put_registration_result,
+    put_registration_session,
+    seal_registration_contents,
+    take_recovery_link,
+    take_registration_session,
+};
+use authentication::store::{DynamoDbSessionStore, SessionBackend, load_database};
+use authentication::telemetry::{init_tracing, redact, request_span, truncate};
+use authentication::tenant::{
+    QuotaKind,
+    Tenant,
+    TenantDirectory,
+    load_tenant_directory,
+    unknown_tenant,
+};
+use authentication::username::{UsernamePolicy, is_email, load_username_policy};
+use authentication::users::{CreateUserError, UserDirectory};
+use authentication::warmer::run_with_warmer;
+use authentication::webhooks::{WebhookNotifier, load_webhook_notifier, notify_webhook};
 
 // Shared state.
 struct SharedState {
-    webauthn: Webauthn,
+    default_tenant: Arc<Tenant>,
+    tenants: Option<TenantDirectory>,
     cognito: aws_sdk_cognitoidentityprovider::Client,
     dynamodb: aws_sdk_dynamodb::Client,
+    base_path: String,
+    user_pool_id: String,
+    session_table_name: String,
+    challenge_timeout: ChallengeTimeout,
+    authenticator_attachment: Option<AuthenticatorAttachment>,
+    resident_key: ResidentKeyRequirement,
+    attestation: AttestationConveyancePreference,
+    algorithm_allowlist: Option<AlgorithmAllowlist>,
+    hints: Option<Vec<PublicKeyCredentialHint>>,
+    attestation_ca_list: Option<AttestationCaList>,
+    metadata: Option<MetadataDirectory>,
+    max_body_size: usize,
+    username_policy: UsernamePolicy,
+    max_display_name_length: usize,
+    credential_limit: Option<CredentialLimit>,
+    rate_limit_per_ip: Option<RateLimit>,
+    rate_limit_per_username: Option<RateLimit>,
+    captcha: Option<CaptchaVerifier>,
+    client_binding: ClientBindingPolicy,
+    enumeration_protection: Option<EnumerationProtection>,
+    session_encryption: Option<SessionEncryption>,
+    session_ids: SessionIds,
+    sessions: SessionBackend,
+    users: CredentialBackend,
+    metrics: Metrics,
+    audit_log: Option<AuditLog>,
+    event_publisher: Option<EventPublisher>,
+    webhooks: Option<WebhookNotifier>,
+    recovery_mailer: Option<RecoveryMailer>,
+    extension_policy: ExtensionPolicy,
+}
+
+// Configuration validated at cold start.
+struct Config {
     base_path: String,
     user_pool_id: String,
     session_table_name: String,
     credential_table_name: String,
+    challenge_timeout: ChallengeTimeout,
+    authenticator_attachment: Option<AuthenticatorAttachment>,
+    resident_key: ResidentKeyRequirement,
+    attestation: AttestationConveyancePreference,
+    algorithm_allowlist: Option<AlgorithmAllowlist>,
+    hints: Option<Vec<PublicKeyCredentialHint>>,
+    max_body_size: usize,
+    username_policy: UsernamePolicy,
+    max_display_name_length: usize,
+    credential_limit: Option<CredentialLimit>,
+    rate_limit_per_ip: Option<RateLimit>,
+    rate_limit_per_username: Option<RateLimit>,
+    client_binding: ClientBindingPolicy,
+    enumeration_protection: Option<EnumerationProtection>,
+    extension_policy: ExtensionPolicy,
+    bearer_auth: Option<BearerAuth>,
+}
+
+impl Config {
+    fn from_env(secrets: aws_sdk_secretsmanager::Client) -> Result<Self, Error> {
+        let mut check = ConfigCheck::new();
+        check.required_any(&["RP_ORIGIN", "RP_ORIGIN_PARAMETER_PATH"]);
+        let config = Self {
+            base_path: check.required("BASE_PATH"),
+            user_pool_id: check.required("USER_POOL_ID"),
+            session_table_name: check.required("SESSION_TABLE_NAME"),
+            credential_table_name: check.required("CREDENTIAL_TABLE_NAME"),
+            challenge_timeout: check.load(load_challenge_timeout()),
+            authenticator_attachment: check.load(load_authenticator_attachment_policy()),
+            resident_key: check.load_or(
+                load_resident_key_requirement(),
+                ResidentKeyRequirement::Required,
+            ),
+            attestation: check.load_or(
+                load_attestation_conveyance_preference(),
+                AttestationConveyancePreference::None,
+            ),
+            algorithm_allowlist: check.load(load_algorithm_allowlist()),
+            hints: check.load(load_hints()),
+            max_body_size: check.load(load_max_body_size()),
+            username_policy: check.load(load_username_policy()),
+            max_display_name_length: check.load(load_max_display_name_length()),
+            credential_limit: check.load(load_credential_limit()),
+            rate_limit_per_ip: check.load(load_rate_limit(
+                "RATE_LIMIT_PER_IP",
+                Some(RateLimit { limit: 30, window: 60 }),
+            )),
+            rate_limit_per_username: check.load(load_rate_limit(
+                "RATE_LIMIT_PER_USERNAME",
+                Some(RateLimit { limit: 10, window: 60 }),
+            )),
+            client_binding: check.load(load_client_binding_policy()),
+            enumeration_protection: check.load(load_enumeration_protection(secrets)),
+            extension_policy: check.load(load_extension_policy()),
+            bearer_auth: check.load(load_bearer_auth()),
+        };
+        Ok(check.finish(config)?)
+    }
 }
 
 impl SharedState {
-    async fn new() -> Result<Self, Error> {
-        let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
-        let (rp_id, rp_origin) =
-            load_relying_party_origin(aws_sdk_ssm::Client::new(&config)).await?;
-        let webauthn = WebauthnBuilder::new(&rp_id, &rp_origin)?
-            .rp_name("Passkey Test")
-            .build()?;
-        let base_path = env::var("BASE_PATH")
-            .or(Err("BASE_PATH env must be set"))?;
+    #[instrument(name = "cold_start", skip_all)]
+    async fn new(sdk_config: &SdkConfig, config: Config) -> Result<Self, Error> {
+        let ssm = aws_sdk_ssm::Client::new(sdk_config);
+        let webauthn = load_webauthn(ssm.clone()).await?;
+        let dynamodb = aws_sdk_dynamodb::Client::new(sdk_config);
+        let pii = load_pii_protection(
+            aws_sdk_kms::Client::new(sdk_config),
+            aws_sdk_secretsmanager::Client::new(sdk_config),
+        )?;
+        // registration sessions hold PII
+        let session_encryption = load_session_encryption(
+            aws_sdk_kms::Client::new(sdk_config),
+        )?.or_else(|| pii.as_ref().map(PiiProtection::session_encryption));
+        let database = load_database().await?;
+        let sessions = SessionBackend::new(
+            DynamoDbSessionStore::new(dynamodb.clone(), config.session_table_name.clone()),
+            database.as_ref(),
+        );
+        let users = CredentialBackend::new(
+            UserDirectory::new(dynamodb.clone(), config.credential_table_name)
+                .with_pii_protection(pii),
+            database.as_ref(),
+        );
         Ok(Self {
-            webauthn,
-            cognito: aws_sdk_cognitoidentityprovider::Client::new(&config),
-            dynamodb: aws_sdk_dynamodb::Client::new(&config),
-            base_path: base_path.trim_end_matches('/').into(),
-            user_pool_id: env::var("USER_POOL_ID")
-                .or(Err("USER_POOL_ID env must be set"))?,
-            session_table_name: env::var("SESSION_TABLE_NAME")
-                .or(Err("SESSION_TABLE_NAME env must be set"))?,
-            credential_table_name: env::var("CREDENTIAL_TABLE_NAME")
-                .or(Err("CREDENTIAL_TABLE_NAME env must be set"))?,
+            default_tenant: Arc::new(Tenant::default_tenant(webauthn)),
+            tenants: load_tenant_directory(dynamodb.clone())?,
+            cognito: aws_sdk_cognitoidentityprovider::Client::new(sdk_config),
+            dynamodb: dynamodb.clone(),
+            base_path: config.base_path.trim_end_matches('/').into(),
+            user_pool_id: config.user_pool_id,
+            session_table_name: config.session_table_name,
+            challenge_timeout: config.challenge_timeout,
+            authenticator_attachment: config.authenticator_attachment,
+            resident_key: config.resident_key,
+            attestation: config.attestation,
+            algorithm_allowlist: config.algorithm_allowlist,
+            hints: config.hints,
+            attestation_ca_list: load_attestation_ca_list(ssm).await?,
+            metadata: load_metadata_directory(dynamodb.clone())?,
+            max_body_size: config.max_body_size,
+            username_policy: config.username_policy,
+            max_display_name_length: config.max_display_name_length,
+            credential_limit: config.credential_limit,
+            rate_limit_per_ip: config.rate_limit_per_ip,
+            rate_limit_per_username: config.rate_limit_per_username,
+            captcha: load_captcha_verifier(
+                aws_sdk_secretsmanager::Client::new(sdk_config),
+            )?,
+            client_binding: config.client_binding,
+            enumeration_protection: config.enumeration_protection,
+            session_encryption,
+            session_ids: load_session_ids(
+                aws_sdk_secretsmanager::Client::new(sdk_config),
+            )?,
+            sessions,
+            users,
+            metrics: load_metrics("registration")?,
+            audit_log: load_audit_log(dynamodb)?,
+            event_publisher: load_event_publisher(
+                aws_sdk_eventbridge::Client::new(sdk_config),
+            )?,
+            webhooks: load_webhook_notifier(
+                aws_sdk_secretsmanager::Client::new(sdk_config),
+            )?,
+            recovery_mailer: load_recovery_mailer(
+                aws_sdk_sesv2::Client::new(sdk_config),
+            )?,
+            extension_policy: config.extension_policy,
         })
     }
+
+    // parses new user info, normalizes the username, and sanitizes the
+    // display name.
+    fn parse_new_user_info(&self, body: &[u8]) -> Result<NewUserInfo, PayloadError> {
+        let mut user_info: NewUserInfo =
+            parse_json_payload(body, self.max_body_size)?;
+        user_info.username = self.username_policy.apply(&user_info.username)
+            .map_err(|e| PayloadError::Malformed {
+                field: Some("username".into()),
+                message: e.to_string(),
+            })?;
+        user_info.display_name = sanitize_display_name(
+            &user_info.display_name,
+            self.max_display_name_length,
+        ).ok_or_else(|| PayloadError::Malformed {
+            field: Some("displayName".into()),
+            message: "display name must not be blank".into(),
+        })?;
+        Ok(user_info)
+    }
+
+    // parses a recovery request and normalizes the username.
+    fn parse_recovery_request(&self, body: &[u8]) -> Result<RecoveryRequest, PayloadError> {
+        let mut request: RecoveryRequest =
+            parse_json_payload(body, self.max_body_size)?;
+        request.username = self.username_policy.apply(&request.username)
+            .map_err(|e| PayloadError::Malformed {
+                field: Some("username".into()),
+                message: e.to_string(),
+            })?;
+        Ok(request)
+    }
+
+    // parses a request for a recovery link and normalizes the username.
+    fn parse_recovery_link_request(
+        &self,
+        body: &[u8],
+    ) -> Result<RecoveryLinkRequest, PayloadError> {
+        let mut request: RecoveryLinkRequest =
+            parse_json_payload(body, self.max_body_size)?;
+        request.username = self.username_policy.apply(&request.username)
+            .map_err(|e| PayloadError::Malformed {
+                field: Some("username".into()),
+                message: e.to_string(),
+            })?;
+        Ok(request)
+    }
 }
 
-/// Information on a new user.
-#[derive(Clone, Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct NewUserInfo {
-    /// Username.
-    pub username: String,
+// Maximum number of attempts to generate a unique session ID.
+const MAX_SESSION_ID_ATTEMPTS: usize = 3;
+
+// Time to live in seconds of the result record of a finished registration.
+const IDEMPOTENCY_RECORD_TTL: i64 = 60 * 60;
 
-    /// Display name.
-    pub display_name: String,
+// Kind of registration.
+#[derive(Clone, Copy, Debug)]
+enum RegistrationKind {
+    // Passkey.
+    Passkey,
+    // Security key with enforced attestation.
+    SecurityKey,
+    // Passkey of an existing user recovering the account.
+    Recovery,
+    // Additional passkey of an authenticated existing user.
+    Additional,
+    // First passkey of an authenticated password user.
+    Upgrade,
 }
 
-/// Beginning of a session to register a new user.
-#[derive(Clone, Debug, Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct StartRegistrationSession {
-    /// Session ID.
-    pub session_id: String,
+impl RegistrationKind {
+    // key of a session.
+    fn session_key(self, session_id: &str) -> SessionKey<'_> {
+        match self {
+            RegistrationKind::Passkey => SessionKey::Registration(session_id),
+            RegistrationKind::SecurityKey =>
+                SessionKey::SecurityKeyRegistration(session_id),
+            RegistrationKind::Recovery =>
+                SessionKey::RecoveryRegistration(session_id),
+            RegistrationKind::Additional =>
+                SessionKey::AdditionalRegistration(session_id),
+            RegistrationKind::Upgrade =>
+                SessionKey::UpgradeRegistration(session_id),
+        }
+    }
+
+    // key of the result record of a finished registration.
+    fn result_key(self, idempotency_key: &str) -> SessionKey<'_> {
+        match self {
+            RegistrationKind::Passkey =>
+                SessionKey::RegistrationResult(idempotency_key),
+            RegistrationKind::SecurityKey =>
+                SessionKey::SecurityKeyRegistrationResult(idempotency_key),
+            RegistrationKind::Recovery =>
+                SessionKey::RecoveryRegistrationResult(idempotency_key),
+            RegistrationKind::Additional =>
+                SessionKey::AdditionalRegistrationResult(idempotency_key),
+            RegistrationKind::Upgrade =>
+                SessionKey::UpgradeRegistrationResult(idempotency_key),
+        }
+    }
+
+    // value of the `credentialType` attribute of credentials.
+    fn credential_type(self) -> &'static str {
+        match self {
+            RegistrationKind::Passkey
+                | RegistrationKind::Recovery
+                | RegistrationKind::Additional
+                | RegistrationKind::Upgrade => "passkey",
+            RegistrationKind::SecurityKey => "securityKey",
+        }
+    }
 
-    /// Credential creation options.
-    pub credential_creation_options: CreationChallengeResponse,
+    // whether a credential is added to an existing user, who keeps the
+    // recovery codes.
+    fn is_existing_user(self) -> bool {
+        matches!(self, RegistrationKind::Recovery | RegistrationKind::Additional)
+    }
+
+    // detail of the audit event of a registered credential.
+    fn audit_detail(self) -> &'static str {
+        match self {
+            RegistrationKind::Passkey => "passkey",
+            RegistrationKind::SecurityKey => "securityKey",
+            RegistrationKind::Recovery => "recovery",
+            RegistrationKind::Additional => "additional",
+            RegistrationKind::Upgrade => "upgrade",
+        }
+    }
+}
+
+// Context of a job.
+#[derive(Clone)]
+struct Job {
+    shared_state: Arc<SharedState>,
+    tenant: Arc<Tenant>,
 }
 
-/// End of a session to register a new user.
-#[derive(Clone, Debug, Deserialize)]
-#[serde(rename_all = "camelCase")]
-pub struct FinishRegistrationSession {
-    /// Session ID.
-    pub session_id: String,
+impl Job {
+    // runs a job and records the latency under a given metric name.
+    async fn timed<F>(&self, metric: &'static str, job: F) -> Result<Response<Body>, Error>
+    where
+        F: Future<Output = Result<Response<Body>, Error>>,
+    {
+        let started_at = Instant::now();
+        let res = job.await;
+        self.shared_state.metrics.latency(metric, started_at.elapsed());
+        res
+    }
+
+    // runs a job that takes a username, which may be enumerated, and records
+    // the latency under a given metric name.
+    //
+    // the response time must not tell whether the username exists.
+    async fn padded<F>(&self, metric: &'static str, job: F) -> Result<Response<Body>, Error>
+    where
+        F: Future<Output = Result<Response<Body>, Error>>,
+    {
+        let started_at = Instant::now();
+        let res = job.await;
+        if let Some(protection) = self.shared_state.enumeration_protection.as_ref() {
+            protection.pad(started_at).await;
+        }
+        self.shared_state.metrics.latency(metric, started_at.elapsed());
+        res
+    }
+}
 
-    /// Public key credential.
-    pub public_key_credential: RegisterPublicKeyCredential,
+// routes of the jobs.
+//
+// every job takes a JSON body by POST.
+fn router(bearer_auth: Option<BearerAuth>) -> Router<Job> {
+    Router::new()
+        .post("/start", |job: Job, event: Request, _| async move {
+            require_json_body(&event)?;
+            job.padded("start_registration_latency", start_job(job.clone(), event)).await
+        })
+        .post("/finish", |job: Job, event: Request, _| async move {
+            require_json_body(&event)?;
+            job.timed(
+                "finish_registration_latency",
+                finish_job(job.clone(), event, RegistrationKind::Passkey),
+            ).await
+        })
+        .post("/security-key/start", |job: Job, event: Request, _| async move {
+            require_json_body(&event)?;
+            job.padded(
+                "start_security_key_registration_latency",
+                start_security_key_job(job.clone(), event),
+            ).await
+        })
+        .post("/security-key/finish", |job: Job, event: Request, _| async move {
+            require_json_body(&event)?;
+            job.timed(
+                "finish_security_key_registration_latency",
+                finish_security_key_job(job.clone(), event),
+            ).await
+        })
+        .post("/recovery/start", |job: Job, event: Request, _| async move {
+            require_json_body(&event)?;
+            job.padded("start_recovery_latency", start_recovery_job(job.clone(), event)).await
+        })
+        .post("/recovery/finish", |job: Job, event: Request, _| async move {
+            require_json_body(&event)?;
+            job.timed(
+                "finish_recovery_latency",
+                finish_job(job.clone(), event, RegistrationKind::Recovery),
+            ).await
+        })
+        .post("/recovery/email", |job: Job, event: Request, _| async move {
+            require_json_body(&event)?;
+            job.padded(
+                "request_recovery_link_latency",
+                request_recovery_link_job(job.clone(), event),
+            ).await
+        })
+        .post("/recovery/email/start", |job: Job, event: Request, _| async move {
+            require_json_body(&event)?;
+            job.timed(
+                "start_recovery_link_latency",
+                start_recovery_link_job(job.clone(), event),
+            ).await
+        })
+        .post("/passkeys/start", |job: Job, event: Request, _| async move {
+            require_json_body(&event)?;
+            job.timed(
+                "start_additional_registration_latency",
+                start_additional_job(job.clone(), event),
+            ).await
+        })
+        .post("/passkeys/finish", |job: Job, event: Request, _| async move {
+            require_json_body(&event)?;
+            job.timed(
+                "finish_additional_registration_latency",
+                finish_additional_job(job.clone(), event),
+            ).await
+        })
+        .post("/upgrade/start", |job: Job, event: Request, _| async move {
+            require_json_body(&event)?;
+            job.timed(
+                "start_password_upgrade_latency",
+                start_password_upgrade_job(job.clone(), event),
+            ).await
+        })
+        .post("/upgrade/finish", |job: Job, event: Request, _| async move {
+            require_json_body(&event)?;
+            job.timed(
+                "finish_password_upgrade_latency",
+                finish_password_upgrade_job(job.clone(), event),
+            ).await
+        })
+        .with_bearer_auth(bearer_auth)
 }
 
 async fn function_handler(
     shared_state: Arc<SharedState>,
+    router: &Router<Job>,
     event: Request,
 ) -> Result<Response<Body>, Error> {
-    let job_path = event.raw_http_path()
-        .strip_prefix(&shared_state.base_path)
-        .ok_or(format!("path must start with \"{}\"", shared_state.base_path))?;
-    match job_path {
-        "/start" => {
-            let user_info: NewUserInfo = event
-                .payload()?
-                .ok_or("missing new user info")?;
-            start_registration(shared_state, user_info).await
+    let job_path = job_path(&event, &shared_state.base_path)?;
+    if job_path == HEALTH_PATH {
+        let mut tables = vec![("sessionTable", shared_state.session_table_name.as_str())];
+        if let Some(table_name) = shared_state.users.table_name() {
+            tables.push(("credentialTable", table_name));
         }
-        "/finish" => {
-            let session: FinishRegistrationSession = event
-                .payload()?
-                .ok_or("missing registration session")?;
-            finish_registration(shared_state, session).await
+        if let Some(audit_log) = shared_state.audit_log.as_ref() {
+            tables.push(("auditTable", audit_log.table_name()));
         }
-        _ => Err(format!("unsupported job path: {}", job_path).into()),
+        return health_check("registration", &event, &shared_state.dynamodb, &tables).await;
     }
+    let (tenant, job_path) = match shared_state.tenants.as_ref() {
+        Some(tenants) => match tenants.resolve_request(&event, job_path).await? {
+            Some(resolved) => resolved,
+            None => return unknown_tenant(),
+        },
+        None => (shared_state.default_tenant.clone(), job_path),
+    };
+    let route = match resolve_version(job_path) {
+        Some((ApiVersion::V1, route)) => route.to_string(),
+        None => return unsupported_version(job_path),
+    };
+    let job = Job {
+        shared_state,
+        tenant,
+    };
+    router.handle(job, event, &route).await
 }
 
-async fn start_registration(
-    shared_state: Arc<SharedState>,
-    user_info: NewUserInfo,
+// parses the payload of a finish request.
+fn parse_finish_session(
+    shared_state: &SharedState,
+    event: &Request,
+) -> Result<FinishRegistrationSession, PayloadError> {
+    parse_json_payload(event.body().as_ref(), shared_state.max_body_size)
+}
+
+async fn start_job(job: Job, event: Request) -> Result<Response<Body>, Error> {
+    let Job { shared_state, tenant } = job;
+    let user_info = match shared_state.parse_new_user_info(event.body().as_ref()) {
+        Ok(user_info) => user_info,
+        Err(e) => {
+            error!("bad payload: {:?}", e);
+            return e.into_response();
+        }
+    };
+    // CAPTCHA is verified before the rate limits are counted
+    if let Some(res) = require_captcha(shared_state.captcha.as_ref(), &event).await? {
+        return Ok(res);
+    }
+    if let Some(res) = check_rate_limits(&shared_state, &tenant, &event, &user_info.username).await? {
+        return Ok(res);
+    }
+    let client = ClientInfo::of(&event);
+    start_registration(shared_state, tenant, user_info, client).await
+}
+
+async fn finish_job(
+    job: Job,
+    event: Request,
+    kind: RegistrationKind,
 ) -> Result<Response<Body>, Error> {
-    info!("start_registration: {:?}", user_info);
+    let Job { shared_state, tenant } = job;
+    let session = match parse_finish_session(&shared_state, &event) {
+        Ok(session) => session,
+        Err(e) => {
+            error!("bad payload: {:?}", e);
+            return e.into_response();
+        }
+    };
+    let client = ClientInfo::of(&event);
+    let key = idempotency_key(&event, &session);
+    let extensions = ExtensionOutputs::of_payload(event.body().as_ref());
+    finish_registration(
+        shared_state,
+        tenant,
+        kind,
+        session,
+        extensions,
+        client,
+        key,
+        None,
+    ).await
+}
 
-    // resolves the existing user
-    let existing_user = shared_state.cognito
-        .list_users()
-        .user_pool_id(shared_state.user_pool_id.clone())
-        .attributes_to_get("username")
-        .filter(format!("username = \"{}\"", user_info.username))
-        .limit(1)
-        .send()
-        .await?
-        .users
-        .unwrap_or_default()
-        .pop();
+async fn start_security_key_job(job: Job, event: Request) -> Result<Response<Body>, Error> {
+    let Job { shared_state, tenant } = job;
+    let user_info = match shared_state.parse_new_user_info(event.body().as_ref()) {
+        Ok(user_info) => user_info,
+        Err(e) => {
+            error!("bad payload: {:?}", e);
+            return e.into_response();
+        }
+    };
+    // CAPTCHA is verified before the rate limits are counted
+    if let Some(res) = require_captcha(shared_state.captcha.as_ref(), &event).await? {
+        return Ok(res);
+    }
+    if let Some(res) = check_rate_limits(&shared_state, &tenant, &event, &user_info.username).await? {
+        return Ok(res);
+    }
+    start_security_key_registration(
+        shared_state,
+        tenant,
+        user_info,
+        ClientInfo::of(&event),
+    ).await
+}
 
-    // obtains the user ID or generates a new one for a new user
-    let user_unique_id = existing_user.as_ref()
-        .map(|u| u.username.as_ref()
-            .ok_or("missing username in user pool"))
-        .transpose()?
-        .map(|username| base64url.decode(username)
-            .or(Err("malformed username in user pool"))
-            .and_then(|id| Uuid::from_slice(&id)
-                .or(Err("malformed username in user pool"))))
-        .transpose()?
-        .unwrap_or_else(Uuid::new_v4);
+async fn finish_security_key_job(job: Job, event: Request) -> Result<Response<Body>, Error> {
+    let Job { shared_state, tenant } = job;
+    let session = match parse_finish_session(&shared_state, &event) {
+        Ok(session) => session,
+        Err(e) => {
+            error!("bad payload: {:?}", e);
+            return e.into_response();
+        }
+    };
+    let client = ClientInfo::of(&event);
+    let key = idempotency_key(&event, &session);
+    let extensions = ExtensionOutputs::of_payload(event.body().as_ref());
+    finish_security_key_registration(
+        shared_state,
+        tenant,
+        session,
+        extensions,
+        client,
+        key,
+    ).await
+}
 
-    // lists existing credentials for the user to be excluded
-    let exclude_credentials: Option<Vec<CredentialID>> =
-        match existing_user.as_ref()
-    {
-        Some(user) => {
-            let username = user.username.as_ref().unwrap();
-            info!("listing credentials for {}", username);
-            let credentials = shared_state.dynamodb
-                .query()
-                .table_name(shared_state.credential_table_name.clone())
-                .key_condition_expression("pk = :pk")
-                .expression_attribute_values(":pk", AttributeValue::S(
-                    format!("user#{}", username),
-                ))
-                .send()
-                .await?
-                .items
-                .unwrap_or_default();
-            Some(
-                credentials.into_iter()
-                    .map(|c| {
-                        let id = c.get("credentialId")
-                            .ok_or("missing credentialId in the database")?
-                            .as_s()
-                            .or(Err("malformed credentialId in the database"))?
-                            .as_str();
-                        // as far as I know, we have to use serde::Deserialize
-                        // to build HumanBinaryData from a base64-encoded string
-                        serde_json::from_value(serde_json::Value::String(id.into()))
-                            .or(Err("malformed credentialId in the database"))
-                    })
-                    .collect::<Result<_, _>>()?,
-            )
+async fn start_recovery_job(job: Job, event: Request) -> Result<Response<Body>, Error> {
+    let Job { shared_state, tenant } = job;
+    let request = match shared_state.parse_recovery_request(event.body().as_ref()) {
+        Ok(request) => request,
+        Err(e) => {
+            error!("bad payload: {:?}", e);
+            return e.into_response();
+        }
+    };
+    if let Some(res) = check_rate_limits(&shared_state, &tenant, &event, &request.username).await? {
+        return Ok(res);
+    }
+    let client = ClientInfo::of(&event);
+    start_recovery(shared_state, tenant, request, client).await
+}
+
+async fn request_recovery_link_job(job: Job, event: Request) -> Result<Response<Body>, Error> {
+    let Job { shared_state, tenant } = job;
+    let request = match shared_state.parse_recovery_link_request(event.body().as_ref()) {
+        Ok(request) => request,
+        Err(e) => {
+            error!("bad payload: {:?}", e);
+            return e.into_response();
+        }
+    };
+    if let Some(res) = check_rate_limits(&shared_state, &tenant, &event, &request.username).await? {
+        return Ok(res);
+    }
+    let client = ClientInfo::of(&event);
+    request_recovery_link(shared_state, tenant, request, client).await
+}
+
+async fn start_recovery_link_job(job: Job, event: Request) -> Result<Response<Body>, Error> {
+    let Job { shared_state, tenant } = job;
+    let session = match parse_json_payload::<RecoveryLinkSession>(
+        event.body().as_ref(),
+        shared_state.max_body_size,
+    ) {
+        Ok(session) => session,
+        Err(e) => {
+            error!("bad payload: {:?}", e);
+            return e.into_response();
+        }
+    };
+    let client = ClientInfo::of(&event);
+    start_recovery_link(shared_state, tenant, session, client).await
+}
+
+async fn start_additional_job(job: Job, event: Request) -> Result<Response<Body>, Error> {
+    let Job { shared_state, tenant } = job;
+    let user_handle = authenticated_user_handle(&event)
+        .ok_or(ApiError::Unauthenticated)?;
+    match parse_json_payload::<AdditionalPasskeyRequest>(
+        event.body().as_ref(),
+        shared_state.max_body_size,
+    ) {
+        Ok(request) => start_additional_registration(
+            shared_state,
+            tenant,
+            user_handle,
+            request,
+            ClientInfo::of(&event),
+        ).await,
+        Err(e) => {
+            error!("bad payload: {:?}", e);
+            e.into_response()
+        }
+    }
+}
+
+async fn finish_additional_job(job: Job, event: Request) -> Result<Response<Body>, Error> {
+    let Job { shared_state, tenant } = job;
+    let user_handle = authenticated_user_handle(&event)
+        .ok_or(ApiError::Unauthenticated)?;
+    let session = match parse_finish_session(&shared_state, &event) {
+        Ok(session) => session,
+        Err(e) => {
+            error!("bad payload: {:?}", e);
+            return e.into_response();
+        }
+    };
+    let client = ClientInfo::of(&event);
+    let key = idempotency_key(&event, &session);
+    let extensions = ExtensionOutputs::of_payload(event.body().as_ref());
+    finish_registration(
+        shared_state,
+        tenant,
+        RegistrationKind::Additional,
+        session,
+        extensions,
+        client,
+        key,
+        Some(user_handle),
+    ).await
+}
+
+async fn start_password_upgrade_job(job: Job, event: Request) -> Result<Response<Body>, Error> {
+    let Job { shared_state, tenant } = job;
+    let user_handle = authenticated_user_handle(&event)
+        .ok_or(ApiError::Unauthenticated)?;
+    match parse_json_payload::<AdditionalPasskeyRequest>(
+        event.body().as_ref(),
+        shared_state.max_body_size,
+    ) {
+        Ok(request) => start_password_upgrade(
+            shared_state,
+            tenant,
+            user_handle,
+            request,
+            ClientInfo::of(&event),
+        ).await,
+        Err(e) => {
+            error!("bad payload: {:?}", e);
+            e.into_response()
+        }
+    }
+}
+
+async fn finish_password_upgrade_job(job: Job, event: Request) -> Result<Response<Body>, Error> {
+    let Job { shared_state, tenant } = job;
+    let user_handle = authenticated_user_handle(&event)
+        .ok_or(ApiError::Unauthenticated)?;
+    let payload = parse_finish_session(&shared_state, &event)
+        .and_then(|session| parse_json_payload::<PasswordUpgradeOptions>(
+            event.body().as_ref(),
+            shared_state.max_body_size,
+        ).map(|options| (session, options)));
+    let (session, options) = match payload {
+        Ok(payload) => payload,
+        Err(e) => {
+            error!("bad payload: {:?}", e);
+            return e.into_response();
         }
-        None => None,
     };
+    let client = ClientInfo::of(&event);
+    let key = idempotency_key(&event, &session);
+    let extensions = ExtensionOutputs::of_payload(event.body().as_ref());
+    finish_password_upgrade(
+        shared_state,
+        tenant,
+        session,
+        options,
+        extensions,
+        client,
+        key,
+        user_handle,
+    ).await
+}
+
+#[instrument(skip_all, fields(session_id))]
+async fn start_registration(
+    shared_state: Arc<SharedState>,
+    tenant: Arc<Tenant>,
+    user_info: NewUserInfo,
+    client: ClientInfo,
+) -> Result<Response<Body>, Error> {
+    info!(
+        "start_registration: {} {:?}",
+        redact(&user_info.username),
+        user_info.authenticator_attachment,
+    );
+
+    let authenticator_attachment = resolve_authenticator_attachment(
+        shared_state.authenticator_attachment,
+        user_info.authenticator_attachment,
+    )?;
+    let (user_unique_id, exclude_credentials) =
+        resolve_user(&shared_state, &tenant, &user_info.username).await?;
+
+    begin_passkey_registration(
+        &shared_state,
+        &tenant,
+        RegistrationKind::Passkey,
+        user_unique_id,
+        user_info,
+        exclude_credentials,
+        authenticator_attachment,
+        &client,
+    ).await
+}
 
-    let res = match shared_state.webauthn.start_passkey_registration(
+// starts a passkey registration session for a new or existing user.
+//
+// the session is bound to `client` if the client binding is enabled.
+#[allow(clippy::too_many_arguments)]
+async fn begin_passkey_registration(
+    shared_state: &SharedState,
+    tenant: &Tenant,
+    kind: RegistrationKind,
+    user_unique_id: Uuid,
+    user_info: NewUserInfo,
+    exclude_credentials: Option<Vec<CredentialID>>,
+    authenticator_attachment: Option<AuthenticatorAttachment>,
+    client: &ClientInfo,
+) -> Result<Response<Body>, Error> {
+    let hints = resolve_hints(shared_state.hints.as_deref(), user_info.hints.as_deref());
+    let res = match tenant.webauthn().start_passkey_registration(
         user_unique_id,
         &user_info.username,
         &user_info.display_name,
         exclude_credentials,
     ) {
         Ok((mut ccr, reg_state)) => {
+            restrict_algorithms(shared_state, &mut ccr)?;
             // caches `reg_state`
-            let user_unique_id = base64url.encode(user_unique_id.into_bytes());
-            let session_id = base64url.encode(Uuid::new_v4().as_bytes());
-            let ttl = DateTime::from(SystemTime::now()).secs() + 60;
-            info!("putting registration session: {}", session_id);
-            shared_state.dynamodb
-                .put_item()
-                .table_name(shared_state.session_table_name.clone())
-                .item(
-                    "pk",
-                    AttributeValue::S(format!("registration#{}", session_id)),
-                )
-                .item("ttl", AttributeValue::N(format!("{}", ttl)))
-                .item("userId", AttributeValue::S(user_unique_id))
-                .item("userInfo", AttributeValue::M(HashMap::from([
-                    (
-                        "username".into(),
-                        AttributeValue::S(user_info.username.into()),
-                    ),
-                    (
-                        "displayName".into(),
-                        AttributeValue::S(user_info.display_name.into()),
-                    ),
-                ])))
-                .item(
-                    "state",
-                    AttributeValue::S(serde_json::to_string(&reg_state)?),
-                )
-                .send()
-                .await?;
-            // requires a resident key
+            let session_id = put_registration_session(
+                shared_state,
+                tenant,
+                kind,
+                user_unique_id,
+                user_info,
+                serde_json::to_string(&reg_state)?,
+                authenticator_attachment,
+                shared_state.client_binding.bind(client),
+            ).await?;
+            Span::current().record("session_id", field::display(truncate(&session_id)));
+            shared_state.metrics.count("registration_started");
+            // applies the resident key requirement
             if let Some(selection) = ccr.public_key.authenticator_selection.as_mut() {
-                selection.require_resident_key = true;
+                selection.resident_key = Some(shared_state.resident_key);
+                selection.require_resident_key =
+                    shared_state.resident_key == ResidentKeyRequirement::Required;
+                selection.authenticator_attachment = authenticator_attachment;
             }
-            serde_json::to_string(&StartRegistrationSession {
-                session_id,
-                credential_creation_options: ccr,
-            })?
+            // asks the client whether the credential is resident
+            ccr.public_key.extensions
+                .get_or_insert_with(Default::default)
+                .cred_props = Some(true);
+            ccr.public_key.attestation = Some(shared_state.attestation);
+            start_registration_body(shared_state, session_id, ccr, hints.as_deref())?
         }
         Err(e) => {
             error!("failed to start registration: {}", e);
-            return Err("failed to start registration".into());
+            return Err(ApiError::internal("failed to start registration").into());
         }
     };
 
@@ -287,163 +1369,1586 @@ async fn start_registration(
         .body(res.into())?)
 }
 
+// finishes a passkey registration.
+//
+// `caller` is the authenticated user who must own the session if specified.
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip_all, fields(session_id = %truncate(&session.session_id)))]
 async fn finish_registration(
     shared_state: Arc<SharedState>,
+    tenant: Arc<Tenant>,
+    kind: RegistrationKind,
     session: FinishRegistrationSession,
+    extensions: ExtensionOutputs,
+    client: ClientInfo,
+    idempotency_key: String,
+    caller: Option<String>,
 ) -> Result<Response<Body>, Error> {
-    info!("finish_registration: {}", session.session_id);
-
-    // pops the session
-    let item = shared_state.dynamodb
-        .delete_item()
-        .table_name(shared_state.session_table_name.clone())
-        .key(
-            "pk",
-            AttributeValue::S(format!("registration#{}", session.session_id)),
-        )
-        .return_values(ReturnValue::AllOld)
-        .send()
-        .await?
-        .attributes
-        .ok_or("expired or wrong registration session")?;
-
-    // the session may have expired
-    let ttl: i64 = item.get("ttl")
-        .ok_or("missing ttl")?
-        .as_n()
-        .or(Err("invalid ttl"))?
-        .parse()?;
-    if ttl < DateTime::from(SystemTime::now()).secs() {
-        return Err("registration session expired".into());
-    }
-
-    // extracts the registration state
-    let reg_state: PasskeyRegistration = serde_json::from_str(
-        item.get("state")
-            .ok_or("missing registration state")?
-            .as_s()
-            .or(Err("invalid state"))?,
-    )?;
+    info!("finish_registration: {:?} {}", kind, truncate(&session.session_id));
+    check_session_id(&shared_state, &session.session_id).await?;
+
+    let Some(item) = pop_registration_session(
+        &shared_state,
+        &tenant,
+        kind,
+        &session.session_id,
+    ).await? else {
+        // the client may be retrying a finished registration
+        return replay_registration_result(
+            &shared_state,
+            &tenant,
+            kind,
+            &idempotency_key,
+            &session,
+        ).await;
+    };
+    if caller.is_some_and(|caller| caller != item.user_id) {
+        error!("registration session of another user");
+        return Err(ApiError::SessionExpired("registration session of another user").into());
+    }
+    check_client_binding(&shared_state, &item, &client)?;
+    let reg_state: PasskeyRegistration = registration_state(&item)?;
 
     // verifies the request
-    match shared_state.webauthn.finish_passkey_registration(
-        &session.public_key_credential,
-        &reg_state,
-    ) {
+    let verified = info_span!("verify_registration").in_scope(|| {
+        tenant.webauthn().finish_passkey_registration(
+            &session.public_key_credential,
+            &reg_state,
+        )
+    });
+    match verified {
         Ok(key) => {
             info!("verified key: {:?}", key);
-            // extracts the user information
-            let user_unique_id = item.get("userId")
-                .ok_or("missing userId in session")?
-                .as_s()
-                .or(Err("malformed userId in session"))?;
-            let user_info = item.get("userInfo")
-                .ok_or("missing userInfo in session")?
-                .as_m()
-                .or(Err("malformed userInfo in session"))?;
-            let username = user_info.get("username")
-                .ok_or("missing username in session")?
-                .as_s()
-                .or(Err("malformed username in session"))?;
-            let display_name = user_info.get("displayName")
-                .ok_or("missing displayName in session")?
-                .as_s()
-                .or(Err("malformed displayName in session"))?;
-            // generates a random password that is never used
-            let mut password = [0u8; 24];
-            getrandom::getrandom(&mut password)?;
-            let password = base64url.encode(&password);
-            // creates the Cognito user if not exists
-            let cognito_user = shared_state.cognito
-                .admin_create_user()
-                .user_pool_id(shared_state.user_pool_id.clone())
-                .username(user_unique_id.clone())
-                .user_attributes(UserAttributeType::builder()
-                    .name("preferred_username")
-                    .value(username.clone())
-                    .build()
-                    .unwrap())
-                .user_attributes(UserAttributeType::builder()
-                    .name("name")
-                    .value(display_name.clone())
-                    .build()
-                    .unwrap())
-                .message_action(MessageActionType::Suppress)
-                .temporary_password(password.clone())
-                .send()
-                .await?
-                .user
-                .ok_or("failed to create a new user")?;
-            let sub = cognito_user.attributes
-                .ok_or("missing Cognito user attributes")?
-                .into_iter()
-                .find_map(|a| a.value
-                    .map(|v| (a.name, v))
-                    .filter(|(name, _)| *name == "sub")
-                    .map(|(_, value)| value))
-                .ok_or("missing Cognito user sub attribute")?;
-            info!("created Cognito user: {}", sub);
-            // force-confirms the password
-            shared_state.cognito
-                .admin_set_user_password()
-                .user_pool_id(shared_state.user_pool_id.clone())
-                .username(user_unique_id.clone())
-                .password(password)
-                .permanent(true)
-                .send()
-                .await?;
-            // stores `key` in the credential table
-            // TODO: delete the Cognito user upon failure
-            let credential_id = base64url.encode(key.cred_id());
-            let created_at = DateTime::from(SystemTime::now())
-                .fmt(DateTimeFormat::DateTime)?;
-            info!("storing credential: {}", credential_id);
-            shared_state.dynamodb
-                .put_item()
-                .table_name(shared_state.credential_table_name.clone())
-                .item(
-                    "pk",
-                    AttributeValue::S(format!("user#{}", user_unique_id)),
-                )
-                .item(
-                    "sk",
-                    AttributeValue::S(format!("credential#{}", credential_id)),
-                )
-                .item("credentialId", AttributeValue::S(credential_id))
-                .item(
-                    "credential",
-                    AttributeValue::S(serde_json::to_string(&key)?),
-                )
-                .item("cognitoSub", AttributeValue::S(sub))
-                .item("createdAt", AttributeValue::S(created_at.clone()))
-                .item("updatedAt", AttributeValue::S(created_at))
-                .send()
-                .await?;
+            if !(PasskeyProperties::of(&key)?.user_verified && user_verified_in(&session)) {
+                error!("user verification required but not performed");
+                return Err(ApiError::UserVerificationRequired.into());
+            }
+            warn_authenticator_attachment_mismatch(&item, &session)?;
+            check_algorithm(&shared_state, &key)?;
+            if !satisfies_resident_key_requirement(
+                shared_state.resident_key,
+                discoverable(&session),
+            ) {
+                error!("resident key required but not created");
+                return Err(ApiError::VerificationFailed("resident key required").into());
+            }
+            if !satisfies_attestation_conveyance(
+                shared_state.attestation,
+                is_attested(&key)?,
+            ) {
+                error!("direct attestation required but not provided");
+                return Err(ApiError::VerificationFailed("attestation required").into());
+            }
+            let authenticator = lookup_authenticator(&shared_state, &session).await?;
+            let stored = match kind {
+                RegistrationKind::Upgrade => store_upgraded_user(
+                    &shared_state,
+                    &tenant,
+                    kind,
+                    &item,
+                    key.cred_id(),
+                    &key,
+                    &session,
+                    &extensions,
+                    authenticator.as_ref(),
+                    client,
+                ).await?,
+                kind if kind.is_existing_user() => add_existing_user_credential(
+                    &shared_state,
+                    &tenant,
+                    kind,
+                    &item,
+                    key.cred_id(),
+                    &key,
+                    &session,
+                    &extensions,
+                    authenticator.as_ref(),
+                    client,
+                ).await?,
+                _ => store_credential(
+                    &shared_state,
+                    &tenant,
+                    kind,
+                    &item,
+                    key.cred_id(),
+                    &key,
+                    &session,
+                    &extensions,
+                    authenticator.as_ref(),
+                    client,
+                ).await?,
+            };
+            if let Some(res) = stored {
+                return Ok(res);
+            }
+            remember_registration_result(
+                &shared_state,
+                &tenant,
+                kind,
+                &idempotency_key,
+                &session,
+            ).await?;
+            shared_state.metrics.count("registration_succeeded");
         }
         Err(e) => {
             error!("failed to finish registration: {}", e);
-            return Err("failed to finish registration".into());
+            shared_state.metrics.count("verification_failed");
+            return Err(ApiError::VerificationFailed("failed to finish registration").into());
+        }
+    };
+
+    // an existing user keeps the remaining recovery codes
+    let recovery_codes = match kind {
+        kind if kind.is_existing_user() => None,
+        _ => Some(issue_recovery_codes(&shared_state, &item.user_id).await?),
+    };
+    registration_finished(&FinishRegistrationResult {
+        recovery_codes,
+        cred_props: cred_props(&session),
+        large_blob: extensions.large_blob,
+        prf: extensions.prf,
+    })
+}
+
+#[instrument(skip_all, fields(session_id))]
+async fn start_security_key_registration(
+    shared_state: Arc<SharedState>,
+    tenant: Arc<Tenant>,
+    user_info: NewUserInfo,
+    client: ClientInfo,
+) -> Result<Response<Body>, Error> {
+    info!(
+        "start_security_key_registration: {} {:?}",
+        redact(&user_info.username),
+        user_info.authenticator_attachment,
+    );
+
+    let attestation_ca_list = shared_state.attestation_ca_list.clone()
+        .ok_or(ApiError::NotConfigured("security key registration is not configured"))?;
+    let authenticator_attachment = resolve_authenticator_attachment(
+        shared_state.authenticator_attachment,
+        user_info.authenticator_attachment,
+    )?;
+    let (user_unique_id, exclude_credentials) =
+        resolve_user(&shared_state, &tenant, &user_info.username).await?;
+    let hints = resolve_hints(shared_state.hints.as_deref(), user_info.hints.as_deref())
+        .unwrap_or_else(|| vec![PublicKeyCredentialHint::SecurityKey]);
+
+    let res = match tenant.webauthn().start_securitykey_registration(
+        user_unique_id,
+        &user_info.username,
+        &user_info.display_name,
+        exclude_credentials,
+        Some(attestation_ca_list),
+        authenticator_attachment,
+    ) {
+        Ok((mut ccr, reg_state)) => {
+            restrict_algorithms(&shared_state, &mut ccr)?;
+            // caches `reg_state`
+            let session_id = put_registration_session(
+                &shared_state,
+                &tenant,
+                RegistrationKind::SecurityKey,
+                user_unique_id,
+                user_info,
+                serde_json::to_string(&reg_state)?,
+                authenticator_attachment,
+                shared_state.client_binding.bind(&client),
+            ).await?;
+            Span::current().record("session_id", field::display(truncate(&session_id)));
+            shared_state.metrics.count("registration_started");
+            if let Some(selection) = ccr.public_key.authenticator_selection.as_mut() {
+            }
+            ccr.public_key.extensions
+                .get_or_insert_with(Default::default)
+                .cred_props = Some(true);
+            start_registration_body(&shared_state, session_id, ccr, Some(&hints))?
+        }
+        Err(e) => {
+            error!("failed to start security key registration: {}", e);
+            return Err(ApiError::internal("failed to start security key registration").into());
         }
     };
 
     Ok(Response::builder()
         .status(StatusCode::OK)
-        .header("Content-Type", "text/plain")
-        .body(().into())?)
+        .header("Content-Type", "application/json")
+        .body(res.into())?)
+}
+
+#[instrument(skip_all, fields(session_id = %truncate(&session.session_id)))]
+async fn finish_security_key_registration(
+    shared_state: Arc<SharedState>,
+    tenant: Arc<Tenant>,
+    session: FinishRegistrationSession,
+    extensions: ExtensionOutputs,
+    client: ClientInfo,
+    idempotency_key: String,
+) -> Result<Response<Body>, Error> {
+    info!("finish_security_key_registration: {}", truncate(&session.session_id));
+    check_session_id(&shared_state, &session.session_id).await?;
+
+    let Some(item) = pop_registration_session(
+        &shared_state,
+        &tenant,
+        RegistrationKind::SecurityKey,
+        &session.session_id,
+    ).await? else {
+        // the client may be retrying a finished registration
+        return replay_registration_result(
+            &shared_state,
+            &tenant,
+            RegistrationKind::SecurityKey,
+            &idempotency_key,
+            &session,
+        ).await;
+    };
+    check_client_binding(&shared_state, &item, &client)?;
+    let reg_state: SecurityKeyRegistration = registration_state(&item)?;
+
+    // verifies the request including the attestation
+    let verified = info_span!("verify_registration").in_scope(|| {
+        tenant.webauthn().finish_securitykey_registration(
+            &session.public_key_credential,
+            &reg_state,
+        )
+    });
+    match verified {
+        Ok(key) => {
+            info!("verified security key: {:?}", key);
+            if !(PasskeyProperties::of(&key)?.user_verified && user_verified_in(&session)) {
+                error!("user verification required but not performed");
+                return Err(ApiError::UserVerificationRequired.into());
+            }
+            warn_authenticator_attachment_mismatch(&item, &session)?;
+            check_algorithm(&shared_state, &key)?;
+            let authenticator = lookup_authenticator(&shared_state, &session).await?;
+            if let Some(res) = store_credential(
+                &shared_state,
+                &tenant,
+                RegistrationKind::SecurityKey,
+                &item,
+                key.cred_id(),
+                &key,
+                &session,
+                &extensions,
+                authenticator.as_ref(),
+                client,
+            ).await? {
+                return Ok(res);
+            }
+            remember_registration_result(
+                &shared_state,
+                &tenant,
+                RegistrationKind::SecurityKey,
+                &idempotency_key,
+                &session,
+            ).await?;
+            shared_state.metrics.count("registration_succeeded");
+        }
+        Err(e) => {
+            error!("failed to finish security key registration: {}", e);
+            shared_state.metrics.count("verification_failed");
+            return Err(ApiError::VerificationFailed(
+                "failed to finish security key registration",
+            ).into());
+        }
+    };
+
+    let recovery_codes = issue_recovery_codes(&shared_state, &item.user_id).await?;
+    registration_finished(&FinishRegistrationResult {
+        recovery_codes: Some(recovery_codes),
+        cred_props: cred_props(&session),
+        large_blob: extensions.large_blob,
+        prf: extensions.prf,
+    })
+}
+
+#[instrument(skip_all, fields(session_id))]
+async fn start_recovery(
+    shared_state: Arc<SharedState>,
+    tenant: Arc<Tenant>,
+    request: RecoveryRequest,
+    client: ClientInfo,
+) -> Result<Response<Body>, Error> {
+    info!("start_recovery: {}", redact(&request.username));
+
+    let authenticator_attachment = resolve_authenticator_attachment(
+        shared_state.authenticator_attachment,
+        request.authenticator_attachment,
+    )?;
+    // an unknown user and a wrong code are indistinguishable
+    let Some((user_handle, credentials)) = shared_state.users
+        .list_credentials_by_username(&tenant.qualify_username(&request.username))
+        .await? else
+    {
+        error!("recovery of unknown user");
+        shared_state.metrics.count("recovery_code_rejected");
+        return invalid_recovery_code();
+    };
+    let code_hash = hash_recovery_code(&user_handle, &request.recovery_code);
+    if !shared_state.users.consume_recovery_code(&user_handle, &code_hash).await? {
+        error!("invalid recovery code for {}", redact(&user_handle));
+        shared_state.metrics.count("recovery_code_rejected");
+        return invalid_recovery_code();
+    }
+    if let Some(audit_log) = shared_state.audit_log.as_ref() {
+        audit_log.record_best_effort(AuditEvent {
+            event_type: AuditEventType::RecoveryCodeUsed,
+            user_handle: user_handle.clone(),
+            credential_id: None,
+            client: client.clone(),
+            detail: None,
+        }).await;
+    }
+
+    begin_existing_user_registration(
+        &shared_state,
+        &tenant,
+        RegistrationKind::Recovery,
+        &user_handle,
+        credentials,
+        authenticator_attachment,
+        &client,
+    ).await
+}
+
+#[instrument(skip_all, fields(session_id))]
+async fn start_additional_registration(
+    shared_state: Arc<SharedState>,
+    tenant: Arc<Tenant>,
+    user_handle: String,
+    request: AdditionalPasskeyRequest,
+    client: ClientInfo,
+) -> Result<Response<Body>, Error> {
+    info!("start_additional_registration: {}", redact(&user_handle));
+
+    let authenticator_attachment = resolve_authenticator_attachment(
+        shared_state.authenticator_attachment,
+        request.authenticator_attachment,
+    )?;
+    // the existing credentials are excluded
+    let credentials = shared_state.users
+        .list_credentials(&user_handle)
+        .await?;
+
+    begin_existing_user_registration(
+        &shared_state,
+        &tenant,
+        RegistrationKind::Additional,
+        &user_handle,
+        credentials,
+        authenticator_attachment,
+        &client,
+    ).await
+}
+
+#[instrument(skip_all, fields(session_id))]
+async fn start_password_upgrade(
+    shared_state: Arc<SharedState>,
+    tenant: Arc<Tenant>,
+    user_handle: String,
+    request: AdditionalPasskeyRequest,
+    client: ClientInfo,
+) -> Result<Response<Body>, Error> {
+    info!("start_password_upgrade: {}", redact(&user_handle));
+
+    let authenticator_attachment = resolve_authenticator_attachment(
+        shared_state.authenticator_attachment,
+        request.authenticator_attachment,
+    )?;
+    if shared_state.users.get_user(&user_handle).await?.is_some() {
+        error!("user already has a passkey");
+        return conflict("user_exists", "user already has a passkey; use passkeys/start");
+    }
+    // the Cognito username becomes the user handle of the passkey
+    let Ok(user_unique_id) = parse_user_handle(&user_handle) else {
+        error!("Cognito username is not a user handle");
+        return Err(ApiError::NotAllowed("username is not a user handle").into());
+    };
+    let attributes = cognito_user_attributes(&shared_state, &user_handle).await?;
+    let username = user_attribute(&attributes, "preferred_username")
+        .or_else(|| user_attribute(&attributes, "email"))
+        .ok_or(ApiError::NotAllowed("user has no username"))?;
+    if shared_state.users.find_user_handle(username).await?.is_some() {
+        error!("username taken by another user");
+        return conflict("username_taken", "username already registered");
+    }
+    let username = tenant.unqualify_username(username).to_string();
+    let display_name = user_attribute(&attributes, "name")
+        .and_then(|name| sanitize_display_name(name, shared_state.max_display_name_length))
+        .unwrap_or_else(|| username.clone());
+
+    begin_passkey_registration(
+        &shared_state,
+        &tenant,
+        RegistrationKind::Upgrade,
+        user_unique_id,
+        NewUserInfo {
+            username,
+            display_name,
+            authenticator_attachment,
+            hints: None,
+        },
+        None,
+        authenticator_attachment,
+        &client,
+    ).await
+}
+
+// finishes a password-to-passkey upgrade and disables the password if
+// requested.
+//
+// the password stays intact unless the passkey is stored.
+#[allow(clippy::too_many_arguments)]
+async fn finish_password_upgrade(
+    shared_state: Arc<SharedState>,
+    tenant: Arc<Tenant>,
+    session: FinishRegistrationSession,
+    options: PasswordUpgradeOptions,
+    extensions: ExtensionOutputs,
+    client: ClientInfo,
+    idempotency_key: String,
+    user_handle: String,
+) -> Result<Response<Body>, Error> {
+    let res = finish_registration(
+        shared_state.clone(),
+        tenant,
+        RegistrationKind::Upgrade,
+        session,
+        extensions,
+        client,
+        idempotency_key,
+        Some(user_handle.clone()),
+    ).await?;
+    if options.disable_password && res.status().is_success() {
+        disable_password(&shared_state, &user_handle).await?;
+    }
+    Ok(res)
+}
+
+// replaces the password of a Cognito user with a random one that is never
+// disclosed.
+#[instrument(skip_all)]
+async fn disable_password(
+    shared_state: &SharedState,
+    user_handle: &str,
+) -> Result<(), Error> {
+    shared_state.cognito
+        .admin_set_user_password()
+        .user_pool_id(shared_state.user_pool_id.clone())
+        .username(user_handle)
+        .password(random_password()?)
+        .permanent(true)
+        .send()
+        .await?;
+    info!("disabled password: {}", redact(user_handle));
+    shared_state.metrics.count("password_disabled");
+    Ok(())
+}
+
+// obtains the attributes of a Cognito user.
+async fn cognito_user_attributes(
+    shared_state: &SharedState,
+    user_handle: &str,
+) -> Result<Vec<UserAttributeType>, Error> {
+    Ok(shared_state.cognito
+        .admin_get_user()
+        .user_pool_id(shared_state.user_pool_id.clone())
+        .username(user_handle)
+        .send()
+        .await?
+        .user_attributes
+        .unwrap_or_default())
+}
+
+// returns the value of a given attribute of a Cognito user.
+fn user_attribute<'a>(attributes: &'a [UserAttributeType], name: &str) -> Option<&'a str> {
+    attributes.iter()
+        .find(|a| a.name == name)
+        .and_then(|a| a.value.as_deref())
+}
+
+#[instrument(skip_all)]
+async fn request_recovery_link(
+    shared_state: Arc<SharedState>,
+    tenant: Arc<Tenant>,
+    request: RecoveryLinkRequest,
+    client: ClientInfo,
+) -> Result<Response<Body>, Error> {
+    info!("request_recovery_link: {}", redact(&request.username));
+
+    let mailer = shared_state.recovery_mailer.as_ref()
+        .ok_or(ApiError::NotConfigured("email recovery is not configured"))?;
+    // responds the same whether the user exists or not
+    if !is_email(&request.username) {
+        info!("username is not an email address");
+        return recovery_link_accepted();
+    }
+    let Some(user_handle) = shared_state.users
+        .find_user_handle(&tenant.qualify_username(&request.username))
+        .await? else
+    {
+        info!("recovery link for unknown user");
+        return recovery_link_accepted();
+    };
+
+    let token = generate_recovery_token()?;
+    let token_hash = hash_recovery_token(&token);
+    let ttl = DateTime::from(SystemTime::now()).secs() + mailer.link_ttl();
+    let link = RecoveryLinkItem {
+        ttl,
+        user_handle: user_handle.clone(),
+    };
+    if !put_recovery_link(&shared_state.sessions, &tenant, &token_hash, link).await? {
+        error!("recovery token collision");
+        return Err(ApiError::internal("failed to generate a unique recovery token").into());
+    }
+    mailer.send_recovery_link(&request.username, &token).await?;
+    shared_state.metrics.count("recovery_link_sent");
+    if let Some(audit_log) = shared_state.audit_log.as_ref() {
+        audit_log.record_best_effort(AuditEvent {
+            event_type: AuditEventType::RecoveryLinkSent,
+            user_handle,
+            credential_id: None,
+            client,
+            detail: None,
+        }).await;
+    }
+
+    recovery_link_accepted()
+}
+
+#[instrument(skip_all, fields(session_id))]
+async fn start_recovery_link(
+    shared_state: Arc<SharedState>,
+    tenant: Arc<Tenant>,
+    session: RecoveryLinkSession,
+    client: ClientInfo,
+) -> Result<Response<Body>, Error> {
+    info!("start_recovery_link");
+
+    let authenticator_attachment = resolve_authenticator_attachment(
+        shared_state.authenticator_attachment,
+        session.authenticator_attachment,
+    )?;
+    // consumes the link so that it cannot be used twice
+    let token_hash = hash_recovery_token(&session.token);
+    let item = take_recovery_link(
+        &shared_state.sessions,
+        &tenant,
+        &token_hash,
+        DateTime::from(SystemTime::now()).secs(),
+    ).await?;
+    let Some(item) = item.and_then(TakenSession::valid) else
+    {
+        error!("unknown, used, or expired recovery link");
+        shared_state.metrics.count("recovery_link_rejected");
+        return invalid_recovery_link();
+    };
+    if let Some(audit_log) = shared_state.audit_log.as_ref() {
+        audit_log.record_best_effort(AuditEvent {
+            event_type: AuditEventType::RecoveryLinkUsed,
+            user_handle: item.user_handle.clone(),
+            credential_id: None,
+            client: client.clone(),
+            detail: None,
+        }).await;
+    }
+    let credentials = shared_state.users
+        .list_credentials(&item.user_handle)
+        .await?;
+
+    begin_existing_user_registration(
+        &shared_state,
+        &tenant,
+        RegistrationKind::Recovery,
+        &item.user_handle,
+        credentials,
+        authenticator_attachment,
+        &client,
+    ).await
+}
+
+// starts registration of a new passkey for an existing user.
+//
+// fails if the user has as many credentials as the limit unless the user is
+// recovering the account.
+async fn begin_existing_user_registration(
+    shared_state: &SharedState,
+    tenant: &Tenant,
+    kind: RegistrationKind,
+    user_handle: &str,
+    credentials: Vec<CredentialItem>,
+    authenticator_attachment: Option<AuthenticatorAttachment>,
+    client: &ClientInfo,
+) -> Result<Response<Body>, Error> {
+    if let Some(limit) = shared_state.credential_limit {
+        if !matches!(kind, RegistrationKind::Recovery) {
+            limit.check(&credentials)?;
+        }
+    }
+    let user = shared_state.users
+        .get_user(user_handle)
+        .await?
+        .ok_or(ApiError::Storage("missing user in the database"))?;
+
+    begin_passkey_registration(
+        shared_state,
+        tenant,
+        kind,
+        parse_user_handle(user_handle)?,
+        NewUserInfo {
+            username: tenant.unqualify_username(&user.username).into(),
+            display_name: user.display_name,
+            authenticator_attachment,
+            hints: None,
+        },
+        Some(exclude_credential_ids(credentials)?),
+        authenticator_attachment,
+        client,
+    ).await
+}
+
+// counts a registration start against the quota of the tenant, and the rate
+// limits per source IP and per username.
+//
+// the limit per username is scoped to the tenant.
+// returns a 429 response if any limit is exceeded.
+#[instrument(skip_all)]
+async fn check_rate_limits(
+    shared_state: &SharedState,
+    tenant: &Tenant,
+    event: &Request,
+    username: &str,
+) -> Result<Option<Response<Body>>, Error> {
+    let now = DateTime::from(SystemTime::now()).secs();
+    if let Some(retry_after) = tenant.hit_quota(
+        &shared_state.dynamodb,
+        &shared_state.session_table_name,
+        QuotaKind::Registration,
+        now,
+    ).await? {
+        return Ok(Some(too_many_requests(retry_after)?));
+    }
+    let mut checks = Vec::with_capacity(2);
+    if let Some(rate_limit) = shared_state.rate_limit_per_ip.as_ref() {
+        match source_ip(event) {
+            Some(ip) => checks.push(("ip", ip, rate_limit)),
+            None => error!("no source IP to rate limit"),
+        }
+    }
+    if let Some(rate_limit) = shared_state.rate_limit_per_username.as_ref() {
+        checks.push(("username", tenant.qualify_username(username), rate_limit));
+    }
+    for (scope, key, rate_limit) in checks {
+        if let Some(retry_after) = hit(
+            &shared_state.dynamodb,
+            &shared_state.session_table_name,
+            scope,
+            &key,
+            rate_limit,
+            now,
+        ).await? {
+            error!("rate limit per {} exceeded", scope);
+            return Ok(Some(too_many_requests(retry_after)?));
+        }
+    }
+    Ok(None)
+}
+
+// resolves the user ID and the credentials to be excluded.
+//
+// generates a new user ID for a new user, and for an existing user in the
+// protection mode against user enumeration.
+// fails if an existing user has as many credentials as the limit, except in
+// the protection mode, where the registration never adds a credential to an
+// existing user.
+#[instrument(skip_all)]
+async fn resolve_user(
+    shared_state: &SharedState,
+    tenant: &Tenant,
+    username: &str,
+) -> Result<(Uuid, Option<Vec<CredentialID>>), Error> {
+    // resolves the existing user and credentials via the username index
+    let existing_user = shared_state.users
+        .list_credentials_by_username(&tenant.qualify_username(username))
+        .await?;
+    // an existing user looks like a new user in the protection mode
+    let existing_user = existing_user
+        .filter(|_| shared_state.enumeration_protection.is_none());
+    if let (Some(limit), Some((_, credentials))) =
+        (shared_state.credential_limit, existing_user.as_ref())
+    {
+        limit.check(credentials)?;
+    }
+
+    // obtains the user ID or generates a new one for a new user
+    let user_unique_id = existing_user.as_ref()
+        .map(|(user_handle, _)| parse_user_handle(user_handle))
+        .transpose()?
+        .unwrap_or_else(Uuid::new_v4);
+
+    // existing credentials for the user to be excluded
+    let exclude_credentials: Option<Vec<CredentialID>> = existing_user
+        .map(|(user_handle, credentials)| {
+            info!("excluding credentials of {}", redact(&user_handle));
+            exclude_credential_ids(credentials)
+        })
+        .transpose()?;
+
+    Ok((user_unique_id, exclude_credentials))
+}
+
+// parses a "base64url"-encoded user handle into the unique user ID.
+fn parse_user_handle(user_handle: &str) -> Result<Uuid, Error> {
+    let id = base64url.decode(user_handle)
+        .or(Err(ApiError::Storage("malformed user handle in the database")))?;
+    Ok(Uuid::from_slice(&id)
+        .or(Err(ApiError::Storage("malformed user handle in the database")))?)
+}
+
+// extracts the IDs of credentials to be excluded from a new registration.
+fn exclude_credential_ids(
+    credentials: Vec<CredentialItem>,
+) -> Result<Vec<CredentialID>, Error> {
+    credentials.into_iter()
+        .map(|c| {
+            // as far as I know, we have to use serde::Deserialize
+            // to build HumanBinaryData from a base64-encoded string
+            serde_json::from_value(serde_json::Value::String(c.credential_id))
+                .map_err(|_| Error::from(
+                    ApiError::Storage("malformed credentialId in the database"),
+                ))
+        })
+        .collect()
+}
+
+// puts a new registration session and returns the session ID.
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip_all)]
+async fn put_registration_session(
+    shared_state: &SharedState,
+    tenant: &Tenant,
+    kind: RegistrationKind,
+    user_unique_id: Uuid,
+    user_info: NewUserInfo,
+    state: String,
+    authenticator_attachment: Option<AuthenticatorAttachment>,
+    client_binding: ClientBinding,
+) -> Result<String, Error> {
+    let user_id = base64url.encode(user_unique_id.into_bytes());
+    let ttl = shared_state.challenge_timeout
+        .session_ttl(DateTime::from(SystemTime::now()).secs());
+    let user_info = RegistrationUserInfo {
+        username: user_info.username,
+        display_name: user_info.display_name,
+    };
+    let authenticator_attachment = authenticator_attachment
+        .map(|a| authenticator_attachment_name(a).into());
+    let data_key = match shared_state.session_encryption.as_ref() {
+        Some(encryption) => Some(encryption.generate_data_key().await?),
+        None => None,
+    };
+    // never overwrites an existing session; regenerates the session ID upon
+    // collision
+    for _ in 0..MAX_SESSION_ID_ATTEMPTS {
+        let session_id = shared_state.session_ids.generate().await?;
+        info!("putting {:?} registration session: {}", kind, truncate(&session_id));
+        let key = kind.session_key(&session_id);
+        let key = tenant.scope(&key);
+        let contents = match data_key.as_ref() {
+            Some(data_key) => seal_registration_contents(data_key, &key.pk(), &user_info, &state)?,
+            None => RegistrationContents::Plain {
+                user_info: user_info.clone(),
+                state: state.clone(),
+            },
+        };
+        let session = RegistrationSessionItem {
+            ttl,
+            user_id: user_id.clone(),
+            authenticator_attachment: authenticator_attachment.clone(),
+            client_binding: client_binding.clone(),
+            contents,
+        };
+        if put_registration_session(&shared_state.sessions, key, session).await? {
+            return Ok(session_id);
+        }
+        error!("session ID collision: {}", truncate(&session_id));
+    }
+    Err(ApiError::internal("failed to generate a unique session ID").into())
+}
+
+// rejects a forged or truncated session ID before looking up the session
+// table.
+async fn check_session_id(shared_state: &SharedState, session_id: &str) -> Result<(), Error> {
+    if !shared_state.session_ids.verify(session_id).await? {
+        error!("forged session ID: {}", truncate(session_id));
+        return Err(ApiError::SessionExpired("invalid session ID").into());
+    }
+    Ok(())
+}
+
+// registration session opened by `pop_registration_session`.
+struct RegistrationSession {
+    // "base64url"-encoded unique user ID.
+    user_id: String,
+    // user information.
+    user_info: RegistrationUserInfo,
+    // serialized registration state.
+    state: String,
+    // requested authenticator attachment.
+    authenticator_attachment: Option<String>,
+    // client to which the session is bound.
+    client_binding: ClientBinding,
+}
+
+// pops a registration session.
+//
+// returns `None` if the session does not exist; e.g., deleted by the TTL or
+// already finished.
+// fails if the session has expired.
+#[instrument(skip_all)]
+async fn pop_registration_session(
+    shared_state: &SharedState,
+    tenant: &Tenant,
+    kind: RegistrationKind,
+    session_id: &str,
+) -> Result<Option<RegistrationSession>, Error> {
+    let key = kind.session_key(session_id);
+    let key = tenant.scope(&key);
+    let now = DateTime::from(SystemTime::now()).secs();
+    let item = match take_registration_session(&shared_state.sessions, key, now).await? {
+        Some(TakenSession::Valid(item)) => item,
+        Some(TakenSession::Expired) => {
+            shared_state.metrics.count("session_expired");
+            return Err(ApiError::SessionExpired("registration session expired").into());
+        }
+        None => return Ok(None),
+    };
+
+    // decrypts the sealed attributes
+    let (user_info, state) = match item.contents {
+        RegistrationContents::Plain { user_info, state } => (user_info, state),
+        RegistrationContents::Sealed { data_key, user_info, state } => {
+            let encryption = shared_state.session_encryption.as_ref()
+                .ok_or(ApiError::config("encrypted registration session but no KMS key"))?;
+            let data_key = encryption.decrypt_data_key(&data_key).await?;
+            open_registration_contents(&data_key, &key.pk(), &user_info, &state)?
+        }
+    };
+
+    Ok(Some(RegistrationSession {
+        user_id: item.user_id,
+        user_info,
+        state,
+        authenticator_attachment: item.authenticator_attachment,
+        client_binding: item.client_binding,
+    }))
+}
+
+// rejects a registration session finished by another client than the one to
+// which the session is bound; the challenge may have been relayed.
+fn check_client_binding(
+    shared_state: &SharedState,
+    item: &RegistrationSession,
+    client: &ClientInfo,
+) -> Result<(), Error> {
+    if !item.client_binding.matches(client) {
+        error!("registration session bound to another client");
+        shared_state.metrics.count("client_mismatch");
+        return Err(ApiError::SessionExpired("registration session of another client").into());
+    }
+    Ok(())
+}
+
+// returns the key to deduplicate retries of a finish request.
+//
+// the `Idempotency-Key` header takes precedence over the session ID.
+// the key is hashed so that its length is bounded.
+fn idempotency_key(event: &Request, session: &FinishRegistrationSession) -> String {
+    let key = event.headers()
+        .get("Idempotency-Key")
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+        .unwrap_or(&session.session_id);
+    base64url.encode(digest::digest(&digest::SHA256, key.as_bytes()))
+}
+
+// records the result of a finished registration so that retries can be
+// answered with the same outcome.
+#[instrument(skip_all)]
+async fn remember_registration_result(
+    shared_state: &SharedState,
+    tenant: &Tenant,
+    kind: RegistrationKind,
+    idempotency_key: &str,
+    session: &FinishRegistrationSession,
+) -> Result<(), Error> {
+    let result = RegistrationResultItem {
+        ttl: DateTime::from(SystemTime::now()).secs() + IDEMPOTENCY_RECORD_TTL,
+        session_id: session.session_id.clone(),
+        credential_id: session.public_key_credential.id.clone(),
+    };
+    let key = tenant.scope(&kind.result_key(idempotency_key));
+    if !put_registration_result(&shared_state.sessions, key, result).await? {
+        // the idempotency key has been used for another registration
+        warn!("registration result already recorded: {}", truncate(&session.session_id));
+    }
+    Ok(())
+}
+
+// answers a finish request whose session no longer exists.
+//
+// succeeds if the same credential has been registered in the same session;
+// i.e., the request is a retry of a finished registration.
+#[instrument(skip_all)]
+async fn replay_registration_result(
+    shared_state: &SharedState,
+    tenant: &Tenant,
+    kind: RegistrationKind,
+    idempotency_key: &str,
+    session: &FinishRegistrationSession,
+) -> Result<Response<Body>, Error> {
+    let result = get_registration_result(
+        &shared_state.sessions,
+        tenant.scope(&kind.result_key(idempotency_key)),
+        DateTime::from(SystemTime::now()).secs(),
+    ).await?;
+    let is_retry = result.is_some_and(|result| {
+        result.session_id == session.session_id
+            && result.credential_id == session.public_key_credential.id
+    });
+    if !is_retry {
+        // the session may have been deleted by the TTL
+        shared_state.metrics.count("session_not_found");
+        return Err(ApiError::SessionExpired("expired or wrong registration session").into());
+    }
+    info!("replaying finished registration: {}", truncate(&session.session_id));
+    // recovery codes are never shown twice
+    registration_finished(&FinishRegistrationResult::default())
+}
+
+// serializes the beginning of a registration session with the enabled
+// extension inputs, hints, and the timeout of the session.
+fn start_registration_body(
+    shared_state: &SharedState,
+    session_id: String,
+    mut ccr: CreationChallengeResponse,
+    hints: Option<&[PublicKeyCredentialHint]>,
+) -> Result<String, Error> {
+    ccr.public_key.timeout = Some(shared_state.challenge_timeout.as_millis());
+    let mut body = serde_json::to_value(&StartRegistrationSession {
+        session_id,
+        credential_creation_options: ccr,
+    })?;
+    shared_state.extension_policy
+        .registration_inputs()
+        .merge_into(&mut body["credentialCreationOptions"]);
+    if let Some(hints) = hints {
+        merge_hints_into(hints, &mut body["credentialCreationOptions"]);
+    }
+    Ok(serde_json::to_string(&body)?)
+}
+
+// creates a 200 response of a finished registration.
+fn registration_finished(
+    result: &FinishRegistrationResult,
+) -> Result<Response<Body>, Error> {
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .body(serde_json::to_string(result)?.into())?)
+}
+
+// extracts the registration state from a registration session.
+fn registration_state<T>(item: &RegistrationSession) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+{
+    Ok(serde_json::from_str(&item.state)?)
+}
+
+// logs if the authenticator attachment reported by the client does not
+// satisfy the one requested by the registration session.
+//
+// never rejects the registration, because the client reports the attachment
+// without a signature and a malicious client can claim any.
+fn warn_authenticator_attachment_mismatch(
+    item: &RegistrationSession,
+    session: &FinishRegistrationSession,
+) -> Result<(), Error> {
+    let requested_attachment = item.authenticator_attachment.as_ref()
+        .map(|a| parse_authenticator_attachment(a))
+        .transpose()?;
+    if !satisfies_authenticator_attachment(
+        requested_attachment,
+        session.authenticator_attachment,
+    ) {
+        warn!(
+            "authenticator attachment mismatch: {:?} vs {:?}",
+            requested_attachment,
+            session.authenticator_attachment,
+        );
+    }
+    Ok(())
+}
+
+// removes the public key algorithms disallowed by the allowlist from
+// creation options.
+//
+// fails if no algorithm remains, because the client could create no
+// credential.
+fn restrict_algorithms(
+    shared_state: &SharedState,
+    ccr: &mut CreationChallengeResponse,
+) -> Result<(), Error> {
+    if let Some(allowlist) = shared_state.algorithm_allowlist.as_ref() {
+        allowlist.filter(&mut ccr.public_key.pub_key_cred_params);
+        if ccr.public_key.pub_key_cred_params.is_empty() {
+            error!("no allowed public key algorithm is supported");
+            return Err(ApiError::internal("no allowed public key algorithm").into());
+        }
+    }
+    Ok(())
+}
+
+// checks if the public key algorithm of a verified credential is allowed.
+//
+// the Webauthn library accepts any algorithm it offered, and a client may
+// ignore the restricted `pubKeyCredParams`.
+fn check_algorithm(
+    shared_state: &SharedState,
+    key: &impl Serialize,
+) -> Result<(), Error> {
+    if let Some(allowlist) = shared_state.algorithm_allowlist.as_ref() {
+        let algorithm = algorithm_of(key)?;
+        if !allowlist.allows(algorithm) {
+            error!("public key algorithm not allowed: {}", algorithm);
+            shared_state.metrics.count("algorithm_rejected");
+            return Err(ApiError::NotAllowed("public key algorithm not allowed").into());
+        }
+    }
+    Ok(())
+}
+
+// creates the Cognito user and stores a verified credential.
+//
+// the user and credential items are written in a single transaction, and the
+// Cognito user is deleted if the transaction fails.
+// returns a 409 response if the user or credential already exists, or a
+// concurrent registration conflicted.
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip_all)]
+async fn store_credential(
+    shared_state: &SharedState,
+    tenant: &Tenant,
+    kind: RegistrationKind,
+    item: &RegistrationSession,
+    credential_id: &CredentialID,
+    credential: &impl Serialize,
+    session: &FinishRegistrationSession,
+    extensions: &ExtensionOutputs,
+    authenticator: Option<&AuthenticatorMetadata>,
+    client: ClientInfo,
+) -> Result<Option<Response<Body>>, Error> {
+    let properties = PasskeyProperties::of(credential)?;
+    let attestation = requested_attestation(shared_state, kind, credential)?;
+    let credential = serde_json::to_string(credential)?;
+    // extracts the user information
+    let user_unique_id = &item.user_id;
+    let username = &tenant.qualify_username(&item.user_info.username);
+    let display_name = &item.user_info.display_name;
+    // an existing user was given a new user ID in the protection mode against
+    // user enumeration
+    if shared_state.enumeration_protection.is_some()
+        && shared_state.users.find_user_handle(username).await?.is_some()
+    {
+        error!("username already registered: {}", redact(username));
+        return create_user_failed(CreateUserError::UserExists);
+    }
+    // generates a random password that is never used
+    let password = random_password()?;
+    // creates the Cognito user if not exists
+    let cognito_user = shared_state.cognito
+        .admin_create_user()
+        .user_pool_id(shared_state.user_pool_id.clone())
+        .username(user_unique_id.clone())
+        .user_attributes(UserAttributeType::builder()
+            .name("preferred_username")
+            .value(username.clone())
+            .build()
+            .unwrap())
+        .user_attributes(UserAttributeType::builder()
+            .name("name")
+            .value(display_name.clone())
+            .build()
+            .unwrap())
+        .message_action(MessageActionType::Suppress)
+        .temporary_password(password.clone())
+        .send()
+        .await?
+        .user
+        .ok_or(ApiError::internal("failed to create a new user"))?;
+    let sub = cognito_user.attributes
+        .ok_or(ApiError::internal("missing Cognito user attributes"))?
+        .into_iter()
+        .find_map(|a| a.value
+            .map(|v| (a.name, v))
+            .filter(|(name, _)| *name == "sub")
+            .map(|(_, value)| value))
+        .ok_or(ApiError::internal("missing Cognito user sub attribute"))?;
+    info!("created Cognito user: {}", redact(&sub));
+    // force-confirms the password
+    shared_state.cognito
+        .admin_set_user_password()
+        .user_pool_id(shared_state.user_pool_id.clone())
+        .username(user_unique_id.clone())
+        .password(password)
+        .permanent(true)
+        .send()
+        .await?;
+    // stores the user and credential in the credential table
+    let credential_id = base64url.encode(credential_id);
+    let created_at = DateTime::from(SystemTime::now())
+        .fmt(DateTimeFormat::DateTime)?;
+    info!("storing credential: {}", truncate(&credential_id));
+    let credential_item = new_credential_item(
+        kind,
+        item,
+        username.clone(),
+        credential_id.clone(),
+        credential,
+        &properties,
+        sub.clone(),
+        session,
+        extensions,
+        authenticator,
+        attestation,
+        &client,
+        created_at.clone(),
+    );
+    let user_item = UserItem {
+        user_handle: user_unique_id.clone(),
+        username: username.clone(),
+        display_name: display_name.clone(),
+        cognito_sub: sub,
+        created_at,
+    };
+    if let Err(e) = shared_state.users.create_user(user_item, credential_item).await {
+        error!("failed to store credential: {}", e);
+        // the Cognito user must not remain without the user item
+        shared_state.cognito
+            .admin_delete_user()
+            .user_pool_id(shared_state.user_pool_id.clone())
+            .username(user_unique_id.clone())
+            .send()
+            .await?;
+        return create_user_failed(e);
+    }
+    record_registration(
+        shared_state,
+        tenant,
+        kind,
+        user_unique_id,
+        credential_id,
+        client,
+    ).await?;
+    Ok(None)
+}
+
+// stores the user and the first credential of a password user upgrading to a
+// passkey.
+//
+// the Cognito user already exists and is never deleted here.
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip_all)]
+async fn store_upgraded_user(
+    shared_state: &SharedState,
+    tenant: &Tenant,
+    kind: RegistrationKind,
+    item: &RegistrationSession,
+    credential_id: &CredentialID,
+    credential: &impl Serialize,
+    session: &FinishRegistrationSession,
+    extensions: &ExtensionOutputs,
+    authenticator: Option<&AuthenticatorMetadata>,
+    client: ClientInfo,
+) -> Result<Option<Response<Body>>, Error> {
+    let properties = PasskeyProperties::of(credential)?;
+    let attestation = requested_attestation(shared_state, kind, credential)?;
+    let credential = serde_json::to_string(credential)?;
+    let username = tenant.qualify_username(&item.user_info.username);
+    let attributes = cognito_user_attributes(shared_state, &item.user_id).await?;
+    let sub = user_attribute(&attributes, "sub")
+        .ok_or(ApiError::internal("missing Cognito user sub attribute"))?
+        .to_string();
+    let credential_id = base64url.encode(credential_id);
+    let created_at = DateTime::from(SystemTime::now())
+        .fmt(DateTimeFormat::DateTime)?;
+    info!("storing upgraded credential: {}", truncate(&credential_id));
+    let credential_item = new_credential_item(
+        kind,
+        item,
+        username.clone(),
+        credential_id.clone(),
+        credential,
+        &properties,
+        sub.clone(),
+        session,
+        extensions,
+        authenticator,
+        attestation,
+        &client,
+        created_at.clone(),
+    );
+    let user_item = UserItem {
+        user_handle: item.user_id.clone(),
+        username,
+        display_name: item.user_info.display_name.clone(),
+        cognito_sub: sub,
+        created_at,
+    };
+    if let Err(e) = shared_state.users.create_user(user_item, credential_item).await {
+        error!("failed to store upgraded credential: {}", e);
+        return create_user_failed(e);
+    }
+    record_registration(
+        shared_state,
+        tenant,
+        kind,
+        &item.user_id,
+        credential_id,
+        client,
+    ).await?;
+    Ok(None)
+}
+
+// returns the response to a failure to store a new user.
+fn create_user_failed(e: CreateUserError) -> Result<Option<Response<Body>>, Error> {
+    match e {
+        CreateUserError::UserExists => conflict(
+            "user_exists",
+            "user already exists",
+        ).map(Some),
+        CreateUserError::CredentialExists => conflict(
+            "credential_exists",
+            "credential already registered",
+        ).map(Some),
+        CreateUserError::Conflict => conflict(
+            "conflict",
+            "conflicting registration; try again",
+        ).map(Some),
+        CreateUserError::Other(e) => Err(e.into()),
+    }
+}
+
+// generates a random password that satisfies the policy of the user pool.
+fn random_password() -> Result<String, Error> {
+    let mut password = [0u8; 24];
+    getrandom::getrandom(&mut password)?;
+    Ok(base64url.encode(password))
+}
+
+// adds a verified credential to an existing user who is recovering the
+// account or adding a passkey.
+//
+// returns a 409 response if the credential already exists.
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip_all)]
+async fn add_existing_user_credential(
+    shared_state: &SharedState,
+    tenant: &Tenant,
+    kind: RegistrationKind,
+    item: &RegistrationSession,
+    credential_id: &CredentialID,
+    credential: &impl Serialize,
+    session: &FinishRegistrationSession,
+    extensions: &ExtensionOutputs,
+    authenticator: Option<&AuthenticatorMetadata>,
+    client: ClientInfo,
+) -> Result<Option<Response<Body>>, Error> {
+    let properties = PasskeyProperties::of(credential)?;
+    let attestation = requested_attestation(shared_state, kind, credential)?;
+    let credential = serde_json::to_string(credential)?;
+    let user = shared_state.users
+        .get_user(&item.user_id)
+        .await?
+        .ok_or(ApiError::Storage("missing user in the database"))?;
+    let credential_id = base64url.encode(credential_id);
+    let created_at = DateTime::from(SystemTime::now())
+        .fmt(DateTimeFormat::DateTime)?;
+    info!("adding {:?} credential: {}", kind, truncate(&credential_id));
+    let credential_item = new_credential_item(
+        kind,
+        item,
+        tenant.qualify_username(&item.user_info.username),
+        credential_id.clone(),
+        credential,
+        &properties,
+        user.cognito_sub,
+        session,
+        extensions,
+        authenticator,
+        attestation,
+        &client,
+        created_at,
+    );
+    if !shared_state.users.add_credential(credential_item).await? {
+        return conflict(
+            "credential_exists",
+            "credential already registered",
+        ).map(Some);
+    }
+    record_registration(
+        shared_state,
+        tenant,
+        kind,
+        &item.user_id,
+        credential_id,
+        client,
+    ).await?;
+    Ok(None)
+}
+
+// extracts the attestation statement of a new credential to be stored if
+// attestation was requested.
+fn requested_attestation(
+    shared_state: &SharedState,
+    kind: RegistrationKind,
+    credential: &impl Serialize,
+) -> Result<Option<Attestation>, Error> {
+    let requested = matches!(kind, RegistrationKind::SecurityKey)
+        || shared_state.attestation != AttestationConveyancePreference::None;
+    if !requested {
+        return Ok(None);
+    }
+    Ok(attestation_of(credential)?)
+}
+
+// builds the item of a verified credential.
+#[allow(clippy::too_many_arguments)]
+fn new_credential_item(
+    kind: RegistrationKind,
+    item: &RegistrationSession,
+    username: String,
+    credential_id: String,
+    credential: String,
+    properties: &PasskeyProperties,
+    cognito_sub: String,
+    session: &FinishRegistrationSession,
+    extensions: &ExtensionOutputs,
+    authenticator: Option<&AuthenticatorMetadata>,
+    attestation: Option<Attestation>,
+    client: &ClientInfo,
+    created_at: String,
+) -> CredentialItem {
+    CredentialItem {
+        user_handle: item.user_id.clone(),
+        credential_id,
+        username: Some(username),
+        credential,
+        credential_type: Some(kind.credential_type().into()),
+        backup_eligible: Some(properties.backup_eligible),
+        backup_state: Some(properties.backup_state),
+        discoverable: discoverable(session),
+        prf_enabled: extensions.prf_enabled(),
+        cognito_sub: Some(cognito_sub),
+        authenticator_attachment: session.authenticator_attachment
+            .map(|a| authenticator_attachment_name(a).into()),
+        aaguid: authenticator.map(|a| a.aaguid.clone()),
+        authenticator_name: authenticator.and_then(|a| a.description.clone()),
+        attestation_format: attestation.as_ref().and_then(|a| a.format.clone()),
+        attestation_certificates: attestation.map(|a| a.certificates),
+        registered_ip: client.source_ip.clone(),
+        registered_user_agent: client.user_agent.clone(),
+        created_at: created_at.clone(),
+        updated_at: created_at,
+        last_used_at: None,
+        auth_count: None,
+        disabled_at: None,
+        deleted_at: None,
+        legacy_rp_id: None,
+        version: Some(1),
+    }
+}
+
+// looks up the authenticator of a new credential in the FIDO Metadata
+// Service.
+//
+// returns `None` if the client hid the AAGUID.
+// fails if the authenticator is listed with a compromised status.
+async fn lookup_authenticator(
+    shared_state: &SharedState,
+    session: &FinishRegistrationSession,
+) -> Result<Option<AuthenticatorMetadata>, Error> {
+    let Some(aaguid) = aaguid_of_attestation_object(
+        session.public_key_credential.response.attestation_object.as_ref(),
+    ) else {
+        return Ok(None);
+    };
+    let authenticator = match shared_state.metadata.as_ref() {
+        Some(metadata) => metadata.get(&aaguid).await?,
+        None => None,
+    }.unwrap_or_else(|| AuthenticatorMetadata::unlisted(aaguid.to_string()));
+    if authenticator.is_compromised() {
+        error!(
+            "authenticator no longer trusted: {} {:?}",
+            authenticator.aaguid,
+            authenticator.status,
+        );
+        shared_state.metrics.count("untrusted_authenticator");
+        return Err(ApiError::NotAllowed("authenticator no longer trusted").into());
+    }
+    Ok(Some(authenticator))
+}
+
+// returns whether the credential is discoverable as reported by the
+// `credProps` extension.
+fn discoverable(session: &FinishRegistrationSession) -> Option<bool> {
+    session.public_key_credential.extensions.cred_props
+        .as_ref()
+        .map(|p| p.rk)
+}
+
+// returns whether the user verified (UV) flag is set in the authenticator
+// data of the attestation object.
+//
+// checked besides the verification result so that a misbehaving client cannot
+// skip user verification.
+fn user_verified_in(session: &FinishRegistrationSession) -> bool {
+    authenticator_data_of(session.public_key_credential.response.attestation_object.as_ref())
+        .is_some_and(|data| is_user_verified_in(&data))
+}
+
+// returns the outputs of the `credProps` extension to pass through to the
+// client.
+fn cred_props(session: &FinishRegistrationSession) -> Option<CredentialProperties> {
+    discoverable(session).map(|rk| CredentialProperties { rk })
+}
+
+// records a registered credential in the audit log and publishes it.
+async fn record_registration(
+    shared_state: &SharedState,
+    tenant: &Tenant,
+    kind: RegistrationKind,
+    user_handle: &str,
+    credential_id: String,
+    client: ClientInfo,
+) -> Result<(), Error> {
+    if let Some(audit_log) = shared_state.audit_log.as_ref() {
+        audit_log.record_best_effort(AuditEvent {
+            event_type: AuditEventType::CredentialRegistered,
+            user_handle: user_handle.into(),
+            credential_id: Some(credential_id.clone()),
+            client,
+            detail: Some(kind.audit_detail().into()),
+        }).await;
+    }
+    let event = DomainEvent::PasskeyRegistered(PasskeyRegistered {
+        user_handle: user_handle.into(),
+        credential_id,
+        tenant_id: tenant.id().map(Into::into),
+        registration: kind.audit_detail(),
+    });
+    notify_webhook(shared_state.webhooks.as_ref(), &event).await;
+    publish_event(shared_state.event_publisher.as_ref(), event).await;
+    Ok(())
+}
+
+// generates a new set of recovery codes for a user and returns the codes.
+//
+// replaces the existing codes if any.
+#[instrument(skip_all)]
+async fn issue_recovery_codes(
+    shared_state: &SharedState,
+    user_handle: &str,
+) -> Result<Vec<String>, Error> {
+    let (codes, item) = new_recovery_codes(
+        user_handle,
+        DateTime::from(SystemTime::now()).fmt(DateTimeFormat::DateTime)?,
+    )?;
+    shared_state.users.put_recovery_codes(item).await?;
+    Ok(codes)
+}
+
+// creates a 401 response to a recovery with a wrong code or username.
+fn invalid_recovery_code() -> Result<Response<Body>, Error> {
+    let body = serde_json::to_string(&ErrorResponseBody {
+        error: "invalid_recovery_code",
+        message: "invalid username or recovery code".into(),
+        field: None,
+        localized_message: None,
+        request_id: None,
+    })?;
+    Ok(Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .header("Content-Type", "application/json")
+        .body(body.into())?)
+}
+
+// creates a 202 response to a request for a recovery link.
+fn recovery_link_accepted() -> Result<Response<Body>, Error> {
+    Ok(Response::builder()
+        .status(StatusCode::ACCEPTED)
+        .body(Body::Empty)?)
+}
+
+// creates a 401 response to a recovery with an unknown, used, or expired
+// link.
+fn invalid_recovery_link() -> Result<Response<Body>, Error> {
+    let body = serde_json::to_string(&ErrorResponseBody {
+        error: "invalid_recovery_link",
+        message: "invalid or expired recovery link".into(),
+        field: None,
+        localized_message: None,
+        request_id: None,
+    })?;
+    Ok(Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .header("Content-Type", "application/json")
+        .body(body.into())?)
+}
+
+// creates a 409 response.
+fn conflict(error: &'static str, message: &str) -> Result<Response<Body>, Error> {
+    let body = serde_json::to_string(&ErrorResponseBody {
+        error,
+        message: message.into(),
+        field: None,
+        localized_message: None,
+        request_id: None,
+    })?;
+    Ok(Response::builder()
+        .status(StatusCode::CONFLICT)
+        .header("Content-Type", "application/json")
+        .body(body.into())?)
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::INFO)
-        // disable printing the name of the module in every log line.
-        .with_target(false)
-        // disabling time is handy because CloudWatch will add the ingestion time.
-        .without_time()
-        .init();
-
-    let shared_state = Arc::new(SharedState::new().await?);
-    run(service_fn(|req| async {
-        function_handler(shared_state.clone(), req).await
-    })).await
+    let started_at = Instant::now();
+    let telemetry = init_tracing("registration")?;
+
+    let sdk_config = load_sdk_config().await?;
+    load_config_parameters(&aws_sdk_ssm::Client::new(&sdk_config)).await?;
+    let mut config = Config::from_env(aws_sdk_secretsmanager::Client::new(&sdk_config))?;
+    let router = router(config.bearer_auth.take());
+    let shared_state = Arc::new(SharedState::new(&sdk_config, config).await?);
+    let metrics = shared_state.metrics.clone();
+    let cold_start = ColdStart::initialized_since(started_at);
+    run_with_warmer(&cold_start, &metrics, |req: Request| async {
+        let span = request_span(&req);
+        let handler_started_at = Instant::now();
+        let res = negotiate_content(
+            req,
+            shared_state.max_body_size,
+            |req| handle_api_errors(function_handler(shared_state.clone(), &router, req)),
+        )
+            .instrument(span)
+            .await;
+        cold_start.report(&metrics, handler_started_at.elapsed());
+        telemetry.flush().await;
+        res
+    }).await
 }
@@ -0,0 +1,9 @@
+//! Prints the TypeScript types of the registration API.
+//!
+//! See [`authentication::typescript`] for details.
+
+use authentication::typescript::declarations;
+
+fn main() {
+    print!("{}", declarations());
+}
@@ -0,0 +1,157 @@
+//! Cognito user migration trigger that imports users from a legacy user
+//! store.
+//!
+//! Creates a user who signs in with a password, or requests a password
+//! reset, in the user pool if the legacy user store knows the user; see
+//! [`authentication::legacy_users`] for the protocol of the store and how
+//! migrated users register passkeys.
+//!
+//! You have to configure the following environment variables:
+//! - `LEGACY_USER_STORE_URL`: URL of the HTTP endpoint of the legacy user
+//!   store
+//!
+//! You can optionally configure the following environment variables:
+//! - `CONFIG_PARAMETER_PATH`: path to the parameters in Parameter Store on
+//!   AWS Systems Manager that override the other environment variables. See
+//!   [`authentication::config`] for details.
+//! - `LEGACY_USER_STORE_SECRET_ID`: ID of the secret in Secrets Manager that
+//!   signs the requests to the legacy user store. See
+//!   [`authentication::legacy_users::load_legacy_user_store`].
+//! - `SECRET_CACHE_TTL`: see [`authentication::secrets`].
+//! - `LOG_LEVEL`, `LOG_REDACTION`: log level or `RUST_LOG`-style directives,
+//!   and whether identifiers are redacted in logs; "info" and redacted by
+//!   default. See [`authentication::telemetry`] for details.
+//! - `METRICS_NAMESPACE`: namespace of the CloudWatch metrics; "PasskeyTest"
+//!   by default. The following metrics are reported:
+//!     - `users_migrated`: number of users migrated from the legacy user
+//!       store
+//!     - `users_not_migrated`: number of users unknown to the legacy user
+//!       store, or with wrong passwords
+//!
+//! Only the `UserMigration_Authentication` and `UserMigration_ForgotPassword`
+//! events are supported. The function fails with "User does not exist" if
+//! the legacy user store does not know the user, which Cognito turns into a
+//! failed sign-in; the app client should prevent user existence errors.
+//!
+//! The function fails at cold start if any required variable is missing or
+//! any variable is invalid, and the error lists all of them; see
+//! [`authentication::config`].
+
+use lambda_runtime::{run, service_fn, Error, LambdaEvent};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{error, info, instrument};
+
+use authentication::config::{ConfigCheck, load_config_parameters};
+use authentication::legacy_users::{
+    AUTHENTICATION_TRIGGER_SOURCE,
+    FORGOT_PASSWORD_TRIGGER_SOURCE,
+    LegacyUserRequest,
+    LegacyUserStore,
+    load_legacy_user_store,
+    set_migration_response,
+};
+use authentication::metrics::{ColdStart, Metrics, load_metrics};
+use authentication::telemetry::{init_tracing, redact};
+
+// Error message of a user who cannot be migrated.
+const USER_NOT_FOUND: &str = "User does not exist";
+
+// State shared among Lambda invocations.
+struct SharedState {
+    legacy_users: LegacyUserStore,
+}
+
+// Configuration validated at cold start.
+struct Config {
+    legacy_users: LegacyUserStore,
+}
+
+impl Config {
+    fn from_env(secrets: aws_sdk_secretsmanager::Client) -> Result<Self, Error> {
+        let mut check = ConfigCheck::new();
+        check.required("LEGACY_USER_STORE_URL");
+        let legacy_users = check.load(load_legacy_user_store(secrets));
+        // `required` has reported a missing URL with the other problems
+        let legacy_users = check.finish(legacy_users)?
+            .ok_or("LEGACY_USER_STORE_URL must be set")?;
+        Ok(Self { legacy_users })
+    }
+}
+
+impl SharedState {
+    #[instrument(name = "cold_start", skip_all)]
+    fn new(config: Config) -> Self {
+        Self {
+            legacy_users: config.legacy_users,
+        }
+    }
+}
+
+#[instrument(skip_all)]
+async fn function_handler(
+    shared_state: Arc<SharedState>,
+    metrics: &Metrics,
+    event: LambdaEvent<Value>,
+) -> Result<Value, Error> {
+    let mut event = event.payload;
+    let trigger_source = event.get("triggerSource")
+        .and_then(Value::as_str)
+        .ok_or("missing trigger source in request")?
+        .to_string();
+    let password = match trigger_source.as_str() {
+        AUTHENTICATION_TRIGGER_SOURCE => Some(
+            event.pointer("/request/password")
+                .and_then(Value::as_str)
+                .ok_or("missing password in request")?
+                .to_string(),
+        ),
+        FORGOT_PASSWORD_TRIGGER_SOURCE => None,
+        _ => {
+            error!("unsupported trigger source: {}", trigger_source);
+            return Err(format!("unsupported trigger source: {}", trigger_source).into());
+        }
+    };
+    let username = event.get("userName")
+        .and_then(Value::as_str)
+        .ok_or("missing username in request")?
+        .to_string();
+    info!("migrating user: {} ({})", redact(&username), trigger_source);
+    let user = shared_state.legacy_users
+        .find_user(&LegacyUserRequest {
+            username: &username,
+            password: password.as_deref(),
+            trigger_source: &trigger_source,
+        })
+        .await?;
+    let Some(user) = user else {
+        info!("user not migrated: {}", redact(&username));
+        metrics.count("users_not_migrated");
+        return Err(USER_NOT_FOUND.into());
+    };
+    set_migration_response(&mut event, &user)?;
+    info!("user migrated: {}", redact(&username));
+    metrics.count("users_migrated");
+    Ok(event)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let started_at = Instant::now();
+    let telemetry = init_tracing("user-migration")?;
+
+    let sdk_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+    load_config_parameters(&aws_sdk_ssm::Client::new(&sdk_config)).await?;
+    let config = Config::from_env(aws_sdk_secretsmanager::Client::new(&sdk_config))?;
+    let shared_state = Arc::new(SharedState::new(config));
+    let metrics = load_metrics("user-migration")?;
+    let cold_start = ColdStart::initialized_since(started_at);
+    run(service_fn(|event| async {
+        let handler_started_at = Instant::now();
+        let res = function_handler(shared_state.clone(), &metrics, event).await;
+        cold_start.report(&metrics, handler_started_at.elapsed());
+        telemetry.flush().await;
+        res
+    })).await
+}
@@ -6,29 +6,106 @@
 //!   credentials
 //! - `RP_ORIGIN_PARAMETER_PATH`: path to the parameter that stores the origin
 //!   (URL) of the relying party in Parameter Store on AWS Systems Manager
+//!
+//! You can optionally configure the following environment variables:
+//! - `CONFIG_PARAMETER_PATH`: path to the parameters in Parameter Store on
+//!   AWS Systems Manager that override the other environment variables. See
+//!   [`authentication::config`] for details.
+//! - `DATABASE_URL`: URL of the PostgreSQL database that stores users,
+//!   credentials, and sessions in place of the DynamoDB tables; needs the
+//!   `sql` feature. See [`authentication::store::load_database`]. The other
+//!   items in the session table, like rate limits, stay on DynamoDB.
+//! - `RP_ORIGIN`: origin (URL) of the relying party that takes precedence over
+//!   `RP_ORIGIN_PARAMETER_PATH`
+//! - `RP_ID`: ID of the relying party; the domain of the origin by default
+//! - `RP_ALLOWED_ORIGINS`: comma-separated origins (URLs) allowed in addition
+//!   to the origin of the relying party; e.g., `https://www.example.com`
+//! - `ANDROID_APK_KEY_HASHES`: comma-separated SHA-256 hashes of the
+//!   certificates that sign Android apps allowed to use the relying party.
+//!   See [`authentication::android`] for details.
+//! - `LEGACY_RP_ID`, `LEGACY_RP_ORIGIN`: legacy ID and origin of the relying
+//!   party, which enable the migration mode of the RP ID. An assertion that
+//!   fails with the current RP ID is verified with the legacy RP ID, and the
+//!   credential is flagged for re-registration. See
+//!   [`authentication::rp_migration`] for details.
+//! - `CHALLENGE_TIMEOUT`: timeout of an authentication in seconds; 60 by
+//!   default. Given to the client as the `timeout` of the request options.
+//!   Should be the same as the discoverable credentials API.
+//! - `AUDIT_TABLE_NAME`: name of the DynamoDB table for the audit log.
+//!   Authentication failures are recorded if specified.
+//! - `LOCKOUT_THRESHOLD`, `LOCKOUT_DURATION`, `LOCKOUT_MAX_DURATION`: lockout
+//!   of credentials after consecutive failed authentications. Disabled unless
+//!   specified. An answer with a locked credential fails with an error whose
+//!   message starts with "credential_locked"; Cognito tells it in the message
+//!   of `UserLambdaValidationException`. See [`authentication::lockout`] for
+//!   details.
+//! - `RISK_HOOK_URL`, `RISK_HOOK_FUNCTION_NAME`, `RISK_HOOK_SECRET_ID`,
+//!   `RISK_HOOK_ON_ERROR`: HTTP endpoint or Lambda function that assesses
+//!   the risk of a verified answer. The source IP and user agent are unknown
+//!   to the hook. A rejected answer fails with an error whose message is
+//!   "step_up_required" or "authentication_denied". Every verified answer is
+//!   allowed unless specified. See [`authentication::risk`] for details.
+//! - `EVENT_BUS_NAME`: name of the EventBridge event bus. Successful
+//!   authentications are published as `AuthenticationSucceeded` if specified;
+//!   see [`authentication::domain_events`].
+//! - `PASSKEY_CLAIMS`: "true" to record successful authentications in the
+//!   session table for the `pre-token-generation` trigger, which adds the
+//!   claims of the passkey to ID tokens. See [`authentication::passkey_claims`]
+//!   for details.
+//! - `LARGE_BLOB`: support of the `largeBlob` extension; "required" or
+//!   "preferred". Authentication requests to read the large blob if
+//!   specified. See [`load_extension_policy`] for details.
+//! - `PRF`, `PRF_SALT`: whether the `prf` extension is evaluated and its
+//!   salt. Disabled unless specified. See [`load_extension_policy`] for
+//!   details.
+//! - `HINTS`: comma-separated "security-key", "client-device", and "hybrid"
+//!   given as the `hints` of the request options in the order of
+//!   preference. No hints unless specified. See [`authentication::hints`]
+//!   for details.
+//! - `ENUMERATION_PROTECTION`, `ENUMERATION_MIN_DURATION`,
+//!   `ENUMERATION_SALT_SECRET_ID`: protection mode against user enumeration.
+//!   A user without any usable credential gets a dummy challenge like an
+//!   unknown user does, and challenges are created no earlier than the
+//!   minimum duration. Unknown users always get dummy challenges, whose
+//!   credential IDs are salted with the secret in the protection mode;
+//!   `ENUMERATION_SALT_SECRET_ID` is required then. See
+//!   [`authentication::enumeration`] for details.
+//! - `TENANT_TABLE_NAME`: name of the DynamoDB table of tenants. The tenant
+//!   key must be given in the `tenant` client metadata if specified.
+//!   Challenges beyond the authentication quota of the tenant fail. See
+//!   [`authentication::tenant`] for details.
+//! - `PII_KMS_KEY_ARN`, `PII_INDEX_SECRET_ID`: ARN of the KMS key and ID of
+//!   the secret in Secrets Manager that protect the usernames and display
+//!   names of users. Stored in plaintext unless specified. See
+//!   [`authentication::pii`].
+//! - `LOG_LEVEL`, `LOG_REDACTION`: log level or `RUST_LOG`-style directives,
+//!   and whether identifiers are redacted in logs; "info" and redacted by
+//!   default. See [`authentication::telemetry`] for details.
+//! - `METRICS_NAMESPACE`: namespace of the CloudWatch metrics; "PasskeyTest"
+//!   by default. The cold start of the function is reported as metrics; see
+//!   [`ColdStart`].
+//!
+//! The function fails at cold start if any required variable is missing or
+//! any variable is invalid, and the error lists all of them; see
+//! [`authentication::config`].
 
+use aws_config::SdkConfig;
 use aws_lambda_events::event::cognito::{
     CognitoEventUserPoolsCreateAuthChallenge,
     CognitoEventUserPoolsDefineAuthChallenge,
     CognitoEventUserPoolsVerifyAuthChallenge,
 };
-use aws_sdk_dynamodb::{
-    primitives::{DateTime, DateTimeFormat},
-    types::{AttributeValue, ReturnValue},
-};
+use aws_sdk_dynamodb::primitives::DateTime;
 use base64::{
     Engine as _,
     engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
 };
 use lambda_runtime::{run, service_fn, Error, LambdaEvent};
-use ring::digest;
-use std::env;
+use std::collections::HashMap;
 use std::sync::Arc;
-use std::time::SystemTime;
-use tracing::{error, info};
+use std::time::{Instant, SystemTime};
+use tracing::{debug, error, info, info_span, instrument};
 use webauthn_rs::{
-    Webauthn,
-    WebauthnBuilder,
     prelude::{
         DiscoverableAuthentication,
         DiscoverableKey,
@@ -39,10 +116,23 @@ use webauthn_rs::{
 };
 use webauthn_rs_proto::{
     CollectedClientData,
-    auth::{PublicKeyCredential, PublicKeyCredentialRequestOptions},
-    options::{AllowCredentials, UserVerificationPolicy},
+    auth::PublicKeyCredential,
+    options::AllowCredentials,
 };
 
+use authentication::audit::{
+    AuditEvent,
+    AuditEventType,
+    AuditLog,
+    ClientInfo,
+    load_audit_log,
+};
+use authentication::config::{ConfigCheck, load_config_parameters};
+use authentication::credential_store::{
+    CredentialBackend,
+    CredentialStore,
+    record_authentication,
+};
 use authentication::event::{
     CognitoChallengeEvent,
     CognitoChallengeEventCase,
@@ -50,35 +140,238 @@ use authentication::event::{
     CognitoEventUserPoolsDefineAuthChallengeOps,
     CognitoEventUserPoolsVerifyAuthChallengeOps,
 };
-use authentication::parameters::load_relying_party_origin;
+use authentication::domain_events::{
+    AuthenticationSucceeded,
+    DomainEvent,
+    EventPublisher,
+    load_event_publisher,
+    publish_event,
+};
+use authentication::enumeration::{
+    EnumerationProtection,
+    default_dummy_credential_id,
+    load_enumeration_protection,
+};
+use authentication::extensions::{ExtensionPolicy, load_extension_policy};
+use authentication::hints::{PublicKeyCredentialHint, load_hints, merge_hints_into};
+use authentication::items::{
+    CredentialItem,
+    CredentialKey,
+    PasskeyAuthenticationItem,
+};
+use authentication::lockout::{
+    CREDENTIAL_LOCKED,
+    CredentialLockout,
+    load_credential_lockout,
+};
+use authentication::metrics::{ColdStart, load_metrics};
+use authentication::parameters::load_webauthn;
+use authentication::passkey::is_user_verified_in;
+use authentication::passkey_claims::{
+    PASSKEY_AUTHENTICATION_TTL,
+    PasskeyAuthentications,
+    load_passkey_authentications,
+};
+use authentication::pii::load_pii_protection;
+use authentication::policy::{
+    ChallengeTimeout,
+    load_challenge_timeout,
+};
+use authentication::risk::{RemoteRiskHook, RiskContext, assess_risk, load_risk_hook};
+use authentication::rp_migration::{load_legacy_relying_party, verify_with_fallback};
+use authentication::sessions::{TakenSession, take_discoverable_session};
+use authentication::store::{DynamoDbSessionStore, SessionBackend, load_database};
+use authentication::telemetry::{init_tracing, redact, truncate};
+use authentication::tenant::{
+    QuotaKind,
+    TENANT_CLIENT_METADATA,
+    Tenant,
+    TenantDirectory,
+    load_tenant_directory,
+};
+use authentication::users::UserDirectory;
 
 const CHALLENGE_PARAMETER_NAME: &str = "passkeyTestChallenge";
 
 // State shared among Lambda invocations.
 struct SharedState {
-    webauthn: Webauthn,
+    default_tenant: Arc<Tenant>,
+    tenants: Option<TenantDirectory>,
     dynamodb: aws_sdk_dynamodb::Client,
+    session_table_name: String,
+    sessions: SessionBackend,
+    challenge_timeout: ChallengeTimeout,
+    users: CredentialBackend,
+    lockout: Option<CredentialLockout>,
+    risk_hook: Option<RemoteRiskHook>,
+    audit_log: Option<AuditLog>,
+    event_publisher: Option<EventPublisher>,
+    extension_policy: ExtensionPolicy,
+    hints: Option<Vec<PublicKeyCredentialHint>>,
+    enumeration_protection: Option<EnumerationProtection>,
+    passkey_authentications: Option<PasskeyAuthentications>,
+}
+
+// Configuration validated at cold start.
+struct Config {
     session_table_name: String,
     credential_table_name: String,
+    challenge_timeout: ChallengeTimeout,
+    extension_policy: ExtensionPolicy,
+    hints: Option<Vec<PublicKeyCredentialHint>>,
+    enumeration_protection: Option<EnumerationProtection>,
+}
+
+impl Config {
+    fn from_env(secrets: aws_sdk_secretsmanager::Client) -> Result<Self, Error> {
+        let mut check = ConfigCheck::new();
+        check.required_any(&["RP_ORIGIN", "RP_ORIGIN_PARAMETER_PATH"]);
+        let config = Self {
+            session_table_name: check.required("SESSION_TABLE_NAME"),
+            credential_table_name: check.required("CREDENTIAL_TABLE_NAME"),
+            challenge_timeout: check.load(load_challenge_timeout()),
+            extension_policy: check.load(load_extension_policy()),
+            hints: check.load(load_hints()),
+            enumeration_protection: check.load(load_enumeration_protection(secrets)),
+        };
+        Ok(check.finish(config)?)
+    }
 }
 
 impl SharedState {
-    async fn new() -> Result<Self, Error> {
-        let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
-        let (rp_id, rp_origin) =
-            load_relying_party_origin(aws_sdk_ssm::Client::new(&config)).await?;
-        let webauthn = WebauthnBuilder::new(&rp_id, &rp_origin)?
-            .rp_name("Passkey Test")
-            .build()?;
+    #[instrument(name = "cold_start", skip_all)]
+    async fn new(sdk_config: &SdkConfig, config: Config) -> Result<Self, Error> {
+        let ssm = aws_sdk_ssm::Client::new(sdk_config);
+        let webauthn = load_webauthn(ssm.clone()).await?;
+        let legacy_relying_party = load_legacy_relying_party(ssm).await?;
+        let dynamodb = aws_sdk_dynamodb::Client::new(sdk_config);
+        let credential_table_name = config.credential_table_name;
+        let database = load_database().await?;
         Ok(Self {
-            webauthn,
-            dynamodb: aws_sdk_dynamodb::Client::new(&config),
-            session_table_name: env::var("SESSION_TABLE_NAME")
-                .or(Err("SESSION_TABLE_NAME env must be set"))?,
-            credential_table_name: env::var("CREDENTIAL_TABLE_NAME")
-                .or(Err("CREDENTIAL_TABLE_NAME env must be set"))?,
+            default_tenant: Arc::new(
+                Tenant::default_tenant(webauthn)
+                    .with_legacy_relying_party(legacy_relying_party),
+            ),
+            tenants: load_tenant_directory(dynamodb.clone())?,
+            dynamodb: dynamodb.clone(),
+            passkey_authentications: load_passkey_authentications(
+                dynamodb.clone(),
+                config.session_table_name.clone(),
+            )?,
+            sessions: SessionBackend::new(
+                DynamoDbSessionStore::new(dynamodb.clone(), config.session_table_name.clone()),
+                database.as_ref(),
+            ),
+            session_table_name: config.session_table_name,
+            challenge_timeout: config.challenge_timeout,
+            users: CredentialBackend::new(
+                UserDirectory::new(dynamodb.clone(), credential_table_name.clone())
+                    .with_pii_protection(load_pii_protection(
+                        aws_sdk_kms::Client::new(sdk_config),
+                        aws_sdk_secretsmanager::Client::new(sdk_config),
+                    )?),
+                database.as_ref(),
+            ),
+            // the lockout stays on the DynamoDB credential table
+            lockout: load_credential_lockout(dynamodb.clone(), credential_table_name)?,
+            risk_hook: load_risk_hook(
+                aws_sdk_lambda::Client::new(sdk_config),
+                aws_sdk_secretsmanager::Client::new(sdk_config),
+            )?,
+            enumeration_protection: config.enumeration_protection,
+            audit_log: load_audit_log(dynamodb)?,
+            event_publisher: load_event_publisher(
+                aws_sdk_eventbridge::Client::new(sdk_config),
+            )?,
+            extension_policy: config.extension_policy,
+            hints: config.hints,
         })
     }
+
+    // resolves the tenant from the client metadata.
+    async fn resolve_tenant(
+        &self,
+        client_metadata: &HashMap<String, String>,
+    ) -> Result<Arc<Tenant>, Error> {
+        let Some(tenants) = self.tenants.as_ref() else {
+            return Ok(self.default_tenant.clone());
+        };
+        let tenant_key = client_metadata.get(TENANT_CLIENT_METADATA)
+            .ok_or("missing tenant in client metadata")?;
+        Ok(tenants.resolve(tenant_key).await?.ok_or("unknown tenant")?)
+    }
+
+    // returns whether a credential item is enabled.
+    //
+    // the authenticator attachment of a credential is not checked, because
+    // it is reported by the client without a signature.
+    fn is_allowed_credential(&self, credential: &CredentialItem) -> bool {
+        credential.disabled_at.is_none()
+    }
+
+    // records a failed authentication with a registered credential.
+    async fn record_failure(
+        &self,
+        key: CredentialKey<'_>,
+        registered: bool,
+        now: i64,
+    ) -> Result<(), Error> {
+        if let Some(lockout) = self.lockout.as_ref().filter(|_| registered) {
+            if let Some(duration) = lockout.record_failure(key, now).await? {
+                info!(
+                    "credential locked for {} seconds: {}",
+                    duration,
+                    truncate(key.credential_id),
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // records a successful authentication for the pre token generation
+    // trigger.
+    async fn record_passkey_authentication(
+        &self,
+        credential: &CredentialItem,
+        user_verified: bool,
+        now: i64,
+    ) -> Result<(), Error> {
+        if let Some(authentications) = self.passkey_authentications.as_ref() {
+            authentications.record(&credential.user_handle, PasskeyAuthenticationItem {
+                ttl: now + PASSKEY_AUTHENTICATION_TTL,
+                credential_id: credential.credential_id.clone(),
+                aaguid: credential.aaguid.clone(),
+                user_verified,
+                authenticated_at: now,
+            }).await?;
+        }
+        Ok(())
+    }
+
+    // assesses the risk of a verified answer with a credential.
+    //
+    // returns the error code and detail of the rejection if the answer is
+    // rejected.
+    async fn assess_answer(
+        &self,
+        tenant: &Tenant,
+        credential: &CredentialItem,
+        user_verified: bool,
+    ) -> Result<Option<(&'static str, String)>, Error> {
+        // Cognito triggers do not tell the source IP or user agent
+        let client = ClientInfo::default();
+        let assessment = assess_risk(
+            self.risk_hook.as_ref(),
+            &RiskContext::new(credential, tenant.id(), &client, user_verified),
+        ).await?;
+        Ok(assessment.rejection(user_verified).map(|rejection| {
+            let detail = match assessment.reason {
+                Some(reason) => format!("{}: {}", rejection, reason),
+                None => rejection.into(),
+            };
+            (rejection, detail)
+        }))
+    }
 }
 
 /// This is the main body for the function.
@@ -106,6 +399,7 @@ async fn function_handler(
 }
 
 // Handles "Define auth challenge" events.
+#[instrument(skip_all)]
 async fn define_auth_challenge(
     _shared_state: Arc<SharedState>,
     mut event: CognitoEventUserPoolsDefineAuthChallenge,
@@ -128,54 +422,62 @@ async fn define_auth_challenge(
 }
 
 // Handles "Create auth challenge" events.
+#[instrument(skip_all)]
 async fn create_auth_challenge(
     shared_state: Arc<SharedState>,
     mut event: CognitoEventUserPoolsCreateAuthChallenge,
 ) -> Result<CognitoEventUserPoolsCreateAuthChallenge, Error> {
-    info!("create_auth_challenge: {:?}", event);
+    info!(
+        "create_auth_challenge: {:?}",
+        event.cognito_event_user_pools_header.user_name.as_deref().map(redact),
+    );
+    // the rest of the event tells the username and user attributes
+    debug!("create_auth_challenge: {:?}", event.request.session);
     if event.sessions().is_empty() {
+        let started_at = Instant::now();
         let username = event.cognito_event_user_pools_header.user_name
-            .as_ref()
+            .clone()
             .ok_or("missing username in Cognito trigger")?;
+        let tenant = shared_state
+            .resolve_tenant(&event.request.client_metadata)
+            .await?;
+        if tenant.hit_quota(
+            &shared_state.dynamodb,
+            &shared_state.session_table_name,
+            QuotaKind::Authentication,
+            DateTime::from(SystemTime::now()).secs(),
+        ).await?.is_some() {
+            return Err("quota of tenant exceeded".into());
+        }
+        let mut challenged = false;
         if event.user_exists() {
             // lists credentials of the user
-            let credentials = shared_state.dynamodb
-                .query()
-                .table_name(shared_state.credential_table_name.clone())
-                .key_condition_expression("pk = :pk")
-                .expression_attribute_values(
-                    ":pk",
-                    AttributeValue::S(format!("user#{}", username)),
-                )
-                .send()
-                .await?
-                .items
-                .unwrap_or_default();
-            let credentials: Vec<&String> = credentials.iter()
-                .map(|c| c.get("credential")
-                    .ok_or("missing credential in the database")?
-                    .as_s()
-                    .or(Err("malformed credential in the database")))
-                .collect::<Result<Vec<_>, _>>()?;
-            let passkeys: Vec<Passkey> = credentials.into_iter()
-                .map(|c| serde_json::from_str(c)
+            let credentials = shared_state.users
+                .list_credentials(&username)
+                .await?;
+            let passkeys: Vec<Passkey> = credentials.iter()
+                .filter(|c| shared_state.is_allowed_credential(c))
+                .map(|c| serde_json::from_str(&c.credential)
                     .or(Err("malformed credential in the database")))
                 .collect::<Result<Vec<_>, _>>()?;
 
             // starts authentication
-            match shared_state.webauthn
+            match tenant.webauthn()
                 .start_passkey_authentication(&passkeys)
             {
-                Ok((rcr, auth_state)) => {
+                Ok((mut rcr, auth_state)) => {
+                    rcr.public_key.timeout =
+                        Some(shared_state.challenge_timeout.as_millis());
                     event.set_challenge_metadata("PASSKEY_TEST_CHALLENGE");
                     event.set_public_challenge_parameter(
                         CHALLENGE_PARAMETER_NAME,
-                        &rcr,
+                        &request_options(&shared_state, &rcr)?,
                     )?;
                     event.set_private_challenge_parameter(
                         CHALLENGE_PARAMETER_NAME,
                         &auth_state,
                     )?;
+                    challenged = true;
                 }
                 Err(e) => {
                     error!("failed to start authentication: {}", e);
@@ -183,41 +485,16 @@ async fn create_auth_challenge(
             }
         } else {
             info!("non existing user");
-            // generates 160 bits (20 bytes) fake credential ID from username
-            // TODO: secret salt
-            let mut hash = ring::digest::Context::new(&digest::SHA256);
-            hash.update(b"TODO: replace this with a secret salt!!!");
-            hash.update(username.as_bytes());
-            let hash = hash.finish();
-            let mut credential_id: Vec<u8> = Vec::with_capacity(20);
-            credential_id.extend_from_slice(&hash.as_ref()[0..20]);
-            let mut challenge = vec![0u8; 32];
-            getrandom::getrandom(&mut challenge)?;
-            let rcr = RequestChallengeResponse {
-                public_key: PublicKeyCredentialRequestOptions {
-                    rp_id: "localhost".into(),
-                    challenge: challenge.into(),
-                    allow_credentials: vec![AllowCredentials {
-                        type_: "public-key".into(),
-                        id: credential_id.into(),
-                        transports: None,
-                    }],
-                    user_verification: UserVerificationPolicy::Preferred,
-                    timeout: Some(60000),
-                    hints: None, // TODO: client-device?
-                    extensions: None,
-                },
-                mediation: None,
-            };
-            event.set_challenge_metadata("PASSKEY_TEST_CHALLENGE");
-            event.set_public_challenge_parameter(
-                CHALLENGE_PARAMETER_NAME,
-                &rcr,
-            )?;
-            event.set_private_challenge_parameter(
-                CHALLENGE_PARAMETER_NAME,
-                "",
-            )?;
+        }
+        // a user without any usable credential looks like an unknown user in
+        // the protection mode
+        if !challenged
+            && (!event.user_exists() || shared_state.enumeration_protection.is_some())
+        {
+            set_dummy_challenge(&shared_state, &tenant, &username, &mut event).await?;
+        }
+        if let Some(protection) = shared_state.enumeration_protection.as_ref() {
+            protection.pad(started_at).await;
         }
         Ok(event)
     } else {
@@ -225,14 +502,71 @@ async fn create_auth_challenge(
     }
 }
 
+// serializes request options with the enabled extension inputs and hints.
+fn request_options(
+    shared_state: &SharedState,
+    rcr: &RequestChallengeResponse,
+) -> Result<serde_json::Value, Error> {
+    let mut options = shared_state.extension_policy
+        .authentication_inputs()
+        .add_to(rcr)?;
+    if let Some(hints) = shared_state.hints.as_deref() {
+        merge_hints_into(hints, &mut options);
+    }
+    Ok(options)
+}
+
+// sets a dummy challenge that allows the dummy credential ID of a given
+// username, which no answer can satisfy.
+//
+// the options are generated by the Webauthn of the tenant so that they look
+// the same as real ones except for the credential ID.
+async fn set_dummy_challenge(
+    shared_state: &SharedState,
+    tenant: &Tenant,
+    username: &str,
+    event: &mut CognitoEventUserPoolsCreateAuthChallenge,
+) -> Result<(), Error> {
+    let credential_id = match shared_state.enumeration_protection.as_ref() {
+        Some(protection) => protection.dummy_credential_id(username).await?,
+        None => default_dummy_credential_id(username),
+    };
+    let (mut rcr, _) = tenant.webauthn()
+        .start_discoverable_authentication()
+        .map_err(|e| {
+            error!("failed to start dummy authentication: {}", e);
+            "failed to start dummy authentication"
+        })?;
+    rcr.public_key.allow_credentials = vec![AllowCredentials {
+        type_: "public-key".into(),
+        id: credential_id.into(),
+        transports: None,
+    }];
+    rcr.public_key.timeout = Some(shared_state.challenge_timeout.as_millis());
+    event.set_challenge_metadata("PASSKEY_TEST_CHALLENGE");
+    event.set_public_challenge_parameter(
+        CHALLENGE_PARAMETER_NAME,
+        &request_options(shared_state, &rcr)?,
+    )?;
+    event.set_private_challenge_parameter(
+        CHALLENGE_PARAMETER_NAME,
+        "",
+    )?;
+    Ok(())
+}
+
 // Handles "Verify auth challenge" events.
+#[instrument(skip_all)]
 async fn verify_auth_challenge(
     shared_state: Arc<SharedState>,
     mut event: CognitoEventUserPoolsVerifyAuthChallenge,
 ) -> Result<CognitoEventUserPoolsVerifyAuthChallenge, Error> {
-    info!("verify_auth_challenge: {:?}", event);
+    info!(
+        "verify_auth_challenge: {:?}",
+        event.cognito_event_user_pools_header.user_name.as_deref().map(redact),
+    );
 
-    let user_handle = event.cognito_event_user_pools_header.user_name.as_ref()
+    let user_handle = event.cognito_event_user_pools_header.user_name.clone()
         .ok_or("missing username in request")?;
     let credential: PublicKeyCredential = match event.get_challenge_answer() {
         Ok(credential) => credential,
@@ -250,11 +584,42 @@ async fn verify_auth_challenge(
     let cred_user_handle = credential.response.user_handle.as_ref()
         .map(|h| base64url.encode(h))
         .ok_or("missing user handle in credential")?;
-    if user_handle != &cred_user_handle {
-        error!("user handle mismatch: {} vs {}", user_handle, cred_user_handle);
+    if user_handle != cred_user_handle {
+        error!(
+            "user handle mismatch: {} vs {}",
+            redact(&user_handle),
+            redact(&cred_user_handle),
+        );
         return Err("credential mismatch".into());
     }
 
+    // a locked credential is rejected before verification
+    let now = DateTime::from(SystemTime::now()).secs();
+    let credential_id = base64url.encode(&credential.raw_id);
+    let credential_key = CredentialKey {
+        user_handle: &user_handle,
+        credential_id: &credential_id,
+    };
+    let lockout_state = match shared_state.lockout.as_ref() {
+        Some(lockout) => lockout.get(credential_key).await?,
+        None => None,
+    };
+    if let Some(retry_after) = lockout_state.and_then(|s| s.retry_after(now)) {
+        error!("credential locked: {}", truncate(&credential_id));
+        reject_answer(
+            &shared_state,
+            &mut event,
+            &user_handle,
+            &credential,
+            "credential locked",
+        ).await?;
+        return Err(format!(
+            "{}: retry after {} seconds",
+            CREDENTIAL_LOCKED,
+            retry_after,
+        ).into());
+    }
+
     // extracts the challenge from `credential`
     // https://github.com/kanidm/webauthn-rs/blob/0ff6b525d428b5155243a37e1672c1e3205d41e8/webauthn-rs-core/src/core.rs#L702-L705
     // https://developer.mozilla.org/en-US/docs/Web/API/AuthenticatorResponse/clientDataJSON#type
@@ -266,121 +631,121 @@ async fn verify_auth_challenge(
         return Err("invalild client data type".into());
     }
     let client_challenge = client_data.challenge;
+    let tenant = shared_state
+        .resolve_tenant(&event.request.client_metadata)
+        .await?;
 
     // obtains the session corresponding to the challenge
     // TODO: we want to save DynamoDB access by first checking if the challenge
     //       was made by InitiateAuth, but we are not allowed to access the
     //       challenge data in PasskeyAuthentication. maybe we can extract it
     //       by serializing PasskeyAuthentication as serde_json::Value
-    let session = shared_state.dynamodb
-        .delete_item()
-        .table_name(shared_state.session_table_name.clone())
-        .key(
-            "pk",
-            AttributeValue::S(format!("discoverable#{}", base64url.encode(client_challenge))),
-        )
-        .return_values(ReturnValue::AllOld)
-        .send()
-        .await?
-        .attributes;
+    let session = take_discoverable_session(
+        &shared_state.sessions,
+        &tenant,
+        &base64url.encode(client_challenge),
+        now,
+    ).await?;
     if let Some(session) = session {
         info!("client-side discoverable credential");
         // session may have expired
-        let ttl: i64 = session.get("ttl")
-            .ok_or("missing ttl")?
-            .as_n()
-            .or(Err("malformed ttl"))?
-            .parse()?;
-        if ttl < DateTime::from(SystemTime::now()).secs() {
+        let TakenSession::Valid(session) = session else {
             return Err("session expired".into());
-        }
-        let auth_state = session.get("state")
-            .ok_or("missing authentication state")?
-            .as_s()
-            .or(Err("malformed authentication state"))?;
+        };
         let auth_state: DiscoverableAuthentication =
-            serde_json::from_str(&auth_state)?;
+            serde_json::from_str(&session.state)?;
 
         // obtains the credentials (passkeys) associated with the user
-        let credentials = shared_state.dynamodb
-            .query()
-            .table_name(shared_state.credential_table_name.clone())
-            .key_condition_expression("pk = :pk")
-            .expression_attribute_values(
-                ":pk",
-                AttributeValue::S(format!("user#{}", user_handle)),
-            )
-            .send()
+        let credentials: Vec<CredentialItem> = shared_state.users
+            .list_credentials(&user_handle)
             .await?
-            .items
-            .ok_or("no credentials")?;
-        let credentials: Vec<&String> = credentials.iter()
-            .map(|c| c.get("credential")
-                .ok_or("missing credential")?
-                .as_s()
-                .or(Err("malformed credential")))
-            .collect::<Result<Vec<_>, _>>()?;
-        let mut passkeys: Vec<Passkey> = credentials.into_iter()
-            .map(|c| serde_json::from_str::<Passkey>(c)
+            .into_iter()
+            .filter(|c| shared_state.is_allowed_credential(c))
+            .collect();
+        let passkeys: Vec<Passkey> = credentials.iter()
+            .map(|c| serde_json::from_str::<Passkey>(&c.credential)
                 .or(Err("malformed credential")))
             .collect::<Result<Vec<_>, _>>()?;
+        let registered = credentials.iter().any(|c| c.credential_id == credential_id);
 
         // verifies the challenge
         let discoverable_keys: Vec<DiscoverableKey> = passkeys.iter()
             .map(|c| c.into())
             .collect();
-        match shared_state.webauthn.finish_discoverable_authentication(
-            &credential,
-            auth_state,
-            &discoverable_keys,
-        ) {
-            Ok(auth_result) => {
+        let verified = info_span!("verify_authentication").in_scope(|| {
+            verify_with_fallback(&tenant, |webauthn| {
+                webauthn.finish_discoverable_authentication(
+                    &credential,
+                    auth_state.clone(),
+                    &discoverable_keys,
+                )
+            })
+        });
+        match verified {
+            Ok((auth_result, _)) if !(
+                auth_result.user_verified()
+                    && is_user_verified_in(credential.response.authenticator_data.as_ref())
+            ) => {
+                error!("user verification required but not performed");
+                shared_state.record_failure(credential_key, registered, now).await?;
+                reject_answer(
+                    &shared_state,
+                    &mut event,
+                    &user_handle,
+                    &credential,
+                    "user not verified",
+                ).await?;
+            }
+            Ok((auth_result, legacy_rp_id)) => {
+                let credential_item = credentials.into_iter()
+                    .find(|c| c.credential_id == credential_id);
+                let rejection = match credential_item.as_ref() {
+                    Some(item) => shared_state
+                        .assess_answer(&tenant, item, auth_result.user_verified())
+                        .await?,
+                    None => None,
+                };
+                if let Some((rejection, detail)) = rejection {
+                    error!("rejected by risk assessment: {}", detail);
+                    reject_answer(
+                        &shared_state,
+                        &mut event,
+                        &user_handle,
+                        &credential,
+                        &detail,
+                    ).await?;
+                    return Err(rejection.into());
+                }
+                if let (Some(lockout), Some(_)) = (shared_state.lockout.as_ref(), lockout_state) {
+                    lockout.reset(credential_key).await?;
+                }
                 // updates the stored credential if necessary
-                for passkey in passkeys.iter_mut() {
-                    let credential_id = base64url.encode(passkey.cred_id());
-                    info!("checking credential updates: {}", credential_id);
-                    let updated_at = DateTime::from(SystemTime::now())
-                        .fmt(DateTimeFormat::DateTime)?;
-                    if passkey.update_credential(&auth_result)
-                        .is_some_and(|b| b)
-                    {
-                        info!("updating credential: {}", credential_id);
-                        shared_state.dynamodb
-                            .update_item()
-                            .table_name(
-                                shared_state.credential_table_name.clone(),
-                            )
-                            .key("pk", AttributeValue::S(
-                                format!("user#{}", user_handle),
-                            ))
-                            .key(
-                                "sk",
-                                AttributeValue::S(
-                                    format!("credential#{}", credential_id),
-                                ),
-                            )
-                            .update_expression("SET credential = :credential, updatedAt = :updatedAt")
-                            .expression_attribute_values(
-                                ":credential",
-                                AttributeValue::S(
-                                    serde_json::to_string(passkey)?,
-                                ),
-                            )
-                            .expression_attribute_values(
-                                ":updatedAt",
-                                AttributeValue::S(updated_at),
-                            )
-                            .condition_expression("attributes_exists(pk)")
-                            .return_values(ReturnValue::None)
-                            .send()
+                if let Some(credential_item) = credential_item {
+                    shared_state
+                        .record_passkey_authentication(&credential_item, auth_result.user_verified(), now)
+                        .await?;
+                    if let Some(legacy_rp_id) = legacy_rp_id {
+                        shared_state.users
+                            .flag_legacy_credential(&credential_item, legacy_rp_id)
                             .await?;
                     }
+                    record_authentication(&shared_state.users, credential_item, &auth_result)
+                        .await?;
                 }
+                publish_authentication(&shared_state, &tenant, &user_handle, credential_id)
+                    .await;
                 event.accept();
             }
             Err(e) => {
                 error!("authentication failed: {}", e);
-                event.reject();
+                shared_state.record_failure(credential_key, registered, now).await?;
+                reject_answer(
+                    &shared_state,
+                    &mut event,
+                    &user_handle,
+                    &credential,
+                    "verification failed",
+                ).await?;
             }
         };
     } else {
@@ -389,90 +754,148 @@ async fn verify_auth_challenge(
         let auth_state: PasskeyAuthentication = event
             .get_private_challenge_parameter(CHALLENGE_PARAMETER_NAME)?
             .ok_or("missing private challenge parameter")?;
-        match shared_state.webauthn.finish_passkey_authentication(
-            &credential,
-            &auth_state,
-        ) {
-            Ok(auth_result) => {
+        let verified = info_span!("verify_authentication").in_scope(|| {
+            verify_with_fallback(&tenant, |webauthn| {
+                webauthn.finish_passkey_authentication(&credential, &auth_state)
+            })
+        });
+        match verified {
+            Ok((auth_result, _)) if !(
+                auth_result.user_verified()
+                    && is_user_verified_in(credential.response.authenticator_data.as_ref())
+            ) => {
+                error!("user verification required but not performed");
+                let registered = shared_state.lockout.is_some()
+                    && shared_state.users.get_credential(credential_key).await?.is_some();
+                shared_state.record_failure(credential_key, registered, now).await?;
+                reject_answer(
+                    &shared_state,
+                    &mut event,
+                    &user_handle,
+                    &credential,
+                    "user not verified",
+                ).await?;
+            }
+            Ok((auth_result, legacy_rp_id)) => {
                 // updates the stored credential if necessary
-                let credential_item = shared_state.dynamodb
-                    .get_item()
-                    .table_name(shared_state.credential_table_name.clone())
-                    .key(
-                        "pk",
-                        AttributeValue::S(format!("user#{}", user_handle)),
-                    )
-                    .key("sk", AttributeValue::S(
-                        format!("credential#{}", base64url.encode(auth_result.cred_id())),
-                    ))
-                    .send()
+                let credential_item = shared_state.users
+                    .get_credential(credential_key)
                     .await?
-                    .item
                     .ok_or("missing credential in the database")?;
-                let passkey = credential_item.get("credential")
-                    .ok_or("malformed credential in the database")?
-                    .as_s()
-                    .or(Err("malformed credential in the database"))?;
-                let mut passkey: Passkey = serde_json::from_str(passkey)
-                    .or(Err("malformed credential in the database"))?;
-                if passkey.update_credential(&auth_result).is_some_and(|b| b) {
-                    info!("updating credential: {:?}", auth_result.cred_id());
-                    let updated_at = DateTime::from(SystemTime::now())
-                        .fmt(DateTimeFormat::DateTime)?;
-                    shared_state.dynamodb
-                        .update_item()
-                        .table_name(
-                            shared_state.credential_table_name.clone(),
-                        )
-                        .key(
-                            "pk",
-                            AttributeValue::S(format!("user#{}", user_handle)),
-                        )
-                        .key(
-                            "sk",
-                            AttributeValue::S(
-                                format!("credential#{}", base64url.encode(auth_result.cred_id())),
-                            ),
-                        )
-                        .update_expression("SET credential = :credential, updatedAt = :updateAt")
-                        .expression_attribute_values(
-                            ":credential",
-                            AttributeValue::S(
-                                serde_json::to_string(&passkey)?,
-                            ),
-                        )
-                        .expression_attribute_values(
-                            ":updatedAt",
-                            AttributeValue::S(updated_at),
-                        )
-                        .condition_expression("attributes_exists(pk)")
-                        .return_values(ReturnValue::None)
-                        .send()
+                if credential_item.disabled_at.is_some() {
+                    error!("credential disabled");
+                    reject_answer(
+                        &shared_state,
+                        &mut event,
+                        &user_handle,
+                        &credential,
+                        "credential disabled",
+                    ).await?;
+                    return Ok(event);
+                }
+                if let Some((rejection, detail)) = shared_state
+                    .assess_answer(&tenant, &credential_item, auth_result.user_verified())
+                    .await?
+                {
+                    error!("rejected by risk assessment: {}", detail);
+                    reject_answer(
+                        &shared_state,
+                        &mut event,
+                        &user_handle,
+                        &credential,
+                        &detail,
+                    ).await?;
+                    return Err(rejection.into());
+                }
+                if let (Some(lockout), Some(_)) = (shared_state.lockout.as_ref(), lockout_state) {
+                    lockout.reset(credential_key).await?;
+                }
+                shared_state
+                    .record_passkey_authentication(&credential_item, auth_result.user_verified(), now)
+                    .await?;
+                if let Some(legacy_rp_id) = legacy_rp_id {
+                    shared_state.users
+                        .flag_legacy_credential(&credential_item, legacy_rp_id)
                         .await?;
                 }
+                record_authentication(&shared_state.users, credential_item, &auth_result)
+                    .await?;
+                publish_authentication(&shared_state, &tenant, &user_handle, credential_id)
+                    .await;
                 event.accept();
             }
             Err(e) => {
                 error!("authentication failed: {}", e);
-                event.reject();
+                let registered = shared_state.lockout.is_some()
+                    && shared_state.users.get_credential(credential_key).await?.is_some();
+                shared_state.record_failure(credential_key, registered, now).await?;
+                reject_answer(
+                    &shared_state,
+                    &mut event,
+                    &user_handle,
+                    &credential,
+                    "verification failed",
+                ).await?;
             }
         };
     }
     Ok(event)
 }
 
+// publishes a successful authentication.
+async fn publish_authentication(
+    shared_state: &SharedState,
+    tenant: &Tenant,
+    user_handle: &str,
+    credential_id: String,
+) {
+    publish_event(
+        shared_state.event_publisher.as_ref(),
+        DomainEvent::AuthenticationSucceeded(AuthenticationSucceeded {
+            user_handle: user_handle.into(),
+            credential_id,
+            tenant_id: tenant.id().map(Into::into),
+        }),
+    ).await;
+}
+
+// rejects a challenge answer and records the failure in the audit log.
+async fn reject_answer(
+    shared_state: &SharedState,
+    event: &mut CognitoEventUserPoolsVerifyAuthChallenge,
+    user_handle: &str,
+    credential: &PublicKeyCredential,
+    reason: &str,
+) -> Result<(), Error> {
+    event.reject();
+    if let Some(audit_log) = shared_state.audit_log.as_ref() {
+        audit_log.record_best_effort(AuditEvent {
+            event_type: AuditEventType::AuthenticationFailed,
+            user_handle: user_handle.into(),
+            credential_id: Some(credential.id.clone()),
+            client: ClientInfo::of_client_metadata(&event.request.client_metadata),
+            detail: Some(reason.into()),
+        }).await;
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::INFO)
-        // disable printing the name of the module in every log line.
-        .with_target(false)
-        // disabling time is handy because CloudWatch will add the ingestion time.
-        .without_time()
-        .init();
+    let started_at = Instant::now();
+    let telemetry = init_tracing("user-pool-triggers")?;
 
-    let shared_state = Arc::new(SharedState::new().await?);
+    let sdk_config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+    load_config_parameters(&aws_sdk_ssm::Client::new(&sdk_config)).await?;
+    let config = Config::from_env(aws_sdk_secretsmanager::Client::new(&sdk_config))?;
+    let shared_state = Arc::new(SharedState::new(&sdk_config, config).await?);
+    let metrics = load_metrics("user-pool-triggers")?;
+    let cold_start = ColdStart::initialized_since(started_at);
     run(service_fn(|req| async {
-        function_handler(shared_state.clone(), req).await
+        let handler_started_at = Instant::now();
+        let res = function_handler(shared_state.clone(), req).await;
+        cold_start.report(&metrics, handler_started_at.elapsed());
+        telemetry.flush().await;
+        res
     })).await
 }
@@ -0,0 +1,127 @@
+//! Association of native apps with the relying party.
+//!
+//! Serves the following files at the root of the domain of the relying party
+//! ID, generated from the configuration:
+//! - `GET /.well-known/assetlinks.json`: Digital Asset Links for Android apps
+//! - `GET /.well-known/apple-app-site-association`: associated domains for
+//!   Apple apps
+//!
+//! A file responds with 404 unless the apps of its platform are configured.
+//! See [`authentication::well_known`] for details.
+//!
+//! You can optionally configure the following environment variables:
+//! - `CONFIG_PARAMETER_PATH`: path to the parameters in Parameter Store on
+//!   AWS Systems Manager that override the other environment variables. See
+//!   [`authentication::config`] for details.
+//! - `ANDROID_PACKAGE_NAMES`: comma-separated package names of the Android
+//!   apps
+//! - `ANDROID_APK_KEY_HASHES`: comma-separated SHA-256 hashes of the
+//!   certificates that sign the Android apps. Must be the same as the other
+//!   functions. See [`authentication::android`] for details.
+//! - `APPLE_APP_IDS`: comma-separated app IDs of the Apple apps;
+//!   "<team ID>.<bundle ID>"
+//! - `LOG_LEVEL`, `LOG_REDACTION`: log level or `RUST_LOG`-style directives,
+//!   and whether identifiers are redacted in logs; "info" and redacted by
+//!   default. See [`authentication::telemetry`] for details.
+//! - `METRICS_NAMESPACE`: namespace of the CloudWatch metrics; "PasskeyTest"
+//!   by default.
+//!
+//! Unlike the other HTTP functions, this function serves no health check,
+//! because the paths are fixed at the root of the domain.
+
+use lambda_http::{
+    Body,
+    Error,
+    Request,
+    RequestExt,
+    Response,
+    http::{Method, StatusCode},
+};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{Instrument, info, instrument};
+
+use authentication::api_error::{ApiError, handle_api_errors};
+use authentication::config::load_config_parameters;
+use authentication::metrics::{ColdStart, load_metrics};
+use authentication::routing::require_method;
+use authentication::telemetry::{init_tracing, request_span};
+use authentication::warmer::run_with_warmer;
+use authentication::well_known::{
+    APPLE_APP_SITE_ASSOCIATION_PATH,
+    ASSET_LINKS_PATH,
+    AppAssociation,
+    load_app_association,
+};
+
+// Clients and crawlers may cache the files for an hour.
+const CACHE_CONTROL: &str = "public, max-age=3600";
+
+// State shared among Lambda invocations.
+struct SharedState {
+    association: AppAssociation,
+}
+
+impl SharedState {
+    #[instrument(name = "cold_start")]
+    async fn new() -> Result<Self, Error> {
+        let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+        load_config_parameters(&aws_sdk_ssm::Client::new(&config)).await?;
+        Ok(Self {
+            association: load_app_association()?,
+        })
+    }
+}
+
+async fn function_handler(
+    shared_state: Arc<SharedState>,
+    event: Request,
+) -> Result<Response<Body>, Error> {
+    let path = event.raw_http_path();
+    info!("well-known: {}", path);
+    require_method(&event, Method::GET)?;
+    match path {
+        ASSET_LINKS_PATH => json_file(
+            shared_state.association.asset_links()
+                .ok_or(ApiError::NotConfigured("no Android apps"))?,
+        ),
+        APPLE_APP_SITE_ASSOCIATION_PATH => json_file(
+            shared_state.association.apple_app_site_association()
+                .ok_or(ApiError::NotConfigured("no Apple apps"))?,
+        ),
+        _ => Err(format!("unsupported path: {}", path).into()),
+    }
+}
+
+// creates a 200 response of a cacheable JSON file.
+//
+// the Apple App Site Association has no extension but must be served as
+// `application/json` too.
+fn json_file(body: impl Serialize) -> Result<Response<Body>, Error> {
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", "application/json")
+        .header("Cache-Control", CACHE_CONTROL)
+        .body(serde_json::to_string(&body)?.into())?)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Error> {
+    let started_at = Instant::now();
+    let telemetry = init_tracing("well-known")?;
+
+    let shared_state = Arc::new(SharedState::new().await?);
+    let metrics = load_metrics("well-known")?;
+    let cold_start = ColdStart::initialized_since(started_at);
+    run_with_warmer(&cold_start, &metrics, |req: Request| async {
+        let span = request_span(&req);
+        let handler_started_at = Instant::now();
+        let res = handle_api_errors(function_handler(shared_state.clone(), req))
+            .instrument(span)
+            .await;
+        cold_start.report(&metrics, handler_started_at.elapsed());
+        telemetry.flush().await;
+        res
+    }).await
+}
@@ -0,0 +1,121 @@
+//! Synthetic canary.
+//!
+//! Defines the steps the `canary` binary runs against a deployed stage with
+//! the software authenticator in [`crate::authenticator`].
+//! Every step is expected to succeed; a failure tells a regression in the
+//! configuration or the tables of the stage.
+//!
+//! Every run reports the following metrics:
+//! - `canary_<step>_success`: 1 if the step succeeded, 0 if it failed.
+//!   Steps after a failed step are not run and not reported.
+//! - `canary_<step>_latency`: time spent on the step
+//! - [`CANARY_SUCCESS_METRIC`]: 1 if every step succeeded, 0 otherwise
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::metrics::{Metrics, Unit};
+
+/// Prefix of usernames registered by the canary.
+///
+/// A user is deleted at the end of a successful run. Users left by failed
+/// runs can be found by the prefix.
+pub const USERNAME_PREFIX: &str = "canary-";
+
+/// Metric that tells whether every step of a run succeeded.
+pub const CANARY_SUCCESS_METRIC: &str = "canary_success";
+
+/// Step of a run.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CanaryStep {
+    /// Registration of a new user with a passkey.
+    Registration,
+
+    /// Authentication with the registered passkey through Cognito.
+    Authentication,
+
+    /// Deletion of the account after a step-up.
+    Cleanup,
+}
+
+impl CanaryStep {
+    /// All the steps in the order they are run.
+    pub const ALL: [CanaryStep; 3] = [
+        CanaryStep::Registration,
+        CanaryStep::Authentication,
+        CanaryStep::Cleanup,
+    ];
+
+    /// Name of the step.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CanaryStep::Registration => "registration",
+            CanaryStep::Authentication => "authentication",
+            CanaryStep::Cleanup => "cleanup",
+        }
+    }
+
+    /// Name of the metric that tells whether the step succeeded.
+    pub fn success_metric(&self) -> &'static str {
+        match self {
+            CanaryStep::Registration => "canary_registration_success",
+            CanaryStep::Authentication => "canary_authentication_success",
+            CanaryStep::Cleanup => "canary_cleanup_success",
+        }
+    }
+
+    /// Name of the metric of the time spent on the step.
+    pub fn latency_metric(&self) -> &'static str {
+        match self {
+            CanaryStep::Registration => "canary_registration_latency",
+            CanaryStep::Authentication => "canary_authentication_latency",
+            CanaryStep::Cleanup => "canary_cleanup_latency",
+        }
+    }
+
+    /// Reports the result of the step.
+    pub fn report(&self, metrics: &Metrics, passed: bool, latency: Duration) {
+        metrics.put(self.success_metric(), success_value(passed), Unit::Count);
+        metrics.latency(self.latency_metric(), latency);
+    }
+}
+
+impl fmt::Display for CanaryStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Reports whether every step of a run succeeded.
+pub fn report_run(metrics: &Metrics, passed: bool) {
+    metrics.put(CANARY_SUCCESS_METRIC, success_value(passed), Unit::Count);
+}
+
+fn success_value(passed: bool) -> f64 {
+    if passed { 1.0 } else { 0.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canary_step_metrics_should_be_named_after_step() {
+        for step in CanaryStep::ALL {
+            assert_eq!(
+                step.success_metric(),
+                format!("canary_{}_success", step.name()),
+            );
+            assert_eq!(
+                step.latency_metric(),
+                format!("canary_{}_latency", step.name()),
+            );
+        }
+    }
+
+    #[test]
+    fn success_value_should_be_one_or_zero() {
+        assert_eq!(success_value(true), 1.0);
+        assert_eq!(success_value(false), 0.0);
+    }
+}
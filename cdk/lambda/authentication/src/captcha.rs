@@ -0,0 +1,268 @@
+//! Verification of CAPTCHA tokens.
+//!
+//! The `start` endpoints may require a token proving that a human solved a
+//! CAPTCHA, so that automation is rejected before any item is written to the
+//! DynamoDB tables, including the counters of [`crate::rate_limit`].
+//! The token is passed in a header of the request.
+//!
+//! The following providers are supported:
+//! - [Cloudflare Turnstile](https://developers.cloudflare.com/turnstile/):
+//!   the token is verified with the siteverify API and the secret key.
+//! - [AWS WAF CAPTCHA](https://docs.aws.amazon.com/waf/latest/developerguide/waf-captcha-and-challenge.html):
+//!   AWS WAF in front of the API verifies the token, and a `CAPTCHA` rule
+//!   inserts a custom header into the request it lets through. The value of the
+//!   header must equal the secret, so that a request bypassing AWS WAF cannot
+//!   forge it.
+//!
+//! A request without a valid token ends with 403 and `captcha_required`.
+
+use lambda_http::{Body, Request, Response, http::StatusCode};
+use ring::digest;
+use serde::Deserialize;
+use serde_json::json;
+use std::env;
+use std::time::Duration;
+use tracing::{error, instrument};
+
+use crate::config;
+use crate::error::Error;
+use crate::payload::ErrorResponseBody;
+use crate::rate_limit::source_ip;
+use crate::secrets::{SecretCache, load_secret_cache_ttl};
+
+/// URL of the siteverify API of Cloudflare Turnstile.
+pub const TURNSTILE_VERIFY_URL: &str =
+    "https://challenges.cloudflare.com/turnstile/v0/siteverify";
+
+/// Default header of a Turnstile token.
+pub const DEFAULT_TURNSTILE_HEADER: &str = "CF-Turnstile-Response";
+
+/// Default header that AWS WAF inserts into a request with a valid token.
+///
+/// AWS WAF prefixes "x-amzn-waf-" to the name of a custom header; i.e., the
+/// custom header of the rule should be named "captcha-verified".
+pub const DEFAULT_AWS_WAF_HEADER: &str = "X-Amzn-Waf-Captcha-Verified";
+
+/// Error code of a request without a valid token.
+pub const CAPTCHA_REQUIRED: &str = "captcha_required";
+
+// timeout of the siteverify API.
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Provider of CAPTCHA tokens.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CaptchaProvider {
+    /// Cloudflare Turnstile.
+    Turnstile,
+    /// AWS WAF CAPTCHA.
+    AwsWaf,
+}
+
+impl CaptchaProvider {
+    /// Parses a string; "turnstile" or "aws-waf".
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "turnstile" => Some(Self::Turnstile),
+            "aws-waf" => Some(Self::AwsWaf),
+            _ => None,
+        }
+    }
+
+    /// Returns the default header of the token.
+    pub fn default_header(self) -> &'static str {
+        match self {
+            Self::Turnstile => DEFAULT_TURNSTILE_HEADER,
+            Self::AwsWaf => DEFAULT_AWS_WAF_HEADER,
+        }
+    }
+}
+
+/// Verifier of CAPTCHA tokens.
+pub struct CaptchaVerifier {
+    provider: CaptchaProvider,
+    header: String,
+    secret_id: String,
+    secrets: SecretCache,
+    http: reqwest::Client,
+}
+
+/// Response from the siteverify API of Turnstile.
+#[derive(Clone, Debug, Deserialize)]
+struct TurnstileResponse {
+    success: bool,
+    #[serde(default, rename = "error-codes")]
+    error_codes: Vec<String>,
+}
+
+/// Loads the verifier of CAPTCHA tokens.
+///
+/// You can specify the following environment variables:
+/// - `CAPTCHA_PROVIDER`: "turnstile" or "aws-waf"
+/// - `CAPTCHA_SECRET_ID`: ID of the secret in Secrets Manager; the secret key
+///   of Turnstile, or the value of the header that AWS WAF inserts.
+///   Mandatory if `CAPTCHA_PROVIDER` is set.
+/// - `CAPTCHA_HEADER`: header of the token; [`DEFAULT_TURNSTILE_HEADER`] or
+///   [`DEFAULT_AWS_WAF_HEADER`] by default.
+///
+/// Returns `None` if `CAPTCHA_PROVIDER` is not set, which means no tokens are
+/// required.
+pub fn load_captcha_verifier(
+    secrets: aws_sdk_secretsmanager::Client,
+) -> Result<Option<CaptchaVerifier>, Error> {
+    let provider = match config::var("CAPTCHA_PROVIDER") {
+        Ok(provider) => CaptchaProvider::parse(&provider)
+            .ok_or(Error::BadEnvironmentVariable("CAPTCHA_PROVIDER", provider))?,
+        Err(env::VarError::NotPresent) => return Ok(None),
+        Err(env::VarError::NotUnicode(provider)) => return Err(
+            Error::BadEnvironmentVariable(
+                "CAPTCHA_PROVIDER",
+                provider.to_string_lossy().into(),
+            ),
+        ),
+    };
+    let secret_id = config::var("CAPTCHA_SECRET_ID")
+        .ok()
+        .filter(|id| !id.is_empty())
+        .ok_or(Error::BadEnvironmentVariable("CAPTCHA_SECRET_ID", "".into()))?;
+    let header = config::var("CAPTCHA_HEADER")
+        .unwrap_or_else(|_| provider.default_header().into());
+    let http = reqwest::Client::builder()
+        .timeout(VERIFY_TIMEOUT)
+        .build()
+        .or(Err(Error::Captcha("failed to build HTTP client")))?;
+    Ok(Some(CaptchaVerifier {
+        provider,
+        header,
+        secret_id,
+        secrets: SecretCache::new(secrets, load_secret_cache_ttl()?),
+        http,
+    }))
+}
+
+impl CaptchaVerifier {
+    /// Provider of the tokens.
+    pub fn provider(&self) -> CaptchaProvider {
+        self.provider
+    }
+
+    /// Verifies the token in a given request.
+    ///
+    /// Returns `false` if the token is missing or invalid.
+    #[instrument(skip_all, fields(provider = ?self.provider))]
+    pub async fn verify(&self, request: &Request) -> Result<bool, Error> {
+        let Some(token) = request.headers()
+            .get(&self.header)
+            .and_then(|v| v.to_str().ok())
+            .filter(|v| !v.is_empty()) else
+        {
+            error!("missing CAPTCHA token: {}", self.header);
+            return Ok(false);
+        };
+        let secret = self.secrets.get(&self.secret_id).await?;
+        match self.provider {
+            CaptchaProvider::Turnstile =>
+                self.verify_turnstile(token, &secret, source_ip(request)).await,
+            CaptchaProvider::AwsWaf => Ok(equals_secret(token, &secret)),
+        }
+    }
+
+    async fn verify_turnstile(
+        &self,
+        token: &str,
+        secret: &str,
+        remote_ip: Option<String>,
+    ) -> Result<bool, Error> {
+        let mut body = json!({ "secret": secret, "response": token });
+        if let Some(remote_ip) = remote_ip {
+            body["remoteip"] = remote_ip.into();
+        }
+        let res: TurnstileResponse = self.http
+            .post(TURNSTILE_VERIFY_URL)
+            .json(&body)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| {
+                error!(?e, "verifying Turnstile token");
+                Error::Captcha("failed to verify Turnstile token")
+            })?
+            .json()
+            .await
+            .map_err(|e| {
+                error!(?e, "parsing Turnstile response");
+                Error::Captcha("malformed Turnstile response")
+            })?;
+        if !res.success {
+            error!("invalid Turnstile token: {:?}", res.error_codes);
+        }
+        Ok(res.success)
+    }
+}
+
+// compares digests so that the time does not tell how much of the secret
+// matches.
+fn equals_secret(value: &str, secret: &str) -> bool {
+    digest::digest(&digest::SHA256, value.as_bytes()).as_ref()
+        == digest::digest(&digest::SHA256, secret.as_bytes()).as_ref()
+}
+
+/// Requires a valid CAPTCHA token in a given request.
+///
+/// Returns a 403 response if the token is missing or invalid.
+/// Always passes if `verifier` is `None`.
+pub async fn require_captcha(
+    verifier: Option<&CaptchaVerifier>,
+    request: &Request,
+) -> Result<Option<Response<Body>>, lambda_http::Error> {
+    match verifier {
+        Some(verifier) if !verifier.verify(request).await? =>
+            Ok(Some(captcha_required()?)),
+        _ => Ok(None),
+    }
+}
+
+/// Creates a 403 response for a request without a valid CAPTCHA token.
+pub fn captcha_required() -> Result<Response<Body>, lambda_http::Error> {
+    let body = serde_json::to_string(&ErrorResponseBody {
+        error: CAPTCHA_REQUIRED,
+        message: "CAPTCHA verification required".into(),
+        field: None,
+        localized_message: None,
+        request_id: None,
+    })?;
+    Ok(Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .header("Content-Type", "application/json")
+        .body(body.into())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captcha_provider_should_parse_provider() {
+        assert_eq!(CaptchaProvider::parse("turnstile"), Some(CaptchaProvider::Turnstile));
+        assert_eq!(CaptchaProvider::parse("aws-waf"), Some(CaptchaProvider::AwsWaf));
+        assert_eq!(CaptchaProvider::parse("recaptcha"), None);
+    }
+
+    #[test]
+    fn equals_secret_should_compare_whole_value() {
+        assert!(equals_secret("s3cret", "s3cret"));
+        assert!(!equals_secret("s3cre", "s3cret"));
+        assert!(!equals_secret("", "s3cret"));
+    }
+
+    #[test]
+    fn turnstile_response_should_parse_error_codes() {
+        let res: TurnstileResponse = serde_json::from_str(
+            r#"{"success":false,"error-codes":["invalid-input-response"]}"#,
+        ).unwrap();
+        assert!(!res.success);
+        assert_eq!(res.error_codes, vec!["invalid-input-response"]);
+        let res: TurnstileResponse = serde_json::from_str(r#"{"success":true}"#)
+            .unwrap();
+        assert!(res.success);
+    }
+}
@@ -0,0 +1,157 @@
+//! Binding of sessions to clients.
+//!
+//! A challenge issued to a legitimate client may be relayed to another client
+//! that finishes the ceremony. If the binding is enabled, the source IP
+//! and the SHA-256 hash of the user agent of the client that starts a
+//! ceremony are stored with the session, and the ceremony fails if it is
+//! finished by a client from a different network or browser.
+//!
+//! The binding is opt-in, because the IP address of a mobile client may
+//! change during a ceremony.
+
+use base64::{
+    Engine as _,
+    engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
+};
+use ring::digest;
+use std::env;
+
+use crate::audit::ClientInfo;
+use crate::config;
+use crate::error::Error;
+
+/// What a session is bound to.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ClientBindingPolicy {
+    /// Whether a session is bound to the source IP.
+    pub source_ip: bool,
+
+    /// Whether a session is bound to the user agent.
+    pub user_agent: bool,
+}
+
+/// Loads the client binding policy.
+///
+/// You can specify to `CLIENT_BINDING` environment variable a comma-separated
+/// list of the following values:
+/// - "ip": source IP of the client
+/// - "user-agent": user agent of the client
+///
+/// Returns the policy that binds nothing if `CLIENT_BINDING` is not set.
+pub fn load_client_binding_policy() -> Result<ClientBindingPolicy, Error> {
+    match config::var("CLIENT_BINDING") {
+        Ok(policy) => parse_client_binding_policy(&policy).ok_or(
+            Error::BadEnvironmentVariable("CLIENT_BINDING", policy),
+        ),
+        Err(env::VarError::NotPresent) => Ok(ClientBindingPolicy::default()),
+        Err(env::VarError::NotUnicode(policy)) => Err(
+            Error::BadEnvironmentVariable(
+                "CLIENT_BINDING",
+                policy.to_string_lossy().into(),
+            ),
+        ),
+    }
+}
+
+fn parse_client_binding_policy(policy: &str) -> Option<ClientBindingPolicy> {
+    let mut parsed = ClientBindingPolicy::default();
+    for value in policy.split(',').map(str::trim).filter(|v| !v.is_empty()) {
+        match value {
+            "ip" => parsed.source_ip = true,
+            "user-agent" => parsed.user_agent = true,
+            _ => return None,
+        }
+    }
+    Some(parsed)
+}
+
+impl ClientBindingPolicy {
+    /// Binds a session to a given client.
+    pub fn bind(&self, client: &ClientInfo) -> ClientBinding {
+        ClientBinding {
+            source_ip: client.source_ip.clone().filter(|_| self.source_ip),
+            user_agent_hash: client.user_agent.as_deref()
+                .filter(|_| self.user_agent)
+                .map(hash_user_agent),
+        }
+    }
+}
+
+/// Client to which a session is bound.
+///
+/// Each attribute is `None` if the session is not bound to it.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ClientBinding {
+    /// Source IP.
+    pub source_ip: Option<String>,
+
+    /// "base64url"-encoded SHA-256 hash of the user agent.
+    pub user_agent_hash: Option<String>,
+}
+
+impl ClientBinding {
+    /// Returns whether a given client is the one to which the session is
+    /// bound.
+    ///
+    /// Only the bound attributes are compared, so sessions started before the
+    /// binding is enabled are not affected.
+    pub fn matches(&self, client: &ClientInfo) -> bool {
+        let source_ip_matches = self.source_ip.as_ref()
+            .map_or(true, |ip| client.source_ip.as_ref() == Some(ip));
+        let user_agent_matches = self.user_agent_hash.as_ref()
+            .map_or(true, |hash| {
+                client.user_agent.as_deref().map(hash_user_agent).as_ref() == Some(hash)
+            });
+        source_ip_matches && user_agent_matches
+    }
+}
+
+fn hash_user_agent(user_agent: &str) -> String {
+    base64url.encode(digest::digest(&digest::SHA256, user_agent.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(source_ip: &str, user_agent: &str) -> ClientInfo {
+        ClientInfo {
+            source_ip: Some(source_ip.into()),
+            user_agent: Some(user_agent.into()),
+            synthetic_label: None,
+        }
+    }
+
+    #[test]
+    fn parse_client_binding_policy_should_accept_ip_and_user_agent() {
+        assert_eq!(
+            parse_client_binding_policy("ip, user-agent"),
+            Some(ClientBindingPolicy { source_ip: true, user_agent: true }),
+        );
+        assert_eq!(
+            parse_client_binding_policy("user-agent"),
+            Some(ClientBindingPolicy { source_ip: false, user_agent: true }),
+        );
+        assert_eq!(parse_client_binding_policy(""), Some(ClientBindingPolicy::default()));
+        assert_eq!(parse_client_binding_policy("ip,cookie"), None);
+    }
+
+    #[test]
+    fn client_binding_should_reject_different_client() {
+        let policy = ClientBindingPolicy { source_ip: true, user_agent: true };
+        let binding = policy.bind(&client("192.0.2.1", "Browser/1"));
+        assert!(binding.matches(&client("192.0.2.1", "Browser/1")));
+        assert!(!binding.matches(&client("192.0.2.2", "Browser/1")));
+        assert!(!binding.matches(&client("192.0.2.1", "Browser/2")));
+        assert!(!binding.matches(&ClientInfo::default()));
+    }
+
+    #[test]
+    fn client_binding_should_compare_only_bound_attributes() {
+        let policy = ClientBindingPolicy { source_ip: false, user_agent: true };
+        let binding = policy.bind(&client("192.0.2.1", "Browser/1"));
+        assert_eq!(binding.source_ip, None);
+        assert!(binding.matches(&client("192.0.2.2", "Browser/1")));
+        assert!(ClientBinding::default().matches(&ClientInfo::default()));
+    }
+}
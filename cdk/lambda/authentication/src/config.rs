@@ -0,0 +1,269 @@
+//! Configuration.
+//!
+//! Configuration values are resolved from parameters under a path in
+//! Parameter Store on AWS Systems Manager, falling back to environment
+//! variables. Parameters are loaded once at cold start, so you can change the
+//! configuration by updating parameters and letting the Lambda functions
+//! restart, without redeploying them.
+//!
+//! A parameter is named after the environment variable it replaces; e.g.,
+//! `/passkey-test/config/CHALLENGE_TIMEOUT` for `CHALLENGE_TIMEOUT`.
+//!
+//! ## Validation at cold start
+//!
+//! Every binary validates its configuration in `main` with a typed
+//! `Config::from_env`, so that a misconfigured function fails at cold start
+//! instead of failing requests with 500. [`ConfigCheck`] collects every
+//! missing or invalid variable, and [`ConfigCheck::finish`] fails with
+//! [`Error::BadConfiguration`] listing all of them at once.
+
+use std::collections::HashMap;
+use std::env;
+use std::sync::OnceLock;
+use tracing::{error, info};
+
+use crate::error::Error;
+
+// Parameters loaded at cold start.
+static PARAMETERS: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// Loads the configuration parameters from the Parameter Store.
+///
+/// You can specify to `CONFIG_PARAMETER_PATH` environment variable the path
+/// under which the configuration parameters are stored; e.g.,
+/// `/passkey-test/config/`.
+///
+/// Does nothing if `CONFIG_PARAMETER_PATH` is not set, or the parameters have
+/// already been loaded.
+pub async fn load_config_parameters(ssm: &aws_sdk_ssm::Client) -> Result<(), Error> {
+    if PARAMETERS.get().is_some() {
+        return Ok(());
+    }
+    let path = match env::var("CONFIG_PARAMETER_PATH") {
+        Ok(path) => path,
+        Err(_) => {
+            let _ = PARAMETERS.set(HashMap::new());
+            return Ok(());
+        }
+    };
+    let mut parameters = HashMap::new();
+    let mut pages = ssm.get_parameters_by_path()
+        .path(path.clone())
+        .recursive(false)
+        .with_decryption(true)
+        .into_paginator()
+        .send();
+    while let Some(page) = pages.next().await {
+        let page = page.map_err(|e| {
+            error!(?e, "getting SSM parameters by path");
+            Error::ParameterNotFound("CONFIG_PARAMETER_PATH")
+        })?;
+        for parameter in page.parameters.unwrap_or_default() {
+            if let (Some(name), Some(value)) = (parameter.name, parameter.value) {
+                if let Some(key) = parameter_key(&path, &name) {
+                    parameters.insert(key.to_string(), value);
+                }
+            }
+        }
+    }
+    info!(
+        "loaded configuration parameters: {:?}",
+        parameters.keys().collect::<Vec<_>>(),
+    );
+    let _ = PARAMETERS.set(parameters);
+    Ok(())
+}
+
+/// Returns a configuration value.
+///
+/// A parameter loaded by [`load_config_parameters`] takes precedence over the
+/// environment variable of the same name.
+///
+/// Fails in the same way as [`std::env::var`] if neither exists.
+pub fn var(name: &str) -> Result<String, env::VarError> {
+    match PARAMETERS.get().and_then(|p| p.get(name)) {
+        Some(value) => Ok(value.clone()),
+        None => env::var(name),
+    }
+}
+
+/// Collector of configuration problems.
+///
+/// Reads variables and runs loaders without failing on the first problem.
+/// A missing or invalid value is recorded and replaced with a placeholder,
+/// which never escapes because [`ConfigCheck::finish`] fails if any problem
+/// has been recorded.
+///
+/// `Config::from_env` of every binary reads its configuration through a
+/// check, so that it fails with every missing or invalid variable at once.
+/// A value that may be absent only if a problem has been recorded should be
+/// unwrapped after [`ConfigCheck::finish`] rather than with `expect`, so that
+/// a cold start never panics.
+#[derive(Debug, Default)]
+pub struct ConfigCheck {
+    problems: Vec<String>,
+}
+
+impl ConfigCheck {
+    /// Creates a check without any problem.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads a required variable.
+    ///
+    /// Records a problem if the variable is not set, empty, or not Unicode.
+    pub fn required(&mut self, name: &'static str) -> String {
+        self.record_required(name, var(name))
+    }
+
+    /// Requires at least one of given variables.
+    ///
+    /// Useful when a value may be configured directly or through a parameter;
+    /// e.g., `RP_ORIGIN` or `RP_ORIGIN_PARAMETER_PATH`.
+    pub fn required_any(&mut self, names: &[&'static str]) {
+        if !names.iter().any(|name| var(name).is_ok_and(|value| !value.is_empty())) {
+            self.problems.push(format!("{} must be set", names.join(" or ")));
+        }
+    }
+
+    /// Takes the result of a loader.
+    ///
+    /// Records the error and returns the default value if the loader failed.
+    pub fn load<T: Default>(&mut self, res: Result<T, Error>) -> T {
+        self.load_or(res, T::default())
+    }
+
+    /// Takes the result of a loader, or a given placeholder if it failed.
+    ///
+    /// For values without [`Default`].
+    pub fn load_or<T>(&mut self, res: Result<T, Error>, placeholder: T) -> T {
+        match res {
+            Ok(value) => value,
+            Err(e) => {
+                self.problems.push(e.to_string());
+                placeholder
+            }
+        }
+    }
+
+    /// Returns the recorded problems.
+    pub fn problems(&self) -> &[String] {
+        &self.problems
+    }
+
+    /// Returns a given configuration if no problem has been recorded.
+    ///
+    /// Fails with [`Error::BadConfiguration`] listing every problem otherwise.
+    pub fn finish<T>(self, config: T) -> Result<T, Error> {
+        if self.problems.is_empty() {
+            Ok(config)
+        } else {
+            for problem in &self.problems {
+                error!("configuration: {}", problem);
+            }
+            Err(Error::BadConfiguration(self.problems))
+        }
+    }
+
+    fn record_required(
+        &mut self,
+        name: &'static str,
+        value: Result<String, env::VarError>,
+    ) -> String {
+        match value {
+            Ok(value) if !value.is_empty() => value,
+            Ok(_) | Err(env::VarError::NotPresent) => {
+                self.problems.push(format!("{} must be set", name));
+                String::new()
+            }
+            Err(env::VarError::NotUnicode(value)) => {
+                self.problems.push(
+                    Error::BadEnvironmentVariable(name, value.to_string_lossy().into())
+                        .to_string(),
+                );
+                String::new()
+            }
+        }
+    }
+}
+
+// Extracts the key of a parameter from its full name under a given path.
+fn parameter_key<'a>(path: &str, name: &'a str) -> Option<&'a str> {
+    let key = name.strip_prefix(path.trim_end_matches('/'))?
+        .strip_prefix('/')?;
+    if key.is_empty() || key.contains('/') {
+        None
+    } else {
+        Some(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parameter_key_should_strip_path() {
+        assert_eq!(
+            parameter_key("/passkey-test/config/", "/passkey-test/config/RP_ID"),
+            Some("RP_ID"),
+        );
+        assert_eq!(
+            parameter_key("/passkey-test/config", "/passkey-test/config/RP_ID"),
+            Some("RP_ID"),
+        );
+    }
+
+    #[test]
+    fn config_check_should_collect_every_problem() {
+        let mut check = ConfigCheck::new();
+        assert_eq!(check.record_required("BASE_PATH", Ok("/auth/".into())), "/auth/");
+        assert_eq!(
+            check.record_required("SESSION_TABLE_NAME", Err(env::VarError::NotPresent)),
+            "",
+        );
+        check.record_required("USER_POOL_ID", Ok("".into()));
+        assert_eq!(
+            check.load(Err::<usize, _>(
+                Error::BadEnvironmentVariable("MAX_BODY_SIZE", "0".into()),
+            )),
+            0,
+        );
+        assert_eq!(check.load_or(Ok(3), 1), 3);
+        check.required_any(&["PASSKEY_TEST_UNSET_A", "PASSKEY_TEST_UNSET_B"]);
+        assert_eq!(check.problems(), [
+            "SESSION_TABLE_NAME must be set",
+            "USER_POOL_ID must be set",
+            "bad environment variable MAX_BODY_SIZE: `0`",
+            "PASSKEY_TEST_UNSET_A or PASSKEY_TEST_UNSET_B must be set",
+        ]);
+        match check.finish(()) {
+            Err(Error::BadConfiguration(problems)) => assert_eq!(problems.len(), 4),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn config_check_should_pass_valid_configuration() {
+        let mut check = ConfigCheck::new();
+        let base_path = check.record_required("BASE_PATH", Ok("/auth/".into()));
+        assert_eq!(check.finish(base_path).unwrap(), "/auth/");
+    }
+
+    #[test]
+    fn parameter_key_should_reject_parameters_outside_path() {
+        assert_eq!(
+            parameter_key("/passkey-test/config/", "/passkey-test/RP_ORIGIN"),
+            None,
+        );
+        assert_eq!(
+            parameter_key("/passkey-test/config/", "/passkey-test/configuration/RP_ID"),
+            None,
+        );
+        assert_eq!(
+            parameter_key("/passkey-test/config/", "/passkey-test/config/nested/RP_ID"),
+            None,
+        );
+    }
+}
@@ -0,0 +1,458 @@
+//! Content negotiation between JSON and CBOR.
+//!
+//! Every endpoint speaks JSON, and accepts `application/cbor` as well:
+//! - a CBOR request body (`Content-Type: application/cbor`) is transcoded into
+//!   JSON before it reaches the handler; byte strings become
+//!   "base64url"-encoded strings, which the Webauthn library accepts in place
+//!   of binary fields, so clients may send raw attestations and assertions
+//! - a JSON response body is transcoded into CBOR if the `Accept` header
+//!   prefers `application/cbor`
+//!
+//! A response body is also compressed with Brotli or gzip if the
+//! `Accept-Encoding` header allows it and the body is large enough to be
+//! worth it; e.g., `CreationChallengeResponse` with a long exclude list.
+//!
+//! An error response is given the message localized into the language that
+//! the `Accept-Language` header prefers; see [`crate::i18n`]. It is also
+//! given the correlation ID of the request; see [`crate::correlation`].
+//!
+//! See [`negotiate_content`].
+
+use base64::{
+    Engine as _,
+    engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
+};
+use flate2::{Compression, write::GzEncoder};
+use lambda_http::{
+    Body,
+    Request,
+    Response,
+    http::header::{
+        ACCEPT,
+        ACCEPT_ENCODING,
+        CONTENT_ENCODING,
+        CONTENT_TYPE,
+        HeaderValue,
+        VARY,
+    },
+};
+use std::future::Future;
+use std::io::Write as _;
+use tracing::error;
+
+use crate::correlation::{add_request_id, correlation_of};
+use crate::i18n::{localize_error_response, preferred_language};
+use crate::payload::PayloadError;
+
+/// Media type of CBOR.
+pub const CBOR_CONTENT_TYPE: &str = "application/cbor";
+
+/// Media type of JSON.
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+
+/// Minimum size of a response body to compress in bytes.
+///
+/// Smaller bodies hardly shrink.
+pub const MIN_COMPRESSION_SIZE: usize = 1024;
+
+// Quality of Brotli; balances the ratio and the CPU time of a Lambda.
+const BROTLI_QUALITY: u32 = 5;
+
+// Window size of Brotli in bits.
+const BROTLI_WINDOW: u32 = 22;
+
+/// Content coding of a response body.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ContentCoding {
+    /// Brotli; "br".
+    Brotli,
+    /// gzip; "gzip".
+    Gzip,
+}
+
+impl ContentCoding {
+    /// Returns the token in the `Content-Encoding` header.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentCoding::Brotli => "br",
+            ContentCoding::Gzip => "gzip",
+        }
+    }
+}
+
+/// Transcodes a CBOR request into JSON, runs a handler, and transcodes the
+/// JSON response into CBOR if the client prefers it.
+///
+/// A CBOR body exceeding `max_size` bytes is rejected without decoding.
+/// Responses other than JSON are not transcoded.
+/// A JSON error response is localized and given the correlation ID before it
+/// is transcoded; see [`localize_error_response`] and [`add_request_id`].
+/// The response body is finally compressed if the client accepts it; see
+/// [`compress_response`].
+pub async fn negotiate_content<F, Fut>(
+    mut request: Request,
+    max_size: usize,
+    handler: F,
+) -> Result<Response<Body>, lambda_http::Error>
+where
+    F: FnOnce(Request) -> Fut,
+    Fut: Future<Output = Result<Response<Body>, lambda_http::Error>>,
+{
+    let prefers_cbor = prefers_cbor(&request);
+    let coding = accepted_coding(&request);
+    let language = preferred_language(&request);
+    let correlation = correlation_of(&request);
+    if has_content_type(&request, CBOR_CONTENT_TYPE) {
+        match cbor_to_json(request.body().as_ref(), max_size) {
+            Ok(json) => {
+                *request.body_mut() = json.into();
+                request.headers_mut()
+                    .insert(CONTENT_TYPE, HeaderValue::from_static(JSON_CONTENT_TYPE));
+            }
+            Err(e) => {
+                error!("bad CBOR payload: {:?}", e);
+                return e.into_response();
+            }
+        }
+    }
+    let res = localize_error_response(handler(request).await?, language);
+    let mut res = add_request_id(res, &correlation);
+    res.headers_mut().append(VARY, HeaderValue::from_static("Accept"));
+    res.headers_mut().append(VARY, HeaderValue::from_static("Accept-Encoding"));
+    res.headers_mut().append(VARY, HeaderValue::from_static("Accept-Language"));
+    let is_json = res.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| media_type(v) == JSON_CONTENT_TYPE);
+    if prefers_cbor && is_json {
+        let cbor = json_to_cbor(res.body().as_ref())?;
+        *res.body_mut() = cbor.into();
+        res.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static(CBOR_CONTENT_TYPE));
+    }
+    match coding {
+        Some(coding) => compress_response(res, coding),
+        None => Ok(res),
+    }
+}
+
+/// Compresses a response body with a given coding.
+///
+/// The response is returned as it is if the body is smaller than
+/// [`MIN_COMPRESSION_SIZE`] or already encoded.
+pub fn compress_response(
+    mut res: Response<Body>,
+    coding: ContentCoding,
+) -> Result<Response<Body>, lambda_http::Error> {
+    if res.body().as_ref().len() < MIN_COMPRESSION_SIZE
+        || res.headers().contains_key(CONTENT_ENCODING)
+    {
+        return Ok(res);
+    }
+    let compressed = compress(res.body().as_ref(), coding)?;
+    *res.body_mut() = compressed.into();
+    res.headers_mut()
+        .insert(CONTENT_ENCODING, HeaderValue::from_static(coding.as_str()));
+    Ok(res)
+}
+
+/// Compresses bytes with a given coding.
+pub fn compress(body: &[u8], coding: ContentCoding) -> std::io::Result<Vec<u8>> {
+    match coding {
+        ContentCoding::Brotli => {
+            let mut compressed = Vec::new();
+            {
+                let mut writer = brotli::CompressorWriter::new(
+                    &mut compressed,
+                    4096,
+                    BROTLI_QUALITY,
+                    BROTLI_WINDOW,
+                );
+                writer.write_all(body)?;
+            }
+            Ok(compressed)
+        }
+        ContentCoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(body)?;
+            encoder.finish()
+        }
+    }
+}
+
+/// Returns the content coding that a request accepts in the
+/// `Accept-Encoding` header.
+///
+/// Brotli is preferred to gzip unless the quality of Brotli is lower.
+/// Returns `None` if neither is accepted.
+pub fn accepted_coding(request: &Request) -> Option<ContentCoding> {
+    let accept_encoding = request.headers()
+        .get(ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok())?;
+    let mut brotli = None;
+    let mut gzip = None;
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let coding = params.next().unwrap_or("").trim();
+        let quality = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        match coding {
+            "br" => brotli = Some(quality),
+            "gzip" => gzip = Some(quality),
+            _ => {}
+        }
+    }
+    match (brotli.filter(|q| *q > 0.0), gzip.filter(|q| *q > 0.0)) {
+        (Some(b), Some(g)) if g > b => Some(ContentCoding::Gzip),
+        (Some(_), _) => Some(ContentCoding::Brotli),
+        (None, Some(_)) => Some(ContentCoding::Gzip),
+        (None, None) => None,
+    }
+}
+
+/// Returns whether a request prefers CBOR to JSON in the `Accept` header.
+///
+/// The media type with the higher quality wins, and the one listed first wins
+/// a tie. Wildcards are not considered.
+pub fn prefers_cbor(request: &Request) -> bool {
+    let Some(accept) = request.headers()
+        .get(ACCEPT)
+        .and_then(|v| v.to_str().ok()) else
+    {
+        return false;
+    };
+    let mut best: Option<(&str, f32)> = None;
+    for item in accept.split(',') {
+        let mut params = item.split(';');
+        let media_type = params.next().unwrap_or("").trim();
+        if media_type != CBOR_CONTENT_TYPE && media_type != JSON_CONTENT_TYPE {
+            continue;
+        }
+        let quality = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if quality > 0.0 && best.map_or(true, |(_, q)| quality > q) {
+            best = Some((media_type, quality));
+        }
+    }
+    best.is_some_and(|(media_type, _)| media_type == CBOR_CONTENT_TYPE)
+}
+
+/// Transcodes a CBOR body into JSON.
+///
+/// Byte strings become "base64url"-encoded strings. Map keys must be text
+/// strings or integers.
+pub fn cbor_to_json(body: &[u8], max_size: usize) -> Result<Vec<u8>, PayloadError> {
+    if body.len() > max_size {
+        return Err(PayloadError::TooLarge {
+            size: body.len(),
+            limit: max_size,
+        });
+    }
+    if body.is_empty() {
+        return Err(PayloadError::Missing);
+    }
+    let value: ciborium::Value = ciborium::from_reader(body)
+        .map_err(|e| PayloadError::Malformed {
+            field: None,
+            message: format!("malformed CBOR: {}", e),
+        })?;
+    let json = cbor_value_to_json(value).map_err(|message| PayloadError::Malformed {
+        field: None,
+        message: message.into(),
+    })?;
+    Ok(serde_json::to_vec(&json).expect("JSON value must be serializable"))
+}
+
+/// Transcodes a JSON body into CBOR.
+pub fn json_to_cbor(body: &[u8]) -> Result<Vec<u8>, lambda_http::Error> {
+    let value: serde_json::Value = serde_json::from_slice(body)?;
+    let mut cbor = Vec::new();
+    ciborium::into_writer(&value, &mut cbor)?;
+    Ok(cbor)
+}
+
+fn cbor_value_to_json(value: ciborium::Value) -> Result<serde_json::Value, &'static str> {
+    use ciborium::Value as Cbor;
+    use serde_json::Value as Json;
+    Ok(match value {
+        Cbor::Null => Json::Null,
+        Cbor::Bool(b) => Json::Bool(b),
+        Cbor::Integer(i) => {
+            let i = i128::from(i);
+            if let Ok(i) = i64::try_from(i) {
+                Json::from(i)
+            } else if let Ok(i) = u64::try_from(i) {
+                Json::from(i)
+            } else {
+                return Err("CBOR integer out of range");
+            }
+        }
+        Cbor::Float(f) => serde_json::Number::from_f64(f)
+            .map(Json::Number)
+            .ok_or("non-finite CBOR float")?,
+        Cbor::Text(s) => Json::String(s),
+        Cbor::Bytes(b) => Json::String(base64url.encode(b)),
+        Cbor::Array(items) => Json::Array(
+            items.into_iter()
+                .map(cbor_value_to_json)
+                .collect::<Result<_, _>>()?,
+        ),
+        Cbor::Map(entries) => Json::Object(
+            entries.into_iter()
+                .map(|(k, v)| {
+                    let key = match k {
+                        Cbor::Text(s) => s,
+                        Cbor::Integer(i) => i128::from(i).to_string(),
+                        _ => return Err("unsupported CBOR map key"),
+                    };
+                    Ok((key, cbor_value_to_json(v)?))
+                })
+                .collect::<Result<_, _>>()?,
+        ),
+        Cbor::Tag(_, value) => cbor_value_to_json(*value)?,
+        _ => return Err("unsupported CBOR value"),
+    })
+}
+
+// returns whether the `Content-Type` of a request is a given media type.
+pub(crate) fn has_content_type(request: &Request, expected: &str) -> bool {
+    request.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| media_type(v) == expected)
+}
+
+// media type without parameters; e.g., "application/json" of
+// "application/json; charset=utf-8".
+fn media_type(content_type: &str) -> &str {
+    content_type.split(';').next().unwrap_or("").trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request_accepting(accept: &str) -> Request {
+        lambda_http::http::Request::builder()
+            .header(ACCEPT, accept)
+            .body(Body::Empty)
+            .unwrap()
+    }
+
+    #[test]
+    fn prefers_cbor_should_respect_quality_and_order() {
+        assert!(prefers_cbor(&request_accepting("application/cbor")));
+        assert!(prefers_cbor(&request_accepting("application/cbor, application/json")));
+        assert!(!prefers_cbor(&request_accepting("application/json, application/cbor")));
+        assert!(prefers_cbor(&request_accepting(
+            "application/json;q=0.5, application/cbor",
+        )));
+        assert!(!prefers_cbor(&request_accepting("application/cbor;q=0")));
+        assert!(!prefers_cbor(&request_accepting("*/*")));
+    }
+
+    fn request_accepting_encoding(accept_encoding: &str) -> Request {
+        lambda_http::http::Request::builder()
+            .header(ACCEPT_ENCODING, accept_encoding)
+            .body(Body::Empty)
+            .unwrap()
+    }
+
+    #[test]
+    fn accepted_coding_should_prefer_brotli() {
+        assert_eq!(
+            accepted_coding(&request_accepting_encoding("gzip, deflate, br")),
+            Some(ContentCoding::Brotli),
+        );
+        assert_eq!(
+            accepted_coding(&request_accepting_encoding("gzip, br;q=0.5")),
+            Some(ContentCoding::Gzip),
+        );
+        assert_eq!(
+            accepted_coding(&request_accepting_encoding("gzip")),
+            Some(ContentCoding::Gzip),
+        );
+        assert_eq!(accepted_coding(&request_accepting_encoding("br;q=0, identity")), None);
+        assert_eq!(accepted_coding(&request_accepting("application/json")), None);
+    }
+
+    #[test]
+    fn compress_response_should_skip_small_body() {
+        let res = Response::new(Body::from("{}"));
+        let res = compress_response(res, ContentCoding::Gzip).unwrap();
+        assert!(!res.headers().contains_key(CONTENT_ENCODING));
+        assert_eq!(res.body().as_ref(), b"{}");
+    }
+
+    #[test]
+    fn compress_response_should_round_trip() {
+        use std::io::Read as _;
+
+        let body = "{\"excludeCredentials\":[]}".repeat(100);
+        let res = compress_response(
+            Response::new(Body::from(body.clone())),
+            ContentCoding::Gzip,
+        ).unwrap();
+        assert_eq!(res.headers()[CONTENT_ENCODING], "gzip");
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(res.body().as_ref())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, body);
+
+        let res = compress_response(
+            Response::new(Body::from(body.clone())),
+            ContentCoding::Brotli,
+        ).unwrap();
+        assert_eq!(res.headers()[CONTENT_ENCODING], "br");
+        assert!(res.body().as_ref().len() < body.len());
+        let mut decoded = String::new();
+        brotli::Decompressor::new(res.body().as_ref(), 4096)
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, body);
+    }
+
+    #[test]
+    fn cbor_to_json_should_encode_bytes_as_base64url() {
+        let value = ciborium::Value::Map(vec![
+            (
+                ciborium::Value::Text("sessionId".into()),
+                ciborium::Value::Text("abc".into()),
+            ),
+            (
+                ciborium::Value::Text("rawId".into()),
+                ciborium::Value::Bytes(vec![0xFB, 0xFF]),
+            ),
+        ]);
+        let mut cbor = Vec::new();
+        ciborium::into_writer(&value, &mut cbor).unwrap();
+        let json: serde_json::Value =
+            serde_json::from_slice(&cbor_to_json(&cbor, 1024).unwrap()).unwrap();
+        assert_eq!(json, serde_json::json!({ "sessionId": "abc", "rawId": "-_8" }));
+    }
+
+    #[test]
+    fn cbor_to_json_should_reject_large_or_malformed_body() {
+        assert!(matches!(
+            cbor_to_json(&[0xA0; 16], 8),
+            Err(PayloadError::TooLarge { size: 16, limit: 8 }),
+        ));
+        assert_eq!(cbor_to_json(&[], 8), Err(PayloadError::Missing));
+        assert!(matches!(
+            cbor_to_json(&[0xFF], 8),
+            Err(PayloadError::Malformed { .. }),
+        ));
+    }
+
+    #[test]
+    fn json_to_cbor_should_round_trip() {
+        let cbor = json_to_cbor(br#"{"challenge":"AAAA","timeout":60000}"#).unwrap();
+        let value: serde_json::Value = ciborium::from_reader(cbor.as_slice()).unwrap();
+        assert_eq!(value, serde_json::json!({ "challenge": "AAAA", "timeout": 60000 }));
+    }
+}
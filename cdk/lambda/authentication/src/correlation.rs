@@ -0,0 +1,328 @@
+//! Correlation IDs.
+//!
+//! Every HTTP request is given a correlation ID, so that a failing flow can
+//! be traced across the client, API Gateway, the functions, and AWS
+//! services:
+//! - the `X-Request-Id` header of the request if the client sends a
+//!   well-formed one; see [`is_valid_request_id`]
+//! - otherwise, the API Gateway request ID
+//! - otherwise, the Lambda request ID
+//!
+//! The `X-Amzn-Trace-Id` header that API Gateway adds is carried along as the
+//! trace ID.
+//!
+//! Both are recorded in the request span as `correlationId` and `traceId`;
+//! see [`crate::telemetry::request_span`]. Both are also forwarded on
+//! outbound calls of the AWS SDK in the `X-Request-Id` and `X-Amzn-Trace-Id`
+//! headers if the SDK is configured by [`load_sdk_config`]. The trace ID of
+//! the Lambda function is preferred to the one of the request if active
+//! tracing is enabled, because it has the segment of the function as the
+//! parent.
+//!
+//! The correlation ID is returned in the `X-Request-Id` header of every
+//! response; see [`crate::warmer::run_with_warmer`]. An error response of an
+//! endpoint with content negotiation also tells it as `requestId` of the
+//! [`ErrorResponseBody`]; see [`crate::content::negotiate_content`]. A server
+//! error still fails the invocation, and API Gateway responds with its own
+//! body; the correlation ID of the failure is found in the logs.
+//!
+//! [`ErrorResponseBody`]: crate::payload::ErrorResponseBody
+
+use aws_config::SdkConfig;
+use aws_smithy_runtime_api::client::{
+    http::{
+        HttpClient,
+        HttpConnector,
+        HttpConnectorFuture,
+        HttpConnectorSettings,
+        SharedHttpClient,
+        SharedHttpConnector,
+    },
+    orchestrator::HttpRequest,
+    runtime_components::RuntimeComponents,
+};
+use lambda_http::{
+    Body,
+    Request,
+    RequestExt,
+    Response,
+    http::header::{CONTENT_TYPE, HeaderValue},
+    request::RequestContext,
+};
+use std::future::Future;
+
+use crate::error::Error;
+
+/// Header of a request ID.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Header of a trace ID of AWS X-Ray.
+pub const TRACE_ID_HEADER: &str = "x-amzn-trace-id";
+
+/// Maximum length of a request ID that a client sends.
+pub const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+// maximum length of a trace ID; a trace header may have extra fields
+// after `Root`, `Parent`, and `Sampled`.
+const MAX_TRACE_ID_LENGTH: usize = 256;
+
+tokio::task_local! {
+    // correlation of the request being handled.
+    static CURRENT: Correlation;
+}
+
+/// Correlation ID and trace ID of a request.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Correlation {
+    /// Correlation ID.
+    pub request_id: String,
+
+    /// Trace ID in the form of the `X-Amzn-Trace-Id` header.
+    ///
+    /// `None` if the request has no trace ID.
+    pub trace_id: Option<String>,
+}
+
+/// Returns the correlation of a given request.
+pub fn correlation_of(request: &Request) -> Correlation {
+    let request_id = request.headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| is_valid_request_id(id))
+        .map(String::from)
+        .or_else(|| api_request_id(request))
+        .or_else(|| request.lambda_context_ref().map(|c| c.request_id.clone()))
+        .unwrap_or_else(|| "-".into());
+    let trace_id = request.headers()
+        .get(TRACE_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| is_valid_trace_id(id))
+        .map(String::from)
+        .or_else(|| request.lambda_context_ref().and_then(|c| c.xray_trace_id.clone()));
+    Correlation { request_id, trace_id }
+}
+
+/// Returns whether a request ID that a client sends is acceptable.
+///
+/// A request ID must consist of 1 to [`MAX_REQUEST_ID_LENGTH`] ASCII
+/// alphanumerics, '-', '_', '.', or ':', so that it cannot forge log lines or
+/// headers; e.g., a UUID.
+pub fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LENGTH
+        && id.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+}
+
+// returns whether a trace ID in the form of `X-Amzn-Trace-Id` is acceptable;
+// e.g., "Root=1-5759e988-bd862e3fe1be46a994272793;Sampled=1".
+fn is_valid_trace_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_TRACE_ID_LENGTH
+        && id.bytes().all(|b| b.is_ascii_alphanumeric() || b"-=;".contains(&b))
+}
+
+// API Gateway request ID of a request.
+pub(crate) fn api_request_id(request: &Request) -> Option<String> {
+    match request.request_context_ref() {
+        Some(RequestContext::ApiGatewayV2(context)) => context.request_id.clone(),
+        Some(RequestContext::ApiGatewayV1(context)) => context.request_id.clone(),
+        _ => None,
+    }
+}
+
+/// Runs a future with a given correlation.
+///
+/// Outbound calls of the AWS SDK made by the future forward the correlation.
+/// See [`load_sdk_config`].
+pub async fn with_correlation<Fut>(correlation: Correlation, f: Fut) -> Fut::Output
+where
+    Fut: Future,
+{
+    CURRENT.scope(correlation, f).await
+}
+
+/// Returns the correlation of the request being handled.
+///
+/// `None` outside [`with_correlation`].
+pub fn current_correlation() -> Option<Correlation> {
+    CURRENT.try_with(Correlation::clone).ok()
+}
+
+/// Sets the correlation ID to the `X-Request-Id` header of a response.
+pub fn set_request_id_header(res: &mut Response<Body>, correlation: &Correlation) {
+    if let Ok(value) = HeaderValue::from_str(&correlation.request_id) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+}
+
+/// Adds the correlation ID to an error response as `requestId`.
+///
+/// The response must be a client or server error with a JSON object as
+/// `application/json`; any other response is returned as it is.
+pub fn add_request_id(mut res: Response<Body>, correlation: &Correlation) -> Response<Body> {
+    let is_error = res.status().is_client_error() || res.status().is_server_error();
+    let is_json = res.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if !is_error || !is_json {
+        return res;
+    }
+    let Ok(serde_json::Value::Object(mut body)) =
+        serde_json::from_slice::<serde_json::Value>(res.body().as_ref()) else
+    {
+        return res;
+    };
+    body.insert("requestId".into(), correlation.request_id.clone().into());
+    let Ok(body) = serde_json::to_string(&body) else {
+        return res;
+    };
+    *res.body_mut() = body.into();
+    res
+}
+
+/// Loads the configuration of the AWS SDK that forwards the correlation.
+///
+/// The HTTP client of the SDK adds the `X-Request-Id` and `X-Amzn-Trace-Id`
+/// headers of the current correlation to outbound requests; see
+/// [`with_correlation`]. `X-Amzn-Trace-Id` that the SDK already has set is
+/// kept.
+pub async fn load_sdk_config() -> Result<SdkConfig, Error> {
+    let inner = aws_smithy_runtime::client::http::hyper_014::default_client()
+        .ok_or(Error::Telemetry("no default HTTP client of AWS SDK"))?;
+    Ok(aws_config::defaults(aws_config::BehaviorVersion::latest())
+        .http_client(CorrelatingHttpClient { inner })
+        .load()
+        .await)
+}
+
+// HTTP client that forwards the current correlation.
+#[derive(Debug)]
+struct CorrelatingHttpClient {
+    inner: SharedHttpClient,
+}
+
+impl HttpClient for CorrelatingHttpClient {
+    fn http_connector(
+        &self,
+        settings: &HttpConnectorSettings,
+        components: &RuntimeComponents,
+    ) -> SharedHttpConnector {
+        SharedHttpConnector::new(CorrelatingHttpConnector {
+            inner: self.inner.http_connector(settings, components),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct CorrelatingHttpConnector {
+    inner: SharedHttpConnector,
+}
+
+impl HttpConnector for CorrelatingHttpConnector {
+    fn call(&self, mut request: HttpRequest) -> HttpConnectorFuture {
+        if let Some(correlation) = current_correlation() {
+            let headers = request.headers_mut();
+            // values have been validated
+            let _ = headers.try_insert(REQUEST_ID_HEADER, correlation.request_id);
+            if let Some(trace_id) = correlation.trace_id {
+                if !headers.contains_key(TRACE_ID_HEADER) {
+                    let _ = headers.try_insert(TRACE_ID_HEADER, trace_id);
+                }
+            }
+        }
+        self.inner.call(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use lambda_http::http::StatusCode;
+
+    fn request_with_headers(headers: &[(&str, &str)]) -> Request {
+        let mut builder = lambda_http::http::Request::builder();
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(Body::Empty).unwrap()
+    }
+
+    fn correlation(request_id: &str) -> Correlation {
+        Correlation {
+            request_id: request_id.into(),
+            trace_id: None,
+        }
+    }
+
+    #[test]
+    fn correlation_of_should_take_request_id_and_trace_id_headers() {
+        let correlation = correlation_of(&request_with_headers(&[
+            ("X-Request-Id", "6f0c2a51-2f4e-4b8e-9d43-4d1b1e1c9a07"),
+            ("X-Amzn-Trace-Id", "Root=1-5759e988-bd862e3fe1be46a994272793;Sampled=1"),
+        ]));
+        assert_eq!(correlation.request_id, "6f0c2a51-2f4e-4b8e-9d43-4d1b1e1c9a07");
+        assert_eq!(
+            correlation.trace_id.as_deref(),
+            Some("Root=1-5759e988-bd862e3fe1be46a994272793;Sampled=1"),
+        );
+    }
+
+    #[test]
+    fn correlation_of_should_ignore_malformed_headers() {
+        let correlation = correlation_of(&request_with_headers(&[
+            ("X-Request-Id", "forged\" level=error"),
+            ("X-Amzn-Trace-Id", "Root=1-5759e988 Sampled=1"),
+        ]));
+        assert_eq!(correlation.request_id, "-");
+        assert_eq!(correlation.trace_id, None);
+    }
+
+    #[test]
+    fn is_valid_request_id_should_limit_characters_and_length() {
+        assert!(is_valid_request_id("abc-123_DEF.4:5"));
+        assert!(!is_valid_request_id(""));
+        assert!(!is_valid_request_id("a b"));
+        assert!(!is_valid_request_id("a\nb"));
+        assert!(is_valid_request_id(&"a".repeat(MAX_REQUEST_ID_LENGTH)));
+        assert!(!is_valid_request_id(&"a".repeat(MAX_REQUEST_ID_LENGTH + 1)));
+    }
+
+    #[test]
+    fn add_request_id_should_tell_request_id_in_error_response() {
+        let res = Response::builder()
+            .status(StatusCode::UNAUTHORIZED)
+            .header(CONTENT_TYPE, "application/json")
+            .body(r#"{"error":"session_expired","message":"session not found"}"#.into())
+            .unwrap();
+        let res = add_request_id(res, &correlation("abc"));
+        let body: serde_json::Value = serde_json::from_slice(res.body().as_ref()).unwrap();
+        assert_eq!(body["error"], "session_expired");
+        assert_eq!(body["requestId"], "abc");
+    }
+
+    #[test]
+    fn add_request_id_should_leave_body_of_other_responses() {
+        let res = Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, "application/json")
+            .body(r#"{"sessionId":"AAAA"}"#.into())
+            .unwrap();
+        let mut res = add_request_id(res, &correlation("abc"));
+        assert_eq!(res.body().as_ref(), br#"{"sessionId":"AAAA"}"#);
+        set_request_id_header(&mut res, &correlation("abc"));
+        assert_eq!(res.headers()[REQUEST_ID_HEADER], "abc");
+        assert_eq!(res.body().as_ref(), br#"{"sessionId":"AAAA"}"#);
+    }
+
+    #[tokio::test]
+    async fn with_correlation_should_scope_current_correlation() {
+        assert_eq!(current_correlation(), None);
+        let current = with_correlation(correlation("abc"), async {
+            current_correlation()
+        }).await;
+        assert_eq!(current, Some(correlation("abc")));
+        assert_eq!(current_correlation(), None);
+    }
+}
@@ -0,0 +1,759 @@
+//! Access to users and credentials behind a trait.
+//!
+//! Logic that registers and authenticates users takes a [`CredentialStore`]
+//! instead of a [`UserDirectory`], so that the credentials can live in
+//! another database. [`UserDirectory`] is the implementation on the DynamoDB
+//! credential table, and [`crate::sql::SqlCredentialStore`] is the one on a
+//! relational database if the `sql` feature is enabled. The handlers hold a
+//! [`CredentialBackend`], which is either of them selected at cold start.
+//!
+//! The trait covers the operations of registration and authentication,
+//! including recovery codes. Paging, renaming, and exports stay specific to
+//! [`UserDirectory`].
+
+use aws_sdk_dynamodb::primitives::{DateTime, DateTimeFormat};
+use std::future::Future;
+use std::time::SystemTime;
+use tracing::{info, warn};
+use webauthn_rs::prelude::{AuthenticationResult, Passkey};
+
+use crate::error::Error;
+use crate::items::{CredentialItem, CredentialKey, RecoveryCodesItem, UserItem};
+use crate::passkey::PasskeyProperties;
+#[cfg(feature = "sql")]
+use crate::sql::SqlCredentialStore;
+use crate::store::Database;
+use crate::telemetry::truncate;
+use crate::users::{CreateUserError, UserDirectory};
+
+// Maximum number of attempts to update a credential that is concurrently
+// updated.
+const MAX_UPDATE_ATTEMPTS: usize = 3;
+
+/// Store of users and their credentials.
+pub trait CredentialStore {
+    /// Resolves the user handle of a given username.
+    ///
+    /// Returns `None` if no user has the username.
+    fn find_user_handle(
+        &self,
+        username: &str,
+    ) -> impl Future<Output = Result<Option<String>, Error>> + Send;
+
+    /// Obtains a user.
+    fn get_user(
+        &self,
+        user_handle: &str,
+    ) -> impl Future<Output = Result<Option<UserItem>, Error>> + Send;
+
+    /// Lists credentials of a given user.
+    fn list_credentials(
+        &self,
+        user_handle: &str,
+    ) -> impl Future<Output = Result<Vec<CredentialItem>, Error>> + Send;
+
+    /// Lists credentials of a user specified by the username.
+    ///
+    /// Returns the user handle and credentials, or `None` if no user has the
+    /// username.
+    fn list_credentials_by_username(
+        &self,
+        username: &str,
+    ) -> impl Future<Output = Result<Option<(String, Vec<CredentialItem>)>, Error>> + Send;
+
+    /// Obtains a credential with a strongly consistent read.
+    fn get_credential(
+        &self,
+        key: CredentialKey<'_>,
+    ) -> impl Future<Output = Result<Option<CredentialItem>, Error>> + Send;
+
+    /// Creates a new user with the first credential.
+    ///
+    /// Neither is written if the user or the credential already exists.
+    fn create_user(
+        &self,
+        user: UserItem,
+        credential: CredentialItem,
+    ) -> impl Future<Output = Result<(), CreateUserError>> + Send;
+
+    /// Adds a credential to an existing user.
+    ///
+    /// Returns `false` if the credential already exists.
+    fn add_credential(
+        &self,
+        credential: CredentialItem,
+    ) -> impl Future<Output = Result<bool, Error>> + Send;
+
+    /// Updates the credential and backup flags of a credential used for
+    /// authentication.
+    ///
+    /// `updated_at` is also recorded as the last-used timestamp, and the
+    /// authentication is counted.
+    /// Returns `false` if the credential has been updated since `current` was
+    /// read.
+    fn update_credential(
+        &self,
+        current: &CredentialItem,
+        credential: String,
+        backup_eligible: bool,
+        backup_state: bool,
+        updated_at: String,
+    ) -> impl Future<Output = Result<bool, Error>> + Send;
+
+    /// Records the last-used timestamp of a credential, and counts the
+    /// authentication.
+    fn touch_credential(
+        &self,
+        key: CredentialKey<'_>,
+        used_at: String,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Flags a credential verified with a legacy RP ID.
+    ///
+    /// Does nothing if the credential is already flagged with the RP ID.
+    fn flag_legacy_credential(
+        &self,
+        credential_item: &CredentialItem,
+        legacy_rp_id: &str,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Replaces the recovery codes of a user.
+    fn put_recovery_codes(
+        &self,
+        codes: RecoveryCodesItem,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Consumes a recovery code of a user specified by the hash.
+    ///
+    /// Returns `false` if the code is not one of the unused codes.
+    fn consume_recovery_code(
+        &self,
+        user_handle: &str,
+        code_hash: &str,
+    ) -> impl Future<Output = Result<bool, Error>> + Send;
+
+    /// Deletes a credential.
+    ///
+    /// Returns `false` if the credential does not exist.
+    fn delete_credential(
+        &self,
+        key: CredentialKey<'_>,
+    ) -> impl Future<Output = Result<bool, Error>> + Send;
+}
+
+impl CredentialStore for UserDirectory {
+    async fn find_user_handle(&self, username: &str) -> Result<Option<String>, Error> {
+        UserDirectory::find_user_handle(self, username).await
+    }
+
+    async fn get_user(&self, user_handle: &str) -> Result<Option<UserItem>, Error> {
+        UserDirectory::get_user(self, user_handle).await
+    }
+
+    async fn list_credentials(
+        &self,
+        user_handle: &str,
+    ) -> Result<Vec<CredentialItem>, Error> {
+        UserDirectory::list_credentials(self, user_handle).await
+    }
+
+    async fn list_credentials_by_username(
+        &self,
+        username: &str,
+    ) -> Result<Option<(String, Vec<CredentialItem>)>, Error> {
+        UserDirectory::list_credentials_by_username(self, username).await
+    }
+
+    async fn get_credential(
+        &self,
+        key: CredentialKey<'_>,
+    ) -> Result<Option<CredentialItem>, Error> {
+        UserDirectory::get_credential(self, key).await
+    }
+
+    async fn create_user(
+        &self,
+        user: UserItem,
+        credential: CredentialItem,
+    ) -> Result<(), CreateUserError> {
+        UserDirectory::create_user(self, user, credential).await
+    }
+
+    async fn add_credential(&self, credential: CredentialItem) -> Result<bool, Error> {
+        UserDirectory::add_credential(self, credential).await
+    }
+
+    async fn update_credential(
+        &self,
+        current: &CredentialItem,
+        credential: String,
+        backup_eligible: bool,
+        backup_state: bool,
+        updated_at: String,
+    ) -> Result<bool, Error> {
+        UserDirectory::update_credential(
+            self,
+            current,
+            credential,
+            backup_eligible,
+            backup_state,
+            updated_at,
+        ).await
+    }
+
+    async fn touch_credential(
+        &self,
+        key: CredentialKey<'_>,
+        used_at: String,
+    ) -> Result<(), Error> {
+        UserDirectory::touch_credential(self, key, used_at).await
+    }
+
+    async fn flag_legacy_credential(
+        &self,
+        credential_item: &CredentialItem,
+        legacy_rp_id: &str,
+    ) -> Result<(), Error> {
+        UserDirectory::flag_legacy_credential(self, credential_item, legacy_rp_id).await
+    }
+
+    async fn put_recovery_codes(&self, codes: RecoveryCodesItem) -> Result<(), Error> {
+        UserDirectory::put_recovery_codes(self, codes).await
+    }
+
+    async fn consume_recovery_code(
+        &self,
+        user_handle: &str,
+        code_hash: &str,
+    ) -> Result<bool, Error> {
+        UserDirectory::consume_recovery_code(self, user_handle, code_hash).await
+    }
+
+    async fn delete_credential(&self, key: CredentialKey<'_>) -> Result<bool, Error> {
+        UserDirectory::delete_credential(self, key).await
+    }
+}
+
+/// Credential store selected at cold start.
+#[derive(Clone, Debug)]
+pub enum CredentialBackend {
+    /// Credential table on DynamoDB.
+    DynamoDb(UserDirectory),
+    /// Tables on a relational database.
+    #[cfg(feature = "sql")]
+    Sql(SqlCredentialStore),
+}
+
+impl CredentialBackend {
+    /// Selects the store on a given database, or `users` if no database is
+    /// configured.
+    #[cfg_attr(not(feature = "sql"), allow(unused_variables))]
+    pub fn new(users: UserDirectory, database: Option<&Database>) -> Self {
+        #[cfg(feature = "sql")]
+        if let Some(database) = database {
+            return Self::Sql(SqlCredentialStore::new(database.pool().clone()));
+        }
+        Self::DynamoDb(users)
+    }
+
+    /// Returns the name of the DynamoDB table if the store is on DynamoDB.
+    ///
+    /// Health checks probe the table.
+    pub fn table_name(&self) -> Option<&str> {
+        match self {
+            Self::DynamoDb(users) => Some(users.table_name()),
+            #[cfg(feature = "sql")]
+            Self::Sql(_) => None,
+        }
+    }
+}
+
+impl CredentialStore for CredentialBackend {
+    async fn find_user_handle(&self, username: &str) -> Result<Option<String>, Error> {
+        match self {
+            Self::DynamoDb(store) => store.find_user_handle(username).await,
+            #[cfg(feature = "sql")]
+            Self::Sql(store) => store.find_user_handle(username).await,
+        }
+    }
+
+    async fn get_user(&self, user_handle: &str) -> Result<Option<UserItem>, Error> {
+        match self {
+            Self::DynamoDb(store) => store.get_user(user_handle).await,
+            #[cfg(feature = "sql")]
+            Self::Sql(store) => store.get_user(user_handle).await,
+        }
+    }
+
+    async fn list_credentials(
+        &self,
+        user_handle: &str,
+    ) -> Result<Vec<CredentialItem>, Error> {
+        match self {
+            Self::DynamoDb(store) => store.list_credentials(user_handle).await,
+            #[cfg(feature = "sql")]
+            Self::Sql(store) => store.list_credentials(user_handle).await,
+        }
+    }
+
+    async fn list_credentials_by_username(
+        &self,
+        username: &str,
+    ) -> Result<Option<(String, Vec<CredentialItem>)>, Error> {
+        match self {
+            Self::DynamoDb(store) => store.list_credentials_by_username(username).await,
+            #[cfg(feature = "sql")]
+            Self::Sql(store) => store.list_credentials_by_username(username).await,
+        }
+    }
+
+    async fn get_credential(
+        &self,
+        key: CredentialKey<'_>,
+    ) -> Result<Option<CredentialItem>, Error> {
+        match self {
+            Self::DynamoDb(store) => store.get_credential(key).await,
+            #[cfg(feature = "sql")]
+            Self::Sql(store) => store.get_credential(key).await,
+        }
+    }
+
+    async fn create_user(
+        &self,
+        user: UserItem,
+        credential: CredentialItem,
+    ) -> Result<(), CreateUserError> {
+        match self {
+            Self::DynamoDb(store) => store.create_user(user, credential).await,
+            #[cfg(feature = "sql")]
+            Self::Sql(store) => store.create_user(user, credential).await,
+        }
+    }
+
+    async fn add_credential(&self, credential: CredentialItem) -> Result<bool, Error> {
+        match self {
+            Self::DynamoDb(store) => store.add_credential(credential).await,
+            #[cfg(feature = "sql")]
+            Self::Sql(store) => store.add_credential(credential).await,
+        }
+    }
+
+    async fn update_credential(
+        &self,
+        current: &CredentialItem,
+        credential: String,
+        backup_eligible: bool,
+        backup_state: bool,
+        updated_at: String,
+    ) -> Result<bool, Error> {
+        match self {
+            Self::DynamoDb(store) => store.update_credential(
+                current,
+                credential,
+                backup_eligible,
+                backup_state,
+                updated_at,
+            ).await,
+            #[cfg(feature = "sql")]
+            Self::Sql(store) => store.update_credential(
+                current,
+                credential,
+                backup_eligible,
+                backup_state,
+                updated_at,
+            ).await,
+        }
+    }
+
+    async fn touch_credential(
+        &self,
+        key: CredentialKey<'_>,
+        used_at: String,
+    ) -> Result<(), Error> {
+        match self {
+            Self::DynamoDb(store) => store.touch_credential(key, used_at).await,
+            #[cfg(feature = "sql")]
+            Self::Sql(store) => store.touch_credential(key, used_at).await,
+        }
+    }
+
+    async fn flag_legacy_credential(
+        &self,
+        credential_item: &CredentialItem,
+        legacy_rp_id: &str,
+    ) -> Result<(), Error> {
+        match self {
+            Self::DynamoDb(store) =>
+                store.flag_legacy_credential(credential_item, legacy_rp_id).await,
+            #[cfg(feature = "sql")]
+            Self::Sql(store) =>
+                store.flag_legacy_credential(credential_item, legacy_rp_id).await,
+        }
+    }
+
+    async fn put_recovery_codes(&self, codes: RecoveryCodesItem) -> Result<(), Error> {
+        match self {
+            Self::DynamoDb(store) => store.put_recovery_codes(codes).await,
+            #[cfg(feature = "sql")]
+            Self::Sql(store) => store.put_recovery_codes(codes).await,
+        }
+    }
+
+    async fn consume_recovery_code(
+        &self,
+        user_handle: &str,
+        code_hash: &str,
+    ) -> Result<bool, Error> {
+        match self {
+            Self::DynamoDb(store) => store.consume_recovery_code(user_handle, code_hash).await,
+            #[cfg(feature = "sql")]
+            Self::Sql(store) => store.consume_recovery_code(user_handle, code_hash).await,
+        }
+    }
+
+    async fn delete_credential(&self, key: CredentialKey<'_>) -> Result<bool, Error> {
+        match self {
+            Self::DynamoDb(store) => store.delete_credential(key).await,
+            #[cfg(feature = "sql")]
+            Self::Sql(store) => store.delete_credential(key).await,
+        }
+    }
+}
+
+/// Updates a credential with an authentication result if necessary.
+///
+/// The last-used timestamp and the count of authentications are always
+/// recorded.
+/// The backup flags are also recorded, and a warning event is logged if the
+/// backup state has changed.
+/// The update is retried with the latest credential if the credential has
+/// been updated concurrently; e.g., by another authentication with it.
+pub async fn record_authentication(
+    store: &impl CredentialStore,
+    mut credential_item: CredentialItem,
+    auth_result: &AuthenticationResult,
+) -> Result<(), Error> {
+    let credential_id = credential_item.credential_id.clone();
+    for _ in 0..MAX_UPDATE_ATTEMPTS {
+        info!("checking credential updates: {}", truncate(&credential_id));
+        let mut passkey: Passkey = serde_json::from_str(&credential_item.credential)
+            .or(Err(Error::BadItemAttribute("credential")))?;
+        let used_at = DateTime::from(SystemTime::now())
+            .fmt(DateTimeFormat::DateTime)
+            .or(Err(Error::Storage("failed to format timestamp")))?;
+        let previous = PasskeyProperties::of(&passkey)?;
+        if !passkey.update_credential(auth_result).is_some_and(|b| b) {
+            return store.touch_credential(credential_item.key(), used_at).await;
+        }
+        let current = PasskeyProperties::of(&passkey)?;
+        if current.backup_state != previous.backup_state {
+            warn!(
+                event = "backup_state_changed",
+                credential_id = %truncate(&credential_id),
+                backup_eligible = current.backup_eligible,
+                previous = previous.backup_state,
+                current = current.backup_state,
+                "backup state of credential changed",
+            );
+        }
+        info!("updating credential: {}", truncate(&credential_id));
+        if store.update_credential(
+            &credential_item,
+            serde_json::to_string(&passkey)
+                .or(Err(Error::Inconvertible("non-serializable passkey")))?,
+            current.backup_eligible,
+            current.backup_state,
+            used_at,
+        ).await? {
+            return Ok(());
+        }
+        warn!("credential updated concurrently: {}", truncate(&credential_id));
+        credential_item = store.get_credential(credential_item.key())
+            .await?
+            .ok_or(Error::Storage("credential deleted during update"))?;
+    }
+    Err(Error::Storage("too many concurrent updates of credential"))
+}
+
+/// In-memory fake of the users and credentials for unit tests.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct MemoryCredentialStore {
+    users: std::sync::Mutex<std::collections::BTreeMap<String, UserItem>>,
+    credentials: std::sync::Mutex<
+        std::collections::BTreeMap<(String, String), CredentialItem>,
+    >,
+    recovery_codes: std::sync::Mutex<
+        std::collections::BTreeMap<String, RecoveryCodesItem>,
+    >,
+}
+
+#[cfg(test)]
+impl MemoryCredentialStore {
+    /// Puts a credential regardless of its version; e.g., to emulate another
+    /// update.
+    pub fn put_credential(&self, credential: CredentialItem) {
+        self.credentials.lock().unwrap().insert(
+            (credential.user_handle.clone(), credential.credential_id.clone()),
+            credential,
+        );
+    }
+
+    /// Returns a stored credential.
+    pub fn credential(&self, key: CredentialKey<'_>) -> Option<CredentialItem> {
+        self.credentials.lock().unwrap()
+            .get(&(key.user_handle.into(), key.credential_id.into()))
+            .cloned()
+    }
+}
+
+#[cfg(test)]
+impl CredentialStore for MemoryCredentialStore {
+    async fn find_user_handle(&self, username: &str) -> Result<Option<String>, Error> {
+        Ok(self.users.lock().unwrap()
+            .values()
+            .find(|u| u.username == username)
+            .map(|u| u.user_handle.clone()))
+    }
+
+    async fn get_user(&self, user_handle: &str) -> Result<Option<UserItem>, Error> {
+        Ok(self.users.lock().unwrap().get(user_handle).cloned())
+    }
+
+    async fn list_credentials(
+        &self,
+        user_handle: &str,
+    ) -> Result<Vec<CredentialItem>, Error> {
+        Ok(self.credentials.lock().unwrap()
+            .values()
+            .filter(|c| c.user_handle == user_handle)
+            .cloned()
+            .collect())
+    }
+
+    async fn list_credentials_by_username(
+        &self,
+        username: &str,
+    ) -> Result<Option<(String, Vec<CredentialItem>)>, Error> {
+        let Some(user_handle) = self.find_user_handle(username).await? else {
+            return Ok(None);
+        };
+        let credentials = self.list_credentials(&user_handle).await?;
+        Ok(Some((user_handle, credentials)))
+    }
+
+    async fn get_credential(
+        &self,
+        key: CredentialKey<'_>,
+    ) -> Result<Option<CredentialItem>, Error> {
+        Ok(self.credential(key))
+    }
+
+    async fn create_user(
+        &self,
+        user: UserItem,
+        credential: CredentialItem,
+    ) -> Result<(), CreateUserError> {
+        let mut users = self.users.lock().unwrap();
+        let mut credentials = self.credentials.lock().unwrap();
+        if users.contains_key(&user.user_handle) {
+            return Err(CreateUserError::UserExists);
+        }
+        let key = (credential.user_handle.clone(), credential.credential_id.clone());
+        if credentials.contains_key(&key) {
+            return Err(CreateUserError::CredentialExists);
+        }
+        users.insert(user.user_handle.clone(), user);
+        credentials.insert(key, credential);
+        Ok(())
+    }
+
+    async fn add_credential(&self, credential: CredentialItem) -> Result<bool, Error> {
+        let mut credentials = self.credentials.lock().unwrap();
+        let key = (credential.user_handle.clone(), credential.credential_id.clone());
+        if credentials.contains_key(&key) {
+            return Ok(false);
+        }
+        credentials.insert(key, credential);
+        Ok(true)
+    }
+
+    async fn update_credential(
+        &self,
+        current: &CredentialItem,
+        credential: String,
+        backup_eligible: bool,
+        backup_state: bool,
+        updated_at: String,
+    ) -> Result<bool, Error> {
+        let mut credentials = self.credentials.lock().unwrap();
+        let Some(stored) = credentials
+            .get_mut(&(current.user_handle.clone(), current.credential_id.clone()))
+            .filter(|stored| stored.version == current.version) else
+        {
+            return Ok(false);
+        };
+        stored.credential = credential;
+        stored.backup_eligible = Some(backup_eligible);
+        stored.backup_state = Some(backup_state);
+        stored.last_used_at = Some(updated_at.clone());
+        stored.updated_at = updated_at;
+        stored.auth_count = Some(stored.auth_count.unwrap_or(0) + 1);
+        stored.version = Some(current.version.unwrap_or(0) + 1);
+        Ok(true)
+    }
+
+    async fn touch_credential(
+        &self,
+        key: CredentialKey<'_>,
+        used_at: String,
+    ) -> Result<(), Error> {
+        let mut credentials = self.credentials.lock().unwrap();
+        let stored = credentials
+            .get_mut(&(key.user_handle.into(), key.credential_id.into()))
+            .ok_or(Error::Storage("failed to record last use of credential"))?;
+        stored.last_used_at = Some(used_at);
+        stored.auth_count = Some(stored.auth_count.unwrap_or(0) + 1);
+        Ok(())
+    }
+
+    async fn flag_legacy_credential(
+        &self,
+        credential_item: &CredentialItem,
+        legacy_rp_id: &str,
+    ) -> Result<(), Error> {
+        let mut credentials = self.credentials.lock().unwrap();
+        let stored = credentials
+            .get_mut(&(
+                credential_item.user_handle.clone(),
+                credential_item.credential_id.clone(),
+            ))
+            .ok_or(Error::Storage("failed to flag legacy credential"))?;
+        stored.legacy_rp_id = Some(legacy_rp_id.into());
+        Ok(())
+    }
+
+    async fn put_recovery_codes(&self, codes: RecoveryCodesItem) -> Result<(), Error> {
+        self.recovery_codes.lock().unwrap().insert(codes.user_handle.clone(), codes);
+        Ok(())
+    }
+
+    async fn consume_recovery_code(
+        &self,
+        user_handle: &str,
+        code_hash: &str,
+    ) -> Result<bool, Error> {
+        let mut recovery_codes = self.recovery_codes.lock().unwrap();
+        let Some(codes) = recovery_codes.get_mut(user_handle) else {
+            return Ok(false);
+        };
+        let count = codes.code_hashes.len();
+        codes.code_hashes.retain(|h| h != code_hash);
+        Ok(codes.code_hashes.len() < count)
+    }
+
+    async fn delete_credential(&self, key: CredentialKey<'_>) -> Result<bool, Error> {
+        Ok(self.credentials.lock().unwrap()
+            .remove(&(key.user_handle.into(), key.credential_id.into()))
+            .is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD as base64url};
+    use webauthn_rs::prelude::Webauthn;
+
+    use crate::authenticator::VirtualAuthenticator;
+    use crate::authenticator::testing::{ORIGIN, register, webauthn};
+
+    // authenticates with a registered passkey.
+    fn authenticate(
+        webauthn: &Webauthn,
+        authenticator: &mut VirtualAuthenticator,
+        passkey: &Passkey,
+    ) -> AuthenticationResult {
+        let (options, state) = webauthn
+            .start_passkey_authentication(&[passkey.clone()])
+            .unwrap();
+        let credential = authenticator.get(&options).unwrap();
+        webauthn.finish_passkey_authentication(&credential, &state).unwrap()
+    }
+
+    fn stored_credential(passkey: &Passkey) -> CredentialItem {
+        CredentialItem {
+            credential: serde_json::to_string(passkey).unwrap(),
+            version: Some(0),
+            ..CredentialItem::for_test("AAAA", &base64url.encode(passkey.cred_id()))
+        }
+    }
+
+    fn counter_of(credential: &CredentialItem) -> u64 {
+        let credential: serde_json::Value =
+            serde_json::from_str(&credential.credential).unwrap();
+        credential["cred"]["counter"].as_u64().unwrap()
+    }
+
+    #[tokio::test]
+    async fn record_authentication_should_update_then_touch_credential() {
+        let webauthn = webauthn();
+        let mut authenticator = VirtualAuthenticator::new(ORIGIN);
+        let passkey = register(&webauthn, &mut authenticator);
+        let store = MemoryCredentialStore::default();
+        let credential = stored_credential(&passkey);
+        store.put_credential(credential.clone());
+        let auth_result = authenticate(&webauthn, &mut authenticator, &passkey);
+
+        // the signature counter has increased
+        record_authentication(&store, credential.clone(), &auth_result).await.unwrap();
+        let updated = store.credential(credential.key()).unwrap();
+        assert_eq!(counter_of(&updated), 1);
+        assert_eq!(updated.version, Some(1));
+        assert_eq!(updated.auth_count, Some(1));
+        assert_eq!(updated.last_used_at.as_ref(), Some(&updated.updated_at));
+
+        // nothing but the last use changes for the same result
+        record_authentication(&store, updated.clone(), &auth_result).await.unwrap();
+        let touched = store.credential(credential.key()).unwrap();
+        assert_eq!(touched.version, Some(1));
+        assert_eq!(touched.auth_count, Some(2));
+        assert_eq!(touched.credential, updated.credential);
+    }
+
+    #[tokio::test]
+    async fn record_authentication_should_retry_concurrently_updated_credential() {
+        let webauthn = webauthn();
+        let mut authenticator = VirtualAuthenticator::new(ORIGIN);
+        let passkey = register(&webauthn, &mut authenticator);
+        let store = MemoryCredentialStore::default();
+        let stale = stored_credential(&passkey);
+        // another update has bumped the version since `stale` was read
+        store.put_credential(CredentialItem {
+            version: Some(3),
+            ..stale.clone()
+        });
+        let auth_result = authenticate(&webauthn, &mut authenticator, &passkey);
+
+        record_authentication(&store, stale.clone(), &auth_result).await.unwrap();
+        let updated = store.credential(stale.key()).unwrap();
+        assert_eq!(counter_of(&updated), 1);
+        assert_eq!(updated.version, Some(4));
+        assert_eq!(updated.auth_count, Some(1));
+    }
+
+    #[tokio::test]
+    async fn record_authentication_should_fail_if_credential_is_deleted() {
+        let webauthn = webauthn();
+        let mut authenticator = VirtualAuthenticator::new(ORIGIN);
+        let passkey = register(&webauthn, &mut authenticator);
+        let store = MemoryCredentialStore::default();
+        let auth_result = authenticate(&webauthn, &mut authenticator, &passkey);
+
+        assert!(matches!(
+            record_authentication(&store, stored_credential(&passkey), &auth_result).await,
+            Err(Error::Storage("credential deleted during update")),
+        ));
+    }
+}
@@ -0,0 +1,550 @@
+//! Information on credentials exposed by the APIs.
+
+use base64::{
+    Engine as _,
+    engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
+};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::error::Error;
+use crate::items::CredentialItem;
+use crate::passkey::PasskeyProperties;
+use crate::telemetry::truncate;
+
+/// Information on a credential.
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialInfo {
+    /// Credential ID.
+    pub credential_id: String,
+
+    /// Type of the credential; "passkey" or "securityKey".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credential_type: Option<String>,
+
+    /// Authenticator attachment reported at registration.
+    ///
+    /// Advisory; the client reports it without a signature.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authenticator_attachment: Option<String>,
+
+    /// AAGUID of the authenticator.
+    ///
+    /// Omitted if the client hid it at registration.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aaguid: Option<String>,
+
+    /// Name of the authenticator; e.g., "YubiKey 5 Series".
+    ///
+    /// Omitted unless the FIDO Metadata Service lists the authenticator.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authenticator_name: Option<String>,
+
+    /// Client that registered the credential.
+    ///
+    /// Omitted if the credential was registered before clients were recorded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registered_from: Option<ClientOrigin>,
+
+    /// Whether the credential is eligible for backup.
+    pub backup_eligible: bool,
+
+    /// Whether the credential is backed up.
+    pub backup_state: bool,
+
+    /// Whether the credential is client-side discoverable and usable without
+    /// a username.
+    ///
+    /// Omitted if the client did not report it at registration.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discoverable: Option<bool>,
+
+    /// Whether the credential supports the `prf` extension.
+    ///
+    /// Omitted if the client did not report it at registration.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prf_enabled: Option<bool>,
+
+    /// When the credential was registered.
+    pub created_at: String,
+
+    /// When the credential was last updated.
+    pub updated_at: String,
+
+    /// When the credential was last used for authentication.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<String>,
+
+    /// Number of successful authentications with the credential.
+    ///
+    /// Authentications before they were counted are not included.
+    pub auth_count: u64,
+
+    /// When the credential was disabled by an administrator.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disabled_at: Option<String>,
+
+    /// When the credential was deleted by the user.
+    ///
+    /// A deleted credential can be restored until the retention window
+    /// elapses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<String>,
+}
+
+impl CredentialInfo {
+    /// Extracts the information from a credential item.
+    ///
+    /// The backup flags fall back to the serialized credential for items
+    /// stored before they were recorded.
+    pub fn from_credential(item: CredentialItem) -> Result<Self, Error> {
+        let (backup_eligible, backup_state) = match (
+            item.backup_eligible,
+            item.backup_state,
+        ) {
+            (Some(backup_eligible), Some(backup_state)) =>
+                (backup_eligible, backup_state),
+            _ => {
+                let credential: serde_json::Value =
+                    serde_json::from_str(&item.credential)
+                        .or(Err(Error::BadItemAttribute("credential")))?;
+                let properties = PasskeyProperties::of(&credential)?;
+                (properties.backup_eligible, properties.backup_state)
+            }
+        };
+        Ok(Self {
+            credential_id: item.credential_id,
+            credential_type: item.credential_type,
+            authenticator_attachment: item.authenticator_attachment,
+            aaguid: item.aaguid,
+            authenticator_name: item.authenticator_name,
+            registered_from: ClientOrigin::of(item.registered_ip, item.registered_user_agent),
+            backup_eligible,
+            backup_state,
+            discoverable: item.discoverable,
+            prf_enabled: item.prf_enabled,
+            created_at: item.created_at,
+            updated_at: item.updated_at,
+            last_used_at: item.last_used_at,
+            auth_count: item.auth_count.unwrap_or(0),
+            disabled_at: item.disabled_at,
+            deleted_at: item.deleted_at,
+        })
+    }
+}
+
+/// Attestation statement of a credential given at registration.
+///
+/// Exposed only to administrators for compliance review.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialAttestation {
+    /// Format of the attestation statement.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+
+    /// AAGUID of the authenticator.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aaguid: Option<String>,
+
+    /// "base64url"-encoded DER certificates, the attestation certificate
+    /// first.
+    pub certificates: Vec<String>,
+}
+
+impl CredentialAttestation {
+    /// Extracts the attestation statement from a credential item.
+    ///
+    /// Returns `None` unless the attestation statement has been stored.
+    pub fn of(item: &CredentialItem) -> Option<Self> {
+        if item.attestation_format.is_none() && item.attestation_certificates.is_none() {
+            return None;
+        }
+        Some(Self {
+            format: item.attestation_format.clone(),
+            aaguid: item.aaguid.clone(),
+            certificates: item.attestation_certificates.clone().unwrap_or_default(),
+        })
+    }
+}
+
+/// Client from which a credential was registered.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+#[serde(rename_all = "camelCase")]
+pub struct ClientOrigin {
+    /// Source IP address.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_ip: Option<String>,
+
+    /// User agent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<String>,
+}
+
+impl ClientOrigin {
+    /// Returns `None` if neither is known.
+    pub fn of(source_ip: Option<String>, user_agent: Option<String>) -> Option<Self> {
+        if source_ip.is_none() && user_agent.is_none() {
+            return None;
+        }
+        Some(Self { source_ip, user_agent })
+    }
+}
+
+/// Public keys of credentials as a JSON Web Key Set (RFC 7517).
+#[derive(Clone, Debug, Serialize)]
+pub struct CredentialPublicKeys {
+    /// Keys.
+    pub keys: Vec<CredentialPublicKey>,
+}
+
+impl CredentialPublicKeys {
+    /// Collects the public keys of the enabled credentials among given ones.
+    ///
+    /// Credentials whose keys have no JWK representation are omitted.
+    pub fn of_credentials(credentials: &[CredentialItem]) -> Result<Self, Error> {
+        let mut keys = Vec::with_capacity(credentials.len());
+        for credential in credentials.iter().filter(|c| c.disabled_at.is_none()) {
+            match CredentialPublicKey::from_credential(credential) {
+                Ok(key) => keys.push(key),
+                Err(Error::Inconvertible(reason)) => warn!(
+                    "omitting public key of {}: {}",
+                    truncate(&credential.credential_id),
+                    reason,
+                ),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(Self { keys })
+    }
+}
+
+/// Public key of a credential as a JSON Web Key (RFC 7517).
+///
+/// Backends may verify signatures made with the credential; e.g., assertions
+/// over a transaction.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct CredentialPublicKey {
+    /// Key type; "EC", "OKP", or "RSA".
+    pub kty: &'static str,
+
+    /// Curve; "P-256", "P-384", "P-521", "Ed25519", or "Ed448".
+    ///
+    /// Omitted for RSA keys.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crv: Option<&'static str>,
+
+    /// "base64url"-encoded x coordinate of an EC or OKP key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub x: Option<String>,
+
+    /// "base64url"-encoded y coordinate of an EC key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub y: Option<String>,
+
+    /// "base64url"-encoded modulus of an RSA key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub n: Option<String>,
+
+    /// "base64url"-encoded exponent of an RSA key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub e: Option<String>,
+
+    /// Key ID; the credential ID.
+    pub kid: String,
+
+    /// JWS algorithm; e.g., "ES256".
+    pub alg: &'static str,
+
+    /// COSE algorithm identifier; e.g., -7 for ES256.
+    #[serde(rename = "coseAlg")]
+    pub cose_alg: i64,
+
+    /// Use; always "sig".
+    #[serde(rename = "use")]
+    pub use_: &'static str,
+}
+
+impl CredentialPublicKey {
+    /// Extracts the public key from a credential item.
+    ///
+    /// Fails with [`Error::Inconvertible`] if the algorithm or curve has no
+    /// JWK representation; e.g., RS1.
+    pub fn from_credential(item: &CredentialItem) -> Result<Self, Error> {
+        #[derive(Deserialize)]
+        struct SerializedPasskey {
+            cred: SerializedCredential,
+        }
+        #[derive(Deserialize)]
+        struct SerializedCredential {
+            cred: SerializedCoseKey,
+        }
+        #[derive(Deserialize)]
+        struct SerializedCoseKey {
+            type_: String,
+            key: SerializedKeyType,
+        }
+        #[derive(Deserialize)]
+        enum SerializedKeyType {
+            #[serde(rename = "EC_EC2")]
+            Ec2 { curve: String, x: String, y: String },
+            #[serde(rename = "EC_OKP")]
+            Okp { curve: String, x: String },
+            #[serde(rename = "RSA")]
+            Rsa { n: String, e: Vec<u8> },
+        }
+
+        let key = serde_json::from_str::<SerializedPasskey>(&item.credential)
+            .or(Err(Error::BadItemAttribute("credential")))?
+            .cred
+            .cred;
+        let (alg, cose_alg) = jws_algorithm(&key.type_)
+            .ok_or(Error::Inconvertible("unsupported key algorithm"))?;
+        let curve = |curve: &str| jwk_curve(curve)
+            .ok_or(Error::Inconvertible("unsupported key curve"));
+        let jwk = Self {
+            kty: "EC",
+            crv: None,
+            x: None,
+            y: None,
+            n: None,
+            e: None,
+            kid: item.credential_id.clone(),
+            alg,
+            cose_alg,
+            use_: "sig",
+        };
+        Ok(match key.key {
+            SerializedKeyType::Ec2 { curve: crv, x, y } => Self {
+                crv: Some(curve(&crv)?),
+                x: Some(x),
+                y: Some(y),
+                ..jwk
+            },
+            SerializedKeyType::Okp { curve: crv, x } => Self {
+                kty: "OKP",
+                crv: Some(curve(&crv)?),
+                x: Some(x),
+                ..jwk
+            },
+            SerializedKeyType::Rsa { n, e } => Self {
+                kty: "RSA",
+                n: Some(n),
+                e: Some(base64url.encode(e)),
+                ..jwk
+            },
+        })
+    }
+}
+
+// JWS algorithm and COSE algorithm identifier of a COSE algorithm serialized
+// by the Webauthn library.
+fn jws_algorithm(cose_algorithm: &str) -> Option<(&'static str, i64)> {
+    match cose_algorithm {
+        "ES256" => Some(("ES256", -7)),
+        "ES384" => Some(("ES384", -35)),
+        "ES512" => Some(("ES512", -36)),
+        "RS256" => Some(("RS256", -257)),
+        "RS384" => Some(("RS384", -258)),
+        "RS512" => Some(("RS512", -259)),
+        "PS256" => Some(("PS256", -37)),
+        "PS384" => Some(("PS384", -38)),
+        "PS512" => Some(("PS512", -39)),
+        "EDDSA" => Some(("EdDSA", -8)),
+        _ => None,
+    }
+}
+
+// JWK curve of a curve serialized by the Webauthn library.
+fn jwk_curve(curve: &str) -> Option<&'static str> {
+    match curve {
+        "SECP256R1" => Some("P-256"),
+        "SECP384R1" => Some("P-384"),
+        "SECP521R1" => Some("P-521"),
+        "ED25519" => Some("Ed25519"),
+        "ED448" => Some("Ed448"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credential_item(backup_state: Option<bool>) -> CredentialItem {
+        CredentialItem {
+            credential: serde_json::json!({
+                "cred": {
+                    "cred_id": "BBBB",
+                    "counter": 0,
+                    "user_verified": true,
+                    "backup_eligible": true,
+                    "backup_state": true,
+                },
+            }).to_string(),
+            backup_eligible: backup_state.map(|_| true),
+            backup_state,
+            last_used_at: Some("2024-01-02T00:00:00Z".into()),
+            auth_count: Some(2),
+            ..CredentialItem::for_test("AAAA", "BBBB")
+        }
+    }
+
+    #[test]
+    fn credential_info_should_prefer_recorded_backup_flags() {
+        let info = CredentialInfo::from_credential(credential_item(Some(false)))
+            .unwrap();
+        assert!(info.backup_eligible);
+        assert!(!info.backup_state);
+        assert_eq!(info.last_used_at.as_deref(), Some("2024-01-02T00:00:00Z"));
+        assert_eq!(info.auth_count, 2);
+    }
+
+    #[test]
+    fn credential_info_should_fall_back_to_serialized_credential() {
+        let info = CredentialInfo::from_credential(credential_item(None))
+            .unwrap();
+        assert!(info.backup_eligible);
+        assert!(info.backup_state);
+        assert_eq!(info.registered_from, None);
+    }
+
+    #[test]
+    fn credential_info_should_count_no_authentication_if_never_counted() {
+        let info = CredentialInfo::from_credential(CredentialItem {
+            last_used_at: None,
+            auth_count: None,
+            ..credential_item(Some(true))
+        }).unwrap();
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["authCount"], serde_json::json!(0));
+        assert!(json.get("lastUsedAt").is_none());
+    }
+
+    #[test]
+    fn credential_info_should_tell_registered_client() {
+        let info = CredentialInfo::from_credential(CredentialItem {
+            registered_ip: Some("192.0.2.1".into()),
+            ..credential_item(Some(true))
+        }).unwrap();
+        assert_eq!(info.registered_from, Some(ClientOrigin {
+            source_ip: Some("192.0.2.1".into()),
+            user_agent: None,
+        }));
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["registeredFrom"], serde_json::json!({ "sourceIp": "192.0.2.1" }));
+    }
+
+    fn credential_with_key(
+        type_: &str,
+        key: serde_json::Value,
+    ) -> CredentialItem {
+        CredentialItem {
+            credential: serde_json::json!({
+                "cred": {
+                    "cred_id": "BBBB",
+                    "cred": { "type_": type_, "key": key },
+                    "counter": 0,
+                    "user_verified": true,
+                },
+            }).to_string(),
+            ..credential_item(Some(true))
+        }
+    }
+
+    #[test]
+    fn credential_public_key_should_convert_ec2_key() {
+        let item = credential_with_key("ES256", serde_json::json!({
+            "EC_EC2": { "curve": "SECP256R1", "x": "xxxx", "y": "yyyy" },
+        }));
+        let jwk = CredentialPublicKey::from_credential(&item).unwrap();
+        assert_eq!(
+            serde_json::to_value(&jwk).unwrap(),
+            serde_json::json!({
+                "kty": "EC",
+                "crv": "P-256",
+                "x": "xxxx",
+                "y": "yyyy",
+                "kid": "BBBB",
+                "alg": "ES256",
+                "coseAlg": -7,
+                "use": "sig",
+            }),
+        );
+    }
+
+    #[test]
+    fn credential_public_key_should_convert_okp_and_rsa_keys() {
+        let item = credential_with_key("EDDSA", serde_json::json!({
+            "EC_OKP": { "curve": "ED25519", "x": "xxxx" },
+        }));
+        let jwk = CredentialPublicKey::from_credential(&item).unwrap();
+        assert_eq!(
+            (jwk.kty, jwk.crv, jwk.alg, jwk.cose_alg),
+            ("OKP", Some("Ed25519"), "EdDSA", -8),
+        );
+
+        let item = credential_with_key("RS256", serde_json::json!({
+            "RSA": { "n": "nnnn", "e": [1, 0, 1] },
+        }));
+        let jwk = CredentialPublicKey::from_credential(&item).unwrap();
+        assert_eq!(jwk.kty, "RSA");
+        assert_eq!(jwk.crv, None);
+        assert_eq!(jwk.e.as_deref(), Some("AQAB"));
+        assert_eq!(jwk.cose_alg, -257);
+    }
+
+    #[test]
+    fn credential_public_key_should_reject_unsupported_algorithm() {
+        let item = credential_with_key("INSECURE_RS1", serde_json::json!({
+            "RSA": { "n": "nnnn", "e": [1, 0, 1] },
+        }));
+        assert!(matches!(
+            CredentialPublicKey::from_credential(&item),
+            Err(Error::Inconvertible(_)),
+        ));
+        assert!(matches!(
+            CredentialPublicKey::from_credential(&credential_item(Some(true))),
+            Err(Error::BadItemAttribute(_)),
+        ));
+    }
+
+    #[test]
+    fn credential_public_keys_should_omit_disabled_and_unsupported_keys() {
+        let ec2 = serde_json::json!({
+            "EC_EC2": { "curve": "SECP256R1", "x": "xxxx", "y": "yyyy" },
+        });
+        let credentials = vec![
+            credential_with_key("ES256", ec2.clone()),
+            CredentialItem {
+                disabled_at: Some("2024-01-03T00:00:00Z".into()),
+                ..credential_with_key("ES256", ec2)
+            },
+            credential_with_key("INSECURE_RS1", serde_json::json!({
+                "RSA": { "n": "nnnn", "e": [1, 0, 1] },
+            })),
+        ];
+        let keys = CredentialPublicKeys::of_credentials(&credentials).unwrap();
+        assert_eq!(keys.keys.len(), 1);
+        assert_eq!(keys.keys[0].alg, "ES256");
+    }
+
+    #[test]
+    fn credential_attestation_should_exist_only_if_stored() {
+        assert_eq!(CredentialAttestation::of(&credential_item(Some(true))), None);
+        let item = CredentialItem {
+            aaguid: Some("cccc".into()),
+            attestation_format: Some("packed".into()),
+            attestation_certificates: Some(vec!["leaf".into()]),
+            ..credential_item(Some(true))
+        };
+        assert_eq!(CredentialAttestation::of(&item), Some(CredentialAttestation {
+            format: Some("packed".into()),
+            aaguid: Some("cccc".into()),
+            certificates: vec!["leaf".into()],
+        }));
+    }
+}
@@ -0,0 +1,137 @@
+//! Soft deletion of credentials.
+//!
+//! A credential deleted by the user is marked deleted and disabled rather
+//! than removed, so that an accidental deletion can be undone within the
+//! retention window. The `credential-cleanup` and `data-retention` jobs
+//! purge credentials whose retention window has elapsed; see
+//! [`purge_deleted_credentials`].
+//!
+//! Timestamps are in the format of `DateTimeFormat::DateTime`, which sorts
+//! lexicographically in time order; a credential is restorable if it was
+//! deleted at or after the [`retention_cutoff`].
+
+use aws_sdk_dynamodb::primitives::{DateTime, DateTimeFormat};
+use std::env;
+use std::time::{Duration, SystemTime};
+use tracing::info;
+
+use crate::config;
+use crate::error::Error;
+use crate::telemetry::truncate;
+use crate::users::UserDirectory;
+
+// Maximum number of items evaluated in a page of the scan.
+const SCAN_PAGE_LIMIT: i32 = 100;
+
+/// Default retention window of deleted credentials in seconds; 30 days.
+pub const DEFAULT_DELETION_RETENTION: u64 = 30 * 24 * 60 * 60;
+
+/// Loads the retention window of deleted credentials.
+///
+/// You can specify to `DELETION_RETENTION` environment variable the retention
+/// window in seconds. "0" turns soft deletion off and credentials are deleted
+/// immediately.
+///
+/// Defaults to [`DEFAULT_DELETION_RETENTION`].
+/// Returns `None` if soft deletion is off.
+pub fn load_deletion_retention() -> Result<Option<Duration>, Error> {
+    match config::var("DELETION_RETENTION") {
+        Ok(retention) => parse_deletion_retention(&retention)
+            .ok_or(Error::BadEnvironmentVariable("DELETION_RETENTION", retention)),
+        Err(env::VarError::NotPresent) =>
+            Ok(Some(Duration::from_secs(DEFAULT_DELETION_RETENTION))),
+        Err(env::VarError::NotUnicode(retention)) => Err(
+            Error::BadEnvironmentVariable(
+                "DELETION_RETENTION",
+                retention.to_string_lossy().into(),
+            ),
+        ),
+    }
+}
+
+fn parse_deletion_retention(retention: &str) -> Option<Option<Duration>> {
+    match retention.parse::<u64>().ok()? {
+        0 => Some(None),
+        secs => Some(Some(Duration::from_secs(secs))),
+    }
+}
+
+/// Formats a given time as a deletion timestamp.
+pub fn deletion_timestamp(at: SystemTime) -> Result<String, Error> {
+    DateTime::from(at)
+        .fmt(DateTimeFormat::DateTime)
+        .or(Err(Error::Storage("failed to format timestamp")))
+}
+
+/// Returns the earliest deletion timestamp that is still within the
+/// retention window at `now`.
+pub fn retention_cutoff(now: SystemTime, retention: Duration) -> Result<String, Error> {
+    let cutoff = now.checked_sub(retention)
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+    deletion_timestamp(cutoff)
+}
+
+/// Purges the credentials deleted before a given cutoff.
+///
+/// A credential restored or deleted again during the purge is not purged.
+/// Returns the number of purged credentials.
+pub async fn purge_deleted_credentials(
+    users: &UserDirectory,
+    cutoff: String,
+) -> Result<usize, Error> {
+    let mut purged = 0;
+    let mut exclusive_start_key = None;
+    loop {
+        let page = users
+            .scan_deleted_credentials(cutoff.clone(), SCAN_PAGE_LIMIT, exclusive_start_key)
+            .await?;
+        for credential in page.credentials {
+            let Some(deleted_at) = credential.deleted_at.clone() else {
+                continue;
+            };
+            if users.purge_credential(credential.key(), deleted_at).await? {
+                info!("purged credential: {}", truncate(&credential.credential_id));
+                purged += 1;
+            } else {
+                info!("credential restored or deleted again: {}", truncate(&credential.credential_id));
+            }
+        }
+        exclusive_start_key = page.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+            return Ok(purged);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_deletion_retention_should_turn_off_with_zero() {
+        assert_eq!(
+            parse_deletion_retention("86400"),
+            Some(Some(Duration::from_secs(86400))),
+        );
+        assert_eq!(parse_deletion_retention("0"), Some(None));
+        assert_eq!(parse_deletion_retention("-1"), None);
+        assert_eq!(parse_deletion_retention("30d"), None);
+    }
+
+    #[test]
+    fn retention_cutoff_should_precede_now_by_retention() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(2 * 86400);
+        assert_eq!(
+            retention_cutoff(now, Duration::from_secs(86400)).unwrap(),
+            "1970-01-02T00:00:00Z",
+        );
+        assert_eq!(
+            retention_cutoff(now, Duration::from_secs(3 * 86400)).unwrap(),
+            "1970-01-01T00:00:00Z",
+        );
+        assert!(
+            retention_cutoff(now, Duration::from_secs(86400)).unwrap()
+                < deletion_timestamp(now).unwrap(),
+        );
+    }
+}
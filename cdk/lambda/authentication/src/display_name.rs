@@ -0,0 +1,122 @@
+//! Display name sanitization.
+//!
+//! Display names are free text shown by authenticators, so they are
+//! sanitized rather than validated.
+
+use std::env;
+
+use crate::config;
+use crate::error::Error;
+
+/// Default maximum length of a display name in characters.
+pub const DEFAULT_MAX_LENGTH: usize = 64;
+
+/// Loads the maximum length of a display name.
+///
+/// You can specify to `DISPLAY_NAME_MAX_LENGTH` environment variable the
+/// maximum length in characters.
+///
+/// Defaults to [`DEFAULT_MAX_LENGTH`].
+pub fn load_max_display_name_length() -> Result<usize, Error> {
+    match config::var("DISPLAY_NAME_MAX_LENGTH") {
+        Ok(length) => length.parse()
+            .ok()
+            .filter(|length| *length > 0)
+            .ok_or(Error::BadEnvironmentVariable("DISPLAY_NAME_MAX_LENGTH", length)),
+        Err(env::VarError::NotPresent) => Ok(DEFAULT_MAX_LENGTH),
+        Err(env::VarError::NotUnicode(length)) => Err(
+            Error::BadEnvironmentVariable(
+                "DISPLAY_NAME_MAX_LENGTH",
+                length.to_string_lossy().into(),
+            ),
+        ),
+    }
+}
+
+/// Sanitizes a display name.
+///
+/// Turns line breaks, tabs, and other whitespace into spaces, collapses runs
+/// of whitespace, removes the other control and bidirectional formatting
+/// characters, trims whitespace, and truncates the result to `max_length`
+/// characters.
+///
+/// Returns `None` if nothing remains.
+pub fn sanitize_display_name(name: &str, max_length: usize) -> Option<String> {
+    let mut sanitized = String::with_capacity(name.len());
+    let mut pending_space = false;
+    for c in name.chars() {
+        if c.is_whitespace() {
+            pending_space = true;
+        } else if !c.is_control() && !is_bidi_control(c) {
+            if pending_space && !sanitized.is_empty() {
+                sanitized.push(' ');
+            }
+            pending_space = false;
+            sanitized.push(c);
+        }
+    }
+    let name: String = sanitized.chars().take(max_length).collect();
+    // truncation may leave trailing whitespace
+    let name = name.trim_end();
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.into())
+    }
+}
+
+// Bidirectional formatting characters that can disguise text in logs.
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{200E}' | '\u{200F}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_display_name_should_strip_control_characters() {
+        assert_eq!(
+            sanitize_display_name("  John\r\nDoe\t ", 64),
+            Some("John Doe".to_string()),
+        );
+        assert_eq!(
+            sanitize_display_name("evil\u{202E}txt.exe", 64),
+            Some("eviltxt.exe".to_string()),
+        );
+        assert_eq!(
+            sanitize_display_name("\u{1b}[31mred", 64),
+            Some("[31mred".to_string()),
+        );
+    }
+
+    #[test]
+    fn sanitize_display_name_should_collapse_whitespace() {
+        assert_eq!(
+            sanitize_display_name("John\t\tQ.  \u{3000}Doe", 64),
+            Some("John Q. Doe".to_string()),
+        );
+        assert_eq!(
+            sanitize_display_name("John \u{1b} Doe", 64),
+            Some("John Doe".to_string()),
+        );
+    }
+
+    #[test]
+    fn sanitize_display_name_should_cap_length_in_characters() {
+        assert_eq!(
+            sanitize_display_name("山田太郎さん", 4),
+            Some("山田太郎".to_string()),
+        );
+        assert_eq!(
+            sanitize_display_name("John Doe", 5),
+            Some("John".to_string()),
+        );
+    }
+
+    #[test]
+    fn sanitize_display_name_should_reject_blank_name() {
+        assert_eq!(sanitize_display_name("", 64), None);
+        assert_eq!(sanitize_display_name(" \n\t", 64), None);
+    }
+}
@@ -0,0 +1,237 @@
+//! Domain events published to Amazon EventBridge.
+//!
+//! Downstream systems like analytics, CRM, and fraud detection subscribe to
+//! the events with EventBridge rules instead of being called by the
+//! functions. Every event has the source [`EVENT_SOURCE`], the detail type of
+//! [`DomainEvent::detail_type`], and the detail of the serialized struct in
+//! this module; the structs are the schema of the events.
+//!
+//! Publishing an event is best effort: a failure is logged but does not fail
+//! the request, and the audit log remains the record of the events.
+
+use aws_sdk_eventbridge::{
+    primitives::DateTime,
+    types::PutEventsRequestEntry,
+};
+use serde::Serialize;
+use std::env;
+use std::time::SystemTime;
+use tracing::error;
+
+use crate::config;
+use crate::error::Error;
+
+/// Source of the domain events.
+pub const EVENT_SOURCE: &str = "passkey-test.authentication";
+
+/// A passkey has been registered.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PasskeyRegistered {
+    /// User handle of the user who owns the passkey.
+    pub user_handle: String,
+
+    /// "base64url"-encoded ID of the credential.
+    pub credential_id: String,
+
+    /// ID of the tenant.
+    ///
+    /// Omitted for the default relying party.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+
+    /// Kind of the registration; e.g., "passkey", "recovery".
+    pub registration: &'static str,
+}
+
+/// A user has authenticated with a passkey.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticationSucceeded {
+    /// User handle of the authenticated user.
+    pub user_handle: String,
+
+    /// "base64url"-encoded ID of the credential used.
+    pub credential_id: String,
+
+    /// ID of the tenant.
+    ///
+    /// Omitted for the default relying party.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+}
+
+/// A credential has been deleted or disabled.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialRevoked {
+    /// User handle of the user who owns the credential.
+    pub user_handle: String,
+
+    /// "base64url"-encoded ID of the credential.
+    pub credential_id: String,
+
+    /// ID of the tenant.
+    ///
+    /// Omitted for the default relying party.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+
+    /// How the credential has been revoked; "deleted" or "disabled".
+    pub action: &'static str,
+
+    /// Who revoked the credential; "user" or "administrator".
+    pub revoked_by: &'static str,
+}
+
+/// Domain event.
+///
+/// Serializes into the detail of the event.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum DomainEvent {
+    /// See [`PasskeyRegistered`].
+    PasskeyRegistered(PasskeyRegistered),
+    /// See [`AuthenticationSucceeded`].
+    AuthenticationSucceeded(AuthenticationSucceeded),
+    /// See [`CredentialRevoked`].
+    CredentialRevoked(CredentialRevoked),
+}
+
+impl DomainEvent {
+    /// Detail type of the event.
+    pub fn detail_type(&self) -> &'static str {
+        match self {
+            DomainEvent::PasskeyRegistered(_) => "PasskeyRegistered",
+            DomainEvent::AuthenticationSucceeded(_) => "AuthenticationSucceeded",
+            DomainEvent::CredentialRevoked(_) => "CredentialRevoked",
+        }
+    }
+
+    /// Serializes the detail of the event.
+    pub fn detail(&self) -> Result<String, Error> {
+        serde_json::to_string(self)
+            .or(Err(Error::Event("failed to serialize event detail")))
+    }
+}
+
+/// Publisher of domain events to an EventBridge event bus.
+#[derive(Clone, Debug)]
+pub struct EventPublisher {
+    eventbridge: aws_sdk_eventbridge::Client,
+    event_bus_name: String,
+}
+
+/// Loads the event publisher configuration.
+///
+/// You can specify to `EVENT_BUS_NAME` environment variable the name of the
+/// EventBridge event bus.
+///
+/// Returns `None` if `EVENT_BUS_NAME` is not set, which means no events are
+/// published.
+pub fn load_event_publisher(
+    eventbridge: aws_sdk_eventbridge::Client,
+) -> Result<Option<EventPublisher>, Error> {
+    match config::var("EVENT_BUS_NAME") {
+        Ok(event_bus_name) if !event_bus_name.is_empty() => Ok(Some(EventPublisher {
+            eventbridge,
+            event_bus_name,
+        })),
+        Ok(event_bus_name) => Err(
+            Error::BadEnvironmentVariable("EVENT_BUS_NAME", event_bus_name),
+        ),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(event_bus_name)) => Err(
+            Error::BadEnvironmentVariable(
+                "EVENT_BUS_NAME",
+                event_bus_name.to_string_lossy().into(),
+            ),
+        ),
+    }
+}
+
+impl EventPublisher {
+    /// Publishes an event.
+    pub async fn publish(&self, event: &DomainEvent) -> Result<(), Error> {
+        let res = self.eventbridge
+            .put_events()
+            .entries(self.entry(event)?)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "publishing {}", event.detail_type());
+                Error::Event("failed to publish event")
+            })?;
+        if res.failed_entry_count > 0 {
+            error!(entries = ?res.entries, "rejected {}", event.detail_type());
+            return Err(Error::Event("event rejected"));
+        }
+        Ok(())
+    }
+
+    // builds the entry of an event.
+    fn entry(&self, event: &DomainEvent) -> Result<PutEventsRequestEntry, Error> {
+        Ok(PutEventsRequestEntry::builder()
+            .event_bus_name(self.event_bus_name.clone())
+            .source(EVENT_SOURCE)
+            .detail_type(event.detail_type())
+            .detail(event.detail()?)
+            .time(DateTime::from(SystemTime::now()))
+            .build())
+    }
+}
+
+/// Publishes an event if a publisher is configured.
+///
+/// A failure is logged and ignored.
+pub async fn publish_event(publisher: Option<&EventPublisher>, event: DomainEvent) {
+    if let Some(publisher) = publisher {
+        if let Err(e) = publisher.publish(&event).await {
+            error!("failed to publish {}: {}", event.detail_type(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn domain_event_should_serialize_detail_in_camel_case() {
+        let event = DomainEvent::PasskeyRegistered(PasskeyRegistered {
+            user_handle: "user".into(),
+            credential_id: "abc".into(),
+            tenant_id: None,
+            registration: "passkey",
+        });
+        assert_eq!(event.detail_type(), "PasskeyRegistered");
+        let detail: serde_json::Value =
+            serde_json::from_str(&event.detail().unwrap()).unwrap();
+        assert_eq!(detail, serde_json::json!({
+            "userHandle": "user",
+            "credentialId": "abc",
+            "registration": "passkey",
+        }));
+    }
+
+    #[test]
+    fn domain_event_should_include_tenant_id_if_any() {
+        let event = DomainEvent::CredentialRevoked(CredentialRevoked {
+            user_handle: "user".into(),
+            credential_id: "abc".into(),
+            tenant_id: Some("acme".into()),
+            action: "disabled",
+            revoked_by: "administrator",
+        });
+        assert_eq!(event.detail_type(), "CredentialRevoked");
+        let detail: serde_json::Value =
+            serde_json::from_str(&event.detail().unwrap()).unwrap();
+        assert_eq!(detail, serde_json::json!({
+            "userHandle": "user",
+            "credentialId": "abc",
+            "tenantId": "acme",
+            "action": "disabled",
+            "revokedBy": "administrator",
+        }));
+    }
+}
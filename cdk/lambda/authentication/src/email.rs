@@ -0,0 +1,169 @@
+//! Recovery emails.
+//!
+//! A user who has lost all the authenticators may request a recovery link by
+//! email, which is sent through Amazon SES. The link carries a one-time token
+//! whose hash is stored in the session table as a [`RecoveryLinkItem`].
+//!
+//! [`RecoveryLinkItem`]: crate::items::RecoveryLinkItem
+
+use aws_sdk_sesv2::types::{Body, Content, Destination, EmailContent, Message};
+use std::env;
+use tracing::error;
+
+use crate::config;
+use crate::error::Error;
+
+/// Default time to live of a recovery link in seconds.
+pub const DEFAULT_RECOVERY_LINK_TTL: i64 = 15 * 60;
+
+/// Sender of recovery links.
+#[derive(Clone, Debug)]
+pub struct RecoveryMailer {
+    ses: aws_sdk_sesv2::Client,
+    sender: String,
+    link_url: String,
+    link_ttl: i64,
+}
+
+/// Loads the sender of recovery links from the environment variables.
+///
+/// - `RECOVERY_EMAIL_SENDER`: email address of the sender verified in SES
+/// - `RECOVERY_LINK_URL`: URL of the page that starts recovery with the token
+///   given in the `token` query parameter
+/// - `RECOVERY_LINK_TTL`: (optional) time to live of a link in seconds; 15
+///   minutes by default
+///
+/// Returns `None` if neither `RECOVERY_EMAIL_SENDER` nor `RECOVERY_LINK_URL`
+/// is set, which means email recovery is disabled.
+pub fn load_recovery_mailer(
+    ses: aws_sdk_sesv2::Client,
+) -> Result<Option<RecoveryMailer>, Error> {
+    let sender = load_optional_var("RECOVERY_EMAIL_SENDER")?;
+    let link_url = load_optional_var("RECOVERY_LINK_URL")?;
+    let (sender, link_url) = match (sender, link_url) {
+        (Some(sender), Some(link_url)) => (sender, link_url),
+        (None, None) => return Ok(None),
+        (Some(_), None) => return Err(Error::BadEnvironmentVariable(
+            "RECOVERY_LINK_URL",
+            "must be set with RECOVERY_EMAIL_SENDER".into(),
+        )),
+        (None, Some(_)) => return Err(Error::BadEnvironmentVariable(
+            "RECOVERY_EMAIL_SENDER",
+            "must be set with RECOVERY_LINK_URL".into(),
+        )),
+    };
+    let link_ttl = match load_optional_var("RECOVERY_LINK_TTL")? {
+        Some(ttl) => ttl.parse()
+            .ok()
+            .filter(|ttl| *ttl > 0)
+            .ok_or(Error::BadEnvironmentVariable("RECOVERY_LINK_TTL", ttl))?,
+        None => DEFAULT_RECOVERY_LINK_TTL,
+    };
+    Ok(Some(RecoveryMailer {
+        ses,
+        sender,
+        link_url,
+        link_ttl,
+    }))
+}
+
+impl RecoveryMailer {
+    /// Time to live of a recovery link in seconds.
+    pub fn link_ttl(&self) -> i64 {
+        self.link_ttl
+    }
+
+    /// Sends a recovery link with a given token to an email address.
+    pub async fn send_recovery_link(
+        &self,
+        to: &str,
+        token: &str,
+    ) -> Result<(), Error> {
+        let link = recovery_link(&self.link_url, token);
+        let (subject, text) = recovery_message(&link, self.link_ttl);
+        let message = Message::builder()
+            .subject(text_content(subject)?)
+            .body(Body::builder().text(text_content(text)?).build())
+            .build()
+            .or(Err(Error::Email("failed to build recovery email")))?;
+        self.ses
+            .send_email()
+            .from_email_address(self.sender.clone())
+            .destination(Destination::builder().to_addresses(to).build())
+            .content(EmailContent::builder().simple(message).build())
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "sending recovery email");
+                Error::Email("failed to send recovery email")
+            })?;
+        Ok(())
+    }
+}
+
+/// Builds a recovery link with a given token.
+///
+/// The token is appended to the `token` query parameter. The token must be
+/// "base64url"-encoded so that it needs no escaping.
+pub fn recovery_link(link_url: &str, token: &str) -> String {
+    let separator = if link_url.contains('?') { '&' } else { '?' };
+    format!("{}{}token={}", link_url, separator, token)
+}
+
+// subject and plain text body of a recovery email.
+fn recovery_message(link: &str, link_ttl: i64) -> (String, String) {
+    let minutes = (link_ttl + 59) / 60;
+    (
+        "Recover your Passkey Test account".into(),
+        format!(
+            "Open the following link within {} minute(s) to register a new \
+             passkey:\n\n{}\n\n\
+             If you did not request this email, you can safely ignore it.\n",
+            minutes,
+            link,
+        ),
+    )
+}
+
+fn text_content(data: String) -> Result<Content, Error> {
+    Content::builder()
+        .data(data)
+        .charset("UTF-8")
+        .build()
+        .or(Err(Error::Email("failed to build recovery email")))
+}
+
+fn load_optional_var(name: &'static str) -> Result<Option<String>, Error> {
+    match config::var(name) {
+        Ok(value) if !value.is_empty() => Ok(Some(value)),
+        Ok(value) => Err(Error::BadEnvironmentVariable(name, value)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(value)) => Err(
+            Error::BadEnvironmentVariable(name, value.to_string_lossy().into()),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recovery_link_should_append_token() {
+        assert_eq!(
+            recovery_link("https://example.com/recover", "abc"),
+            "https://example.com/recover?token=abc",
+        );
+        assert_eq!(
+            recovery_link("https://example.com/recover?lang=en", "abc"),
+            "https://example.com/recover?lang=en&token=abc",
+        );
+    }
+
+    #[test]
+    fn recovery_message_should_include_link_and_expiration() {
+        let (_, text) = recovery_message("https://example.com/?token=abc", 900);
+        assert!(text.contains("https://example.com/?token=abc"));
+        assert!(text.contains("within 15 minute(s)"));
+    }
+}
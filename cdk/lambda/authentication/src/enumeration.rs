@@ -0,0 +1,175 @@
+//! Protection against user enumeration.
+//!
+//! Without the protection, the `start` endpoints tell whether a username
+//! exists; e.g., registration for an existing username reuses the user ID and
+//! excludes the credentials of the user. In the protection mode:
+//! - registration for an existing username starts like one for a new
+//!   username; the user ID is random and no credential is excluded. Finishing
+//!   it still fails with `user_exists`, but only after a ceremony with an
+//!   authenticator.
+//! - authentication for an unknown username, or for a user without any
+//!   usable credential, gets a dummy challenge that allows a credential ID
+//!   derived from the username; see
+//!   [`EnumerationProtection::dummy_credential_id`].
+//! - the `start` endpoints respond no earlier than a minimum duration so that
+//!   the timing does not depend on the username; see
+//!   [`EnumerationProtection::pad`].
+
+use ring::hmac;
+use std::env;
+use std::time::{Duration, Instant};
+
+use crate::config;
+use crate::error::Error;
+use crate::secrets::{SecretCache, load_secret_cache_ttl};
+
+/// Default minimum duration of a `start` response in milliseconds.
+pub const DEFAULT_MIN_DURATION: u64 = 300;
+
+/// Length of a dummy credential ID in bytes.
+pub const DUMMY_CREDENTIAL_ID_LEN: usize = 20;
+
+// salt of dummy credential IDs without the protection mode; public, so
+// dummy credential IDs tell unknown users.
+const DEFAULT_SALT: &[u8] = b"passkey-test dummy credential ID";
+
+/// Protection against user enumeration.
+pub struct EnumerationProtection {
+    min_duration: Duration,
+    salt_secret_id: String,
+    secrets: SecretCache,
+}
+
+/// Loads the protection against user enumeration.
+///
+/// You can specify to the following environment variables:
+/// - `ENUMERATION_PROTECTION`: "true" to enable the protection mode
+/// - `ENUMERATION_MIN_DURATION`: minimum duration of a `start` response in
+///   milliseconds; [`DEFAULT_MIN_DURATION`] by default
+/// - `ENUMERATION_SALT_SECRET_ID`: ID of the secret in Secrets Manager that
+///   salts dummy credential IDs. Required in the protection mode, because
+///   anyone can tell dummy credential IDs salted with a public salt.
+///
+/// Returns `None` if `ENUMERATION_PROTECTION` is not "true".
+pub fn load_enumeration_protection(
+    secrets: aws_sdk_secretsmanager::Client,
+) -> Result<Option<EnumerationProtection>, Error> {
+    match config::var("ENUMERATION_PROTECTION").as_deref() {
+        Ok("true") => {}
+        Ok("false") | Err(env::VarError::NotPresent) => return Ok(None),
+        Ok(value) => return Err(
+            Error::BadEnvironmentVariable("ENUMERATION_PROTECTION", value.into()),
+        ),
+        Err(env::VarError::NotUnicode(value)) => return Err(
+            Error::BadEnvironmentVariable(
+                "ENUMERATION_PROTECTION",
+                value.to_string_lossy().into(),
+            ),
+        ),
+    }
+    let min_duration = match config::var("ENUMERATION_MIN_DURATION") {
+        Ok(millis) => millis.parse().map(Duration::from_millis).or(Err(
+            Error::BadEnvironmentVariable("ENUMERATION_MIN_DURATION", millis),
+        ))?,
+        Err(env::VarError::NotPresent) => Duration::from_millis(DEFAULT_MIN_DURATION),
+        Err(env::VarError::NotUnicode(millis)) => return Err(
+            Error::BadEnvironmentVariable(
+                "ENUMERATION_MIN_DURATION",
+                millis.to_string_lossy().into(),
+            ),
+        ),
+    };
+    let salt_secret_id = match config::var("ENUMERATION_SALT_SECRET_ID") {
+        Ok(secret_id) if !secret_id.is_empty() => secret_id,
+        Ok(_) | Err(env::VarError::NotPresent) => return Err(
+            Error::BadEnvironmentVariable("ENUMERATION_SALT_SECRET_ID", "".into()),
+        ),
+        Err(env::VarError::NotUnicode(secret_id)) => return Err(
+            Error::BadEnvironmentVariable(
+                "ENUMERATION_SALT_SECRET_ID",
+                secret_id.to_string_lossy().into(),
+            ),
+        ),
+    };
+    Ok(Some(EnumerationProtection {
+        min_duration,
+        salt_secret_id,
+        secrets: SecretCache::new(secrets, load_secret_cache_ttl()?),
+    }))
+}
+
+impl EnumerationProtection {
+    /// Minimum duration of a `start` response.
+    pub fn min_duration(&self) -> Duration {
+        self.min_duration
+    }
+
+    /// Waits until the minimum duration has passed since `started_at`.
+    pub async fn pad(&self, started_at: Instant) {
+        pad_response(self.min_duration, started_at).await;
+    }
+
+    /// Returns the dummy credential ID of a given username.
+    ///
+    /// The same username always gets the same dummy credential ID, like the
+    /// credential IDs of an existing user.
+    pub async fn dummy_credential_id(&self, username: &str) -> Result<Vec<u8>, Error> {
+        let salt = self.secrets.get(&self.salt_secret_id).await?;
+        Ok(dummy_credential_id_with(salt.as_bytes(), username))
+    }
+}
+
+/// Returns the dummy credential ID of a given username salted with the
+/// default salt.
+///
+/// Only for challenges outside the protection mode, where unknown users are
+/// not hidden anyway.
+pub fn default_dummy_credential_id(username: &str) -> Vec<u8> {
+    dummy_credential_id_with(DEFAULT_SALT, username)
+}
+
+/// Waits until `min_duration` has passed since `started_at`.
+///
+/// Useful if the [`EnumerationProtection`] is not at hand when a response is
+/// ready.
+pub async fn pad_response(min_duration: Duration, started_at: Instant) {
+    let remaining = remaining_duration(min_duration, started_at.elapsed());
+    if !remaining.is_zero() {
+        tokio::time::sleep(remaining).await;
+    }
+}
+
+fn dummy_credential_id_with(salt: &[u8], username: &str) -> Vec<u8> {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, salt);
+    hmac::sign(&key, username.as_bytes()).as_ref()[..DUMMY_CREDENTIAL_ID_LEN].to_vec()
+}
+
+fn remaining_duration(min_duration: Duration, elapsed: Duration) -> Duration {
+    min_duration.saturating_sub(elapsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dummy_credential_id_should_be_stable_per_username_and_salt() {
+        let id = dummy_credential_id_with(b"salt", "alice");
+        assert_eq!(id.len(), DUMMY_CREDENTIAL_ID_LEN);
+        assert_eq!(id, dummy_credential_id_with(b"salt", "alice"));
+        assert_ne!(id, dummy_credential_id_with(b"salt", "bob"));
+        assert_ne!(id, dummy_credential_id_with(b"pepper", "alice"));
+    }
+
+    #[test]
+    fn remaining_duration_should_not_underflow() {
+        assert_eq!(
+            remaining_duration(Duration::from_millis(300), Duration::from_millis(100)),
+            Duration::from_millis(200),
+        );
+        assert_eq!(
+            remaining_duration(Duration::from_millis(300), Duration::from_millis(400)),
+            Duration::ZERO,
+        );
+    }
+}
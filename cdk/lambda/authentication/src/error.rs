@@ -14,4 +14,61 @@ pub enum Error {
     /// Bad relying party origin.
     #[error("bad relying party origin: `{0}`")]
     BadRelyingPartyOrigin(String),
+    /// Bad environment variable.
+    #[error("bad environment variable {0}: `{1}`")]
+    BadEnvironmentVariable(&'static str, String),
+    /// Missing or invalid configuration values found at cold start.
+    #[error("bad configuration: {}", .0.join("; "))]
+    BadConfiguration(Vec<String>),
+    /// Policy violation.
+    #[error("policy violation: `{0}`")]
+    PolicyViolation(&'static str),
+    /// Storage failure.
+    #[error("storage: `{0}`")]
+    Storage(&'static str),
+    /// Missing or malformed attribute of a stored item.
+    #[error("bad item attribute: `{0}`")]
+    BadItemAttribute(&'static str),
+    /// Invalid record to import.
+    #[error("bad record: `{0}`")]
+    BadRecord(&'static str),
+    /// Email failure.
+    #[error("email: `{0}`")]
+    Email(&'static str),
+    /// Event publication failure.
+    #[error("event: `{0}`")]
+    Event(&'static str),
+    /// Encryption failure.
+    #[error("encryption: `{0}`")]
+    Encryption(&'static str),
+    /// Secret not available.
+    #[error("secret not available: `{0}`")]
+    Secret(String),
+    /// Telemetry not available.
+    #[error("telemetry error: {0}")]
+    Telemetry(&'static str),
+    /// Software authenticator failure.
+    #[error("software authenticator: `{0}`")]
+    SoftwareAuthenticator(&'static str),
+    /// Webhook delivery failure.
+    #[error("webhook: `{0}`")]
+    Webhook(&'static str),
+    /// Token failure.
+    #[error("token: `{0}`")]
+    Token(&'static str),
+    /// Invalid authenticator metadata.
+    #[error("metadata: `{0}`")]
+    Metadata(&'static str),
+    /// CAPTCHA verification failure.
+    #[error("captcha: `{0}`")]
+    Captcha(&'static str),
+    /// Risk hook failure.
+    #[error("risk hook: `{0}`")]
+    RiskHook(&'static str),
+    /// Session ID failure.
+    #[error("session ID: `{0}`")]
+    SessionId(&'static str),
+    /// Legacy user store failure.
+    #[error("legacy user store: `{0}`")]
+    LegacyUserStore(&'static str),
 }
@@ -0,0 +1,424 @@
+//! Client extensions that the Webauthn library does not cover.
+//!
+//! `webauthn_rs_proto` drops unknown client extensions, like `largeBlob` and
+//! `prf`, from both the options and the client extension outputs. This module
+//! adds the inputs of such extensions to serialized options, and extracts the
+//! outputs from a raw request body so that they can be passed through to the
+//! client.
+//!
+//! Every extension is opt-in; see [`load_extension_policy`].
+//!
+//! ## PRF
+//!
+//! The `prf` extension evaluates a pseudo-random function of the credential
+//! so that a client can derive end-to-end encryption keys from a passkey.
+//! Every ceremony evaluates the same salt, which is shared by all the users,
+//! because the user is unknown when a discoverable credential is
+//! authenticated. The outputs are different for every credential anyway.
+//! The server never stores the PRF results; it only records whether a
+//! credential supports the extension.
+
+use base64::{
+    Engine as _,
+    engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
+};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::env;
+
+use crate::config;
+use crate::error::Error;
+use crate::policy::load_env_policy;
+
+// Context string from which the default PRF salt is derived.
+const DEFAULT_PRF_SALT_CONTEXT: &[u8] = b"passkey-test prf salt v1";
+
+/// Support of the `largeBlob` extension requested at registration.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LargeBlobSupport {
+    /// The authenticator must support large blobs.
+    Required,
+    /// The authenticator should support large blobs.
+    Preferred,
+}
+
+/// Policy on the client extensions.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ExtensionPolicy {
+    /// Support of the `largeBlob` extension.
+    ///
+    /// `None` if the extension is disabled.
+    pub large_blob: Option<LargeBlobSupport>,
+
+    /// "base64url"-encoded salt evaluated by the `prf` extension.
+    ///
+    /// `None` if the extension is disabled.
+    pub prf_salt: Option<String>,
+}
+
+/// Loads the policy on the client extensions.
+///
+/// You can specify to the following environment variables:
+/// - `LARGE_BLOB`: support of the `largeBlob` extension; "required" or
+///   "preferred". Registration requests the support, and authentication
+///   requests to read the large blob. Disabled unless specified.
+/// - `PRF`: "true" to evaluate the `prf` extension in every ceremony.
+///   Disabled by default.
+/// - `PRF_SALT`: "base64url"-encoded salt evaluated by the `prf` extension.
+///   Derived from a fixed string by default. Changing the salt changes every
+///   key derived by clients.
+pub fn load_extension_policy() -> Result<ExtensionPolicy, Error> {
+    let prf = match config::var("PRF").as_deref() {
+        Ok("true") => true,
+        Ok("false") | Err(env::VarError::NotPresent) => false,
+        Ok(value) => return Err(Error::BadEnvironmentVariable("PRF", value.into())),
+        Err(env::VarError::NotUnicode(value)) => return Err(
+            Error::BadEnvironmentVariable("PRF", value.to_string_lossy().into()),
+        ),
+    };
+    let prf_salt = match config::var("PRF_SALT") {
+        Ok(salt) => parse_prf_salt(&salt)
+            .ok_or(Error::BadEnvironmentVariable("PRF_SALT", salt))?,
+        Err(env::VarError::NotPresent) => default_prf_salt(),
+        Err(env::VarError::NotUnicode(salt)) => return Err(
+            Error::BadEnvironmentVariable("PRF_SALT", salt.to_string_lossy().into()),
+        ),
+    };
+    Ok(ExtensionPolicy {
+        large_blob: load_env_policy("LARGE_BLOB")?,
+        prf_salt: prf.then_some(prf_salt),
+    })
+}
+
+// accepts a non-empty "base64url"-encoded salt.
+fn parse_prf_salt(salt: &str) -> Option<String> {
+    base64url.decode(salt)
+        .ok()
+        .filter(|salt| !salt.is_empty())
+        .map(|_| salt.to_string())
+}
+
+fn default_prf_salt() -> String {
+    base64url.encode(digest::digest(&digest::SHA256, DEFAULT_PRF_SALT_CONTEXT))
+}
+
+impl ExtensionPolicy {
+    /// Returns the extension inputs for registration.
+    pub fn registration_inputs(&self) -> ExtensionInputs {
+        ExtensionInputs {
+            large_blob: self.large_blob.map(|support| LargeBlobInputs {
+                support: Some(support),
+                read: None,
+            }),
+            prf: self.prf_inputs(),
+        }
+    }
+
+    /// Returns the extension inputs for authentication.
+    pub fn authentication_inputs(&self) -> ExtensionInputs {
+        ExtensionInputs {
+            large_blob: self.large_blob.map(|_| LargeBlobInputs {
+                support: None,
+                read: Some(true),
+            }),
+            prf: self.prf_inputs(),
+        }
+    }
+
+    // inputs of the `prf` extension, which are the same for registration and
+    // authentication.
+    fn prf_inputs(&self) -> Option<PrfInputs> {
+        self.prf_salt.as_ref().map(|salt| PrfInputs {
+            eval: Some(PrfValues {
+                first: salt.clone(),
+                second: None,
+            }),
+        })
+    }
+}
+
+/// Inputs of client extensions.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtensionInputs {
+    /// Inputs of the `largeBlob` extension.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub large_blob: Option<LargeBlobInputs>,
+
+    /// Inputs of the `prf` extension.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prf: Option<PrfInputs>,
+}
+
+/// Inputs of the `largeBlob` extension.
+///
+/// `AuthenticationExtensionsLargeBlobInputs` of the Web Authentication API.
+/// `write` is left to the client, which owns the blob.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LargeBlobInputs {
+    /// Requested support at registration.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub support: Option<LargeBlobSupport>,
+
+    /// Whether to read the large blob at authentication.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read: Option<bool>,
+}
+
+/// Inputs of the `prf` extension.
+///
+/// `AuthenticationExtensionsPRFInputs` of the Web Authentication API.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PrfInputs {
+    /// Salts to evaluate.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub eval: Option<PrfValues>,
+}
+
+/// Salts or results of the `prf` extension.
+///
+/// `AuthenticationExtensionsPRFValues` of the Web Authentication API. Every
+/// value is "base64url"-encoded.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct PrfValues {
+    /// First value.
+    pub first: String,
+
+    /// Second value.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub second: Option<String>,
+}
+
+impl ExtensionInputs {
+    /// Merges the inputs into serialized options.
+    ///
+    /// `options` must be the serialized form of `CreationChallengeResponse`
+    /// or `RequestChallengeResponse`; the inputs are added to
+    /// `publicKey.extensions`. Does nothing if `options` is not an object.
+    pub fn merge_into(&self, options: &mut Value) {
+        let Ok(Value::Object(inputs)) = serde_json::to_value(self) else {
+            return;
+        };
+        if inputs.is_empty() {
+            return;
+        }
+        let Some(public_key) = options.get_mut("publicKey")
+            .and_then(Value::as_object_mut) else
+        {
+            return;
+        };
+        let extensions = public_key.entry("extensions")
+            .or_insert_with(|| Value::Object(Map::new()));
+        if !extensions.is_object() {
+            *extensions = Value::Object(Map::new());
+        }
+        if let Value::Object(extensions) = extensions {
+            extensions.extend(inputs);
+        }
+    }
+
+    /// Serializes options and merges the inputs into them.
+    pub fn add_to(&self, options: &impl Serialize) -> Result<Value, Error> {
+        let mut options = serde_json::to_value(options)
+            .or(Err(Error::Inconvertible("non-serializable options")))?;
+        self.merge_into(&mut options);
+        Ok(options)
+    }
+}
+
+/// Outputs of client extensions.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtensionOutputs {
+    /// Outputs of the `largeBlob` extension.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub large_blob: Option<LargeBlobOutputs>,
+
+    /// Outputs of the `prf` extension.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prf: Option<PrfOutputs>,
+}
+
+/// Outputs of the `largeBlob` extension.
+///
+/// `AuthenticationExtensionsLargeBlobOutputs` of the Web Authentication API.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct LargeBlobOutputs {
+    /// Whether the authenticator supports large blobs; reported at
+    /// registration.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub supported: Option<bool>,
+
+    /// "base64url"-encoded large blob read at authentication.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob: Option<String>,
+
+    /// Whether the large blob was written at authentication.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub written: Option<bool>,
+}
+
+/// Outputs of the `prf` extension.
+///
+/// `AuthenticationExtensionsPRFOutputs` of the Web Authentication API.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct PrfOutputs {
+    /// Whether the credential supports the extension; reported at
+    /// registration.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+
+    /// Results of the evaluation.
+    ///
+    /// Passed through to the client and never stored.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub results: Option<PrfValues>,
+}
+
+impl ExtensionOutputs {
+    /// Returns whether the credential supports the `prf` extension.
+    ///
+    /// Results imply the support even if `enabled` is not reported. `None`
+    /// if the client did not report the extension.
+    pub fn prf_enabled(&self) -> Option<bool> {
+        self.prf.as_ref()
+            .map(|prf| prf.enabled.unwrap_or(false) || prf.results.is_some())
+    }
+
+    /// Extracts the outputs from a request body that carries a
+    /// `publicKeyCredential`.
+    ///
+    /// Malformed or missing outputs are ignored, because extensions are
+    /// optional.
+    pub fn of_payload(body: &[u8]) -> Self {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Payload {
+            public_key_credential: Credential,
+        }
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Credential {
+            #[serde(default)]
+            client_extension_results: ExtensionOutputs,
+        }
+        serde_json::from_slice::<Payload>(body)
+            .map(|p| p.public_key_credential.client_extension_results)
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn large_blob_policy() -> ExtensionPolicy {
+        ExtensionPolicy {
+            large_blob: Some(LargeBlobSupport::Preferred),
+            prf_salt: None,
+        }
+    }
+
+    #[test]
+    fn merge_into_should_add_inputs_to_extensions() {
+        let mut options = serde_json::json!({
+            "publicKey": {
+                "challenge": "AAAA",
+                "extensions": { "credProps": true },
+            },
+        });
+        large_blob_policy().registration_inputs().merge_into(&mut options);
+        assert_eq!(
+            options["publicKey"]["extensions"],
+            serde_json::json!({
+                "credProps": true,
+                "largeBlob": { "support": "preferred" },
+            }),
+        );
+        let mut options = serde_json::json!({ "publicKey": { "extensions": null } });
+        large_blob_policy().authentication_inputs().merge_into(&mut options);
+        assert_eq!(
+            options["publicKey"]["extensions"],
+            serde_json::json!({ "largeBlob": { "read": true } }),
+        );
+    }
+
+    #[test]
+    fn merge_into_should_leave_options_without_inputs() {
+        let mut options = serde_json::json!({ "publicKey": {} });
+        ExtensionPolicy::default().registration_inputs().merge_into(&mut options);
+        assert_eq!(options, serde_json::json!({ "publicKey": {} }));
+    }
+
+    #[test]
+    fn of_payload_should_extract_large_blob_outputs() {
+        let outputs = ExtensionOutputs::of_payload(br#"{
+            "sessionId": "abc",
+            "publicKeyCredential": {
+                "id": "BBBB",
+                "clientExtensionResults": {
+                    "credProps": { "rk": true },
+                    "largeBlob": { "supported": true }
+                }
+            }
+        }"#);
+        assert_eq!(
+            outputs.large_blob,
+            Some(LargeBlobOutputs {
+                supported: Some(true),
+                ..Default::default()
+            }),
+        );
+        assert_eq!(ExtensionOutputs::of_payload(b"{}"), ExtensionOutputs::default());
+    }
+
+    #[test]
+    fn prf_inputs_should_evaluate_salt() {
+        let policy = ExtensionPolicy {
+            large_blob: None,
+            prf_salt: Some("c2FsdA".into()),
+        };
+        let expected = serde_json::json!({
+            "prf": { "eval": { "first": "c2FsdA" } },
+        });
+        assert_eq!(serde_json::to_value(policy.registration_inputs()).unwrap(), expected);
+        assert_eq!(serde_json::to_value(policy.authentication_inputs()).unwrap(), expected);
+    }
+
+    #[test]
+    fn prf_enabled_should_be_implied_by_results() {
+        let outputs = ExtensionOutputs::of_payload(br#"{
+            "publicKeyCredential": {
+                "clientExtensionResults": {
+                    "prf": { "results": { "first": "AAAA" } }
+                }
+            }
+        }"#);
+        assert_eq!(outputs.prf_enabled(), Some(true));
+        let outputs = ExtensionOutputs::of_payload(br#"{
+            "publicKeyCredential": {
+                "clientExtensionResults": { "prf": { "enabled": false } }
+            }
+        }"#);
+        assert_eq!(outputs.prf_enabled(), Some(false));
+        assert_eq!(ExtensionOutputs::default().prf_enabled(), None);
+    }
+
+    #[test]
+    fn parse_prf_salt_should_accept_base64url() {
+        assert_eq!(parse_prf_salt("c2FsdA").as_deref(), Some("c2FsdA"));
+        assert!(parse_prf_salt("").is_none());
+        assert!(parse_prf_salt("not base64!").is_none());
+        assert!(parse_prf_salt(&default_prf_salt()).is_some());
+    }
+}
@@ -0,0 +1,638 @@
+//! GraphQL facade of the credential management.
+//!
+//! Available if the `graphql` feature is enabled, and served by the `graphql`
+//! function. The schema exposes the operations of the credentials function on
+//! the same tables through this library:
+//! - `credentials` query: lists the credentials of the authenticated user
+//! - `startStepUp` and `finishStepUp` mutations: step-up re-authentication
+//! - `deleteCredential` mutation: deletes a credential with a step-up token
+//! - `restoreCredential` mutation: restores a deleted credential with a
+//!   step-up token; see [`crate::deletion`]
+//!
+//! Every operation is of the authenticated user given as a [`Viewer`] in the
+//! data of a request. A failure is reported in the `errors` of the response
+//! with the error code of the REST APIs in the `code` extension; e.g.,
+//! `step_up_required`. The details of a server error are not exposed.
+//!
+//! Registration and sign-in stay on the REST APIs, because their flows run in
+//! the registration function and the Cognito triggers.
+
+use async_graphql::{
+    Context,
+    EmptySubscription,
+    Enum,
+    ErrorExtensions,
+    Json,
+    Object,
+    Schema,
+    SimpleObject,
+};
+use aws_sdk_dynamodb::primitives::DateTime;
+use base64::{
+    Engine as _,
+    engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
+};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing::{error, info};
+use webauthn_rs::prelude::{
+    Passkey,
+    PasskeyAuthentication,
+    RequestChallengeResponse,
+};
+use webauthn_rs_proto::{
+    auth::PublicKeyCredential,
+    options::UserVerificationPolicy,
+};
+
+use crate::api_error::ApiError;
+use crate::audit::{AuditEvent, AuditEventType, AuditLog, ClientInfo};
+use crate::credentials::CredentialInfo;
+use crate::deletion::{deletion_timestamp, retention_cutoff};
+use crate::domain_events::{
+    CredentialRevoked,
+    DomainEvent,
+    EventPublisher,
+    publish_event,
+};
+use crate::error::Error;
+use crate::items::{CredentialKey, StepUpSessionItem, user_handle_of};
+use crate::pagination::{decode_page_token, encode_page_token};
+use crate::passkey::is_user_verified_in;
+use crate::policy::{ChallengeTimeout, CredentialLimit};
+use crate::session_id::SessionIds;
+use crate::step_up::{
+    issue_step_up_token,
+    save_step_up_session,
+    take_step_up_session,
+    verify_step_up_token,
+};
+use crate::store::DynamoDbSessionStore;
+use crate::telemetry::{redact, truncate};
+use crate::tenant::Tenant;
+use crate::users::{CredentialFilter, UserDirectory};
+use crate::webhooks::{WebhookNotifier, notify_webhook};
+
+/// Default number of credentials in a page.
+pub const DEFAULT_PAGE_LIMIT: i32 = 50;
+
+/// Maximum number of credentials in a page.
+pub const MAX_PAGE_LIMIT: i32 = 100;
+
+/// GraphQL schema.
+pub type CredentialSchema = Schema<QueryRoot, MutationRoot, EmptySubscription>;
+
+/// Builds the schema on given services.
+pub fn build_schema(services: GraphQlServices) -> CredentialSchema {
+    Schema::build(QueryRoot, MutationRoot, EmptySubscription)
+        .data(services)
+        .finish()
+}
+
+/// Services shared among requests.
+pub struct GraphQlServices {
+    /// Credential table.
+    pub users: UserDirectory,
+
+    /// Session table that stores step-up sessions and tokens.
+    pub sessions: DynamoDbSessionStore,
+
+    /// Timeout of a step-up.
+    pub challenge_timeout: ChallengeTimeout,
+
+    /// Audit log if configured.
+    pub audit_log: Option<AuditLog>,
+
+    /// Publisher of domain events if configured.
+    pub event_publisher: Option<EventPublisher>,
+
+    /// Webhook notifier if configured.
+    pub webhooks: Option<WebhookNotifier>,
+
+    /// Issuer of step-up session IDs.
+    pub session_ids: SessionIds,
+
+    /// Retention window of deleted credentials.
+    ///
+    /// Credentials are deleted immediately if `None`.
+    pub deletion_retention: Option<Duration>,
+
+    /// Maximum number of credentials per user if configured.
+    ///
+    /// A deleted credential cannot be restored beyond the limit.
+    pub credential_limit: Option<CredentialLimit>,
+}
+
+/// Authenticated user of a request.
+#[derive(Clone, Debug)]
+pub struct Viewer {
+    /// User handle of the user.
+    pub user_handle: String,
+
+    /// Tenant that serves the request.
+    pub tenant: Arc<Tenant>,
+
+    /// Client that sent the request.
+    pub client: ClientInfo,
+}
+
+/// Type of a credential.
+#[derive(Clone, Copy, Debug, Enum, Eq, PartialEq)]
+pub enum CredentialType {
+    /// Passkey.
+    Passkey,
+    /// Security key.
+    SecurityKey,
+}
+
+impl CredentialType {
+    /// Value in the credential table.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CredentialType::Passkey => "passkey",
+            CredentialType::SecurityKey => "securityKey",
+        }
+    }
+}
+
+/// Page of credentials of a user.
+#[derive(Clone, Debug, SimpleObject)]
+pub struct CredentialPage {
+    /// Credentials.
+    pub credentials: Vec<CredentialInfo>,
+
+    /// Token to obtain the next page.
+    ///
+    /// Null if this is the last page.
+    pub next_token: Option<String>,
+}
+
+/// Beginning of a step-up session.
+#[derive(Clone, Debug, SimpleObject)]
+pub struct StepUpChallenge {
+    /// Session ID.
+    pub session_id: String,
+
+    /// `CredentialRequestOptions` of the Web Authentication API.
+    pub credential_request_options: Json<RequestChallengeResponse>,
+}
+
+/// Step-up token issued by a finished step-up.
+#[derive(Clone, Debug, SimpleObject)]
+pub struct StepUpGrant {
+    /// Step-up token to give to `deleteCredential`.
+    pub step_up_token: String,
+
+    /// Expiration time of the token in seconds since the epoch.
+    pub expires_at: i64,
+}
+
+/// Root of the queries.
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Lists the credentials of the authenticated user.
+    async fn credentials(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "Maximum number of credentials evaluated in a page; 1–100. 50 by default.")]
+        limit: Option<i32>,
+        #[graphql(desc = "Token to obtain the next page.")]
+        next_token: Option<String>,
+        #[graphql(desc = "Lists only credentials of the type.")]
+        credential_type: Option<CredentialType>,
+        #[graphql(desc = "Lists only credentials that are or are not backed up.")]
+        backup_state: Option<bool>,
+    ) -> async_graphql::Result<CredentialPage> {
+        let viewer = viewer(ctx)?;
+        let services = ctx.data::<GraphQlServices>()?;
+        info!("credentials: {}", redact(&viewer.user_handle));
+
+        let limit = match limit {
+            None => DEFAULT_PAGE_LIMIT,
+            Some(limit) if (1..=MAX_PAGE_LIMIT).contains(&limit) => limit,
+            Some(_) => return Err(client_error(
+                "bad_query",
+                &format!("limit must be between 1 and {}", MAX_PAGE_LIMIT),
+            )),
+        };
+        // a token must not start a page of another user
+        let exclusive_start_key = match next_token.as_deref().map(decode_page_token) {
+            None => None,
+            Some(Some(key)) if user_handle_of(&key) == Some(viewer.user_handle.as_str()) =>
+                Some(key),
+            Some(_) => return Err(client_error("bad_query", "malformed nextToken")),
+        };
+        let page = services.users
+            .query_credentials(
+                &viewer.user_handle,
+                limit,
+                exclusive_start_key,
+                &CredentialFilter {
+                    credential_type: credential_type.map(|t| t.as_str().into()),
+                    backup_state,
+                },
+            )
+            .await
+            .map_err(common_error)?;
+        let next_token = page.last_evaluated_key.as_ref()
+            .map(encode_page_token)
+            .transpose()
+            .map_err(common_error)?;
+        let credentials = page.credentials.into_iter()
+            .map(CredentialInfo::from_credential)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(common_error)?;
+        Ok(CredentialPage { credentials, next_token })
+    }
+}
+
+/// Root of the mutations.
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    /// Starts step-up re-authentication of the authenticated user.
+    ///
+    /// Only the enabled passkeys of the user are allowed, and user
+    /// verification is required.
+    async fn start_step_up(
+        &self,
+        ctx: &Context<'_>,
+    ) -> async_graphql::Result<StepUpChallenge> {
+        let viewer = viewer(ctx)?;
+        let services = ctx.data::<GraphQlServices>()?;
+        info!("start_step_up: {}", redact(&viewer.user_handle));
+
+        let passkeys: Vec<Passkey> = services.users
+            .list_credentials(&viewer.user_handle)
+            .await
+            .map_err(common_error)?
+            .iter()
+            .filter(|c| c.disabled_at.is_none())
+            .map(|c| serde_json::from_str(&c.credential)
+                .or(Err(ApiError::Storage("malformed credential in the database"))))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(graphql_error)?;
+        if passkeys.is_empty() {
+            return Err(client_error("no_credentials", "no credentials to step up with"));
+        }
+        let (mut rcr, auth_state) = viewer.tenant.webauthn()
+            .start_passkey_authentication(&passkeys)
+            .map_err(|e| {
+                error!("failed to start step-up: {}", e);
+                graphql_error(ApiError::internal("failed to start step-up"))
+            })?;
+        rcr.public_key.user_verification = UserVerificationPolicy::Required;
+        rcr.public_key.timeout = Some(services.challenge_timeout.as_millis());
+
+        let session_id = services.session_ids.generate().await.map_err(common_error)?;
+        let session = StepUpSessionItem {
+            ttl: services.challenge_timeout
+                .session_ttl(DateTime::from(SystemTime::now()).secs()),
+            user_handle: viewer.user_handle.clone(),
+            state: serde_json::to_string(&auth_state)
+                .map_err(|_| graphql_error(ApiError::internal("non-serializable state")))?,
+        };
+        save_step_up_session(&services.sessions, &viewer.tenant, &session_id, session)
+            .await
+            .map_err(common_error)?;
+        Ok(StepUpChallenge {
+            session_id,
+            credential_request_options: Json(rcr),
+        })
+    }
+
+    /// Verifies the assertion of a step-up and issues a step-up token valid
+    /// for 5 minutes.
+    async fn finish_step_up(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "Session ID given by startStepUp.")]
+        session_id: String,
+        #[graphql(desc = "PublicKeyCredential of the Web Authentication API in the JSON form.")]
+        public_key_credential: Json<PublicKeyCredential>,
+    ) -> async_graphql::Result<StepUpGrant> {
+        let viewer = viewer(ctx)?;
+        let services = ctx.data::<GraphQlServices>()?;
+        info!("finish_step_up: {} {}", redact(&viewer.user_handle), truncate(&session_id));
+        let credential = public_key_credential.0;
+        if !services.session_ids.verify(&session_id).await.map_err(common_error)? {
+            error!("forged step-up session ID");
+            return Err(client_error("step_up_failed", "step-up failed"));
+        }
+
+        let now = DateTime::from(SystemTime::now()).secs();
+        let item = take_step_up_session(
+            &services.sessions,
+            &viewer.tenant,
+            &session_id,
+            &viewer.user_handle,
+            now,
+        ).await.map_err(common_error)?;
+        let Some(item) = item else {
+            error!("expired or wrong step-up session");
+            return Err(step_up_failed(services, viewer, None, "expired or wrong session").await);
+        };
+        let auth_state: PasskeyAuthentication = serde_json::from_str(&item.state)
+            .map_err(|_| graphql_error(ApiError::Storage("malformed step-up session")))?;
+        let auth_result = match viewer.tenant.webauthn()
+            .finish_passkey_authentication(&credential, &auth_state)
+        {
+            Ok(auth_result) if auth_result.user_verified()
+                && is_user_verified_in(credential.response.authenticator_data.as_ref()) =>
+            {
+                auth_result
+            }
+            Ok(_) => {
+                error!("user verification required but not performed");
+                record_step_up_failure(
+                    services,
+                    viewer,
+                    Some(credential.id),
+                    "user not verified",
+                ).await?;
+                return Err(graphql_error(ApiError::UserVerificationRequired));
+            }
+            Err(e) => {
+                error!("step-up failed: {}", e);
+                return Err(step_up_failed(
+                    services,
+                    viewer,
+                    Some(credential.id),
+                    "verification failed",
+                ).await);
+            }
+        };
+        let credential_item = services.users
+            .get_credential(CredentialKey {
+                user_handle: &viewer.user_handle,
+                credential_id: &base64url.encode(auth_result.cred_id()),
+            })
+            .await
+            .map_err(common_error)?
+            .ok_or_else(|| graphql_error(ApiError::Storage("missing credential in the database")))?;
+        if credential_item.disabled_at.is_some() {
+            error!("credential disabled");
+            return Err(step_up_failed(
+                services,
+                viewer,
+                Some(credential.id),
+                "credential disabled",
+            ).await);
+        }
+        services.users
+            .record_authentication(credential_item, &auth_result)
+            .await
+            .map_err(common_error)?;
+
+        let (step_up_token, expires_at) = issue_step_up_token(
+            &services.sessions,
+            &viewer.tenant,
+            &viewer.user_handle,
+            now,
+        ).await.map_err(common_error)?;
+        Ok(StepUpGrant { step_up_token, expires_at })
+    }
+
+    /// Deletes a credential of the authenticated user.
+    ///
+    /// The credential is restorable until the retention window elapses
+    /// unless soft deletion is off.
+    /// Returns the ID of the deleted credential.
+    async fn delete_credential(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "ID of the credential to delete.")]
+        credential_id: String,
+        #[graphql(desc = "Step-up token issued by finishStepUp.")]
+        step_up_token: String,
+    ) -> async_graphql::Result<String> {
+        let viewer = viewer(ctx)?;
+        let services = ctx.data::<GraphQlServices>()?;
+        info!("delete_credential: {} {}", redact(&viewer.user_handle), truncate(&credential_id));
+
+        let stepped_up = verify_step_up_token(
+            &services.sessions,
+            &viewer.tenant,
+            &step_up_token,
+            &viewer.user_handle,
+            DateTime::from(SystemTime::now()).secs(),
+        ).await.map_err(common_error)?;
+        if !stepped_up {
+            error!("step-up required");
+            return Err(client_error(
+                "step_up_required",
+                "recent authentication is required",
+            ));
+        }
+        let key = CredentialKey {
+            user_handle: &viewer.user_handle,
+            credential_id: &credential_id,
+        };
+        let deleted = match services.deletion_retention {
+            Some(_) => {
+                let deleted_at = deletion_timestamp(SystemTime::now())
+                    .map_err(common_error)?;
+                services.users.soft_delete_credential(key, deleted_at).await
+            }
+            None => services.users.delete_credential(key).await,
+        }.map_err(common_error)?;
+        if !deleted {
+            return Err(client_error("credential_not_found", "no such credential"));
+        }
+        if let Some(audit_log) = services.audit_log.as_ref() {
+            audit_log.record(AuditEvent {
+                event_type: AuditEventType::CredentialDeleted,
+                user_handle: viewer.user_handle.clone(),
+                credential_id: Some(credential_id.clone()),
+                client: viewer.client.clone(),
+                detail: Some("deleted by user".into()),
+            }).await.map_err(common_error)?;
+        }
+        let revocation = DomainEvent::CredentialRevoked(CredentialRevoked {
+            user_handle: viewer.user_handle.clone(),
+            credential_id: credential_id.clone(),
+            tenant_id: viewer.tenant.id().map(Into::into),
+            action: "deleted",
+            revoked_by: "user",
+        });
+        notify_webhook(services.webhooks.as_ref(), &revocation).await;
+        publish_event(services.event_publisher.as_ref(), revocation).await;
+        Ok(credential_id)
+    }
+
+    /// Restores a deleted credential of the authenticated user within the
+    /// retention window.
+    ///
+    /// Returns the ID of the restored credential.
+    async fn restore_credential(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(desc = "ID of the credential to restore.")]
+        credential_id: String,
+        #[graphql(desc = "Step-up token issued by finishStepUp.")]
+        step_up_token: String,
+    ) -> async_graphql::Result<String> {
+        let viewer = viewer(ctx)?;
+        let services = ctx.data::<GraphQlServices>()?;
+        info!("restore_credential: {} {}", redact(&viewer.user_handle), truncate(&credential_id));
+
+        let now = SystemTime::now();
+        let stepped_up = verify_step_up_token(
+            &services.sessions,
+            &viewer.tenant,
+            &step_up_token,
+            &viewer.user_handle,
+            DateTime::from(now).secs(),
+        ).await.map_err(common_error)?;
+        if !stepped_up {
+            error!("step-up required");
+            return Err(client_error(
+                "step_up_required",
+                "recent authentication is required",
+            ));
+        }
+        if let Some(limit) = services.credential_limit {
+            let credentials = services.users
+                .list_credentials(&viewer.user_handle)
+                .await
+                .map_err(common_error)?;
+            limit.check(&credentials).map_err(graphql_error)?;
+        }
+        let restored = match services.deletion_retention {
+            Some(retention) => services.users
+                .restore_credential(
+                    CredentialKey {
+                        user_handle: &viewer.user_handle,
+                        credential_id: &credential_id,
+                    },
+                    retention_cutoff(now, retention).map_err(common_error)?,
+                )
+                .await
+                .map_err(common_error)?,
+            None => false,
+        };
+        if !restored {
+            return Err(client_error("credential_not_found", "no such deleted credential"));
+        }
+        if let Some(audit_log) = services.audit_log.as_ref() {
+            audit_log.record(AuditEvent {
+                event_type: AuditEventType::CredentialRestored,
+                user_handle: viewer.user_handle.clone(),
+                credential_id: Some(credential_id.clone()),
+                client: viewer.client.clone(),
+                detail: Some("restored by user".into()),
+            }).await.map_err(common_error)?;
+        }
+        Ok(credential_id)
+    }
+}
+
+// obtains the authenticated user of a request.
+fn viewer<'a>(ctx: &Context<'a>) -> async_graphql::Result<&'a Viewer> {
+    ctx.data_opt::<Viewer>().ok_or_else(|| graphql_error(ApiError::Unauthenticated))
+}
+
+// records a failed step-up and creates its error.
+async fn step_up_failed(
+    services: &GraphQlServices,
+    viewer: &Viewer,
+    credential_id: Option<String>,
+    reason: &str,
+) -> async_graphql::Error {
+    match record_step_up_failure(services, viewer, credential_id, reason).await {
+        Ok(_) => client_error("step_up_failed", "step-up failed"),
+        Err(e) => e,
+    }
+}
+
+// records a step-up failure in the audit log.
+async fn record_step_up_failure(
+    services: &GraphQlServices,
+    viewer: &Viewer,
+    credential_id: Option<String>,
+    reason: &str,
+) -> Result<(), async_graphql::Error> {
+    if let Some(audit_log) = services.audit_log.as_ref() {
+        audit_log.record(AuditEvent {
+            event_type: AuditEventType::AuthenticationFailed,
+            user_handle: viewer.user_handle.clone(),
+            credential_id,
+            client: viewer.client.clone(),
+            detail: Some(format!("step-up: {}", reason)),
+        }).await.map_err(common_error)?;
+    }
+    Ok(())
+}
+
+/// Converts an [`ApiError`] into a GraphQL error.
+///
+/// The error code of the [`crate::payload::ErrorResponseBody`] is set to the
+/// `code` extension, and the details of a server error are not exposed.
+pub fn graphql_error(e: ApiError) -> async_graphql::Error {
+    if !e.is_client_error() {
+        error!("server error: {}", e);
+    }
+    let body = e.response_body();
+    client_error(body.error, &body.message)
+}
+
+// creates a GraphQL error with a given error code.
+fn client_error(code: &'static str, message: &str) -> async_graphql::Error {
+    async_graphql::Error::new(message).extend_with(|_, e| e.set("code", code))
+}
+
+// converts a common error into a GraphQL error.
+fn common_error(e: Error) -> async_graphql::Error {
+    graphql_error(ApiError::from(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema_without_services() -> CredentialSchema {
+        Schema::build(QueryRoot, MutationRoot, EmptySubscription).finish()
+    }
+
+    fn error_code(res: &async_graphql::Response) -> Option<String> {
+        let extensions = res.errors.first()?.extensions.as_ref()?;
+        match extensions.get("code")? {
+            async_graphql::Value::String(code) => Some(code.clone()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn schema_should_expose_credential_management() {
+        let sdl = schema_without_services().sdl();
+        assert!(sdl.contains("credentials("), "{}", sdl);
+        assert!(sdl.contains("startStepUp"), "{}", sdl);
+        assert!(sdl.contains("finishStepUp("), "{}", sdl);
+        assert!(sdl.contains("deleteCredential("), "{}", sdl);
+        assert!(sdl.contains("restoreCredential("), "{}", sdl);
+        assert!(sdl.contains("SECURITY_KEY"), "{}", sdl);
+    }
+
+    #[tokio::test]
+    async fn request_without_viewer_should_be_unauthenticated() {
+        let res = schema_without_services()
+            .execute("{ credentials { nextToken } }")
+            .await;
+        assert_eq!(error_code(&res).as_deref(), Some("unauthenticated"));
+        let res = schema_without_services()
+            .execute(r#"mutation { deleteCredential(credentialId: "abc", stepUpToken: "xyz") }"#)
+            .await;
+        assert_eq!(error_code(&res).as_deref(), Some("unauthenticated"));
+    }
+
+    #[test]
+    fn graphql_error_should_hide_details_of_server_error() {
+        let e = graphql_error(ApiError::Storage("failed to get session item"));
+        assert_eq!(e.message, "internal server error");
+        let e = graphql_error(ApiError::SessionExpired("expired"));
+        assert_eq!(e.message, "expired");
+    }
+}
@@ -0,0 +1,194 @@
+//! Health check.
+//!
+//! Every HTTP function serves `GET ${BASE_PATH}health` for synthetic
+//! monitoring and canaries. The path is neither versioned nor scoped to a
+//! tenant.
+//!
+//! The configuration is validated at cold start, and a misconfigured function
+//! never serves a request; so the `configuration` check always passes once
+//! the endpoint responds. With the `deep=true` query parameter, the DynamoDB
+//! tables the function depends on are also checked with `DescribeTable`.
+//!
+//! The response body is a [`HealthReport`] as `application/json`, and the
+//! status code is 200 if every check passes, or 503 otherwise.
+
+use lambda_http::{Body, Request, RequestExt, Response, http::StatusCode};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use tracing::error;
+
+/// Path of the health check under the base path.
+pub const HEALTH_PATH: &str = "/health";
+
+/// Status of a health check.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    /// The check passed.
+    Ok,
+    /// The check failed.
+    Failed,
+}
+
+/// Result of a health check.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct CheckResult {
+    /// Status.
+    pub status: HealthStatus,
+
+    /// Reason of a failure.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl CheckResult {
+    /// Passed check.
+    pub fn ok() -> Self {
+        Self {
+            status: HealthStatus::Ok,
+            message: None,
+        }
+    }
+
+    /// Failed check with a reason.
+    pub fn failed(message: impl Into<String>) -> Self {
+        Self {
+            status: HealthStatus::Failed,
+            message: Some(message.into()),
+        }
+    }
+}
+
+/// Report of health checks.
+#[derive(Clone, Debug, Serialize)]
+pub struct HealthReport {
+    /// Overall status; failed if any check has failed.
+    pub status: HealthStatus,
+
+    /// Name of the function; e.g., "registration".
+    pub service: &'static str,
+
+    /// Results of the individual checks.
+    pub checks: BTreeMap<String, CheckResult>,
+}
+
+impl HealthReport {
+    /// Creates a report with the passed `configuration` check.
+    pub fn new(service: &'static str) -> Self {
+        Self {
+            status: HealthStatus::Ok,
+            service,
+            checks: BTreeMap::from([("configuration".into(), CheckResult::ok())]),
+        }
+    }
+
+    /// Adds the result of a check.
+    pub fn add(&mut self, name: impl Into<String>, result: CheckResult) {
+        if result.status == HealthStatus::Failed {
+            self.status = HealthStatus::Failed;
+        }
+        self.checks.insert(name.into(), result);
+    }
+
+    /// Converts into a JSON response.
+    ///
+    /// The status code is 503 if any check has failed.
+    pub fn into_response(self) -> Result<Response<Body>, lambda_http::Error> {
+        let status = match self.status {
+            HealthStatus::Ok => StatusCode::OK,
+            HealthStatus::Failed => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        Ok(Response::builder()
+            .status(status)
+            .header("Content-Type", "application/json")
+            .header("Cache-Control", "no-store")
+            .body(serde_json::to_string(&self)?.into())?)
+    }
+}
+
+/// Returns whether a request asks for the deep check.
+pub fn is_deep_check(request: &Request) -> bool {
+    request.query_string_parameters_ref()
+        .and_then(|params| params.first("deep"))
+        .is_some_and(|deep| deep == "true" || deep == "1")
+}
+
+/// Checks if a DynamoDB table is reachable.
+pub async fn check_table(
+    dynamodb: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+) -> CheckResult {
+    match dynamodb.describe_table().table_name(table_name).send().await {
+        Ok(_) => CheckResult::ok(),
+        Err(e) => {
+            error!(?e, "describing table {}", table_name);
+            CheckResult::failed("table not reachable")
+        }
+    }
+}
+
+/// Serves the health check of a function.
+///
+/// `tables` are the names of the checks and the DynamoDB tables checked if
+/// the request asks for the deep check; e.g., `("sessionTable", "...")`.
+pub async fn health_check(
+    service: &'static str,
+    request: &Request,
+    dynamodb: &aws_sdk_dynamodb::Client,
+    tables: &[(&'static str, &str)],
+) -> Result<Response<Body>, lambda_http::Error> {
+    let mut report = HealthReport::new(service);
+    if is_deep_check(request) {
+        for (name, table_name) in tables {
+            report.add(*name, check_table(dynamodb, table_name).await);
+        }
+    }
+    report.into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    #[test]
+    fn health_report_should_fail_if_any_check_fails() {
+        let mut report = HealthReport::new("registration");
+        report.add("sessionTable", CheckResult::ok());
+        assert_eq!(report.status, HealthStatus::Ok);
+        assert_eq!(report.clone().into_response().unwrap().status(), StatusCode::OK);
+        report.add("credentialTable", CheckResult::failed("table not reachable"));
+        assert_eq!(report.status, HealthStatus::Failed);
+        let document = serde_json::to_value(&report).unwrap();
+        assert_eq!(document["status"], "failed");
+        assert_eq!(document["checks"]["configuration"]["status"], "ok");
+        assert_eq!(
+            document["checks"]["credentialTable"]["message"],
+            "table not reachable",
+        );
+        assert_eq!(
+            report.into_response().unwrap().status(),
+            StatusCode::SERVICE_UNAVAILABLE,
+        );
+    }
+
+    #[test]
+    fn is_deep_check_should_read_query_parameter() {
+        let request = |deep: Option<&str>| {
+            let request = lambda_http::http::Request::builder()
+                .body(Body::Empty)
+                .unwrap();
+            match deep {
+                Some(deep) => request.with_query_string_parameters(
+                    HashMap::from([("deep".to_string(), deep.to_string())]),
+                ),
+                None => request,
+            }
+        };
+        assert!(is_deep_check(&request(Some("true"))));
+        assert!(is_deep_check(&request(Some("1"))));
+        assert!(!is_deep_check(&request(Some("false"))));
+        assert!(!is_deep_check(&request(None)));
+    }
+}
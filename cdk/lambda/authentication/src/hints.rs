@@ -0,0 +1,146 @@
+//! Hints of the Web Authentication API Level 3.
+//!
+//! `hints` of the creation and request options tell the client which kind of
+//! authenticator the relying party expects, so that a browser shows the
+//! corresponding UI first; e.g., a QR code for "hybrid". `webauthn_rs_proto`
+//! does not cover them, so this module adds them to serialized options like
+//! [`crate::extensions`] does.
+//!
+//! Hints are advisory; they never restrict the authenticator, and nothing is
+//! verified at the end of a ceremony. So is the `AUTHENTICATOR_ATTACHMENT`
+//! policy, because the attachment a client reports is unsigned.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::env;
+
+use crate::config;
+use crate::error::Error;
+
+/// Hint of the kind of authenticator.
+///
+/// `PublicKeyCredentialHint` of the Web Authentication API.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+#[serde(rename_all = "kebab-case")]
+pub enum PublicKeyCredentialHint {
+    /// Physical security key; "security-key".
+    SecurityKey,
+    /// Authenticator built into the client device; "client-device".
+    ClientDevice,
+    /// Authenticator on another device like a phone; "hybrid".
+    Hybrid,
+}
+
+/// Loads the default hints.
+///
+/// You can specify to `HINTS` environment variable a comma-separated list of
+/// "security-key", "client-device", and "hybrid" in the order of preference.
+///
+/// Returns `None` if `HINTS` is not set, which means no hints unless a request
+/// asks for them.
+pub fn load_hints() -> Result<Option<Vec<PublicKeyCredentialHint>>, Error> {
+    match config::var("HINTS") {
+        Ok(hints) => parse_hints(&hints)
+            .map(Some)
+            .or(Err(Error::BadEnvironmentVariable("HINTS", hints))),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(hints)) => Err(
+            Error::BadEnvironmentVariable("HINTS", hints.to_string_lossy().into()),
+        ),
+    }
+}
+
+/// Parses a comma-separated list of hints.
+///
+/// Duplicates are removed, keeping the first occurrence.
+pub fn parse_hints(hints: &str) -> Result<Vec<PublicKeyCredentialHint>, Error> {
+    let mut parsed = Vec::new();
+    for hint in hints.split(',').map(str::trim).filter(|h| !h.is_empty()) {
+        let hint = serde_json::from_value(Value::String(hint.into()))
+            .or(Err(Error::Inconvertible("unknown hint")))?;
+        if !parsed.contains(&hint) {
+            parsed.push(hint);
+        }
+    }
+    Ok(parsed)
+}
+
+/// Resolves the hints of a ceremony.
+///
+/// Requested hints take precedence over the configured ones, because hints
+/// only tell the client which UI to show first.
+pub fn resolve_hints(
+    configured: Option<&[PublicKeyCredentialHint]>,
+    requested: Option<&[PublicKeyCredentialHint]>,
+) -> Option<Vec<PublicKeyCredentialHint>> {
+    requested.or(configured).map(<[_]>::to_vec)
+}
+
+/// Merges hints into serialized options.
+///
+/// `options` must be the serialized form of `CreationChallengeResponse` or
+/// `RequestChallengeResponse`; the hints replace `publicKey.hints`. Does
+/// nothing if `hints` is empty or `options` is not an object.
+pub fn merge_hints_into(hints: &[PublicKeyCredentialHint], options: &mut Value) {
+    if hints.is_empty() {
+        return;
+    }
+    let Some(public_key) = options.get_mut("publicKey")
+        .and_then(Value::as_object_mut) else
+    {
+        return;
+    };
+    if let Ok(hints) = serde_json::to_value(hints) {
+        public_key.insert("hints".into(), hints);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use PublicKeyCredentialHint::*;
+
+    #[test]
+    fn parse_hints_should_accept_known_hints_in_order() {
+        assert_eq!(
+            parse_hints("hybrid, client-device,hybrid").unwrap(),
+            vec![Hybrid, ClientDevice],
+        );
+        assert_eq!(parse_hints("security-key").unwrap(), vec![SecurityKey]);
+        assert_eq!(parse_hints("").unwrap(), vec![]);
+        assert!(parse_hints("security-key,usb").is_err());
+    }
+
+    #[test]
+    fn resolve_hints_should_prefer_requested_hints() {
+        assert_eq!(
+            resolve_hints(Some(&[ClientDevice]), Some(&[Hybrid])),
+            Some(vec![Hybrid]),
+        );
+        assert_eq!(
+            resolve_hints(Some(&[ClientDevice]), None),
+            Some(vec![ClientDevice]),
+        );
+        assert_eq!(resolve_hints(None, None), None);
+    }
+
+    #[test]
+    fn merge_hints_into_should_set_hints_of_public_key() {
+        let mut options = serde_json::json!({
+            "publicKey": { "challenge": "AAAA", "hints": ["security-key"] },
+        });
+        merge_hints_into(&[ClientDevice, Hybrid], &mut options);
+        assert_eq!(
+            options["publicKey"]["hints"],
+            serde_json::json!(["client-device", "hybrid"]),
+        );
+        assert_eq!(options["publicKey"]["challenge"], "AAAA");
+
+        let mut options = serde_json::json!({ "publicKey": {} });
+        merge_hints_into(&[], &mut options);
+        assert!(options["publicKey"].get("hints").is_none());
+    }
+}
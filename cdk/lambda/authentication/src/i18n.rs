@@ -0,0 +1,323 @@
+//! Localized error messages.
+//!
+//! The `message` of an [`ErrorResponseBody`] describes the details of an
+//! error for developers, and is always in English. An error response also
+//! carries a `localizedMessage` that can be shown to the user as it is, in
+//! the language that the client prefers in the `Accept-Language` header.
+//! The `error` code stays the same in any language so that clients can still
+//! match it.
+//!
+//! English and Japanese are supported, and English is the default. See
+//! [`localize_error_response`].
+//!
+//! [`ErrorResponseBody`]: crate::payload::ErrorResponseBody
+
+use lambda_http::{
+    Body,
+    Request,
+    Response,
+    http::header::{ACCEPT_LANGUAGE, CONTENT_LANGUAGE, CONTENT_TYPE, HeaderValue},
+};
+
+/// Language of localized messages.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Language {
+    /// English; "en".
+    #[default]
+    English,
+    /// Japanese; "ja".
+    Japanese,
+}
+
+impl Language {
+    /// Returns the language tag in the `Content-Language` header.
+    pub fn tag(self) -> &'static str {
+        match self {
+            Language::English => "en",
+            Language::Japanese => "ja",
+        }
+    }
+
+    // language of a given primary subtag; e.g., "ja" of "ja-JP".
+    fn of_primary_subtag(subtag: &str) -> Option<Self> {
+        if subtag.eq_ignore_ascii_case("en") {
+            Some(Language::English)
+        } else if subtag.eq_ignore_ascii_case("ja") {
+            Some(Language::Japanese)
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns the language that a request prefers in the `Accept-Language`
+/// header.
+///
+/// The supported language with the highest quality wins, and the one listed
+/// first wins a tie. Only the primary subtag is compared; e.g., "ja-JP"
+/// selects Japanese. Returns [`Language::English`] if no supported language
+/// is accepted.
+pub fn preferred_language(request: &Request) -> Language {
+    request.headers()
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(parse_accept_language)
+        .unwrap_or_default()
+}
+
+fn parse_accept_language(accept_language: &str) -> Option<Language> {
+    let mut best: Option<(Language, f32)> = None;
+    for item in accept_language.split(',') {
+        let mut params = item.split(';');
+        let range = params.next().unwrap_or("").trim();
+        let Some(language) = Language::of_primary_subtag(
+            range.split('-').next().unwrap_or(""),
+        ) else {
+            continue;
+        };
+        let quality = params
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .find_map(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if quality > 0.0 && best.map_or(true, |(_, q)| quality > q) {
+            best = Some((language, quality));
+        }
+    }
+    best.map(|(language, _)| language)
+}
+
+/// Returns the localized message of a given error code.
+///
+/// Returns `None` if the error code is unknown.
+pub fn localized_message(error: &str, language: Language) -> Option<&'static str> {
+    let (en, ja) = match error {
+        "bad_request" | "bad_query" => (
+            "The request is invalid.",
+            "リクエストが正しくありません。",
+        ),
+        "payload_too_large" => (
+            "The request is too large.",
+            "リクエストが大きすぎます。",
+        ),
+        "missing_payload" | "malformed_payload" => (
+            "The request contents are invalid.",
+            "リクエストの内容が正しくありません。",
+        ),
+        "not_found" | "not_configured" => (
+            "The requested resource was not found.",
+            "リクエストされたリソースが見つかりません。",
+        ),
+        "method_not_allowed" => (
+            "The request method is not allowed.",
+            "リクエストメソッドは許可されていません。",
+        ),
+        "unsupported_media_type" => (
+            "The request format is not supported.",
+            "リクエストの形式はサポートされていません。",
+        ),
+        "unauthenticated" => (
+            "Please sign in.",
+            "サインインしてください。",
+        ),
+        "session_expired" => (
+            "The session has expired. Please start over.",
+            "セッションの有効期限が切れました。最初からやり直してください。",
+        ),
+        "verification_failed" | "authentication_failed" => (
+            "The passkey could not be verified.",
+            "パスキーを検証できませんでした。",
+        ),
+        "user_verification_required" => (
+            "Please verify yourself with your authenticator; e.g., with a PIN or biometrics.",
+            "認証器で本人確認をしてください (PIN や生体認証など)。",
+        ),
+        "not_allowed" => (
+            "This operation is not allowed.",
+            "この操作は許可されていません。",
+        ),
+        "internal_error" => (
+            "Something went wrong. Please try again later.",
+            "問題が発生しました。しばらくしてからもう一度お試しください。",
+        ),
+        "unsupported_version" => (
+            "The API version is not supported.",
+            "API のバージョンはサポートされていません。",
+        ),
+        "unknown_tenant" => (
+            "The service was not found.",
+            "サービスが見つかりません。",
+        ),
+        "too_many_requests" => (
+            "Too many requests. Please wait a moment and try again.",
+            "リクエストが多すぎます。しばらく待ってからもう一度お試しください。",
+        ),
+        "credential_locked" => (
+            "The passkey is temporarily locked after failed attempts. Please try again later.",
+            "認証の失敗が続いたため、パスキーが一時的にロックされています。しばらくしてからもう一度お試しください。",
+        ),
+        "captcha_required" => (
+            "Please complete the CAPTCHA.",
+            "CAPTCHA を完了してください。",
+        ),
+        "step_up_required" => (
+            "Please sign in with your passkey again to continue.",
+            "続けるには、もう一度パスキーでサインインしてください。",
+        ),
+        "step_up_failed" => (
+            "The additional sign-in failed.",
+            "追加のサインインに失敗しました。",
+        ),
+        "authentication_denied" => (
+            "The sign-in was denied.",
+            "サインインは拒否されました。",
+        ),
+        "credential_limit_exceeded" => (
+            "You cannot register any more passkeys. Please delete one you no longer use.",
+            "これ以上パスキーを登録できません。使わなくなったパスキーを削除してください。",
+        ),
+        "user_exists" | "username_taken" => (
+            "The username is already taken.",
+            "このユーザー名はすでに使われています。",
+        ),
+        "credential_exists" => (
+            "The passkey is already registered.",
+            "このパスキーはすでに登録されています。",
+        ),
+        "conflict" => (
+            "The request conflicts with the current state. Please try again.",
+            "リクエストが現在の状態と競合しています。もう一度お試しください。",
+        ),
+        "invalid_recovery_code" => (
+            "The username or recovery code is incorrect.",
+            "ユーザー名またはリカバリーコードが正しくありません。",
+        ),
+        "invalid_recovery_link" => (
+            "The recovery link is invalid or has expired.",
+            "リカバリーリンクが無効か、有効期限が切れています。",
+        ),
+        "credential_not_found" | "no_credentials" => (
+            "The passkey was not found.",
+            "パスキーが見つかりません。",
+        ),
+        "invalid_refresh_token" => (
+            "Your session has expired. Please sign in again.",
+            "セッションの有効期限が切れました。もう一度サインインしてください。",
+        ),
+        _ => return None,
+    };
+    Some(match language {
+        Language::English => en,
+        Language::Japanese => ja,
+    })
+}
+
+/// Adds the localized message to an error response.
+///
+/// The response must be a client or server error with an
+/// [`ErrorResponseBody`] as `application/json`; any other response is returned
+/// as it is. `Content-Language` is set if the message is localized.
+///
+/// [`ErrorResponseBody`]: crate::payload::ErrorResponseBody
+pub fn localize_error_response(
+    mut res: Response<Body>,
+    language: Language,
+) -> Response<Body> {
+    let is_error = res.status().is_client_error() || res.status().is_server_error();
+    let is_json = res.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if !is_error || !is_json {
+        return res;
+    }
+    let Ok(serde_json::Value::Object(mut body)) =
+        serde_json::from_slice::<serde_json::Value>(res.body().as_ref()) else
+    {
+        return res;
+    };
+    let Some(message) = body.get("error")
+        .and_then(|e| e.as_str())
+        .and_then(|e| localized_message(e, language)) else
+    {
+        return res;
+    };
+    body.insert("localizedMessage".into(), message.into());
+    let Ok(body) = serde_json::to_string(&body) else {
+        return res;
+    };
+    *res.body_mut() = body.into();
+    res.headers_mut()
+        .insert(CONTENT_LANGUAGE, HeaderValue::from_static(language.tag()));
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use lambda_http::http::StatusCode;
+
+    use crate::payload::ERROR_CODES;
+
+    fn error_response(status: StatusCode, body: &str) -> Response<Body> {
+        Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.into())
+            .unwrap()
+    }
+
+    #[test]
+    fn parse_accept_language_should_pick_supported_language_of_highest_quality() {
+        assert_eq!(parse_accept_language("ja-JP,ja;q=0.9,en;q=0.8"), Some(Language::Japanese));
+        assert_eq!(parse_accept_language("fr, en;q=0.5, ja;q=0.7"), Some(Language::Japanese));
+        assert_eq!(parse_accept_language("en-US, ja"), Some(Language::English));
+        assert_eq!(parse_accept_language("JA"), Some(Language::Japanese));
+        assert_eq!(parse_accept_language("ja;q=0, en;q=0.1"), Some(Language::English));
+        assert_eq!(parse_accept_language("fr, *"), None);
+    }
+
+    #[test]
+    fn localized_message_should_cover_error_codes() {
+        for code in ERROR_CODES {
+            assert!(localized_message(code, Language::English).is_some(), "{}", code);
+            assert!(localized_message(code, Language::Japanese).is_some(), "{}", code);
+        }
+        assert_eq!(localized_message("unknown_code", Language::English), None);
+    }
+
+    #[test]
+    fn localize_error_response_should_add_localized_message() {
+        let res = localize_error_response(
+            error_response(
+                StatusCode::UNAUTHORIZED,
+                r#"{"error":"session_expired","message":"session not found"}"#,
+            ),
+            Language::Japanese,
+        );
+        assert_eq!(res.headers().get(CONTENT_LANGUAGE).unwrap(), "ja");
+        let body: serde_json::Value = serde_json::from_slice(res.body().as_ref()).unwrap();
+        assert_eq!(body["error"], "session_expired");
+        assert_eq!(body["message"], "session not found");
+        assert_eq!(
+            body["localizedMessage"],
+            localized_message("session_expired", Language::Japanese).unwrap(),
+        );
+    }
+
+    #[test]
+    fn localize_error_response_should_leave_other_responses() {
+        let res = localize_error_response(
+            error_response(StatusCode::OK, r#"{"error":"session_expired"}"#),
+            Language::Japanese,
+        );
+        assert!(res.headers().get(CONTENT_LANGUAGE).is_none());
+        assert_eq!(res.body().as_ref(), br#"{"error":"session_expired"}"#);
+
+        let res = localize_error_response(
+            error_response(StatusCode::BAD_REQUEST, r#"{"error":"unknown_code"}"#),
+            Language::English,
+        );
+        assert!(res.headers().get(CONTENT_LANGUAGE).is_none());
+    }
+}
@@ -0,0 +1,131 @@
+//! Identity of authenticated callers.
+//!
+//! Protected endpoints are supposed to sit behind a JWT authorizer of
+//! API Gateway that verifies tokens issued by the Cognito user pool, or to
+//! verify tokens themselves with [`crate::routing::BearerAuth`], which puts
+//! an [`AuthenticatedUser`] in the extensions of a request.
+
+use lambda_http::{Request, RequestExt, request::RequestContext};
+use std::collections::HashMap;
+
+/// Caller authenticated by the function itself.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AuthenticatedUser {
+    /// User handle.
+    pub user_handle: String,
+
+    /// Groups in the Cognito user pool that the user belongs to.
+    pub groups: Vec<String>,
+}
+
+/// Returns the user handle of the authenticated caller of a given request.
+///
+/// The user handle equals the username in the Cognito user pool, and is taken
+/// from the [`AuthenticatedUser`] of the request, or the claims verified by
+/// the JWT authorizer.
+///
+/// Returns `None` if the request has not been authorized.
+pub fn authenticated_user_handle(request: &Request) -> Option<String> {
+    if let Some(user) = request.extensions().get::<AuthenticatedUser>() {
+        return Some(user.user_handle.clone());
+    }
+    match request.request_context_ref()? {
+        RequestContext::ApiGatewayV2(context) => context.authorizer.as_ref()?
+            .jwt.as_ref()
+            .and_then(|jwt| user_handle_from_claims(&jwt.claims)),
+        _ => None,
+    }
+}
+
+/// Returns whether the authenticated caller of a given request belongs to a
+/// given group in the Cognito user pool.
+///
+/// Returns `false` if the request has not been authorized.
+pub fn is_member_of(request: &Request, group: &str) -> bool {
+    if let Some(user) = request.extensions().get::<AuthenticatedUser>() {
+        return user.groups.iter().any(|g| g == group);
+    }
+    match request.request_context_ref() {
+        Some(RequestContext::ApiGatewayV2(context)) => context.authorizer.as_ref()
+            .and_then(|a| a.jwt.as_ref())
+            .and_then(|jwt| jwt.claims.get("cognito:groups"))
+            .is_some_and(|groups| parse_groups_claim(groups).any(|g| g == group)),
+        _ => false,
+    }
+}
+
+// the JWT authorizer of HTTP APIs flattens an array claim into a string like
+// "[admin users]".
+fn parse_groups_claim(groups: &str) -> impl Iterator<Item = &str> {
+    groups.trim_start_matches('[')
+        .trim_end_matches(']')
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|g| !g.is_empty())
+}
+
+// ID tokens have "cognito:username" while access tokens have "username".
+fn user_handle_from_claims(claims: &HashMap<String, String>) -> Option<String> {
+    claims.get("cognito:username")
+        .or_else(|| claims.get("username"))
+        .filter(|username| !username.is_empty())
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_handle_from_claims_should_accept_id_and_access_token_claims() {
+        let claims = HashMap::from([
+            ("cognito:username".to_string(), "id-token-user".to_string()),
+        ]);
+        assert_eq!(
+            user_handle_from_claims(&claims),
+            Some("id-token-user".to_string()),
+        );
+        let claims = HashMap::from([
+            ("username".to_string(), "access-token-user".to_string()),
+        ]);
+        assert_eq!(
+            user_handle_from_claims(&claims),
+            Some("access-token-user".to_string()),
+        );
+    }
+
+    #[test]
+    fn user_handle_from_claims_should_reject_missing_or_empty_username() {
+        assert_eq!(user_handle_from_claims(&HashMap::new()), None);
+        let claims = HashMap::from([
+            ("cognito:username".to_string(), "".to_string()),
+        ]);
+        assert_eq!(user_handle_from_claims(&claims), None);
+    }
+
+    #[test]
+    fn authenticated_user_should_take_precedence() {
+        let mut request = Request::default();
+        assert_eq!(authenticated_user_handle(&request), None);
+        request.extensions_mut().insert(AuthenticatedUser {
+            user_handle: "AAAA".into(),
+            groups: vec!["admin".into()],
+        });
+        assert_eq!(authenticated_user_handle(&request), Some("AAAA".into()));
+        assert!(is_member_of(&request, "admin"));
+        assert!(!is_member_of(&request, "users"));
+    }
+
+    #[test]
+    fn parse_groups_claim_should_split_flattened_array() {
+        assert_eq!(
+            parse_groups_claim("[admin users]").collect::<Vec<_>>(),
+            vec!["admin", "users"],
+        );
+        assert_eq!(
+            parse_groups_claim("[admin, users]").collect::<Vec<_>>(),
+            vec!["admin", "users"],
+        );
+        assert_eq!(parse_groups_claim("admin").collect::<Vec<_>>(), vec!["admin"]);
+        assert_eq!(parse_groups_claim("[]").count(), 0);
+    }
+}
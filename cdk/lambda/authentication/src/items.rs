@@ -0,0 +1,1682 @@
+//! Typed items in the DynamoDB tables.
+//!
+//! Keys and attributes of items are built and parsed only in this module so
+//! that handlers never assemble partition keys like "registration#..." or
+//! attribute maps by hand.
+//! See `cdk/lib/session-store.ts` and `cdk/lib/user-pool.ts` for the layouts
+//! of the session table and the credential table respectively.
+//!
+//! A user in the credential table is a [`UserItem`] and the credential items
+//! sharing the partition key made by [`user_pk`]. The user itself also lives
+//! in the Cognito user pool.
+
+use aws_sdk_dynamodb::{primitives::Blob, types::AttributeValue};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::client_binding::ClientBinding;
+use crate::error::Error;
+use crate::telemetry::redact;
+
+/// Attributes of an item.
+pub type Item = HashMap<String, AttributeValue>;
+
+/// Prefix of the partition key of users and credentials.
+const USER_PK_PREFIX: &str = "user#";
+
+/// Prefix of the sort key of credentials.
+pub const CREDENTIAL_SK_PREFIX: &str = "credential#";
+
+/// Sort key of users.
+pub const USER_SK: &str = "user";
+
+/// Sort key of the recovery codes of a user.
+pub const RECOVERY_CODES_SK: &str = "recovery-codes";
+
+/// Prefix of the sort key of the lockout states of credentials.
+pub const LOCKOUT_SK_PREFIX: &str = "lockout#";
+
+/// Key of an item in the session table.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SessionKey<'a> {
+    /// Passkey registration session identified by the session ID.
+    Registration(&'a str),
+    /// Security key registration session identified by the session ID.
+    SecurityKeyRegistration(&'a str),
+    /// Result of a finished passkey registration identified by the key hash.
+    RegistrationResult(&'a str),
+    /// Result of a finished security key registration identified by the key
+    /// hash.
+    SecurityKeyRegistrationResult(&'a str),
+    /// Registration session of a new passkey for account recovery identified
+    /// by the session ID.
+    RecoveryRegistration(&'a str),
+    /// Result of a finished registration for account recovery identified by
+    /// the key hash.
+    RecoveryRegistrationResult(&'a str),
+    /// Registration session of an additional passkey of an authenticated user
+    /// identified by the session ID.
+    AdditionalRegistration(&'a str),
+    /// Result of a finished registration of an additional passkey identified
+    /// by the key hash.
+    AdditionalRegistrationResult(&'a str),
+    /// Registration session of the first passkey of an authenticated
+    /// password user identified by the session ID.
+    UpgradeRegistration(&'a str),
+    /// Result of a finished password-to-passkey upgrade identified by the key
+    /// hash.
+    UpgradeRegistrationResult(&'a str),
+    /// Emailed recovery link identified by the "base64url"-encoded hash of the
+    /// token.
+    RecoveryLink(&'a str),
+    /// Step-up session of an authenticated user identified by the session ID.
+    StepUp(&'a str),
+    /// Step-up token identified by the "base64url"-encoded hash of the token.
+    StepUpToken(&'a str),
+    /// Authentication session identified by the "base64url"-encoded
+    /// challenge.
+    Discoverable(&'a str),
+    /// Refresh token identified by the "base64url"-encoded hash of the token.
+    RefreshToken(&'a str),
+    /// Family of refresh tokens identified by the family ID.
+    RefreshTokenFamily(&'a str),
+    /// Latest passkey authentication of a user identified by the
+    /// "base64url"-encoded user handle.
+    PasskeyAuthentication(&'a str),
+    /// Rate limit counter.
+    RateLimit {
+        /// Scope of the limit; e.g., "ip" or "username".
+        scope: &'a str,
+        /// "base64url"-encoded hash of the key.
+        key_hash: &'a str,
+        /// Start of the window in seconds since the epoch.
+        window_start: i64,
+    },
+    /// Key of another item scoped to a tenant.
+    Tenant {
+        /// Tenant ID.
+        tenant_id: &'a str,
+        /// Key in the tenant.
+        key: &'a SessionKey<'a>,
+    },
+}
+
+impl SessionKey<'_> {
+    /// Returns the partition key.
+    pub fn pk(&self) -> String {
+        match self {
+            SessionKey::Registration(id) => format!("registration#{}", id),
+            SessionKey::SecurityKeyRegistration(id) =>
+                format!("securitykey-registration#{}", id),
+            SessionKey::RegistrationResult(hash) =>
+                format!("registration-result#{}", hash),
+            SessionKey::SecurityKeyRegistrationResult(hash) =>
+                format!("securitykey-registration-result#{}", hash),
+            SessionKey::RecoveryRegistration(id) =>
+                format!("recovery-registration#{}", id),
+            SessionKey::RecoveryRegistrationResult(hash) =>
+                format!("recovery-registration-result#{}", hash),
+            SessionKey::AdditionalRegistration(id) =>
+                format!("additional-registration#{}", id),
+            SessionKey::AdditionalRegistrationResult(hash) =>
+                format!("additional-registration-result#{}", hash),
+            SessionKey::UpgradeRegistration(id) =>
+                format!("upgrade-registration#{}", id),
+            SessionKey::UpgradeRegistrationResult(hash) =>
+                format!("upgrade-registration-result#{}", hash),
+            SessionKey::RecoveryLink(hash) => format!("recovery-link#{}", hash),
+            SessionKey::StepUp(id) => format!("stepup#{}", id),
+            SessionKey::StepUpToken(hash) => format!("stepup-token#{}", hash),
+            SessionKey::Discoverable(challenge) =>
+                format!("discoverable#{}", challenge),
+            SessionKey::RefreshToken(hash) => format!("refresh-token#{}", hash),
+            SessionKey::RefreshTokenFamily(id) => format!("refresh-family#{}", id),
+            SessionKey::PasskeyAuthentication(user_handle) =>
+                format!("passkey-authentication#{}", user_handle),
+            SessionKey::RateLimit { scope, key_hash, window_start } =>
+                format!("ratelimit#{}#{}#{}", scope, key_hash, window_start),
+            SessionKey::Tenant { tenant_id, key } =>
+                format!("tenant#{}#{}", tenant_id, key.pk()),
+        }
+    }
+
+    /// Returns the partition key as an attribute value.
+    pub fn attribute(&self) -> AttributeValue {
+        AttributeValue::S(self.pk())
+    }
+}
+
+/// Returns the partition key of credentials of a given user.
+pub fn user_pk(user_handle: &str) -> String {
+    format!("{}{}", USER_PK_PREFIX, user_handle)
+}
+
+/// Extracts the user handle from an item in the credential table or an index
+/// of it.
+pub fn user_handle_of(item: &Item) -> Option<&str> {
+    item.get("pk")
+        .and_then(|pk| pk.as_s().ok())
+        .and_then(|pk| pk.strip_prefix(USER_PK_PREFIX))
+}
+
+/// Extracts the expiration of an item in the session table in seconds since
+/// the epoch.
+pub fn ttl_of(item: &Item) -> Result<i64, Error> {
+    required(get_n(item, "ttl")?, "ttl")
+}
+
+/// Key of a credential item.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CredentialKey<'a> {
+    /// "base64url"-encoded user handle.
+    pub user_handle: &'a str,
+
+    /// "base64url"-encoded credential ID.
+    pub credential_id: &'a str,
+}
+
+impl CredentialKey<'_> {
+    /// Returns the partition key.
+    pub fn pk(&self) -> String {
+        user_pk(self.user_handle)
+    }
+
+    /// Returns the sort key.
+    pub fn sk(&self) -> String {
+        format!("{}{}", CREDENTIAL_SK_PREFIX, self.credential_id)
+    }
+
+    /// Returns the primary key attributes.
+    pub fn key(&self) -> Item {
+        HashMap::from([
+            ("pk".to_string(), AttributeValue::S(self.pk())),
+            ("sk".to_string(), AttributeValue::S(self.sk())),
+        ])
+    }
+
+    /// Returns the primary key attributes of the lockout state of the
+    /// credential.
+    pub fn lockout_key(&self) -> Item {
+        HashMap::from([
+            ("pk".to_string(), AttributeValue::S(self.pk())),
+            (
+                "sk".to_string(),
+                AttributeValue::S(format!("{}{}", LOCKOUT_SK_PREFIX, self.credential_id)),
+            ),
+        ])
+    }
+}
+
+/// Credential item in the credential table.
+///
+/// Serializable for exports; see [`crate::migration`].
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialItem {
+    /// "base64url"-encoded user handle.
+    pub user_handle: String,
+
+    /// "base64url"-encoded credential ID.
+    pub credential_id: String,
+
+    /// Normalized username.
+    ///
+    /// `None` for credentials stored before usernames were recorded.
+    pub username: Option<String>,
+
+    /// Serialized JSON representation of the credential.
+    pub credential: String,
+
+    /// Type of the credential; "passkey" or "securityKey".
+    pub credential_type: Option<String>,
+
+    /// Whether the credential is eligible for backup (BE flag).
+    pub backup_eligible: Option<bool>,
+
+    /// Whether the credential is backed up (BS flag).
+    pub backup_state: Option<bool>,
+
+    /// Whether the credential is client-side discoverable (resident).
+    ///
+    /// Reported by the `credProps` extension at registration. `None` if the
+    /// client did not report it.
+    pub discoverable: Option<bool>,
+
+    /// Whether the credential supports the `prf` extension.
+    ///
+    /// Reported at registration. `None` if the client did not report it.
+    pub prf_enabled: Option<bool>,
+
+    /// Cognito sub ID.
+    pub cognito_sub: Option<String>,
+
+    /// Authenticator attachment reported at registration.
+    ///
+    /// Advisory; the client reports it without a signature.
+    pub authenticator_attachment: Option<String>,
+
+    /// AAGUID of the authenticator reported at registration.
+    ///
+    /// `None` if the client hid it or the credential was stored before
+    /// AAGUIDs were recorded.
+    pub aaguid: Option<String>,
+
+    /// Description of the authenticator in the FIDO Metadata Service.
+    ///
+    /// `None` if the authenticator is not listed or the metadata is not
+    /// configured; see [`crate::mds`].
+    pub authenticator_name: Option<String>,
+
+    /// Format of the attestation statement given at registration.
+    ///
+    /// `None` unless attestation was requested and the authenticator attested
+    /// the credential.
+    pub attestation_format: Option<String>,
+
+    /// "base64url"-encoded DER certificates of the attestation statement
+    /// given at registration, the attestation certificate first.
+    ///
+    /// `None` unless attestation was requested and the authenticator attested
+    /// the credential.
+    pub attestation_certificates: Option<Vec<String>>,
+
+    /// Source IP address of the client that registered the credential.
+    ///
+    /// `None` if unknown or the credential was stored before clients were
+    /// recorded.
+    pub registered_ip: Option<String>,
+
+    /// User agent of the client that registered the credential.
+    ///
+    /// `None` if unknown or the credential was stored before clients were
+    /// recorded.
+    pub registered_user_agent: Option<String>,
+
+    /// When the credential was registered.
+    pub created_at: String,
+
+    /// When the credential was last updated.
+    pub updated_at: String,
+
+    /// When the credential was last used for authentication.
+    ///
+    /// `None` if the credential has never been used since last-used
+    /// timestamps were recorded.
+    pub last_used_at: Option<String>,
+
+    /// Number of successful authentications with the credential.
+    ///
+    /// `None` if the credential has never been used since authentications
+    /// were counted.
+    pub auth_count: Option<u64>,
+
+    /// When the credential was disabled by an administrator.
+    ///
+    /// `None` if the credential is enabled. Disabled credentials cannot be
+    /// used for authentication.
+    pub disabled_at: Option<String>,
+
+    /// When the credential was deleted by the user.
+    ///
+    /// `None` unless the credential is soft-deleted. A soft-deleted
+    /// credential is also disabled, and can be restored within the retention
+    /// window; see [`crate::deletion`].
+    pub deleted_at: Option<String>,
+
+    /// Legacy ID of the relying party with which the credential was last
+    /// verified in the migration mode of the RP ID.
+    ///
+    /// `None` if the credential has never been verified only with a legacy
+    /// RP ID; see [`crate::rp_migration`]. Such credentials need
+    /// re-registration.
+    pub legacy_rp_id: Option<String>,
+
+    /// Version incremented on every update for optimistic locking.
+    ///
+    /// `None` for credentials stored before versions were recorded.
+    /// Not exported.
+    #[serde(skip)]
+    pub version: Option<u64>,
+}
+
+impl CredentialItem {
+    /// Returns the key of the item.
+    pub fn key(&self) -> CredentialKey<'_> {
+        CredentialKey {
+            user_handle: &self.user_handle,
+            credential_id: &self.credential_id,
+        }
+    }
+
+    /// Parses an item in the credential table.
+    pub fn from_item(item: &Item) -> Result<Self, Error> {
+        Ok(Self {
+            user_handle: user_handle_of(item)
+                .ok_or(Error::BadItemAttribute("pk"))?
+                .into(),
+            credential_id: required(get_s(item, "credentialId")?, "credentialId")?,
+            username: get_s(item, "username")?,
+            credential: required(get_s(item, "credential")?, "credential")?,
+            credential_type: get_s(item, "credentialType")?,
+            backup_eligible: get_bool(item, "backupEligible")?,
+            backup_state: get_bool(item, "backupState")?,
+            discoverable: get_bool(item, "discoverable")?,
+            prf_enabled: get_bool(item, "prfEnabled")?,
+            cognito_sub: get_s(item, "cognitoSub")?,
+            authenticator_attachment: get_s(item, "authenticatorAttachment")?,
+            aaguid: get_s(item, "aaguid")?,
+            authenticator_name: get_s(item, "authenticatorName")?,
+            attestation_format: get_s(item, "attestationFormat")?,
+            attestation_certificates: get_s_list(item, "attestationCertificates")?,
+            registered_ip: get_s(item, "registeredIp")?,
+            registered_user_agent: get_s(item, "registeredUserAgent")?,
+            created_at: required(get_s(item, "createdAt")?, "createdAt")?,
+            updated_at: required(get_s(item, "updatedAt")?, "updatedAt")?,
+            last_used_at: get_s(item, "lastUsedAt")?,
+            auth_count: get_n(item, "authCount")?,
+            disabled_at: get_s(item, "disabledAt")?,
+            deleted_at: get_s(item, "deletedAt")?,
+            legacy_rp_id: get_s(item, "legacyRpId")?,
+            version: get_n(item, "version")?,
+        })
+    }
+
+    /// Converts into the attributes of an item including the keys.
+    pub fn into_item(self) -> Item {
+        let mut item = self.key().key();
+        if let Some(version) = self.version {
+            item.insert("version".into(), AttributeValue::N(format!("{}", version)));
+        }
+        item.insert("credentialId".into(), AttributeValue::S(self.credential_id));
+        item.insert("credential".into(), AttributeValue::S(self.credential));
+        put_s(&mut item, "username", self.username);
+        put_s(&mut item, "credentialType", self.credential_type);
+        if let Some(backup_eligible) = self.backup_eligible {
+            item.insert("backupEligible".into(), AttributeValue::Bool(backup_eligible));
+        }
+        if let Some(backup_state) = self.backup_state {
+            item.insert("backupState".into(), AttributeValue::Bool(backup_state));
+        }
+        if let Some(discoverable) = self.discoverable {
+            item.insert("discoverable".into(), AttributeValue::Bool(discoverable));
+        }
+        if let Some(prf_enabled) = self.prf_enabled {
+            item.insert("prfEnabled".into(), AttributeValue::Bool(prf_enabled));
+        }
+        put_s(&mut item, "cognitoSub", self.cognito_sub);
+        put_s(&mut item, "authenticatorAttachment", self.authenticator_attachment);
+        put_s(&mut item, "aaguid", self.aaguid);
+        put_s(&mut item, "authenticatorName", self.authenticator_name);
+        put_s(&mut item, "attestationFormat", self.attestation_format);
+        if let Some(certificates) = self.attestation_certificates {
+            item.insert(
+                "attestationCertificates".into(),
+                AttributeValue::L(certificates.into_iter().map(AttributeValue::S).collect()),
+            );
+        }
+        put_s(&mut item, "registeredIp", self.registered_ip);
+        put_s(&mut item, "registeredUserAgent", self.registered_user_agent);
+        item.insert("createdAt".into(), AttributeValue::S(self.created_at));
+        item.insert("updatedAt".into(), AttributeValue::S(self.updated_at));
+        put_s(&mut item, "lastUsedAt", self.last_used_at);
+        if let Some(auth_count) = self.auth_count {
+            item.insert("authCount".into(), AttributeValue::N(format!("{}", auth_count)));
+        }
+        put_s(&mut item, "disabledAt", self.disabled_at);
+        put_s(&mut item, "deletedAt", self.deleted_at);
+        put_s(&mut item, "legacyRpId", self.legacy_rp_id);
+        item
+    }
+}
+
+#[cfg(test)]
+impl CredentialItem {
+    /// Builds a credential for unit tests.
+    ///
+    /// The credential has none of the optional attributes, and was created
+    /// and updated at "2024-01-01T00:00:00Z". Override the attributes a test
+    /// cares about with the struct update syntax.
+    pub fn for_test(user_handle: &str, credential_id: &str) -> Self {
+        Self {
+            user_handle: user_handle.into(),
+            credential_id: credential_id.into(),
+            username: None,
+            credential: "{}".into(),
+            credential_type: None,
+            backup_eligible: None,
+            backup_state: None,
+            discoverable: None,
+            prf_enabled: None,
+            cognito_sub: None,
+            authenticator_attachment: None,
+            aaguid: None,
+            authenticator_name: None,
+            attestation_format: None,
+            attestation_certificates: None,
+            registered_ip: None,
+            registered_user_agent: None,
+            created_at: "2024-01-01T00:00:00Z".into(),
+            updated_at: "2024-01-01T00:00:00Z".into(),
+            last_used_at: None,
+            auth_count: None,
+            disabled_at: None,
+            deleted_at: None,
+            legacy_rp_id: None,
+            version: None,
+        }
+    }
+}
+
+/// User item in the credential table.
+///
+/// Created together with the first credential of the user.
+/// Serializable for exports; see [`crate::migration`].
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserItem {
+    /// "base64url"-encoded user handle.
+    pub user_handle: String,
+
+    /// Normalized username.
+    pub username: String,
+
+    /// Display name.
+    pub display_name: String,
+
+    /// Cognito sub ID.
+    pub cognito_sub: String,
+
+    /// When the user was created.
+    pub created_at: String,
+}
+
+impl UserItem {
+    /// Returns the primary key attributes of a given user.
+    pub fn key(user_handle: &str) -> Item {
+        HashMap::from([
+            ("pk".to_string(), AttributeValue::S(user_pk(user_handle))),
+            ("sk".to_string(), AttributeValue::S(USER_SK.into())),
+        ])
+    }
+
+    /// Parses an item in the credential table.
+    pub fn from_item(item: &Item) -> Result<Self, Error> {
+        Ok(Self {
+            user_handle: user_handle_of(item)
+                .ok_or(Error::BadItemAttribute("pk"))?
+                .into(),
+            username: required(get_s(item, "username")?, "username")?,
+            display_name: required(get_s(item, "displayName")?, "displayName")?,
+            cognito_sub: required(get_s(item, "cognitoSub")?, "cognitoSub")?,
+            created_at: required(get_s(item, "createdAt")?, "createdAt")?,
+        })
+    }
+
+    /// Converts into the attributes of an item including the keys.
+    pub fn into_item(self) -> Item {
+        let mut item = Self::key(&self.user_handle);
+        item.insert("username".into(), AttributeValue::S(self.username));
+        item.insert("displayName".into(), AttributeValue::S(self.display_name));
+        item.insert("cognitoSub".into(), AttributeValue::S(self.cognito_sub));
+        item.insert("createdAt".into(), AttributeValue::S(self.created_at));
+        item
+    }
+}
+
+/// Recovery codes of a user in the credential table.
+///
+/// Only the hashes of unused codes are stored; see [`crate::recovery`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RecoveryCodesItem {
+    /// "base64url"-encoded user handle.
+    pub user_handle: String,
+
+    /// Hashes of the unused recovery codes.
+    pub code_hashes: Vec<String>,
+
+    /// When the recovery codes were generated.
+    pub created_at: String,
+}
+
+impl RecoveryCodesItem {
+    /// Returns the primary key attributes of the recovery codes of a given
+    /// user.
+    pub fn key(user_handle: &str) -> Item {
+        HashMap::from([
+            ("pk".to_string(), AttributeValue::S(user_pk(user_handle))),
+            ("sk".to_string(), AttributeValue::S(RECOVERY_CODES_SK.into())),
+        ])
+    }
+
+    /// Parses an item in the credential table.
+    ///
+    /// `codeHashes` is missing if all the codes have been used, because
+    /// DynamoDB removes an empty set.
+    pub fn from_item(item: &Item) -> Result<Self, Error> {
+        Ok(Self {
+            user_handle: user_handle_of(item)
+                .ok_or(Error::BadItemAttribute("pk"))?
+                .into(),
+            code_hashes: item.get("codeHashes")
+                .map(|v| v.as_ss()
+                    .cloned()
+                    .or(Err(Error::BadItemAttribute("codeHashes"))))
+                .transpose()?
+                .unwrap_or_default(),
+            created_at: required(get_s(item, "createdAt")?, "createdAt")?,
+        })
+    }
+
+    /// Converts into the attributes of an item including the keys.
+    pub fn into_item(self) -> Item {
+        let mut item = Self::key(&self.user_handle);
+        // an empty set is not allowed
+        if !self.code_hashes.is_empty() {
+            item.insert("codeHashes".into(), AttributeValue::Ss(self.code_hashes));
+        }
+        item.insert("createdAt".into(), AttributeValue::S(self.created_at));
+        item
+    }
+}
+
+/// Lockout state of a credential in the credential table.
+///
+/// Updated only with atomic update expressions; see [`crate::lockout`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct LockoutItem {
+    /// Consecutive failed authentications since the last lock.
+    pub failures: u64,
+
+    /// Locks since the last successful authentication.
+    pub locks: u64,
+
+    /// End of the current lock in seconds since the epoch.
+    pub locked_until: Option<i64>,
+}
+
+impl LockoutItem {
+    /// Parses an item in the credential table.
+    pub fn from_item(item: &Item) -> Result<Self, Error> {
+        Ok(Self {
+            failures: get_n(item, "failures")?.unwrap_or(0),
+            locks: get_n(item, "locks")?.unwrap_or(0),
+            locked_until: get_n(item, "lockedUntil")?,
+        })
+    }
+}
+
+/// User information in a registration session.
+///
+/// The `Debug` representation redacts the username and display name.
+#[derive(Clone, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationUserInfo {
+    /// Unique username.
+    pub username: String,
+
+    /// Display name.
+    pub display_name: String,
+}
+
+impl fmt::Debug for RegistrationUserInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegistrationUserInfo")
+            .field("username", &redact(&self.username))
+            .field("display_name", &redact(&self.display_name))
+            .finish()
+    }
+}
+
+/// Contents of a registration session.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RegistrationContents {
+    /// Stored in plaintext.
+    Plain {
+        /// User information.
+        user_info: RegistrationUserInfo,
+        /// Serialized registration state.
+        state: String,
+    },
+    /// Sealed under a data key.
+    ///
+    /// `user_info` is the sealed JSON representation of
+    /// [`RegistrationUserInfo`].
+    Sealed {
+        /// Data key encrypted by KMS.
+        data_key: Vec<u8>,
+        /// Sealed user information.
+        user_info: Vec<u8>,
+        /// Sealed registration state.
+        state: Vec<u8>,
+    },
+}
+
+/// Registration session item in the session table.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RegistrationSessionItem {
+    /// Expiration time in seconds since the epoch.
+    pub ttl: i64,
+
+    /// "base64url"-encoded unique user ID.
+    pub user_id: String,
+
+    /// Authenticator attachment requested in the creation options.
+    pub authenticator_attachment: Option<String>,
+
+    /// Client to which the session is bound.
+    pub client_binding: ClientBinding,
+
+    /// User information and registration state.
+    pub contents: RegistrationContents,
+}
+
+impl RegistrationSessionItem {
+    /// Parses an item in the session table.
+    pub fn from_item(item: &Item) -> Result<Self, Error> {
+        let contents = match get_b(item, "dataKey")? {
+            Some(data_key) => RegistrationContents::Sealed {
+                data_key,
+                user_info: required(get_b(item, "userInfo")?, "userInfo")?,
+                state: required(get_b(item, "state")?, "state")?,
+            },
+            None => {
+                let user_info = item.get("userInfo")
+                    .ok_or(Error::BadItemAttribute("userInfo"))?
+                    .as_m()
+                    .or(Err(Error::BadItemAttribute("userInfo")))?;
+                RegistrationContents::Plain {
+                    user_info: RegistrationUserInfo {
+                        username: required(
+                            get_s(user_info, "username")?,
+                            "username",
+                        )?,
+                        display_name: required(
+                            get_s(user_info, "displayName")?,
+                            "displayName",
+                        )?,
+                    },
+                    state: required(get_s(item, "state")?, "state")?,
+                }
+            }
+        };
+        Ok(Self {
+            ttl: required(get_n(item, "ttl")?, "ttl")?,
+            user_id: required(get_s(item, "userId")?, "userId")?,
+            authenticator_attachment: get_s(item, "authenticatorAttachment")?,
+            client_binding: get_client_binding(item)?,
+            contents,
+        })
+    }
+
+    /// Converts into the attributes of an item with a given key.
+    pub fn into_item(self, key: SessionKey<'_>) -> Item {
+        let mut item = HashMap::from([
+            ("pk".to_string(), key.attribute()),
+            ("ttl".into(), AttributeValue::N(format!("{}", self.ttl))),
+            ("userId".into(), AttributeValue::S(self.user_id)),
+        ]);
+        put_s(&mut item, "authenticatorAttachment", self.authenticator_attachment);
+        put_client_binding(&mut item, self.client_binding);
+        match self.contents {
+            RegistrationContents::Plain { user_info, state } => {
+                item.insert("userInfo".into(), AttributeValue::M(HashMap::from([
+                    ("username".into(), AttributeValue::S(user_info.username)),
+                    (
+                        "displayName".into(),
+                        AttributeValue::S(user_info.display_name),
+                    ),
+                ])));
+                item.insert("state".into(), AttributeValue::S(state));
+            }
+            RegistrationContents::Sealed { data_key, user_info, state } => {
+                item.insert("dataKey".into(), AttributeValue::B(Blob::new(data_key)));
+                item.insert("userInfo".into(), AttributeValue::B(Blob::new(user_info)));
+                item.insert("state".into(), AttributeValue::B(Blob::new(state)));
+            }
+        }
+        item
+    }
+}
+
+/// Result of a finished registration in the session table.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RegistrationResultItem {
+    /// Expiration time in seconds since the epoch.
+    pub ttl: i64,
+
+    /// Session ID of the registration.
+    pub session_id: String,
+
+    /// ID of the registered credential.
+    pub credential_id: String,
+}
+
+impl RegistrationResultItem {
+    /// Parses an item in the session table.
+    pub fn from_item(item: &Item) -> Result<Self, Error> {
+        Ok(Self {
+            ttl: required(get_n(item, "ttl")?, "ttl")?,
+            session_id: required(get_s(item, "sessionId")?, "sessionId")?,
+            credential_id: required(get_s(item, "credentialId")?, "credentialId")?,
+        })
+    }
+
+    /// Converts into the attributes of an item with a given key.
+    pub fn into_item(self, key: SessionKey<'_>) -> Item {
+        HashMap::from([
+            ("pk".to_string(), key.attribute()),
+            ("ttl".into(), AttributeValue::N(format!("{}", self.ttl))),
+            ("sessionId".into(), AttributeValue::S(self.session_id)),
+            ("credentialId".into(), AttributeValue::S(self.credential_id)),
+        ])
+    }
+}
+
+/// Emailed recovery link in the session table.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RecoveryLinkItem {
+    /// Expiration time in seconds since the epoch.
+    pub ttl: i64,
+
+    /// "base64url"-encoded user handle of the recovering user.
+    pub user_handle: String,
+}
+
+impl RecoveryLinkItem {
+    /// Parses an item in the session table.
+    pub fn from_item(item: &Item) -> Result<Self, Error> {
+        Ok(Self {
+            ttl: required(get_n(item, "ttl")?, "ttl")?,
+            user_handle: required(get_s(item, "userHandle")?, "userHandle")?,
+        })
+    }
+
+    /// Converts into the attributes of an item with a given key.
+    pub fn into_item(self, key: SessionKey<'_>) -> Item {
+        HashMap::from([
+            ("pk".to_string(), key.attribute()),
+            ("ttl".into(), AttributeValue::N(format!("{}", self.ttl))),
+            ("userHandle".into(), AttributeValue::S(self.user_handle)),
+        ])
+    }
+}
+
+/// Step-up session of an authenticated user in the session table.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StepUpSessionItem {
+    /// Expiration time in seconds since the epoch.
+    pub ttl: i64,
+
+    /// "base64url"-encoded user handle of the authenticated user.
+    pub user_handle: String,
+
+    /// Serialized authentication state.
+    pub state: String,
+}
+
+impl StepUpSessionItem {
+    /// Parses an item in the session table.
+    pub fn from_item(item: &Item) -> Result<Self, Error> {
+        Ok(Self {
+            ttl: required(get_n(item, "ttl")?, "ttl")?,
+            user_handle: required(get_s(item, "userHandle")?, "userHandle")?,
+            state: required(get_s(item, "state")?, "state")?,
+        })
+    }
+
+    /// Converts into the attributes of an item with a given key.
+    pub fn into_item(self, key: SessionKey<'_>) -> Item {
+        HashMap::from([
+            ("pk".to_string(), key.attribute()),
+            ("ttl".into(), AttributeValue::N(format!("{}", self.ttl))),
+            ("userHandle".into(), AttributeValue::S(self.user_handle)),
+            ("state".into(), AttributeValue::S(self.state)),
+        ])
+    }
+}
+
+/// Step-up token in the session table.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StepUpTokenItem {
+    /// Expiration time in seconds since the epoch.
+    pub ttl: i64,
+
+    /// "base64url"-encoded user handle of the user who stepped up.
+    pub user_handle: String,
+}
+
+impl StepUpTokenItem {
+    /// Parses an item in the session table.
+    pub fn from_item(item: &Item) -> Result<Self, Error> {
+        Ok(Self {
+            ttl: required(get_n(item, "ttl")?, "ttl")?,
+            user_handle: required(get_s(item, "userHandle")?, "userHandle")?,
+        })
+    }
+
+    /// Converts into the attributes of an item with a given key.
+    pub fn into_item(self, key: SessionKey<'_>) -> Item {
+        HashMap::from([
+            ("pk".to_string(), key.attribute()),
+            ("ttl".into(), AttributeValue::N(format!("{}", self.ttl))),
+            ("userHandle".into(), AttributeValue::S(self.user_handle)),
+        ])
+    }
+}
+
+/// Refresh token in the session table.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RefreshTokenItem {
+    /// Expiration time in seconds since the epoch.
+    pub ttl: i64,
+
+    /// "base64url"-encoded user handle of the user who owns the token.
+    pub user_handle: String,
+
+    /// ID of the family that the token belongs to.
+    pub family_id: String,
+
+    /// Time when the token was used in seconds since the epoch.
+    ///
+    /// `None` if the token has not been used.
+    pub used_at: Option<i64>,
+}
+
+impl RefreshTokenItem {
+    /// Parses an item in the session table.
+    pub fn from_item(item: &Item) -> Result<Self, Error> {
+        Ok(Self {
+            ttl: required(get_n(item, "ttl")?, "ttl")?,
+            user_handle: required(get_s(item, "userHandle")?, "userHandle")?,
+            family_id: required(get_s(item, "familyId")?, "familyId")?,
+            used_at: get_n(item, "usedAt")?,
+        })
+    }
+
+    /// Converts into the attributes of an item with a given key.
+    pub fn into_item(self, key: SessionKey<'_>) -> Item {
+        let mut item = HashMap::from([
+            ("pk".to_string(), key.attribute()),
+            ("ttl".into(), AttributeValue::N(format!("{}", self.ttl))),
+            ("userHandle".into(), AttributeValue::S(self.user_handle)),
+            ("familyId".into(), AttributeValue::S(self.family_id)),
+        ]);
+        if let Some(used_at) = self.used_at {
+            item.insert("usedAt".into(), AttributeValue::N(format!("{}", used_at)));
+        }
+        item
+    }
+}
+
+/// Family of refresh tokens in the session table.
+///
+/// Every refresh token rotated from the one issued at authentication belongs
+/// to the same family.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RefreshTokenFamilyItem {
+    /// Expiration time in seconds since the epoch.
+    ///
+    /// No token of the family outlives this time.
+    pub ttl: i64,
+
+    /// "base64url"-encoded user handle of the user who owns the family.
+    pub user_handle: String,
+
+    /// Time when the family was revoked in seconds since the epoch.
+    ///
+    /// `None` if the family has not been revoked.
+    pub revoked_at: Option<i64>,
+}
+
+impl RefreshTokenFamilyItem {
+    /// Parses an item in the session table.
+    pub fn from_item(item: &Item) -> Result<Self, Error> {
+        Ok(Self {
+            ttl: required(get_n(item, "ttl")?, "ttl")?,
+            user_handle: required(get_s(item, "userHandle")?, "userHandle")?,
+            revoked_at: get_n(item, "revokedAt")?,
+        })
+    }
+
+    /// Converts into the attributes of an item with a given key.
+    pub fn into_item(self, key: SessionKey<'_>) -> Item {
+        let mut item = HashMap::from([
+            ("pk".to_string(), key.attribute()),
+            ("ttl".into(), AttributeValue::N(format!("{}", self.ttl))),
+            ("userHandle".into(), AttributeValue::S(self.user_handle)),
+        ]);
+        if let Some(revoked_at) = self.revoked_at {
+            item.insert("revokedAt".into(), AttributeValue::N(format!("{}", revoked_at)));
+        }
+        item
+    }
+}
+
+/// Passkey authentication verified by the Cognito trigger in the session
+/// table.
+///
+/// Tells the pre token generation trigger which credential the user has just
+/// authenticated with; see [`crate::passkey_claims`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PasskeyAuthenticationItem {
+    /// Expiration time in seconds since the epoch.
+    pub ttl: i64,
+
+    /// "base64url"-encoded credential ID.
+    pub credential_id: String,
+
+    /// AAGUID of the authenticator reported at registration.
+    pub aaguid: Option<String>,
+
+    /// Whether the user was verified in the assertion.
+    pub user_verified: bool,
+
+    /// Time of the authentication in seconds since the epoch.
+    pub authenticated_at: i64,
+}
+
+impl PasskeyAuthenticationItem {
+    /// Parses an item in the session table.
+    pub fn from_item(item: &Item) -> Result<Self, Error> {
+        Ok(Self {
+            ttl: required(get_n(item, "ttl")?, "ttl")?,
+            credential_id: required(get_s(item, "credentialId")?, "credentialId")?,
+            aaguid: get_s(item, "aaguid")?,
+            user_verified: required(get_bool(item, "userVerified")?, "userVerified")?,
+            authenticated_at: required(get_n(item, "authenticatedAt")?, "authenticatedAt")?,
+        })
+    }
+
+    /// Converts into the attributes of an item with a given key.
+    pub fn into_item(self, key: SessionKey<'_>) -> Item {
+        let mut item = HashMap::from([
+            ("pk".to_string(), key.attribute()),
+            ("ttl".into(), AttributeValue::N(format!("{}", self.ttl))),
+            ("credentialId".into(), AttributeValue::S(self.credential_id)),
+            ("userVerified".into(), AttributeValue::Bool(self.user_verified)),
+            (
+                "authenticatedAt".into(),
+                AttributeValue::N(format!("{}", self.authenticated_at)),
+            ),
+        ]);
+        if let Some(aaguid) = self.aaguid {
+            item.insert("aaguid".into(), AttributeValue::S(aaguid));
+        }
+        item
+    }
+}
+
+/// Authentication session with a user-side discoverable credential in the
+/// session table.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DiscoverableSessionItem {
+    /// Expiration time in seconds since the epoch.
+    pub ttl: i64,
+
+    /// Serialized authentication state.
+    pub state: String,
+
+    /// Client to which the session is bound.
+    pub client_binding: ClientBinding,
+}
+
+impl DiscoverableSessionItem {
+    /// Parses an item in the session table.
+    pub fn from_item(item: &Item) -> Result<Self, Error> {
+        Ok(Self {
+            ttl: required(get_n(item, "ttl")?, "ttl")?,
+            state: required(get_s(item, "state")?, "state")?,
+            client_binding: get_client_binding(item)?,
+        })
+    }
+
+    /// Converts into the attributes of an item with a given key.
+    pub fn into_item(self, key: SessionKey<'_>) -> Item {
+        let mut item = HashMap::from([
+            ("pk".to_string(), key.attribute()),
+            ("ttl".into(), AttributeValue::N(format!("{}", self.ttl))),
+            ("state".into(), AttributeValue::S(self.state)),
+        ]);
+        put_client_binding(&mut item, self.client_binding);
+        item
+    }
+}
+
+fn required<T>(value: Option<T>, name: &'static str) -> Result<T, Error> {
+    value.ok_or(Error::BadItemAttribute(name))
+}
+
+fn get_s(item: &Item, name: &'static str) -> Result<Option<String>, Error> {
+    item.get(name)
+        .map(|v| v.as_s()
+            .cloned()
+            .or(Err(Error::BadItemAttribute(name))))
+        .transpose()
+}
+
+fn get_n<T: FromStr>(item: &Item, name: &'static str) -> Result<Option<T>, Error> {
+    item.get(name)
+        .map(|v| v.as_n()
+            .ok()
+            .and_then(|n| n.parse().ok())
+            .ok_or(Error::BadItemAttribute(name)))
+        .transpose()
+}
+
+fn get_bool(item: &Item, name: &'static str) -> Result<Option<bool>, Error> {
+    item.get(name)
+        .map(|v| v.as_bool()
+            .copied()
+            .or(Err(Error::BadItemAttribute(name))))
+        .transpose()
+}
+
+fn get_s_list(item: &Item, name: &'static str) -> Result<Option<Vec<String>>, Error> {
+    item.get(name)
+        .map(|v| v.as_l()
+            .ok()
+            .and_then(|l| l.iter().map(|v| v.as_s().ok().cloned()).collect())
+            .ok_or(Error::BadItemAttribute(name)))
+        .transpose()
+}
+
+fn get_b(item: &Item, name: &'static str) -> Result<Option<Vec<u8>>, Error> {
+    item.get(name)
+        .map(|v| v.as_b()
+            .map(|b| b.as_ref().to_vec())
+            .or(Err(Error::BadItemAttribute(name))))
+        .transpose()
+}
+
+fn put_s(item: &mut Item, name: &str, value: Option<String>) {
+    if let Some(value) = value {
+        item.insert(name.into(), AttributeValue::S(value));
+    }
+}
+
+fn get_client_binding(item: &Item) -> Result<ClientBinding, Error> {
+    Ok(ClientBinding {
+        source_ip: get_s(item, "boundSourceIp")?,
+        user_agent_hash: get_s(item, "boundUserAgentHash")?,
+    })
+}
+
+fn put_client_binding(item: &mut Item, binding: ClientBinding) {
+    put_s(item, "boundSourceIp", binding.source_ip);
+    put_s(item, "boundUserAgentHash", binding.user_agent_hash);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credential_item() -> CredentialItem {
+        CredentialItem {
+            username: Some("alice".into()),
+            credential_type: Some("passkey".into()),
+            backup_eligible: Some(true),
+            backup_state: Some(false),
+            discoverable: Some(true),
+            prf_enabled: Some(false),
+            cognito_sub: Some("sub".into()),
+            aaguid: Some("cb69481e-8ff7-4039-93ec-0a2729a154a8".into()),
+            authenticator_name: Some("YubiKey 5 Series".into()),
+            attestation_format: Some("packed".into()),
+            attestation_certificates: Some(vec!["leaf".into()]),
+            registered_ip: Some("192.0.2.1".into()),
+            last_used_at: Some("2024-01-01T12:00:00Z".into()),
+            auth_count: Some(3),
+            disabled_at: Some("2024-01-02T00:00:00Z".into()),
+            deleted_at: Some("2024-01-02T00:00:00Z".into()),
+            legacy_rp_id: Some("old.example.com".into()),
+            version: Some(1),
+            ..CredentialItem::for_test("AAAA", "BBBB")
+        }
+    }
+
+    #[test]
+    fn session_key_pk_should_have_prefix() {
+        assert_eq!(SessionKey::Registration("abc").pk(), "registration#abc");
+        assert_eq!(
+            SessionKey::SecurityKeyRegistration("abc").pk(),
+            "securitykey-registration#abc",
+        );
+        assert_eq!(
+            SessionKey::RegistrationResult("abc").pk(),
+            "registration-result#abc",
+        );
+        assert_eq!(
+            SessionKey::RecoveryRegistration("abc").pk(),
+            "recovery-registration#abc",
+        );
+        assert_eq!(
+            SessionKey::AdditionalRegistration("abc").pk(),
+            "additional-registration#abc",
+        );
+        assert_eq!(
+            SessionKey::UpgradeRegistration("abc").pk(),
+            "upgrade-registration#abc",
+        );
+        assert_eq!(SessionKey::RecoveryLink("abc").pk(), "recovery-link#abc");
+        assert_eq!(SessionKey::StepUp("abc").pk(), "stepup#abc");
+        assert_eq!(SessionKey::StepUpToken("abc").pk(), "stepup-token#abc");
+        assert_eq!(SessionKey::Discoverable("abc").pk(), "discoverable#abc");
+        assert_eq!(SessionKey::RefreshToken("abc").pk(), "refresh-token#abc");
+        assert_eq!(
+            SessionKey::RefreshTokenFamily("abc").pk(),
+            "refresh-family#abc",
+        );
+        assert_eq!(
+            SessionKey::RateLimit {
+                scope: "ip",
+                key_hash: "abc",
+                window_start: 60,
+            }.pk(),
+            "ratelimit#ip#abc#60",
+        );
+        assert_eq!(
+            SessionKey::Tenant {
+                tenant_id: "acme",
+                key: &SessionKey::Registration("abc"),
+            }.pk(),
+            "tenant#acme#registration#abc",
+        );
+    }
+
+    #[test]
+    fn credential_item_should_round_trip() {
+        let item = credential_item().into_item();
+        assert_eq!(item["pk"], AttributeValue::S("user#AAAA".into()));
+        assert_eq!(item["sk"], AttributeValue::S("credential#BBBB".into()));
+        assert!(!item.contains_key("authenticatorAttachment"));
+        assert_eq!(item["version"], AttributeValue::N("1".into()));
+        assert_eq!(item["authCount"], AttributeValue::N("3".into()));
+        assert_eq!(CredentialItem::from_item(&item).unwrap(), credential_item());
+    }
+
+    #[test]
+    fn credential_item_from_item_should_report_missing_attribute() {
+        let mut item = credential_item().into_item();
+        item.remove("createdAt");
+        assert!(matches!(
+            CredentialItem::from_item(&item),
+            Err(Error::BadItemAttribute("createdAt")),
+        ));
+    }
+
+    #[test]
+    fn user_item_should_round_trip() {
+        let user = UserItem {
+            user_handle: "AAAA".into(),
+            username: "alice".into(),
+            display_name: "Alice".into(),
+            cognito_sub: "sub".into(),
+            created_at: "2024-01-01T00:00:00Z".into(),
+        };
+        let item = user.clone().into_item();
+        assert_eq!(item["pk"], AttributeValue::S("user#AAAA".into()));
+        assert_eq!(item["sk"], AttributeValue::S("user".into()));
+        assert_eq!(UserItem::from_item(&item).unwrap(), user);
+    }
+
+    #[test]
+    fn recovery_codes_item_should_allow_no_codes_left() {
+        let codes = RecoveryCodesItem {
+            user_handle: "AAAA".into(),
+            code_hashes: vec!["hash1".into(), "hash2".into()],
+            created_at: "2024-01-01T00:00:00Z".into(),
+        };
+        let item = codes.clone().into_item();
+        assert_eq!(item["sk"], AttributeValue::S("recovery-codes".into()));
+        assert_eq!(RecoveryCodesItem::from_item(&item).unwrap(), codes);
+
+        let used_up = RecoveryCodesItem {
+            code_hashes: Vec::new(),
+            ..codes
+        };
+        let item = used_up.clone().into_item();
+        assert!(!item.contains_key("codeHashes"));
+        assert_eq!(RecoveryCodesItem::from_item(&item).unwrap(), used_up);
+    }
+
+    #[test]
+    fn lockout_item_should_default_missing_counts() {
+        let key = CredentialKey {
+            user_handle: "AAAA",
+            credential_id: "BBBB",
+        };
+        let mut item = key.lockout_key();
+        assert_eq!(item["sk"], AttributeValue::S("lockout#BBBB".into()));
+        item.insert("failures".into(), AttributeValue::N("2".into()));
+        assert_eq!(LockoutItem::from_item(&item).unwrap(), LockoutItem {
+            failures: 2,
+            locks: 0,
+            locked_until: None,
+        });
+        item.insert("lockedUntil".into(), AttributeValue::S("soon".into()));
+        assert!(LockoutItem::from_item(&item).is_err());
+    }
+
+    #[test]
+    fn registration_session_item_should_round_trip() {
+        for contents in [
+            RegistrationContents::Plain {
+                user_info: RegistrationUserInfo {
+                    username: "alice".into(),
+                    display_name: "Alice".into(),
+                },
+                state: "{}".into(),
+            },
+            RegistrationContents::Sealed {
+                data_key: vec![1, 2],
+                user_info: vec![3, 4],
+                state: vec![5, 6],
+            },
+        ] {
+            let session = RegistrationSessionItem {
+                ttl: 123,
+                user_id: "AAAA".into(),
+                authenticator_attachment: Some("platform".into()),
+                client_binding: ClientBinding {
+                    source_ip: Some("192.0.2.1".into()),
+                    user_agent_hash: None,
+                },
+                contents,
+            };
+            let item = session.clone().into_item(SessionKey::Registration("abc"));
+            assert_eq!(item["pk"], AttributeValue::S("registration#abc".into()));
+            assert_eq!(RegistrationSessionItem::from_item(&item).unwrap(), session);
+        }
+    }
+
+    #[test]
+    fn recovery_link_item_should_round_trip() {
+        let item = RecoveryLinkItem {
+            ttl: 900,
+            user_handle: "AAAA".into(),
+        };
+        let attributes = item.clone().into_item(SessionKey::RecoveryLink("abc"));
+        assert_eq!(attributes["pk"], AttributeValue::S("recovery-link#abc".into()));
+        assert_eq!(RecoveryLinkItem::from_item(&attributes).unwrap(), item);
+    }
+
+    #[test]
+    fn step_up_session_item_should_round_trip() {
+        let item = StepUpSessionItem {
+            ttl: 60,
+            user_handle: "AAAA".into(),
+            state: "{}".into(),
+        };
+        let attributes = item.clone().into_item(SessionKey::StepUp("abc"));
+        assert_eq!(attributes["pk"], AttributeValue::S("stepup#abc".into()));
+        assert_eq!(StepUpSessionItem::from_item(&attributes).unwrap(), item);
+    }
+
+    #[test]
+    fn discoverable_session_item_should_round_trip() {
+        let item = DiscoverableSessionItem {
+            ttl: 60,
+            state: "{}".into(),
+            client_binding: ClientBinding {
+                source_ip: None,
+                user_agent_hash: Some("hash".into()),
+            },
+        };
+        let attributes = item.clone().into_item(SessionKey::Discoverable("abc"));
+        assert_eq!(attributes["boundUserAgentHash"], AttributeValue::S("hash".into()));
+        assert!(!attributes.contains_key("boundSourceIp"));
+        assert_eq!(DiscoverableSessionItem::from_item(&attributes).unwrap(), item);
+    }
+
+    #[test]
+    fn refresh_token_items_should_round_trip() {
+        let item = RefreshTokenItem {
+            ttl: 60,
+            user_handle: "AAAA".into(),
+            family_id: "family".into(),
+            used_at: Some(30),
+        };
+        let attributes = item.clone().into_item(SessionKey::RefreshToken("abc"));
+        assert_eq!(attributes["pk"], AttributeValue::S("refresh-token#abc".into()));
+        assert_eq!(RefreshTokenItem::from_item(&attributes).unwrap(), item);
+        let family = RefreshTokenFamilyItem {
+            ttl: 60,
+            user_handle: "AAAA".into(),
+            revoked_at: None,
+        };
+        let attributes = family.clone()
+            .into_item(SessionKey::RefreshTokenFamily("family"));
+        assert!(!attributes.contains_key("revokedAt"));
+        assert_eq!(RefreshTokenFamilyItem::from_item(&attributes).unwrap(), family);
+    }
+
+    #[test]
+    fn passkey_authentication_item_should_round_trip() {
+        let item = PasskeyAuthenticationItem {
+            ttl: 360,
+            credential_id: "BBBB".into(),
+            aaguid: Some("ea9b8d66-4d01-1d21-3ce4-b6b48cb575d4".into()),
+            user_verified: true,
+            authenticated_at: 60,
+        };
+        let attributes = item.clone().into_item(SessionKey::PasskeyAuthentication("AAAA"));
+        assert_eq!(
+            attributes["pk"],
+            AttributeValue::S("passkey-authentication#AAAA".into()),
+        );
+        assert_eq!(PasskeyAuthenticationItem::from_item(&attributes).unwrap(), item);
+        let item = PasskeyAuthenticationItem { aaguid: None, ..item };
+        let attributes = item.clone().into_item(SessionKey::PasskeyAuthentication("AAAA"));
+        assert!(!attributes.contains_key("aaguid"));
+        assert_eq!(PasskeyAuthenticationItem::from_item(&attributes).unwrap(), item);
+    }
+
+    #[test]
+    fn user_handle_of_should_reject_other_items() {
+        let item = HashMap::from([
+            ("pk".to_string(), SessionKey::Registration("AAAA").attribute()),
+        ]);
+        assert_eq!(user_handle_of(&item), None);
+        assert_eq!(user_handle_of(&HashMap::new()), None);
+    }
+
+    #[test]
+    fn ttl_of_should_require_numeric_ttl() {
+        let item = HashMap::from([
+            ("ttl".to_string(), AttributeValue::N("123".into())),
+        ]);
+        assert_eq!(ttl_of(&item).unwrap(), 123);
+        let item = HashMap::from([
+            ("ttl".to_string(), AttributeValue::S("123".into())),
+        ]);
+        assert!(ttl_of(&item).is_err());
+        assert!(ttl_of(&HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn registration_user_info_should_redact_debug_representation() {
+        let user_info = RegistrationUserInfo {
+            username: "alice".into(),
+            display_name: "Alice Liddell".into(),
+        };
+        let debug = format!("{:?}", user_info);
+        assert!(!debug.contains("alice"));
+        assert!(!debug.contains("Alice Liddell"));
+        assert!(debug.contains(&format!("{}", redact("alice"))));
+    }
+
+    mod round_trip {
+        use super::*;
+
+        use proptest::prelude::*;
+        use webauthn_rs::prelude::{
+            DiscoverableAuthentication,
+            Passkey,
+            PasskeyAuthentication,
+            PasskeyRegistration,
+            Uuid,
+        };
+
+        use crate::authenticator::VirtualAuthenticator;
+        use crate::authenticator::testing::{ORIGIN, create_credential, webauthn};
+
+        // ceremonies generate key pairs, so fewer cases are run.
+        const CEREMONY_CASES: u32 = 16;
+
+        // "base64url"-encoded value, which never contains '#'.
+        fn base64url_value() -> impl Strategy<Value = String> {
+            "[A-Za-z0-9_-]{1,64}"
+        }
+
+        fn client_binding() -> impl Strategy<Value = ClientBinding> {
+            (any::<Option<String>>(), proptest::option::of(base64url_value()))
+                .prop_map(|(source_ip, user_agent_hash)| ClientBinding {
+                    source_ip,
+                    user_agent_hash,
+                })
+        }
+
+        fn registration_contents() -> impl Strategy<Value = RegistrationContents> {
+            prop_oneof![
+                (any::<String>(), any::<String>(), any::<String>())
+                    .prop_map(|(username, display_name, state)| RegistrationContents::Plain {
+                        user_info: RegistrationUserInfo { username, display_name },
+                        state,
+                    }),
+                (
+                    any::<Vec<u8>>(),
+                    any::<Vec<u8>>(),
+                    any::<Vec<u8>>(),
+                ).prop_map(|(data_key, user_info, state)| RegistrationContents::Sealed {
+                    data_key,
+                    user_info,
+                    state,
+                }),
+            ]
+        }
+
+        fn credential_item() -> impl Strategy<Value = CredentialItem> {
+            let keys = (base64url_value(), base64url_value(), any::<String>());
+            let flags = (
+                any::<Option<bool>>(),
+                any::<Option<bool>>(),
+                any::<Option<bool>>(),
+                any::<Option<bool>>(),
+            );
+            let authenticator = (
+                any::<Option<String>>(),
+                any::<Option<String>>(),
+                any::<Option<String>>(),
+                any::<Option<String>>(),
+                any::<Option<String>>(),
+                any::<Option<Vec<String>>>(),
+            );
+            let history = (
+                any::<Option<String>>(),
+                any::<Option<String>>(),
+                any::<String>(),
+                any::<String>(),
+                any::<Option<String>>(),
+                any::<Option<u64>>(),
+                any::<Option<String>>(),
+                any::<Option<String>>(),
+                any::<Option<String>>(),
+                any::<Option<u64>>(),
+            );
+            (keys, any::<Option<String>>(), any::<Option<String>>(), flags, authenticator, history)
+                .prop_map(|(keys, username, credential_type, flags, authenticator, history)| {
+                    let (user_handle, credential_id, credential) = keys;
+                    let (backup_eligible, backup_state, discoverable, prf_enabled) = flags;
+                    let (
+                        cognito_sub,
+                        authenticator_attachment,
+                        aaguid,
+                        authenticator_name,
+                        attestation_format,
+                        attestation_certificates,
+                    ) = authenticator;
+                    let (
+                        registered_ip,
+                        registered_user_agent,
+                        created_at,
+                        updated_at,
+                        last_used_at,
+                        auth_count,
+                        disabled_at,
+                        deleted_at,
+                        legacy_rp_id,
+                        version,
+                    ) = history;
+                    CredentialItem {
+                        user_handle,
+                        credential_id,
+                        username,
+                        credential,
+                        credential_type,
+                        backup_eligible,
+                        backup_state,
+                        discoverable,
+                        prf_enabled,
+                        cognito_sub,
+                        authenticator_attachment,
+                        aaguid,
+                        authenticator_name,
+                        attestation_format,
+                        attestation_certificates,
+                        registered_ip,
+                        registered_user_agent,
+                        created_at,
+                        updated_at,
+                        last_used_at,
+                        auth_count,
+                        disabled_at,
+                        deleted_at,
+                        legacy_rp_id,
+                        version,
+                    }
+                })
+        }
+
+        proptest! {
+            #[test]
+            fn credential_item_should_survive_attributes(item in credential_item()) {
+                prop_assert_eq!(CredentialItem::from_item(&item.clone().into_item()).unwrap(), item);
+            }
+
+            #[test]
+            fn registration_session_item_should_survive_attributes(
+                ttl in any::<i64>(),
+                user_id in base64url_value(),
+                authenticator_attachment in any::<Option<String>>(),
+                client_binding in client_binding(),
+                contents in registration_contents(),
+            ) {
+                let item = RegistrationSessionItem {
+                    ttl,
+                    user_id,
+                    authenticator_attachment,
+                    client_binding,
+                    contents,
+                };
+                let attributes = item.clone().into_item(SessionKey::Registration("AAAA"));
+                prop_assert_eq!(RegistrationSessionItem::from_item(&attributes).unwrap(), item);
+            }
+
+            #[test]
+            fn session_items_with_states_should_survive_attributes(
+                ttl in any::<i64>(),
+                user_handle in base64url_value(),
+                state in any::<String>(),
+                client_binding in client_binding(),
+            ) {
+                let item = StepUpSessionItem {
+                    ttl,
+                    user_handle,
+                    state: state.clone(),
+                };
+                let attributes = item.clone().into_item(SessionKey::StepUp("AAAA"));
+                prop_assert_eq!(StepUpSessionItem::from_item(&attributes).unwrap(), item);
+                let item = DiscoverableSessionItem { ttl, state, client_binding };
+                let attributes = item.clone().into_item(SessionKey::Discoverable("AAAA"));
+                prop_assert_eq!(DiscoverableSessionItem::from_item(&attributes).unwrap(), item);
+            }
+        }
+
+        proptest! {
+            #![proptest_config(ProptestConfig::with_cases(CEREMONY_CASES))]
+
+            #[test]
+            fn webauthn_states_should_survive_session_items(
+                user_handle in any::<u128>().prop_map(Uuid::from_u128),
+                username in "[a-z0-9._-]{1,32}",
+                display_name in "\\PC{1,32}",
+                client_binding in client_binding(),
+            ) {
+                let webauthn = webauthn();
+                let mut authenticator = VirtualAuthenticator::new(ORIGIN);
+                let (credential, state) = create_credential(
+                    &webauthn,
+                    &mut authenticator,
+                    user_handle,
+                    &username,
+                    &display_name,
+                );
+
+                // the state of a registration
+                let item = RegistrationSessionItem {
+                    ttl: 0,
+                    user_id: "AAAA".into(),
+                    authenticator_attachment: None,
+                    client_binding: client_binding.clone(),
+                    contents: RegistrationContents::Plain {
+                        user_info: RegistrationUserInfo {
+                            username: username.clone(),
+                            display_name: display_name.clone(),
+                        },
+                        state: serde_json::to_string(&state).unwrap(),
+                    },
+                };
+                let attributes = item.into_item(SessionKey::Registration("AAAA"));
+                let restored: PasskeyRegistration =
+                    match RegistrationSessionItem::from_item(&attributes).unwrap().contents {
+                        RegistrationContents::Plain { state, .. } => {
+                            serde_json::from_str(&state).unwrap()
+                        }
+                        RegistrationContents::Sealed { .. } => unreachable!(),
+                    };
+                prop_assert_eq!(
+                    serde_json::to_value(&restored).unwrap(),
+                    serde_json::to_value(&state).unwrap(),
+                );
+                let passkey = webauthn.finish_passkey_registration(&credential, &restored).unwrap();
+
+                // the credential record in the credential table
+                let item = CredentialItem {
+                    credential: serde_json::to_string(&passkey).unwrap(),
+                    ..super::credential_item()
+                };
+                let attributes = item.into_item();
+                let credential = CredentialItem::from_item(&attributes).unwrap().credential;
+                let restored: Passkey = serde_json::from_str(&credential).unwrap();
+                prop_assert_eq!(
+                    serde_json::to_value(&restored).unwrap(),
+                    serde_json::to_value(&passkey).unwrap(),
+                );
+
+                // the state of a step-up
+                let (options, state) = webauthn
+                    .start_passkey_authentication(&[restored.clone()])
+                    .unwrap();
+                let item = StepUpSessionItem {
+                    ttl: 0,
+                    user_handle: "AAAA".into(),
+                    state: serde_json::to_string(&state).unwrap(),
+                };
+                let attributes = item.into_item(SessionKey::StepUp("AAAA"));
+                let state: PasskeyAuthentication = serde_json::from_str(
+                    &StepUpSessionItem::from_item(&attributes).unwrap().state,
+                ).unwrap();
+                let credential = authenticator.get(&options).unwrap();
+                webauthn.finish_passkey_authentication(&credential, &state).unwrap();
+
+                // the state of a discoverable authentication
+                let (options, state) = webauthn.start_discoverable_authentication().unwrap();
+                let item = DiscoverableSessionItem {
+                    ttl: 0,
+                    state: serde_json::to_string(&state).unwrap(),
+                    client_binding,
+                };
+                let attributes = item.into_item(SessionKey::Discoverable("AAAA"));
+                let state: DiscoverableAuthentication = serde_json::from_str(
+                    &DiscoverableSessionItem::from_item(&attributes).unwrap().state,
+                ).unwrap();
+                let credential = authenticator.get(&options).unwrap();
+                webauthn
+                    .finish_discoverable_authentication(&credential, state, &[(&restored).into()])
+                    .unwrap();
+            }
+        }
+    }
+}
@@ -0,0 +1,708 @@
+//! Verification of JWTs with remote JWKS.
+//!
+//! Verifies the tokens that clients obtain after signing in with a passkey:
+//! - access tokens issued by the Cognito user pool, signed with RS256; or ID
+//!   tokens instead if configured. Tokens of the other kind are rejected,
+//!   because `token_use` is checked.
+//! - self-issued tokens, signed with ES256; see [`crate::token`]
+//!
+//! The public keys are fetched from the JWKS (JSON Web Key Set) of each
+//! trusted issuer and cached for a while. The JWKS is fetched again before
+//! the cache expires if a token is signed with an unknown key, so that a
+//! rotated key is picked up without waiting for the expiration; at most once
+//! per [`MIN_JWKS_REFRESH_INTERVAL`] though, so that forged key IDs cannot
+//! flood the issuer. If a refresh fails, the stale keys keep being served.
+//!
+//! Time-based claims are checked with a leeway for the clock skew between
+//! the issuer and the verifier; see [`load_jwt_verifier`].
+
+use base64::{
+    Engine as _,
+    engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
+};
+use ring::signature::{
+    ECDSA_P256_SHA256_FIXED,
+    RSA_PKCS1_2048_8192_SHA256,
+    RsaPublicKeyComponents,
+    UnparsedPublicKey,
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{error, info};
+
+use crate::config;
+use crate::error::Error;
+
+/// Default time to live of a cached JWKS.
+pub const DEFAULT_JWKS_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Minimum interval between fetches of a JWKS triggered by an unknown key.
+pub const MIN_JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Default leeway of time-based claims in seconds.
+pub const DEFAULT_CLOCK_SKEW: i64 = 60;
+
+/// Default kind of Cognito tokens accepted.
+pub const DEFAULT_TOKEN_USE: &str = "access";
+
+// Timeout of a request for a JWKS.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Issuer whose tokens are trusted.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TrustedIssuer {
+    /// Issuer (`iss`) of tokens.
+    pub issuer: String,
+
+    /// URL of the JWKS of the issuer.
+    pub jwks_url: String,
+
+    /// Audiences accepted in `aud`, or `client_id` of a Cognito access token.
+    ///
+    /// Any audience is accepted if empty.
+    pub audiences: Vec<String>,
+
+    /// `token_use` that tokens must have; "access" or "id" for Cognito.
+    ///
+    /// Not checked if `None`; self-issued tokens have no `token_use`.
+    pub token_use: Option<String>,
+}
+
+impl TrustedIssuer {
+    /// Trusts the tokens of a given Cognito user pool.
+    ///
+    /// `client_ids` are the IDs of the app clients whose tokens are accepted,
+    /// and `token_use` is the kind of accepted tokens; "access" or "id".
+    pub fn cognito(
+        region: &str,
+        user_pool_id: &str,
+        client_ids: Vec<String>,
+        token_use: impl Into<String>,
+    ) -> Self {
+        let issuer = format!("https://cognito-idp.{}.amazonaws.com/{}", region, user_pool_id);
+        Self {
+            jwks_url: format!("{}/.well-known/jwks.json", issuer),
+            issuer,
+            audiences: client_ids,
+            token_use: Some(token_use.into()),
+        }
+    }
+}
+
+/// Loads the verifier of JWTs.
+///
+/// You can specify the following environment variables:
+/// - `USER_POOL_ID`: ID of the Cognito user pool whose tokens are trusted.
+///   The region is taken from `AWS_REGION`.
+/// - `USER_POOL_CLIENT_IDS`: comma-separated IDs of the app clients whose
+///   tokens are accepted. Required with `USER_POOL_ID`.
+/// - `USER_POOL_TOKEN_USE`: kind of tokens of the user pool that are
+///   accepted; "access" or "id". Defaults to [`DEFAULT_TOKEN_USE`].
+/// - `TOKEN_ISSUER`, `TOKEN_JWKS_URL`: issuer of self-issued tokens that are
+///   trusted and the URL of its JWKS; e.g., the `jwks` endpoint of the
+///   discoverable credential authentication. Both must be specified together.
+/// - `TOKEN_AUDIENCE`: audience of self-issued tokens; any audience by default
+/// - `JWKS_CACHE_TTL`: time to live of cached JWKS in seconds; defaults to
+///   [`DEFAULT_JWKS_CACHE_TTL`]
+/// - `JWT_CLOCK_SKEW`: leeway of `exp`, `nbf`, and `iat` in seconds; defaults
+///   to [`DEFAULT_CLOCK_SKEW`]
+///
+/// Returns `None` if neither `USER_POOL_ID` nor `TOKEN_ISSUER` is set.
+pub fn load_jwt_verifier() -> Result<Option<JwtVerifier>, Error> {
+    let mut issuers = Vec::new();
+    if let Some(user_pool_id) = optional_var("USER_POOL_ID")? {
+        let region = optional_var("AWS_REGION")?
+            .ok_or(Error::BadEnvironmentVariable("AWS_REGION", "".into()))?;
+        let client_ids = optional_var("USER_POOL_CLIENT_IDS")?
+            .map(|ids| split_list(&ids))
+            .filter(|ids| !ids.is_empty())
+            .ok_or(Error::BadEnvironmentVariable("USER_POOL_CLIENT_IDS", "".into()))?;
+        let token_use = parse_token_use(optional_var("USER_POOL_TOKEN_USE")?)?;
+        issuers.push(TrustedIssuer::cognito(&region, &user_pool_id, client_ids, token_use));
+    }
+    match (optional_var("TOKEN_ISSUER")?, optional_var("TOKEN_JWKS_URL")?) {
+        (Some(issuer), Some(jwks_url)) => issuers.push(TrustedIssuer {
+            issuer,
+            jwks_url,
+            audiences: optional_var("TOKEN_AUDIENCE")?.into_iter().collect(),
+            token_use: None,
+        }),
+        (Some(_), None) => return Err(
+            Error::BadEnvironmentVariable("TOKEN_JWKS_URL", "".into()),
+        ),
+        (None, Some(jwks_url)) => return Err(
+            Error::BadEnvironmentVariable("TOKEN_JWKS_URL", jwks_url),
+        ),
+        (None, None) => {}
+    }
+    if issuers.is_empty() {
+        return Ok(None);
+    }
+    let cache_ttl = match optional_var("JWKS_CACHE_TTL")? {
+        Some(ttl) => ttl.parse()
+            .map(Duration::from_secs)
+            .or(Err(Error::BadEnvironmentVariable("JWKS_CACHE_TTL", ttl)))?,
+        None => DEFAULT_JWKS_CACHE_TTL,
+    };
+    let clock_skew = match optional_var("JWT_CLOCK_SKEW")? {
+        Some(skew) => skew.parse::<i64>()
+            .ok()
+            .filter(|skew| *skew >= 0)
+            .ok_or(Error::BadEnvironmentVariable("JWT_CLOCK_SKEW", skew))?,
+        None => DEFAULT_CLOCK_SKEW,
+    };
+    JwtVerifier::new(issuers, cache_ttl, clock_skew).map(Some)
+}
+
+/// Verifier of JWTs issued by trusted issuers.
+pub struct JwtVerifier {
+    issuers: Vec<IssuerKeys>,
+    cache_ttl: Duration,
+    clock_skew: i64,
+    http: reqwest::Client,
+}
+
+struct IssuerKeys {
+    issuer: TrustedIssuer,
+    cached: Mutex<Option<CachedJwks>>,
+}
+
+#[derive(Clone)]
+struct CachedJwks {
+    keys: Arc<JwkSet>,
+    fetched_at: Instant,
+}
+
+impl JwtVerifier {
+    /// Creates a verifier that trusts given issuers.
+    pub fn new(
+        issuers: Vec<TrustedIssuer>,
+        cache_ttl: Duration,
+        clock_skew: i64,
+    ) -> Result<Self, Error> {
+        let http = reqwest::Client::builder()
+            .timeout(FETCH_TIMEOUT)
+            .build()
+            .or(Err(Error::Token("failed to build HTTP client")))?;
+        Ok(Self {
+            issuers: issuers.into_iter()
+                .map(|issuer| IssuerKeys { issuer, cached: Mutex::new(None) })
+                .collect(),
+            cache_ttl,
+            clock_skew,
+            http,
+        })
+    }
+
+    /// Verifies a token and returns its identity.
+    ///
+    /// Fails if the token is malformed, its issuer is not trusted, or the
+    /// signature, audience, `token_use`, or any time-based claim is invalid.
+    pub async fn verify(&self, token: &str) -> Result<VerifiedToken, Error> {
+        let parsed = ParsedToken::parse(token)?;
+        let issuer = self.issuers.iter()
+            .find(|i| i.issuer.issuer == parsed.issuer())
+            .ok_or(Error::Token("untrusted issuer"))?;
+        let keys = self.keys_for(issuer, parsed.header.kid.as_deref()).await?;
+        parsed.verify(&issuer.issuer, &keys, now(), self.clock_skew)
+    }
+
+    // returns the keys of an issuer, fetching them if the cache is stale or
+    // lacks a given key.
+    async fn keys_for(
+        &self,
+        issuer: &IssuerKeys,
+        kid: Option<&str>,
+    ) -> Result<Arc<JwkSet>, Error> {
+        let cached = issuer.cached.lock().unwrap().clone();
+        if let Some(cached) = cached.as_ref() {
+            let age = cached.fetched_at.elapsed();
+            let has_key = kid.map_or(true, |kid| cached.keys.find(Some(kid)).is_some());
+            if age < self.cache_ttl && (has_key || age < MIN_JWKS_REFRESH_INTERVAL) {
+                return Ok(cached.keys.clone());
+            }
+        }
+        match self.fetch(&issuer.issuer.jwks_url).await {
+            Ok(keys) => {
+                let keys = Arc::new(keys);
+                *issuer.cached.lock().unwrap() = Some(CachedJwks {
+                    keys: keys.clone(),
+                    fetched_at: Instant::now(),
+                });
+                Ok(keys)
+            }
+            Err(e) => match cached {
+                Some(cached) => {
+                    error!("serving stale JWKS of {}: {}", issuer.issuer.issuer, e);
+                    Ok(cached.keys)
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    async fn fetch(&self, jwks_url: &str) -> Result<JwkSet, Error> {
+        info!("fetching JWKS: {}", jwks_url);
+        self.http.get(jwks_url)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+            .map_err(|e| {
+                error!(?e, "fetching JWKS");
+                Error::Token("failed to fetch JWKS")
+            })?
+            .json()
+            .await
+            .map_err(|e| {
+                error!(?e, "parsing JWKS");
+                Error::Token("malformed JWKS")
+            })
+    }
+}
+
+/// Identity in a verified token.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VerifiedToken {
+    /// Issuer of the token.
+    pub issuer: String,
+
+    /// User handle of the authenticated user.
+    ///
+    /// `cognito:username` of an ID token, `username` of an access token, or
+    /// `sub` of a self-issued token.
+    pub user_handle: String,
+
+    /// Groups in the Cognito user pool that the user belongs to.
+    pub groups: Vec<String>,
+
+    /// Every claim of the token.
+    pub claims: Map<String, Value>,
+}
+
+/// JSON Web Key Set.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct JwkSet {
+    /// Keys.
+    pub keys: Vec<PublicJwk>,
+}
+
+/// Public key in a JWKS.
+///
+/// Only RSA and P-256 keys are usable; the others are ignored.
+#[derive(Clone, Debug, Deserialize)]
+pub struct PublicJwk {
+    /// Key type; "RSA" or "EC".
+    pub kty: String,
+
+    /// Key ID.
+    #[serde(default)]
+    pub kid: Option<String>,
+
+    /// Modulus of an RSA key.
+    #[serde(default)]
+    pub n: Option<String>,
+
+    /// Exponent of an RSA key.
+    #[serde(default)]
+    pub e: Option<String>,
+
+    /// Curve of an EC key.
+    #[serde(default)]
+    pub crv: Option<String>,
+
+    /// x coordinate of an EC key.
+    #[serde(default)]
+    pub x: Option<String>,
+
+    /// y coordinate of an EC key.
+    #[serde(default)]
+    pub y: Option<String>,
+}
+
+impl JwkSet {
+    // finds the key of a given ID, or the only key if the token has no key
+    // ID.
+    fn find(&self, kid: Option<&str>) -> Option<&PublicJwk> {
+        match kid {
+            Some(kid) => self.keys.iter().find(|k| k.kid.as_deref() == Some(kid)),
+            None if self.keys.len() == 1 => self.keys.first(),
+            None => None,
+        }
+    }
+}
+
+impl PublicJwk {
+    // verifies a signature made by this key with a given JWS algorithm.
+    fn verify(&self, alg: &str, message: &[u8], signature: &[u8]) -> Result<(), Error> {
+        let decode = |c: &Option<String>| c.as_deref()
+            .and_then(|c| base64url.decode(c).ok())
+            .ok_or(Error::Token("malformed JWK"));
+        match (alg, self.kty.as_str()) {
+            ("RS256", "RSA") => RsaPublicKeyComponents {
+                n: decode(&self.n)?,
+                e: decode(&self.e)?,
+            }
+                .verify(&RSA_PKCS1_2048_8192_SHA256, message, signature)
+                .or(Err(Error::Token("bad signature"))),
+            ("ES256", "EC") if self.crv.as_deref() == Some("P-256") => {
+                let mut public_key = vec![0x04];
+                public_key.extend(decode(&self.x)?);
+                public_key.extend(decode(&self.y)?);
+                UnparsedPublicKey::new(&ECDSA_P256_SHA256_FIXED, public_key)
+                    .verify(message, signature)
+                    .or(Err(Error::Token("bad signature")))
+            }
+            _ => Err(Error::Token("unsupported algorithm")),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct JwtHeader {
+    alg: String,
+    #[serde(default)]
+    kid: Option<String>,
+}
+
+// token whose signature has not been verified yet.
+struct ParsedToken<'a> {
+    signing_input: &'a str,
+    signature: Vec<u8>,
+    header: JwtHeader,
+    claims: Map<String, Value>,
+}
+
+impl<'a> ParsedToken<'a> {
+    fn parse(token: &'a str) -> Result<Self, Error> {
+        let (signing_input, signature) = token.rsplit_once('.')
+            .ok_or(Error::Token("malformed token"))?;
+        let (header, claims) = signing_input.split_once('.')
+            .ok_or(Error::Token("malformed token"))?;
+        Ok(Self {
+            signing_input,
+            signature: base64url.decode(signature)
+                .or(Err(Error::Token("malformed signature")))?,
+            header: decode_segment(header)?,
+            claims: decode_segment(claims)?,
+        })
+    }
+
+    fn issuer(&self) -> &str {
+        self.claims.get("iss").and_then(Value::as_str).unwrap_or("")
+    }
+
+    fn verify(
+        self,
+        issuer: &TrustedIssuer,
+        keys: &JwkSet,
+        now: i64,
+        clock_skew: i64,
+    ) -> Result<VerifiedToken, Error> {
+        let key = keys.find(self.header.kid.as_deref())
+            .ok_or(Error::Token("unknown key"))?;
+        key.verify(&self.header.alg, self.signing_input.as_bytes(), &self.signature)?;
+        if self.issuer() != issuer.issuer {
+            return Err(Error::Token("wrong issuer"));
+        }
+        if !issuer.audiences.is_empty() && !self.audiences().any(|aud| {
+            issuer.audiences.iter().any(|a| a == aud)
+        }) {
+            return Err(Error::Token("wrong audience"));
+        }
+        if issuer.token_use.as_deref().is_some_and(|token_use| {
+            self.claims.get("token_use").and_then(Value::as_str) != Some(token_use)
+        }) {
+            return Err(Error::Token("wrong token use"));
+        }
+        let exp = self.time_claim("exp")?.ok_or(Error::Token("missing exp"))?;
+        if exp + clock_skew <= now {
+            return Err(Error::Token("token expired"));
+        }
+        if self.time_claim("nbf")?.is_some_and(|nbf| nbf - clock_skew > now) {
+            return Err(Error::Token("token not yet valid"));
+        }
+        if self.time_claim("iat")?.is_some_and(|iat| iat - clock_skew > now) {
+            return Err(Error::Token("token issued in the future"));
+        }
+        let user_handle = ["cognito:username", "username", "sub"].iter()
+            .find_map(|name| self.claims.get(*name).and_then(Value::as_str))
+            .filter(|handle| !handle.is_empty())
+            .ok_or(Error::Token("missing user handle"))?
+            .to_string();
+        let groups = match self.claims.get("cognito:groups") {
+            Some(Value::Array(groups)) => groups.iter()
+                .filter_map(|g| g.as_str().map(Into::into))
+                .collect(),
+            _ => Vec::new(),
+        };
+        Ok(VerifiedToken {
+            issuer: issuer.issuer.clone(),
+            user_handle,
+            groups,
+            claims: self.claims,
+        })
+    }
+
+    // `aud` as a string or an array, and `client_id` of a Cognito access
+    // token, which has no `aud`.
+    fn audiences(&self) -> impl Iterator<Item = &str> {
+        let aud: Vec<&str> = match self.claims.get("aud") {
+            Some(Value::String(aud)) => vec![aud.as_str()],
+            Some(Value::Array(aud)) => aud.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        aud.into_iter()
+            .chain(self.claims.get("client_id").and_then(Value::as_str))
+    }
+
+    fn time_claim(&self, name: &'static str) -> Result<Option<i64>, Error> {
+        match self.claims.get(name) {
+            None => Ok(None),
+            Some(value) => value.as_i64()
+                .or_else(|| value.as_f64().map(|v| v as i64))
+                .map(Some)
+                .ok_or(Error::Token("malformed time claim")),
+        }
+    }
+}
+
+fn decode_segment<T>(segment: &str) -> Result<T, Error>
+where
+    T: serde::de::DeserializeOwned,
+{
+    let bytes = base64url.decode(segment)
+        .or(Err(Error::Token("malformed token")))?;
+    serde_json::from_slice(&bytes).or(Err(Error::Token("malformed token")))
+}
+
+// "access" unless "id" is specified.
+fn parse_token_use(value: Option<String>) -> Result<&'static str, Error> {
+    match value.as_deref() {
+        None | Some("access") => Ok(DEFAULT_TOKEN_USE),
+        Some("id") => Ok("id"),
+        Some(_) => Err(Error::BadEnvironmentVariable(
+            "USER_POOL_TOKEN_USE",
+            value.unwrap_or_default(),
+        )),
+    }
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(Into::into)
+        .collect()
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+// returns the value of an optional variable; an empty value is rejected.
+fn optional_var(name: &'static str) -> Result<Option<String>, Error> {
+    match config::var(name) {
+        Ok(value) if !value.is_empty() => Ok(Some(value)),
+        Ok(value) => Err(Error::BadEnvironmentVariable(name, value)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(value)) => Err(
+            Error::BadEnvironmentVariable(name, value.to_string_lossy().into()),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ring::{
+        rand::SystemRandom,
+        signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair},
+    };
+
+    use crate::refresh::DEFAULT_REFRESH_TOKEN_TTL;
+    use crate::token::{DEFAULT_TOKEN_TTL, TokenIssuer, TokenSettings};
+
+    const ISSUER: &str = "https://auth.example.com";
+
+    fn token_issuer() -> TokenIssuer {
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(
+            &ECDSA_P256_SHA256_FIXED_SIGNING,
+            &SystemRandom::new(),
+        ).unwrap();
+        TokenIssuer::from_pkcs8(
+            TokenSettings {
+                issuer: ISSUER.into(),
+                audience: Some("api".into()),
+                ttl: DEFAULT_TOKEN_TTL,
+                refresh_ttl: DEFAULT_REFRESH_TOKEN_TTL,
+            },
+            pkcs8.as_ref(),
+        ).unwrap()
+    }
+
+    fn trusted_issuer(audiences: &[&str]) -> TrustedIssuer {
+        TrustedIssuer {
+            issuer: ISSUER.into(),
+            jwks_url: format!("{}/jwks", ISSUER),
+            audiences: audiences.iter().map(|a| a.to_string()).collect(),
+            token_use: None,
+        }
+    }
+
+    // signs given claims with a new key, and returns the token and the JWKS.
+    fn sign(claims: Value) -> (String, JwkSet) {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+            .unwrap();
+        let key_pair = EcdsaKeyPair::from_pkcs8(
+            &ECDSA_P256_SHA256_FIXED_SIGNING,
+            pkcs8.as_ref(),
+            &rng,
+        ).unwrap();
+        let signing_input = format!(
+            "{}.{}",
+            base64url.encode(r#"{"alg":"ES256","kid":"k"}"#),
+            base64url.encode(claims.to_string()),
+        );
+        let signature = key_pair.sign(&rng, signing_input.as_bytes()).unwrap();
+        let (x, y) = key_pair.public_key().as_ref()[1..].split_at(32);
+        let jwks = JwkSet {
+            keys: vec![PublicJwk {
+                kty: "EC".into(),
+                kid: Some("k".into()),
+                n: None,
+                e: None,
+                crv: Some("P-256".into()),
+                x: Some(base64url.encode(x)),
+                y: Some(base64url.encode(y)),
+            }],
+        };
+        (format!("{}.{}", signing_input, base64url.encode(signature.as_ref())), jwks)
+    }
+
+    fn jwks_of(issuer: &TokenIssuer) -> JwkSet {
+        serde_json::from_value(serde_json::to_value(issuer.jwks()).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn verify_should_accept_self_issued_token() {
+        let issuer = token_issuer();
+        let issued = issuer.issue("AAAA", None).await.unwrap();
+        let verified = ParsedToken::parse(&issued.token).unwrap()
+            .verify(&trusted_issuer(&["api"]), &jwks_of(&issuer), now(), 0)
+            .unwrap();
+        assert_eq!(verified.issuer, ISSUER);
+        assert_eq!(verified.user_handle, "AAAA");
+        assert!(verified.groups.is_empty());
+        assert_eq!(verified.claims["aud"], "api");
+    }
+
+    #[tokio::test]
+    async fn verify_should_reject_wrong_audience_or_foreign_key() {
+        let issuer = token_issuer();
+        let issued = issuer.issue("AAAA", None).await.unwrap();
+        assert!(
+            ParsedToken::parse(&issued.token).unwrap()
+                .verify(&trusted_issuer(&["other"]), &jwks_of(&issuer), now(), 0)
+                .is_err(),
+        );
+        assert!(
+            ParsedToken::parse(&issued.token).unwrap()
+                .verify(&trusted_issuer(&[]), &jwks_of(&token_issuer()), now(), 0)
+                .is_err(),
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_should_tolerate_clock_skew() {
+        let issuer = token_issuer();
+        let issued = issuer.issue("AAAA", None).await.unwrap();
+        let verify = |now, skew| ParsedToken::parse(&issued.token).unwrap()
+            .verify(&trusted_issuer(&[]), &jwks_of(&issuer), now, skew);
+        assert!(verify(issued.expires_at, 0).is_err());
+        assert!(verify(issued.expires_at, 60).is_ok());
+        assert!(verify(issued.expires_at + 60, 60).is_err());
+        // issued in the future
+        let iat = issued.expires_at - DEFAULT_TOKEN_TTL;
+        assert!(verify(iat - 30, 0).is_err());
+        assert!(verify(iat - 30, 60).is_ok());
+    }
+
+    #[test]
+    fn verify_should_require_token_use() {
+        let issuer = TrustedIssuer {
+            token_use: Some("access".into()),
+            ..trusted_issuer(&["app"])
+        };
+        let (access_token, jwks) = sign(serde_json::json!({
+            "iss": ISSUER,
+            "client_id": "app",
+            "token_use": "access",
+            "username": "AAAA",
+            "exp": now() + 60,
+        }));
+        let verified = ParsedToken::parse(&access_token).unwrap()
+            .verify(&issuer, &jwks, now(), 0)
+            .unwrap();
+        assert_eq!(verified.user_handle, "AAAA");
+        let (id_token, jwks) = sign(serde_json::json!({
+            "iss": ISSUER,
+            "aud": "app",
+            "token_use": "id",
+            "cognito:username": "AAAA",
+            "exp": now() + 60,
+        }));
+        assert!(matches!(
+            ParsedToken::parse(&id_token).unwrap().verify(&issuer, &jwks, now(), 0),
+            Err(Error::Token("wrong token use")),
+        ));
+    }
+
+    #[test]
+    fn parse_token_use_should_default_to_access() {
+        assert_eq!(parse_token_use(None).unwrap(), "access");
+        assert_eq!(parse_token_use(Some("id".into())).unwrap(), "id");
+        assert!(parse_token_use(Some("refresh".into())).is_err());
+    }
+
+    #[test]
+    fn parsed_token_should_take_audience_from_aud_or_client_id() {
+        let token = |claims: Value| format!(
+            "{}.{}.AAAA",
+            base64url.encode(r#"{"alg":"RS256","kid":"k"}"#),
+            base64url.encode(claims.to_string()),
+        );
+        let access_token = token(serde_json::json!({ "client_id": "app" }));
+        let parsed = ParsedToken::parse(&access_token).unwrap();
+        assert_eq!(parsed.audiences().collect::<Vec<_>>(), vec!["app"]);
+        let id_token = token(serde_json::json!({ "aud": ["app", "other"] }));
+        let parsed = ParsedToken::parse(&id_token).unwrap();
+        assert_eq!(parsed.audiences().collect::<Vec<_>>(), vec!["app", "other"]);
+    }
+
+    #[test]
+    fn trusted_issuer_cognito_should_point_to_jwks_of_user_pool() {
+        let issuer = TrustedIssuer::cognito(
+            "ap-northeast-1",
+            "ap-northeast-1_abc",
+            vec!["app".into()],
+            "access",
+        );
+        assert_eq!(
+            issuer.issuer,
+            "https://cognito-idp.ap-northeast-1.amazonaws.com/ap-northeast-1_abc",
+        );
+        assert_eq!(
+            issuer.jwks_url,
+            "https://cognito-idp.ap-northeast-1.amazonaws.com/ap-northeast-1_abc/.well-known/jwks.json",
+        );
+        assert_eq!(issuer.token_use.as_deref(), Some("access"));
+    }
+}
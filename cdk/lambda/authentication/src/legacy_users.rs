@@ -0,0 +1,267 @@
+//! Lazy migration of users from a legacy user store.
+//!
+//! The `user-migration` binary is the user migration trigger of the Cognito
+//! user pool. When a user who does not exist in the pool signs in with a
+//! password, or requests a password reset, Cognito invokes the trigger, which
+//! looks up the user in the legacy user store with [`LegacyUserStore`]. A
+//! user found in the store is created in the pool with the attributes that
+//! the store responds, and the user goes on to register the first passkey
+//! with `upgrade/start` of the registration API.
+//!
+//! The legacy user store is an HTTP endpoint that receives a
+//! [`LegacyUserRequest`] as a JSON POST request and responds with a
+//! [`LegacyUser`] as JSON. The endpoint responds with 404 if the user does
+//! not exist or the password does not match; both are indistinguishable to
+//! the client. The request is signed in the `X-Legacy-Signature` header like
+//! a webhook if a secret is configured; see [`crate::webhooks`].
+//!
+//! Cognito creates the migrated user with the username given at sign-in,
+//! and `upgrade/start` accepts only users whose usernames are user handles
+//! ("base64url"-encoded UUIDs). The legacy user store therefore has to let
+//! users sign in with their user handles, which it may assign as part of the
+//! migration, and respond with the legacy username as `preferred_username`
+//! so that it becomes the username of the passkey.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+use std::env;
+use std::time::{Duration, SystemTime};
+use tracing::{error, info};
+
+use crate::config;
+use crate::error::Error;
+use crate::secrets::{SecretCache, load_secret_cache_ttl};
+use crate::webhooks::sign;
+
+/// Name of the header that carries the signature of a request to the legacy
+/// user store.
+pub const LEGACY_SIGNATURE_HEADER: &str = "X-Legacy-Signature";
+
+/// Trigger source of a sign-in with a password.
+pub const AUTHENTICATION_TRIGGER_SOURCE: &str = "UserMigration_Authentication";
+
+/// Trigger source of a password reset.
+pub const FORGOT_PASSWORD_TRIGGER_SOURCE: &str = "UserMigration_ForgotPassword";
+
+// timeout of a lookup, which keeps the sign-in responsive.
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(3);
+
+// attributes that Cognito does not let the trigger set.
+const RESERVED_ATTRIBUTES: &[&str] = &["sub", "username", "cognito:username"];
+
+/// What the legacy user store is asked about a user.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LegacyUserRequest<'a> {
+    /// Username given to Cognito.
+    pub username: &'a str,
+
+    /// Password to verify.
+    ///
+    /// Omitted for a password reset, where the store only tells whether the
+    /// user exists.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<&'a str>,
+
+    /// Trigger source of the Cognito event; e.g.,
+    /// "UserMigration_Authentication".
+    pub trigger_source: &'a str,
+}
+
+/// User found in the legacy user store.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LegacyUser {
+    /// Attributes of the Cognito user; e.g., `preferred_username`, `name`,
+    /// and `email`.
+    ///
+    /// Attributes that Cognito manages, like `sub`, are ignored.
+    #[serde(default)]
+    pub user_attributes: HashMap<String, String>,
+}
+
+/// Legacy user store configured with the environment variables.
+pub struct LegacyUserStore {
+    http: reqwest::Client,
+    url: reqwest::Url,
+    // secret ID and cache if the requests are signed
+    secret: Option<(String, SecretCache)>,
+}
+
+/// Loads the legacy user store.
+///
+/// You can specify the following environment variables:
+/// - `LEGACY_USER_STORE_URL`: URL of the HTTP endpoint of the legacy user
+///   store
+/// - `LEGACY_USER_STORE_SECRET_ID`: ID of the secret in Secrets Manager that
+///   signs the requests to `LEGACY_USER_STORE_URL`. Requests are not signed
+///   unless specified.
+///
+/// Returns `None` if `LEGACY_USER_STORE_URL` is not set.
+pub fn load_legacy_user_store(
+    secrets: aws_sdk_secretsmanager::Client,
+) -> Result<Option<LegacyUserStore>, Error> {
+    let Some(url) = load_var("LEGACY_USER_STORE_URL")? else {
+        return Ok(None);
+    };
+    let url = reqwest::Url::parse(&url)
+        .ok()
+        .filter(|u| u.scheme() == "https" || u.scheme() == "http")
+        .ok_or(Error::BadEnvironmentVariable("LEGACY_USER_STORE_URL", url))?;
+    let http = reqwest::Client::builder()
+        .timeout(LOOKUP_TIMEOUT)
+        .build()
+        .or(Err(Error::LegacyUserStore("failed to build HTTP client")))?;
+    let secret = match load_var("LEGACY_USER_STORE_SECRET_ID")? {
+        Some(secret_id) => Some((
+            secret_id,
+            SecretCache::new(secrets, load_secret_cache_ttl()?),
+        )),
+        None => None,
+    };
+    Ok(Some(LegacyUserStore { http, url, secret }))
+}
+
+// loads a non-empty environment variable.
+fn load_var(name: &'static str) -> Result<Option<String>, Error> {
+    match config::var(name) {
+        Ok(value) if value.is_empty() => Ok(None),
+        Ok(value) => Ok(Some(value)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(value)) => Err(
+            Error::BadEnvironmentVariable(name, value.to_string_lossy().into()),
+        ),
+    }
+}
+
+impl LegacyUserStore {
+    /// Looks up a user in the legacy user store.
+    ///
+    /// Returns `None` if the user does not exist or the password does not
+    /// match.
+    pub async fn find_user(
+        &self,
+        request: &LegacyUserRequest<'_>,
+    ) -> Result<Option<LegacyUser>, Error> {
+        let body = serde_json::to_string(request)
+            .or(Err(Error::LegacyUserStore("failed to serialize request")))?;
+        let mut req = self.http
+            .post(self.url.clone())
+            .header("Content-Type", "application/json");
+        if let Some((secret_id, secrets)) = self.secret.as_ref() {
+            let secret = secrets.get(secret_id).await?;
+            req = req.header(
+                LEGACY_SIGNATURE_HEADER,
+                sign(secret.as_bytes(), unix_time(), &body),
+            );
+        }
+        let res = req.body(body).send().await.map_err(|e| {
+            error!(?e, "calling legacy user store");
+            Error::LegacyUserStore("legacy user store unreachable")
+        })?;
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            info!("user not found in legacy user store");
+            return Ok(None);
+        }
+        let payload = res.error_for_status()
+            .map_err(|e| {
+                error!(?e, "legacy user store failed");
+                Error::LegacyUserStore("legacy user store failed")
+            })?
+            .bytes()
+            .await
+            .or(Err(Error::LegacyUserStore("failed to read legacy user")))?;
+        serde_json::from_slice(&payload).map(Some).map_err(|e| {
+            error!(?e, "parsing legacy user");
+            Error::LegacyUserStore("malformed legacy user")
+        })
+    }
+}
+
+/// Fills the response of a user migration event with a migrated user.
+///
+/// The user is confirmed, and Cognito sends no welcome message. Ignores the
+/// attributes that Cognito does not let the trigger set. Fails if the event
+/// is not an object.
+pub fn set_migration_response(event: &mut Value, user: &LegacyUser) -> Result<(), Error> {
+    let attributes: Map<String, Value> = user.user_attributes.iter()
+        .filter(|(name, _)| !RESERVED_ATTRIBUTES.contains(&name.as_str()))
+        .map(|(name, value)| (name.clone(), Value::String(value.clone())))
+        .collect();
+    let event = event.as_object_mut()
+        .ok_or(Error::Inconvertible("user migration event must be an object"))?;
+    event.insert("response".into(), json!({
+        "userAttributes": attributes,
+        "finalUserStatus": "CONFIRMED",
+        "messageAction": "SUPPRESS",
+    }));
+    Ok(())
+}
+
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_user_request_should_omit_password_of_password_reset() {
+        let request = LegacyUserRequest {
+            username: "AAAA",
+            password: None,
+            trigger_source: FORGOT_PASSWORD_TRIGGER_SOURCE,
+        };
+        assert_eq!(
+            serde_json::to_value(request).unwrap(),
+            json!({
+                "username": "AAAA",
+                "triggerSource": "UserMigration_ForgotPassword",
+            }),
+        );
+    }
+
+    #[test]
+    fn set_migration_response_should_confirm_user_without_reserved_attributes() {
+        let user: LegacyUser = serde_json::from_value(json!({
+            "userAttributes": {
+                "preferred_username": "alice",
+                "email": "alice@example.com",
+                "email_verified": "true",
+                "sub": "not-allowed",
+            },
+        })).unwrap();
+        let mut event = json!({
+            "version": "1",
+            "triggerSource": AUTHENTICATION_TRIGGER_SOURCE,
+            "userName": "AAAA",
+            "request": { "password": "secret" },
+            "response": {},
+        });
+        set_migration_response(&mut event, &user).unwrap();
+        assert_eq!(
+            event["response"],
+            json!({
+                "userAttributes": {
+                    "preferred_username": "alice",
+                    "email": "alice@example.com",
+                    "email_verified": "true",
+                },
+                "finalUserStatus": "CONFIRMED",
+                "messageAction": "SUPPRESS",
+            }),
+        );
+        assert_eq!(event["userName"], "AAAA");
+    }
+
+    #[test]
+    fn legacy_user_should_default_to_no_attributes() {
+        let user: LegacyUser = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(user, LegacyUser::default());
+    }
+}
@@ -2,6 +2,77 @@
 
 //! Library for Cognito triggers.
 
+pub mod account_export;
+pub mod android;
+pub mod api_error;
+pub mod audit;
+#[cfg(any(test, feature = "test-support"))]
+pub mod authenticator;
+pub mod authorizer;
+#[cfg(any(test, feature = "canary"))]
+pub mod canary;
+pub mod captcha;
+pub mod client_binding;
+pub mod config;
+pub mod content;
+pub mod correlation;
+pub mod credential_store;
+pub mod credentials;
+pub mod deletion;
+pub mod display_name;
+pub mod domain_events;
+pub mod email;
+pub mod enumeration;
 pub mod error;
 pub mod event;
+pub mod extensions;
+#[cfg(feature = "graphql")]
+pub mod graphql;
+pub mod health;
+pub mod hints;
+pub mod i18n;
+pub mod identity;
+pub mod items;
+pub mod jwt;
+pub mod legacy_users;
+pub mod lockout;
+pub mod mds;
+pub mod metrics;
+pub mod migration;
+#[cfg(feature = "openapi")]
+pub mod openapi;
+pub mod pagination;
 pub mod parameters;
+pub mod passkey;
+pub mod passkey_claims;
+pub mod payload;
+pub mod pii;
+pub mod policy;
+pub mod rate_limit;
+pub mod recovery;
+#[cfg(any(test, feature = "red-team"))]
+pub mod red_team;
+pub mod refresh;
+pub mod registration;
+pub mod retention;
+pub mod risk;
+pub mod rp_migration;
+pub mod routing;
+pub mod secrets;
+pub mod session_crypto;
+pub mod session_id;
+pub mod sessions;
+#[cfg(feature = "sql")]
+pub mod sql;
+pub mod step_up;
+pub mod store;
+pub mod telemetry;
+pub mod tenant;
+pub mod token;
+#[cfg(feature = "typescript")]
+pub mod typescript;
+pub mod username;
+pub mod users;
+pub mod warmer;
+pub mod webhooks;
+pub mod well_known;
@@ -0,0 +1,339 @@
+//! Lockout of credentials after consecutive failed authentications.
+//!
+//! Consecutive failed verifications of assertions are counted per credential
+//! in the lockout item of the credential in the credential table; see
+//! [`LockoutItem`]. A credential is locked for a while when the count reaches
+//! the threshold, and every subsequent lock doubles the duration up to the
+//! maximum. A successful authentication, or an administrator, unlocks the
+//! credential and resets the duration.
+//!
+//! The lockout is disabled unless `LOCKOUT_THRESHOLD` is configured; see
+//! [`load_lockout_policy`].
+
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
+use lambda_http::{Body, Response, http::StatusCode};
+use std::env;
+use tracing::{error, instrument};
+
+use crate::config;
+use crate::error::Error;
+use crate::items::{CredentialKey, LockoutItem};
+use crate::payload::ErrorResponseBody;
+
+/// Default duration of the first lock in seconds.
+pub const DEFAULT_LOCKOUT_DURATION: u64 = 60;
+
+/// Default maximum duration of a lock in seconds.
+pub const DEFAULT_MAX_LOCKOUT_DURATION: u64 = 3600;
+
+/// Error code of a locked credential.
+pub const CREDENTIAL_LOCKED: &str = "credential_locked";
+
+/// Lockout policy.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LockoutPolicy {
+    /// Number of consecutive failures that locks a credential.
+    pub threshold: u64,
+
+    /// Duration of the first lock in seconds.
+    pub duration: u64,
+
+    /// Maximum duration of a lock in seconds.
+    pub max_duration: u64,
+}
+
+impl LockoutPolicy {
+    /// Returns the duration of the `locks`-th lock since the last successful
+    /// authentication in seconds.
+    ///
+    /// The duration doubles on every lock up to the maximum.
+    pub fn lock_duration(&self, locks: u64) -> u64 {
+        let exponent = locks.saturating_sub(1).min(u32::MAX as u64) as u32;
+        self.duration
+            .saturating_mul(2u64.saturating_pow(exponent))
+            .min(self.max_duration)
+    }
+}
+
+impl LockoutItem {
+    /// Returns the seconds until the credential is unlocked if it is locked
+    /// at a given time.
+    pub fn retry_after(&self, now: i64) -> Option<u64> {
+        self.locked_until
+            .filter(|until| *until > now)
+            .map(|until| (until - now) as u64)
+    }
+}
+
+/// Loads the lockout policy from the environment variables.
+///
+/// - `LOCKOUT_THRESHOLD`: number of consecutive failures that locks a
+///   credential. "off" or unset disables the lockout.
+/// - `LOCKOUT_DURATION`: duration of the first lock in seconds;
+///   [`DEFAULT_LOCKOUT_DURATION`] by default.
+/// - `LOCKOUT_MAX_DURATION`: maximum duration of a lock in seconds;
+///   [`DEFAULT_MAX_LOCKOUT_DURATION`] by default.
+pub fn load_lockout_policy() -> Result<Option<LockoutPolicy>, Error> {
+    let Some(threshold) = load_positive("LOCKOUT_THRESHOLD", true)? else {
+        return Ok(None);
+    };
+    let duration = load_positive("LOCKOUT_DURATION", false)?
+        .unwrap_or(DEFAULT_LOCKOUT_DURATION);
+    let max_duration = load_positive("LOCKOUT_MAX_DURATION", false)?
+        .unwrap_or(DEFAULT_MAX_LOCKOUT_DURATION);
+    if max_duration < duration {
+        return Err(Error::BadEnvironmentVariable(
+            "LOCKOUT_MAX_DURATION",
+            max_duration.to_string(),
+        ));
+    }
+    Ok(Some(LockoutPolicy { threshold, duration, max_duration }))
+}
+
+// loads a positive number from an environment variable.
+//
+// "off" is `None` if `allow_off`.
+fn load_positive(name: &'static str, allow_off: bool) -> Result<Option<u64>, Error> {
+    match config::var(name) {
+        Ok(value) => parse_positive(&value, allow_off)
+            .ok_or(Error::BadEnvironmentVariable(name, value)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(value)) => Err(
+            Error::BadEnvironmentVariable(name, value.to_string_lossy().into()),
+        ),
+    }
+}
+
+fn parse_positive(value: &str, allow_off: bool) -> Option<Option<u64>> {
+    if allow_off && value == "off" {
+        return Some(None);
+    }
+    value.trim().parse().ok().filter(|n| *n > 0).map(Some)
+}
+
+/// Lockout of credentials in the credential table.
+#[derive(Clone, Debug)]
+pub struct CredentialLockout {
+    dynamodb: aws_sdk_dynamodb::Client,
+    table_name: String,
+    policy: LockoutPolicy,
+}
+
+/// Loads the lockout of credentials in a given credential table.
+///
+/// Returns `None` if the lockout is disabled. See [`load_lockout_policy`].
+pub fn load_credential_lockout(
+    dynamodb: aws_sdk_dynamodb::Client,
+    table_name: String,
+) -> Result<Option<CredentialLockout>, Error> {
+    Ok(load_lockout_policy()?
+        .map(|policy| CredentialLockout::new(dynamodb, table_name, policy)))
+}
+
+impl CredentialLockout {
+    /// Creates a lockout of credentials in a given credential table.
+    pub fn new(
+        dynamodb: aws_sdk_dynamodb::Client,
+        table_name: String,
+        policy: LockoutPolicy,
+    ) -> Self {
+        Self { dynamodb, table_name, policy }
+    }
+
+    /// Lockout policy.
+    pub fn policy(&self) -> &LockoutPolicy {
+        &self.policy
+    }
+
+    /// Obtains the lockout state of a credential.
+    ///
+    /// Returns `None` if the credential has never failed since the last
+    /// successful authentication.
+    pub async fn get(&self, key: CredentialKey<'_>) -> Result<Option<LockoutItem>, Error> {
+        self.dynamodb
+            .get_item()
+            .table_name(self.table_name.clone())
+            .set_key(Some(key.lockout_key()))
+            .consistent_read(true)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "getting lockout");
+                Error::Storage("failed to get lockout")
+            })?
+            .item
+            .map(|item| LockoutItem::from_item(&item))
+            .transpose()
+    }
+
+    /// Records a failed authentication with a credential.
+    ///
+    /// The caller should make sure that the credential exists so that
+    /// arbitrary credential IDs do not leave lockout items.
+    ///
+    /// Returns the duration of the lock in seconds if this failure locks the
+    /// credential.
+    #[instrument(skip_all)]
+    pub async fn record_failure(
+        &self,
+        key: CredentialKey<'_>,
+        now: i64,
+    ) -> Result<Option<u64>, Error> {
+        let state = self.dynamodb
+            .update_item()
+            .table_name(self.table_name.clone())
+            .set_key(Some(key.lockout_key()))
+            .update_expression("ADD failures :one")
+            .expression_attribute_values(":one", AttributeValue::N("1".into()))
+            .return_values(ReturnValue::AllNew)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "counting failure");
+                Error::Storage("failed to count failure")
+            })?
+            .attributes
+            .map(|item| LockoutItem::from_item(&item))
+            .transpose()?
+            .ok_or(Error::Storage("missing lockout"))?;
+        if state.failures < self.policy.threshold {
+            return Ok(None);
+        }
+        // concurrent failures may reach the threshold at the same time, and
+        // only the first one locks the credential
+        let duration = self.policy.lock_duration(state.locks + 1);
+        let res = self.dynamodb
+            .update_item()
+            .table_name(self.table_name.clone())
+            .set_key(Some(key.lockout_key()))
+            .update_expression("SET failures = :zero, lockedUntil = :until ADD locks :one")
+            .condition_expression("failures >= :threshold")
+            .expression_attribute_values(":zero", AttributeValue::N("0".into()))
+            .expression_attribute_values(":one", AttributeValue::N("1".into()))
+            .expression_attribute_values(
+                ":until",
+                AttributeValue::N((now + duration as i64).to_string()),
+            )
+            .expression_attribute_values(
+                ":threshold",
+                AttributeValue::N(self.policy.threshold.to_string()),
+            )
+            .return_values(ReturnValue::None)
+            .send()
+            .await;
+        match res {
+            Ok(_) => Ok(Some(duration)),
+            Err(e) if e.as_service_error()
+                .is_some_and(|e| e.is_conditional_check_failed_exception()) =>
+            {
+                Ok(None)
+            }
+            Err(e) => {
+                error!(?e, "locking credential");
+                Err(Error::Storage("failed to lock credential"))
+            }
+        }
+    }
+
+    /// Resets the lockout state of a credential after a successful
+    /// authentication.
+    pub async fn reset(&self, key: CredentialKey<'_>) -> Result<(), Error> {
+        unlock_credential(&self.dynamodb, &self.table_name, key).await?;
+        Ok(())
+    }
+}
+
+/// Unlocks a credential and resets its lockout state.
+///
+/// Returns `false` if the credential has no lockout state.
+pub async fn unlock_credential(
+    dynamodb: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    key: CredentialKey<'_>,
+) -> Result<bool, Error> {
+    let res = dynamodb
+        .delete_item()
+        .table_name(table_name)
+        .set_key(Some(key.lockout_key()))
+        .condition_expression("attribute_exists(pk)")
+        .return_values(ReturnValue::None)
+        .send()
+        .await;
+    match res {
+        Ok(_) => Ok(true),
+        Err(e) if e.as_service_error()
+            .is_some_and(|e| e.is_conditional_check_failed_exception()) =>
+        {
+            Ok(false)
+        }
+        Err(e) => {
+            error!(?e, "unlocking credential");
+            Err(Error::Storage("failed to unlock credential"))
+        }
+    }
+}
+
+/// Creates a 423 response with `Retry-After` for a locked credential.
+pub fn credential_locked(
+    retry_after: u64,
+) -> Result<Response<Body>, lambda_http::Error> {
+    let body = serde_json::to_string(&ErrorResponseBody {
+        error: CREDENTIAL_LOCKED,
+        message: format!("credential locked; retry after {} seconds", retry_after),
+        field: None,
+        localized_message: None,
+        request_id: None,
+    })?;
+    Ok(Response::builder()
+        .status(StatusCode::LOCKED)
+        .header("Content-Type", "application/json")
+        .header("Retry-After", retry_after.to_string())
+        .body(body.into())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_positive_should_parse_number_or_off() {
+        assert_eq!(parse_positive("5", true), Some(Some(5)));
+        assert_eq!(parse_positive("off", true), Some(None));
+        assert_eq!(parse_positive("off", false), None);
+        assert_eq!(parse_positive("0", true), None);
+        assert_eq!(parse_positive("five", true), None);
+    }
+
+    #[test]
+    fn lock_duration_should_double_up_to_max() {
+        let policy = LockoutPolicy {
+            threshold: 5,
+            duration: 60,
+            max_duration: 3600,
+        };
+        assert_eq!(policy.lock_duration(1), 60);
+        assert_eq!(policy.lock_duration(2), 120);
+        assert_eq!(policy.lock_duration(6), 1920);
+        assert_eq!(policy.lock_duration(7), 3600);
+        assert_eq!(policy.lock_duration(100), 3600);
+    }
+
+    #[test]
+    fn lockout_item_should_tell_retry_after_while_locked() {
+        let locked = LockoutItem {
+            failures: 0,
+            locks: 1,
+            locked_until: Some(160),
+        };
+        assert_eq!(locked.retry_after(100), Some(60));
+        assert_eq!(locked.retry_after(160), None);
+        assert_eq!(LockoutItem::default().retry_after(100), None);
+    }
+
+    #[test]
+    fn credential_locked_should_tell_retry_after() {
+        let res = credential_locked(30).unwrap();
+        assert_eq!(res.status(), StatusCode::LOCKED);
+        assert_eq!(res.headers()["Retry-After"], "30");
+    }
+}
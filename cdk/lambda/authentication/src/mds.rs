@@ -0,0 +1,652 @@
+//! FIDO Metadata Service (MDS).
+//!
+//! The [FIDO Metadata Service](https://fidoalliance.org/metadata/) publishes
+//! a BLOB, a JWT signed with a certificate chained to the root certificate of
+//! the FIDO Alliance, which lists the metadata statements and the status
+//! reports of certified authenticators.
+//!
+//! The `mds-refresh` function periodically downloads the BLOB, verifies it
+//! with [`verify_blob`], and stores the entries in a DynamoDB table with
+//! [`MetadataDirectory::replace_entries`]. The registration function looks up
+//! the AAGUID of a new credential with [`MetadataDirectory::get`] to name the
+//! authenticator and to reject one whose attestation is no longer trusted;
+//! see [`AuthenticatorStatus::is_compromised`].
+//!
+//! ## Items in the metadata table
+//!
+//! - `aaguid`: (partition key) AAGUID of the authenticator in the hyphenated
+//!   lowercase form
+//! - `description`: (optional) description of the authenticator in English
+//! - `status`: (optional) latest status of the authenticator; e.g.,
+//!   "FIDO_CERTIFIED_L1"
+//! - `effectiveDate`: (optional) "<yyyy-mm-dd>" since when `status` has been
+//!   effective
+//! - `blobNumber`: serial number of the BLOB that provided the entry
+//!
+//! The item whose `aaguid` is [`BLOB_KEY`] records the BLOB last stored:
+//! - `blobNumber`: serial number of the BLOB
+//! - `nextUpdate`: "<yyyy-mm-dd>" when the next BLOB is published
+//! - `updatedAt`: when the BLOB was stored
+//!
+//! Entries are never removed, because the BLOB keeps listing revoked
+//! authenticators.
+
+use aws_sdk_dynamodb::types::{AttributeValue, PutRequest, WriteRequest};
+use base64::{
+    Engine as _,
+    engine::general_purpose::{
+        STANDARD as base64,
+        URL_SAFE_NO_PAD as base64url,
+    },
+};
+use rustls_pki_types::{CertificateDer, SignatureVerificationAlgorithm, UnixTime};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{error, warn};
+use webauthn_rs::prelude::Uuid;
+use webpki::{EndEntityCert, KeyUsage};
+
+use crate::config;
+use crate::error::Error;
+
+/// Default URL of the BLOB.
+pub const DEFAULT_MDS_URL: &str = "https://mds3.fidoalliance.org/";
+
+/// Key of the item that records the BLOB last stored.
+pub const BLOB_KEY: &str = "#blob";
+
+// Maximum number of items in a single `BatchWriteItem` request.
+const MAX_BATCH_SIZE: usize = 25;
+
+// Maximum number of attempts to write unprocessed items.
+const MAX_BATCH_ATTEMPTS: usize = 5;
+
+// Signature algorithms allowed in the certificate chain.
+static CHAIN_ALGORITHMS: &[&dyn SignatureVerificationAlgorithm] = &[
+    webpki::ring::ECDSA_P256_SHA256,
+    webpki::ring::ECDSA_P384_SHA384,
+    webpki::ring::RSA_PKCS1_2048_8192_SHA256,
+    webpki::ring::RSA_PKCS1_2048_8192_SHA384,
+    webpki::ring::RSA_PKCS1_2048_8192_SHA512,
+];
+
+/// Payload of the BLOB.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetadataBlob {
+    /// Serial number; increases with every BLOB.
+    pub no: u64,
+
+    /// "<yyyy-mm-dd>" when the next BLOB is published.
+    pub next_update: String,
+
+    /// Entries of authenticators.
+    pub entries: Vec<MetadataEntry>,
+}
+
+/// Entry of an authenticator in the BLOB.
+///
+/// Only the properties this library uses are parsed.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetadataEntry {
+    /// AAGUID of a FIDO2 authenticator.
+    ///
+    /// `None` for U2F and UAF authenticators.
+    pub aaguid: Option<String>,
+
+    /// Metadata statement.
+    pub metadata_statement: Option<MetadataStatement>,
+
+    /// Status reports.
+    #[serde(default)]
+    pub status_reports: Vec<StatusReport>,
+}
+
+/// Metadata statement of an authenticator.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MetadataStatement {
+    /// Description in English.
+    pub description: Option<String>,
+}
+
+/// Status report of an authenticator.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusReport {
+    /// Status; e.g., "FIDO_CERTIFIED_L1" or "REVOKED".
+    pub status: AuthenticatorStatus,
+
+    /// "<yyyy-mm-dd>" since when the status has been effective.
+    pub effective_date: Option<String>,
+}
+
+/// Status of an authenticator.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(transparent)]
+pub struct AuthenticatorStatus(pub String);
+
+impl AuthenticatorStatus {
+    /// Returns whether the status tells that the attestation of the
+    /// authenticator is no longer trustworthy.
+    pub fn is_compromised(&self) -> bool {
+        matches!(
+            self.0.as_str(),
+            "REVOKED"
+                | "USER_VERIFICATION_BYPASS"
+                | "ATTESTATION_KEY_COMPROMISE"
+                | "USER_KEY_REMOTE_COMPROMISE"
+                | "USER_KEY_PHYSICAL_COMPROMISE",
+        )
+    }
+}
+
+impl MetadataEntry {
+    /// Returns the latest status report.
+    ///
+    /// The reports are ordered by their effective dates; a report without
+    /// one comes first.
+    pub fn latest_status(&self) -> Option<&StatusReport> {
+        self.status_reports
+            .iter()
+            .enumerate()
+            .max_by(|(i, a), (j, b)| {
+                a.effective_date.cmp(&b.effective_date).then(i.cmp(j))
+            })
+            .map(|(_, report)| report)
+    }
+}
+
+/// Metadata of an authenticator looked up by the AAGUID.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AuthenticatorMetadata {
+    /// AAGUID in the hyphenated lowercase form.
+    pub aaguid: String,
+
+    /// Description in English.
+    pub description: Option<String>,
+
+    /// Latest status.
+    pub status: Option<AuthenticatorStatus>,
+}
+
+impl AuthenticatorMetadata {
+    /// Metadata of an authenticator not listed in the BLOB.
+    pub fn unlisted(aaguid: String) -> Self {
+        Self {
+            aaguid,
+            description: None,
+            status: None,
+        }
+    }
+
+    /// Returns whether the authenticator is listed with a compromised status.
+    pub fn is_compromised(&self) -> bool {
+        self.status.as_ref().is_some_and(AuthenticatorStatus::is_compromised)
+    }
+
+    fn from_item(item: &HashMap<String, AttributeValue>) -> Result<Self, Error> {
+        let get_s = |name: &'static str| item.get(name)
+            .map(|v| v.as_s()
+                .cloned()
+                .or(Err(Error::BadItemAttribute(name))))
+            .transpose();
+        Ok(Self {
+            aaguid: get_s("aaguid")?.ok_or(Error::BadItemAttribute("aaguid"))?,
+            description: get_s("description")?,
+            status: get_s("status")?.map(AuthenticatorStatus),
+        })
+    }
+}
+
+/// Verifies a BLOB and parses the payload.
+///
+/// The BLOB must be signed with the leaf certificate in the `x5c` header,
+/// and the chain must be valid at `now` up to `root_certificate`, a DER
+/// encoded certificate of the FIDO Alliance. RS256 and ES256 signatures are
+/// accepted. Revocation of the certificates is not checked.
+pub fn verify_blob(
+    blob: &str,
+    root_certificate: &[u8],
+    now: SystemTime,
+) -> Result<MetadataBlob, Error> {
+    #[derive(Deserialize)]
+    struct Header {
+        alg: String,
+        x5c: Vec<String>,
+    }
+
+    let (signed, signature) = blob.trim()
+        .rsplit_once('.')
+        .ok_or(Error::Metadata("BLOB is not a JWT"))?;
+    let (header, payload) = signed.split_once('.')
+        .filter(|(_, payload)| !payload.contains('.'))
+        .ok_or(Error::Metadata("BLOB is not a JWT"))?;
+    let decode = |part: &str| base64url.decode(part)
+        .or(Err(Error::Metadata("malformed base64url in BLOB")));
+    let header: Header = serde_json::from_slice(&decode(header)?)
+        .or(Err(Error::Metadata("malformed BLOB header")))?;
+    let signature = decode(signature)?;
+    let certificates = header.x5c
+        .iter()
+        .map(|c| base64.decode(c)
+            .map(CertificateDer::from)
+            .or(Err(Error::Metadata("malformed x5c certificate"))))
+        .collect::<Result<Vec<_>, _>>()?;
+    let (leaf, intermediates) = certificates.split_first()
+        .ok_or(Error::Metadata("missing x5c certificate"))?;
+
+    // verifies the certificate chain
+    let root = CertificateDer::from(root_certificate);
+    let trust_anchor = webpki::anchor_from_trusted_cert(&root)
+        .map_err(|e| {
+            error!(?e, "parsing MDS root certificate");
+            Error::Metadata("malformed root certificate")
+        })?;
+    let leaf = EndEntityCert::try_from(leaf)
+        .map_err(|e| {
+            error!(?e, "parsing MDS signing certificate");
+            Error::Metadata("malformed signing certificate")
+        })?;
+    let now = UnixTime::since_unix_epoch(
+        now.duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO),
+    );
+    leaf.verify_for_usage(
+        CHAIN_ALGORITHMS,
+        &[trust_anchor],
+        intermediates,
+        now,
+        KeyUsage::server_auth(),
+        None,
+        None,
+    ).map_err(|e| {
+        error!(?e, "verifying MDS certificate chain");
+        Error::Metadata("untrusted signing certificate")
+    })?;
+
+    // verifies the signature
+    let (algorithm, signature) = match header.alg.as_str() {
+        "RS256" => (webpki::ring::RSA_PKCS1_2048_8192_SHA256, signature),
+        "ES256" => (
+            webpki::ring::ECDSA_P256_SHA256,
+            ecdsa_signature_to_der(&signature)
+                .ok_or(Error::Metadata("malformed ES256 signature"))?,
+        ),
+        _ => return Err(Error::Metadata("unsupported BLOB algorithm")),
+    };
+    leaf.verify_signature(algorithm, signed.as_bytes(), &signature)
+        .map_err(|e| {
+            error!(?e, "verifying MDS signature");
+            Error::Metadata("bad BLOB signature")
+        })?;
+
+    serde_json::from_slice(&decode(payload)?)
+        .map_err(|e| {
+            error!(?e, "parsing MDS payload");
+            Error::Metadata("malformed BLOB payload")
+        })
+}
+
+/// Parses a certificate in PEM or in base64-encoded DER.
+pub fn parse_certificate(certificate: &str) -> Result<Vec<u8>, Error> {
+    let encoded: String = certificate
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    base64.decode(encoded).or(Err(Error::Metadata("malformed certificate")))
+}
+
+/// Extracts the AAGUID from an attestation object.
+///
+/// Returns `None` if the attestation object conveys no AAGUID, or the AAGUID
+/// is all zeros as it is when the client hides it.
+pub fn aaguid_of_attestation_object(attestation_object: &[u8]) -> Option<Uuid> {
+    // flags (1 byte) follows the RP ID hash (32 bytes), and the AAGUID (16
+    // bytes) follows the signature counter (4 bytes)
+    const FLAGS_OFFSET: usize = 32;
+    const AAGUID_OFFSET: usize = 37;
+    const ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+    let value: ciborium::Value = ciborium::from_reader(attestation_object).ok()?;
+    let auth_data = value.as_map()?
+        .iter()
+        .find(|(k, _)| k.as_text() == Some("authData"))?
+        .1
+        .as_bytes()?;
+    if auth_data.get(FLAGS_OFFSET)? & ATTESTED_CREDENTIAL_DATA == 0 {
+        return None;
+    }
+    let aaguid = Uuid::from_slice(auth_data.get(AAGUID_OFFSET..AAGUID_OFFSET + 16)?).ok()?;
+    (!aaguid.is_nil()).then_some(aaguid)
+}
+
+/// Metadata of authenticators stored in a DynamoDB table.
+#[derive(Clone, Debug)]
+pub struct MetadataDirectory {
+    dynamodb: aws_sdk_dynamodb::Client,
+    table_name: String,
+}
+
+/// Loads the metadata directory.
+///
+/// You can specify to `METADATA_TABLE_NAME` environment variable the name of
+/// the DynamoDB table that stores the entries of the BLOB.
+///
+/// Returns `None` if `METADATA_TABLE_NAME` is not set, which means
+/// authenticators are neither named nor checked.
+pub fn load_metadata_directory(
+    dynamodb: aws_sdk_dynamodb::Client,
+) -> Result<Option<MetadataDirectory>, Error> {
+    match config::var("METADATA_TABLE_NAME") {
+        Ok(table_name) if !table_name.is_empty() => Ok(Some(
+            MetadataDirectory::new(dynamodb, table_name),
+        )),
+        Ok(table_name) => Err(
+            Error::BadEnvironmentVariable("METADATA_TABLE_NAME", table_name),
+        ),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(table_name)) => Err(
+            Error::BadEnvironmentVariable(
+                "METADATA_TABLE_NAME",
+                table_name.to_string_lossy().into(),
+            ),
+        ),
+    }
+}
+
+impl MetadataDirectory {
+    /// Creates a directory on a given table.
+    pub fn new(dynamodb: aws_sdk_dynamodb::Client, table_name: String) -> Self {
+        Self { dynamodb, table_name }
+    }
+
+    /// Looks up the metadata of an authenticator.
+    ///
+    /// Returns `None` if the authenticator is not listed.
+    pub async fn get(&self, aaguid: &Uuid) -> Result<Option<AuthenticatorMetadata>, Error> {
+        let res = self.dynamodb
+            .get_item()
+            .table_name(self.table_name.clone())
+            .key("aaguid", AttributeValue::S(aaguid.to_string()))
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "getting authenticator metadata");
+                Error::Storage("failed to get authenticator metadata")
+            })?;
+        res.item.as_ref().map(AuthenticatorMetadata::from_item).transpose()
+    }
+
+    /// Returns the serial number of the BLOB last stored.
+    pub async fn blob_number(&self) -> Result<Option<u64>, Error> {
+        let res = self.dynamodb
+            .get_item()
+            .table_name(self.table_name.clone())
+            .key("aaguid", AttributeValue::S(BLOB_KEY.into()))
+            .consistent_read(true)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "getting MDS BLOB number");
+                Error::Storage("failed to get MDS BLOB number")
+            })?;
+        res.item
+            .as_ref()
+            .and_then(|item| item.get("blobNumber"))
+            .map(|n| n.as_n()
+                .ok()
+                .and_then(|n| n.parse().ok())
+                .ok_or(Error::BadItemAttribute("blobNumber")))
+            .transpose()
+    }
+
+    /// Stores the entries of a BLOB.
+    ///
+    /// Entries without AAGUIDs are skipped. The BLOB is recorded after all
+    /// the entries have been stored, so a failed attempt is retried from the
+    /// start with the same BLOB.
+    pub async fn replace_entries(
+        &self,
+        blob: &MetadataBlob,
+        updated_at: String,
+    ) -> Result<usize, Error> {
+        let items = entry_items(blob);
+        let count = items.len();
+        for chunk in items.chunks(MAX_BATCH_SIZE) {
+            let mut requests: Vec<WriteRequest> = chunk.iter()
+                .map(|item| Ok(WriteRequest::builder()
+                    .put_request(PutRequest::builder()
+                        .set_item(Some(item.clone()))
+                        .build()
+                        .map_err(|e| {
+                            error!(?e, "building put request");
+                            Error::Storage("failed to build put request")
+                        })?)
+                    .build()))
+                .collect::<Result<_, Error>>()?;
+            for attempt in 1.. {
+                let res = self.dynamodb
+                    .batch_write_item()
+                    .request_items(self.table_name.clone(), requests)
+                    .send()
+                    .await
+                    .map_err(|e| {
+                        error!(?e, "storing authenticator metadata");
+                        Error::Storage("failed to store authenticator metadata")
+                    })?;
+                requests = res.unprocessed_items
+                    .and_then(|mut items| items.remove(&self.table_name))
+                    .unwrap_or_default();
+                if requests.is_empty() {
+                    break;
+                }
+                if attempt >= MAX_BATCH_ATTEMPTS {
+                    return Err(Error::Storage("too many unprocessed metadata items"));
+                }
+                warn!("retrying {} unprocessed metadata items", requests.len());
+                tokio::time::sleep(Duration::from_millis(100 << attempt)).await;
+            }
+        }
+        self.dynamodb
+            .put_item()
+            .table_name(self.table_name.clone())
+            .item("aaguid", AttributeValue::S(BLOB_KEY.into()))
+            .item("blobNumber", AttributeValue::N(blob.no.to_string()))
+            .item("nextUpdate", AttributeValue::S(blob.next_update.clone()))
+            .item("updatedAt", AttributeValue::S(updated_at))
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "recording MDS BLOB");
+                Error::Storage("failed to record MDS BLOB")
+            })?;
+        Ok(count)
+    }
+}
+
+// builds the items of the entries with AAGUIDs in a BLOB.
+fn entry_items(blob: &MetadataBlob) -> Vec<HashMap<String, AttributeValue>> {
+    blob.entries
+        .iter()
+        .filter_map(|entry| {
+            let aaguid = entry.aaguid.as_ref()?.to_lowercase();
+            let mut item = HashMap::from([
+                ("aaguid".to_string(), AttributeValue::S(aaguid)),
+                ("blobNumber".to_string(), AttributeValue::N(blob.no.to_string())),
+            ]);
+            if let Some(description) = entry.metadata_statement
+                .as_ref()
+                .and_then(|s| s.description.clone())
+            {
+                item.insert("description".into(), AttributeValue::S(description));
+            }
+            if let Some(report) = entry.latest_status() {
+                item.insert("status".into(), AttributeValue::S(report.status.0.clone()));
+                if let Some(date) = report.effective_date.clone() {
+                    item.insert("effectiveDate".into(), AttributeValue::S(date));
+                }
+            }
+            Some(item)
+        })
+        .collect()
+}
+
+// converts a fixed-length ECDSA P-256 signature (r || s) used in JWS into the
+// ASN.1 DER form.
+fn ecdsa_signature_to_der(signature: &[u8]) -> Option<Vec<u8>> {
+    if signature.len() != 64 {
+        return None;
+    }
+    let (r, s) = signature.split_at(32);
+    let (r, s) = (der_integer(r), der_integer(s));
+    let mut der = vec![0x30, (r.len() + s.len()) as u8];
+    der.extend(r);
+    der.extend(s);
+    Some(der)
+}
+
+// encodes an unsigned big-endian integer as an ASN.1 DER INTEGER.
+fn der_integer(bytes: &[u8]) -> Vec<u8> {
+    let start = bytes.iter().position(|b| *b != 0).unwrap_or(bytes.len() - 1);
+    let bytes = &bytes[start..];
+    let pad = bytes[0] & 0x80 != 0;
+    let mut der = vec![0x02, (bytes.len() + pad as usize) as u8];
+    if pad {
+        der.push(0);
+    }
+    der.extend_from_slice(bytes);
+    der
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_blob_should_parse_payload() {
+        let blob: MetadataBlob = serde_json::from_value(serde_json::json!({
+            "legalHeader": "Retrieval and use of this BLOB indicates acceptance...",
+            "no": 42,
+            "nextUpdate": "2026-11-01",
+            "entries": [
+                {
+                    "aaguid": "cb69481e-8ff7-4039-93ec-0a2729a154a8",
+                    "metadataStatement": {
+                        "description": "YubiKey 5 Series",
+                        "attestationRootCertificates": ["MIIB..."],
+                    },
+                    "statusReports": [
+                        { "status": "FIDO_CERTIFIED", "effectiveDate": "2020-05-12" },
+                        { "status": "FIDO_CERTIFIED_L1", "effectiveDate": "2020-05-12" },
+                    ],
+                    "timeOfLastStatusChange": "2020-05-12",
+                },
+                {
+                    "attestationCertificateKeyIdentifiers": ["923881fe2f214ee465484371aeb72e97f5a58e0a"],
+                    "statusReports": [],
+                },
+            ],
+        })).unwrap();
+        assert_eq!(blob.no, 42);
+        assert_eq!(blob.entries.len(), 2);
+        let items = entry_items(&blob);
+        assert_eq!(items.len(), 1);
+        assert_eq!(
+            items[0]["description"],
+            AttributeValue::S("YubiKey 5 Series".into()),
+        );
+        assert_eq!(items[0]["status"], AttributeValue::S("FIDO_CERTIFIED_L1".into()));
+        assert_eq!(items[0]["blobNumber"], AttributeValue::N("42".into()));
+    }
+
+    #[test]
+    fn latest_status_should_prefer_latest_effective_date() {
+        let entry: MetadataEntry = serde_json::from_value(serde_json::json!({
+            "aaguid": "00000000-0000-0000-0000-000000000001",
+            "statusReports": [
+                { "status": "REVOKED", "effectiveDate": "2024-01-01" },
+                { "status": "FIDO_CERTIFIED", "effectiveDate": "2021-01-01" },
+            ],
+        })).unwrap();
+        let status = &entry.latest_status().unwrap().status;
+        assert_eq!(status.0, "REVOKED");
+        assert!(status.is_compromised());
+        assert!(!AuthenticatorStatus("FIDO_CERTIFIED_L2".into()).is_compromised());
+        assert!(!AuthenticatorStatus("UPDATE_AVAILABLE".into()).is_compromised());
+    }
+
+    #[test]
+    fn verify_blob_should_reject_non_jwt() {
+        assert!(verify_blob("not a JWT", &[], SystemTime::now()).is_err());
+        assert!(verify_blob("a.b.c.d", &[], SystemTime::now()).is_err());
+    }
+
+    #[test]
+    fn verify_blob_should_reject_untrusted_certificate() {
+        let header = base64url.encode(serde_json::json!({
+            "alg": "RS256",
+            "typ": "JWT",
+            "x5c": [base64.encode(b"not a certificate")],
+        }).to_string());
+        let payload = base64url.encode(r#"{"no":1,"nextUpdate":"2026-11-01","entries":[]}"#);
+        let blob = format!("{}.{}.{}", header, payload, base64url.encode(b"signature"));
+        assert!(verify_blob(&blob, b"not a root", SystemTime::now()).is_err());
+    }
+
+    #[test]
+    fn parse_certificate_should_accept_pem() {
+        let pem = "-----BEGIN CERTIFICATE-----\nAAEC\nAwQ=\n-----END CERTIFICATE-----\n";
+        assert_eq!(parse_certificate(pem).unwrap(), vec![0, 1, 2, 3, 4]);
+        assert_eq!(parse_certificate("AAECAwQ=").unwrap(), vec![0, 1, 2, 3, 4]);
+        assert!(parse_certificate("-----BEGIN CERTIFICATE-----\n!!").is_err());
+    }
+
+    #[test]
+    fn aaguid_of_attestation_object_should_extract_aaguid() {
+        let aaguid = Uuid::parse_str("cb69481e-8ff7-4039-93ec-0a2729a154a8").unwrap();
+        let attestation_object = |flags: u8, aaguid: &[u8; 16]| {
+            let mut auth_data = vec![0u8; 32];
+            auth_data.push(flags);
+            auth_data.extend_from_slice(&[0, 0, 0, 1]);
+            auth_data.extend_from_slice(aaguid);
+            auth_data.extend_from_slice(&[0, 0]);
+            let value = ciborium::Value::Map(vec![
+                (ciborium::Value::Text("fmt".into()), ciborium::Value::Text("none".into())),
+                (ciborium::Value::Text("attStmt".into()), ciborium::Value::Map(vec![])),
+                (ciborium::Value::Text("authData".into()), ciborium::Value::Bytes(auth_data)),
+            ]);
+            let mut cbor = Vec::new();
+            ciborium::into_writer(&value, &mut cbor).unwrap();
+            cbor
+        };
+        assert_eq!(
+            aaguid_of_attestation_object(&attestation_object(0x45, aaguid.as_bytes())),
+            Some(aaguid),
+        );
+        assert_eq!(
+            aaguid_of_attestation_object(&attestation_object(0x45, &[0u8; 16])),
+            None,
+        );
+        assert_eq!(
+            aaguid_of_attestation_object(&attestation_object(0x05, aaguid.as_bytes())),
+            None,
+        );
+        assert_eq!(aaguid_of_attestation_object(b"not CBOR"), None);
+    }
+
+    #[test]
+    fn ecdsa_signature_to_der_should_encode_integers() {
+        let mut signature = [0u8; 64];
+        signature[31] = 0x01;
+        signature[32] = 0x80;
+        let der = ecdsa_signature_to_der(&signature).unwrap();
+        let mut expected = vec![0x30, 0x26, 0x02, 0x01, 0x01, 0x02, 0x21, 0x00, 0x80];
+        expected.extend_from_slice(&[0u8; 31]);
+        assert_eq!(der, expected);
+        assert!(ecdsa_signature_to_der(&[0u8; 63]).is_none());
+    }
+}
@@ -0,0 +1,213 @@
+//! Metrics in the CloudWatch embedded metric format (EMF).
+//!
+//! Every metric is written to the standard output as a JSON log line, and
+//! CloudWatch Logs extracts it into a CloudWatch metric.
+//!
+//! See <https://docs.aws.amazon.com/AmazonCloudWatch/latest/monitoring/CloudWatch_Embedded_Metric_Format_Specification.html>
+//! for the format.
+//!
+//! Every Lambda function also reports its cold starts with [`ColdStart`].
+
+use serde_json::json;
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+use crate::config;
+use crate::error::Error;
+
+/// Default namespace of metrics.
+pub const DEFAULT_NAMESPACE: &str = "PasskeyTest";
+
+/// Unit of a metric.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Unit {
+    /// Count.
+    Count,
+    /// Milliseconds.
+    Milliseconds,
+}
+
+impl Unit {
+    fn as_str(self) -> &'static str {
+        match self {
+            Unit::Count => "Count",
+            Unit::Milliseconds => "Milliseconds",
+        }
+    }
+}
+
+/// Metrics emitter.
+#[derive(Clone, Debug)]
+pub struct Metrics {
+    namespace: String,
+    service: &'static str,
+}
+
+/// Loads the metrics configuration.
+///
+/// You can specify to `METRICS_NAMESPACE` environment variable the namespace
+/// of metrics. Defaults to [`DEFAULT_NAMESPACE`].
+///
+/// `service` becomes the value of the `Service` dimension.
+pub fn load_metrics(service: &'static str) -> Result<Metrics, Error> {
+    let namespace = match config::var("METRICS_NAMESPACE") {
+        Ok(namespace) if !namespace.is_empty() => namespace,
+        Ok(namespace) => return Err(
+            Error::BadEnvironmentVariable("METRICS_NAMESPACE", namespace),
+        ),
+        Err(env::VarError::NotPresent) => DEFAULT_NAMESPACE.into(),
+        Err(env::VarError::NotUnicode(namespace)) => return Err(
+            Error::BadEnvironmentVariable(
+                "METRICS_NAMESPACE",
+                namespace.to_string_lossy().into(),
+            ),
+        ),
+    };
+    Ok(Metrics { namespace, service })
+}
+
+impl Metrics {
+    /// Counts an event.
+    pub fn count(&self, name: &str) {
+        self.put(name, 1.0, Unit::Count);
+    }
+
+    /// Records a latency.
+    ///
+    /// CloudWatch computes percentiles from the recorded values.
+    pub fn latency(&self, name: &str, latency: Duration) {
+        self.put(name, latency.as_secs_f64() * 1000.0, Unit::Milliseconds);
+    }
+
+    /// Records a value of a metric.
+    pub fn put(&self, name: &str, value: f64, unit: Unit) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|t| t.as_millis() as u64)
+            .unwrap_or_default();
+        // EMF documents must be written as they are, bypassing the tracing
+        // subscriber
+        println!("{}", self.document(name, value, unit, timestamp));
+    }
+
+    fn document(&self, name: &str, value: f64, unit: Unit, timestamp: u64) -> String {
+        json!({
+            "_aws": {
+                "Timestamp": timestamp,
+                "CloudWatchMetrics": [{
+                    "Namespace": self.namespace,
+                    "Dimensions": [["Service"]],
+                    "Metrics": [{
+                        "Name": name,
+                        "Unit": unit.as_str(),
+                    }],
+                }],
+            },
+            "Service": self.service,
+            name: value,
+        }).to_string()
+    }
+}
+
+/// Cold start of an execution environment.
+///
+/// Measures the initialization in `main`, which loads the configuration and
+/// creates the clients once for the lifetime of the execution environment,
+/// and reports it with the first invocation:
+/// - `cold_start`: count of cold starts
+/// - `init_duration`: time spent on the initialization
+/// - `cold_start_handler_duration`: time spent on the first invocation
+pub struct ColdStart {
+    init_duration: Duration,
+    pending: AtomicBool,
+}
+
+impl ColdStart {
+    /// Finishes the initialization started at a given time.
+    pub fn initialized_since(started_at: Instant) -> Self {
+        Self {
+            init_duration: started_at.elapsed(),
+            pending: AtomicBool::new(true),
+        }
+    }
+
+    /// Returns the time spent on the initialization.
+    pub fn init_duration(&self) -> Duration {
+        self.init_duration
+    }
+
+    /// Reports the cold start if this is the first invocation.
+    ///
+    /// `handler_duration` is the time spent on the invocation.
+    /// Does nothing on subsequent invocations.
+    pub fn report(&self, metrics: &Metrics, handler_duration: Duration) {
+        if !self.take() {
+            return;
+        }
+        info!(
+            init_ms = self.init_duration.as_millis() as u64,
+            handler_ms = handler_duration.as_millis() as u64,
+            "cold start",
+        );
+        metrics.count("cold_start");
+        metrics.latency("init_duration", self.init_duration);
+        metrics.latency("cold_start_handler_duration", handler_duration);
+    }
+
+    // returns `true` only for the first call.
+    fn take(&self) -> bool {
+        self.pending.swap(false, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::Value;
+
+    #[test]
+    fn metrics_document_should_follow_embedded_metric_format() {
+        let metrics = Metrics {
+            namespace: "Test".into(),
+            service: "registration",
+        };
+        let document: Value = serde_json::from_str(
+            &metrics.document("registration_started", 1.0, Unit::Count, 1234),
+        ).unwrap();
+        assert_eq!(document["_aws"]["Timestamp"], 1234);
+        let directive = &document["_aws"]["CloudWatchMetrics"][0];
+        assert_eq!(directive["Namespace"], "Test");
+        assert_eq!(directive["Dimensions"][0][0], "Service");
+        assert_eq!(directive["Metrics"][0]["Name"], "registration_started");
+        assert_eq!(directive["Metrics"][0]["Unit"], "Count");
+        assert_eq!(document["Service"], "registration");
+        assert_eq!(document["registration_started"], 1.0);
+    }
+
+    #[test]
+    fn metrics_document_should_record_latency_in_milliseconds() {
+        let metrics = Metrics {
+            namespace: "Test".into(),
+            service: "registration",
+        };
+        let document: Value = serde_json::from_str(
+            &metrics.document("finish_latency", 12.5, Unit::Milliseconds, 0),
+        ).unwrap();
+        assert_eq!(
+            document["_aws"]["CloudWatchMetrics"][0]["Metrics"][0]["Unit"],
+            "Milliseconds",
+        );
+        assert_eq!(document["finish_latency"], 12.5);
+    }
+
+    #[test]
+    fn cold_start_should_be_reported_once() {
+        let cold_start = ColdStart::initialized_since(Instant::now());
+        assert!(cold_start.take());
+        assert!(!cold_start.take());
+        assert!(!cold_start.take());
+    }
+}
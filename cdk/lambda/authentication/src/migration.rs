@@ -0,0 +1,193 @@
+//! Export and import of users and credentials.
+//!
+//! The credential table is exported as JSON Lines of [`ExportRecord`] so that
+//! users and credentials can be backed up or migrated between environments.
+//! Users in the Cognito user pool are not included; they have to be migrated
+//! separately with the same usernames (user handles).
+
+use base64::{
+    Engine as _,
+    engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
+};
+use serde::{Deserialize, Serialize};
+use webauthn_rs::prelude::Passkey;
+
+use crate::error::Error;
+use crate::items::{
+    CREDENTIAL_SK_PREFIX,
+    CredentialItem,
+    Item,
+    USER_SK,
+    UserItem,
+    user_pk,
+};
+
+/// Record in an export.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum ExportRecord {
+    /// User.
+    User(UserItem),
+    /// Credential.
+    Credential(CredentialItem),
+}
+
+impl ExportRecord {
+    /// Parses an item in the credential table.
+    ///
+    /// Returns `None` if the item is neither a user nor a credential.
+    pub fn from_item(item: &Item) -> Result<Option<Self>, Error> {
+        let sk = item.get("sk")
+            .and_then(|sk| sk.as_s().ok())
+            .ok_or(Error::BadItemAttribute("sk"))?;
+        if sk == USER_SK {
+            Ok(Some(ExportRecord::User(UserItem::from_item(item)?)))
+        } else if sk.starts_with(CREDENTIAL_SK_PREFIX) {
+            Ok(Some(ExportRecord::Credential(CredentialItem::from_item(item)?)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Converts into the attributes of an item including the keys.
+    ///
+    /// The version of a credential starts over.
+    pub fn into_item(self) -> Item {
+        match self {
+            ExportRecord::User(user) => user.into_item(),
+            ExportRecord::Credential(credential) => CredentialItem {
+                version: Some(1),
+                ..credential
+            }.into_item(),
+        }
+    }
+
+    /// Returns the partition key and the sort key, which identify the record.
+    pub fn key(&self) -> (String, String) {
+        match self {
+            ExportRecord::User(user) =>
+                (user_pk(&user.user_handle), USER_SK.into()),
+            ExportRecord::Credential(credential) => {
+                let key = credential.key();
+                (key.pk(), key.sk())
+            }
+        }
+    }
+
+    /// Validates the record.
+    ///
+    /// The user handle must be "base64url"-encoded. The credential of a
+    /// credential record must be a serialized passkey whose ID matches the
+    /// credential ID.
+    pub fn validate(&self) -> Result<(), Error> {
+        match self {
+            ExportRecord::User(user) => {
+                validate_user_handle(&user.user_handle)?;
+                if user.username.is_empty() {
+                    return Err(Error::BadRecord("empty username"));
+                }
+                Ok(())
+            }
+            ExportRecord::Credential(credential) => {
+                validate_credential(credential)
+            }
+        }
+    }
+}
+
+fn validate_user_handle(user_handle: &str) -> Result<(), Error> {
+    match base64url.decode(user_handle) {
+        Ok(user_handle) if !user_handle.is_empty() => Ok(()),
+        _ => Err(Error::BadRecord("user handle must be base64url-encoded")),
+    }
+}
+
+fn validate_credential(credential: &CredentialItem) -> Result<(), Error> {
+    validate_user_handle(&credential.user_handle)?;
+    let passkey: Passkey = serde_json::from_str(&credential.credential)
+        .or(Err(Error::BadRecord("malformed credential")))?;
+    if base64url.encode(passkey.cred_id()) != credential.credential_id {
+        return Err(Error::BadRecord("credential ID mismatch"));
+    }
+    match credential.credential_type.as_deref() {
+        None | Some("passkey") | Some("securityKey") => Ok(()),
+        Some(_) => Err(Error::BadRecord("unknown credential type")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user() -> UserItem {
+        UserItem {
+            user_handle: "AAAA".into(),
+            username: "alice".into(),
+            display_name: "Alice".into(),
+            cognito_sub: "sub".into(),
+            created_at: "2024-01-01T00:00:00Z".into(),
+        }
+    }
+
+    fn credential(credential: &str) -> CredentialItem {
+        CredentialItem {
+            username: Some("alice".into()),
+            credential: credential.into(),
+            credential_type: Some("passkey".into()),
+            backup_eligible: Some(true),
+            backup_state: Some(false),
+            version: Some(5),
+            ..CredentialItem::for_test("AAAA", "BBBB")
+        }
+    }
+
+    #[test]
+    fn export_record_should_be_tagged_with_kind() {
+        let json = serde_json::to_value(ExportRecord::User(user())).unwrap();
+        assert_eq!(json["kind"], "user");
+        assert_eq!(json["userHandle"], "AAAA");
+        let record: ExportRecord = serde_json::from_value(json).unwrap();
+        assert_eq!(record, ExportRecord::User(user()));
+    }
+
+    #[test]
+    fn export_record_should_round_trip_item() {
+        let item = ExportRecord::User(user()).into_item();
+        assert_eq!(
+            ExportRecord::from_item(&item).unwrap(),
+            Some(ExportRecord::User(user())),
+        );
+    }
+
+    #[test]
+    fn export_record_should_restart_credential_version() {
+        let record = ExportRecord::Credential(credential("{}"));
+        let (pk, sk) = record.key();
+        assert_eq!(pk, "user#AAAA");
+        assert_eq!(sk, "credential#BBBB");
+        let item = record.into_item();
+        match ExportRecord::from_item(&item).unwrap() {
+            Some(ExportRecord::Credential(c)) => assert_eq!(c.version, Some(1)),
+            r => panic!("unexpected record: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn export_record_should_not_export_version() {
+        let json = serde_json::to_value(ExportRecord::Credential(credential("{}")))
+            .unwrap();
+        assert_eq!(json["kind"], "credential");
+        assert!(json.get("version").is_none());
+    }
+
+    #[test]
+    fn validate_should_reject_bad_records() {
+        let mut bad_user = user();
+        bad_user.user_handle = "not base64url!".into();
+        assert!(ExportRecord::User(bad_user).validate().is_err());
+        assert!(ExportRecord::User(user()).validate().is_ok());
+        assert!(
+            ExportRecord::Credential(credential("{}")).validate().is_err(),
+        );
+    }
+}
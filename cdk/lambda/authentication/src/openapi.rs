@@ -0,0 +1,321 @@
+//! OpenAPI specification of the registration API.
+//!
+//! Available if the `openapi` feature is enabled.
+//! The following command writes the OpenAPI 3 document to `openapi.json`:
+//!
+//! ```sh
+//! cargo run --bin openapi --features openapi > openapi.json
+//! ```
+//!
+//! Paths are relative to the base path of the Credentials API; e.g.,
+//! `/auth/credentials`.
+
+// the functions below only exist to describe the paths.
+#![allow(dead_code)]
+
+use utoipa::OpenApi;
+
+use crate::extensions::{LargeBlobOutputs, PrfOutputs, PrfValues};
+use crate::hints::PublicKeyCredentialHint;
+use crate::payload::ErrorResponseBody;
+use crate::registration::{
+    AdditionalPasskeyRequest,
+    AuthenticatorAttachmentSchema,
+    CredentialProperties,
+    FinishRegistrationResult,
+    FinishRegistrationSession,
+    NewUserInfo,
+    PasswordUpgradeOptions,
+    RecoveryLinkRequest,
+    RecoveryLinkSession,
+    RecoveryRequest,
+    StartRegistrationSession,
+};
+
+/// OpenAPI document.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Passkey Test Credentials API",
+        description = "Registration of passkeys and security keys",
+    ),
+    paths(
+        start_registration,
+        finish_registration,
+        start_security_key_registration,
+        finish_security_key_registration,
+        start_recovery,
+        finish_recovery,
+        request_recovery_link,
+        start_recovery_link,
+        start_additional_registration,
+        finish_additional_registration,
+        start_password_upgrade,
+        finish_password_upgrade,
+    ),
+    components(schemas(
+        AdditionalPasskeyRequest,
+        AuthenticatorAttachmentSchema,
+        CredentialProperties,
+        ErrorResponseBody,
+        FinishRegistrationResult,
+        FinishRegistrationSession,
+        LargeBlobOutputs,
+        NewUserInfo,
+        PasswordUpgradeOptions,
+        PrfOutputs,
+        PrfValues,
+        PublicKeyCredentialHint,
+        RecoveryLinkRequest,
+        RecoveryLinkSession,
+        RecoveryRequest,
+        StartRegistrationSession,
+    )),
+    tags((name = "registration", description = "Registration of new users")),
+)]
+pub struct ApiDoc;
+
+/// Header to deduplicate retries of a finish request.
+#[derive(utoipa::IntoParams)]
+#[into_params(parameter_in = Header)]
+struct IdempotencyKey {
+    /// Key that identifies the finish request; the session ID by default.
+    ///
+    /// A retry with the same key succeeds again if the registration has
+    /// finished.
+    #[param(rename = "Idempotency-Key")]
+    idempotency_key: Option<String>,
+}
+
+/// Starts registration of a new user.
+#[utoipa::path(
+    post,
+    path = "/registration/v1/start",
+    tag = "registration",
+    request_body = NewUserInfo,
+    responses(
+        (status = 200, description = "Registration started", body = StartRegistrationSession),
+        (status = 400, description = "Malformed request body", body = ErrorResponseBody),
+        (status = 413, description = "Too large request body", body = ErrorResponseBody),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponseBody),
+    ),
+)]
+fn start_registration() {}
+
+/// Verifies the new user and finishes registration.
+#[utoipa::path(
+    post,
+    path = "/registration/v1/finish",
+    tag = "registration",
+    params(IdempotencyKey),
+    request_body = FinishRegistrationSession,
+    responses(
+        (status = 200, description = "Registration finished", body = FinishRegistrationResult),
+        (status = 400, description = "Malformed request body", body = ErrorResponseBody),
+        (status = 409, description = "User or credential already exists", body = ErrorResponseBody),
+        (status = 413, description = "Too large request body", body = ErrorResponseBody),
+    ),
+)]
+fn finish_registration() {}
+
+/// Starts registration of a new user with a security key.
+#[utoipa::path(
+    post,
+    path = "/registration/v1/security-key/start",
+    tag = "registration",
+    request_body = NewUserInfo,
+    responses(
+        (status = 200, description = "Registration started", body = StartRegistrationSession),
+        (status = 400, description = "Malformed request body", body = ErrorResponseBody),
+        (status = 413, description = "Too large request body", body = ErrorResponseBody),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponseBody),
+    ),
+)]
+fn start_security_key_registration() {}
+
+/// Verifies the attestation of the security key and finishes registration.
+#[utoipa::path(
+    post,
+    path = "/registration/v1/security-key/finish",
+    tag = "registration",
+    params(IdempotencyKey),
+    request_body = FinishRegistrationSession,
+    responses(
+        (status = 200, description = "Registration finished", body = FinishRegistrationResult),
+        (status = 400, description = "Malformed request body", body = ErrorResponseBody),
+        (status = 409, description = "User or credential already exists", body = ErrorResponseBody),
+        (status = 413, description = "Too large request body", body = ErrorResponseBody),
+    ),
+)]
+fn finish_security_key_registration() {}
+
+/// Consumes a recovery code and starts registration of a new passkey of an
+/// existing user.
+#[utoipa::path(
+    post,
+    path = "/registration/v1/recovery/start",
+    tag = "registration",
+    request_body = RecoveryRequest,
+    responses(
+        (status = 200, description = "Registration started", body = StartRegistrationSession),
+        (status = 400, description = "Malformed request body", body = ErrorResponseBody),
+        (status = 401, description = "Wrong username or recovery code", body = ErrorResponseBody),
+        (status = 413, description = "Too large request body", body = ErrorResponseBody),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponseBody),
+    ),
+)]
+fn start_recovery() {}
+
+/// Verifies the new passkey and adds it to the existing user.
+#[utoipa::path(
+    post,
+    path = "/registration/v1/recovery/finish",
+    tag = "registration",
+    params(IdempotencyKey),
+    request_body = FinishRegistrationSession,
+    responses(
+        (status = 200, description = "Registration finished", body = FinishRegistrationResult),
+        (status = 400, description = "Malformed request body", body = ErrorResponseBody),
+        (status = 409, description = "Credential already exists", body = ErrorResponseBody),
+        (status = 413, description = "Too large request body", body = ErrorResponseBody),
+    ),
+)]
+fn finish_recovery() {}
+
+/// Emails a recovery link to an existing user.
+#[utoipa::path(
+    post,
+    path = "/registration/v1/recovery/email",
+    tag = "registration",
+    request_body = RecoveryLinkRequest,
+    responses(
+        (status = 202, description = "Recovery link sent if the user exists"),
+        (status = 400, description = "Malformed request body", body = ErrorResponseBody),
+        (status = 413, description = "Too large request body", body = ErrorResponseBody),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponseBody),
+    ),
+)]
+fn request_recovery_link() {}
+
+/// Consumes the token of a recovery link and starts registration of a new
+/// passkey of the user.
+///
+/// Finished with `/registration/v1/recovery/finish`.
+#[utoipa::path(
+    post,
+    path = "/registration/v1/recovery/email/start",
+    tag = "registration",
+    request_body = RecoveryLinkSession,
+    responses(
+        (status = 200, description = "Registration started", body = StartRegistrationSession),
+        (status = 400, description = "Malformed request body", body = ErrorResponseBody),
+        (status = 401, description = "Unknown, used, or expired recovery link", body = ErrorResponseBody),
+        (status = 413, description = "Too large request body", body = ErrorResponseBody),
+    ),
+)]
+fn start_recovery_link() {}
+
+/// Starts registration of an additional passkey for the authenticated user.
+///
+/// Requires an ID or access token issued by the Cognito user pool in the
+/// `Authorization` header.
+#[utoipa::path(
+    post,
+    path = "/registration/v1/passkeys/start",
+    tag = "registration",
+    request_body = AdditionalPasskeyRequest,
+    responses(
+        (status = 200, description = "Registration started", body = StartRegistrationSession),
+        (status = 400, description = "Malformed request body", body = ErrorResponseBody),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 413, description = "Too large request body", body = ErrorResponseBody),
+    ),
+)]
+fn start_additional_registration() {}
+
+/// Verifies the additional passkey and adds it to the authenticated user.
+#[utoipa::path(
+    post,
+    path = "/registration/v1/passkeys/finish",
+    tag = "registration",
+    params(IdempotencyKey),
+    request_body = FinishRegistrationSession,
+    responses(
+        (status = 200, description = "Registration finished", body = FinishRegistrationResult),
+        (status = 400, description = "Malformed request body", body = ErrorResponseBody),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 409, description = "Credential already exists", body = ErrorResponseBody),
+        (status = 413, description = "Too large request body", body = ErrorResponseBody),
+    ),
+)]
+fn finish_additional_registration() {}
+
+/// Starts registration of the first passkey for the authenticated user who
+/// has signed in with a password.
+///
+/// Requires an ID or access token issued by the Cognito user pool in the
+/// `Authorization` header.
+#[utoipa::path(
+    post,
+    path = "/registration/v1/upgrade/start",
+    tag = "registration",
+    request_body = AdditionalPasskeyRequest,
+    responses(
+        (status = 200, description = "Registration started", body = StartRegistrationSession),
+        (status = 400, description = "Malformed request body", body = ErrorResponseBody),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 403, description = "Username of the user is not a user handle", body = ErrorResponseBody),
+        (status = 409, description = "User already has a passkey", body = ErrorResponseBody),
+        (status = 413, description = "Too large request body", body = ErrorResponseBody),
+    ),
+)]
+fn start_password_upgrade() {}
+
+/// Verifies the passkey, stores the user, and disables the password if
+/// requested.
+///
+/// The request body also takes [`PasswordUpgradeOptions`].
+#[utoipa::path(
+    post,
+    path = "/registration/v1/upgrade/finish",
+    tag = "registration",
+    params(IdempotencyKey),
+    request_body = FinishRegistrationSession,
+    responses(
+        (status = 200, description = "Registration finished", body = FinishRegistrationResult),
+        (status = 400, description = "Malformed request body", body = ErrorResponseBody),
+        (status = 401, description = "Missing or invalid token"),
+        (status = 409, description = "User or credential already exists", body = ErrorResponseBody),
+        (status = 413, description = "Too large request body", body = ErrorResponseBody),
+    ),
+)]
+fn finish_password_upgrade() {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_doc_should_describe_registration_paths() {
+        let doc = ApiDoc::openapi();
+        for path in [
+            "/registration/v1/start",
+            "/registration/v1/finish",
+            "/registration/v1/security-key/start",
+            "/registration/v1/security-key/finish",
+            "/registration/v1/recovery/start",
+            "/registration/v1/recovery/finish",
+            "/registration/v1/recovery/email",
+            "/registration/v1/recovery/email/start",
+            "/registration/v1/passkeys/start",
+            "/registration/v1/passkeys/finish",
+            "/registration/v1/upgrade/start",
+            "/registration/v1/upgrade/finish",
+        ] {
+            assert!(doc.paths.paths.contains_key(path), "missing {}", path);
+        }
+        let schemas = doc.components.unwrap().schemas;
+        assert!(schemas.contains_key("NewUserInfo"));
+        assert!(schemas.contains_key("ErrorResponseBody"));
+    }
+}
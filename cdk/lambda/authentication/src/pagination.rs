@@ -0,0 +1,70 @@
+//! Pagination of DynamoDB queries.
+//!
+//! The last evaluated key of a query is handed to clients as an opaque
+//! "base64url"-encoded token.
+
+use aws_sdk_dynamodb::types::AttributeValue;
+use base64::{
+    Engine as _,
+    engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
+};
+use std::collections::HashMap;
+
+use crate::error::Error;
+
+/// Encodes the last evaluated key of a query into a page token.
+///
+/// Only string attributes are supported, which suffices for the keys of the
+/// tables in this project.
+pub fn encode_page_token(
+    key: &HashMap<String, AttributeValue>,
+) -> Result<String, Error> {
+    let key = key.iter()
+        .map(|(name, value)| value.as_s()
+            .map(|value| (name.clone(), value.clone()))
+            .or(Err(Error::Inconvertible("non-string key in page token"))))
+        .collect::<Result<HashMap<_, _>, _>>()?;
+    let key = serde_json::to_vec(&key)
+        .or(Err(Error::Inconvertible("page token")))?;
+    Ok(base64url.encode(key))
+}
+
+/// Decodes a page token into the exclusive start key of a query.
+///
+/// Returns `None` if `token` is malformed.
+pub fn decode_page_token(token: &str) -> Option<HashMap<String, AttributeValue>> {
+    let key = base64url.decode(token).ok()?;
+    let key: HashMap<String, String> = serde_json::from_slice(&key).ok()?;
+    Some(key.into_iter()
+        .map(|(name, value)| (name, AttributeValue::S(value)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_token_should_round_trip_string_key() {
+        let key = HashMap::from([
+            ("pk".to_string(), AttributeValue::S("user#abc".into())),
+            ("sk".to_string(), AttributeValue::S("event#2024".into())),
+        ]);
+        let token = encode_page_token(&key).unwrap();
+        assert_eq!(decode_page_token(&token), Some(key));
+    }
+
+    #[test]
+    fn encode_page_token_should_reject_non_string_key() {
+        let key = HashMap::from([
+            ("pk".to_string(), AttributeValue::N("1".into())),
+        ]);
+        assert!(encode_page_token(&key).is_err());
+    }
+
+    #[test]
+    fn decode_page_token_should_reject_malformed_token() {
+        assert_eq!(decode_page_token("!"), None);
+        assert_eq!(decode_page_token(&base64url.encode(b"[1]")), None);
+    }
+}
@@ -1,39 +1,167 @@
 //! Provides access to parameters in Parameter Store on AWS Systems Manager.
 
-use std::env;
 use tracing::error;
-use webauthn_rs::prelude::Url;
+use webauthn_rs::{
+    Webauthn,
+    WebauthnBuilder,
+    prelude::{AttestationCaList, Url},
+};
 
+use crate::android::load_apk_key_hashes;
+use crate::config;
 use crate::error::Error;
+use crate::mds::parse_certificate;
 
 /// Loads the relying party origin from the Parameter Store.
 ///
 /// You have to specify to `RP_ORIGIN_PARAMETER_PATH` environment variable the
 /// path to the parameter that stores the origin (URL) of the relying party in
 /// Parameter Store on AWS Systems Manager.
+/// The origin may be directly configured as `RP_ORIGIN` instead.
 ///
-/// The domain of the URL is used as the ID of the relying party.
+/// The domain of the URL is used as the ID of the relying party unless `RP_ID`
+/// is configured.
 pub async fn load_relying_party_origin(
     ssm: aws_sdk_ssm::Client,
 ) -> Result<(String, Url), Error> {
-    let parameter_name = env::var("RP_ORIGIN_PARAMETER_PATH")
-        .map_err(|_| Error::ParameterNotFound("RP_ORIGIN_PARAMETER_PATH"))?;
-    let origin = ssm.get_parameter()
+    let origin = match config::var("RP_ORIGIN") {
+        Ok(origin) => origin,
+        Err(_) => get_parameter(&ssm, "RP_ORIGIN_PARAMETER_PATH")
+            .await?
+            .ok_or(Error::ParameterNotFound("RP_ORIGIN_PARAMETER_PATH"))?,
+    };
+    let (domain, rp_origin) = parse_relying_party_origin(origin)?;
+    let rp_id = config::var("RP_ID")
+        .ok()
+        .filter(|rp_id| !rp_id.is_empty())
+        .unwrap_or(domain);
+    Ok((rp_id, rp_origin))
+}
+
+/// Loads additional origins allowed for the relying party.
+///
+/// You can specify to `RP_ALLOWED_ORIGINS` environment variable a
+/// comma-separated list of origins (URLs) allowed in addition to the origin
+/// of the relying party; e.g., `https://www.example.com` when the relying
+/// party origin is `https://example.com`.
+///
+/// Returns an empty list if `RP_ALLOWED_ORIGINS` is not set.
+pub fn load_allowed_origins() -> Result<Vec<Url>, Error> {
+    match config::var("RP_ALLOWED_ORIGINS") {
+        Ok(origins) => parse_allowed_origins(&origins),
+        Err(_) => Ok(Vec::new()),
+    }
+}
+
+/// Builds the [`Webauthn`] of the relying party.
+///
+/// The relying party is loaded with [`load_relying_party_origin`], and the
+/// origins loaded with [`load_allowed_origins`] and the origins of the Android
+/// apps loaded with [`load_apk_key_hashes`] are also allowed.
+pub async fn load_webauthn(ssm: aws_sdk_ssm::Client) -> Result<Webauthn, Error> {
+    let (rp_id, rp_origin) = load_relying_party_origin(ssm).await?;
+    let mut allowed_origins = load_allowed_origins()?;
+    allowed_origins.extend(load_apk_key_hashes()?.iter().map(|hash| hash.origin()));
+    build_webauthn(&rp_id, &rp_origin, "Passkey Test", &allowed_origins)
+}
+
+/// Builds a [`Webauthn`] of a given relying party.
+pub fn build_webauthn(
+    rp_id: &str,
+    rp_origin: &Url,
+    rp_name: &str,
+    allowed_origins: &[Url],
+) -> Result<Webauthn, Error> {
+    let builder = WebauthnBuilder::new(rp_id, rp_origin)
+        .map_err(|e| {
+            error!(?e, "configuring relying party");
+            Error::BadRelyingPartyOrigin(rp_origin.to_string())
+        })?
+        .rp_name(rp_name);
+    allowed_origins.iter()
+        .fold(builder, |builder, origin| builder.append_allowed_origin(origin))
+        .build()
+        .map_err(|e| {
+            error!(?e, "building Webauthn");
+            Error::BadRelyingPartyOrigin(rp_origin.to_string())
+        })
+}
+
+/// Loads the attestation CA list from the Parameter Store.
+///
+/// You have to specify to `ATTESTATION_CA_LIST_PARAMETER_PATH` environment
+/// variable the path to the parameter that stores the attestation CA list as
+/// JSON in Parameter Store on AWS Systems Manager.
+///
+/// Returns `None` if the parameter does not exist, which means security key
+/// registration is disabled.
+pub async fn load_attestation_ca_list(
+    ssm: aws_sdk_ssm::Client,
+) -> Result<Option<AttestationCaList>, Error> {
+    get_parameter(&ssm, "ATTESTATION_CA_LIST_PARAMETER_PATH")
+        .await?
+        .map(parse_attestation_ca_list)
+        .transpose()
+}
+
+fn parse_attestation_ca_list(
+    ca_list: impl AsRef<str>,
+) -> Result<AttestationCaList, Error> {
+    serde_json::from_str(ca_list.as_ref())
+        .map_err(|e| {
+            error!(?e, "parsing attestation CA list");
+            Error::Inconvertible("malformed attestation CA list")
+        })
+}
+
+/// Loads the root certificate of the FIDO Metadata Service from the Parameter
+/// Store.
+///
+/// You have to specify to `MDS_ROOT_CERTIFICATE_PARAMETER_PATH` environment
+/// variable the path to the parameter that stores the root certificate in PEM
+/// in Parameter Store on AWS Systems Manager.
+///
+/// Returns the DER encoded certificate.
+pub async fn load_mds_root_certificate(
+    ssm: aws_sdk_ssm::Client,
+) -> Result<Vec<u8>, Error> {
+    let certificate = get_parameter(&ssm, "MDS_ROOT_CERTIFICATE_PARAMETER_PATH")
+        .await?
+        .ok_or(Error::ParameterNotFound("MDS_ROOT_CERTIFICATE_PARAMETER_PATH"))?;
+    parse_certificate(&certificate)
+}
+
+// Gets the value of the parameter whose path is specified to a given
+// environment variable.
+//
+// `None` if the parameter does not exist.
+async fn get_parameter(
+    ssm: &aws_sdk_ssm::Client,
+    path_env: &'static str,
+) -> Result<Option<String>, Error> {
+    let parameter_name = config::var(path_env)
+        .map_err(|_| Error::ParameterNotFound(path_env))?;
+    let res = ssm.get_parameter()
         .name(parameter_name)
         .with_decryption(false)
         .send()
-        .await
-        .map_err(|e| {
+        .await;
+    let parameter = match res {
+        Ok(res) => res.parameter,
+        Err(e) if e.as_service_error()
+            .is_some_and(|e| e.is_parameter_not_found()) => return Ok(None),
+        Err(e) => {
             error!(?e, "getting SSM parameter");
-            Error::ParameterNotFound("RP_ORIGIN_PARAMETER_PATH")
-        })?
-        .parameter
+            return Err(Error::ParameterNotFound(path_env));
+        }
+    };
+    parameter
         .and_then(|p| p.value)
+        .map(Some)
         .ok_or_else(|| {
             error!("missing SSM parameter value");
-            Error::ParameterNotFound("RP_ORIGIN_PARAMETER_PATH")
-        })?;
-    parse_relying_party_origin(origin)
+            Error::ParameterNotFound(path_env)
+        })
 }
 
 fn parse_relying_party_origin(origin: impl Into<String>) -> Result<(String, Url), Error> {
@@ -52,6 +180,17 @@ fn parse_relying_party_origin(origin: impl Into<String>) -> Result<(String, Url)
     Ok((rp_id, rp_origin))
 }
 
+fn parse_allowed_origins(origins: &str) -> Result<Vec<Url>, Error> {
+    origins.split(',')
+        .map(str::trim)
+        .filter(|origin| !origin.is_empty())
+        .map(|origin| Url::parse(origin).map_err(|e| {
+            error!(?e, "parsing allowed origin");
+            Error::BadEnvironmentVariable("RP_ALLOWED_ORIGINS", origin.into())
+        }))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -77,4 +216,37 @@ mod tests {
         let origin = "passkey-test.codemonger.io";
         assert!(parse_relying_party_origin(origin).is_err());
     }
+
+    #[test]
+    fn parse_allowed_origins_should_split_comma_separated_urls() {
+        assert_eq!(
+            parse_allowed_origins(
+                "https://example.com, https://www.example.com,",
+            ).unwrap(),
+            vec![
+                Url::parse("https://example.com").unwrap(),
+                Url::parse("https://www.example.com").unwrap(),
+            ],
+        );
+        assert!(parse_allowed_origins("").unwrap().is_empty());
+        assert!(parse_allowed_origins("https://example.com,example").is_err());
+    }
+
+    #[test]
+    fn build_webauthn_should_accept_android_origin() {
+        let origin = crate::android::ApkKeyHash::parse(
+            "FG3pg8VzBlDY7rmVLzT8ZBagg0LmHb6oigSWsj_PROU",
+        ).unwrap().origin();
+        assert!(build_webauthn(
+            "localhost",
+            &Url::parse("http://localhost:5173").unwrap(),
+            "Test",
+            &[origin],
+        ).is_ok());
+    }
+
+    #[test]
+    fn parse_attestation_ca_list_should_fail_for_non_json() {
+        assert!(parse_attestation_ca_list("not a CA list").is_err());
+    }
 }
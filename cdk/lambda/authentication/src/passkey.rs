@@ -0,0 +1,319 @@
+//! Utilities for passkeys.
+
+use serde::{Deserialize, Serialize};
+use webauthn_rs::prelude::Passkey;
+
+use crate::error::Error;
+
+// Offset of the flags in authenticator data, which follow the RP ID hash.
+const FLAGS_OFFSET: usize = 32;
+
+// User verified (UV) flag.
+const USER_VERIFIED_FLAG: u8 = 0x04;
+
+/// Properties of a passkey that [`Passkey`] does not expose.
+///
+/// [`Passkey`] hides the underlying credential, so we extract these from its
+/// serialized representation.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct PasskeyProperties {
+    /// Whether the user was verified when the passkey was registered or last
+    /// used.
+    pub user_verified: bool,
+
+    /// Whether the passkey is eligible for backup (BE flag).
+    #[serde(default)]
+    pub backup_eligible: bool,
+
+    /// Whether the passkey is currently backed up (BS flag).
+    #[serde(default)]
+    pub backup_state: bool,
+}
+
+impl PasskeyProperties {
+    /// Extracts the properties of a given passkey.
+    ///
+    /// Also works for a `SecurityKey`, which is serialized in the same shape.
+    pub fn of(passkey: &impl Serialize) -> Result<Self, Error> {
+        let passkey = serde_json::to_value(passkey)
+            .or(Err(Error::Inconvertible("non-serializable passkey")))?;
+        Self::from_serialized_passkey(passkey)
+    }
+
+    fn from_serialized_passkey(passkey: serde_json::Value) -> Result<Self, Error> {
+        #[derive(Deserialize)]
+        struct SerializedPasskey {
+            cred: PasskeyProperties,
+        }
+        serde_json::from_value::<SerializedPasskey>(passkey)
+            .map(|p| p.cred)
+            .or(Err(Error::Inconvertible("incompatible passkey")))
+    }
+}
+
+/// Returns whether a new passkey comes with an attestation statement.
+///
+/// The attestation has already been verified by the Webauthn library; this
+/// tells whether the authenticator attested anything at all, in other words,
+/// the attestation is not "none".
+pub fn is_attested(passkey: &impl Serialize) -> Result<bool, Error> {
+    let passkey = serde_json::to_value(passkey)
+        .or(Err(Error::Inconvertible("non-serializable passkey")))?;
+    Ok(is_attested_serialized_passkey(&passkey))
+}
+
+fn is_attested_serialized_passkey(passkey: &serde_json::Value) -> bool {
+    match passkey.pointer("/cred/attestation/data") {
+        Some(serde_json::Value::String(data)) => data != "None",
+        Some(_) => true,
+        None => false,
+    }
+}
+
+/// Returns the COSE algorithm identifier of the public key of a passkey.
+///
+/// Also works for a `SecurityKey`.
+/// Fails if the algorithm is unknown to this function.
+pub fn algorithm_of(passkey: &impl Serialize) -> Result<i64, Error> {
+    let passkey = serde_json::to_value(passkey)
+        .or(Err(Error::Inconvertible("non-serializable passkey")))?;
+    algorithm_of_serialized_passkey(&passkey)
+        .ok_or(Error::Inconvertible("unknown public key algorithm"))
+}
+
+fn algorithm_of_serialized_passkey(passkey: &serde_json::Value) -> Option<i64> {
+    // the algorithm is serialized by its name in the Webauthn library
+    match passkey.pointer("/cred/cred/type_")?.as_str()? {
+        "ES256" => Some(-7),
+        "ES384" => Some(-35),
+        "ES512" => Some(-36),
+        "EDDSA" => Some(-8),
+        "RS256" => Some(-257),
+        "RS384" => Some(-258),
+        "RS512" => Some(-259),
+        "PS256" => Some(-37),
+        "PS384" => Some(-38),
+        "PS512" => Some(-39),
+        _ => None,
+    }
+}
+
+/// Returns whether the user verified (UV) flag is set in given authenticator
+/// data.
+///
+/// Checked in addition to the result of the Webauthn library, so that an
+/// assertion or attestation without the flag is never accepted where a policy
+/// requires user verification; e.g., from a misbehaving client.
+/// Returns `false` if the authenticator data is too short to have flags.
+pub fn is_user_verified_in(authenticator_data: &[u8]) -> bool {
+    authenticator_data.get(FLAGS_OFFSET)
+        .is_some_and(|flags| flags & USER_VERIFIED_FLAG != 0)
+}
+
+/// Extracts the authenticator data from a CBOR-encoded attestation object.
+///
+/// Returns `None` if the attestation object is malformed.
+pub fn authenticator_data_of(attestation_object: &[u8]) -> Option<Vec<u8>> {
+    let value: ciborium::Value = ciborium::from_reader(attestation_object).ok()?;
+    value.into_map()
+        .ok()?
+        .into_iter()
+        .find(|(k, _)| k.as_text() == Some("authData"))?
+        .1
+        .into_bytes()
+        .ok()
+}
+
+/// Attestation statement with which the authenticator attested a new
+/// passkey.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Attestation {
+    /// Format of the attestation statement as serialized by the Webauthn
+    /// library.
+    ///
+    /// `None` if the library did not record it.
+    pub format: Option<String>,
+
+    /// "base64url"-encoded DER certificates of the attestation statement, the
+    /// attestation certificate first.
+    ///
+    /// Empty if the statement carries no certificate chain; e.g., self
+    /// attestation.
+    pub certificates: Vec<String>,
+}
+
+/// Extracts the attestation statement of a new passkey.
+///
+/// Also works for a `SecurityKey`.
+/// Returns `None` if the passkey is not attested; see [`is_attested`].
+pub fn attestation_of(passkey: &impl Serialize) -> Result<Option<Attestation>, Error> {
+    let passkey = serde_json::to_value(passkey)
+        .or(Err(Error::Inconvertible("non-serializable passkey")))?;
+    Ok(attestation_of_serialized_passkey(&passkey))
+}
+
+fn attestation_of_serialized_passkey(passkey: &serde_json::Value) -> Option<Attestation> {
+    if !is_attested_serialized_passkey(passkey) {
+        return None;
+    }
+    let format = passkey.pointer("/cred/attestation_format")
+        .and_then(|f| f.as_str())
+        .map(Into::into);
+    // certificates are the content of a variant like `{"Basic": [...]}`, or
+    // the first field of a tuple variant
+    let certificates = match passkey.pointer("/cred/attestation/data") {
+        Some(serde_json::Value::Object(data)) => data.values()
+            .next()
+            .and_then(|content| match content.as_array()?.first()? {
+                serde_json::Value::Array(chain) => Some(chain),
+                serde_json::Value::String(_) => content.as_array(),
+                _ => None,
+            })
+            .map(|chain| chain.iter()
+                .filter_map(|c| c.as_str().map(Into::into))
+                .collect())
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    };
+    Some(Attestation { format, certificates })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passkey_properties_from_serialized_passkey_should_extract_user_verified() {
+        let passkey = serde_json::json!({
+            "cred": {
+                "cred_id": "AAAA",
+                "counter": 0,
+                "user_verified": true,
+            },
+        });
+        assert_eq!(
+            PasskeyProperties::from_serialized_passkey(passkey).unwrap(),
+            PasskeyProperties {
+                user_verified: true,
+                backup_eligible: false,
+                backup_state: false,
+            },
+        );
+    }
+
+    #[test]
+    fn passkey_properties_from_serialized_passkey_should_extract_backup_flags() {
+        let passkey = serde_json::json!({
+            "cred": {
+                "cred_id": "AAAA",
+                "counter": 0,
+                "user_verified": false,
+                "backup_eligible": true,
+                "backup_state": true,
+            },
+        });
+        let properties =
+            PasskeyProperties::from_serialized_passkey(passkey).unwrap();
+        assert!(properties.backup_eligible);
+        assert!(properties.backup_state);
+    }
+
+    #[test]
+    fn passkey_properties_from_serialized_passkey_should_fail_without_cred() {
+        let passkey = serde_json::json!({ "user_verified": true });
+        assert!(PasskeyProperties::from_serialized_passkey(passkey).is_err());
+    }
+
+    #[test]
+    fn is_attested_serialized_passkey_should_reject_none_attestation() {
+        let attested = |data: serde_json::Value| is_attested_serialized_passkey(
+            &serde_json::json!({ "cred": { "attestation": { "data": data } } }),
+        );
+        assert!(!attested(serde_json::json!("None")));
+        assert!(attested(serde_json::json!("Self_")));
+        assert!(attested(serde_json::json!({ "Basic": [] })));
+        assert!(!is_attested_serialized_passkey(&serde_json::json!({ "cred": {} })));
+    }
+
+    #[test]
+    fn attestation_of_serialized_passkey_should_extract_certificate_chain() {
+        let passkey = serde_json::json!({
+            "cred": {
+                "attestation": { "data": { "Basic": ["leaf", "intermediate"] } },
+                "attestation_format": "packed",
+            },
+        });
+        assert_eq!(
+            attestation_of_serialized_passkey(&passkey),
+            Some(Attestation {
+                format: Some("packed".into()),
+                certificates: vec!["leaf".into(), "intermediate".into()],
+            }),
+        );
+        let passkey = serde_json::json!({
+            "cred": {
+                "attestation": { "data": { "AttCa": [["leaf"], null] } },
+                "attestation_format": "tpm",
+            },
+        });
+        assert_eq!(
+            attestation_of_serialized_passkey(&passkey).unwrap().certificates,
+            vec!["leaf".to_string()],
+        );
+    }
+
+    #[test]
+    fn attestation_of_serialized_passkey_should_ignore_none_attestation() {
+        let passkey = serde_json::json!({
+            "cred": { "attestation": { "data": "None" }, "attestation_format": "none" },
+        });
+        assert_eq!(attestation_of_serialized_passkey(&passkey), None);
+        let passkey = serde_json::json!({
+            "cred": { "attestation": { "data": "Self_" }, "attestation_format": "packed" },
+        });
+        assert_eq!(
+            attestation_of_serialized_passkey(&passkey),
+            Some(Attestation { format: Some("packed".into()), certificates: vec![] }),
+        );
+    }
+
+    #[test]
+    fn is_user_verified_in_should_check_uv_flag() {
+        let authenticator_data = |flags: u8| {
+            let mut data = vec![0u8; 37];
+            data[32] = flags;
+            data
+        };
+        assert!(is_user_verified_in(&authenticator_data(0x05)));
+        assert!(!is_user_verified_in(&authenticator_data(0x01)));
+        assert!(!is_user_verified_in(&[0u8; 16]));
+    }
+
+    #[test]
+    fn authenticator_data_of_should_extract_auth_data() {
+        let mut attestation_object = Vec::new();
+        ciborium::into_writer(
+            &ciborium::Value::Map(vec![
+                ("fmt".into(), "none".into()),
+                ("authData".into(), ciborium::Value::Bytes(vec![1, 2, 3])),
+            ]),
+            &mut attestation_object,
+        ).unwrap();
+        assert_eq!(authenticator_data_of(&attestation_object), Some(vec![1, 2, 3]));
+        assert_eq!(authenticator_data_of(b"not CBOR"), None);
+    }
+
+    #[test]
+    fn algorithm_of_serialized_passkey_should_map_to_cose_identifier() {
+        let passkey = |type_: &str| serde_json::json!({
+            "cred": {
+                "cred": { "type_": type_, "key": {} },
+            },
+        });
+        assert_eq!(algorithm_of_serialized_passkey(&passkey("ES256")), Some(-7));
+        assert_eq!(algorithm_of_serialized_passkey(&passkey("EDDSA")), Some(-8));
+        assert_eq!(algorithm_of_serialized_passkey(&passkey("RS256")), Some(-257));
+        assert_eq!(algorithm_of_serialized_passkey(&passkey("INSECURE_RS1")), None);
+        assert_eq!(algorithm_of_serialized_passkey(&serde_json::json!({})), None);
+    }
+}
@@ -0,0 +1,264 @@
+//! Claims of passkey authentications in the tokens of the user pool.
+//!
+//! The verify auth challenge trigger records every successful passkey
+//! authentication in the session table; see [`PasskeyAuthenticationItem`].
+//! The pre token generation trigger consumes the record, and adds the
+//! following claims to the ID token so that downstream services can require
+//! sessions backed by passkeys:
+//! - `amr`: `["webauthn"]`; the name is configurable with `AMR_CLAIM`
+//!   because Cognito may not let a trigger override the claim
+//! - `passkey_credential_id`: "base64url"-encoded credential ID
+//! - `passkey_aaguid`: AAGUID of the authenticator; omitted if unknown
+//! - `passkey_uv`: whether the user was verified in the assertion
+//! - `passkey_auth_time`: time of the authentication in seconds since the
+//!   epoch
+//!
+//! The record expires in [`PASSKEY_AUTHENTICATION_TTL`] seconds without being
+//! consumed. Tokens refreshed with a refresh token do not have the claims,
+//! because Cognito does not tell how the user has signed in.
+//!
+//! Both the version 1 and 2 events of the pre token generation trigger are
+//! supported. The version 1 event takes only string claims, so an array is
+//! joined with spaces and a boolean becomes "true" or "false".
+
+use aws_sdk_dynamodb::types::ReturnValue;
+use serde_json::{Map, Value, json};
+use std::env;
+use tracing::error;
+
+use crate::config;
+use crate::error::Error;
+use crate::items::{PasskeyAuthenticationItem, SessionKey};
+
+/// Time to live of a recorded passkey authentication in seconds.
+pub const PASSKEY_AUTHENTICATION_TTL: i64 = 5 * 60;
+
+/// Default name of the claim of authentication methods.
+pub const DEFAULT_AMR_CLAIM: &str = "amr";
+
+/// Authentication method reference of passkeys.
+pub const WEBAUTHN_AMR: &str = "webauthn";
+
+/// Record of passkey authentications in the session table.
+#[derive(Clone, Debug)]
+pub struct PasskeyAuthentications {
+    dynamodb: aws_sdk_dynamodb::Client,
+    table_name: String,
+}
+
+/// Loads the record of passkey authentications in a given session table.
+///
+/// You can specify to `PASSKEY_CLAIMS` environment variable "true" to record
+/// passkey authentications for the pre token generation trigger.
+///
+/// Returns `None` if `PASSKEY_CLAIMS` is not "true".
+pub fn load_passkey_authentications(
+    dynamodb: aws_sdk_dynamodb::Client,
+    table_name: String,
+) -> Result<Option<PasskeyAuthentications>, Error> {
+    match config::var("PASSKEY_CLAIMS").as_deref() {
+        Ok("true") => Ok(Some(PasskeyAuthentications::new(dynamodb, table_name))),
+        Ok("false") | Err(env::VarError::NotPresent) => Ok(None),
+        Ok(value) => Err(Error::BadEnvironmentVariable("PASSKEY_CLAIMS", value.into())),
+        Err(env::VarError::NotUnicode(value)) => Err(
+            Error::BadEnvironmentVariable("PASSKEY_CLAIMS", value.to_string_lossy().into()),
+        ),
+    }
+}
+
+impl PasskeyAuthentications {
+    /// Creates a record in a given session table.
+    pub fn new(dynamodb: aws_sdk_dynamodb::Client, table_name: String) -> Self {
+        Self { dynamodb, table_name }
+    }
+
+    /// Records a passkey authentication of a user.
+    ///
+    /// Replaces the previous authentication of the user if any.
+    pub async fn record(
+        &self,
+        user_handle: &str,
+        authentication: PasskeyAuthenticationItem,
+    ) -> Result<(), Error> {
+        self.dynamodb
+            .put_item()
+            .table_name(self.table_name.clone())
+            .set_item(Some(
+                authentication.into_item(SessionKey::PasskeyAuthentication(user_handle)),
+            ))
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "recording passkey authentication");
+                Error::Storage("failed to record passkey authentication")
+            })?;
+        Ok(())
+    }
+
+    /// Consumes the latest passkey authentication of a user.
+    ///
+    /// Returns `None` if the user has not authenticated with a passkey
+    /// recently.
+    pub async fn take(
+        &self,
+        user_handle: &str,
+        now: i64,
+    ) -> Result<Option<PasskeyAuthenticationItem>, Error> {
+        let authentication = self.dynamodb
+            .delete_item()
+            .table_name(self.table_name.clone())
+            .key("pk", SessionKey::PasskeyAuthentication(user_handle).attribute())
+            .return_values(ReturnValue::AllOld)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "taking passkey authentication");
+                Error::Storage("failed to take passkey authentication")
+            })?
+            .attributes
+            .map(|item| PasskeyAuthenticationItem::from_item(&item))
+            .transpose()?;
+        // DynamoDB deletes expired items lazily
+        Ok(authentication.filter(|a| a.ttl >= now))
+    }
+}
+
+/// Returns the claims of a passkey authentication.
+pub fn passkey_claims(
+    authentication: &PasskeyAuthenticationItem,
+    amr_claim: &str,
+) -> Map<String, Value> {
+    let mut claims = Map::new();
+    claims.insert(amr_claim.into(), json!([WEBAUTHN_AMR]));
+    claims.insert(
+        "passkey_credential_id".into(),
+        authentication.credential_id.clone().into(),
+    );
+    if let Some(aaguid) = authentication.aaguid.as_ref() {
+        claims.insert("passkey_aaguid".into(), aaguid.clone().into());
+    }
+    claims.insert("passkey_uv".into(), authentication.user_verified.into());
+    claims.insert("passkey_auth_time".into(), authentication.authenticated_at.into());
+    claims
+}
+
+/// Adds claims to the ID token in a pre token generation event.
+///
+/// Keeps the claims that another part of the response has already added.
+/// Fails if the event is not an object.
+pub fn add_id_token_claims(
+    event: &mut Value,
+    claims: Map<String, Value>,
+) -> Result<(), Error> {
+    let is_v1 = event.get("version").and_then(Value::as_str).map_or(true, |v| v == "1");
+    let response = event.as_object_mut()
+        .ok_or(Error::Inconvertible("pre token generation event must be an object"))?
+        .entry("response")
+        .or_insert_with(|| json!({}));
+    let target = if is_v1 {
+        object_entry(response, "claimsOverrideDetails")
+    } else {
+        let details = object_entry(response, "claimsAndScopeOverrideDetails");
+        object_entry(details, "idTokenGeneration")
+    };
+    let target = object_entry(target, "claimsToAddOrOverride")
+        .as_object_mut()
+        .ok_or(Error::Inconvertible("claimsToAddOrOverride must be an object"))?;
+    for (name, value) in claims {
+        let value = if is_v1 { Value::String(stringify_claim(value)) } else { value };
+        target.insert(name, value);
+    }
+    Ok(())
+}
+
+// returns the object of a given key, replacing null or a missing value with
+// an empty object.
+fn object_entry<'a>(value: &'a mut Value, key: &str) -> &'a mut Value {
+    if !value.is_object() {
+        *value = json!({});
+    }
+    let entry = value.as_object_mut()
+        .expect("value must be an object")
+        .entry(key)
+        .or_insert_with(|| json!({}));
+    if entry.is_null() {
+        *entry = json!({});
+    }
+    entry
+}
+
+// the version 1 event takes only string claims.
+fn stringify_claim(value: Value) -> String {
+    match value {
+        Value::String(value) => value,
+        Value::Array(values) => values.into_iter()
+            .map(stringify_claim)
+            .collect::<Vec<_>>()
+            .join(" "),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn authentication() -> PasskeyAuthenticationItem {
+        PasskeyAuthenticationItem {
+            ttl: 360,
+            credential_id: "BBBB".into(),
+            aaguid: Some("ea9b8d66-4d01-1d21-3ce4-b6b48cb575d4".into()),
+            user_verified: true,
+            authenticated_at: 60,
+        }
+    }
+
+    #[test]
+    fn passkey_claims_should_include_amr_aaguid_and_uv() {
+        let claims = passkey_claims(&authentication(), DEFAULT_AMR_CLAIM);
+        assert_eq!(claims["amr"], json!(["webauthn"]));
+        assert_eq!(claims["passkey_aaguid"], "ea9b8d66-4d01-1d21-3ce4-b6b48cb575d4");
+        assert_eq!(claims["passkey_uv"], true);
+        assert_eq!(claims["passkey_auth_time"], 60);
+
+        let unknown = PasskeyAuthenticationItem { aaguid: None, ..authentication() };
+        let claims = passkey_claims(&unknown, "custom:amr");
+        assert_eq!(claims["custom:amr"], json!(["webauthn"]));
+        assert!(!claims.contains_key("passkey_aaguid"));
+    }
+
+    #[test]
+    fn add_id_token_claims_should_stringify_claims_of_v1_event() {
+        let mut event = json!({
+            "version": "1",
+            "triggerSource": "TokenGeneration_Authentication",
+            "response": { "claimsOverrideDetails": null },
+        });
+        add_id_token_claims(&mut event, passkey_claims(&authentication(), "amr")).unwrap();
+        let claims = &event["response"]["claimsOverrideDetails"]["claimsToAddOrOverride"];
+        assert_eq!(claims["amr"], "webauthn");
+        assert_eq!(claims["passkey_uv"], "true");
+        assert_eq!(claims["passkey_auth_time"], "60");
+    }
+
+    #[test]
+    fn add_id_token_claims_should_keep_json_claims_of_v2_event() {
+        let mut event = json!({
+            "version": "2",
+            "triggerSource": "TokenGeneration_Authentication",
+            "response": {
+                "claimsAndScopeOverrideDetails": {
+                    "idTokenGeneration": {
+                        "claimsToAddOrOverride": { "tenant": "acme" },
+                    },
+                },
+            },
+        });
+        add_id_token_claims(&mut event, passkey_claims(&authentication(), "amr")).unwrap();
+        let claims = &event["response"]["claimsAndScopeOverrideDetails"]
+            ["idTokenGeneration"]["claimsToAddOrOverride"];
+        assert_eq!(claims["tenant"], "acme");
+        assert_eq!(claims["amr"], json!(["webauthn"]));
+        assert_eq!(claims["passkey_uv"], true);
+    }
+}
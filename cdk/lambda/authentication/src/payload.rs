@@ -0,0 +1,293 @@
+//! Request payloads.
+//!
+//! Validates the size and shape of JSON request bodies, so that malformed
+//! requests end with a client error instead of an internal server error.
+
+use lambda_http::{Body, Response, http::StatusCode};
+use serde::{Serialize, de::DeserializeOwned};
+use std::env;
+use thiserror::{Error as ThisError};
+
+use crate::config;
+use crate::error::Error;
+
+/// Default maximum size of a request body in bytes.
+pub const DEFAULT_MAX_BODY_SIZE: usize = 32 * 1024;
+
+/// Loads the maximum size of a request body.
+///
+/// You can specify to `MAX_BODY_SIZE` environment variable the maximum size in
+/// bytes.
+///
+/// Defaults to [`DEFAULT_MAX_BODY_SIZE`].
+pub fn load_max_body_size() -> Result<usize, Error> {
+    match config::var("MAX_BODY_SIZE") {
+        Ok(size) => size.parse()
+            .ok()
+            .filter(|size| *size > 0)
+            .ok_or(Error::BadEnvironmentVariable("MAX_BODY_SIZE", size)),
+        Err(env::VarError::NotPresent) => Ok(DEFAULT_MAX_BODY_SIZE),
+        Err(env::VarError::NotUnicode(size)) => Err(
+            Error::BadEnvironmentVariable(
+                "MAX_BODY_SIZE",
+                size.to_string_lossy().into(),
+            ),
+        ),
+    }
+}
+
+/// Error on a request payload.
+#[derive(Clone, Debug, Eq, PartialEq, ThisError)]
+pub enum PayloadError {
+    /// The body exceeds the maximum size.
+    #[error("request body of {size} bytes exceeds the limit of {limit} bytes")]
+    TooLarge {
+        /// Size of the body.
+        size: usize,
+        /// Maximum size.
+        limit: usize,
+    },
+
+    /// The body is empty.
+    #[error("request body is required")]
+    Missing,
+
+    /// The body is not valid JSON or does not match the expected shape.
+    #[error("malformed request body at {}: {message}", .field.as_deref().unwrap_or("."))]
+    Malformed {
+        /// Path to the offending field; e.g., `userInfo.username`.
+        ///
+        /// `None` if the error is not associated with a specific field.
+        field: Option<String>,
+        /// Description of the error.
+        message: String,
+    },
+}
+
+/// Error codes in the [`ErrorResponseBody`] of the registration API.
+///
+/// Exported as the `ErrorCode` type of the TypeScript types. See
+/// [`crate::typescript`].
+pub const ERROR_CODES: &[&str] = &[
+    // common to the HTTP APIs
+    "bad_request",
+    "payload_too_large",
+    "missing_payload",
+    "malformed_payload",
+    "not_found",
+    "method_not_allowed",
+    "unsupported_media_type",
+    "unauthenticated",
+    "session_expired",
+    "verification_failed",
+    "user_verification_required",
+    "not_allowed",
+    "not_configured",
+    "internal_error",
+    "unsupported_version",
+    "unknown_tenant",
+    "too_many_requests",
+    "credential_locked",
+    "captcha_required",
+    "step_up_required",
+    "authentication_denied",
+    "credential_limit_exceeded",
+    // specific to the registration API
+    "user_exists",
+    "credential_exists",
+    "conflict",
+    "invalid_recovery_code",
+    "invalid_recovery_link",
+];
+
+/// Body of a response to a bad request.
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorResponseBody {
+    /// Error code.
+    ///
+    /// One of [`ERROR_CODES`].
+    #[cfg_attr(feature = "typescript", ts(type = "ErrorCode"))]
+    pub error: &'static str,
+
+    /// Description of the error.
+    pub message: String,
+
+    /// Path to the offending field.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "typescript", ts(optional))]
+    pub field: Option<String>,
+
+    /// Message to show to the user in the language that the client prefers.
+    ///
+    /// Filled in on the way out of the function; see [`crate::i18n`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "typescript", ts(optional))]
+    pub localized_message: Option<String>,
+
+    /// Correlation ID of the request.
+    ///
+    /// Filled in on the way out of the function; see [`crate::correlation`].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "typescript", ts(optional))]
+    pub request_id: Option<String>,
+}
+
+impl PayloadError {
+    /// Status code of the response.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            PayloadError::TooLarge { .. } => StatusCode::PAYLOAD_TOO_LARGE,
+            PayloadError::Missing | PayloadError::Malformed { .. } =>
+                StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// Body of the response.
+    pub fn response_body(&self) -> ErrorResponseBody {
+        match self {
+            PayloadError::TooLarge { .. } => ErrorResponseBody {
+                error: "payload_too_large",
+                message: self.to_string(),
+                field: None,
+                localized_message: None,
+                request_id: None,
+            },
+            PayloadError::Missing => ErrorResponseBody {
+                error: "missing_payload",
+                message: self.to_string(),
+                field: None,
+                localized_message: None,
+                request_id: None,
+            },
+            PayloadError::Malformed { field, message } => ErrorResponseBody {
+                error: "malformed_payload",
+                message: message.clone(),
+                field: field.clone(),
+                localized_message: None,
+                request_id: None,
+            },
+        }
+    }
+
+    /// Converts into a JSON response.
+    pub fn into_response(self) -> Result<Response<Body>, lambda_http::Error> {
+        let body = serde_json::to_string(&self.response_body())?;
+        Ok(Response::builder()
+            .status(self.status_code())
+            .header("Content-Type", "application/json")
+            .body(body.into())?)
+    }
+}
+
+/// Parses a JSON request body.
+///
+/// Fails with [`PayloadError::TooLarge`] before parsing if the body exceeds
+/// `max_size` bytes.
+pub fn parse_json_payload<T>(body: &[u8], max_size: usize) -> Result<T, PayloadError>
+where
+    T: DeserializeOwned,
+{
+    if body.len() > max_size {
+        return Err(PayloadError::TooLarge {
+            size: body.len(),
+            limit: max_size,
+        });
+    }
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Err(PayloadError::Missing);
+    }
+    let deserializer = &mut serde_json::Deserializer::from_slice(body);
+    serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let path = e.path().to_string();
+        PayloadError::Malformed {
+            // the root path is "."
+            field: Some(path).filter(|p| p != "."),
+            message: e.into_inner().to_string(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    #[allow(dead_code)]
+    struct UserInfo {
+        username: String,
+        display_name: String,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    #[allow(dead_code)]
+    struct Session {
+        session_id: String,
+        user_info: UserInfo,
+    }
+
+    #[test]
+    fn parse_json_payload_should_parse_valid_payload() {
+        let body = br#"{"username":"test","displayName":"Test"}"#;
+        let user_info: UserInfo = parse_json_payload(body, 1024).unwrap();
+        assert_eq!(user_info.username, "test");
+    }
+
+    #[test]
+    fn parse_json_payload_should_reject_oversized_payload() {
+        let body = br#"{"username":"test","displayName":"Test"}"#;
+        assert_eq!(
+            parse_json_payload::<UserInfo>(body, 8).unwrap_err(),
+            PayloadError::TooLarge { size: body.len(), limit: 8 },
+        );
+    }
+
+    #[test]
+    fn parse_json_payload_should_reject_empty_payload() {
+        assert_eq!(
+            parse_json_payload::<UserInfo>(b"", 1024).unwrap_err(),
+            PayloadError::Missing,
+        );
+        assert_eq!(
+            parse_json_payload::<UserInfo>(b" \n", 1024).unwrap_err(),
+            PayloadError::Missing,
+        );
+    }
+
+    #[test]
+    fn parse_json_payload_should_report_offending_field() {
+        let body = br#"{"sessionId":"s","userInfo":{"username":1,"displayName":"Test"}}"#;
+        match parse_json_payload::<Session>(body, 1024).unwrap_err() {
+            PayloadError::Malformed { field, .. } => {
+                assert_eq!(field.as_deref(), Some("userInfo.username"));
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn parse_json_payload_should_report_missing_field() {
+        let body = br#"{"username":"test"}"#;
+        match parse_json_payload::<UserInfo>(body, 1024).unwrap_err() {
+            PayloadError::Malformed { message, .. } => {
+                assert!(message.contains("displayName"));
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn payload_error_status_code_should_distinguish_too_large_payload() {
+        assert_eq!(
+            PayloadError::TooLarge { size: 2, limit: 1 }.status_code(),
+            StatusCode::PAYLOAD_TOO_LARGE,
+        );
+        assert_eq!(PayloadError::Missing.status_code(), StatusCode::BAD_REQUEST);
+    }
+}
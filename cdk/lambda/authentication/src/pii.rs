@@ -0,0 +1,324 @@
+//! Field-level encryption of personally identifiable information (PII).
+//!
+//! For deployments with strict PII requirements, [`PiiProtection`] encrypts
+//! the username and display name in the user and credential items of the
+//! credential table. An item is sealed with a data key generated by AWS KMS,
+//! like sessions in [`crate::session_crypto`]:
+//! - `username` is replaced with a deterministic index, the "base64url"-
+//!   encoded HMAC-SHA256 of the username keyed with a secret, so that the
+//!   username index still resolves usernames into user handles
+//! - `sealedUsername` and `sealedDisplayName` are the username and display
+//!   name encrypted with AES-256-GCM, bound to the primary key of the item
+//! - `piiDataKey` is the data key encrypted by KMS
+//!
+//! [`crate::users::UserDirectory`] seals items when it writes them and opens
+//! them when it reads them, so the rest of the code sees plaintext. Every
+//! sealed item costs a KMS `Decrypt` call when it is read.
+//!
+//! Items written before the protection was enabled are read as they are, but
+//! cannot be looked up by username. Export the credential table and import it
+//! into a new table to seal them; see [`crate::migration`]. The secret of the
+//! index must never change, otherwise no existing user can be looked up.
+//!
+//! Registration sessions, which hold the username and display name of a new
+//! user, are sealed with the same KMS key unless `SESSION_KMS_KEY_ARN` is
+//! configured; see [`PiiProtection::session_encryption`].
+//!
+//! The protection does not apply to [`crate::sql`].
+
+use aws_sdk_dynamodb::{primitives::Blob, types::AttributeValue};
+use base64::{
+    Engine as _,
+    engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
+};
+use ring::hmac;
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::config;
+use crate::error::Error;
+use crate::items::Item;
+use crate::secrets::{SecretCache, load_secret_cache_ttl};
+use crate::session_crypto::{DataKey, SESSION_PURPOSE, SessionEncryption};
+
+/// Purpose of the data keys of PII.
+pub const PII_PURPOSE: &str = "passkey-test-pii";
+
+/// Name of the attribute of the data key encrypted by KMS.
+pub const DATA_KEY_ATTRIBUTE: &str = "piiDataKey";
+
+// attributes sealed in an item, and the attributes of their ciphertexts.
+const SEALED_ATTRIBUTES: &[(&str, &str)] = &[
+    ("username", "sealedUsername"),
+    ("displayName", "sealedDisplayName"),
+];
+
+// attribute that keeps the index of the username.
+const INDEXED_ATTRIBUTE: &str = "username";
+
+/// Field-level encryption of PII.
+#[derive(Clone)]
+pub struct PiiProtection {
+    encryption: SessionEncryption,
+    kms: aws_sdk_kms::Client,
+    key_id: String,
+    index_secret_id: String,
+    secrets: Arc<SecretCache>,
+}
+
+impl fmt::Debug for PiiProtection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PiiProtection")
+            .field("key_id", &self.key_id)
+            .field("index_secret_id", &self.index_secret_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Loads the protection of PII.
+///
+/// You can specify the following environment variables:
+/// - `PII_KMS_KEY_ARN`: ARN of the KMS key that encrypts data keys
+/// - `PII_INDEX_SECRET_ID`: ID of the secret in Secrets Manager that keys the
+///   index of usernames
+///
+/// Returns `None` if neither is set, which means PII is stored in plaintext.
+/// Fails if only one of them is set.
+pub fn load_pii_protection(
+    kms: aws_sdk_kms::Client,
+    secrets: aws_sdk_secretsmanager::Client,
+) -> Result<Option<PiiProtection>, Error> {
+    let key_id = load_var("PII_KMS_KEY_ARN")?;
+    let index_secret_id = load_var("PII_INDEX_SECRET_ID")?;
+    match (key_id, index_secret_id) {
+        (Some(key_id), Some(index_secret_id)) => Ok(Some(PiiProtection {
+            encryption: SessionEncryption::new(kms.clone(), key_id.clone(), PII_PURPOSE),
+            kms,
+            key_id,
+            index_secret_id,
+            secrets: Arc::new(SecretCache::new(secrets, load_secret_cache_ttl()?)),
+        })),
+        (None, None) => Ok(None),
+        (Some(_), None) => Err(
+            Error::BadEnvironmentVariable("PII_INDEX_SECRET_ID", "".into()),
+        ),
+        (None, Some(_)) => Err(
+            Error::BadEnvironmentVariable("PII_KMS_KEY_ARN", "".into()),
+        ),
+    }
+}
+
+// loads a non-empty environment variable.
+fn load_var(name: &'static str) -> Result<Option<String>, Error> {
+    match config::var(name) {
+        Ok(value) if value.is_empty() => Ok(None),
+        Ok(value) => Ok(Some(value)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(value)) => Err(
+            Error::BadEnvironmentVariable(name, value.to_string_lossy().into()),
+        ),
+    }
+}
+
+impl PiiProtection {
+    /// Returns the index of a given username.
+    ///
+    /// `username` must be normalized and qualified with the tenant ID if
+    /// tenants are configured.
+    pub async fn index(&self, username: &str) -> Result<String, Error> {
+        let secret = self.secrets.get(&self.index_secret_id).await?;
+        Ok(index_with(secret.as_bytes(), username))
+    }
+
+    /// Envelope encryption of sessions with the KMS key of PII.
+    ///
+    /// Data keys are generated for sessions, and cannot decrypt PII.
+    pub fn session_encryption(&self) -> SessionEncryption {
+        SessionEncryption::new(self.kms.clone(), self.key_id.clone(), SESSION_PURPOSE)
+    }
+
+    /// Seals the PII attributes of given items under a single data key.
+    ///
+    /// Every item must have the primary key. Items without any PII attribute
+    /// are returned as they are. No data key is generated unless any item has
+    /// a PII attribute.
+    pub async fn seal_items(&self, mut items: Vec<Item>) -> Result<Vec<Item>, Error> {
+        if !items.iter().any(has_pii) {
+            return Ok(items);
+        }
+        let secret = self.secrets.get(&self.index_secret_id).await?;
+        let data_key = self.encryption.generate_data_key().await?;
+        for item in items.iter_mut().filter(|item| has_pii(item)) {
+            seal_attributes(&data_key, secret.as_bytes(), item)?;
+        }
+        Ok(items)
+    }
+
+    /// Seals the PII attributes of a given item.
+    ///
+    /// See [`PiiProtection::seal_items`].
+    pub async fn seal_item(&self, item: Item) -> Result<Item, Error> {
+        self.seal_items(vec![item])
+            .await?
+            .pop()
+            .ok_or(Error::Encryption("no sealed item"))
+    }
+
+    /// Opens the PII attributes of a given item.
+    ///
+    /// Returns the item as it is unless it is sealed.
+    pub async fn open_item(&self, mut item: Item) -> Result<Item, Error> {
+        let Some(encrypted) = item.get(DATA_KEY_ATTRIBUTE) else {
+            return Ok(item);
+        };
+        let encrypted = encrypted.as_b()
+            .or(Err(Error::BadItemAttribute(DATA_KEY_ATTRIBUTE)))?;
+        let data_key = self.encryption.decrypt_data_key(encrypted.as_ref()).await?;
+        open_attributes(&data_key, &mut item)?;
+        Ok(item)
+    }
+}
+
+/// Returns whether a given item has sealed PII attributes.
+pub fn is_sealed(item: &Item) -> bool {
+    item.contains_key(DATA_KEY_ATTRIBUTE)
+}
+
+// returns whether a given item has any PII attribute in plaintext.
+fn has_pii(item: &Item) -> bool {
+    SEALED_ATTRIBUTES.iter().any(|(name, _)| item.contains_key(*name))
+}
+
+/// Returns the index of a given username keyed with a given secret.
+pub fn index_with(secret: &[u8], username: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+    base64url.encode(hmac::sign(&key, username.as_bytes()).as_ref())
+}
+
+/// Seals the PII attributes of an item under a given data key.
+///
+/// The plaintext attributes are moved to the sealed ones, and `username` is
+/// replaced with the index keyed with `index_secret`. The ciphertexts are
+/// bound to the primary key of the item.
+pub fn seal_attributes(
+    data_key: &DataKey,
+    index_secret: &[u8],
+    item: &mut Item,
+) -> Result<(), Error> {
+    let key = primary_key_aad(item)?;
+    for (name, sealed_name) in SEALED_ATTRIBUTES {
+        let Some(value) = item.remove(*name) else {
+            continue;
+        };
+        let value = value.as_s().or(Err(Error::BadItemAttribute(*name)))?;
+        let sealed = data_key.seal(
+            sealed_attribute_aad(&key, name).as_bytes(),
+            value.as_bytes(),
+        )?;
+        item.insert(sealed_name.to_string(), AttributeValue::B(Blob::new(sealed)));
+        if *name == INDEXED_ATTRIBUTE {
+            item.insert(
+                INDEXED_ATTRIBUTE.into(),
+                AttributeValue::S(index_with(index_secret, value)),
+            );
+        }
+    }
+    item.insert(
+        DATA_KEY_ATTRIBUTE.into(),
+        AttributeValue::B(Blob::new(data_key.encrypted())),
+    );
+    Ok(())
+}
+
+/// Opens the sealed PII attributes of an item with a given data key.
+///
+/// The sealed attributes are replaced with the plaintext ones, and the data
+/// key is removed.
+pub fn open_attributes(data_key: &DataKey, item: &mut Item) -> Result<(), Error> {
+    let key = primary_key_aad(item)?;
+    for (name, sealed_name) in SEALED_ATTRIBUTES {
+        let Some(sealed) = item.remove(*sealed_name) else {
+            continue;
+        };
+        let sealed = sealed.as_b().or(Err(Error::BadItemAttribute(*sealed_name)))?;
+        let value = data_key.open(
+            sealed_attribute_aad(&key, name).as_bytes(),
+            sealed.as_ref(),
+        )?;
+        let value = String::from_utf8(value)
+            .or(Err(Error::Encryption("sealed attribute is not UTF-8")))?;
+        item.insert(name.to_string(), AttributeValue::S(value));
+    }
+    item.remove(DATA_KEY_ATTRIBUTE);
+    Ok(())
+}
+
+// part of the additional authenticated data that binds a ciphertext to the
+// primary key of an item.
+fn primary_key_aad(item: &Item) -> Result<String, Error> {
+    let pk = item.get("pk")
+        .and_then(|pk| pk.as_s().ok())
+        .ok_or(Error::BadItemAttribute("pk"))?;
+    let sk = item.get("sk")
+        .and_then(|sk| sk.as_s().ok())
+        .ok_or(Error::BadItemAttribute("sk"))?;
+    Ok(format!("{}#{}", pk, sk))
+}
+
+// additional authenticated data for a sealed attribute.
+fn sealed_attribute_aad(key: &str, name: &str) -> String {
+    format!("{}#{}", key, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn data_key() -> DataKey {
+        DataKey::new(vec![7u8; 32], b"encrypted".to_vec()).unwrap()
+    }
+
+    fn user_item() -> Item {
+        HashMap::from([
+            ("pk".to_string(), AttributeValue::S("user#AAAA".into())),
+            ("sk".into(), AttributeValue::S("user".into())),
+            ("username".into(), AttributeValue::S("alice".into())),
+            ("displayName".into(), AttributeValue::S("Alice".into())),
+            ("cognitoSub".into(), AttributeValue::S("sub".into())),
+        ])
+    }
+
+    #[test]
+    fn index_with_should_be_deterministic_per_secret() {
+        assert_eq!(index_with(b"secret", "alice"), index_with(b"secret", "alice"));
+        assert_ne!(index_with(b"secret", "alice"), index_with(b"secret", "bob"));
+        assert_ne!(index_with(b"secret", "alice"), index_with(b"other", "alice"));
+    }
+
+    #[test]
+    fn seal_attributes_should_index_username_and_open_to_plaintext() {
+        let key = data_key();
+        let mut item = user_item();
+        seal_attributes(&key, b"secret", &mut item).unwrap();
+        assert!(is_sealed(&item));
+        assert!(!item.contains_key("displayName"));
+        assert_eq!(
+            item["username"],
+            AttributeValue::S(index_with(b"secret", "alice")),
+        );
+        assert_eq!(item["cognitoSub"], AttributeValue::S("sub".into()));
+        open_attributes(&key, &mut item).unwrap();
+        assert_eq!(item, user_item());
+    }
+
+    #[test]
+    fn open_attributes_should_fail_for_item_with_another_key() {
+        let key = data_key();
+        let mut item = user_item();
+        seal_attributes(&key, b"secret", &mut item).unwrap();
+        item.insert("pk".into(), AttributeValue::S("user#BBBB".into()));
+        assert!(open_attributes(&key, &mut item).is_err());
+    }
+}
@@ -0,0 +1,554 @@
+//! Policies on Web Authentication ceremonies.
+
+use serde::de::DeserializeOwned;
+use std::env;
+use webauthn_rs_proto::options::{
+    AttestationConveyancePreference,
+    AuthenticatorAttachment,
+    PubKeyCredParams,
+    ResidentKeyRequirement,
+};
+
+use crate::api_error::ApiError;
+use crate::config;
+use crate::error::Error;
+use crate::items::CredentialItem;
+
+/// Loads the authenticator attachment policy.
+///
+/// You can specify to `AUTHENTICATOR_ATTACHMENT` environment variable one of
+/// the following values:
+/// - "platform": passkeys on platform authenticators only
+/// - "cross-platform": roaming authenticators like security keys only
+///
+/// Returns `None` if `AUTHENTICATOR_ATTACHMENT` is not set, which means any
+/// authenticator is allowed unless a request asks for a specific one.
+///
+/// The policy is advisory. It is requested in the creation options, and a
+/// well-behaved client honors it, but the attachment a client reports is not
+/// covered by any signature. Never use it as a security control; e.g., to
+/// keep credentials on roaming authenticators from authenticating.
+pub fn load_authenticator_attachment_policy(
+) -> Result<Option<AuthenticatorAttachment>, Error> {
+    load_env_policy("AUTHENTICATOR_ATTACHMENT")
+}
+
+/// Parses an authenticator attachment.
+///
+/// Accepts "platform" or "cross-platform".
+pub fn parse_authenticator_attachment(
+    attachment: impl Into<String>,
+) -> Result<AuthenticatorAttachment, Error> {
+    let attachment = attachment.into();
+    serde_json::from_value(serde_json::Value::String(attachment))
+        .or(Err(Error::Inconvertible("unknown authenticator attachment")))
+}
+
+/// Returns the name of a given authenticator attachment.
+pub fn authenticator_attachment_name(
+    attachment: AuthenticatorAttachment,
+) -> &'static str {
+    match attachment {
+        AuthenticatorAttachment::Platform => "platform",
+        AuthenticatorAttachment::CrossPlatform => "cross-platform",
+    }
+}
+
+/// Resolves the authenticator attachment for a registration.
+///
+/// A requested attachment is taken if the policy does not specify one.
+/// Fails if the requested attachment conflicts with the policy.
+pub fn resolve_authenticator_attachment(
+    policy: Option<AuthenticatorAttachment>,
+    requested: Option<AuthenticatorAttachment>,
+) -> Result<Option<AuthenticatorAttachment>, Error> {
+    match (policy, requested) {
+        (Some(policy), Some(requested)) if policy != requested => Err(
+            Error::PolicyViolation("authenticator attachment not allowed"),
+        ),
+        (Some(policy), _) => Ok(Some(policy)),
+        (None, requested) => Ok(requested),
+    }
+}
+
+/// Returns whether an authenticator attachment satisfies a required one.
+///
+/// An unknown attachment never satisfies a specific requirement. An
+/// attachment reported by a client is advisory; see
+/// [`load_authenticator_attachment_policy`].
+pub fn satisfies_authenticator_attachment(
+    required: Option<AuthenticatorAttachment>,
+    actual: Option<AuthenticatorAttachment>,
+) -> bool {
+    required.is_none() || actual == required
+}
+
+/// Loads the resident key requirement.
+///
+/// You can specify to `RESIDENT_KEY` environment variable one of the following
+/// values:
+/// - "required"
+/// - "preferred"
+/// - "discouraged"
+///
+/// Defaults to "required", because the usernameless authentication depends on
+/// discoverable credentials.
+pub fn load_resident_key_requirement() -> Result<ResidentKeyRequirement, Error> {
+    load_env_policy("RESIDENT_KEY")
+        .map(|policy| policy.unwrap_or(ResidentKeyRequirement::Required))
+}
+
+/// Returns whether the `rk` property of the `credProps` extension satisfies a
+/// given resident key requirement.
+///
+/// Only [`ResidentKeyRequirement::Required`] demands a resident key.
+/// A missing `credProps` extension is tolerated because not every client
+/// supports it.
+pub fn satisfies_resident_key_requirement(
+    requirement: ResidentKeyRequirement,
+    rk: Option<bool>,
+) -> bool {
+    requirement != ResidentKeyRequirement::Required || rk != Some(false)
+}
+
+/// Loads the attestation conveyance preference of passkey registrations.
+///
+/// You can specify to `ATTESTATION` environment variable one of the following
+/// values:
+/// - "none"
+/// - "indirect"
+/// - "direct"
+///
+/// Defaults to "none", which is what the Webauthn library requests for
+/// passkeys. Security key registrations always request direct attestation.
+pub fn load_attestation_conveyance_preference(
+) -> Result<AttestationConveyancePreference, Error> {
+    load_env_policy("ATTESTATION")
+        .map(|policy| policy.unwrap_or(AttestationConveyancePreference::None))
+}
+
+/// Returns whether the attestation of a new credential satisfies a given
+/// attestation conveyance preference.
+///
+/// Only [`AttestationConveyancePreference::Direct`] demands an attestation
+/// statement. "indirect" tolerates a missing one because the client may
+/// replace it with none.
+pub fn satisfies_attestation_conveyance(
+    preference: AttestationConveyancePreference,
+    attested: bool,
+) -> bool {
+    preference != AttestationConveyancePreference::Direct || attested
+}
+
+/// Default timeout of a ceremony in seconds.
+pub const DEFAULT_CHALLENGE_TIMEOUT: u32 = 60;
+
+/// Maximum timeout of a ceremony in seconds.
+///
+/// The longest timeout that the Web Authentication specification recommends.
+pub const MAX_CHALLENGE_TIMEOUT: u32 = 600;
+
+/// Timeout of a ceremony.
+///
+/// The same timeout is given to the client as the `timeout` of the options,
+/// and to the session that caches the state of the ceremony as its time to
+/// live, so that the session lasts exactly as long as the client waits.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ChallengeTimeout {
+    seconds: u32,
+}
+
+impl ChallengeTimeout {
+    /// Creates a timeout of given seconds.
+    ///
+    /// Returns `None` unless `1 <= seconds <=` [`MAX_CHALLENGE_TIMEOUT`].
+    pub fn from_secs(seconds: u32) -> Option<Self> {
+        (1..=MAX_CHALLENGE_TIMEOUT)
+            .contains(&seconds)
+            .then_some(Self { seconds })
+    }
+
+    /// Timeout in milliseconds, which is the unit of the `timeout` of the
+    /// options.
+    pub fn as_millis(self) -> u32 {
+        self.seconds * 1000
+    }
+
+    /// Returns the TTL of a session that starts at a given Unix time.
+    pub fn session_ttl(self, now: i64) -> i64 {
+        now + i64::from(self.seconds)
+    }
+}
+
+impl Default for ChallengeTimeout {
+    fn default() -> Self {
+        Self { seconds: DEFAULT_CHALLENGE_TIMEOUT }
+    }
+}
+
+/// Loads the timeout of ceremonies.
+///
+/// You can specify to `CHALLENGE_TIMEOUT` environment variable the timeout in
+/// seconds up to [`MAX_CHALLENGE_TIMEOUT`].
+///
+/// Defaults to [`DEFAULT_CHALLENGE_TIMEOUT`].
+pub fn load_challenge_timeout() -> Result<ChallengeTimeout, Error> {
+    match config::var("CHALLENGE_TIMEOUT") {
+        Ok(timeout) => timeout.parse()
+            .ok()
+            .and_then(ChallengeTimeout::from_secs)
+            .ok_or(Error::BadEnvironmentVariable("CHALLENGE_TIMEOUT", timeout)),
+        Err(env::VarError::NotPresent) => Ok(ChallengeTimeout::default()),
+        Err(env::VarError::NotUnicode(timeout)) => Err(
+            Error::BadEnvironmentVariable(
+                "CHALLENGE_TIMEOUT",
+                timeout.to_string_lossy().into(),
+            ),
+        ),
+    }
+}
+
+/// Maximum number of credentials per user.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CredentialLimit {
+    max: usize,
+}
+
+impl CredentialLimit {
+    /// Creates a limit of a given number of credentials.
+    ///
+    /// Returns `None` if `max` is zero.
+    pub fn new(max: usize) -> Option<Self> {
+        (max > 0).then_some(Self { max })
+    }
+
+    /// Maximum number of credentials.
+    pub fn max(self) -> usize {
+        self.max
+    }
+
+    /// Checks whether a user with given credentials may register another
+    /// credential.
+    ///
+    /// Deleted credentials do not count, because they are purged after the
+    /// retention window.
+    pub fn check(self, credentials: &[CredentialItem]) -> Result<(), ApiError> {
+        let count = credentials.iter()
+            .filter(|c| c.deleted_at.is_none())
+            .count();
+        if count < self.max {
+            Ok(())
+        } else {
+            Err(ApiError::CredentialLimitExceeded(self.max))
+        }
+    }
+}
+
+/// Loads the maximum number of credentials per user.
+///
+/// You can specify to `MAX_CREDENTIALS_PER_USER` environment variable a
+/// positive number of credentials.
+///
+/// Returns `None` if `MAX_CREDENTIALS_PER_USER` is not set, which means no
+/// limit.
+pub fn load_credential_limit() -> Result<Option<CredentialLimit>, Error> {
+    match config::var("MAX_CREDENTIALS_PER_USER") {
+        Ok(max) => max.parse()
+            .ok()
+            .and_then(CredentialLimit::new)
+            .map(Some)
+            .ok_or(Error::BadEnvironmentVariable("MAX_CREDENTIALS_PER_USER", max)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(max)) => Err(
+            Error::BadEnvironmentVariable(
+                "MAX_CREDENTIALS_PER_USER",
+                max.to_string_lossy().into(),
+            ),
+        ),
+    }
+}
+
+/// Allowlist of the COSE algorithms of public keys.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AlgorithmAllowlist {
+    algorithms: Vec<i64>,
+}
+
+impl AlgorithmAllowlist {
+    /// Returns whether a given COSE algorithm identifier is allowed.
+    pub fn allows(&self, algorithm: i64) -> bool {
+        self.algorithms.contains(&algorithm)
+    }
+
+    /// Removes disallowed algorithms from the `pubKeyCredParams` of creation
+    /// options.
+    ///
+    /// The options offer no algorithm if the Webauthn library supports none
+    /// of the allowed ones.
+    pub fn filter(&self, params: &mut Vec<PubKeyCredParams>) {
+        params.retain(|p| self.allows(p.alg));
+    }
+}
+
+/// Loads the allowlist of the COSE algorithms of public keys.
+///
+/// You can specify to `ALLOWED_ALGORITHMS` environment variable a
+/// comma-separated list of algorithm names or COSE algorithm identifiers;
+/// e.g., "ES256,EdDSA" or "-7,-8". The following names are accepted:
+/// - "ES256", "ES384", "ES512"
+/// - "EdDSA", which covers Ed25519
+/// - "RS256", "RS384", "RS512"
+/// - "PS256", "PS384", "PS512"
+///
+/// Returns `None` if `ALLOWED_ALGORITHMS` is not set, which means every
+/// algorithm that the Webauthn library supports is allowed.
+pub fn load_algorithm_allowlist() -> Result<Option<AlgorithmAllowlist>, Error> {
+    match config::var("ALLOWED_ALGORITHMS") {
+        Ok(algorithms) => parse_algorithm_allowlist(&algorithms)
+            .map(Some)
+            .ok_or(Error::BadEnvironmentVariable("ALLOWED_ALGORITHMS", algorithms)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(algorithms)) => Err(
+            Error::BadEnvironmentVariable(
+                "ALLOWED_ALGORITHMS",
+                algorithms.to_string_lossy().into(),
+            ),
+        ),
+    }
+}
+
+fn parse_algorithm_allowlist(algorithms: &str) -> Option<AlgorithmAllowlist> {
+    let algorithms = algorithms.split(',')
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .map(parse_cose_algorithm)
+        .collect::<Option<Vec<_>>>()?;
+    (!algorithms.is_empty()).then_some(AlgorithmAllowlist { algorithms })
+}
+
+// COSE algorithm identifier of a given name or identifier.
+fn parse_cose_algorithm(algorithm: &str) -> Option<i64> {
+    match algorithm {
+        "ES256" => Some(-7),
+        "ES384" => Some(-35),
+        "ES512" => Some(-36),
+        "EdDSA" => Some(-8),
+        "RS256" => Some(-257),
+        "RS384" => Some(-258),
+        "RS512" => Some(-259),
+        "PS256" => Some(-37),
+        "PS384" => Some(-38),
+        "PS512" => Some(-39),
+        _ => algorithm.parse().ok(),
+    }
+}
+
+// Loads a policy from an environment variable.
+//
+// `None` if the environment variable is not set.
+pub(crate) fn load_env_policy<T>(name: &'static str) -> Result<Option<T>, Error>
+where
+    T: DeserializeOwned,
+{
+    match config::var(name) {
+        Ok(policy) => parse_env_policy(name, policy).map(Some),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(policy)) => Err(
+            Error::BadEnvironmentVariable(name, policy.to_string_lossy().into()),
+        ),
+    }
+}
+
+// Parses a policy given to an environment variable as a JSON string.
+fn parse_env_policy<T>(
+    name: &'static str,
+    policy: impl Into<String>,
+) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+{
+    let policy = policy.into();
+    serde_json::from_value(serde_json::Value::String(policy.clone()))
+        .or(Err(Error::BadEnvironmentVariable(name, policy)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_authenticator_attachment_should_accept_known_attachments() {
+        assert_eq!(
+            parse_authenticator_attachment("platform").unwrap(),
+            AuthenticatorAttachment::Platform,
+        );
+        assert_eq!(
+            parse_authenticator_attachment("cross-platform").unwrap(),
+            AuthenticatorAttachment::CrossPlatform,
+        );
+        assert!(parse_authenticator_attachment("roaming").is_err());
+    }
+
+    #[test]
+    fn authenticator_attachment_name_should_round_trip() {
+        use AuthenticatorAttachment::*;
+        for attachment in [Platform, CrossPlatform] {
+            assert_eq!(
+                parse_authenticator_attachment(
+                    authenticator_attachment_name(attachment),
+                ).unwrap(),
+                attachment,
+            );
+        }
+    }
+
+    #[test]
+    fn resolve_authenticator_attachment_should_prefer_policy() {
+        use AuthenticatorAttachment::*;
+        assert_eq!(
+            resolve_authenticator_attachment(Some(Platform), None).unwrap(),
+            Some(Platform),
+        );
+        assert_eq!(
+            resolve_authenticator_attachment(Some(Platform), Some(Platform))
+                .unwrap(),
+            Some(Platform),
+        );
+        assert!(
+            resolve_authenticator_attachment(Some(Platform), Some(CrossPlatform))
+                .is_err(),
+        );
+        assert_eq!(
+            resolve_authenticator_attachment(None, Some(CrossPlatform)).unwrap(),
+            Some(CrossPlatform),
+        );
+        assert_eq!(resolve_authenticator_attachment(None, None).unwrap(), None);
+    }
+
+    #[test]
+    fn satisfies_authenticator_attachment_should_match_required_attachment() {
+        use AuthenticatorAttachment::*;
+        assert!(satisfies_authenticator_attachment(None, None));
+        assert!(satisfies_authenticator_attachment(None, Some(Platform)));
+        assert!(satisfies_authenticator_attachment(Some(Platform), Some(Platform)));
+        assert!(!satisfies_authenticator_attachment(Some(Platform), Some(CrossPlatform)));
+        assert!(!satisfies_authenticator_attachment(Some(Platform), None));
+    }
+
+    #[test]
+    fn satisfies_resident_key_requirement_should_reject_non_resident_key_only_if_required() {
+        use ResidentKeyRequirement::*;
+        assert!(satisfies_resident_key_requirement(Required, Some(true)));
+        assert!(!satisfies_resident_key_requirement(Required, Some(false)));
+        assert!(satisfies_resident_key_requirement(Required, None));
+        assert!(satisfies_resident_key_requirement(Preferred, Some(false)));
+        assert!(satisfies_resident_key_requirement(Discouraged, Some(false)));
+    }
+
+    #[test]
+    fn parse_env_policy_should_parse_resident_key_requirement() {
+        assert_eq!(
+            parse_env_policy::<ResidentKeyRequirement>("RESIDENT_KEY", "required")
+                .unwrap(),
+            ResidentKeyRequirement::Required,
+        );
+        assert_eq!(
+            parse_env_policy::<ResidentKeyRequirement>("RESIDENT_KEY", "discouraged")
+                .unwrap(),
+            ResidentKeyRequirement::Discouraged,
+        );
+        assert!(
+            parse_env_policy::<ResidentKeyRequirement>("RESIDENT_KEY", "yes")
+                .is_err(),
+        );
+    }
+
+    #[test]
+    fn challenge_timeout_should_accept_seconds_within_range() {
+        assert_eq!(ChallengeTimeout::from_secs(0), None);
+        assert_eq!(ChallengeTimeout::from_secs(1).unwrap().as_millis(), 1000);
+        assert_eq!(ChallengeTimeout::from_secs(600).unwrap().as_millis(), 600_000);
+        assert_eq!(ChallengeTimeout::from_secs(601), None);
+    }
+
+    #[test]
+    fn challenge_timeout_should_keep_session_ttl_in_sync() {
+        let timeout = ChallengeTimeout::from_secs(120).unwrap();
+        assert_eq!(timeout.as_millis(), 120_000);
+        assert_eq!(timeout.session_ttl(1_700_000_000), 1_700_000_120);
+        assert_eq!(ChallengeTimeout::default().as_millis(), 60_000);
+    }
+
+    #[test]
+    fn parse_env_policy_should_parse_attestation_conveyance_preference() {
+        assert_eq!(
+            parse_env_policy::<AttestationConveyancePreference>("ATTESTATION", "indirect")
+                .unwrap(),
+            AttestationConveyancePreference::Indirect,
+        );
+        assert!(
+            parse_env_policy::<AttestationConveyancePreference>("ATTESTATION", "enterprise")
+                .is_err(),
+        );
+    }
+
+    #[test]
+    fn satisfies_attestation_conveyance_should_demand_attestation_only_if_direct() {
+        use AttestationConveyancePreference::*;
+        assert!(satisfies_attestation_conveyance(None, false));
+        assert!(satisfies_attestation_conveyance(Indirect, false));
+        assert!(satisfies_attestation_conveyance(Direct, true));
+        assert!(!satisfies_attestation_conveyance(Direct, false));
+    }
+
+    fn credential(deleted_at: Option<&str>) -> CredentialItem {
+        CredentialItem {
+            disabled_at: deleted_at.map(Into::into),
+            deleted_at: deleted_at.map(Into::into),
+            ..CredentialItem::for_test("AAAA", "BBBB")
+        }
+    }
+
+    #[test]
+    fn credential_limit_should_not_count_deleted_credentials() {
+        assert_eq!(CredentialLimit::new(0), None);
+        let limit = CredentialLimit::new(2).unwrap();
+        assert!(limit.check(&[credential(None)]).is_ok());
+        assert_eq!(
+            limit.check(&[credential(None), credential(None)]),
+            Err(ApiError::CredentialLimitExceeded(2)),
+        );
+        assert!(
+            limit.check(&[
+                credential(None),
+                credential(Some("2024-01-02T00:00:00Z")),
+            ]).is_ok(),
+        );
+    }
+
+    #[test]
+    fn parse_algorithm_allowlist_should_accept_names_and_identifiers() {
+        let allowlist = parse_algorithm_allowlist("ES256, EdDSA").unwrap();
+        assert!(allowlist.allows(-7));
+        assert!(allowlist.allows(-8));
+        assert!(!allowlist.allows(-257));
+        assert_eq!(
+            parse_algorithm_allowlist("-7,-8"),
+            Some(allowlist),
+        );
+        assert!(parse_algorithm_allowlist("ES256,HS256").is_none());
+        assert!(parse_algorithm_allowlist("").is_none());
+    }
+
+    #[test]
+    fn algorithm_allowlist_should_filter_pub_key_cred_params() {
+        let allowlist = parse_algorithm_allowlist("ES256").unwrap();
+        let mut params = vec![
+            PubKeyCredParams { type_: "public-key".into(), alg: -7 },
+            PubKeyCredParams { type_: "public-key".into(), alg: -257 },
+        ];
+        allowlist.filter(&mut params);
+        assert_eq!(params.len(), 1);
+        assert_eq!(params[0].alg, -7);
+    }
+}
@@ -0,0 +1,184 @@
+//! Rate limiting.
+//!
+//! Counts requests in fixed windows with atomic counters in the DynamoDB
+//! table for sessions. Expired counters are removed by the TTL of the table.
+
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
+use base64::{
+    Engine as _,
+    engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
+};
+use lambda_http::{
+    Body,
+    Request,
+    RequestExt,
+    Response,
+    http::StatusCode,
+    request::RequestContext,
+};
+use ring::digest;
+use std::env;
+use tracing::{error, instrument};
+
+use crate::config;
+use crate::error::Error;
+use crate::items::SessionKey;
+use crate::payload::ErrorResponseBody;
+
+/// Rate limit.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RateLimit {
+    /// Maximum number of requests in a window.
+    pub limit: u64,
+
+    /// Length of a window in seconds.
+    pub window: u64,
+}
+
+impl RateLimit {
+    /// Returns the start of the window that includes a given time.
+    pub fn window_start(&self, now: i64) -> i64 {
+        now - now.rem_euclid(self.window as i64)
+    }
+
+    /// Returns the seconds to wait before the next window starts.
+    pub fn retry_after(&self, now: i64) -> u64 {
+        (self.window_start(now) + self.window as i64 - now) as u64
+    }
+}
+
+/// Loads a rate limit from an environment variable.
+///
+/// The value must be "<limit>/<window seconds>"; e.g., "10/60" allows 10
+/// requests per minute. "off" disables the rate limit.
+///
+/// Returns `default` if the environment variable is not set.
+pub fn load_rate_limit(
+    name: &'static str,
+    default: Option<RateLimit>,
+) -> Result<Option<RateLimit>, Error> {
+    match config::var(name) {
+        Ok(value) => parse_rate_limit(&value)
+            .ok_or(Error::BadEnvironmentVariable(name, value)),
+        Err(env::VarError::NotPresent) => Ok(default),
+        Err(env::VarError::NotUnicode(value)) => Err(
+            Error::BadEnvironmentVariable(name, value.to_string_lossy().into()),
+        ),
+    }
+}
+
+// parses "<limit>/<window seconds>" or "off".
+//
+// `Some(None)` for "off", and `None` if the value is malformed.
+pub(crate) fn parse_rate_limit(value: &str) -> Option<Option<RateLimit>> {
+    if value == "off" {
+        return Some(None);
+    }
+    let (limit, window) = value.split_once('/')?;
+    let limit = limit.trim().parse().ok().filter(|l| *l > 0)?;
+    let window = window.trim().parse().ok().filter(|w| *w > 0)?;
+    Some(Some(RateLimit { limit, window }))
+}
+
+/// Counts a request against a rate limit.
+///
+/// `scope` distinguishes what `key` represents; e.g., "ip" or "username".
+/// `key` is hashed before being stored.
+///
+/// Returns the seconds to wait if the request exceeds the limit.
+#[instrument(skip_all, fields(scope = scope))]
+pub async fn hit(
+    dynamodb: &aws_sdk_dynamodb::Client,
+    table_name: &str,
+    scope: &str,
+    key: &str,
+    rate_limit: &RateLimit,
+    now: i64,
+) -> Result<Option<u64>, Error> {
+    let window_start = rate_limit.window_start(now);
+    let key_hash = base64url.encode(digest::digest(&digest::SHA256, key.as_bytes()));
+    let ttl = window_start + rate_limit.window as i64;
+    let count: u64 = dynamodb
+        .update_item()
+        .table_name(table_name)
+        .key("pk", SessionKey::RateLimit {
+            scope,
+            key_hash: &key_hash,
+            window_start,
+        }.attribute())
+        .update_expression("ADD #count :one SET #ttl = if_not_exists(#ttl, :ttl)")
+        .expression_attribute_names("#count", "count")
+        .expression_attribute_names("#ttl", "ttl")
+        .expression_attribute_values(":one", AttributeValue::N("1".into()))
+        .expression_attribute_values(":ttl", AttributeValue::N(format!("{}", ttl)))
+        .return_values(ReturnValue::UpdatedNew)
+        .send()
+        .await
+        .map_err(|e| {
+            error!(?e, "counting request");
+            Error::Storage("failed to count request")
+        })?
+        .attributes
+        .and_then(|a| a.get("count").cloned())
+        .and_then(|c| c.as_n().ok().and_then(|c| c.parse().ok()))
+        .ok_or(Error::Storage("missing request count"))?;
+    if count > rate_limit.limit {
+        Ok(Some(rate_limit.retry_after(now)))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Returns the source IP address of a given request.
+pub fn source_ip(request: &Request) -> Option<String> {
+    match request.request_context_ref()? {
+        RequestContext::ApiGatewayV2(context) => context.http.source_ip.clone(),
+        RequestContext::ApiGatewayV1(context) => context.identity.source_ip.clone(),
+        _ => None,
+    }
+}
+
+/// Creates a 429 response with `Retry-After`.
+pub fn too_many_requests(
+    retry_after: u64,
+) -> Result<Response<Body>, lambda_http::Error> {
+    let body = serde_json::to_string(&ErrorResponseBody {
+        error: "too_many_requests",
+        message: format!("too many requests; retry after {} seconds", retry_after),
+        field: None,
+        localized_message: None,
+        request_id: None,
+    })?;
+    Ok(Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header("Content-Type", "application/json")
+        .header("Retry-After", retry_after.to_string())
+        .body(body.into())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rate_limit_should_parse_limit_and_window() {
+        assert_eq!(
+            parse_rate_limit("10/60"),
+            Some(Some(RateLimit { limit: 10, window: 60 })),
+        );
+        assert_eq!(parse_rate_limit("off"), Some(None));
+        assert_eq!(parse_rate_limit("10"), None);
+        assert_eq!(parse_rate_limit("0/60"), None);
+        assert_eq!(parse_rate_limit("10/0"), None);
+        assert_eq!(parse_rate_limit("ten/60"), None);
+    }
+
+    #[test]
+    fn rate_limit_should_compute_window_and_retry_after() {
+        let rate_limit = RateLimit { limit: 1, window: 60 };
+        assert_eq!(rate_limit.window_start(125), 120);
+        assert_eq!(rate_limit.retry_after(125), 55);
+        assert_eq!(rate_limit.window_start(120), 120);
+        assert_eq!(rate_limit.retry_after(120), 60);
+    }
+}
@@ -0,0 +1,168 @@
+//! Recovery codes.
+//!
+//! A user receives a set of one-time recovery codes when the account is
+//! created, and may use one of them to register a new passkey after losing
+//! all the authenticators.
+//! Only the hashes of unused codes are stored as a [`RecoveryCodesItem`];
+//! a code is bound to the user by hashing it together with the user handle.
+//!
+//! A user may also recover the account with a one-time token emailed in a
+//! recovery link; see [`crate::email`].
+//!
+//! [`RecoveryCodesItem`]: crate::items::RecoveryCodesItem
+
+use base64::{
+    Engine as _,
+    engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
+};
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+
+use crate::error::Error;
+use crate::items::RecoveryCodesItem;
+
+/// Number of random bytes in a recovery token.
+pub const RECOVERY_TOKEN_SIZE: usize = 32;
+
+/// Number of recovery codes in a set.
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+// Number of characters in a recovery code excluding the separator.
+const CODE_LENGTH: usize = 10;
+
+// Crockford's Base32 alphabet in lowercase, which has no ambiguous letters.
+const ALPHABET: &[u8; 32] = b"0123456789abcdefghjkmnpqrstvwxyz";
+
+/// Generates a set of recovery codes.
+///
+/// Each code consists of 10 characters (50 bits) separated by a hyphen in
+/// the middle; e.g., "7k2mq-x9d4r".
+pub fn generate_recovery_codes() -> Result<Vec<String>, Error> {
+    let rng = SystemRandom::new();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; CODE_LENGTH];
+            rng.fill(&mut bytes)
+                .or(Err(Error::Encryption("failed to generate recovery code")))?;
+            let code: String = bytes.iter()
+                .map(|b| ALPHABET[(b & 0x1F) as usize] as char)
+                .collect();
+            Ok(format!("{}-{}", &code[..CODE_LENGTH / 2], &code[CODE_LENGTH / 2..]))
+        })
+        .collect()
+}
+
+/// Generates a new set of recovery codes of a given user.
+///
+/// Returns the codes to show the user and the item to store, which replaces
+/// the existing codes.
+pub fn new_recovery_codes(
+    user_handle: &str,
+    created_at: String,
+) -> Result<(Vec<String>, RecoveryCodesItem), Error> {
+    let codes = generate_recovery_codes()?;
+    let item = RecoveryCodesItem {
+        user_handle: user_handle.into(),
+        code_hashes: codes.iter()
+            .map(|code| hash_recovery_code(user_handle, code))
+            .collect(),
+        created_at,
+    };
+    Ok((codes, item))
+}
+
+/// Normalizes a recovery code entered by a user.
+///
+/// Removes hyphens and whitespace, lowercases letters, and maps `i`, `l`,
+/// and `o` to the digits they resemble as Crockford's Base32 does.
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .map(|c| match c.to_ascii_lowercase() {
+            'i' | 'l' => '1',
+            'o' => '0',
+            c => c,
+        })
+        .collect()
+}
+
+/// Hashes a recovery code of a given user.
+///
+/// The code is normalized before hashing.
+pub fn hash_recovery_code(user_handle: &str, code: &str) -> String {
+    let input = format!("{}#{}", user_handle, normalize_recovery_code(code));
+    base64url.encode(digest::digest(&digest::SHA256, input.as_bytes()))
+}
+
+/// Generates a "base64url"-encoded recovery token for a recovery link.
+pub fn generate_recovery_token() -> Result<String, Error> {
+    let mut token = [0u8; RECOVERY_TOKEN_SIZE];
+    SystemRandom::new().fill(&mut token)
+        .or(Err(Error::Encryption("failed to generate recovery token")))?;
+    Ok(base64url.encode(token))
+}
+
+/// Hashes a recovery token.
+///
+/// Only the hash is stored so that a leaked session table does not reveal
+/// usable links.
+pub fn hash_recovery_token(token: &str) -> String {
+    base64url.encode(digest::digest(&digest::SHA256, token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_recovery_codes_should_generate_distinct_codes() {
+        let codes = generate_recovery_codes().unwrap();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        for code in &codes {
+            assert_eq!(code.len(), CODE_LENGTH + 1);
+            assert_eq!(code.as_bytes()[CODE_LENGTH / 2], b'-');
+            assert_eq!(normalize_recovery_code(code).len(), CODE_LENGTH);
+        }
+        let mut unique = codes.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), codes.len());
+    }
+
+    #[test]
+    fn new_recovery_codes_should_store_hashes_only() {
+        let (codes, item) = new_recovery_codes("AAAA", "2024-01-01T00:00:00Z".into())
+            .unwrap();
+        assert_eq!(item.user_handle, "AAAA");
+        assert_eq!(
+            item.code_hashes,
+            codes.iter()
+                .map(|code| hash_recovery_code("AAAA", code))
+                .collect::<Vec<_>>(),
+        );
+        assert!(codes.iter().all(|code| !item.code_hashes.contains(code)));
+    }
+
+    #[test]
+    fn normalize_recovery_code_should_accept_loose_input() {
+        assert_eq!(normalize_recovery_code("7K2MQ-X9D4R"), "7k2mqx9d4r");
+        assert_eq!(normalize_recovery_code(" 7k2mq x9d4r "), "7k2mqx9d4r");
+        assert_eq!(normalize_recovery_code("iLo00"), "11000");
+    }
+
+    #[test]
+    fn hash_recovery_code_should_bind_code_to_user() {
+        let hash = hash_recovery_code("AAAA", "7k2mq-x9d4r");
+        assert_eq!(hash, hash_recovery_code("AAAA", "7K2MQX9D4R"));
+        assert_ne!(hash, hash_recovery_code("BBBB", "7k2mq-x9d4r"));
+        assert_ne!(hash, hash_recovery_code("AAAA", "7k2mq-x9d4s"));
+    }
+
+    #[test]
+    fn generate_recovery_token_should_generate_distinct_tokens() {
+        let token = generate_recovery_token().unwrap();
+        assert_eq!(base64url.decode(&token).unwrap().len(), RECOVERY_TOKEN_SIZE);
+        assert_ne!(token, generate_recovery_token().unwrap());
+        assert_ne!(hash_recovery_token(&token), token);
+    }
+}
@@ -0,0 +1,296 @@
+//! Red-team simulation.
+//!
+//! Defines attack scenarios the `red-team` binary emits against a
+//! non-production stage.
+//! Every scenario is expected to be rejected by the relying party, and to
+//! leave audit events labeled with the name of the scenario.
+
+use std::fmt;
+
+use crate::audit::{
+    AuditEventType,
+    AuditRecord,
+    SYNTHETIC_LABEL_CLIENT_METADATA_KEY,
+    SYNTHETIC_LABEL_HEADER,
+};
+
+/// HTTP header that labels synthetic attack traffic.
+pub const SCENARIO_HEADER: &str = SYNTHETIC_LABEL_HEADER;
+
+/// Client metadata key that labels synthetic attack traffic sent to Cognito.
+pub const SCENARIO_CLIENT_METADATA_KEY: &str = SYNTHETIC_LABEL_CLIENT_METADATA_KEY;
+
+/// Prefix of usernames registered by the simulation.
+pub const USERNAME_PREFIX: &str = "red-team-";
+
+/// Attack scenario.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Scenario {
+    /// Registration with client data from a wrong origin.
+    WrongOrigin,
+
+    /// Registration with client data whose challenge is tampered.
+    TamperedClientData,
+
+    /// Replay of a registration that has already been finished.
+    ReplayedRegistration,
+
+    /// Replay of an assertion that has already been accepted.
+    ReplayedAssertion,
+
+    /// Assertion whose signature counter goes backwards.
+    CounterRegression,
+}
+
+impl Scenario {
+    /// All the scenarios in the order they are run.
+    pub const ALL: [Scenario; 5] = [
+        Scenario::WrongOrigin,
+        Scenario::TamperedClientData,
+        Scenario::ReplayedRegistration,
+        Scenario::ReplayedAssertion,
+        Scenario::CounterRegression,
+    ];
+
+    /// Name of the scenario used as the label.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scenario::WrongOrigin => "wrong-origin",
+            Scenario::TamperedClientData => "tampered-client-data",
+            Scenario::ReplayedRegistration => "replayed-registration",
+            Scenario::ReplayedAssertion => "replayed-assertion",
+            Scenario::CounterRegression => "counter-regression",
+        }
+    }
+
+    /// Parses a scenario name.
+    pub fn from_name(name: &str) -> Option<Scenario> {
+        Self::ALL.into_iter().find(|s| s.name() == name)
+    }
+
+    /// Number of credentials the scenario registers legitimately.
+    pub fn expected_registrations(&self) -> usize {
+        match self {
+            Scenario::WrongOrigin | Scenario::TamperedClientData => 0,
+            Scenario::ReplayedRegistration
+                | Scenario::ReplayedAssertion
+                | Scenario::CounterRegression => 1,
+        }
+    }
+
+    /// Whether the relying party is expected to audit the rejection of the
+    /// attack as an authentication failure.
+    ///
+    /// Registration failures are not audited.
+    pub fn expects_authentication_failure(&self) -> bool {
+        matches!(self, Scenario::ReplayedAssertion | Scenario::CounterRegression)
+    }
+
+    /// Checks the audit events of the user the scenario registered.
+    ///
+    /// Every event must be labeled with the name of the scenario, credentials
+    /// must be registered as many times as legitimately done, and the attack
+    /// must be audited as an authentication failure if it is expected to.
+    pub fn check_audit_events(&self, records: &[AuditRecord]) -> Result<(), String> {
+        if let Some(record) = records.iter()
+            .find(|r| r.synthetic_label.as_deref() != Some(self.name()))
+        {
+            return Err(format!(
+                "{} event labeled {:?}",
+                record.event_type,
+                record.synthetic_label,
+            ));
+        }
+        let count = |event_type: AuditEventType| records.iter()
+            .filter(|r| r.event_type == event_type.as_str())
+            .count();
+        let registrations = count(AuditEventType::CredentialRegistered);
+        if registrations != self.expected_registrations() {
+            return Err(format!(
+                "{} credential registrations audited, expected {}",
+                registrations,
+                self.expected_registrations(),
+            ));
+        }
+        let failures = count(AuditEventType::AuthenticationFailed);
+        if self.expects_authentication_failure() && failures == 0 {
+            return Err("authentication failure not audited".into());
+        }
+        if !self.expects_authentication_failure() && failures > 0 {
+            return Err(format!("{} unexpected authentication failures audited", failures));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Scenario {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Outcome of a request observed by the simulation.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    /// The relying party accepted the request.
+    Accepted,
+
+    /// The relying party rejected the request.
+    Rejected(String),
+}
+
+/// Result of a scenario.
+#[derive(Clone, Debug)]
+pub struct ScenarioReport {
+    /// Scenario.
+    pub scenario: Scenario,
+
+    /// Whether the relying party behaved as expected.
+    pub passed: bool,
+
+    /// Details.
+    pub detail: String,
+}
+
+impl ScenarioReport {
+    /// Evaluates the outcome of the attack request in a scenario.
+    ///
+    /// Passes only if the attack request was rejected.
+    pub fn evaluate(scenario: Scenario, outcome: Outcome) -> Self {
+        match outcome {
+            Outcome::Accepted => Self {
+                scenario,
+                passed: false,
+                detail: "attack was accepted".into(),
+            },
+            Outcome::Rejected(reason) => Self {
+                scenario,
+                passed: true,
+                detail: format!("rejected: {}", reason),
+            },
+        }
+    }
+
+    /// Fails a passed scenario if its audit events are not as expected.
+    pub fn audited(self, result: Result<(), String>) -> Self {
+        match result {
+            Err(reason) if self.passed => Self {
+                passed: false,
+                detail: format!("{}; but audit: {}", self.detail, reason),
+                ..self
+            },
+            _ => self,
+        }
+    }
+
+    /// Fails a scenario whose preparation did not work.
+    pub fn aborted(scenario: Scenario, reason: impl fmt::Display) -> Self {
+        Self {
+            scenario,
+            passed: false,
+            detail: format!("aborted: {}", reason),
+        }
+    }
+}
+
+/// Returns whether a given stage name looks like production.
+///
+/// The simulation refuses to run against such stages.
+pub fn is_production_stage(stage: &str) -> bool {
+    let stage = stage.trim().to_ascii_lowercase();
+    stage.is_empty() || stage == "prod" || stage == "production" || stage == "live"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scenario_from_name_should_round_trip_all_scenarios() {
+        for scenario in Scenario::ALL {
+            assert_eq!(Scenario::from_name(scenario.name()), Some(scenario));
+        }
+        assert_eq!(Scenario::from_name("unknown"), None);
+    }
+
+    #[test]
+    fn scenario_report_evaluate_should_pass_only_rejected_attack() {
+        let report = ScenarioReport::evaluate(
+            Scenario::WrongOrigin,
+            Outcome::Rejected("500".into()),
+        );
+        assert!(report.passed);
+        let report = ScenarioReport::evaluate(
+            Scenario::WrongOrigin,
+            Outcome::Accepted,
+        );
+        assert!(!report.passed);
+    }
+
+    fn record(event_type: AuditEventType, label: Option<&str>) -> AuditRecord {
+        AuditRecord {
+            event_id: "event-id".into(),
+            event_type: event_type.as_str().into(),
+            user_handle: "user-handle".into(),
+            credential_id: None,
+            source_ip: None,
+            user_agent: None,
+            detail: None,
+            synthetic_label: label.map(Into::into),
+            timestamp: "2024-01-02T03:04:05Z".into(),
+        }
+    }
+
+    #[test]
+    fn check_audit_events_should_expect_labeled_failure_of_assertion_attack() {
+        let scenario = Scenario::ReplayedAssertion;
+        let label = Some(scenario.name());
+        let registered = record(AuditEventType::CredentialRegistered, label);
+        let failed = record(AuditEventType::AuthenticationFailed, label);
+        assert!(scenario.check_audit_events(&[registered.clone(), failed.clone()]).is_ok());
+        assert!(scenario.check_audit_events(&[registered.clone()]).is_err());
+        assert!(scenario.check_audit_events(&[failed.clone()]).is_err());
+        let unlabeled = record(AuditEventType::AuthenticationFailed, None);
+        assert!(scenario.check_audit_events(&[registered.clone(), unlabeled]).is_err());
+        let mislabeled = record(
+            AuditEventType::AuthenticationFailed,
+            Some(Scenario::CounterRegression.name()),
+        );
+        assert!(scenario.check_audit_events(&[registered, mislabeled]).is_err());
+    }
+
+    #[test]
+    fn check_audit_events_should_expect_no_registration_of_registration_attack() {
+        let scenario = Scenario::WrongOrigin;
+        assert!(scenario.check_audit_events(&[]).is_ok());
+        let registered = record(AuditEventType::CredentialRegistered, Some(scenario.name()));
+        assert!(scenario.check_audit_events(&[registered]).is_err());
+        let scenario = Scenario::ReplayedRegistration;
+        let registered = record(AuditEventType::CredentialRegistered, Some(scenario.name()));
+        assert!(scenario.check_audit_events(&[registered.clone()]).is_ok());
+        assert!(scenario.check_audit_events(&[registered.clone(), registered]).is_err());
+    }
+
+    #[test]
+    fn scenario_report_audited_should_fail_only_passed_scenario() {
+        let report = ScenarioReport::evaluate(
+            Scenario::WrongOrigin,
+            Outcome::Rejected("400".into()),
+        );
+        assert!(report.clone().audited(Ok(())).passed);
+        assert!(!report.audited(Err("missing".into())).passed);
+        let report = ScenarioReport::evaluate(Scenario::WrongOrigin, Outcome::Accepted)
+            .audited(Err("missing".into()));
+        assert_eq!(report.detail, "attack was accepted");
+    }
+
+    #[test]
+    fn is_production_stage_should_detect_production_names() {
+        assert!(is_production_stage("prod"));
+        assert!(is_production_stage(" Production "));
+        assert!(is_production_stage("live"));
+        assert!(is_production_stage(""));
+        assert!(!is_production_stage("dev"));
+        assert!(!is_production_stage("staging"));
+    }
+}
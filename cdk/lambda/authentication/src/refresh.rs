@@ -0,0 +1,301 @@
+//! Rotating refresh tokens of self-issued tokens.
+//!
+//! A refresh token is issued with an access token after a successful
+//! authentication, and every refresh token issued since then belongs to the
+//! same family. A refresh token is used only once; using it issues a new
+//! access token and the next refresh token of the family.
+//!
+//! Presenting a refresh token that has already been used means the token has
+//! leaked, so the whole family is revoked and none of its tokens can be used
+//! any longer.
+//!
+//! Both tokens and families are stored in the session table; only the hash of
+//! a token is stored. See [`crate::token`] for access tokens.
+
+use aws_sdk_dynamodb::types::{AttributeValue, ReturnValue};
+use base64::{
+    Engine as _,
+    engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
+};
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Deserialize;
+use std::time::SystemTime;
+use tracing::{error, warn};
+
+use crate::error::Error;
+use crate::items::{RefreshTokenFamilyItem, RefreshTokenItem, SessionKey};
+use crate::tenant::Tenant;
+use crate::token::IssuedToken;
+
+/// Default time to live of a refresh token family in seconds.
+pub const DEFAULT_REFRESH_TOKEN_TTL: i64 = 30 * 24 * 60 * 60;
+
+// Number of random bytes in a refresh token or a family ID.
+const REFRESH_TOKEN_SIZE: usize = 32;
+
+/// Body of a request to refresh tokens.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RefreshTokenRequest {
+    /// Refresh token.
+    pub refresh_token: String,
+}
+
+/// Outcome of a refresh.
+#[derive(Clone, Debug)]
+pub enum RefreshOutcome {
+    /// The token was valid, and the next token of the family is issued.
+    Rotated {
+        /// "base64url"-encoded user handle of the user who owns the token.
+        user_handle: String,
+        /// Next refresh token.
+        refresh_token: IssuedToken,
+    },
+    /// The token is unknown, expired, or of a revoked family.
+    Invalid,
+    /// The token had already been used, and its family has been revoked.
+    Reused,
+}
+
+/// Store of refresh tokens in the session table.
+#[derive(Clone, Debug)]
+pub struct RefreshTokenStore {
+    dynamodb: aws_sdk_dynamodb::Client,
+    table_name: String,
+    ttl: i64,
+}
+
+impl RefreshTokenStore {
+    /// Creates a store.
+    ///
+    /// `ttl` is the time to live of a family in seconds; no token of a family
+    /// outlives its family.
+    pub fn new(dynamodb: aws_sdk_dynamodb::Client, table_name: String, ttl: i64) -> Self {
+        Self {
+            dynamodb,
+            table_name,
+            ttl,
+        }
+    }
+
+    /// Starts a new family for a given user and issues its first token.
+    pub async fn start_family(
+        &self,
+        tenant: &Tenant,
+        user_handle: &str,
+    ) -> Result<IssuedToken, Error> {
+        let family_id = generate_refresh_token()?;
+        let expires_at = now() + self.ttl;
+        let item = RefreshTokenFamilyItem {
+            ttl: expires_at,
+            user_handle: user_handle.into(),
+            revoked_at: None,
+        }.into_item(tenant.scope(&SessionKey::RefreshTokenFamily(&family_id)));
+        self.dynamodb
+            .put_item()
+            .table_name(self.table_name.clone())
+            .set_item(Some(item))
+            .condition_expression("attribute_not_exists(pk)")
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "putting refresh token family");
+                Error::Storage("failed to put refresh token family")
+            })?;
+        self.put_token(tenant, user_handle, &family_id, expires_at).await
+    }
+
+    /// Uses a refresh token and issues the next token of its family.
+    ///
+    /// The token is marked used with a conditional update, so it is rotated
+    /// only once even under concurrent requests.
+    pub async fn rotate(
+        &self,
+        tenant: &Tenant,
+        refresh_token: &str,
+    ) -> Result<RefreshOutcome, Error> {
+        let now = now();
+        let token_hash = hash_refresh_token(refresh_token);
+        let res = self.dynamodb
+            .update_item()
+            .table_name(self.table_name.clone())
+            .key("pk", tenant.scope(&SessionKey::RefreshToken(&token_hash)).attribute())
+            .update_expression("SET usedAt = :now")
+            .condition_expression("attribute_exists(pk) AND attribute_not_exists(usedAt) AND #ttl >= :now")
+            .expression_attribute_names("#ttl", "ttl")
+            .expression_attribute_values(":now", AttributeValue::N(format!("{}", now)))
+            .return_values(ReturnValue::AllNew)
+            .send()
+            .await;
+        let token = match res {
+            Ok(res) => RefreshTokenItem::from_item(
+                res.attributes.as_ref().ok_or(Error::Storage("missing refresh token"))?,
+            )?,
+            Err(e) if e.as_service_error()
+                .is_some_and(|e| e.is_conditional_check_failed_exception()) =>
+            {
+                return self.reject(tenant, &token_hash).await;
+            }
+            Err(e) => {
+                error!(?e, "using refresh token");
+                return Err(Error::Storage("failed to use refresh token"));
+            }
+        };
+        let family = self.get_family(tenant, &token.family_id).await?;
+        let Some(family) = family.filter(|family| {
+            family.revoked_at.is_none()
+                && family.ttl >= now
+                && family.user_handle == token.user_handle
+        }) else {
+            return Ok(RefreshOutcome::Invalid);
+        };
+        let refresh_token = self.put_token(
+            tenant,
+            &token.user_handle,
+            &token.family_id,
+            family.ttl,
+        ).await?;
+        Ok(RefreshOutcome::Rotated {
+            user_handle: token.user_handle,
+            refresh_token,
+        })
+    }
+
+    /// Revokes a family.
+    pub async fn revoke_family(&self, tenant: &Tenant, family_id: &str) -> Result<(), Error> {
+        self.dynamodb
+            .update_item()
+            .table_name(self.table_name.clone())
+            .key("pk", tenant.scope(&SessionKey::RefreshTokenFamily(family_id)).attribute())
+            .update_expression("SET revokedAt = :now")
+            .condition_expression("attribute_exists(pk)")
+            .expression_attribute_values(":now", AttributeValue::N(format!("{}", now())))
+            .return_values(ReturnValue::None)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "revoking refresh token family");
+                Error::Storage("failed to revoke refresh token family")
+            })?;
+        Ok(())
+    }
+
+    // tells why a token was not rotated, and revokes the family if the token
+    // has been reused.
+    async fn reject(&self, tenant: &Tenant, token_hash: &str) -> Result<RefreshOutcome, Error> {
+        let item = self.dynamodb
+            .get_item()
+            .table_name(self.table_name.clone())
+            .key("pk", tenant.scope(&SessionKey::RefreshToken(token_hash)).attribute())
+            .consistent_read(true)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "getting refresh token");
+                Error::Storage("failed to get refresh token")
+            })?
+            .item
+            .map(|item| RefreshTokenItem::from_item(&item))
+            .transpose()?;
+        match item {
+            Some(token) if token.used_at.is_some() => {
+                warn!(
+                    event = "refresh_token_reused",
+                    user_handle = %token.user_handle,
+                    family_id = %token.family_id,
+                    "refresh token reused; revoking the family",
+                );
+                self.revoke_family(tenant, &token.family_id).await?;
+                Ok(RefreshOutcome::Reused)
+            }
+            _ => Ok(RefreshOutcome::Invalid),
+        }
+    }
+
+    async fn get_family(
+        &self,
+        tenant: &Tenant,
+        family_id: &str,
+    ) -> Result<Option<RefreshTokenFamilyItem>, Error> {
+        self.dynamodb
+            .get_item()
+            .table_name(self.table_name.clone())
+            .key("pk", tenant.scope(&SessionKey::RefreshTokenFamily(family_id)).attribute())
+            .consistent_read(true)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "getting refresh token family");
+                Error::Storage("failed to get refresh token family")
+            })?
+            .item
+            .map(|item| RefreshTokenFamilyItem::from_item(&item))
+            .transpose()
+    }
+
+    async fn put_token(
+        &self,
+        tenant: &Tenant,
+        user_handle: &str,
+        family_id: &str,
+        expires_at: i64,
+    ) -> Result<IssuedToken, Error> {
+        let token = generate_refresh_token()?;
+        let item = RefreshTokenItem {
+            ttl: expires_at,
+            user_handle: user_handle.into(),
+            family_id: family_id.into(),
+            used_at: None,
+        }.into_item(tenant.scope(&SessionKey::RefreshToken(&hash_refresh_token(&token))));
+        self.dynamodb
+            .put_item()
+            .table_name(self.table_name.clone())
+            .set_item(Some(item))
+            .condition_expression("attribute_not_exists(pk)")
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "putting refresh token");
+                Error::Storage("failed to put refresh token")
+            })?;
+        Ok(IssuedToken {
+            token,
+            expires_at,
+        })
+    }
+}
+
+/// Generates a "base64url"-encoded refresh token.
+pub fn generate_refresh_token() -> Result<String, Error> {
+    let mut token = [0u8; REFRESH_TOKEN_SIZE];
+    SystemRandom::new().fill(&mut token)
+        .or(Err(Error::Token("failed to generate refresh token")))?;
+    Ok(base64url.encode(token))
+}
+
+/// Hashes a refresh token.
+pub fn hash_refresh_token(token: &str) -> String {
+    base64url.encode(digest::digest(&digest::SHA256, token.as_bytes()))
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_refresh_token_should_generate_distinct_tokens() {
+        let token = generate_refresh_token().unwrap();
+        assert_eq!(base64url.decode(&token).unwrap().len(), REFRESH_TOKEN_SIZE);
+        assert_ne!(token, generate_refresh_token().unwrap());
+        assert_ne!(hash_refresh_token(&token), token);
+        assert_eq!(hash_refresh_token(&token), hash_refresh_token(&token));
+    }
+}
@@ -0,0 +1,218 @@
+//! Request and response bodies of the registration API.
+//!
+//! Schemas for the OpenAPI specification are derived if the `openapi` feature
+//! is enabled. See [`crate::openapi`].
+//! TypeScript types of [`NewUserInfo`], [`StartRegistrationSession`], and
+//! [`FinishRegistrationSession`] are derived if the `typescript` feature is
+//! enabled. See [`crate::typescript`].
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use webauthn_rs::prelude::CreationChallengeResponse;
+use webauthn_rs_proto::{RegisterPublicKeyCredential, options::AuthenticatorAttachment};
+
+use crate::extensions::{LargeBlobOutputs, PrfOutputs};
+use crate::hints::PublicKeyCredentialHint;
+use crate::telemetry::redact;
+
+/// Information on a new user.
+///
+/// The `Debug` representation redacts the username and display name. See
+/// [`crate::telemetry::redact`].
+#[derive(Clone, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+#[serde(rename_all = "camelCase")]
+pub struct NewUserInfo {
+    /// Username.
+    pub username: String,
+
+    /// Display name.
+    pub display_name: String,
+
+    /// Authenticator attachment to request.
+    ///
+    /// Must not conflict with the `AUTHENTICATOR_ATTACHMENT` policy if it is
+    /// configured.
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<AuthenticatorAttachmentSchema>))]
+    #[cfg_attr(feature = "typescript", ts(optional, type = "AuthenticatorAttachment"))]
+    pub authenticator_attachment: Option<AuthenticatorAttachment>,
+
+    /// Hints of the kind of authenticator in the order of preference.
+    ///
+    /// Take precedence over the `HINTS` configuration.
+    #[cfg_attr(feature = "typescript", ts(optional))]
+    #[serde(default)]
+    pub hints: Option<Vec<PublicKeyCredentialHint>>,
+}
+
+impl fmt::Debug for NewUserInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NewUserInfo")
+            .field("username", &redact(&self.username))
+            .field("display_name", &redact(&self.display_name))
+            .field("authenticator_attachment", &self.authenticator_attachment)
+            .field("hints", &self.hints)
+            .finish()
+    }
+}
+
+/// Beginning of a session to register a new user.
+#[derive(Clone, Debug, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+#[serde(rename_all = "camelCase")]
+pub struct StartRegistrationSession {
+    /// Session ID.
+    pub session_id: String,
+
+    /// Credential creation options.
+    ///
+    /// `CredentialCreationOptions` of the Web Authentication API.
+    #[cfg_attr(feature = "openapi", schema(value_type = Object))]
+    #[cfg_attr(feature = "typescript", ts(type = "Record<string, unknown>"))]
+    pub credential_creation_options: CreationChallengeResponse,
+}
+
+/// End of a session to register a new user.
+#[derive(Clone, Debug, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "typescript", derive(ts_rs::TS))]
+#[serde(rename_all = "camelCase")]
+pub struct FinishRegistrationSession {
+    /// Session ID.
+    pub session_id: String,
+
+    /// Public key credential.
+    ///
+    /// `PublicKeyCredential` of the Web Authentication API in the JSON form.
+    #[cfg_attr(feature = "openapi", schema(value_type = Object))]
+    #[cfg_attr(feature = "typescript", ts(type = "Record<string, unknown>"))]
+    pub public_key_credential: RegisterPublicKeyCredential,
+
+    /// Authenticator attachment reported by the client.
+    ///
+    /// `authenticatorAttachment` of the `PublicKeyCredential`.
+    /// Required if the registration session specifies an authenticator
+    /// attachment.
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<AuthenticatorAttachmentSchema>))]
+    #[cfg_attr(feature = "typescript", ts(optional, type = "AuthenticatorAttachment"))]
+    pub authenticator_attachment: Option<AuthenticatorAttachment>,
+}
+
+/// Result of a finished registration.
+#[derive(Clone, Debug, Default, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct FinishRegistrationResult {
+    /// One-time recovery codes issued to a new user.
+    ///
+    /// Shown only once; omitted for a retried request and for account
+    /// recovery, which keeps the remaining codes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub recovery_codes: Option<Vec<String>>,
+
+    /// Outputs of the `credProps` extension reported by the client.
+    ///
+    /// Omitted if the client did not report them, and for a retried request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cred_props: Option<CredentialProperties>,
+
+    /// Outputs of the `largeBlob` extension reported by the client.
+    ///
+    /// Omitted unless the extension is enabled and reported, and for a
+    /// retried request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub large_blob: Option<LargeBlobOutputs>,
+
+    /// Outputs of the `prf` extension reported by the client.
+    ///
+    /// Omitted unless the extension is enabled and reported, and for a
+    /// retried request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prf: Option<PrfOutputs>,
+}
+
+/// Outputs of the `credProps` extension.
+///
+/// `CredentialPropertiesOutput` of the Web Authentication API.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct CredentialProperties {
+    /// Whether the credential is client-side discoverable (resident).
+    pub rk: bool,
+}
+
+/// Request to recover an account by registering a new passkey.
+#[derive(Clone, Debug, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct RecoveryRequest {
+    /// Username.
+    pub username: String,
+
+    /// One of the unused recovery codes of the user.
+    pub recovery_code: String,
+
+    /// Authenticator attachment to request.
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<AuthenticatorAttachmentSchema>))]
+    pub authenticator_attachment: Option<AuthenticatorAttachment>,
+}
+
+/// Request to email a recovery link.
+#[derive(Clone, Debug, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct RecoveryLinkRequest {
+    /// Username, which must be an email address to receive the link.
+    pub username: String,
+}
+
+/// Request to recover an account with the token in a recovery link.
+#[derive(Clone, Debug, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct RecoveryLinkSession {
+    /// Token in the recovery link.
+    pub token: String,
+
+    /// Authenticator attachment to request.
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<AuthenticatorAttachmentSchema>))]
+    pub authenticator_attachment: Option<AuthenticatorAttachment>,
+}
+
+/// Request of an authenticated user to register an additional passkey.
+#[derive(Clone, Debug, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct AdditionalPasskeyRequest {
+    /// Authenticator attachment to request.
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<AuthenticatorAttachmentSchema>))]
+    pub authenticator_attachment: Option<AuthenticatorAttachment>,
+}
+
+/// Options of a password-to-passkey upgrade given along with the
+/// `publicKeyCredential` at the end of the session.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct PasswordUpgradeOptions {
+    /// Whether the password of the user is disabled once the passkey is
+    /// registered.
+    ///
+    /// `false` by default; the user may keep signing in with the password.
+    #[serde(default)]
+    pub disable_password: bool,
+}
+
+/// Schema of [`AuthenticatorAttachment`].
+#[cfg(feature = "openapi")]
+#[derive(Serialize, utoipa::ToSchema)]
+#[schema(as = AuthenticatorAttachment)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthenticatorAttachmentSchema {
+    /// Platform authenticator; "platform".
+    Platform,
+    /// Roaming authenticator; "cross-platform".
+    CrossPlatform,
+}
@@ -0,0 +1,259 @@
+//! Enforcement of data retention.
+//!
+//! The `data-retention` job purges data that has outlived its retention
+//! period:
+//! - audit events older than the audit retention; see
+//!   [`load_audit_retention`]
+//! - sessions in the session table that have expired but DynamoDB has not
+//!   deleted yet; see [`load_session_expiry_grace`]
+//! - sessions of users who no longer exist; e.g., refresh tokens of a
+//!   deleted account, which are otherwise left to expire
+//! - credentials soft-deleted before the retention window; see
+//!   [`crate::deletion`]
+//!
+//! Registration sessions are never orphaned, because they are started before
+//! the user exists.
+
+use aws_sdk_dynamodb::types::AttributeValue;
+use std::collections::HashMap;
+use std::env;
+use std::time::Duration;
+use tracing::error;
+
+use crate::config;
+use crate::error::Error;
+use crate::items::{Item, ttl_of};
+
+/// Default time in seconds that an expired session is left to DynamoDB TTL
+/// before the job purges it; 1 day.
+pub const DEFAULT_SESSION_EXPIRY_GRACE: u64 = 24 * 60 * 60;
+
+// prefixes of the partition keys of the sessions owned by existing users.
+const USER_SESSION_PREFIXES: &[&str] = &[
+    "refresh-family#",
+    "refresh-token#",
+    "stepup#",
+    "stepup-token#",
+    "recovery-link#",
+];
+
+// prefix of the partition key of the latest passkey authentication, which
+// is followed by the user handle.
+const PASSKEY_AUTHENTICATION_PREFIX: &str = "passkey-authentication#";
+
+/// Loads the retention period of audit events.
+///
+/// You can specify to `AUDIT_RETENTION` environment variable the retention
+/// period in seconds.
+///
+/// Returns `None` if `AUDIT_RETENTION` is not set or "0", which means audit
+/// events are kept forever.
+pub fn load_audit_retention() -> Result<Option<Duration>, Error> {
+    match config::var("AUDIT_RETENTION") {
+        Ok(retention) => match retention.parse::<u64>() {
+            Ok(0) => Ok(None),
+            Ok(secs) => Ok(Some(Duration::from_secs(secs))),
+            Err(_) => Err(Error::BadEnvironmentVariable("AUDIT_RETENTION", retention)),
+        },
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(retention)) => Err(
+            Error::BadEnvironmentVariable(
+                "AUDIT_RETENTION",
+                retention.to_string_lossy().into(),
+            ),
+        ),
+    }
+}
+
+/// Loads the grace period of expired sessions.
+///
+/// You can specify to `SESSION_EXPIRY_GRACE` environment variable the time
+/// in seconds that an expired session is left to DynamoDB TTL, which deletes
+/// expired items lazily, before the job purges it.
+///
+/// Defaults to [`DEFAULT_SESSION_EXPIRY_GRACE`].
+pub fn load_session_expiry_grace() -> Result<Duration, Error> {
+    match config::var("SESSION_EXPIRY_GRACE") {
+        Ok(grace) => grace.parse()
+            .map(Duration::from_secs)
+            .or(Err(Error::BadEnvironmentVariable("SESSION_EXPIRY_GRACE", grace))),
+        Err(env::VarError::NotPresent) =>
+            Ok(Duration::from_secs(DEFAULT_SESSION_EXPIRY_GRACE)),
+        Err(env::VarError::NotUnicode(grace)) => Err(
+            Error::BadEnvironmentVariable(
+                "SESSION_EXPIRY_GRACE",
+                grace.to_string_lossy().into(),
+            ),
+        ),
+    }
+}
+
+/// What to do with an item in the session table.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SessionDisposition {
+    /// Keeps the item.
+    Keep,
+    /// Purges the item, which has expired beyond the grace period.
+    Expired,
+    /// Purges the item unless the user who owns it exists.
+    OwnedBy(String),
+}
+
+/// Decides what to do with an item in the session table.
+///
+/// `now` is in seconds since the epoch.
+pub fn session_disposition(
+    item: &Item,
+    now: i64,
+    grace: Duration,
+) -> Result<SessionDisposition, Error> {
+    // items without a TTL never expire
+    let ttl = item.get("ttl").map(|_| ttl_of(item)).transpose()?;
+    if ttl.is_some_and(|ttl| ttl < now.saturating_sub(grace.as_secs() as i64)) {
+        return Ok(SessionDisposition::Expired);
+    }
+    let pk = item.get("pk")
+        .and_then(|pk| pk.as_s().ok())
+        .ok_or(Error::BadItemAttribute("pk"))?;
+    // a key scoped to a tenant is "tenant#<tenant ID>#<key>"
+    let pk = match pk.strip_prefix("tenant#") {
+        Some(scoped) => scoped.split_once('#').map_or("", |(_, pk)| pk),
+        None => pk.as_str(),
+    };
+    if let Some(user_handle) = pk.strip_prefix(PASSKEY_AUTHENTICATION_PREFIX) {
+        return Ok(SessionDisposition::OwnedBy(user_handle.into()));
+    }
+    if !USER_SESSION_PREFIXES.iter().any(|prefix| pk.starts_with(prefix)) {
+        return Ok(SessionDisposition::Keep);
+    }
+    match item.get("userHandle").and_then(|v| v.as_s().ok()) {
+        Some(user_handle) => Ok(SessionDisposition::OwnedBy(user_handle.clone())),
+        None => Ok(SessionDisposition::Keep),
+    }
+}
+
+/// Page of items in the session table.
+#[derive(Clone, Debug)]
+pub struct SessionPage {
+    /// Items in the page.
+    pub items: Vec<Item>,
+
+    /// Key to start the next page.
+    ///
+    /// `None` if this is the last page.
+    pub last_evaluated_key: Option<HashMap<String, AttributeValue>>,
+}
+
+/// Sweeper of the session table.
+#[derive(Clone, Debug)]
+pub struct SessionSweeper {
+    dynamodb: aws_sdk_dynamodb::Client,
+    table_name: String,
+}
+
+impl SessionSweeper {
+    /// Creates a sweeper of a given session table.
+    pub fn new(dynamodb: aws_sdk_dynamodb::Client, table_name: String) -> Self {
+        Self { dynamodb, table_name }
+    }
+
+    /// Scans a page of the session table.
+    pub async fn scan(
+        &self,
+        limit: i32,
+        exclusive_start_key: Option<HashMap<String, AttributeValue>>,
+    ) -> Result<SessionPage, Error> {
+        let res = self.dynamodb
+            .scan()
+            .table_name(self.table_name.clone())
+            .limit(limit)
+            .set_exclusive_start_key(exclusive_start_key)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "scanning sessions");
+                Error::Storage("failed to scan sessions")
+            })?;
+        Ok(SessionPage {
+            items: res.items.unwrap_or_default(),
+            last_evaluated_key: res.last_evaluated_key,
+        })
+    }
+
+    /// Deletes an item in the session table.
+    pub async fn purge(&self, item: &Item) -> Result<(), Error> {
+        let pk = item.get("pk")
+            .cloned()
+            .ok_or(Error::BadItemAttribute("pk"))?;
+        self.dynamodb
+            .delete_item()
+            .table_name(self.table_name.clone())
+            .key("pk", pk)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "purging session");
+                Error::Storage("failed to purge session")
+            })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GRACE: Duration = Duration::from_secs(60);
+
+    fn item(pk: &str, ttl: i64, user_handle: Option<&str>) -> Item {
+        let mut item = HashMap::from([
+            ("pk".to_string(), AttributeValue::S(pk.into())),
+            ("ttl".into(), AttributeValue::N(format!("{}", ttl))),
+        ]);
+        if let Some(user_handle) = user_handle {
+            item.insert("userHandle".into(), AttributeValue::S(user_handle.into()));
+        }
+        item
+    }
+
+    #[test]
+    fn session_disposition_should_purge_sessions_expired_beyond_grace() {
+        let expired = item("registration#session", 100, None);
+        assert_eq!(
+            session_disposition(&expired, 161, GRACE).unwrap(),
+            SessionDisposition::Expired,
+        );
+        assert_eq!(
+            session_disposition(&expired, 160, GRACE).unwrap(),
+            SessionDisposition::Keep,
+        );
+    }
+
+    #[test]
+    fn session_disposition_should_check_owners_of_user_sessions() {
+        let family = item("tenant#acme#refresh-family#family", 1000, Some("AAAA"));
+        assert_eq!(
+            session_disposition(&family, 100, GRACE).unwrap(),
+            SessionDisposition::OwnedBy("AAAA".into()),
+        );
+        let authentication = item("passkey-authentication#BBBB", 1000, None);
+        assert_eq!(
+            session_disposition(&authentication, 100, GRACE).unwrap(),
+            SessionDisposition::OwnedBy("BBBB".into()),
+        );
+    }
+
+    #[test]
+    fn session_disposition_should_keep_registration_sessions() {
+        let registration = item("upgrade-registration#session", 1000, Some("AAAA"));
+        assert_eq!(
+            session_disposition(&registration, 100, GRACE).unwrap(),
+            SessionDisposition::Keep,
+        );
+        let rate_limit = item("ratelimit#ip#hash#0", 1000, None);
+        assert_eq!(
+            session_disposition(&rate_limit, 100, GRACE).unwrap(),
+            SessionDisposition::Keep,
+        );
+    }
+}
@@ -0,0 +1,491 @@
+//! Risk assessment before authentication succeeds.
+//!
+//! Teams can plug their fraud engine into authentication. Once an assertion
+//! is verified, and before authentication succeeds, a [`RiskHook`] assesses a
+//! [`RiskContext`] that describes the client and the credential, and returns
+//! a [`RiskAssessment`] whose decision is one of [`RiskDecision`]:
+//! - `allow`: authentication succeeds.
+//! - `step_up`: authentication succeeds only if the user has been verified;
+//!   otherwise, it fails with `step_up_required`, and the client should retry
+//!   with user verification required.
+//! - `deny`: authentication fails with `authentication_denied`.
+//!
+//! The HTTP APIs reject an authentication with 403 and the error code; see
+//! [`risk_rejected`]. The reason of a decision is never told to the client.
+//!
+//! [`load_risk_hook`] configures a call-out to an HTTP endpoint or a Lambda
+//! function:
+//! - An HTTP endpoint receives the [`RiskContext`] as a JSON POST request
+//!   and responds with the [`RiskAssessment`] as JSON. The request is signed
+//!   in the `X-Risk-Signature` header like a webhook if a secret is
+//!   configured; see [`crate::webhooks`].
+//! - A Lambda function is synchronously invoked with the [`RiskContext`] as
+//!   the payload and returns the [`RiskAssessment`].
+//!
+//! A call-out that fails or times out is decided by `RISK_HOOK_ON_ERROR`;
+//! "allow" by default so that an outage of the fraud engine does not stop
+//! every authentication.
+
+use aws_sdk_lambda::primitives::Blob;
+use lambda_http::{Body, Response, http::StatusCode};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::future::Future;
+use std::time::{Duration, SystemTime};
+use tracing::{error, info, instrument};
+
+use crate::audit::ClientInfo;
+use crate::config;
+use crate::error::Error;
+use crate::items::CredentialItem;
+use crate::payload::ErrorResponseBody;
+use crate::secrets::{SecretCache, load_secret_cache_ttl};
+use crate::telemetry::redact;
+use crate::webhooks::sign;
+
+/// Name of the header that carries the signature of a request to an HTTP
+/// endpoint.
+pub const RISK_SIGNATURE_HEADER: &str = "X-Risk-Signature";
+
+/// Error code of an authentication that requires a step-up.
+pub const STEP_UP_REQUIRED: &str = "step_up_required";
+
+/// Error code of an authentication denied by the risk assessment.
+pub const AUTHENTICATION_DENIED: &str = "authentication_denied";
+
+// timeout of a call-out, which keeps the authentication responsive.
+const CALL_OUT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What a [`RiskHook`] is told about an authentication.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RiskContext<'a> {
+    /// "base64url"-encoded user handle.
+    pub user_handle: &'a str,
+
+    /// ID of the tenant.
+    ///
+    /// Omitted for the default relying party.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<&'a str>,
+
+    /// Source IP address of the client.
+    ///
+    /// Omitted if unknown; e.g., Cognito triggers are not told it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_ip: Option<&'a str>,
+
+    /// User agent of the client.
+    ///
+    /// Omitted if unknown.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_agent: Option<&'a str>,
+
+    /// Whether the user has been verified in the assertion.
+    pub user_verified: bool,
+
+    /// Credential that made the assertion.
+    pub credential: RiskCredential<'a>,
+}
+
+/// Metadata of the credential in a [`RiskContext`].
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RiskCredential<'a> {
+    /// "base64url"-encoded credential ID.
+    pub credential_id: &'a str,
+
+    /// Type of the credential; "passkey" or "securityKey".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credential_type: Option<&'a str>,
+
+    /// Authenticator attachment reported at registration.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authenticator_attachment: Option<&'a str>,
+
+    /// AAGUID of the authenticator.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aaguid: Option<&'a str>,
+
+    /// Whether the credential is backed up (BS flag).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backup_state: Option<bool>,
+
+    /// Source IP address of the client that registered the credential.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registered_ip: Option<&'a str>,
+
+    /// When the credential was registered.
+    pub created_at: &'a str,
+
+    /// When the credential was last used for authentication.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_used_at: Option<&'a str>,
+}
+
+impl<'a> RiskContext<'a> {
+    /// Describes an authentication with a given credential.
+    pub fn new(
+        credential: &'a CredentialItem,
+        tenant_id: Option<&'a str>,
+        client: &'a ClientInfo,
+        user_verified: bool,
+    ) -> Self {
+        Self {
+            user_handle: &credential.user_handle,
+            tenant_id,
+            source_ip: client.source_ip.as_deref(),
+            user_agent: client.user_agent.as_deref(),
+            user_verified,
+            credential: RiskCredential {
+                credential_id: &credential.credential_id,
+                credential_type: credential.credential_type.as_deref(),
+                authenticator_attachment: credential.authenticator_attachment.as_deref(),
+                aaguid: credential.aaguid.as_deref(),
+                backup_state: credential.backup_state,
+                registered_ip: credential.registered_ip.as_deref(),
+                created_at: &credential.created_at,
+                last_used_at: credential.last_used_at.as_deref(),
+            },
+        }
+    }
+}
+
+/// Decision of a risk assessment.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RiskDecision {
+    /// Authentication succeeds.
+    Allow,
+    /// Authentication succeeds only if the user has been verified.
+    StepUp,
+    /// Authentication fails.
+    Deny,
+}
+
+/// Result of a risk assessment.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RiskAssessment {
+    /// Decision.
+    pub decision: RiskDecision,
+
+    /// Reason of the decision, which is logged and recorded in the audit log
+    /// but never told to the client.
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+impl RiskAssessment {
+    /// Assessment that allows authentication.
+    pub fn allow() -> Self {
+        Self {
+            decision: RiskDecision::Allow,
+            reason: None,
+        }
+    }
+
+    /// Returns the error code of the rejection of an authentication in which
+    /// the user has been verified or not.
+    ///
+    /// Returns `None` if the authentication may succeed.
+    pub fn rejection(&self, user_verified: bool) -> Option<&'static str> {
+        match self.decision {
+            RiskDecision::Allow => None,
+            RiskDecision::StepUp if user_verified => None,
+            RiskDecision::StepUp => Some(STEP_UP_REQUIRED),
+            RiskDecision::Deny => Some(AUTHENTICATION_DENIED),
+        }
+    }
+}
+
+/// Hook that assesses the risk of an authentication.
+pub trait RiskHook {
+    /// Assesses the risk of an authentication.
+    fn assess(
+        &self,
+        context: &RiskContext<'_>,
+    ) -> impl Future<Output = Result<RiskAssessment, Error>> + Send;
+}
+
+/// Risk hook configured with the environment variables.
+pub struct RemoteRiskHook {
+    target: CallOutTarget,
+    on_error: RiskDecision,
+}
+
+enum CallOutTarget {
+    Http {
+        http: reqwest::Client,
+        url: reqwest::Url,
+        // secret ID and cache if the requests are signed
+        secret: Option<(String, SecretCache)>,
+    },
+    Lambda {
+        lambda: aws_sdk_lambda::Client,
+        function_name: String,
+    },
+}
+
+/// Loads the risk hook.
+///
+/// You can specify the following environment variables:
+/// - `RISK_HOOK_URL`: URL of the HTTP endpoint to call out
+/// - `RISK_HOOK_FUNCTION_NAME`: name or ARN of the Lambda function to
+///   invoke. Exclusive with `RISK_HOOK_URL`.
+/// - `RISK_HOOK_SECRET_ID`: ID of the secret in Secrets Manager that signs
+///   the requests to `RISK_HOOK_URL`. Requests are not signed unless
+///   specified.
+/// - `RISK_HOOK_ON_ERROR`: decision when the call-out fails; "allow"
+///   (default), "step_up", or "deny"
+///
+/// Returns `None` if neither `RISK_HOOK_URL` nor `RISK_HOOK_FUNCTION_NAME` is
+/// set, which means every authentication is allowed.
+pub fn load_risk_hook(
+    lambda: aws_sdk_lambda::Client,
+    secrets: aws_sdk_secretsmanager::Client,
+) -> Result<Option<RemoteRiskHook>, Error> {
+    let url = load_var("RISK_HOOK_URL")?;
+    let function_name = load_var("RISK_HOOK_FUNCTION_NAME")?;
+    let target = match (url, function_name) {
+        (Some(url), None) => {
+            let url = reqwest::Url::parse(&url)
+                .ok()
+                .filter(|u| u.scheme() == "https" || u.scheme() == "http")
+                .ok_or(Error::BadEnvironmentVariable("RISK_HOOK_URL", url))?;
+            let http = reqwest::Client::builder()
+                .timeout(CALL_OUT_TIMEOUT)
+                .build()
+                .or(Err(Error::RiskHook("failed to build HTTP client")))?;
+            let secret = match load_var("RISK_HOOK_SECRET_ID")? {
+                Some(secret_id) => Some((
+                    secret_id,
+                    SecretCache::new(secrets, load_secret_cache_ttl()?),
+                )),
+                None => None,
+            };
+            CallOutTarget::Http { http, url, secret }
+        }
+        (None, Some(function_name)) => CallOutTarget::Lambda {
+            lambda,
+            function_name,
+        },
+        (None, None) => return Ok(None),
+        (Some(_), Some(function_name)) => return Err(
+            Error::BadEnvironmentVariable("RISK_HOOK_FUNCTION_NAME", function_name),
+        ),
+    };
+    let on_error = match load_var("RISK_HOOK_ON_ERROR")? {
+        Some(decision) => parse_decision(&decision)
+            .ok_or(Error::BadEnvironmentVariable("RISK_HOOK_ON_ERROR", decision))?,
+        None => RiskDecision::Allow,
+    };
+    Ok(Some(RemoteRiskHook { target, on_error }))
+}
+
+// loads a non-empty environment variable.
+fn load_var(name: &'static str) -> Result<Option<String>, Error> {
+    match config::var(name) {
+        Ok(value) if value.is_empty() => Ok(None),
+        Ok(value) => Ok(Some(value)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(value)) => Err(
+            Error::BadEnvironmentVariable(name, value.to_string_lossy().into()),
+        ),
+    }
+}
+
+fn parse_decision(value: &str) -> Option<RiskDecision> {
+    serde_json::from_value(serde_json::Value::String(value.into())).ok()
+}
+
+impl RemoteRiskHook {
+    async fn call_out(&self, context: &RiskContext<'_>) -> Result<RiskAssessment, Error> {
+        let body = serde_json::to_string(context)
+            .or(Err(Error::RiskHook("failed to serialize context")))?;
+        let payload = match &self.target {
+            CallOutTarget::Http { http, url, secret } => {
+                let mut request = http
+                    .post(url.clone())
+                    .header("Content-Type", "application/json");
+                if let Some((secret_id, secrets)) = secret {
+                    let secret = secrets.get(secret_id).await?;
+                    request = request.header(
+                        RISK_SIGNATURE_HEADER,
+                        sign(secret.as_bytes(), unix_time(), &body),
+                    );
+                }
+                request
+                    .body(body)
+                    .send()
+                    .await
+                    .and_then(|res| res.error_for_status())
+                    .map_err(|e| {
+                        error!(?e, "calling out risk hook");
+                        Error::RiskHook("risk hook unreachable")
+                    })?
+                    .bytes()
+                    .await
+                    .or(Err(Error::RiskHook("failed to read risk assessment")))?
+                    .to_vec()
+            }
+            CallOutTarget::Lambda { lambda, function_name } => {
+                let res = tokio::time::timeout(
+                    CALL_OUT_TIMEOUT,
+                    lambda
+                        .invoke()
+                        .function_name(function_name)
+                        .payload(Blob::new(body))
+                        .send(),
+                )
+                    .await
+                    .or(Err(Error::RiskHook("risk hook timed out")))?
+                    .map_err(|e| {
+                        error!(?e, "invoking risk hook");
+                        Error::RiskHook("failed to invoke risk hook")
+                    })?;
+                if let Some(function_error) = res.function_error() {
+                    error!("risk hook failed: {}", function_error);
+                    return Err(Error::RiskHook("risk hook failed"));
+                }
+                res.payload
+                    .ok_or(Error::RiskHook("missing risk assessment"))?
+                    .into_inner()
+            }
+        };
+        serde_json::from_slice(&payload).map_err(|e| {
+            error!(?e, "parsing risk assessment");
+            Error::RiskHook("malformed risk assessment")
+        })
+    }
+}
+
+impl RiskHook for RemoteRiskHook {
+    // a failed call-out is decided by `on_error` instead of failing the
+    // authentication.
+    #[instrument(skip_all, fields(user_handle = %redact(context.user_handle)))]
+    async fn assess(&self, context: &RiskContext<'_>) -> Result<RiskAssessment, Error> {
+        match self.call_out(context).await {
+            Ok(assessment) => {
+                info!("risk assessment: {:?}", assessment.decision);
+                Ok(assessment)
+            }
+            Err(e) => {
+                error!("risk assessment failed: {}", e);
+                Ok(RiskAssessment {
+                    decision: self.on_error,
+                    reason: Some(format!("risk hook error: {}", e)),
+                })
+            }
+        }
+    }
+}
+
+/// Assesses the risk of an authentication if a hook is configured.
+///
+/// Always allows if `hook` is `None`.
+pub async fn assess_risk(
+    hook: Option<&impl RiskHook>,
+    context: &RiskContext<'_>,
+) -> Result<RiskAssessment, Error> {
+    match hook {
+        Some(hook) => hook.assess(context).await,
+        None => Ok(RiskAssessment::allow()),
+    }
+}
+
+/// Creates a 403 response for an authentication rejected by the risk
+/// assessment.
+///
+/// `error` is an error code returned by [`RiskAssessment::rejection`].
+pub fn risk_rejected(error: &'static str) -> Result<Response<Body>, lambda_http::Error> {
+    let message = match error {
+        STEP_UP_REQUIRED => "user verification is required",
+        _ => "authentication denied",
+    };
+    let body = serde_json::to_string(&ErrorResponseBody {
+        error,
+        message: message.into(),
+        field: None,
+        localized_message: None,
+        request_id: None,
+    })?;
+    Ok(Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .header("Content-Type", "application/json")
+        .body(body.into())?)
+}
+
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn risk_assessment_should_parse_decision_and_reason() {
+        let assessment: RiskAssessment = serde_json::from_str(
+            r#"{"decision":"step_up","reason":"new country"}"#,
+        ).unwrap();
+        assert_eq!(assessment.decision, RiskDecision::StepUp);
+        assert_eq!(assessment.reason.as_deref(), Some("new country"));
+        let assessment: RiskAssessment = serde_json::from_str(r#"{"decision":"deny"}"#)
+            .unwrap();
+        assert_eq!(assessment.decision, RiskDecision::Deny);
+        assert!(serde_json::from_str::<RiskAssessment>(r#"{"decision":"maybe"}"#).is_err());
+    }
+
+    #[test]
+    fn risk_assessment_should_reject_step_up_unless_user_verified() {
+        let assessment = |decision| RiskAssessment { decision, reason: None };
+        assert_eq!(assessment(RiskDecision::Allow).rejection(false), None);
+        assert_eq!(assessment(RiskDecision::StepUp).rejection(true), None);
+        assert_eq!(
+            assessment(RiskDecision::StepUp).rejection(false),
+            Some(STEP_UP_REQUIRED),
+        );
+        assert_eq!(
+            assessment(RiskDecision::Deny).rejection(true),
+            Some(AUTHENTICATION_DENIED),
+        );
+    }
+
+    #[test]
+    fn parse_decision_should_parse_snake_case() {
+        assert_eq!(parse_decision("allow"), Some(RiskDecision::Allow));
+        assert_eq!(parse_decision("step_up"), Some(RiskDecision::StepUp));
+        assert_eq!(parse_decision("deny"), Some(RiskDecision::Deny));
+        assert_eq!(parse_decision("stepUp"), None);
+    }
+
+    #[test]
+    fn risk_context_should_omit_unknown_client() {
+        let credential = CredentialItem {
+            credential_type: Some("passkey".into()),
+            backup_eligible: Some(true),
+            backup_state: Some(true),
+            authenticator_attachment: Some("platform".into()),
+            created_at: "2026-10-01T00:00:00Z".into(),
+            updated_at: "2026-10-01T00:00:00Z".into(),
+            ..CredentialItem::for_test("user", "abc")
+        };
+        let client = ClientInfo::default();
+        let context = serde_json::to_value(RiskContext::new(&credential, None, &client, true))
+            .unwrap();
+        assert_eq!(context, serde_json::json!({
+            "userHandle": "user",
+            "userVerified": true,
+            "credential": {
+                "credentialId": "abc",
+                "credentialType": "passkey",
+                "authenticatorAttachment": "platform",
+                "backupState": true,
+                "createdAt": "2026-10-01T00:00:00Z",
+            },
+        }));
+    }
+}
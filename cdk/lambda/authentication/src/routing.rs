@@ -0,0 +1,810 @@
+//! Versioned routing.
+//!
+//! Endpoints are served under a version prefix like `/v1/` so that the shapes
+//! of requests and responses can evolve without breaking existing clients.
+//! Paths without a version prefix are routed to [`ApiVersion::V1`] for
+//! backward compatibility.
+//!
+//! A route also requires a method with [`require_method`], and a JSON body
+//! with [`require_json_body`]. A request with another method fails with
+//! [`ApiError::MethodNotAllowed`] (405), and a body of another media type
+//! fails with [`ApiError::UnsupportedMediaType`] (415).
+//!
+//! ## Router
+//!
+//! A [`Router`] maps a method and a route (the job path without the base
+//! path, the tenant, and the version) to an async handler, so that a binary
+//! declares its endpoints instead of matching paths by hand:
+//! - a route that no endpoint serves fails with [`ApiError::NotFound`] (404)
+//! - a route served with other methods fails with
+//!   [`ApiError::MethodNotAllowed`] (405), which tells every method of the
+//!   route
+//! - a client error of a handler ends with the response of the [`ApiError`];
+//!   see [`crate::api_error::recover_api_error`]
+//! - every request is logged with the pattern of the route instead of the
+//!   path, so that no identifier in the path leaks into the logs
+//! - if a [`CorsPolicy`] is given, a preflight request is answered, and a
+//!   response to an allowed origin has the CORS headers
+//! - if a [`BearerAuth`] is given, the bearer token of a request is verified,
+//!   and the authenticated user is injected into the request before the
+//!   handler runs; see [`Router::authenticate`]
+//!
+//! A pattern consists of literal segments and parameters in braces; e.g.,
+//! `/users/{userHandle}/credentials`. A parameter matches a non-empty segment,
+//! and is given to the handler in [`RouteParams`].
+
+use lambda_http::{
+    Body,
+    Request,
+    RequestExt as _,
+    Response,
+    http::{
+        HeaderValue,
+        Method,
+        StatusCode,
+        header::{
+            ACCESS_CONTROL_ALLOW_HEADERS,
+            ACCESS_CONTROL_ALLOW_METHODS,
+            ACCESS_CONTROL_ALLOW_ORIGIN,
+            ACCESS_CONTROL_MAX_AGE,
+            ACCESS_CONTROL_REQUEST_HEADERS,
+            ACCESS_CONTROL_REQUEST_METHOD,
+            AUTHORIZATION,
+            ORIGIN,
+            VARY,
+        },
+    },
+};
+use std::env;
+use std::future::Future;
+use std::pin::Pin;
+use tracing::{info, warn};
+
+use crate::api_error::{ApiError, recover_api_error};
+use crate::config;
+use crate::content::{JSON_CONTENT_TYPE, has_content_type};
+use crate::error::Error;
+use crate::identity::{AuthenticatedUser, authenticated_user_handle};
+use crate::jwt::{JwtVerifier, load_jwt_verifier};
+use crate::payload::ErrorResponseBody;
+
+/// Default lifetime of a preflight response in seconds.
+pub const DEFAULT_CORS_MAX_AGE: u32 = 600;
+
+/// Version of the API.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ApiVersion {
+    /// Version 1.
+    V1,
+}
+
+impl ApiVersion {
+    fn from_segment(segment: &str) -> Option<Self> {
+        match segment {
+            "v1" => Some(ApiVersion::V1),
+            _ => None,
+        }
+    }
+}
+
+/// Resolves the API version of a job path.
+///
+/// Returns the version and the rest of the path; e.g.,
+/// `(ApiVersion::V1, "/start")` for "/v1/start" and "/start".
+///
+/// Returns `None` if the version is not supported.
+pub fn resolve_version(job_path: &str) -> Option<(ApiVersion, &str)> {
+    let Some(rest) = job_path.strip_prefix('/') else {
+        return Some((ApiVersion::V1, job_path));
+    };
+    let (segment, rest) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    if is_version_segment(segment) {
+        ApiVersion::from_segment(segment).map(|version| (version, rest))
+    } else {
+        // unversioned legacy path
+        Some((ApiVersion::V1, job_path))
+    }
+}
+
+// a version segment is "v" followed by digits.
+fn is_version_segment(segment: &str) -> bool {
+    segment.strip_prefix('v')
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// Creates a 404 response for an unsupported API version.
+pub fn unsupported_version(
+    job_path: &str,
+) -> Result<Response<Body>, lambda_http::Error> {
+    let body = serde_json::to_string(&ErrorResponseBody {
+        error: "unsupported_version",
+        message: format!("unsupported API version: {}", job_path),
+        field: None,
+        localized_message: None,
+        request_id: None,
+    })?;
+    Ok(Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .header("Content-Type", "application/json")
+        .body(body.into())?)
+}
+
+/// Requires a given method of a request.
+pub fn require_method(request: &Request, method: Method) -> Result<(), ApiError> {
+    if request.method() == method {
+        Ok(())
+    } else {
+        Err(ApiError::MethodNotAllowed(vec![method]))
+    }
+}
+
+/// Requires a JSON body of a request.
+///
+/// A non-empty body must be `application/json`. A CBOR body has been
+/// transcoded into JSON by [`crate::content::negotiate_content`] before it
+/// reaches a route. An empty body passes, so that the payload parser can
+/// report the missing body.
+pub fn require_json_body(request: &Request) -> Result<(), ApiError> {
+    let is_empty = request.body().as_ref().iter().all(u8::is_ascii_whitespace);
+    if is_empty || has_content_type(request, JSON_CONTENT_TYPE) {
+        Ok(())
+    } else {
+        Err(ApiError::UnsupportedMediaType(JSON_CONTENT_TYPE))
+    }
+}
+
+/// Requires a POST request with a JSON body.
+pub fn require_json_post(request: &Request) -> Result<(), ApiError> {
+    require_method(request, Method::POST)?;
+    require_json_body(request)
+}
+
+/// Strips the base path from the path of a request.
+///
+/// Fails if the path does not start with `base_path`, which means the API
+/// is misconfigured.
+pub fn job_path<'a>(
+    request: &'a Request,
+    base_path: &str,
+) -> Result<&'a str, lambda_http::Error> {
+    request.raw_http_path()
+        .strip_prefix(base_path)
+        .ok_or_else(|| format!("path must start with \"{}\"", base_path).into())
+}
+
+/// Future of a route handler.
+pub type RouteFuture = Pin<Box<dyn Future<Output = Result<Response<Body>, lambda_http::Error>>>>;
+
+// Type-erased route handler.
+type BoxedHandler<C> = Box<dyn Fn(C, Request, RouteParams) -> RouteFuture>;
+
+/// Parameters captured from the route of a request.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RouteParams(Vec<(&'static str, String)>);
+
+impl RouteParams {
+    /// Returns the value of a given parameter.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the value of a given parameter that the pattern declares.
+    ///
+    /// Fails if the pattern does not declare the parameter, which is a bug of
+    /// the route.
+    pub fn require(&self, name: &str) -> Result<String, ApiError> {
+        self.get(name)
+            .map(Into::into)
+            .ok_or_else(|| ApiError::internal(format!("no route parameter: {}", name)))
+    }
+}
+
+// Segment of a route pattern.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Segment {
+    Literal(&'static str),
+    Param(&'static str),
+}
+
+fn parse_pattern(pattern: &'static str) -> Vec<Segment> {
+    pattern.split('/')
+        .skip(1)
+        .map(|segment| match segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+            Some(name) => Segment::Param(name),
+            None => Segment::Literal(segment),
+        })
+        .collect()
+}
+
+// matches a route against a pattern and captures the parameters.
+fn match_pattern(pattern: &[Segment], route: &str) -> Option<RouteParams> {
+    let segments: Vec<&str> = route.strip_prefix('/')?.split('/').collect();
+    if segments.len() != pattern.len() {
+        return None;
+    }
+    let mut params = Vec::new();
+    for (expected, segment) in pattern.iter().zip(segments) {
+        match expected {
+            Segment::Literal(literal) if *literal == segment => {}
+            Segment::Param(name) if !segment.is_empty() =>
+                params.push((*name, segment.to_string())),
+            _ => return None,
+        }
+    }
+    Some(RouteParams(params))
+}
+
+struct Route<C> {
+    method: Method,
+    pattern: &'static str,
+    segments: Vec<Segment>,
+    handler: BoxedHandler<C>,
+}
+
+/// Router that maps a method and a route to a handler.
+///
+/// `C` is the context given to every handler; e.g., the shared state and the
+/// authenticated user.
+pub struct Router<C> {
+    routes: Vec<Route<C>>,
+    cors: Option<CorsPolicy>,
+    bearer_auth: Option<BearerAuth>,
+}
+
+impl<C> Default for Router<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C> Router<C> {
+    /// Creates a router without any route.
+    pub fn new() -> Self {
+        Self {
+            routes: Vec::new(),
+            cors: None,
+            bearer_auth: None,
+        }
+    }
+
+    /// Adds a route.
+    ///
+    /// Panics if `pattern` does not start with a slash.
+    pub fn route<F, Fut>(mut self, method: Method, pattern: &'static str, handler: F) -> Self
+    where
+        F: Fn(C, Request, RouteParams) -> Fut + 'static,
+        Fut: Future<Output = Result<Response<Body>, lambda_http::Error>> + 'static,
+    {
+        assert!(pattern.starts_with('/'), "pattern must start with a slash: {}", pattern);
+        self.routes.push(Route {
+            method,
+            pattern,
+            segments: parse_pattern(pattern),
+            handler: Box::new(move |context, request, params| -> RouteFuture {
+                Box::pin(handler(context, request, params))
+            }),
+        });
+        self
+    }
+
+    /// Adds a GET route.
+    pub fn get<F, Fut>(self, pattern: &'static str, handler: F) -> Self
+    where
+        F: Fn(C, Request, RouteParams) -> Fut + 'static,
+        Fut: Future<Output = Result<Response<Body>, lambda_http::Error>> + 'static,
+    {
+        self.route(Method::GET, pattern, handler)
+    }
+
+    /// Adds a POST route.
+    pub fn post<F, Fut>(self, pattern: &'static str, handler: F) -> Self
+    where
+        F: Fn(C, Request, RouteParams) -> Fut + 'static,
+        Fut: Future<Output = Result<Response<Body>, lambda_http::Error>> + 'static,
+    {
+        self.route(Method::POST, pattern, handler)
+    }
+
+    /// Adds a DELETE route.
+    pub fn delete<F, Fut>(self, pattern: &'static str, handler: F) -> Self
+    where
+        F: Fn(C, Request, RouteParams) -> Fut + 'static,
+        Fut: Future<Output = Result<Response<Body>, lambda_http::Error>> + 'static,
+    {
+        self.route(Method::DELETE, pattern, handler)
+    }
+
+    /// Applies a given CORS policy.
+    ///
+    /// No CORS headers are added if `cors` is `None`; e.g., when API Gateway
+    /// handles CORS.
+    pub fn with_cors(mut self, cors: Option<CorsPolicy>) -> Self {
+        self.cors = cors;
+        self
+    }
+
+    /// Verifies bearer tokens with a given [`BearerAuth`].
+    ///
+    /// No token is verified if `bearer_auth` is `None`; e.g., when the JWT
+    /// authorizer of API Gateway verifies tokens.
+    pub fn with_bearer_auth(mut self, bearer_auth: Option<BearerAuth>) -> Self {
+        self.bearer_auth = bearer_auth;
+        self
+    }
+
+    /// Authenticates the caller of a request with the bearer token.
+    ///
+    /// [`Router::handle`] calls this before the handler. Call this earlier if
+    /// the context of the handler needs the authenticated user; e.g., to
+    /// resolve the user handle with
+    /// [`crate::identity::authenticated_user_handle`]. Does nothing without a
+    /// [`BearerAuth`].
+    pub async fn authenticate(&self, request: &mut Request) {
+        if let Some(bearer_auth) = self.bearer_auth.as_ref() {
+            bearer_auth.authenticate(request).await;
+        }
+    }
+
+    /// Returns the methods that a given route accepts.
+    ///
+    /// Returns an empty list if no route matches.
+    pub fn allowed_methods(&self, route: &str) -> Vec<Method> {
+        let mut methods: Vec<Method> = Vec::new();
+        for r in &self.routes {
+            if !methods.contains(&r.method) && match_pattern(&r.segments, route).is_some() {
+                methods.push(r.method.clone());
+            }
+        }
+        methods
+    }
+
+    // finds the route of a given method and route.
+    fn find(&self, method: &Method, route: &str) -> Result<(&Route<C>, RouteParams), ApiError> {
+        let mut allowed: Vec<Method> = Vec::new();
+        for r in &self.routes {
+            if let Some(params) = match_pattern(&r.segments, route) {
+                if r.method == method {
+                    return Ok((r, params));
+                }
+                if !allowed.contains(&r.method) {
+                    allowed.push(r.method.clone());
+                }
+            }
+        }
+        if allowed.is_empty() {
+            Err(ApiError::NotFound(route.into()))
+        } else {
+            Err(ApiError::MethodNotAllowed(allowed))
+        }
+    }
+
+    /// Handles a request on a given route.
+    ///
+    /// `route` is the path of the request without the base path, the tenant,
+    /// and the version; e.g., "/credentials".
+    /// A client error ends with the response of the [`ApiError`], while a
+    /// server error fails.
+    pub async fn handle(
+        &self,
+        context: C,
+        mut request: Request,
+        route: &str,
+    ) -> Result<Response<Body>, lambda_http::Error> {
+        let method = request.method().clone();
+        let origin = request.headers().get(ORIGIN).cloned();
+        let (pattern, res) = match self.cors.as_ref() {
+            Some(cors) if is_preflight(&request) => {
+                ("preflight", cors.preflight(&request, route, &self.allowed_methods(route)))
+            }
+            _ => match self.find(&method, route) {
+                Ok((r, params)) => {
+                    self.authenticate(&mut request).await;
+                    let res = (r.handler)(context, request, params).await;
+                    (r.pattern, recover_api_error(res))
+                }
+                Err(e) => ("-", e.into_response()),
+            },
+        };
+        let mut res = res?;
+        info!("{} {} {}", method, pattern, res.status().as_u16());
+        if let Some(cors) = self.cors.as_ref() {
+            cors.apply(origin.as_ref(), &mut res);
+        }
+        Ok(res)
+    }
+}
+
+/// CORS policy applied by a [`Router`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CorsPolicy {
+    /// Allowed origins; e.g., `https://example.com`.
+    ///
+    /// "*" allows any origin.
+    pub allowed_origins: Vec<String>,
+
+    /// Lifetime of a preflight response in seconds.
+    pub max_age: u32,
+}
+
+impl CorsPolicy {
+    /// Creates a policy that allows given origins.
+    pub fn new(allowed_origins: Vec<String>) -> Self {
+        Self {
+            allowed_origins,
+            max_age: DEFAULT_CORS_MAX_AGE,
+        }
+    }
+
+    // returns the value of `Access-Control-Allow-Origin` for a given origin.
+    fn allow_origin(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+        if self.allowed_origins.iter().any(|o| o == "*") {
+            Some(HeaderValue::from_static("*"))
+        } else if self.allowed_origins.iter().any(|o| o.as_bytes() == origin.as_bytes()) {
+            Some(origin.clone())
+        } else {
+            None
+        }
+    }
+
+    // adds the CORS headers to a response to an allowed origin.
+    fn apply(&self, origin: Option<&HeaderValue>, res: &mut Response<Body>) {
+        let headers = res.headers_mut();
+        headers.append(VARY, HeaderValue::from_static("Origin"));
+        if let Some(allow_origin) = origin.and_then(|o| self.allow_origin(o)) {
+            headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        }
+    }
+
+    // answers a preflight request on a route that accepts given methods.
+    fn preflight(
+        &self,
+        request: &Request,
+        route: &str,
+        methods: &[Method],
+    ) -> Result<Response<Body>, lambda_http::Error> {
+        if methods.is_empty() {
+            return ApiError::NotFound(route.into()).into_response();
+        }
+        let allow_methods = methods.iter()
+            .map(Method::as_str)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut builder = Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .header(ACCESS_CONTROL_ALLOW_METHODS, allow_methods)
+            .header(ACCESS_CONTROL_MAX_AGE, self.max_age);
+        if let Some(headers) = request.headers().get(ACCESS_CONTROL_REQUEST_HEADERS) {
+            builder = builder.header(ACCESS_CONTROL_ALLOW_HEADERS, headers.clone());
+        }
+        Ok(builder.body(Body::Empty)?)
+    }
+}
+
+/// Middleware that authenticates callers with bearer tokens.
+///
+/// Verifies the token in the `Authorization` header with a [`JwtVerifier`],
+/// which caches the JWKS of the issuers and tolerates clock skew, and puts an
+/// [`AuthenticatedUser`] in the extensions of the request. A request without a
+/// valid token passes unauthenticated, so that public routes still work with
+/// a stale token; a protected handler rejects it with
+/// [`ApiError::Unauthenticated`] because
+/// [`crate::identity::authenticated_user_handle`] returns `None`.
+///
+/// A request already authorized by the JWT authorizer of API Gateway is left
+/// as it is.
+pub struct BearerAuth {
+    verifier: JwtVerifier,
+}
+
+impl BearerAuth {
+    /// Creates a middleware that verifies tokens with a given verifier.
+    pub fn new(verifier: JwtVerifier) -> Self {
+        Self { verifier }
+    }
+
+    /// Authenticates the caller of a request.
+    ///
+    /// Does nothing if the request has no bearer token, or has already been
+    /// authenticated.
+    pub async fn authenticate(&self, request: &mut Request) {
+        if authenticated_user_handle(request).is_some() {
+            return;
+        }
+        let Some(token) = bearer_token(request) else {
+            return;
+        };
+        match self.verifier.verify(token).await {
+            Ok(verified) => {
+                request.extensions_mut().insert(AuthenticatedUser {
+                    user_handle: verified.user_handle,
+                    groups: verified.groups,
+                });
+            }
+            Err(e) => warn!("invalid bearer token: {}", e),
+        }
+    }
+}
+
+// returns the token in `Authorization: Bearer <token>`.
+fn bearer_token(request: &Request) -> Option<&str> {
+    let value = request.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then_some(token)
+}
+
+/// Loads the middleware that authenticates callers with bearer tokens.
+///
+/// You can specify to `BEARER_AUTH` environment variable "true" to enable the
+/// middleware. The trusted issuers are configured with the environment
+/// variables of [`load_jwt_verifier`]; e.g., `USER_POOL_ID`.
+///
+/// Returns `None` if `BEARER_AUTH` is not "true".
+pub fn load_bearer_auth() -> Result<Option<BearerAuth>, Error> {
+    match config::var("BEARER_AUTH").as_deref() {
+        Ok("true") => {}
+        Ok("false") | Err(env::VarError::NotPresent) => return Ok(None),
+        Ok(value) => return Err(
+            Error::BadEnvironmentVariable("BEARER_AUTH", value.into()),
+        ),
+        Err(env::VarError::NotUnicode(value)) => return Err(
+            Error::BadEnvironmentVariable("BEARER_AUTH", value.to_string_lossy().into()),
+        ),
+    }
+    load_jwt_verifier()?
+        .map(|verifier| Some(BearerAuth::new(verifier)))
+        .ok_or(Error::BadEnvironmentVariable("USER_POOL_ID", "".into()))
+}
+
+fn is_preflight(request: &Request) -> bool {
+    request.method() == Method::OPTIONS
+        && request.headers().contains_key(ORIGIN)
+        && request.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+/// Loads the CORS policy.
+///
+/// You can specify to `CORS_ALLOWED_ORIGINS` environment variable a
+/// comma-separated list of origins allowed to call the API; e.g.,
+/// `https://example.com,http://localhost:5173`, or "*" to allow any origin.
+/// `CORS_MAX_AGE` optionally specifies the lifetime of a preflight response in
+/// seconds; [`DEFAULT_CORS_MAX_AGE`] by default.
+///
+/// Returns `None` if `CORS_ALLOWED_ORIGINS` is not set; e.g., when API Gateway
+/// handles CORS.
+pub fn load_cors_policy() -> Result<Option<CorsPolicy>, Error> {
+    let allowed_origins = match config::var("CORS_ALLOWED_ORIGINS") {
+        Ok(origins) => parse_cors_origins(&origins),
+        Err(env::VarError::NotPresent) => return Ok(None),
+        Err(env::VarError::NotUnicode(origins)) => return Err(
+            Error::BadEnvironmentVariable(
+                "CORS_ALLOWED_ORIGINS",
+                origins.to_string_lossy().into(),
+            ),
+        ),
+    };
+    let max_age = match config::var("CORS_MAX_AGE") {
+        Ok(max_age) => max_age.parse()
+            .or(Err(Error::BadEnvironmentVariable("CORS_MAX_AGE", max_age)))?,
+        Err(env::VarError::NotPresent) => DEFAULT_CORS_MAX_AGE,
+        Err(env::VarError::NotUnicode(max_age)) => return Err(
+            Error::BadEnvironmentVariable(
+                "CORS_MAX_AGE",
+                max_age.to_string_lossy().into(),
+            ),
+        ),
+    };
+    Ok(Some(CorsPolicy {
+        allowed_origins,
+        max_age,
+    }))
+}
+
+fn parse_cors_origins(origins: &str) -> Vec<String> {
+    origins.split(',')
+        .map(|origin| origin.trim().trim_end_matches('/'))
+        .filter(|origin| !origin.is_empty())
+        .map(Into::into)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: Method, content_type: Option<&str>, body: &str) -> Request {
+        let mut builder = lambda_http::http::Request::builder().method(method);
+        if let Some(content_type) = content_type {
+            builder = builder.header("Content-Type", content_type);
+        }
+        builder.body(Body::from(body)).unwrap()
+    }
+
+    #[test]
+    fn resolve_version_should_strip_version_prefix() {
+        assert_eq!(resolve_version("/v1/start"), Some((ApiVersion::V1, "/start")));
+        assert_eq!(
+            resolve_version("/v1/security-key/start"),
+            Some((ApiVersion::V1, "/security-key/start")),
+        );
+        assert_eq!(resolve_version("/v1"), Some((ApiVersion::V1, "/")));
+    }
+
+    #[test]
+    fn resolve_version_should_route_unversioned_path_to_v1() {
+        assert_eq!(resolve_version("/start"), Some((ApiVersion::V1, "/start")));
+        assert_eq!(
+            resolve_version("/security-key/finish"),
+            Some((ApiVersion::V1, "/security-key/finish")),
+        );
+        assert_eq!(resolve_version("/verify"), Some((ApiVersion::V1, "/verify")));
+    }
+
+    #[test]
+    fn resolve_version_should_reject_unsupported_version() {
+        assert_eq!(resolve_version("/v2/start"), None);
+        assert_eq!(resolve_version("/v10/start"), None);
+    }
+
+    #[test]
+    fn require_method_should_reject_other_method() {
+        assert!(require_method(&request(Method::POST, None, ""), Method::POST).is_ok());
+        assert_eq!(
+            require_method(&request(Method::GET, None, ""), Method::POST),
+            Err(ApiError::MethodNotAllowed(vec![Method::POST])),
+        );
+    }
+
+    #[test]
+    fn require_json_body_should_reject_other_media_type() {
+        let json = Some("application/json; charset=utf-8");
+        assert!(require_json_body(&request(Method::POST, json, "{}")).is_ok());
+        assert!(require_json_body(&request(Method::POST, None, "")).is_ok());
+        assert_eq!(
+            require_json_body(&request(Method::POST, Some("text/plain"), "{}")),
+            Err(ApiError::UnsupportedMediaType(JSON_CONTENT_TYPE)),
+        );
+        assert_eq!(
+            require_json_body(&request(Method::POST, None, "{}")),
+            Err(ApiError::UnsupportedMediaType(JSON_CONTENT_TYPE)),
+        );
+    }
+
+    async fn list(
+        context: &'static str,
+        _: Request,
+        _: RouteParams,
+    ) -> Result<Response<Body>, lambda_http::Error> {
+        Ok(Response::new(Body::from(context)))
+    }
+
+    async fn delete(
+        _: &'static str,
+        _: Request,
+        params: RouteParams,
+    ) -> Result<Response<Body>, lambda_http::Error> {
+        Ok(Response::new(Body::from(params.require("credentialId")?)))
+    }
+
+    async fn expire(
+        _: &'static str,
+        _: Request,
+        _: RouteParams,
+    ) -> Result<Response<Body>, lambda_http::Error> {
+        Err(ApiError::SessionExpired("expired").into())
+    }
+
+    fn router() -> Router<&'static str> {
+        Router::new()
+            .get("/credentials", list)
+            .delete("/credentials/{credentialId}", delete)
+            .post("/step-up/start", expire)
+    }
+
+    #[test]
+    fn match_pattern_should_capture_parameters() {
+        let pattern = parse_pattern("/users/{userHandle}/credentials/{credentialId}");
+        let params = match_pattern(&pattern, "/users/abc/credentials/xyz").unwrap();
+        assert_eq!(params.get("userHandle"), Some("abc"));
+        assert_eq!(params.get("credentialId"), Some("xyz"));
+        assert_eq!(params.get("unknown"), None);
+        assert_eq!(match_pattern(&pattern, "/users//credentials/xyz"), None);
+        assert_eq!(match_pattern(&pattern, "/users/abc/credentials"), None);
+        assert_eq!(match_pattern(&pattern, "/users/abc/credentials/xyz/lockout"), None);
+    }
+
+    #[tokio::test]
+    async fn router_should_dispatch_by_method_and_route() {
+        let router = router();
+        let res = router.handle("context", request(Method::GET, None, ""), "/credentials")
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.body().as_ref(), b"context");
+        let res = router.handle("context", request(Method::DELETE, None, ""), "/credentials/xyz")
+            .await
+            .unwrap();
+        assert_eq!(res.body().as_ref(), b"xyz");
+    }
+
+    #[tokio::test]
+    async fn router_should_reject_unknown_route_and_method() {
+        let router = router();
+        let res = router.handle("context", request(Method::GET, None, ""), "/unknown")
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        let res = router.handle("context", request(Method::PUT, None, ""), "/credentials")
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(res.headers()["Allow"], "GET");
+    }
+
+    #[tokio::test]
+    async fn router_should_recover_client_error_of_handler() {
+        let res = router().handle("context", request(Method::POST, None, ""), "/step-up/start")
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn router_should_apply_cors_policy() {
+        let router = router()
+            .with_cors(Some(CorsPolicy::new(vec!["https://example.com".into()])));
+        let preflight = lambda_http::http::Request::builder()
+            .method(Method::OPTIONS)
+            .header(ORIGIN, "https://example.com")
+            .header(ACCESS_CONTROL_REQUEST_METHOD, "GET")
+            .header(ACCESS_CONTROL_REQUEST_HEADERS, "authorization")
+            .body(Body::Empty)
+            .unwrap();
+        let res = router.handle("context", preflight, "/credentials").await.unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(res.headers()[ACCESS_CONTROL_ALLOW_ORIGIN], "https://example.com");
+        assert_eq!(res.headers()[ACCESS_CONTROL_ALLOW_METHODS], "GET");
+        assert_eq!(res.headers()[ACCESS_CONTROL_ALLOW_HEADERS], "authorization");
+
+        let other = lambda_http::http::Request::builder()
+            .method(Method::GET)
+            .header(ORIGIN, "https://attacker.example")
+            .body(Body::Empty)
+            .unwrap();
+        let res = router.handle("context", other, "/credentials").await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!res.headers().contains_key(ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[test]
+    fn bearer_token_should_require_bearer_scheme() {
+        let with_authorization = |value: &str| lambda_http::http::Request::builder()
+            .header(AUTHORIZATION, value)
+            .body(Body::Empty)
+            .unwrap();
+        assert_eq!(
+            bearer_token(&with_authorization("Bearer abc.def.ghi")),
+            Some("abc.def.ghi"),
+        );
+        assert_eq!(
+            bearer_token(&with_authorization("bearer abc.def.ghi")),
+            Some("abc.def.ghi"),
+        );
+        assert_eq!(bearer_token(&with_authorization("Basic dXNlcjpwYXNz")), None);
+        assert_eq!(bearer_token(&with_authorization("Bearer ")), None);
+        assert_eq!(bearer_token(&request(Method::GET, None, "")), None);
+    }
+
+    #[test]
+    fn parse_cors_origins_should_skip_empty_origins() {
+        assert_eq!(
+            parse_cors_origins("https://example.com/, ,http://localhost:5173"),
+            vec!["https://example.com".to_string(), "http://localhost:5173".into()],
+        );
+    }
+}
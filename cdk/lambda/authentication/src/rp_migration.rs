@@ -0,0 +1,174 @@
+//! Migration of the relying party ID.
+//!
+//! A credential is scoped to the ID of the relying party (RP ID) with which
+//! it was registered, so changing the RP ID orphans every credential
+//! registered before. In the migration mode, an assertion is verified with
+//! the current RP ID first and then with the legacy RP ID, so that users can
+//! still sign in with their legacy credentials until they register new ones;
+//! see [`verify_with_fallback`].
+//!
+//! Credentials verified only with the legacy RP ID are flagged with the
+//! legacy RP ID (`legacyRpId`) in the credential table so that they can be
+//! reported and re-registered; see
+//! [`crate::users::UserDirectory::flag_legacy_credential`].
+//!
+//! Clients have to retry `navigator.credentials.get` with `rpId` of the
+//! legacy RP ID if no credential is found for the current RP ID.
+
+use tracing::{error, info};
+use webauthn_rs::{Webauthn, prelude::Url};
+
+use crate::android::load_apk_key_hashes;
+use crate::config;
+use crate::error::Error;
+use crate::parameters::{build_webauthn, load_allowed_origins, load_relying_party_origin};
+use crate::tenant::Tenant;
+
+/// Legacy relying party in the migration mode of the RP ID.
+#[derive(Debug)]
+pub struct LegacyRelyingParty {
+    rp_id: String,
+    webauthn: Webauthn,
+}
+
+impl LegacyRelyingParty {
+    /// Builds a legacy relying party.
+    ///
+    /// `rp_origin` is the origin of the legacy relying party, and
+    /// `allowed_origins` are the origins from which the legacy credentials
+    /// may be used; e.g., the origin of the current relying party.
+    pub fn new(
+        rp_id: impl Into<String>,
+        rp_origin: &Url,
+        allowed_origins: &[Url],
+    ) -> Result<Self, Error> {
+        let rp_id = rp_id.into();
+        let webauthn = build_webauthn(&rp_id, rp_origin, "Passkey Test", allowed_origins)?;
+        Ok(Self { rp_id, webauthn })
+    }
+
+    /// Legacy RP ID.
+    pub fn rp_id(&self) -> &str {
+        &self.rp_id
+    }
+
+    /// [`Webauthn`] of the legacy relying party.
+    pub fn webauthn(&self) -> &Webauthn {
+        &self.webauthn
+    }
+}
+
+/// Loads the legacy relying party.
+///
+/// You can specify to the following environment variables:
+/// - `LEGACY_RP_ID`: legacy RP ID, which enables the migration mode
+/// - `LEGACY_RP_ORIGIN`: origin (URL) of the legacy relying party; the origin
+///   of the current relying party by default
+///
+/// The origin of the current relying party, and the origins loaded with
+/// [`load_allowed_origins`] and [`load_apk_key_hashes`] are also allowed.
+///
+/// Returns `None` if `LEGACY_RP_ID` is not set, which means the migration
+/// mode is disabled.
+pub async fn load_legacy_relying_party(
+    ssm: aws_sdk_ssm::Client,
+) -> Result<Option<LegacyRelyingParty>, Error> {
+    let rp_id = match config::var("LEGACY_RP_ID") {
+        Ok(rp_id) if !rp_id.is_empty() => rp_id,
+        _ => return Ok(None),
+    };
+    let (current_rp_id, current_origin) = load_relying_party_origin(ssm).await?;
+    if rp_id == current_rp_id {
+        error!("legacy RP ID is the current RP ID");
+        return Err(Error::BadEnvironmentVariable("LEGACY_RP_ID", rp_id));
+    }
+    let rp_origin = match config::var("LEGACY_RP_ORIGIN") {
+        Ok(origin) => Url::parse(&origin).map_err(|e| {
+            error!(?e, "parsing legacy relying party origin");
+            Error::BadEnvironmentVariable("LEGACY_RP_ORIGIN", origin)
+        })?,
+        Err(_) => current_origin.clone(),
+    };
+    let mut allowed_origins = load_allowed_origins()?;
+    allowed_origins.extend(load_apk_key_hashes()?.iter().map(|hash| hash.origin()));
+    if rp_origin != current_origin {
+        allowed_origins.push(current_origin);
+    }
+    info!("migrating RP ID from {} to {}", rp_id, current_rp_id);
+    LegacyRelyingParty::new(rp_id, &rp_origin, &allowed_origins).map(Some)
+}
+
+/// Verifies a response with the [`Webauthn`] of a tenant, and falls back to
+/// the legacy relying party of the tenant if the verification fails.
+///
+/// Returns the legacy RP ID together with the result if the response is
+/// verified only with the legacy relying party.
+/// Returns the error of the current relying party if both fail.
+pub fn verify_with_fallback<'a, T, E>(
+    tenant: &'a Tenant,
+    verify: impl Fn(&Webauthn) -> Result<T, E>,
+) -> Result<(T, Option<&'a str>), E> {
+    let e = match verify(tenant.webauthn()) {
+        Ok(res) => return Ok((res, None)),
+        Err(e) => e,
+    };
+    let Some(legacy) = tenant.legacy_relying_party() else {
+        return Err(e);
+    };
+    match verify(legacy.webauthn()) {
+        Ok(res) => {
+            info!("verified with legacy RP ID: {}", legacy.rp_id());
+            Ok((res, Some(legacy.rp_id())))
+        }
+        Err(_) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn legacy_relying_party() -> LegacyRelyingParty {
+        LegacyRelyingParty::new(
+            "old.localhost",
+            &Url::parse("http://old.localhost:5173").unwrap(),
+            &[Url::parse("http://localhost:5173").unwrap()],
+        ).unwrap()
+    }
+
+    fn default_tenant() -> Tenant {
+        Tenant::default_tenant(build_webauthn(
+            "localhost",
+            &Url::parse("http://localhost:5173").unwrap(),
+            "Test",
+            &[],
+        ).unwrap())
+    }
+
+    #[test]
+    fn verify_with_fallback_should_prefer_current_relying_party() {
+        let tenant = default_tenant().with_legacy_relying_party(Some(legacy_relying_party()));
+        let res: Result<_, ()> = verify_with_fallback(&tenant, |_| Ok(1));
+        assert_eq!(res, Ok((1, None)));
+    }
+
+    #[test]
+    fn verify_with_fallback_should_fall_back_to_legacy_relying_party() {
+        let tenant = default_tenant().with_legacy_relying_party(Some(legacy_relying_party()));
+        let current: *const Webauthn = tenant.webauthn();
+        let res = verify_with_fallback(&tenant, |webauthn| {
+            if std::ptr::eq(webauthn, current) { Err("current") } else { Ok(1) }
+        });
+        assert_eq!(res, Ok((1, Some("old.localhost"))));
+        let res: Result<(i32, _), _> = verify_with_fallback(&tenant, |webauthn| {
+            if std::ptr::eq(webauthn, current) { Err("current") } else { Err("legacy") }
+        });
+        assert_eq!(res, Err("current"));
+    }
+
+    #[test]
+    fn verify_with_fallback_should_fail_without_legacy_relying_party() {
+        let res: Result<(i32, _), _> = verify_with_fallback(&default_tenant(), |_| Err(()));
+        assert_eq!(res, Err(()));
+    }
+}
@@ -0,0 +1,165 @@
+//! Secrets in AWS Secrets Manager.
+//!
+//! Sensitive configuration values like signing keys are stored in Secrets
+//! Manager rather than environment variables.
+//! The environment variable (or configuration parameter) only holds the ID of
+//! the secret, and the secret value is cached in memory for a while.
+
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+use crate::config;
+use crate::error::Error;
+
+/// Default time to live of cached secrets.
+pub const DEFAULT_CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// Loads the time to live of cached secrets.
+///
+/// You can specify to `SECRET_CACHE_TTL` environment variable the time to
+/// live in seconds.
+///
+/// Defaults to [`DEFAULT_CACHE_TTL`].
+pub fn load_secret_cache_ttl() -> Result<Duration, Error> {
+    match config::var("SECRET_CACHE_TTL") {
+        Ok(ttl) => ttl.parse()
+            .map(Duration::from_secs)
+            .or(Err(Error::BadEnvironmentVariable("SECRET_CACHE_TTL", ttl))),
+        Err(env::VarError::NotPresent) => Ok(DEFAULT_CACHE_TTL),
+        Err(env::VarError::NotUnicode(ttl)) => Err(
+            Error::BadEnvironmentVariable(
+                "SECRET_CACHE_TTL",
+                ttl.to_string_lossy().into(),
+            ),
+        ),
+    }
+}
+
+/// Cache of secrets.
+///
+/// A secret is fetched again once its time to live has passed.
+/// If the refresh fails, the stale value keeps being served so that a
+/// transient failure of Secrets Manager does not break requests.
+pub struct SecretCache {
+    client: aws_sdk_secretsmanager::Client,
+    ttl: Duration,
+    entries: Mutex<HashMap<String, CachedSecret>>,
+}
+
+#[derive(Clone, Debug)]
+struct CachedSecret {
+    value: String,
+    fetched_at: Instant,
+}
+
+impl CachedSecret {
+    fn is_fresh(&self, now: Instant, ttl: Duration) -> bool {
+        now.saturating_duration_since(self.fetched_at) < ttl
+    }
+}
+
+impl SecretCache {
+    /// Creates an empty cache.
+    pub fn new(client: aws_sdk_secretsmanager::Client, ttl: Duration) -> Self {
+        Self {
+            client,
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the value of a given secret.
+    pub async fn get(&self, secret_id: &str) -> Result<String, Error> {
+        let cached = self.entries.lock().unwrap().get(secret_id).cloned();
+        if let Some(cached) = cached.as_ref() {
+            if cached.is_fresh(Instant::now(), self.ttl) {
+                return Ok(cached.value.clone());
+            }
+        }
+        match self.fetch(secret_id).await {
+            Ok(value) => {
+                self.entries.lock().unwrap().insert(
+                    secret_id.into(),
+                    CachedSecret {
+                        value: value.clone(),
+                        fetched_at: Instant::now(),
+                    },
+                );
+                Ok(value)
+            }
+            Err(e) => match cached {
+                Some(cached) => {
+                    error!("serving stale secret {}: {}", secret_id, e);
+                    Ok(cached.value)
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    /// Returns the value of the secret whose ID is configured as a given
+    /// variable.
+    ///
+    /// Returns `None` if the variable is not configured.
+    pub async fn get_configured(
+        &self,
+        id_variable: &'static str,
+    ) -> Result<Option<String>, Error> {
+        match config::var(id_variable) {
+            Ok(secret_id) => self.get(&secret_id).await.map(Some),
+            Err(env::VarError::NotPresent) => Ok(None),
+            Err(env::VarError::NotUnicode(secret_id)) => Err(
+                Error::BadEnvironmentVariable(
+                    id_variable,
+                    secret_id.to_string_lossy().into(),
+                ),
+            ),
+        }
+    }
+
+    async fn fetch(&self, secret_id: &str) -> Result<String, Error> {
+        info!("fetching secret: {}", secret_id);
+        self.client
+            .get_secret_value()
+            .secret_id(secret_id)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "getting secret value");
+                Error::Secret(secret_id.into())
+            })?
+            .secret_string
+            .ok_or_else(|| Error::Secret(secret_id.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cached_secret_should_expire_after_ttl() {
+        let fetched_at = Instant::now();
+        let cached = CachedSecret {
+            value: "secret".into(),
+            fetched_at,
+        };
+        let ttl = Duration::from_secs(60);
+        assert!(cached.is_fresh(fetched_at, ttl));
+        assert!(cached.is_fresh(fetched_at + Duration::from_secs(59), ttl));
+        assert!(!cached.is_fresh(fetched_at + Duration::from_secs(60), ttl));
+    }
+
+    #[test]
+    fn cached_secret_should_never_be_fresh_with_zero_ttl() {
+        let fetched_at = Instant::now();
+        let cached = CachedSecret {
+            value: "secret".into(),
+            fetched_at,
+        };
+        assert!(!cached.is_fresh(fetched_at, Duration::ZERO));
+    }
+}
@@ -0,0 +1,203 @@
+//! Envelope encryption of session attributes.
+//!
+//! Every session is encrypted with its own data key generated by AWS KMS.
+//! The data key encrypted by KMS is stored with the session, and attributes
+//! are encrypted with AES-256-GCM under the plaintext data key.
+
+use aws_sdk_kms::{primitives::Blob, types::DataKeySpec};
+use ring::{
+    aead::{Aad, AES_256_GCM, LessSafeKey, NONCE_LEN, Nonce, UnboundKey},
+    rand::{SecureRandom, SystemRandom},
+};
+use std::env;
+use tracing::error;
+
+use crate::config;
+use crate::error::Error;
+
+// Encryption context bound to every data key.
+const ENCRYPTION_CONTEXT_KEY: &str = "purpose";
+
+/// Purpose of the data keys of sessions.
+pub const SESSION_PURPOSE: &str = "passkey-test-session";
+
+/// Envelope encryption with a KMS key.
+#[derive(Clone, Debug)]
+pub struct SessionEncryption {
+    kms: aws_sdk_kms::Client,
+    key_id: String,
+    purpose: &'static str,
+}
+
+/// Loads the session encryption configuration.
+///
+/// You can specify to `SESSION_KMS_KEY_ARN` environment variable the ARN of
+/// the KMS key that encrypts data keys.
+///
+/// Returns `None` if `SESSION_KMS_KEY_ARN` is not set, which means sessions
+/// are stored in plaintext.
+pub fn load_session_encryption(
+    kms: aws_sdk_kms::Client,
+) -> Result<Option<SessionEncryption>, Error> {
+    match config::var("SESSION_KMS_KEY_ARN") {
+        Ok(key_id) if !key_id.is_empty() => Ok(Some(
+            SessionEncryption::new(kms, key_id, SESSION_PURPOSE),
+        )),
+        Ok(key_id) => Err(Error::BadEnvironmentVariable("SESSION_KMS_KEY_ARN", key_id)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(key_id)) => Err(
+            Error::BadEnvironmentVariable(
+                "SESSION_KMS_KEY_ARN",
+                key_id.to_string_lossy().into(),
+            ),
+        ),
+    }
+}
+
+impl SessionEncryption {
+    /// Creates envelope encryption with a given KMS key.
+    ///
+    /// `purpose` is bound to every data key as the encryption context, so a
+    /// data key generated for one purpose cannot be decrypted for another.
+    pub fn new(kms: aws_sdk_kms::Client, key_id: String, purpose: &'static str) -> Self {
+        Self { kms, key_id, purpose }
+    }
+
+    /// Generates a new data key.
+    pub async fn generate_data_key(&self) -> Result<DataKey, Error> {
+        let res = self.kms
+            .generate_data_key()
+            .key_id(self.key_id.clone())
+            .key_spec(DataKeySpec::Aes256)
+            .encryption_context(ENCRYPTION_CONTEXT_KEY, self.purpose)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "generating data key");
+                Error::Encryption("failed to generate data key")
+            })?;
+        let plaintext = res.plaintext
+            .ok_or(Error::Encryption("missing plaintext data key"))?;
+        let encrypted = res.ciphertext_blob
+            .ok_or(Error::Encryption("missing encrypted data key"))?;
+        DataKey::new(plaintext.into_inner(), encrypted.into_inner())
+    }
+
+    /// Decrypts an encrypted data key.
+    pub async fn decrypt_data_key(&self, encrypted: &[u8]) -> Result<DataKey, Error> {
+        let res = self.kms
+            .decrypt()
+            .key_id(self.key_id.clone())
+            .ciphertext_blob(Blob::new(encrypted))
+            .encryption_context(ENCRYPTION_CONTEXT_KEY, self.purpose)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "decrypting data key");
+                Error::Encryption("failed to decrypt data key")
+            })?;
+        let plaintext = res.plaintext
+            .ok_or(Error::Encryption("missing plaintext data key"))?;
+        DataKey::new(plaintext.into_inner(), encrypted.to_vec())
+    }
+}
+
+/// Data key.
+pub struct DataKey {
+    key: LessSafeKey,
+    encrypted: Vec<u8>,
+}
+
+impl DataKey {
+    /// Creates a data key from a plaintext key and its encrypted form.
+    pub fn new(plaintext: Vec<u8>, encrypted: Vec<u8>) -> Result<Self, Error> {
+        let key = UnboundKey::new(&AES_256_GCM, &plaintext)
+            .or(Err(Error::Encryption("invalid data key")))?;
+        Ok(Self {
+            key: LessSafeKey::new(key),
+            encrypted,
+        })
+    }
+
+    /// Data key encrypted by KMS.
+    pub fn encrypted(&self) -> &[u8] {
+        &self.encrypted
+    }
+
+    /// Encrypts a given plaintext.
+    ///
+    /// `aad` must be supplied again to [`DataKey::open`]; e.g., the partition
+    /// key and attribute name, so that ciphertexts cannot be swapped.
+    ///
+    /// Returns the nonce followed by the ciphertext and tag.
+    pub fn seal(&self, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new().fill(&mut nonce)
+            .or(Err(Error::Encryption("failed to generate nonce")))?;
+        let mut in_out = plaintext.to_vec();
+        self.key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(aad),
+            &mut in_out,
+        ).or(Err(Error::Encryption("failed to encrypt")))?;
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&in_out);
+        Ok(sealed)
+    }
+
+    /// Decrypts what [`DataKey::seal`] produced.
+    pub fn open(&self, aad: &[u8], sealed: &[u8]) -> Result<Vec<u8>, Error> {
+        if sealed.len() < NONCE_LEN {
+            return Err(Error::Encryption("truncated ciphertext"));
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce)
+            .or(Err(Error::Encryption("invalid nonce")))?;
+        let mut in_out = ciphertext.to_vec();
+        let plaintext = self.key.open_in_place(nonce, Aad::from(aad), &mut in_out)
+            .or(Err(Error::Encryption("failed to decrypt")))?;
+        Ok(plaintext.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data_key() -> DataKey {
+        DataKey::new(vec![7u8; 32], b"encrypted".to_vec()).unwrap()
+    }
+
+    #[test]
+    fn data_key_should_open_sealed_plaintext() {
+        let key = data_key();
+        let sealed = key.seal(b"registration#abc#state", b"secret").unwrap();
+        assert_ne!(&sealed[NONCE_LEN..], b"secret");
+        assert_eq!(
+            key.open(b"registration#abc#state", &sealed).unwrap(),
+            b"secret",
+        );
+    }
+
+    #[test]
+    fn data_key_open_should_fail_for_different_aad() {
+        let key = data_key();
+        let sealed = key.seal(b"registration#abc#state", b"secret").unwrap();
+        assert!(key.open(b"registration#xyz#state", &sealed).is_err());
+    }
+
+    #[test]
+    fn data_key_open_should_fail_for_tampered_ciphertext() {
+        let key = data_key();
+        let mut sealed = key.seal(b"aad", b"secret").unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 0x01;
+        assert!(key.open(b"aad", &sealed).is_err());
+        assert!(key.open(b"aad", &sealed[..4]).is_err());
+    }
+
+    #[test]
+    fn data_key_new_should_reject_wrong_key_length() {
+        assert!(DataKey::new(vec![0u8; 16], Vec::new()).is_err());
+    }
+}
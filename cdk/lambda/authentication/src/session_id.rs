@@ -0,0 +1,133 @@
+//! Session identifiers.
+//!
+//! A session ID is 256 random bits, "base64url"-encoded. If a key is
+//! configured, the random bits are followed by the HMAC-SHA256 tag of them,
+//! so that a forged or truncated session ID is rejected without looking up
+//! the session table.
+//!
+//! Sessions started before the key was configured cannot be finished once the
+//! key is configured; they end as if expired.
+
+use base64::{
+    Engine as _,
+    engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
+};
+use ring::{
+    hmac,
+    rand::{SecureRandom, SystemRandom},
+};
+use std::env;
+
+use crate::config;
+use crate::error::Error;
+use crate::secrets::{SecretCache, load_secret_cache_ttl};
+
+// Length of the random part in bytes.
+const RANDOM_LEN: usize = 32;
+
+// Length of the HMAC-SHA256 tag in bytes.
+const TAG_LEN: usize = 32;
+
+/// Issuer of session IDs.
+pub struct SessionIds {
+    key: Option<(String, SecretCache)>,
+}
+
+/// Loads the issuer of session IDs.
+///
+/// You can specify to `SESSION_ID_SECRET_ID` environment variable the ID of
+/// the secret in Secrets Manager that signs session IDs.
+///
+/// Session IDs are not signed if `SESSION_ID_SECRET_ID` is not set.
+pub fn load_session_ids(
+    secrets: aws_sdk_secretsmanager::Client,
+) -> Result<SessionIds, Error> {
+    let key = match config::var("SESSION_ID_SECRET_ID") {
+        Ok(secret_id) if !secret_id.is_empty() => Some((
+            secret_id,
+            SecretCache::new(secrets, load_secret_cache_ttl()?),
+        )),
+        Ok(secret_id) => return Err(
+            Error::BadEnvironmentVariable("SESSION_ID_SECRET_ID", secret_id),
+        ),
+        Err(env::VarError::NotPresent) => None,
+        Err(env::VarError::NotUnicode(secret_id)) => return Err(
+            Error::BadEnvironmentVariable(
+                "SESSION_ID_SECRET_ID",
+                secret_id.to_string_lossy().into(),
+            ),
+        ),
+    };
+    Ok(SessionIds { key })
+}
+
+impl SessionIds {
+    /// Generates a new session ID.
+    pub async fn generate(&self) -> Result<String, Error> {
+        let mut random = [0u8; RANDOM_LEN];
+        SystemRandom::new().fill(&mut random)
+            .or(Err(Error::SessionId("failed to generate session ID")))?;
+        match self.key.as_ref() {
+            Some((secret_id, secrets)) => {
+                let secret = secrets.get(secret_id).await?;
+                Ok(signed_session_id(secret.as_bytes(), &random))
+            }
+            None => Ok(base64url.encode(random)),
+        }
+    }
+
+    /// Returns whether a given session ID may have been generated by
+    /// [`SessionIds::generate`].
+    ///
+    /// Always `true` if session IDs are not signed.
+    pub async fn verify(&self, session_id: &str) -> Result<bool, Error> {
+        match self.key.as_ref() {
+            Some((secret_id, secrets)) => {
+                let secret = secrets.get(secret_id).await?;
+                Ok(verify_session_id(secret.as_bytes(), session_id))
+            }
+            None => Ok(true),
+        }
+    }
+}
+
+fn signed_session_id(secret: &[u8], random: &[u8]) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+    let tag = hmac::sign(&key, random);
+    base64url.encode([random, tag.as_ref()].concat())
+}
+
+fn verify_session_id(secret: &[u8], session_id: &str) -> bool {
+    let Ok(bytes) = base64url.decode(session_id) else {
+        return false;
+    };
+    if bytes.len() != RANDOM_LEN + TAG_LEN {
+        return false;
+    }
+    let (random, tag) = bytes.split_at(RANDOM_LEN);
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+    hmac::verify(&key, random, tag).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_session_id_should_accept_signed_session_id() {
+        let session_id = signed_session_id(b"secret", &[1u8; RANDOM_LEN]);
+        assert!(verify_session_id(b"secret", &session_id));
+        assert!(!verify_session_id(b"other secret", &session_id));
+    }
+
+    #[test]
+    fn verify_session_id_should_reject_forged_or_truncated_session_id() {
+        let session_id = signed_session_id(b"secret", &[1u8; RANDOM_LEN]);
+        assert!(!verify_session_id(b"secret", &session_id[..session_id.len() - 2]));
+        let mut forged = base64url.decode(&session_id).unwrap();
+        forged[0] ^= 1;
+        assert!(!verify_session_id(b"secret", &base64url.encode(forged)));
+        assert!(!verify_session_id(b"secret", "not base64url!"));
+        assert!(!verify_session_id(b"secret", &base64url.encode([1u8; RANDOM_LEN])));
+    }
+}
@@ -0,0 +1,457 @@
+//! Registration and discoverable authentication sessions, recovery links,
+//! and results of finished registrations.
+//!
+//! A session is put in the session table when a ceremony starts, and taken
+//! out of it when the ceremony finishes, so that a session is used only once.
+//! A session is deleted even if it has expired, because the TTL of DynamoDB
+//! may not have deleted it yet. A recovery link is taken out in the same way
+//! when it is used. The result of a finished registration is kept until it
+//! expires to answer retries. The functions that access the session table
+//! take a [`SessionStore`] like [`crate::step_up`] does.
+//!
+//! The user information and state of a registration session may be sealed
+//! under a data key; see [`crate::session_crypto`]. The ciphertexts are bound
+//! to the partition key and attribute name, so that they cannot be swapped
+//! between sessions or attributes.
+
+use crate::error::Error;
+use crate::items::{
+    DiscoverableSessionItem,
+    Item,
+    RecoveryLinkItem,
+    RegistrationContents,
+    RegistrationResultItem,
+    RegistrationSessionItem,
+    RegistrationUserInfo,
+    SessionKey,
+};
+use crate::session_crypto::DataKey;
+use crate::store::SessionStore;
+use crate::tenant::Tenant;
+
+/// Session taken out of the session table.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TakenSession<T> {
+    /// The session has not expired.
+    Valid(T),
+    /// The session has expired.
+    Expired,
+}
+
+impl<T> TakenSession<T> {
+    /// Returns the session unless it has expired.
+    pub fn valid(self) -> Option<T> {
+        match self {
+            TakenSession::Valid(session) => Some(session),
+            TakenSession::Expired => None,
+        }
+    }
+}
+
+// takes an item out of the session table and checks its TTL.
+async fn take_session<T>(
+    store: &impl SessionStore,
+    key: SessionKey<'_>,
+    now: i64,
+    parse: impl FnOnce(&Item) -> Result<T, Error>,
+    ttl: impl FnOnce(&T) -> i64,
+) -> Result<Option<TakenSession<T>>, Error> {
+    let Some(item) = store.take(key).await? else {
+        return Ok(None);
+    };
+    let session = parse(&item)?;
+    if ttl(&session) < now {
+        Ok(Some(TakenSession::Expired))
+    } else {
+        Ok(Some(TakenSession::Valid(session)))
+    }
+}
+
+/// Puts a new registration session with a given key.
+///
+/// `key` must be scoped to the tenant. Returns `false` if a session with the
+/// same key exists; the caller should retry with a new session ID.
+pub async fn put_registration_session(
+    store: &impl SessionStore,
+    key: SessionKey<'_>,
+    session: RegistrationSessionItem,
+) -> Result<bool, Error> {
+    store.put_new(session.into_item(key)).await
+}
+
+/// Takes a registration session with a given key out of the session table.
+///
+/// `key` must be scoped to the tenant. Returns `None` if the session does not
+/// exist; e.g., deleted by the TTL or already finished. The session has
+/// expired if its TTL is before `now` in seconds since the epoch.
+pub async fn take_registration_session(
+    store: &impl SessionStore,
+    key: SessionKey<'_>,
+    now: i64,
+) -> Result<Option<TakenSession<RegistrationSessionItem>>, Error> {
+    take_session(store, key, now, RegistrationSessionItem::from_item, |s| s.ttl).await
+}
+
+/// Seals the user information and state of a registration session.
+///
+/// `pk` is the partition key of the session item.
+pub fn seal_registration_contents(
+    data_key: &DataKey,
+    pk: &str,
+    user_info: &RegistrationUserInfo,
+    state: &str,
+) -> Result<RegistrationContents, Error> {
+    let user_info = serde_json::to_vec(user_info)
+        .or(Err(Error::Inconvertible("registration user information")))?;
+    Ok(RegistrationContents::Sealed {
+        data_key: data_key.encrypted().to_vec(),
+        user_info: data_key.seal(sealed_attribute_aad(pk, "userInfo").as_bytes(), &user_info)?,
+        state: data_key.seal(sealed_attribute_aad(pk, "state").as_bytes(), state.as_bytes())?,
+    })
+}
+
+/// Opens the sealed user information and state of a registration session.
+///
+/// `data_key` must be the decrypted data key of [`RegistrationContents::Sealed`],
+/// and `pk` the partition key of the session item.
+pub fn open_registration_contents(
+    data_key: &DataKey,
+    pk: &str,
+    user_info: &[u8],
+    state: &[u8],
+) -> Result<(RegistrationUserInfo, String), Error> {
+    let user_info = data_key.open(sealed_attribute_aad(pk, "userInfo").as_bytes(), user_info)?;
+    let state = data_key.open(sealed_attribute_aad(pk, "state").as_bytes(), state)?;
+    Ok((
+        serde_json::from_slice(&user_info).or(Err(Error::BadItemAttribute("userInfo")))?,
+        String::from_utf8(state).or(Err(Error::BadItemAttribute("state")))?,
+    ))
+}
+
+// additional authenticated data for a sealed attribute.
+fn sealed_attribute_aad(pk: &str, name: &str) -> String {
+    format!("{}#{}", pk, name)
+}
+
+/// Puts a new discoverable authentication session of a given challenge.
+///
+/// `challenge` is "base64url"-encoded. Returns `false` if a session of the same
+/// challenge exists; the caller should start over with a new challenge.
+pub async fn put_discoverable_session(
+    store: &impl SessionStore,
+    tenant: &Tenant,
+    challenge: &str,
+    session: DiscoverableSessionItem,
+) -> Result<bool, Error> {
+    let item = session.into_item(tenant.scope(&SessionKey::Discoverable(challenge)));
+    store.put_new(item).await
+}
+
+/// Takes a discoverable authentication session of a given challenge out of
+/// the session table.
+///
+/// `challenge` is "base64url"-encoded. Returns `None` if the session does not
+/// exist. The session has expired if its TTL is before `now` in seconds since
+/// the epoch.
+pub async fn take_discoverable_session(
+    store: &impl SessionStore,
+    tenant: &Tenant,
+    challenge: &str,
+    now: i64,
+) -> Result<Option<TakenSession<DiscoverableSessionItem>>, Error> {
+    take_session(
+        store,
+        tenant.scope(&SessionKey::Discoverable(challenge)),
+        now,
+        DiscoverableSessionItem::from_item,
+        |s| s.ttl,
+    ).await
+}
+
+/// Puts a new recovery link of a given token hash.
+///
+/// Returns `false` if a link of the same token hash exists; the caller should
+/// generate a new token.
+pub async fn put_recovery_link(
+    store: &impl SessionStore,
+    tenant: &Tenant,
+    token_hash: &str,
+    link: RecoveryLinkItem,
+) -> Result<bool, Error> {
+    let item = link.into_item(tenant.scope(&SessionKey::RecoveryLink(token_hash)));
+    store.put_new(item).await
+}
+
+/// Takes a recovery link of a given token hash out of the session table, so
+/// that the link is used only once.
+///
+/// Returns `None` if the link does not exist; e.g., already used. The link
+/// has expired if its TTL is before `now` in seconds since the epoch.
+pub async fn take_recovery_link(
+    store: &impl SessionStore,
+    tenant: &Tenant,
+    token_hash: &str,
+    now: i64,
+) -> Result<Option<TakenSession<RecoveryLinkItem>>, Error> {
+    take_session(
+        store,
+        tenant.scope(&SessionKey::RecoveryLink(token_hash)),
+        now,
+        RecoveryLinkItem::from_item,
+        |l| l.ttl,
+    ).await
+}
+
+/// Puts the result of a finished registration with a given key.
+///
+/// `key` must be scoped to the tenant. Returns `false` if a result with the
+/// same key exists, which is kept so that the key always answers the same
+/// result.
+pub async fn put_registration_result(
+    store: &impl SessionStore,
+    key: SessionKey<'_>,
+    result: RegistrationResultItem,
+) -> Result<bool, Error> {
+    store.put_new(result.into_item(key)).await
+}
+
+/// Obtains the result of a finished registration with a given key.
+///
+/// `key` must be scoped to the tenant. Unlike a session, the result is not
+/// taken out, because a registration may be retried more than once. Returns
+/// `None` if the result does not exist or its TTL is before `now` in seconds
+/// since the epoch.
+pub async fn get_registration_result(
+    store: &impl SessionStore,
+    key: SessionKey<'_>,
+    now: i64,
+) -> Result<Option<RegistrationResultItem>, Error> {
+    let Some(item) = store.get(key).await? else {
+        return Ok(None);
+    };
+    let result = RegistrationResultItem::from_item(&item)?;
+    Ok((result.ttl >= now).then_some(result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use aws_sdk_dynamodb::types::AttributeValue;
+    use std::collections::HashMap;
+    use webauthn_rs::prelude::Url;
+
+    use crate::client_binding::ClientBinding;
+    use crate::parameters::build_webauthn;
+    use crate::store::MemorySessionStore;
+
+    fn tenant() -> Tenant {
+        Tenant::default_tenant(build_webauthn(
+            "localhost",
+            &Url::parse("http://localhost:5173").unwrap(),
+            "Test",
+            &[],
+        ).unwrap())
+    }
+
+    fn user_info() -> RegistrationUserInfo {
+        RegistrationUserInfo {
+            username: "alice".into(),
+            display_name: "Alice".into(),
+        }
+    }
+
+    fn registration_session(ttl: i64, contents: RegistrationContents) -> RegistrationSessionItem {
+        RegistrationSessionItem {
+            ttl,
+            user_id: "AAAA".into(),
+            authenticator_attachment: None,
+            client_binding: ClientBinding::default(),
+            contents,
+        }
+    }
+
+    fn discoverable_session(ttl: i64) -> DiscoverableSessionItem {
+        DiscoverableSessionItem {
+            ttl,
+            state: "{}".into(),
+            client_binding: ClientBinding::default(),
+        }
+    }
+
+    fn data_key() -> DataKey {
+        DataKey::new(vec![7u8; 32], b"encrypted".to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn take_registration_session_should_use_session_once() {
+        let store = MemorySessionStore::default();
+        let session = registration_session(100, RegistrationContents::Plain {
+            user_info: user_info(),
+            state: "{}".into(),
+        });
+        let key = SessionKey::Registration("abc");
+        assert!(put_registration_session(&store, key, session.clone()).await.unwrap());
+        assert!(!put_registration_session(&store, key, session.clone()).await.unwrap());
+        assert_eq!(
+            take_registration_session(&store, key, 100).await.unwrap(),
+            Some(TakenSession::Valid(session)),
+        );
+        assert_eq!(take_registration_session(&store, key, 100).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn take_registration_session_should_consume_expired_session() {
+        let store = MemorySessionStore::default();
+        let session = registration_session(99, RegistrationContents::Plain {
+            user_info: user_info(),
+            state: "{}".into(),
+        });
+        let key = SessionKey::Registration("abc");
+        put_registration_session(&store, key, session).await.unwrap();
+        assert_eq!(
+            take_registration_session(&store, key, 100).await.unwrap(),
+            Some(TakenSession::Expired),
+        );
+        assert_eq!(store.item_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn take_registration_session_should_reject_malformed_item() {
+        let store = MemorySessionStore::default();
+        let key = SessionKey::Registration("abc");
+        store.put_new(HashMap::from([
+            ("pk".to_string(), key.attribute()),
+            ("ttl".to_string(), AttributeValue::S("soon".into())),
+        ])).await.unwrap();
+        assert!(matches!(
+            take_registration_session(&store, key, 100).await,
+            Err(Error::BadItemAttribute("ttl")),
+        ));
+    }
+
+    #[tokio::test]
+    async fn sealed_registration_contents_should_round_trip_through_store() {
+        let store = MemorySessionStore::default();
+        let key = SessionKey::Registration("abc");
+        let pk = key.pk();
+        let contents = seal_registration_contents(&data_key(), &pk, &user_info(), "{}").unwrap();
+        put_registration_session(&store, key, registration_session(100, contents))
+            .await
+            .unwrap();
+        let session = take_registration_session(&store, key, 100)
+            .await
+            .unwrap()
+            .and_then(TakenSession::valid)
+            .unwrap();
+        let RegistrationContents::Sealed { data_key: encrypted, user_info: sealed, state } =
+            session.contents else
+        {
+            panic!("contents must be sealed");
+        };
+        assert_eq!(encrypted, b"encrypted");
+        let (opened, state) = open_registration_contents(&data_key(), &pk, &sealed, &state)
+            .unwrap();
+        assert_eq!(opened, user_info());
+        assert_eq!(state, "{}");
+    }
+
+    #[test]
+    fn open_registration_contents_should_reject_swapped_attributes() {
+        let pk = SessionKey::Registration("abc").pk();
+        let RegistrationContents::Sealed { user_info: sealed, state, .. } =
+            seal_registration_contents(&data_key(), &pk, &user_info(), "{}").unwrap() else
+        {
+            panic!("contents must be sealed");
+        };
+        assert!(open_registration_contents(&data_key(), &pk, &state, &sealed).is_err());
+        let other_pk = SessionKey::Registration("other").pk();
+        assert!(open_registration_contents(&data_key(), &other_pk, &sealed, &state).is_err());
+    }
+
+    #[tokio::test]
+    async fn take_discoverable_session_should_tell_expired_from_missing() {
+        let store = MemorySessionStore::default();
+        let tenant = tenant();
+        assert!(put_discoverable_session(&store, &tenant, "valid", discoverable_session(100))
+            .await
+            .unwrap());
+        assert!(!put_discoverable_session(&store, &tenant, "valid", discoverable_session(100))
+            .await
+            .unwrap());
+        put_discoverable_session(&store, &tenant, "expired", discoverable_session(99))
+            .await
+            .unwrap();
+        assert_eq!(
+            take_discoverable_session(&store, &tenant, "valid", 100).await.unwrap(),
+            Some(TakenSession::Valid(discoverable_session(100))),
+        );
+        assert_eq!(
+            take_discoverable_session(&store, &tenant, "expired", 100).await.unwrap(),
+            Some(TakenSession::Expired),
+        );
+        assert_eq!(
+            take_discoverable_session(&store, &tenant, "valid", 100).await.unwrap(),
+            None,
+        );
+        assert_eq!(store.item_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn take_recovery_link_should_use_link_once() {
+        let store = MemorySessionStore::default();
+        let tenant = tenant();
+        let link = |ttl| RecoveryLinkItem {
+            ttl,
+            user_handle: "AAAA".into(),
+        };
+        assert!(put_recovery_link(&store, &tenant, "valid", link(100)).await.unwrap());
+        assert!(!put_recovery_link(&store, &tenant, "valid", link(100)).await.unwrap());
+        put_recovery_link(&store, &tenant, "expired", link(99)).await.unwrap();
+        assert_eq!(
+            take_recovery_link(&store, &tenant, "valid", 100).await.unwrap(),
+            Some(TakenSession::Valid(link(100))),
+        );
+        assert_eq!(take_recovery_link(&store, &tenant, "valid", 100).await.unwrap(), None);
+        assert_eq!(
+            take_recovery_link(&store, &tenant, "expired", 100).await.unwrap(),
+            Some(TakenSession::Expired),
+        );
+        assert_eq!(store.item_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn get_registration_result_should_answer_retries_until_expired() {
+        let store = MemorySessionStore::default();
+        let key = SessionKey::RegistrationResult("abc");
+        let result = |session_id: &str| RegistrationResultItem {
+            ttl: 100,
+            session_id: session_id.into(),
+            credential_id: "BBBB".into(),
+        };
+        assert!(put_registration_result(&store, key, result("first")).await.unwrap());
+        assert!(!put_registration_result(&store, key, result("second")).await.unwrap());
+        for _ in 0..2 {
+            assert_eq!(
+                get_registration_result(&store, key, 100).await.unwrap(),
+                Some(result("first")),
+            );
+        }
+        assert_eq!(get_registration_result(&store, key, 101).await.unwrap(), None);
+        assert_eq!(
+            get_registration_result(&store, SessionKey::RegistrationResult("other"), 100)
+                .await
+                .unwrap(),
+            None,
+        );
+    }
+
+    #[tokio::test]
+    async fn take_discoverable_session_should_propagate_storage_error() {
+        let store = MemorySessionStore::failing();
+        assert!(matches!(
+            take_discoverable_session(&store, &tenant(), "abc", 100).await,
+            Err(Error::Storage(_)),
+        ));
+    }
+}
@@ -0,0 +1,308 @@
+//! Step-up re-authentication.
+//!
+//! An authenticated user is asked to authenticate again with one of the
+//! registered passkeys, with user verification required, before a sensitive
+//! operation like deleting a credential. A successful step-up issues a
+//! short-lived token that the sensitive operations demand in the
+//! [`STEP_UP_TOKEN_HEADER`] header.
+//! Both the step-up session and the token are stored in the session table;
+//! only the hash of a token is stored. The functions that access them take a
+//! [`SessionStore`].
+
+use base64::{
+    Engine as _,
+    engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
+};
+use lambda_http::Request;
+use ring::digest;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use webauthn_rs::prelude::RequestChallengeResponse;
+use webauthn_rs_proto::auth::PublicKeyCredential;
+
+use crate::error::Error;
+use crate::extensions::{LargeBlobOutputs, PrfOutputs};
+use crate::items::{SessionKey, StepUpSessionItem, StepUpTokenItem};
+use crate::store::SessionStore;
+use crate::tenant::Tenant;
+
+/// Header that carries a step-up token.
+pub const STEP_UP_TOKEN_HEADER: &str = "X-Step-Up-Token";
+
+/// Time to live of a step-up token in seconds.
+pub const STEP_UP_TOKEN_TTL: i64 = 5 * 60;
+
+// Number of random bytes in a step-up token.
+const STEP_UP_TOKEN_SIZE: usize = 32;
+
+/// Beginning of a step-up session.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StartStepUpSession {
+    /// Session ID.
+    pub session_id: String,
+
+    /// Credential request options.
+    ///
+    /// `CredentialRequestOptions` of the Web Authentication API. Only the
+    /// credentials of the authenticated user are allowed.
+    pub credential_request_options: RequestChallengeResponse,
+}
+
+/// End of a step-up session.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FinishStepUpSession {
+    /// Session ID.
+    pub session_id: String,
+
+    /// Public key credential.
+    ///
+    /// `PublicKeyCredential` of the Web Authentication API in the JSON form.
+    pub public_key_credential: PublicKeyCredential,
+}
+
+/// Result of a finished step-up.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StepUpResult {
+    /// Step-up token to send in the [`STEP_UP_TOKEN_HEADER`] header.
+    pub step_up_token: String,
+
+    /// Expiration time of the token in seconds since the epoch.
+    pub expires_at: i64,
+
+    /// Outputs of the `largeBlob` extension reported by the client.
+    ///
+    /// Omitted unless the extension is enabled and reported.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub large_blob: Option<LargeBlobOutputs>,
+
+    /// Outputs of the `prf` extension reported by the client.
+    ///
+    /// Omitted unless the extension is enabled and reported.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prf: Option<PrfOutputs>,
+}
+
+/// Generates a "base64url"-encoded step-up token.
+pub fn generate_step_up_token() -> Result<String, Error> {
+    let mut token = [0u8; STEP_UP_TOKEN_SIZE];
+    SystemRandom::new().fill(&mut token)
+        .or(Err(Error::Encryption("failed to generate step-up token")))?;
+    Ok(base64url.encode(token))
+}
+
+/// Hashes a step-up token.
+pub fn hash_step_up_token(token: &str) -> String {
+    base64url.encode(digest::digest(&digest::SHA256, token.as_bytes()))
+}
+
+/// Returns the step-up token in a given request.
+///
+/// Returns `None` if the [`STEP_UP_TOKEN_HEADER`] header is missing or empty.
+pub fn step_up_token(request: &Request) -> Option<&str> {
+    request.headers()
+        .get(STEP_UP_TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| !v.is_empty())
+}
+
+/// Saves a new step-up session.
+pub async fn save_step_up_session(
+    store: &impl SessionStore,
+    tenant: &Tenant,
+    session_id: &str,
+    session: StepUpSessionItem,
+) -> Result<(), Error> {
+    let item = session.into_item(tenant.scope(&SessionKey::StepUp(session_id)));
+    if !store.put_new(item).await? {
+        return Err(Error::Storage("duplicate step-up session"));
+    }
+    Ok(())
+}
+
+/// Takes a step-up session of a given user out of the session table.
+///
+/// A session is used only once; it is deleted even if it has expired or
+/// belongs to another user. Returns `None` if the session does not exist, has
+/// expired at `now` in seconds since the epoch, or belongs to another user.
+pub async fn take_step_up_session(
+    store: &impl SessionStore,
+    tenant: &Tenant,
+    session_id: &str,
+    user_handle: &str,
+    now: i64,
+) -> Result<Option<StepUpSessionItem>, Error> {
+    let item = store
+        .take(tenant.scope(&SessionKey::StepUp(session_id)))
+        .await?
+        .map(|item| StepUpSessionItem::from_item(&item))
+        .transpose()?;
+    Ok(item.filter(|item| item.ttl >= now && item.user_handle == user_handle))
+}
+
+/// Issues a step-up token of a given user.
+///
+/// Returns the token and its expiration time in seconds since the epoch.
+pub async fn issue_step_up_token(
+    store: &impl SessionStore,
+    tenant: &Tenant,
+    user_handle: &str,
+    now: i64,
+) -> Result<(String, i64), Error> {
+    let token = generate_step_up_token()?;
+    let token_hash = hash_step_up_token(&token);
+    let expires_at = now + STEP_UP_TOKEN_TTL;
+    let item = StepUpTokenItem {
+        ttl: expires_at,
+        user_handle: user_handle.into(),
+    }.into_item(tenant.scope(&SessionKey::StepUpToken(&token_hash)));
+    if !store.put_new(item).await? {
+        return Err(Error::Storage("duplicate step-up token"));
+    }
+    Ok((token, expires_at))
+}
+
+/// Returns whether a step-up token is valid for a given user at `now` in
+/// seconds since the epoch.
+pub async fn verify_step_up_token(
+    store: &impl SessionStore,
+    tenant: &Tenant,
+    token: &str,
+    user_handle: &str,
+    now: i64,
+) -> Result<bool, Error> {
+    let token_hash = hash_step_up_token(token);
+    let item = store
+        .get(tenant.scope(&SessionKey::StepUpToken(&token_hash)))
+        .await?
+        .map(|item| StepUpTokenItem::from_item(&item))
+        .transpose()?;
+    // the token may not have been deleted by the TTL yet
+    Ok(item.is_some_and(|item| item.user_handle == user_handle && item.ttl >= now))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use webauthn_rs::prelude::Url;
+
+    use crate::parameters::build_webauthn;
+    use crate::store::MemorySessionStore;
+
+    fn tenant() -> Tenant {
+        Tenant::default_tenant(build_webauthn(
+            "localhost",
+            &Url::parse("http://localhost:5173").unwrap(),
+            "Test",
+            &[],
+        ).unwrap())
+    }
+
+    fn session(user_handle: &str, ttl: i64) -> StepUpSessionItem {
+        StepUpSessionItem {
+            ttl,
+            user_handle: user_handle.into(),
+            state: "{}".into(),
+        }
+    }
+
+    #[test]
+    fn generate_step_up_token_should_generate_distinct_tokens() {
+        let token = generate_step_up_token().unwrap();
+        assert_eq!(base64url.decode(&token).unwrap().len(), STEP_UP_TOKEN_SIZE);
+        assert_ne!(token, generate_step_up_token().unwrap());
+        assert_ne!(hash_step_up_token(&token), token);
+    }
+
+    #[test]
+    fn step_up_token_should_ignore_empty_header() {
+        let request = lambda_http::http::Request::builder()
+            .header(STEP_UP_TOKEN_HEADER, "abc")
+            .body(lambda_http::Body::Empty)
+            .unwrap();
+        assert_eq!(step_up_token(&request), Some("abc"));
+        let request = lambda_http::http::Request::builder()
+            .header(STEP_UP_TOKEN_HEADER, "")
+            .body(lambda_http::Body::Empty)
+            .unwrap();
+        assert_eq!(step_up_token(&request), None);
+    }
+
+    #[tokio::test]
+    async fn take_step_up_session_should_use_session_once() {
+        let store = MemorySessionStore::default();
+        let tenant = tenant();
+        save_step_up_session(&store, &tenant, "abc", session("user", 100))
+            .await
+            .unwrap();
+        assert!(save_step_up_session(&store, &tenant, "abc", session("user", 100))
+            .await
+            .is_err());
+        let item = take_step_up_session(&store, &tenant, "abc", "user", 100)
+            .await
+            .unwrap();
+        assert_eq!(item, Some(session("user", 100)));
+        assert_eq!(
+            take_step_up_session(&store, &tenant, "abc", "user", 100)
+                .await
+                .unwrap(),
+            None,
+        );
+    }
+
+    #[tokio::test]
+    async fn take_step_up_session_should_reject_expired_or_other_session() {
+        let store = MemorySessionStore::default();
+        let tenant = tenant();
+        save_step_up_session(&store, &tenant, "expired", session("user", 99))
+            .await
+            .unwrap();
+        save_step_up_session(&store, &tenant, "other", session("other", 100))
+            .await
+            .unwrap();
+        for session_id in ["expired", "other", "missing"] {
+            assert_eq!(
+                take_step_up_session(&store, &tenant, session_id, "user", 100)
+                    .await
+                    .unwrap(),
+                None,
+            );
+        }
+        // rejected sessions are consumed too
+        assert_eq!(store.item_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn verify_step_up_token_should_accept_only_valid_token() {
+        let store = MemorySessionStore::default();
+        let tenant = tenant();
+        let (token, expires_at) = issue_step_up_token(&store, &tenant, "user", 100)
+            .await
+            .unwrap();
+        assert_eq!(expires_at, 100 + STEP_UP_TOKEN_TTL);
+        assert!(verify_step_up_token(&store, &tenant, &token, "user", expires_at)
+            .await
+            .unwrap());
+        assert!(!verify_step_up_token(&store, &tenant, &token, "user", expires_at + 1)
+            .await
+            .unwrap());
+        assert!(!verify_step_up_token(&store, &tenant, &token, "other", 100)
+            .await
+            .unwrap());
+        assert!(!verify_step_up_token(&store, &tenant, "unknown", "user", 100)
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn verify_step_up_token_should_propagate_storage_error() {
+        let store = MemorySessionStore::failing();
+        assert!(matches!(
+            verify_step_up_token(&store, &tenant(), "abc", "user", 100).await,
+            Err(Error::Storage(_)),
+        ));
+    }
+}
@@ -0,0 +1,306 @@
+//! Access to the session table behind a trait.
+//!
+//! Logic that reads and writes the session table takes a [`SessionStore`]
+//! instead of a DynamoDB client, so that the logic like TTL checks, parsing
+//! items, and mapping errors can be unit-tested with an in-memory fake.
+//! [`DynamoDbSessionStore`] is the implementation on DynamoDB, and
+//! [`crate::sql::SqlSessionStore`] is the one on a relational database if the
+//! `sql` feature is enabled. The handlers hold a [`SessionBackend`], which is
+//! either of them selected at cold start; see [`load_database`].
+//!
+//! Conditional updates that depend on DynamoDB expressions, like rotating
+//! refresh tokens and counting rate limits, still use the client directly,
+//! so they stay on the DynamoDB session table even with a database.
+
+use aws_sdk_dynamodb::types::ReturnValue;
+#[cfg(not(feature = "sql"))]
+use std::env;
+use std::future::Future;
+use tracing::error;
+
+#[cfg(not(feature = "sql"))]
+use crate::config;
+use crate::error::Error;
+use crate::items::{Item, SessionKey};
+#[cfg(feature = "sql")]
+use crate::sql::{SqlSessionStore, connect, load_database_url, run_migrations};
+
+/// Store of items in the session table.
+pub trait SessionStore {
+    /// Obtains an item.
+    fn get(
+        &self,
+        key: SessionKey<'_>,
+    ) -> impl Future<Output = Result<Option<Item>, Error>> + Send;
+
+    /// Puts an item unless an item with the same key exists.
+    ///
+    /// Returns `false` if an item with the same key exists.
+    fn put_new(&self, item: Item) -> impl Future<Output = Result<bool, Error>> + Send;
+
+    /// Deletes an item and returns the deleted item.
+    ///
+    /// Returns `None` if the item does not exist.
+    fn take(
+        &self,
+        key: SessionKey<'_>,
+    ) -> impl Future<Output = Result<Option<Item>, Error>> + Send;
+}
+
+/// Session table on DynamoDB.
+#[derive(Clone, Debug)]
+pub struct DynamoDbSessionStore {
+    dynamodb: aws_sdk_dynamodb::Client,
+    table_name: String,
+}
+
+impl DynamoDbSessionStore {
+    /// Creates a store on a given session table.
+    pub fn new(dynamodb: aws_sdk_dynamodb::Client, table_name: String) -> Self {
+        Self { dynamodb, table_name }
+    }
+}
+
+impl SessionStore for DynamoDbSessionStore {
+    fn get(
+        &self,
+        key: SessionKey<'_>,
+    ) -> impl Future<Output = Result<Option<Item>, Error>> + Send {
+        let request = self.dynamodb
+            .get_item()
+            .table_name(self.table_name.clone())
+            .key("pk", key.attribute());
+        async move {
+            let res = request.send().await.map_err(|e| {
+                error!(?e, "getting session item");
+                Error::Storage("failed to get session item")
+            })?;
+            Ok(res.item)
+        }
+    }
+
+    fn put_new(&self, item: Item) -> impl Future<Output = Result<bool, Error>> + Send {
+        let request = self.dynamodb
+            .put_item()
+            .table_name(self.table_name.clone())
+            .set_item(Some(item))
+            .condition_expression("attribute_not_exists(pk)");
+        async move {
+            match request.send().await {
+                Ok(_) => Ok(true),
+                Err(e) if e.as_service_error()
+                    .is_some_and(|e| e.is_conditional_check_failed_exception()) =>
+                {
+                    Ok(false)
+                }
+                Err(e) => {
+                    error!(?e, "putting session item");
+                    Err(Error::Storage("failed to put session item"))
+                }
+            }
+        }
+    }
+
+    fn take(
+        &self,
+        key: SessionKey<'_>,
+    ) -> impl Future<Output = Result<Option<Item>, Error>> + Send {
+        let request = self.dynamodb
+            .delete_item()
+            .table_name(self.table_name.clone())
+            .key("pk", key.attribute())
+            .return_values(ReturnValue::AllOld);
+        async move {
+            let res = request.send().await.map_err(|e| {
+                error!(?e, "deleting session item");
+                Error::Storage("failed to delete session item")
+            })?;
+            Ok(res.attributes)
+        }
+    }
+}
+
+/// Database that stores users, credentials, and sessions in place of
+/// DynamoDB.
+#[derive(Clone, Debug)]
+pub struct Database {
+    #[cfg(feature = "sql")]
+    pool: sqlx::PgPool,
+}
+
+impl Database {
+    /// Returns the connection pool.
+    #[cfg(feature = "sql")]
+    pub fn pool(&self) -> &sqlx::PgPool {
+        &self.pool
+    }
+}
+
+/// Loads the database that stores users, credentials, and sessions in place
+/// of DynamoDB.
+///
+/// You can specify to `DATABASE_URL` environment variable the URL of a
+/// PostgreSQL database if the `sql` feature is enabled; see [`crate::sql`].
+/// The pending migrations are applied to the database.
+///
+/// Returns `None` if `DATABASE_URL` is not set. Fails if `DATABASE_URL` is
+/// set but the `sql` feature is disabled.
+pub async fn load_database() -> Result<Option<Database>, Error> {
+    #[cfg(feature = "sql")]
+    if let Some(url) = load_database_url()? {
+        let pool = connect(&url).await?;
+        run_migrations(&pool).await?;
+        return Ok(Some(Database { pool }));
+    }
+    #[cfg(not(feature = "sql"))]
+    if !matches!(config::var("DATABASE_URL"), Err(env::VarError::NotPresent)) {
+        // never shows the URL, which may contain a password
+        return Err(Error::BadEnvironmentVariable(
+            "DATABASE_URL",
+            "sql feature disabled".into(),
+        ));
+    }
+    Ok(None)
+}
+
+/// Session store selected at cold start.
+#[derive(Clone, Debug)]
+pub enum SessionBackend {
+    /// Session table on DynamoDB.
+    DynamoDb(DynamoDbSessionStore),
+    /// Sessions table on a relational database.
+    #[cfg(feature = "sql")]
+    Sql(SqlSessionStore),
+}
+
+impl SessionBackend {
+    /// Selects the store on a given database, or `sessions` if no database
+    /// is configured.
+    #[cfg_attr(not(feature = "sql"), allow(unused_variables))]
+    pub fn new(sessions: DynamoDbSessionStore, database: Option<&Database>) -> Self {
+        #[cfg(feature = "sql")]
+        if let Some(database) = database {
+            return Self::Sql(SqlSessionStore::new(database.pool.clone()));
+        }
+        Self::DynamoDb(sessions)
+    }
+}
+
+impl SessionStore for SessionBackend {
+    async fn get(&self, key: SessionKey<'_>) -> Result<Option<Item>, Error> {
+        match self {
+            Self::DynamoDb(store) => store.get(key).await,
+            #[cfg(feature = "sql")]
+            Self::Sql(store) => store.get(key).await,
+        }
+    }
+
+    async fn put_new(&self, item: Item) -> Result<bool, Error> {
+        match self {
+            Self::DynamoDb(store) => store.put_new(item).await,
+            #[cfg(feature = "sql")]
+            Self::Sql(store) => store.put_new(item).await,
+        }
+    }
+
+    async fn take(&self, key: SessionKey<'_>) -> Result<Option<Item>, Error> {
+        match self {
+            Self::DynamoDb(store) => store.take(key).await,
+            #[cfg(feature = "sql")]
+            Self::Sql(store) => store.take(key).await,
+        }
+    }
+}
+
+/// In-memory fake of the session table for unit tests.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct MemorySessionStore {
+    items: std::sync::Mutex<std::collections::HashMap<String, Item>>,
+    failing: bool,
+}
+
+#[cfg(test)]
+impl MemorySessionStore {
+    /// Creates a store whose every operation fails like an unreachable
+    /// table.
+    pub fn failing() -> Self {
+        Self {
+            failing: true,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the number of items.
+    pub fn item_count(&self) -> usize {
+        self.items.lock().unwrap().len()
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        if self.failing {
+            Err(Error::Storage("unreachable session table"))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+impl SessionStore for MemorySessionStore {
+    async fn get(&self, key: SessionKey<'_>) -> Result<Option<Item>, Error> {
+        self.check()?;
+        Ok(self.items.lock().unwrap().get(&key.pk()).cloned())
+    }
+
+    async fn put_new(&self, item: Item) -> Result<bool, Error> {
+        self.check()?;
+        let pk = item.get("pk")
+            .and_then(|pk| pk.as_s().ok())
+            .ok_or(Error::BadItemAttribute("pk"))?
+            .clone();
+        let mut items = self.items.lock().unwrap();
+        if items.contains_key(&pk) {
+            return Ok(false);
+        }
+        items.insert(pk, item);
+        Ok(true)
+    }
+
+    async fn take(&self, key: SessionKey<'_>) -> Result<Option<Item>, Error> {
+        self.check()?;
+        Ok(self.items.lock().unwrap().remove(&key.pk()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use aws_sdk_dynamodb::types::AttributeValue;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn memory_session_store_should_put_new_items_only() {
+        let store = MemorySessionStore::default();
+        let item = |ttl: &str| HashMap::from([
+            ("pk".to_string(), SessionKey::StepUp("abc").attribute()),
+            ("ttl".to_string(), AttributeValue::N(ttl.into())),
+        ]);
+        assert!(store.put_new(item("1")).await.unwrap());
+        assert!(!store.put_new(item("2")).await.unwrap());
+        let stored = store.get(SessionKey::StepUp("abc")).await.unwrap().unwrap();
+        assert_eq!(stored["ttl"], AttributeValue::N("1".into()));
+        assert!(store.take(SessionKey::StepUp("abc")).await.unwrap().is_some());
+        assert!(store.take(SessionKey::StepUp("abc")).await.unwrap().is_none());
+        assert_eq!(store.item_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn memory_session_store_should_fail_if_failing() {
+        let store = MemorySessionStore::failing();
+        assert!(matches!(
+            store.get(SessionKey::StepUp("abc")).await,
+            Err(Error::Storage(_)),
+        ));
+    }
+}
@@ -0,0 +1,357 @@
+//! Tracing.
+//!
+//! Logs are written as JSON lines that include the fields of the enclosing
+//! spans; e.g., the Lambda request ID (`requestId`), API Gateway request ID
+//! (`apiRequestId`), correlation ID (`correlationId`), and session ID
+//! (`session_id`), so that CloudWatch Logs Insights can trace a single flow
+//! end to end. See [`crate::correlation`] for correlation IDs.
+//!
+//! Every span is also logged with its duration when the span closes, so that
+//! the time spent in the cold start, DynamoDB, and verification shows up in
+//! CloudWatch Logs.
+//!
+//! ## Log level
+//!
+//! `LOG_LEVEL` environment variable specifies the log level like "debug", or
+//! directives in the form of `RUST_LOG`; e.g., "info,authentication=debug".
+//! `RUST_LOG` is used if `LOG_LEVEL` is not set, and "info" by default.
+//!
+//! ## Redaction
+//!
+//! Usernames, display names, and user handles are logged through [`redact`],
+//! which replaces them with a truncated hash by default, so that the logs do
+//! not reveal who authenticated while the logs of the same user still
+//! correlate. Session IDs, challenges, and credential IDs are logged through
+//! [`truncate`], which keeps only the first characters by default, so that a
+//! log line can be matched with what the client holds while the logs do not
+//! leak anything that can be replayed. `LOG_REDACTION=off` logs them as they
+//! are; e.g., while debugging in a development environment.
+//!
+//! Types that hold a username or display name, like
+//! [`crate::registration::NewUserInfo`], redact them in their `Debug`
+//! representations too, so that formatting them with `{:?}` is safe.
+//!
+//! Both `LOG_LEVEL` and `LOG_REDACTION` must be environment variables, not
+//! configuration parameters, because tracing is initialized before the
+//! configuration parameters are loaded.
+//!
+//! ## OpenTelemetry
+//!
+//! If the `otel` feature is enabled and `OTEL_EXPORTER_OTLP_ENDPOINT`
+//! environment variable is set, spans are also exported to the OTLP endpoint;
+//! e.g., the collector of the AWS Distro for OpenTelemetry Lambda layer, which
+//! forwards them to AWS X-Ray.
+
+use base64::{
+    Engine as _,
+    engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
+};
+use lambda_http::{Request, RequestExt};
+use ring::digest;
+use std::env;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::{Span, info_span};
+use tracing_subscriber::{
+    filter::EnvFilter,
+    fmt::format::FmtSpan,
+    layer::SubscriberExt as _,
+    util::SubscriberInitExt as _,
+};
+
+use crate::correlation::{api_request_id, correlation_of};
+use crate::error::Error;
+
+/// Default log level.
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+
+// number of characters of a redacted value.
+const REDACTED_LENGTH: usize = 8;
+
+// number of characters kept in a truncated value.
+const TRUNCATED_LENGTH: usize = 8;
+
+// whether identifiers are redacted; configured by `init_tracing`.
+static REDACTION: AtomicBool = AtomicBool::new(true);
+
+/// Telemetry initialized by [`init_tracing`].
+pub struct Telemetry {
+    #[cfg(feature = "otel")]
+    tracer_provider: Option<opentelemetry_sdk::trace::TracerProvider>,
+}
+
+/// Initializes the global tracing subscriber.
+///
+/// `service_name` identifies the Lambda function in exported traces.
+///
+/// Fails if `LOG_LEVEL`, `RUST_LOG`, or `LOG_REDACTION` is malformed.
+#[cfg_attr(not(feature = "otel"), allow(unused_variables))]
+pub fn init_tracing(service_name: &'static str) -> Result<Telemetry, Error> {
+    let filter = load_log_filter()?;
+    REDACTION.store(load_log_redaction()?, Ordering::Relaxed);
+    let fmt_layer = tracing_subscriber::fmt::layer()
+        .json()
+        .flatten_event(true)
+        // includes the fields of all the enclosing spans.
+        .with_current_span(false)
+        .with_span_list(true)
+        // disable printing the name of the module in every log line.
+        .with_target(false)
+        // disabling time is handy because CloudWatch will add the ingestion time.
+        .without_time()
+        // reports the duration of every span.
+        .with_span_events(FmtSpan::CLOSE);
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt_layer);
+
+    #[cfg(feature = "otel")]
+    let tracer_provider = otel::init_tracer_provider(service_name)?;
+    #[cfg(feature = "otel")]
+    let registry = registry.with(tracer_provider.as_ref().map(|provider| {
+        use opentelemetry::trace::TracerProvider as _;
+        tracing_opentelemetry::layer().with_tracer(provider.tracer(service_name))
+    }));
+    registry.init();
+
+    Ok(Telemetry {
+        #[cfg(feature = "otel")]
+        tracer_provider,
+    })
+}
+
+// loads the filter of logs from `LOG_LEVEL` or `RUST_LOG`.
+fn load_log_filter() -> Result<EnvFilter, Error> {
+    let (name, directives) = match env::var("LOG_LEVEL") {
+        Ok(directives) => ("LOG_LEVEL", directives),
+        Err(_) => match env::var("RUST_LOG") {
+            Ok(directives) => ("RUST_LOG", directives),
+            Err(_) => ("LOG_LEVEL", DEFAULT_LOG_LEVEL.into()),
+        },
+    };
+    parse_log_filter(&directives)
+        .ok_or(Error::BadEnvironmentVariable(name, directives))
+}
+
+fn parse_log_filter(directives: &str) -> Option<EnvFilter> {
+    EnvFilter::builder().parse(directives.trim()).ok()
+}
+
+// loads whether identifiers are redacted from `LOG_REDACTION`.
+fn load_log_redaction() -> Result<bool, Error> {
+    match env::var("LOG_REDACTION") {
+        Ok(value) => parse_log_redaction(&value)
+            .ok_or(Error::BadEnvironmentVariable("LOG_REDACTION", value)),
+        Err(env::VarError::NotPresent) => Ok(true),
+        Err(env::VarError::NotUnicode(value)) => Err(
+            Error::BadEnvironmentVariable(
+                "LOG_REDACTION",
+                value.to_string_lossy().into(),
+            ),
+        ),
+    }
+}
+
+fn parse_log_redaction(value: &str) -> Option<bool> {
+    match value {
+        "on" => Some(true),
+        "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// Identifier to be logged; see [`redact`].
+#[derive(Clone, Copy)]
+pub struct Redacted<'a> {
+    value: &'a str,
+    redacted: bool,
+}
+
+/// Wraps an identifier like a username, user handle, or credential ID to be
+/// logged.
+///
+/// Formats as "#" followed by the first characters of the "base64url"-encoded
+/// SHA-256 hash of the identifier unless redaction is turned off; see the
+/// [module documentation](self).
+pub fn redact(value: &str) -> Redacted<'_> {
+    Redacted {
+        value,
+        redacted: REDACTION.load(Ordering::Relaxed),
+    }
+}
+
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.redacted {
+            let hash = base64url.encode(digest::digest(&digest::SHA256, self.value.as_bytes()));
+            write!(f, "#{}", &hash[..REDACTED_LENGTH])
+        } else {
+            f.write_str(self.value)
+        }
+    }
+}
+
+impl fmt::Debug for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Random identifier to be logged; see [`truncate`].
+#[derive(Clone, Copy)]
+pub struct Truncated<'a> {
+    value: &'a str,
+    truncated: bool,
+}
+
+/// Wraps a random identifier like a session ID, challenge, or credential ID
+/// to be logged.
+///
+/// Formats as the first characters of the identifier followed by "..."
+/// unless redaction is turned off; see the [module documentation](self).
+pub fn truncate(value: &str) -> Truncated<'_> {
+    Truncated {
+        value,
+        truncated: REDACTION.load(Ordering::Relaxed),
+    }
+}
+
+impl fmt::Display for Truncated<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let end = self.value.char_indices()
+            .nth(TRUNCATED_LENGTH)
+            .map(|(i, _)| i);
+        match end {
+            Some(end) if self.truncated => write!(f, "{}...", &self.value[..end]),
+            _ => f.write_str(self.value),
+        }
+    }
+}
+
+impl fmt::Debug for Truncated<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Creates a span that encloses the handling of a given HTTP request.
+///
+/// The span has the API Gateway request ID as `apiRequestId`, and the
+/// correlation ID and trace ID as `correlationId` and `traceId`; see
+/// [`crate::correlation`]. The Lambda request ID is recorded by the enclosing
+/// span of the Lambda runtime.
+pub fn request_span(request: &Request) -> Span {
+    let api_request_id = api_request_id(request);
+    let correlation = correlation_of(request);
+    info_span!(
+        "request",
+        apiRequestId = api_request_id.as_deref().unwrap_or("-"),
+        correlationId = correlation.request_id.as_str(),
+        traceId = correlation.trace_id.as_deref().unwrap_or("-"),
+        method = %request.method(),
+        path = %request.raw_http_path(),
+    )
+}
+
+impl Telemetry {
+    /// Exports pending spans.
+    ///
+    /// Must be called at the end of every invocation, because the Lambda
+    /// execution environment may be frozen before spans are exported in the
+    /// background.
+    pub async fn flush(&self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.tracer_provider.clone() {
+            // `force_flush` blocks until the exporter finishes
+            match tokio::task::spawn_blocking(move || provider.force_flush()).await {
+                Ok(results) => {
+                    for e in results.into_iter().filter_map(Result::err) {
+                        tracing::error!(?e, "exporting spans");
+                    }
+                }
+                Err(e) => tracing::error!(?e, "flushing spans"),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "otel")]
+mod otel {
+    use opentelemetry::KeyValue;
+    use opentelemetry_sdk::{Resource, runtime, trace::TracerProvider};
+    use std::env;
+
+    use crate::error::Error;
+
+    // the exporter reads the endpoint from the environment variable by
+    // itself.
+    const OTLP_ENDPOINT_VARIABLE: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+    pub(super) fn init_tracer_provider(
+        service_name: &'static str,
+    ) -> Result<Option<TracerProvider>, Error> {
+        if env::var_os(OTLP_ENDPOINT_VARIABLE).is_none() {
+            return Ok(None);
+        }
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_http()
+            .build()
+            .or(Err(Error::Telemetry("failed to build OTLP exporter")))?;
+        let provider = TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            .with_resource(Resource::new(vec![
+                KeyValue::new("service.name", service_name),
+            ]))
+            .build();
+        Ok(Some(provider))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacted_should_hash_and_truncate_value() {
+        let redacted = Redacted { value: "alice", redacted: true };
+        let formatted = redacted.to_string();
+        assert_eq!(formatted.len(), REDACTED_LENGTH + 1);
+        assert!(formatted.starts_with('#'));
+        assert!(!formatted.contains("alice"));
+        assert_eq!(formatted, Redacted { value: "alice", redacted: true }.to_string());
+        assert_ne!(formatted, Redacted { value: "bob", redacted: true }.to_string());
+        assert_eq!(format!("{:?}", Some(redacted)), format!("Some({})", formatted));
+    }
+
+    #[test]
+    fn redacted_should_tell_value_unless_redacted() {
+        assert_eq!(Redacted { value: "alice", redacted: false }.to_string(), "alice");
+    }
+
+    #[test]
+    fn truncated_should_keep_only_leading_characters() {
+        let session_id = "AAAABBBBCCCCDDDD";
+        let truncated = Truncated { value: session_id, truncated: true };
+        assert_eq!(truncated.to_string(), "AAAABBBB...");
+        assert_eq!(format!("{:?}", Some(truncated)), "Some(AAAABBBB...)");
+        assert_eq!(Truncated { value: "short", truncated: true }.to_string(), "short");
+        assert_eq!(
+            Truncated { value: session_id, truncated: false }.to_string(),
+            session_id,
+        );
+    }
+
+    #[test]
+    fn parse_log_filter_should_accept_level_or_directives() {
+        assert!(parse_log_filter("debug").is_some());
+        assert!(parse_log_filter("info,authentication=debug").is_some());
+        assert!(parse_log_filter("authentication=loud").is_none());
+    }
+
+    #[test]
+    fn parse_log_redaction_should_parse_on_or_off() {
+        assert_eq!(parse_log_redaction("on"), Some(true));
+        assert_eq!(parse_log_redaction("off"), Some(false));
+        assert_eq!(parse_log_redaction("yes"), None);
+    }
+}
@@ -0,0 +1,523 @@
+//! Multi-tenant relying parties.
+//!
+//! A single deployment may serve multiple relying parties (tenants) if the
+//! tenant table is configured. A tenant is resolved per request from a
+//! tenant key, which is either the `Host` header or the first path segment
+//! after the base path, and the [`Webauthn`] of each tenant is cached for the
+//! lifetime of the Lambda instance. Cognito triggers take the tenant key from
+//! the `tenant` client metadata.
+//!
+//! Data of tenants are isolated by the tenant IDs:
+//! - partition keys in the session table are prefixed with
+//!   "tenant#<tenant ID>#"; see [`SessionKey::Tenant`]
+//! - usernames in the credential table are qualified as
+//!   "<tenant ID>/<username>" so that the same username may exist in
+//!   different tenants
+//!
+//! User handles are random and unique across tenants, so credential items
+//! keep their keys.
+//!
+//! A tenant may have quotas of registrations and authentications so that one
+//! tenant cannot exhaust the capacity shared with the others. Requests are
+//! counted per tenant in fixed windows in the session table, and requests
+//! beyond the quota are rejected with 429; see [`Tenant::hit_quota`].
+//! Requests are served by the default relying party unless the tenant table
+//! is configured.
+//! Only the default relying party may have a legacy relying party in the
+//! migration mode of the RP ID; see [`crate::rp_migration`].
+
+use aws_sdk_dynamodb::types::AttributeValue;
+use lambda_http::{Body, Request, Response, http::StatusCode};
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex};
+use tracing::{error, info};
+use webauthn_rs::{Webauthn, prelude::Url};
+
+use crate::config;
+use crate::error::Error;
+use crate::items::{Item, SessionKey};
+use crate::parameters::build_webauthn;
+use crate::payload::ErrorResponseBody;
+use crate::rate_limit::{RateLimit, hit, parse_rate_limit};
+use crate::rp_migration::LegacyRelyingParty;
+
+/// Name of the client metadata that carries the tenant key to Cognito
+/// triggers.
+pub const TENANT_CLIENT_METADATA: &str = "tenant";
+
+/// How a tenant is resolved from a request.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TenantResolution {
+    /// Host name in the `Host` header.
+    Host,
+    /// First path segment after the base path; e.g., "acme" of
+    /// "/acme/v1/start".
+    PathSegment,
+}
+
+/// Configuration of a tenant in the tenant table.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TenantConfig {
+    /// Tenant ID.
+    pub tenant_id: String,
+
+    /// ID of the relying party.
+    pub rp_id: String,
+
+    /// Origin (URL) of the relying party.
+    pub rp_origin: String,
+
+    /// Name of the relying party.
+    pub rp_name: Option<String>,
+
+    /// Origins allowed in addition to the origin of the relying party.
+    ///
+    /// May include the `android:apk-key-hash:` origins of Android apps; see
+    /// [`crate::android`].
+    pub allowed_origins: Vec<String>,
+
+    /// Quotas of the tenant.
+    pub quotas: TenantQuotas,
+}
+
+/// Quotas of a tenant.
+///
+/// A quota is given to an item in the tenant table in the same form as
+/// [`crate::rate_limit::load_rate_limit`]; e.g., "1000/3600" allows 1,000
+/// requests per hour.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TenantQuotas {
+    /// Quota of registration starts; `registrationQuota` attribute.
+    ///
+    /// `None` if unlimited.
+    pub registration: Option<RateLimit>,
+
+    /// Quota of authentication starts; `authenticationQuota` attribute.
+    ///
+    /// `None` if unlimited.
+    pub authentication: Option<RateLimit>,
+}
+
+/// Kind of requests counted against a quota of a tenant.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum QuotaKind {
+    /// Registration starts.
+    Registration,
+    /// Authentication starts.
+    Authentication,
+}
+
+impl QuotaKind {
+    // scope of the counters.
+    fn scope(self) -> &'static str {
+        match self {
+            QuotaKind::Registration => "tenant-registration",
+            QuotaKind::Authentication => "tenant-authentication",
+        }
+    }
+}
+
+impl TenantConfig {
+    /// Parses an item in the tenant table.
+    ///
+    /// The partition key of an item is the tenant key.
+    pub fn from_item(item: &Item) -> Result<Self, Error> {
+        let get_s = |name: &'static str| item.get(name)
+            .map(|v| v.as_s().cloned().or(Err(Error::BadItemAttribute(name))))
+            .transpose();
+        let required = |name: &'static str| get_s(name)?
+            .ok_or(Error::BadItemAttribute(name));
+        let quota = |name: &'static str| Ok::<_, Error>(get_s(name)?
+            .map(|quota| parse_rate_limit(&quota).ok_or(Error::BadItemAttribute(name)))
+            .transpose()?
+            .flatten());
+        Ok(Self {
+            tenant_id: required("tenantId")?,
+            rp_id: required("rpId")?,
+            rp_origin: required("rpOrigin")?,
+            rp_name: get_s("rpName")?,
+            allowed_origins: item.get("allowedOrigins")
+                .map(|v| v.as_ss()
+                    .cloned()
+                    .or(Err(Error::BadItemAttribute("allowedOrigins"))))
+                .transpose()?
+                .unwrap_or_default(),
+            quotas: TenantQuotas {
+                registration: quota("registrationQuota")?,
+                authentication: quota("authenticationQuota")?,
+            },
+        })
+    }
+
+    /// Builds the [`Webauthn`] of the tenant.
+    pub fn build_webauthn(&self) -> Result<Webauthn, Error> {
+        let parse = |origin: &str| Url::parse(origin).map_err(|e| {
+            error!(?e, "parsing tenant origin");
+            Error::BadRelyingPartyOrigin(origin.into())
+        });
+        let allowed_origins = self.allowed_origins.iter()
+            .map(|origin| parse(origin))
+            .collect::<Result<Vec<_>, _>>()?;
+        build_webauthn(
+            &self.rp_id,
+            &parse(&self.rp_origin)?,
+            self.rp_name.as_deref().unwrap_or(&self.tenant_id),
+            &allowed_origins,
+        )
+    }
+}
+
+/// Relying party serving a request.
+#[derive(Clone, Debug)]
+pub struct Tenant {
+    id: Option<String>,
+    webauthn: Arc<Webauthn>,
+    legacy: Option<Arc<LegacyRelyingParty>>,
+    quotas: TenantQuotas,
+}
+
+impl Tenant {
+    /// Creates the default relying party, which has no tenant ID.
+    pub fn default_tenant(webauthn: Webauthn) -> Self {
+        Self {
+            id: None,
+            webauthn: Arc::new(webauthn),
+            legacy: None,
+            quotas: TenantQuotas::default(),
+        }
+    }
+
+    /// Sets the legacy relying party in the migration mode of the RP ID.
+    ///
+    /// `None` disables the migration mode.
+    pub fn with_legacy_relying_party(self, legacy: Option<LegacyRelyingParty>) -> Self {
+        Self {
+            legacy: legacy.map(Arc::new),
+            ..self
+        }
+    }
+
+    /// Tenant ID.
+    ///
+    /// `None` for the default relying party.
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    /// [`Webauthn`] of the relying party.
+    pub fn webauthn(&self) -> &Webauthn {
+        &self.webauthn
+    }
+
+    /// Legacy relying party in the migration mode of the RP ID.
+    ///
+    /// `None` unless the migration mode is enabled.
+    pub fn legacy_relying_party(&self) -> Option<&LegacyRelyingParty> {
+        self.legacy.as_deref()
+    }
+
+    /// Quotas of the tenant.
+    ///
+    /// The default relying party has no quotas.
+    pub fn quotas(&self) -> &TenantQuotas {
+        &self.quotas
+    }
+
+    /// Counts a request against a quota of the tenant.
+    ///
+    /// Counters are stored in the session table of a given name. Requests are
+    /// not counted unless the quota is configured.
+    /// Returns the seconds to wait if the request exceeds the quota.
+    pub async fn hit_quota(
+        &self,
+        dynamodb: &aws_sdk_dynamodb::Client,
+        table_name: &str,
+        kind: QuotaKind,
+        now: i64,
+    ) -> Result<Option<u64>, Error> {
+        let quota = match kind {
+            QuotaKind::Registration => self.quotas.registration.as_ref(),
+            QuotaKind::Authentication => self.quotas.authentication.as_ref(),
+        };
+        let (Some(tenant_id), Some(quota)) = (self.id.as_deref(), quota) else {
+            return Ok(None);
+        };
+        let retry_after = hit(dynamodb, table_name, kind.scope(), tenant_id, quota, now).await?;
+        if retry_after.is_some() {
+            error!("quota of tenant exceeded: {} {:?}", tenant_id, kind);
+        }
+        Ok(retry_after)
+    }
+
+    /// Scopes a key in the session table to the tenant.
+    ///
+    /// Returns the key as it is for the default relying party.
+    pub fn scope<'a>(&'a self, key: &'a SessionKey<'a>) -> SessionKey<'a> {
+        match self.id.as_deref() {
+            Some(tenant_id) => SessionKey::Tenant { tenant_id, key },
+            None => *key,
+        }
+    }
+
+    /// Qualifies a username with the tenant ID.
+    ///
+    /// Returns the username as it is for the default relying party.
+    pub fn qualify_username(&self, username: &str) -> String {
+        match self.id.as_deref() {
+            Some(tenant_id) => format!("{}/{}", tenant_id, username),
+            None => username.into(),
+        }
+    }
+
+    /// Removes the tenant ID from a qualified username.
+    pub fn unqualify_username<'a>(&self, username: &'a str) -> &'a str {
+        self.id.as_deref()
+            .and_then(|tenant_id| username.strip_prefix(tenant_id))
+            .and_then(|username| username.strip_prefix('/'))
+            .unwrap_or(username)
+    }
+}
+
+/// Directory of tenants in the tenant table.
+#[derive(Debug)]
+pub struct TenantDirectory {
+    dynamodb: aws_sdk_dynamodb::Client,
+    table_name: String,
+    resolution: TenantResolution,
+    cache: Mutex<HashMap<String, Arc<Tenant>>>,
+}
+
+/// Loads the directory of tenants.
+///
+/// You can specify to the following environment variables:
+/// - `TENANT_TABLE_NAME`: name of the DynamoDB table of tenants
+/// - `TENANT_RESOLUTION`: how a tenant is resolved from a request; "host"
+///   (default) or "path"
+///
+/// Returns `None` if `TENANT_TABLE_NAME` is not set, which means every request
+/// is served by the default relying party.
+pub fn load_tenant_directory(
+    dynamodb: aws_sdk_dynamodb::Client,
+) -> Result<Option<TenantDirectory>, Error> {
+    let table_name = match config::var("TENANT_TABLE_NAME") {
+        Ok(table_name) if !table_name.is_empty() => table_name,
+        Ok(table_name) => return Err(
+            Error::BadEnvironmentVariable("TENANT_TABLE_NAME", table_name),
+        ),
+        Err(env::VarError::NotPresent) => return Ok(None),
+        Err(env::VarError::NotUnicode(table_name)) => return Err(
+            Error::BadEnvironmentVariable(
+                "TENANT_TABLE_NAME",
+                table_name.to_string_lossy().into(),
+            ),
+        ),
+    };
+    let resolution = match config::var("TENANT_RESOLUTION").as_deref() {
+        Ok("host") | Err(_) => TenantResolution::Host,
+        Ok("path") => TenantResolution::PathSegment,
+        Ok(resolution) => return Err(
+            Error::BadEnvironmentVariable("TENANT_RESOLUTION", resolution.into()),
+        ),
+    };
+    Ok(Some(TenantDirectory {
+        dynamodb,
+        table_name,
+        resolution,
+        cache: Mutex::new(HashMap::new()),
+    }))
+}
+
+impl TenantDirectory {
+    /// How a tenant is resolved from a request.
+    pub fn resolution(&self) -> TenantResolution {
+        self.resolution
+    }
+
+    /// Resolves the tenant and the rest of a job path.
+    ///
+    /// Returns `None` if the request has no tenant key or the tenant does not
+    /// exist.
+    pub async fn resolve_request<'a>(
+        &self,
+        request: &Request,
+        job_path: &'a str,
+    ) -> Result<Option<(Arc<Tenant>, &'a str)>, Error> {
+        let (tenant_key, job_path) = match self.resolution {
+            TenantResolution::Host => match host_of(request) {
+                Some(host) => (host, job_path),
+                None => return Ok(None),
+            },
+            TenantResolution::PathSegment => match split_tenant_segment(job_path) {
+                Some((tenant_key, job_path)) => (tenant_key.to_string(), job_path),
+                None => return Ok(None),
+            },
+        };
+        Ok(self.resolve(&tenant_key).await?.map(|tenant| (tenant, job_path)))
+    }
+
+    /// Resolves the tenant of a given tenant key.
+    ///
+    /// Returns `None` if the tenant does not exist.
+    pub async fn resolve(&self, tenant_key: &str) -> Result<Option<Arc<Tenant>>, Error> {
+        if let Some(tenant) = self.cache.lock().unwrap().get(tenant_key) {
+            return Ok(Some(tenant.clone()));
+        }
+        info!("loading tenant: {}", tenant_key);
+        let item = self.dynamodb
+            .get_item()
+            .table_name(self.table_name.clone())
+            .key("pk", AttributeValue::S(tenant_key.into()))
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "getting tenant");
+                Error::Storage("failed to get tenant")
+            })?
+            .item;
+        let Some(item) = item else {
+            return Ok(None);
+        };
+        let config = TenantConfig::from_item(&item)?;
+        let tenant = Arc::new(Tenant {
+            webauthn: Arc::new(config.build_webauthn()?),
+            id: Some(config.tenant_id),
+            legacy: None,
+            quotas: config.quotas,
+        });
+        self.cache.lock().unwrap()
+            .insert(tenant_key.into(), tenant.clone());
+        Ok(Some(tenant))
+    }
+}
+
+/// Creates a 404 response to a request for an unknown tenant.
+pub fn unknown_tenant() -> Result<Response<Body>, lambda_http::Error> {
+    let body = serde_json::to_string(&ErrorResponseBody {
+        error: "unknown_tenant",
+        message: "unknown tenant".into(),
+        field: None,
+        localized_message: None,
+        request_id: None,
+    })?;
+    Ok(Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .header("Content-Type", "application/json")
+        .body(body.into())?)
+}
+
+// host name in the `Host` header without the port.
+fn host_of(request: &Request) -> Option<String> {
+    request.headers()
+        .get("Host")
+        .and_then(|host| host.to_str().ok())
+        .map(|host| host.split(':').next().unwrap_or(host).to_ascii_lowercase())
+        .filter(|host| !host.is_empty())
+}
+
+// splits the first segment off a job path; e.g., ("acme", "/v1/start") for
+// "/acme/v1/start".
+fn split_tenant_segment(job_path: &str) -> Option<(&str, &str)> {
+    let rest = job_path.strip_prefix('/')?;
+    let (segment, rest) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, ""),
+    };
+    (!segment.is_empty()).then_some((segment, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tenant(id: Option<&str>) -> Tenant {
+        Tenant {
+            id: id.map(Into::into),
+            webauthn: Arc::new(build_webauthn(
+                "localhost",
+                &Url::parse("http://localhost:5173").unwrap(),
+                "Test",
+                &[],
+            ).unwrap()),
+            legacy: None,
+            quotas: TenantQuotas::default(),
+        }
+    }
+
+    #[test]
+    fn tenant_should_scope_session_keys() {
+        let key = SessionKey::Registration("abc");
+        assert_eq!(
+            tenant(Some("acme")).scope(&key).pk(),
+            "tenant#acme#registration#abc",
+        );
+        assert_eq!(tenant(None).scope(&key).pk(), "registration#abc");
+    }
+
+    #[test]
+    fn tenant_should_qualify_usernames() {
+        let acme = tenant(Some("acme"));
+        assert_eq!(acme.qualify_username("alice"), "acme/alice");
+        assert_eq!(acme.unqualify_username("acme/alice"), "alice");
+        assert_eq!(acme.unqualify_username("acmealice"), "acmealice");
+        let default = tenant(None);
+        assert_eq!(default.qualify_username("alice"), "alice");
+        assert_eq!(default.unqualify_username("acme/alice"), "acme/alice");
+    }
+
+    #[test]
+    fn split_tenant_segment_should_split_first_segment() {
+        assert_eq!(
+            split_tenant_segment("/acme/v1/start"),
+            Some(("acme", "/v1/start")),
+        );
+        assert_eq!(split_tenant_segment("/acme"), Some(("acme", "")));
+        assert_eq!(split_tenant_segment("//v1/start"), None);
+        assert_eq!(split_tenant_segment("acme/v1/start"), None);
+    }
+
+    #[test]
+    fn host_of_should_strip_port() {
+        let request = lambda_http::http::Request::builder()
+            .header("Host", "Example.com:443")
+            .body(lambda_http::Body::Empty)
+            .unwrap();
+        assert_eq!(host_of(&request), Some("example.com".into()));
+    }
+
+    #[test]
+    fn tenant_config_should_require_rp() {
+        let mut item = HashMap::from([
+            ("pk".to_string(), AttributeValue::S("example.com".into())),
+            ("tenantId".into(), AttributeValue::S("acme".into())),
+            ("rpId".into(), AttributeValue::S("example.com".into())),
+            ("rpOrigin".into(), AttributeValue::S("https://example.com".into())),
+        ]);
+        let config = TenantConfig::from_item(&item).unwrap();
+        assert_eq!(config.tenant_id, "acme");
+        assert!(config.allowed_origins.is_empty());
+        assert_eq!(config.quotas, TenantQuotas::default());
+        assert!(config.build_webauthn().is_ok());
+        item.remove("rpOrigin");
+        assert!(TenantConfig::from_item(&item).is_err());
+    }
+
+    #[test]
+    fn tenant_config_should_parse_quotas() {
+        let mut item = HashMap::from([
+            ("pk".to_string(), AttributeValue::S("example.com".into())),
+            ("tenantId".into(), AttributeValue::S("acme".into())),
+            ("rpId".into(), AttributeValue::S("example.com".into())),
+            ("rpOrigin".into(), AttributeValue::S("https://example.com".into())),
+            ("registrationQuota".into(), AttributeValue::S("100/3600".into())),
+            ("authenticationQuota".into(), AttributeValue::S("off".into())),
+        ]);
+        let config = TenantConfig::from_item(&item).unwrap();
+        assert_eq!(config.quotas, TenantQuotas {
+            registration: Some(RateLimit { limit: 100, window: 3600 }),
+            authentication: None,
+        });
+        item.insert("authenticationQuota".into(), AttributeValue::S("many".into()));
+        assert!(TenantConfig::from_item(&item).is_err());
+    }
+}
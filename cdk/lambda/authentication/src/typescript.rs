@@ -0,0 +1,42 @@
+//! TypeScript types of the registration API.
+//!
+//! Available if the `typescript` feature is enabled.
+//! The following command writes the types for the app:
+//!
+//! ```sh
+//! cargo run --bin typescript --features typescript > ../../../app/src/registration-api.ts
+//! ```
+//!
+//! The types are derived from the request and response bodies in
+//! [`crate::registration`] and [`crate::payload`], so that the app stays in
+//! sync with the functions. Options and credentials of the Web Authentication
+//! API are typed as plain objects, because their JSON forms are encoded by the
+//! app.
+
+use ts_rs::TS;
+
+use crate::hints::PublicKeyCredentialHint;
+use crate::payload::{ERROR_CODES, ErrorResponseBody};
+use crate::registration::{
+    FinishRegistrationSession,
+    NewUserInfo,
+    StartRegistrationSession,
+};
+
+/// Generates the declarations of the TypeScript types.
+pub fn declarations() -> String {
+    let error_code = ERROR_CODES.iter()
+        .map(|code| format!("\"{}\"", code))
+        .collect::<Vec<_>>()
+        .join(" | ");
+    [
+        "// generated by `cargo run --bin typescript --features typescript`; do not edit.".to_string(),
+        "export type AuthenticatorAttachment = \"platform\" | \"cross-platform\";".into(),
+        format!("export type ErrorCode = {};", error_code),
+        format!("export {}", PublicKeyCredentialHint::decl()),
+        format!("export {}", NewUserInfo::decl()),
+        format!("export {}", StartRegistrationSession::decl()),
+        format!("export {}", FinishRegistrationSession::decl()),
+        format!("export {}", ErrorResponseBody::decl()),
+    ].join("\n\n") + "\n"
+}
@@ -0,0 +1,283 @@
+//! Username validation.
+//!
+//! Usernames become Cognito attributes and DynamoDB values, so they are
+//! normalized and validated before a registration starts.
+
+use std::env;
+use std::fmt;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::config;
+use crate::error::Error;
+
+/// Default minimum length of a username in characters.
+pub const DEFAULT_MIN_LENGTH: usize = 1;
+
+/// Default maximum length of a username in characters.
+pub const DEFAULT_MAX_LENGTH: usize = 64;
+
+// Symbols allowed in addition to letters and digits.
+const ALLOWED_SYMBOLS: &[char] = &['.', '_', '-', '@', '+'];
+
+/// Characters allowed in a username.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Charset {
+    /// ASCII letters, digits, and `._-@+`.
+    Ascii,
+
+    /// Unicode letters, digits, and `._-@+`.
+    Unicode,
+}
+
+/// Username validation policy.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UsernamePolicy {
+    /// Minimum length in characters.
+    pub min_length: usize,
+
+    /// Maximum length in characters.
+    pub max_length: usize,
+
+    /// Allowed characters.
+    pub charset: Charset,
+
+    /// Whether to lowercase usernames.
+    pub lowercase: bool,
+
+    /// Whether usernames must be email addresses.
+    pub email: bool,
+}
+
+impl Default for UsernamePolicy {
+    fn default() -> Self {
+        Self {
+            min_length: DEFAULT_MIN_LENGTH,
+            max_length: DEFAULT_MAX_LENGTH,
+            charset: Charset::Unicode,
+            lowercase: false,
+            email: false,
+        }
+    }
+}
+
+/// Reason why a username is rejected.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum UsernameError {
+    /// Too short.
+    TooShort(usize),
+
+    /// Too long.
+    TooLong(usize),
+
+    /// Contains a disallowed character.
+    DisallowedCharacter(char),
+
+    /// Not an email address.
+    NotEmail,
+}
+
+impl fmt::Display for UsernameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UsernameError::TooShort(min) =>
+                write!(f, "username must be at least {} characters", min),
+            UsernameError::TooLong(max) =>
+                write!(f, "username must be at most {} characters", max),
+            UsernameError::DisallowedCharacter(c) =>
+                write!(f, "username must not contain {:?}", c),
+            UsernameError::NotEmail =>
+                write!(f, "username must be an email address"),
+        }
+    }
+}
+
+impl UsernamePolicy {
+    /// Normalizes and validates a given username.
+    ///
+    /// The username is trimmed, NFC-normalized, and lowercased if
+    /// [`UsernamePolicy::lowercase`] is set.
+    ///
+    /// Returns the normalized username.
+    pub fn apply(&self, username: &str) -> Result<String, UsernameError> {
+        let mut username: String = username.trim().nfc().collect();
+        if self.lowercase {
+            username = username.to_lowercase();
+        }
+        let length = username.chars().count();
+        if length < self.min_length {
+            return Err(UsernameError::TooShort(self.min_length));
+        }
+        if length > self.max_length {
+            return Err(UsernameError::TooLong(self.max_length));
+        }
+        if let Some(c) = username.chars().find(|c| !self.is_allowed(*c)) {
+            return Err(UsernameError::DisallowedCharacter(c));
+        }
+        if self.email && !is_email(&username) {
+            return Err(UsernameError::NotEmail);
+        }
+        Ok(username)
+    }
+
+    fn is_allowed(&self, c: char) -> bool {
+        ALLOWED_SYMBOLS.contains(&c) || match self.charset {
+            Charset::Ascii => c.is_ascii_alphanumeric(),
+            Charset::Unicode => c.is_alphanumeric(),
+        }
+    }
+}
+
+/// Loads the username policy.
+///
+/// You can configure the policy with the following environment variables:
+/// - `USERNAME_MIN_LENGTH`: minimum length; [`DEFAULT_MIN_LENGTH`] by default
+/// - `USERNAME_MAX_LENGTH`: maximum length; [`DEFAULT_MAX_LENGTH`] by default
+/// - `USERNAME_CHARSET`: "ascii" or "unicode" (default)
+/// - `USERNAME_LOWERCASE`: "true" to lowercase usernames
+/// - `USERNAME_EMAIL`: "true" to demand email addresses
+pub fn load_username_policy() -> Result<UsernamePolicy, Error> {
+    let default = UsernamePolicy::default();
+    let policy = UsernamePolicy {
+        min_length: load_env("USERNAME_MIN_LENGTH", parse_length)?
+            .unwrap_or(default.min_length),
+        max_length: load_env("USERNAME_MAX_LENGTH", parse_length)?
+            .unwrap_or(default.max_length),
+        charset: load_env("USERNAME_CHARSET", parse_charset)?
+            .unwrap_or(default.charset),
+        lowercase: load_env("USERNAME_LOWERCASE", parse_flag)?
+            .unwrap_or(default.lowercase),
+        email: load_env("USERNAME_EMAIL", parse_flag)?
+            .unwrap_or(default.email),
+    };
+    if policy.min_length > policy.max_length {
+        return Err(Error::BadEnvironmentVariable(
+            "USERNAME_MIN_LENGTH",
+            format!("{} > {}", policy.min_length, policy.max_length),
+        ));
+    }
+    Ok(policy)
+}
+
+// Loads an environment variable with a given parser.
+//
+// `None` if the environment variable is not set.
+fn load_env<T>(
+    name: &'static str,
+    parse: fn(&str) -> Option<T>,
+) -> Result<Option<T>, Error> {
+    match config::var(name) {
+        Ok(value) => parse(&value)
+            .map(Some)
+            .ok_or(Error::BadEnvironmentVariable(name, value)),
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(value)) => Err(
+            Error::BadEnvironmentVariable(name, value.to_string_lossy().into()),
+        ),
+    }
+}
+
+fn parse_length(value: &str) -> Option<usize> {
+    value.parse().ok().filter(|length| *length > 0)
+}
+
+fn parse_charset(value: &str) -> Option<Charset> {
+    match value {
+        "ascii" => Some(Charset::Ascii),
+        "unicode" => Some(Charset::Unicode),
+        _ => None,
+    }
+}
+
+fn parse_flag(value: &str) -> Option<bool> {
+    match value {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Loose check of an email address; `local@domain.tld`.
+pub fn is_email(username: &str) -> bool {
+    match username.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && domain.split('.').all(|label| !label.is_empty())
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn username_policy_apply_should_trim_and_normalize_username() {
+        let policy = UsernamePolicy::default();
+        // "e" + combining acute accent → "é"
+        assert_eq!(policy.apply(" cafe\u{0301} ").unwrap(), "caf\u{00e9}");
+        assert_eq!(policy.apply("Alice").unwrap(), "Alice");
+        let policy = UsernamePolicy {
+            lowercase: true,
+            ..UsernamePolicy::default()
+        };
+        assert_eq!(policy.apply("Alice").unwrap(), "alice");
+    }
+
+    #[test]
+    fn username_policy_apply_should_enforce_length() {
+        let policy = UsernamePolicy {
+            min_length: 3,
+            max_length: 5,
+            ..UsernamePolicy::default()
+        };
+        assert_eq!(policy.apply("ab"), Err(UsernameError::TooShort(3)));
+        assert_eq!(policy.apply("abcdef"), Err(UsernameError::TooLong(5)));
+        // counts characters rather than bytes
+        assert!(policy.apply("あいう").is_ok());
+        assert_eq!(policy.apply("   "), Err(UsernameError::TooShort(3)));
+    }
+
+    #[test]
+    fn username_policy_apply_should_reject_disallowed_characters() {
+        let policy = UsernamePolicy::default();
+        assert_eq!(
+            policy.apply("a b"),
+            Err(UsernameError::DisallowedCharacter(' ')),
+        );
+        assert_eq!(
+            policy.apply("a\"b"),
+            Err(UsernameError::DisallowedCharacter('"')),
+        );
+        assert_eq!(
+            policy.apply("a\nb"),
+            Err(UsernameError::DisallowedCharacter('\n')),
+        );
+        assert!(policy.apply("あいう").is_ok());
+        let policy = UsernamePolicy {
+            charset: Charset::Ascii,
+            ..UsernamePolicy::default()
+        };
+        assert_eq!(
+            policy.apply("あいう"),
+            Err(UsernameError::DisallowedCharacter('あ')),
+        );
+        assert!(policy.apply("john.doe+test@example.com").is_ok());
+    }
+
+    #[test]
+    fn username_policy_apply_should_demand_email_if_configured() {
+        let policy = UsernamePolicy {
+            email: true,
+            ..UsernamePolicy::default()
+        };
+        assert!(policy.apply("john@example.com").is_ok());
+        assert_eq!(policy.apply("john"), Err(UsernameError::NotEmail));
+        assert_eq!(policy.apply("@example.com"), Err(UsernameError::NotEmail));
+        assert_eq!(policy.apply("john@example"), Err(UsernameError::NotEmail));
+        assert_eq!(policy.apply("john@example..com"), Err(UsernameError::NotEmail));
+        assert_eq!(policy.apply("john@a@example.com"), Err(UsernameError::NotEmail));
+    }
+}
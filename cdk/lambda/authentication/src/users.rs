@@ -0,0 +1,1149 @@
+//! Lookup of users and their credentials in the credential table.
+//!
+//! Every credential item has the `username` attribute, which is the partition
+//! key of [`USERNAME_INDEX_NAME`]. A username is resolved into the user handle
+//! by a query on the index, and the user handle is resolved into credentials
+//! by a query on the table; no scan is involved.
+//!
+//! A new user and the first credential are written in a single transaction
+//! so that neither exists without the other.
+//!
+//! Credential items are updated with optimistic locking; an update is
+//! rejected if the `version` attribute has changed since the item was read.
+//!
+//! A recovery code is consumed by removing its hash from the set in a single
+//! conditional update, so it can be used only once even under concurrent
+//! requests.
+//!
+//! A user is renamed by updating the `username` attribute of the user item
+//! and every credential item in a single transaction; the user handle and the
+//! credentials stay intact.
+//!
+//! If [`PiiProtection`] is configured, user and credential items are sealed
+//! when they are written and opened when they are read, and usernames are
+//! looked up by their indexes; see [`crate::pii`].
+
+use aws_sdk_dynamodb::{
+    operation::transact_write_items::TransactWriteItemsError,
+    types::{AttributeValue, Put, ReturnValue, TransactWriteItem, Update},
+};
+use std::collections::HashMap;
+use thiserror::{Error as ThisError};
+use tracing::{error, info};
+use webauthn_rs::prelude::AuthenticationResult;
+
+use crate::credential_store;
+use crate::error::Error;
+use crate::items::{
+    CREDENTIAL_SK_PREFIX,
+    CredentialItem,
+    CredentialKey,
+    Item,
+    RecoveryCodesItem,
+    UserItem,
+    user_handle_of,
+    user_pk,
+};
+use crate::migration::ExportRecord;
+use crate::pii::{PiiProtection, is_sealed};
+use crate::telemetry::{redact, truncate};
+
+/// Name of the index to look up users by username.
+pub const USERNAME_INDEX_NAME: &str = "UsernameIndex";
+
+// Maximum number of items in a transaction that DynamoDB allows.
+const MAX_TRANSACTION_ITEMS: usize = 100;
+
+/// Failure to create a user.
+#[derive(Debug, ThisError)]
+pub enum CreateUserError {
+    /// The user already exists.
+    #[error("user already exists")]
+    UserExists,
+    /// The credential is already registered.
+    #[error("credential already exists")]
+    CredentialExists,
+    /// A concurrent transaction conflicted; the request may be retried.
+    #[error("conflicting transaction")]
+    Conflict,
+    /// Other failure.
+    #[error(transparent)]
+    Other(#[from] Error),
+}
+
+/// Failure to rename a user.
+#[derive(Debug, ThisError)]
+pub enum RenameUserError {
+    /// Another user has the username.
+    #[error("username already taken")]
+    UsernameTaken,
+    /// The user does not exist.
+    #[error("user not found")]
+    UserNotFound,
+    /// A concurrent update conflicted; the request may be retried.
+    #[error("conflicting transaction")]
+    Conflict,
+    /// Other failure.
+    #[error(transparent)]
+    Other(#[from] Error),
+}
+
+/// Filter of credentials.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CredentialFilter {
+    /// Type of credentials; "passkey" or "securityKey".
+    pub credential_type: Option<String>,
+
+    /// Backup state of credentials.
+    ///
+    /// Credentials stored before backup states were recorded never match.
+    pub backup_state: Option<bool>,
+}
+
+impl CredentialFilter {
+    // builds the filter expression and its attribute values.
+    //
+    // returns `None` if no condition is specified.
+    fn expression(&self) -> Option<(String, Vec<(&'static str, AttributeValue)>)> {
+        let mut conditions = Vec::new();
+        let mut values = Vec::new();
+        if let Some(credential_type) = self.credential_type.as_ref() {
+            conditions.push("credentialType = :credentialType");
+            values.push((":credentialType", AttributeValue::S(credential_type.clone())));
+        }
+        if let Some(backup_state) = self.backup_state {
+            conditions.push("backupState = :backupState");
+            values.push((":backupState", AttributeValue::Bool(backup_state)));
+        }
+        (!conditions.is_empty()).then(|| (conditions.join(" AND "), values))
+    }
+}
+
+/// Page of credentials.
+#[derive(Clone, Debug)]
+pub struct CredentialPage {
+    /// Credentials in the page.
+    pub credentials: Vec<CredentialItem>,
+
+    /// Key to start the next page.
+    ///
+    /// `None` if this is the last page.
+    pub last_evaluated_key: Option<HashMap<String, AttributeValue>>,
+}
+
+/// Page of users and credentials for an export.
+#[derive(Clone, Debug)]
+pub struct RecordPage {
+    /// Users and credentials in the page.
+    pub records: Vec<ExportRecord>,
+
+    /// Key to start the next page.
+    ///
+    /// `None` if this is the last page.
+    pub last_evaluated_key: Option<HashMap<String, AttributeValue>>,
+}
+
+/// Users in the credential table.
+#[derive(Clone, Debug)]
+pub struct UserDirectory {
+    dynamodb: aws_sdk_dynamodb::Client,
+    table_name: String,
+    pii: Option<PiiProtection>,
+}
+
+impl UserDirectory {
+    /// Creates a user directory on a given credential table.
+    pub fn new(dynamodb: aws_sdk_dynamodb::Client, table_name: String) -> Self {
+        Self { dynamodb, table_name, pii: None }
+    }
+
+    /// Protects PII in the items with a given [`PiiProtection`].
+    ///
+    /// PII is stored in plaintext if `pii` is `None`.
+    pub fn with_pii_protection(mut self, pii: Option<PiiProtection>) -> Self {
+        self.pii = pii;
+        self
+    }
+
+    /// Name of the credential table.
+    pub fn table_name(&self) -> &str {
+        &self.table_name
+    }
+
+    /// Resolves the user handle of a given username.
+    ///
+    /// Returns `None` if no credential is registered for the username.
+    pub async fn find_user_handle(
+        &self,
+        username: &str,
+    ) -> Result<Option<String>, Error> {
+        let username = self.username_attribute(username).await?;
+        let items = self.dynamodb
+            .query()
+            .table_name(self.table_name.clone())
+            .index_name(USERNAME_INDEX_NAME)
+            .key_condition_expression("username = :username")
+            .expression_attribute_values(
+                ":username",
+                AttributeValue::S(username),
+            )
+            .limit(1)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "querying username index");
+                Error::Storage("failed to look up username")
+            })?
+            .items
+            .unwrap_or_default();
+        items.first()
+            .map(|item| user_handle_of(item)
+                .map(Into::into)
+                .ok_or(Error::Storage("malformed pk in username index")))
+            .transpose()
+    }
+
+    /// Lists credentials of a given user.
+    pub async fn list_credentials(
+        &self,
+        user_handle: &str,
+    ) -> Result<Vec<CredentialItem>, Error> {
+        let items = self.dynamodb
+            .query()
+            .table_name(self.table_name.clone())
+            .key_condition_expression("pk = :pk AND begins_with(sk, :sk)")
+            .expression_attribute_values(
+                ":pk",
+                AttributeValue::S(user_pk(user_handle)),
+            )
+            .expression_attribute_values(
+                ":sk",
+                AttributeValue::S(CREDENTIAL_SK_PREFIX.into()),
+            )
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "querying credentials");
+                Error::Storage("failed to list credentials")
+            })?
+            .items
+            .unwrap_or_default();
+        self.open_credentials(items).await
+    }
+
+    /// Queries a page of credentials of a given user.
+    ///
+    /// `limit` is the maximum number of credentials evaluated in a page.
+    /// The filter applies after the limit, so a page may contain fewer
+    /// credentials even if it is not the last page.
+    pub async fn query_credentials(
+        &self,
+        user_handle: &str,
+        limit: i32,
+        exclusive_start_key: Option<HashMap<String, AttributeValue>>,
+        filter: &CredentialFilter,
+    ) -> Result<CredentialPage, Error> {
+        let mut request = self.dynamodb
+            .query()
+            .table_name(self.table_name.clone())
+            .key_condition_expression("pk = :pk AND begins_with(sk, :sk)")
+            .expression_attribute_values(
+                ":pk",
+                AttributeValue::S(user_pk(user_handle)),
+            )
+            .expression_attribute_values(
+                ":sk",
+                AttributeValue::S(CREDENTIAL_SK_PREFIX.into()),
+            )
+            .limit(limit)
+            .set_exclusive_start_key(exclusive_start_key);
+        if let Some((expression, values)) = filter.expression() {
+            request = request.filter_expression(expression);
+            for (name, value) in values {
+                request = request.expression_attribute_values(name, value);
+            }
+        }
+        let res = request.send()
+            .await
+            .map_err(|e| {
+                error!(?e, "querying credentials");
+                Error::Storage("failed to query credentials")
+            })?;
+        Ok(CredentialPage {
+            credentials: self.open_credentials(res.items.unwrap_or_default()).await?,
+            last_evaluated_key: res.last_evaluated_key,
+        })
+    }
+
+    /// Obtains a credential with a strongly consistent read.
+    pub async fn get_credential(
+        &self,
+        key: CredentialKey<'_>,
+    ) -> Result<Option<CredentialItem>, Error> {
+        let item = self.dynamodb
+            .get_item()
+            .table_name(self.table_name.clone())
+            .set_key(Some(key.key()))
+            .consistent_read(true)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "getting credential");
+                Error::Storage("failed to get credential")
+            })?
+            .item;
+        match item {
+            Some(item) => Ok(Some(CredentialItem::from_item(&self.open(item).await?)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Updates the credential and backup flags of a credential item used for
+    /// authentication.
+    ///
+    /// `updated_at` is also recorded as the last-used timestamp.
+    /// `current` is the item as read before the update. The update is
+    /// rejected if the item has been updated since then, and `false` is
+    /// returned; the caller should read the item again and retry.
+    pub async fn update_credential(
+        &self,
+        current: &CredentialItem,
+        credential: String,
+        backup_eligible: bool,
+        backup_state: bool,
+        updated_at: String,
+    ) -> Result<bool, Error> {
+        let request = self.dynamodb
+            .update_item()
+            .table_name(self.table_name.clone())
+            .set_key(Some(current.key().key()))
+            .update_expression("SET credential = :credential, backupEligible = :backupEligible, backupState = :backupState, updatedAt = :updatedAt, lastUsedAt = :updatedAt, version = :nextVersion ADD authCount :one")
+            .expression_attribute_values(":credential", AttributeValue::S(credential))
+            .expression_attribute_values(
+                ":backupEligible",
+                AttributeValue::Bool(backup_eligible),
+            )
+            .expression_attribute_values(
+                ":backupState",
+                AttributeValue::Bool(backup_state),
+            )
+            .expression_attribute_values(":updatedAt", AttributeValue::S(updated_at))
+            .expression_attribute_values(":one", AttributeValue::N("1".into()))
+            .expression_attribute_values(
+                ":nextVersion",
+                AttributeValue::N(format!("{}", current.version.unwrap_or(0) + 1)),
+            )
+            .return_values(ReturnValue::None);
+        let request = match current.version {
+            Some(version) => request
+                .condition_expression("version = :version")
+                .expression_attribute_values(
+                    ":version",
+                    AttributeValue::N(format!("{}", version)),
+                ),
+            // stored before versions were recorded
+            None => request
+                .condition_expression("attribute_exists(pk) AND attribute_not_exists(version)"),
+        };
+        match request.send().await {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error()
+                .is_some_and(|e| e.is_conditional_check_failed_exception()) =>
+            {
+                Ok(false)
+            }
+            Err(e) => {
+                error!(?e, "updating credential");
+                Err(Error::Storage("failed to update credential"))
+            }
+        }
+    }
+
+    /// Records the last-used timestamp of a credential, and counts the
+    /// authentication.
+    ///
+    /// Does not change the version because neither the timestamp nor the
+    /// count conflicts with other updates.
+    pub async fn touch_credential(
+        &self,
+        key: CredentialKey<'_>,
+        used_at: String,
+    ) -> Result<(), Error> {
+        self.dynamodb
+            .update_item()
+            .table_name(self.table_name.clone())
+            .set_key(Some(key.key()))
+            .update_expression("SET lastUsedAt = :usedAt ADD authCount :one")
+            .expression_attribute_values(":usedAt", AttributeValue::S(used_at))
+            .expression_attribute_values(":one", AttributeValue::N("1".into()))
+            .condition_expression("attribute_exists(pk)")
+            .return_values(ReturnValue::None)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "touching credential");
+                Error::Storage("failed to record last use of credential")
+            })?;
+        Ok(())
+    }
+
+    /// Flags a credential verified only with a legacy RP ID.
+    ///
+    /// Does nothing if the credential has already been flagged with the same
+    /// RP ID. Does not change the version because the flag never conflicts
+    /// with other updates. See [`crate::rp_migration`].
+    pub async fn flag_legacy_credential(
+        &self,
+        credential_item: &CredentialItem,
+        legacy_rp_id: &str,
+    ) -> Result<(), Error> {
+        if credential_item.legacy_rp_id.as_deref() == Some(legacy_rp_id) {
+            return Ok(());
+        }
+        info!(
+            "flagging legacy credential: {}",
+            truncate(&credential_item.credential_id),
+        );
+        self.dynamodb
+            .update_item()
+            .table_name(self.table_name.clone())
+            .set_key(Some(credential_item.key().key()))
+            .update_expression("SET legacyRpId = :legacyRpId")
+            .expression_attribute_values(
+                ":legacyRpId",
+                AttributeValue::S(legacy_rp_id.into()),
+            )
+            .condition_expression("attribute_exists(pk)")
+            .return_values(ReturnValue::None)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "flagging legacy credential");
+                Error::Storage("failed to flag legacy credential")
+            })?;
+        Ok(())
+    }
+
+    /// Scans a page of credentials flagged with a legacy RP ID.
+    ///
+    /// `limit` is the maximum number of items evaluated in a page.
+    /// The filter applies after the limit, so a page may contain fewer
+    /// credentials even if it is not the last page.
+    pub async fn scan_legacy_credentials(
+        &self,
+        limit: i32,
+        exclusive_start_key: Option<HashMap<String, AttributeValue>>,
+    ) -> Result<CredentialPage, Error> {
+        let res = self.dynamodb
+            .scan()
+            .table_name(self.table_name.clone())
+            .filter_expression("begins_with(sk, :sk) AND attribute_exists(legacyRpId)")
+            .expression_attribute_values(
+                ":sk",
+                AttributeValue::S(CREDENTIAL_SK_PREFIX.into()),
+            )
+            .limit(limit)
+            .set_exclusive_start_key(exclusive_start_key)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "scanning legacy credentials");
+                Error::Storage("failed to scan legacy credentials")
+            })?;
+        Ok(CredentialPage {
+            credentials: self.open_credentials(res.items.unwrap_or_default()).await?,
+            last_evaluated_key: res.last_evaluated_key,
+        })
+    }
+
+    /// Updates a credential item with an authentication result if necessary.
+    ///
+    /// See [`crate::credential_store::record_authentication`].
+    pub async fn record_authentication(
+        &self,
+        credential_item: CredentialItem,
+        auth_result: &AuthenticationResult,
+    ) -> Result<(), Error> {
+        credential_store::record_authentication(self, credential_item, auth_result).await
+    }
+
+    /// Deletes a credential.
+    ///
+    /// Returns `false` if the credential does not exist.
+    pub async fn delete_credential(
+        &self,
+        key: CredentialKey<'_>,
+    ) -> Result<bool, Error> {
+        let res = self.dynamodb
+            .delete_item()
+            .table_name(self.table_name.clone())
+            .set_key(Some(key.key()))
+            .condition_expression("attribute_exists(pk)")
+            .return_values(ReturnValue::None)
+            .send()
+            .await;
+        match res {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error()
+                .is_some_and(|e| e.is_conditional_check_failed_exception()) =>
+            {
+                Ok(false)
+            }
+            Err(e) => {
+                error!(?e, "deleting credential");
+                Err(Error::Storage("failed to delete credential"))
+            }
+        }
+    }
+
+    /// Disables a credential so that it can no longer be used for
+    /// authentication.
+    ///
+    /// Keeps the original timestamp if the credential has already been
+    /// disabled. Increments the version so that concurrent updates based on
+    /// an enabled item are rejected.
+    /// Returns `false` if the credential does not exist.
+    pub async fn disable_credential(
+        &self,
+        key: CredentialKey<'_>,
+        disabled_at: String,
+    ) -> Result<bool, Error> {
+        let res = self.dynamodb
+            .update_item()
+            .table_name(self.table_name.clone())
+            .set_key(Some(key.key()))
+            .update_expression("SET disabledAt = if_not_exists(disabledAt, :disabledAt) ADD version :one")
+            .expression_attribute_values(":disabledAt", AttributeValue::S(disabled_at))
+            .expression_attribute_values(":one", AttributeValue::N("1".into()))
+            .condition_expression("attribute_exists(pk)")
+            .return_values(ReturnValue::None)
+            .send()
+            .await;
+        match res {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error()
+                .is_some_and(|e| e.is_conditional_check_failed_exception()) =>
+            {
+                Ok(false)
+            }
+            Err(e) => {
+                error!(?e, "disabling credential");
+                Err(Error::Storage("failed to disable credential"))
+            }
+        }
+    }
+
+    /// Soft-deletes a credential.
+    ///
+    /// The credential is also disabled so that it can no longer be used for
+    /// authentication, but keeps the original timestamp if it has already
+    /// been disabled. Increments the version.
+    /// Returns `false` if the credential does not exist or has already been
+    /// deleted.
+    pub async fn soft_delete_credential(
+        &self,
+        key: CredentialKey<'_>,
+        deleted_at: String,
+    ) -> Result<bool, Error> {
+        let res = self.dynamodb
+            .update_item()
+            .table_name(self.table_name.clone())
+            .set_key(Some(key.key()))
+            .update_expression("SET deletedAt = :deletedAt, disabledAt = if_not_exists(disabledAt, :deletedAt) ADD version :one")
+            .expression_attribute_values(":deletedAt", AttributeValue::S(deleted_at))
+            .expression_attribute_values(":one", AttributeValue::N("1".into()))
+            .condition_expression("attribute_exists(pk) AND attribute_not_exists(deletedAt)")
+            .return_values(ReturnValue::None)
+            .send()
+            .await;
+        match res {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error()
+                .is_some_and(|e| e.is_conditional_check_failed_exception()) =>
+            {
+                Ok(false)
+            }
+            Err(e) => {
+                error!(?e, "soft-deleting credential");
+                Err(Error::Storage("failed to delete credential"))
+            }
+        }
+    }
+
+    /// Restores a soft-deleted credential.
+    ///
+    /// Only a credential deleted at or after `cutoff` is restored. A
+    /// credential disabled before it was deleted, e.g., by an administrator,
+    /// is not restored, because restoring it would also enable it.
+    /// Increments the version.
+    /// Returns `false` if the credential is not restorable.
+    pub async fn restore_credential(
+        &self,
+        key: CredentialKey<'_>,
+        cutoff: String,
+    ) -> Result<bool, Error> {
+        let res = self.dynamodb
+            .update_item()
+            .table_name(self.table_name.clone())
+            .set_key(Some(key.key()))
+            .update_expression("REMOVE deletedAt, disabledAt ADD version :one")
+            .expression_attribute_values(":cutoff", AttributeValue::S(cutoff))
+            .expression_attribute_values(":one", AttributeValue::N("1".into()))
+            .condition_expression("deletedAt >= :cutoff AND disabledAt = deletedAt")
+            .return_values(ReturnValue::None)
+            .send()
+            .await;
+        match res {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error()
+                .is_some_and(|e| e.is_conditional_check_failed_exception()) =>
+            {
+                Ok(false)
+            }
+            Err(e) => {
+                error!(?e, "restoring credential");
+                Err(Error::Storage("failed to restore credential"))
+            }
+        }
+    }
+
+    /// Scans a page of credentials deleted before `cutoff`.
+    ///
+    /// `limit` is the maximum number of items evaluated in a page.
+    /// The filter applies after the limit, so a page may contain fewer
+    /// credentials even if it is not the last page.
+    pub async fn scan_deleted_credentials(
+        &self,
+        cutoff: String,
+        limit: i32,
+        exclusive_start_key: Option<HashMap<String, AttributeValue>>,
+    ) -> Result<CredentialPage, Error> {
+        let res = self.dynamodb
+            .scan()
+            .table_name(self.table_name.clone())
+            .filter_expression("begins_with(sk, :sk) AND deletedAt < :cutoff")
+            .expression_attribute_values(
+                ":sk",
+                AttributeValue::S(CREDENTIAL_SK_PREFIX.into()),
+            )
+            .expression_attribute_values(":cutoff", AttributeValue::S(cutoff))
+            .limit(limit)
+            .set_exclusive_start_key(exclusive_start_key)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "scanning deleted credentials");
+                Error::Storage("failed to scan deleted credentials")
+            })?;
+        Ok(CredentialPage {
+            credentials: self.open_credentials(res.items.unwrap_or_default()).await?,
+            last_evaluated_key: res.last_evaluated_key,
+        })
+    }
+
+    /// Purges a soft-deleted credential.
+    ///
+    /// Returns `false` unless the credential is still deleted at `deleted_at`;
+    /// i.e., it has been restored or deleted again in the meantime.
+    pub async fn purge_credential(
+        &self,
+        key: CredentialKey<'_>,
+        deleted_at: String,
+    ) -> Result<bool, Error> {
+        let res = self.dynamodb
+            .delete_item()
+            .table_name(self.table_name.clone())
+            .set_key(Some(key.key()))
+            .condition_expression("deletedAt = :deletedAt")
+            .expression_attribute_values(":deletedAt", AttributeValue::S(deleted_at))
+            .return_values(ReturnValue::None)
+            .send()
+            .await;
+        match res {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error()
+                .is_some_and(|e| e.is_conditional_check_failed_exception()) =>
+            {
+                Ok(false)
+            }
+            Err(e) => {
+                error!(?e, "purging credential");
+                Err(Error::Storage("failed to purge credential"))
+            }
+        }
+    }
+
+    /// Deletes a user and every item of the user in the credential table;
+    /// i.e., credentials and recovery codes.
+    ///
+    /// Items are sorted by the sort key, so the user item is deleted last, and
+    /// a failed deletion can be retried with the same user handle.
+    /// Returns the number of deleted items, which is zero if the user had
+    /// already been deleted.
+    pub async fn delete_user(&self, user_handle: &str) -> Result<usize, Error> {
+        let mut deleted = 0;
+        let mut exclusive_start_key = None;
+        loop {
+            let res = self.dynamodb
+                .query()
+                .table_name(self.table_name.clone())
+                .key_condition_expression("pk = :pk")
+                .expression_attribute_values(
+                    ":pk",
+                    AttributeValue::S(user_pk(user_handle)),
+                )
+                .projection_expression("pk, sk")
+                .consistent_read(true)
+                .set_exclusive_start_key(exclusive_start_key)
+                .send()
+                .await
+                .map_err(|e| {
+                    error!(?e, "querying items of user");
+                    Error::Storage("failed to list items of user")
+                })?;
+            for key in res.items.unwrap_or_default() {
+                self.dynamodb
+                    .delete_item()
+                    .table_name(self.table_name.clone())
+                    .set_key(Some(key))
+                    .send()
+                    .await
+                    .map_err(|e| {
+                        error!(?e, "deleting item of user");
+                        Error::Storage("failed to delete item of user")
+                    })?;
+                deleted += 1;
+            }
+            exclusive_start_key = res.last_evaluated_key;
+            if exclusive_start_key.is_none() {
+                return Ok(deleted);
+            }
+        }
+    }
+
+    /// Resolves the user handle of a given username and lists the credentials
+    /// of the user.
+    ///
+    /// Returns `None` if no credential is registered for the username.
+    pub async fn list_credentials_by_username(
+        &self,
+        username: &str,
+    ) -> Result<Option<(String, Vec<CredentialItem>)>, Error> {
+        match self.find_user_handle(username).await? {
+            Some(user_handle) => {
+                let credentials = self.list_credentials(&user_handle).await?;
+                Ok(Some((user_handle, credentials)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Obtains a user.
+    pub async fn get_user(&self, user_handle: &str) -> Result<Option<UserItem>, Error> {
+        let item = self.dynamodb
+            .get_item()
+            .table_name(self.table_name.clone())
+            .set_key(Some(UserItem::key(user_handle)))
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "getting user");
+                Error::Storage("failed to get user")
+            })?
+            .item;
+        match item {
+            Some(item) => Ok(Some(UserItem::from_item(&self.open(item).await?)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Adds a credential to an existing user.
+    ///
+    /// Returns `false` if the credential already exists.
+    pub async fn add_credential(&self, credential: CredentialItem) -> Result<bool, Error> {
+        let item = self.seal(vec![credential.into_item()]).await?;
+        let res = self.dynamodb
+            .put_item()
+            .table_name(self.table_name.clone())
+            .set_item(item.into_iter().next())
+            .condition_expression("attribute_not_exists(pk)")
+            .send()
+            .await;
+        match res {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error()
+                .is_some_and(|e| e.is_conditional_check_failed_exception()) =>
+            {
+                Ok(false)
+            }
+            Err(e) => {
+                error!(?e, "adding credential");
+                Err(Error::Storage("failed to add credential"))
+            }
+        }
+    }
+
+    /// Replaces the recovery codes of a user.
+    pub async fn put_recovery_codes(&self, codes: RecoveryCodesItem) -> Result<(), Error> {
+        self.dynamodb
+            .put_item()
+            .table_name(self.table_name.clone())
+            .set_item(Some(codes.into_item()))
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "putting recovery codes");
+                Error::Storage("failed to store recovery codes")
+            })?;
+        Ok(())
+    }
+
+    /// Consumes a recovery code of a user specified by the hash.
+    ///
+    /// Returns `false` if the code is not one of the unused codes.
+    pub async fn consume_recovery_code(
+        &self,
+        user_handle: &str,
+        code_hash: &str,
+    ) -> Result<bool, Error> {
+        let res = self.dynamodb
+            .update_item()
+            .table_name(self.table_name.clone())
+            .set_key(Some(RecoveryCodesItem::key(user_handle)))
+            .update_expression("DELETE codeHashes :codeHashes")
+            .condition_expression("contains(codeHashes, :codeHash)")
+            .expression_attribute_values(
+                ":codeHashes",
+                AttributeValue::Ss(vec![code_hash.into()]),
+            )
+            .expression_attribute_values(":codeHash", AttributeValue::S(code_hash.into()))
+            .return_values(ReturnValue::None)
+            .send()
+            .await;
+        match res {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error()
+                .is_some_and(|e| e.is_conditional_check_failed_exception()) =>
+            {
+                Ok(false)
+            }
+            Err(e) => {
+                error!(?e, "consuming recovery code");
+                Err(Error::Storage("failed to consume recovery code"))
+            }
+        }
+    }
+
+    /// Scans a page of users and credentials for an export.
+    pub async fn scan_records(
+        &self,
+        exclusive_start_key: Option<HashMap<String, AttributeValue>>,
+    ) -> Result<RecordPage, Error> {
+        let res = self.dynamodb
+            .scan()
+            .table_name(self.table_name.clone())
+            .consistent_read(true)
+            .set_exclusive_start_key(exclusive_start_key)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(?e, "scanning credential table");
+                Error::Storage("failed to scan credential table")
+            })?;
+        let mut records = Vec::with_capacity(res.items().len());
+        for item in res.items.unwrap_or_default() {
+            if let Some(record) = ExportRecord::from_item(&self.open(item).await?)? {
+                records.push(record);
+            }
+        }
+        Ok(RecordPage {
+            records,
+            last_evaluated_key: res.last_evaluated_key,
+        })
+    }
+
+    /// Imports a record.
+    ///
+    /// Returns `false` if a user or credential with the same key already
+    /// exists, in which case nothing is written.
+    pub async fn import_record(&self, record: ExportRecord) -> Result<bool, Error> {
+        let item = self.seal(vec![record.into_item()]).await?;
+        let res = self.dynamodb
+            .put_item()
+            .table_name(self.table_name.clone())
+            .set_item(item.into_iter().next())
+            .condition_expression("attribute_not_exists(pk)")
+            .send()
+            .await;
+        match res {
+            Ok(_) => Ok(true),
+            Err(e) if e.as_service_error()
+                .is_some_and(|e| e.is_conditional_check_failed_exception()) =>
+            {
+                Ok(false)
+            }
+            Err(e) => {
+                error!(?e, "importing record");
+                Err(Error::Storage("failed to import record"))
+            }
+        }
+    }
+
+    /// Creates a new user with the first credential.
+    ///
+    /// Writes both items in a single transaction; neither is written if the
+    /// user or the credential already exists.
+    pub async fn create_user(
+        &self,
+        user: UserItem,
+        credential: CredentialItem,
+    ) -> Result<(), CreateUserError> {
+        let put = |item| Put::builder()
+            .table_name(self.table_name.clone())
+            .set_item(Some(item))
+            .condition_expression("attribute_not_exists(pk)")
+            .build()
+            .map(|put| TransactWriteItem::builder().put(put).build())
+            .or(Err(Error::Storage("failed to build transaction")));
+        // the user and the first credential share a data key
+        let mut request = self.dynamodb.transact_write_items();
+        for item in self.seal(vec![user.into_item(), credential.into_item()]).await? {
+            request = request.transact_items(put(item)?);
+        }
+        let res = request.send().await;
+        match res {
+            Ok(_) => Ok(()),
+            Err(e) => match e.into_service_error() {
+                TransactWriteItemsError::TransactionCanceledException(e) => {
+                    let codes: Vec<Option<&str>> = e.cancellation_reasons()
+                        .iter()
+                        .map(|r| r.code())
+                        .collect();
+                    error!(?codes, "user creation canceled");
+                    Err(cancellation_error(&codes))
+                }
+                e => {
+                    error!(?e, "creating user");
+                    Err(Error::Storage("failed to create user").into())
+                }
+            },
+        }
+    }
+
+    /// Changes the username of a user.
+    ///
+    /// `username` must be normalized and qualified with the tenant ID if
+    /// tenants are configured.
+    /// Succeeds without any update if the user already has the username.
+    /// Fails with [`RenameUserError::UsernameTaken`] if another user has the
+    /// username. The check relies on the username index, which is eventually
+    /// consistent, so two users renamed to the same username at the same
+    /// moment may not be detected.
+    pub async fn rename_user(
+        &self,
+        user_handle: &str,
+        username: &str,
+    ) -> Result<(), RenameUserError> {
+        if self.find_user_handle(username)
+            .await?
+            .is_some_and(|other| other != user_handle)
+        {
+            return Err(RenameUserError::UsernameTaken);
+        }
+        let user = self.get_user(user_handle)
+            .await?
+            .ok_or(RenameUserError::UserNotFound)?;
+        if user.username == username {
+            return Ok(());
+        }
+        let credentials = self.list_credentials(user_handle).await?;
+        if credentials.len() + 1 > MAX_TRANSACTION_ITEMS {
+            return Err(Error::Storage("too many credentials to rename").into());
+        }
+        // the display name is sealed again under the same data key as the
+        // new username
+        let mut user_attributes = UserItem::key(user_handle);
+        user_attributes.insert("username".into(), AttributeValue::S(username.into()));
+        if self.pii.is_some() {
+            user_attributes.insert(
+                "displayName".into(),
+                AttributeValue::S(user.display_name.clone()),
+            );
+        }
+        let mut renamed = vec![user_attributes];
+        for credential in &credentials {
+            let mut attributes = credential.key().key();
+            attributes.insert("username".into(), AttributeValue::S(username.into()));
+            renamed.push(attributes);
+        }
+        let mut renamed = self.seal(renamed).await?.into_iter();
+        let old_username = self.username_attribute(&user.username).await?;
+        // the user item must not have been renamed concurrently, while
+        // credential items must merely exist
+        let update = |mut attributes: Item, old_username: Option<&str>| {
+            let key: HashMap<_, _> = ["pk", "sk"].into_iter()
+                .filter_map(|name| attributes.remove_entry(name))
+                .collect();
+            let mut names: Vec<&String> = attributes.keys().collect();
+            names.sort();
+            let update_expression = format!(
+                "SET {}",
+                names.iter()
+                    .map(|name| format!("{0} = :{0}", name))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            );
+            let mut values: HashMap<String, AttributeValue> = attributes.into_iter()
+                .map(|(name, value)| (format!(":{}", name), value))
+                .collect();
+            let condition = match old_username {
+                Some(old_username) => {
+                    values.insert(
+                        ":oldUsername".into(),
+                        AttributeValue::S(old_username.into()),
+                    );
+                    "username = :oldUsername"
+                }
+                None => "attribute_exists(pk)",
+            };
+            Update::builder()
+                .table_name(self.table_name.clone())
+                .set_key(Some(key))
+                .update_expression(update_expression)
+                .condition_expression(condition)
+                .set_expression_attribute_values(Some(values))
+                .build()
+                .map(|update| TransactWriteItem::builder().update(update).build())
+                .or(Err(Error::Storage("failed to build transaction")))
+        };
+        let user_attributes = renamed.next()
+            .ok_or(Error::Storage("no renamed user"))?;
+        let mut request = self.dynamodb
+            .transact_write_items()
+            .transact_items(update(user_attributes, Some(old_username.as_str()))?);
+        for attributes in renamed {
+            request = request.transact_items(update(attributes, None)?);
+        }
+        match request.send().await {
+            Ok(_) => {
+                info!("renamed user {}", redact(user_handle));
+                Ok(())
+            }
+            Err(e) => match e.into_service_error() {
+                TransactWriteItemsError::TransactionCanceledException(e) => {
+                    let codes: Vec<Option<&str>> = e.cancellation_reasons()
+                        .iter()
+                        .map(|r| r.code())
+                        .collect();
+                    error!(?codes, "user rename canceled");
+                    Err(RenameUserError::Conflict)
+                }
+                e => {
+                    error!(?e, "renaming user");
+                    Err(Error::Storage("failed to rename user").into())
+                }
+            },
+        }
+    }
+
+    // returns the value of the `username` attribute of a given username;
+    // i.e., the index if PII is protected.
+    async fn username_attribute(&self, username: &str) -> Result<String, Error> {
+        match self.pii.as_ref() {
+            Some(pii) => pii.index(username).await,
+            None => Ok(username.into()),
+        }
+    }
+
+    // seals given items if PII is protected.
+    async fn seal(&self, items: Vec<Item>) -> Result<Vec<Item>, Error> {
+        match self.pii.as_ref() {
+            Some(pii) => pii.seal_items(items).await,
+            None => Ok(items),
+        }
+    }
+
+    // opens a given item if PII is protected.
+    //
+    // fails if the item is sealed but PII is not protected.
+    async fn open(&self, item: Item) -> Result<Item, Error> {
+        match self.pii.as_ref() {
+            Some(pii) => pii.open_item(item).await,
+            None if is_sealed(&item) => Err(Error::Encryption("PII sealed but not protected")),
+            None => Ok(item),
+        }
+    }
+
+    // opens and parses given credential items.
+    async fn open_credentials(
+        &self,
+        items: Vec<Item>,
+    ) -> Result<Vec<CredentialItem>, Error> {
+        let mut credentials = Vec::with_capacity(items.len());
+        for item in items {
+            credentials.push(CredentialItem::from_item(&self.open(item).await?)?);
+        }
+        Ok(credentials)
+    }
+}
+
+// maps the cancellation reasons of the transaction in `create_user` to an
+// error.
+//
+// `codes` are in the order of the user and the credential.
+fn cancellation_error(codes: &[Option<&str>]) -> CreateUserError {
+    match codes {
+        [Some("ConditionalCheckFailed"), ..] => CreateUserError::UserExists,
+        [_, Some("ConditionalCheckFailed"), ..] => CreateUserError::CredentialExists,
+        _ if codes.contains(&Some("TransactionConflict")) => CreateUserError::Conflict,
+        _ => CreateUserError::Other(Error::Storage("user creation canceled")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn credential_filter_expression_should_combine_conditions() {
+        assert_eq!(CredentialFilter::default().expression(), None);
+        let filter = CredentialFilter {
+            credential_type: Some("passkey".into()),
+            backup_state: Some(true),
+        };
+        let (expression, values) = filter.expression().unwrap();
+        assert_eq!(
+            expression,
+            "credentialType = :credentialType AND backupState = :backupState",
+        );
+        assert_eq!(values, vec![
+            (":credentialType", AttributeValue::S("passkey".into())),
+            (":backupState", AttributeValue::Bool(true)),
+        ]);
+    }
+
+    #[test]
+    fn cancellation_error_should_tell_which_item_exists() {
+        assert!(matches!(
+            cancellation_error(&[Some("ConditionalCheckFailed"), Some("None")]),
+            CreateUserError::UserExists,
+        ));
+        assert!(matches!(
+            cancellation_error(&[Some("None"), Some("ConditionalCheckFailed")]),
+            CreateUserError::CredentialExists,
+        ));
+    }
+
+    #[test]
+    fn cancellation_error_should_tell_conflict() {
+        assert!(matches!(
+            cancellation_error(&[Some("TransactionConflict"), None]),
+            CreateUserError::Conflict,
+        ));
+        assert!(matches!(
+            cancellation_error(&[Some("ThrottlingError"), None]),
+            CreateUserError::Other(_),
+        ));
+    }
+}
+
@@ -0,0 +1,121 @@
+//! Warm-up events.
+//!
+//! A keep-warm rule periodically invokes a function to keep its execution
+//! environment alive. The following payloads are warm-up events:
+//! - a scheduled event of Amazon EventBridge; i.e., `source` is "aws.events"
+//!   and `detail-type` is "Scheduled Event"
+//! - `{ "warmer": true }`
+//!
+//! [`run_with_warmer`] answers a warm-up event with 200 before it reaches the
+//! handler, so a warm-up neither touches DynamoDB nor logs an error about an
+//! unsupported path. Any other payload is handled as an HTTP request as
+//! [`lambda_http::run`] does, with the correlation of the request; see
+//! [`crate::correlation`].
+
+use lambda_http::{
+    Body,
+    Request,
+    RequestExt,
+    Response,
+    request::LambdaRequest,
+    response::LambdaResponse,
+};
+use lambda_runtime::{LambdaEvent, service_fn};
+use serde_json::{Value, json};
+use std::future::Future;
+use std::time::Instant;
+use tracing::info;
+
+use crate::correlation::{correlation_of, set_request_id_header, with_correlation};
+use crate::metrics::{ColdStart, Metrics};
+
+/// Returns whether a given payload is a warm-up event.
+pub fn is_warmer_event(payload: &Value) -> bool {
+    let is_scheduled_event = payload["source"] == "aws.events"
+        && payload["detail-type"] == "Scheduled Event";
+    is_scheduled_event || payload["warmer"] == true
+}
+
+/// Runs a Lambda function that serves HTTP requests and warm-up events.
+///
+/// `handler` serves HTTP requests. A warm-up event is answered with
+/// `{ "statusCode": 200 }` without calling `handler`. If a warm-up event is
+/// the first invocation, it reports the cold start, so the next request is
+/// not reported as one.
+///
+/// `handler` runs with the correlation of the request, and the response has
+/// the correlation ID in the `X-Request-Id` header. See
+/// [`crate::correlation`].
+pub async fn run_with_warmer<F, Fut>(
+    cold_start: &ColdStart,
+    metrics: &Metrics,
+    handler: F,
+) -> Result<(), lambda_http::Error>
+where
+    F: Fn(Request) -> Fut,
+    Fut: Future<Output = Result<Response<Body>, lambda_http::Error>>,
+{
+    lambda_runtime::run(service_fn(|event: LambdaEvent<Value>| async {
+        let (payload, context) = event.into_parts();
+        if is_warmer_event(&payload) {
+            let started_at = Instant::now();
+            info!("warm-up event");
+            cold_start.report(metrics, started_at.elapsed());
+            return Ok(json!({ "statusCode": 200 }));
+        }
+        let request: LambdaRequest = serde_json::from_value(payload)?;
+        let request_origin = request.request_origin();
+        let request = Request::from(request).with_lambda_context(context);
+        let correlation = correlation_of(&request);
+        let mut response = with_correlation(correlation.clone(), handler(request)).await?;
+        set_request_id_header(&mut response, &correlation);
+        let response = LambdaResponse::from_response(&request_origin, response);
+        Ok::<_, lambda_http::Error>(serde_json::to_value(response)?)
+    })).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_warmer_event_should_accept_scheduled_event() {
+        assert!(is_warmer_event(&json!({
+            "version": "0",
+            "id": "53dc4d37-cffa-4f76-80c9-8b7d4a4d2eaa",
+            "detail-type": "Scheduled Event",
+            "source": "aws.events",
+            "account": "123456789012",
+            "time": "2024-10-08T12:00:00Z",
+            "region": "ap-northeast-1",
+            "resources": [],
+            "detail": {},
+        })));
+        assert!(!is_warmer_event(&json!({
+            "detail-type": "Object Created",
+            "source": "aws.s3",
+        })));
+    }
+
+    #[test]
+    fn is_warmer_event_should_accept_warmer_flag() {
+        assert!(is_warmer_event(&json!({ "warmer": true })));
+        assert!(!is_warmer_event(&json!({ "warmer": false })));
+    }
+
+    #[test]
+    fn is_warmer_event_should_reject_http_request() {
+        assert!(!is_warmer_event(&json!({
+            "version": "2.0",
+            "routeKey": "POST /auth/credentials/registration/{proxy+}",
+            "rawPath": "/auth/credentials/registration/start",
+            "requestContext": {
+                "http": {
+                    "method": "POST",
+                    "path": "/auth/credentials/registration/start",
+                },
+            },
+            "body": "{}",
+        })));
+    }
+}
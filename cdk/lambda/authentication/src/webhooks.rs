@@ -0,0 +1,266 @@
+//! Outbound webhooks.
+//!
+//! Integrators who cannot consume EventBridge can receive registered passkeys
+//! and deleted credentials as HTTP POST requests to a webhook URL. The body is
+//! a JSON [`WebhookPayload`] whose `data` is the detail of the
+//! [`DomainEvent`].
+//!
+//! ## Signature
+//!
+//! Every request has the `X-Webhook-Signature` header in the form of
+//! `t=<timestamp>,v1=<signature>`, where `<timestamp>` is the Unix time in
+//! seconds when the request is signed, and `<signature>` is the hex-encoded
+//! HMAC-SHA256 of `<timestamp>.<body>` keyed with the webhook secret.
+//! Receivers should recompute the signature and reject a stale timestamp.
+//!
+//! ## Retries
+//!
+//! A request that fails to connect, times out, or gets a 429 or 5xx response
+//! is retried up to [`MAX_ATTEMPTS`] times in total, waiting
+//! [`INITIAL_BACKOFF`] and doubling the wait after every attempt. Delivery is
+//! best effort like [`crate::domain_events`]: a failure is logged but does not
+//! fail the request.
+
+use base64::{
+    Engine as _,
+    engine::general_purpose::{URL_SAFE_NO_PAD as base64url},
+};
+use ring::{
+    hmac,
+    rand::{SecureRandom, SystemRandom},
+};
+use serde::Serialize;
+use std::env;
+use std::time::{Duration, SystemTime};
+use tracing::{error, info, warn};
+
+use crate::config;
+use crate::domain_events::DomainEvent;
+use crate::error::Error;
+use crate::secrets::{SecretCache, load_secret_cache_ttl};
+
+/// Name of the header that carries the signature.
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+
+/// Maximum number of attempts to deliver a request.
+pub const MAX_ATTEMPTS: u32 = 3;
+
+/// Wait before the first retry.
+pub const INITIAL_BACKOFF: Duration = Duration::from_millis(250);
+
+// timeout of each attempt, which keeps the retries within the timeout of the
+// function.
+const ATTEMPT_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Body of a webhook request.
+#[derive(Clone, Debug, Serialize)]
+pub struct WebhookPayload<'a> {
+    /// Unique ID of the delivery.
+    ///
+    /// Retries of the same delivery have the same ID, so that receivers can
+    /// deduplicate them.
+    pub id: String,
+
+    /// Type of the event; the detail type of the [`DomainEvent`].
+    #[serde(rename = "type")]
+    pub event_type: &'static str,
+
+    /// Unix time in seconds when the event occurred.
+    pub timestamp: i64,
+
+    /// Detail of the event.
+    pub data: &'a DomainEvent,
+}
+
+/// Notifier that delivers events to a webhook URL.
+pub struct WebhookNotifier {
+    http: reqwest::Client,
+    url: reqwest::Url,
+    secret_id: String,
+    secrets: SecretCache,
+}
+
+/// Loads the webhook configuration.
+///
+/// You can specify to the following environment variables:
+/// - `WEBHOOK_URL`: URL to which events are posted
+/// - `WEBHOOK_SECRET_ID`: ID of the secret in Secrets Manager that signs the
+///   requests. Mandatory if `WEBHOOK_URL` is set.
+///
+/// Returns `None` if `WEBHOOK_URL` is not set, which means no webhooks are
+/// delivered.
+pub fn load_webhook_notifier(
+    secrets: aws_sdk_secretsmanager::Client,
+) -> Result<Option<WebhookNotifier>, Error> {
+    let url = match config::var("WEBHOOK_URL") {
+        Ok(url) => reqwest::Url::parse(&url)
+            .ok()
+            .filter(|u| u.scheme() == "https" || u.scheme() == "http")
+            .ok_or(Error::BadEnvironmentVariable("WEBHOOK_URL", url))?,
+        Err(env::VarError::NotPresent) => return Ok(None),
+        Err(env::VarError::NotUnicode(url)) => return Err(
+            Error::BadEnvironmentVariable(
+                "WEBHOOK_URL",
+                url.to_string_lossy().into(),
+            ),
+        ),
+    };
+    let secret_id = config::var("WEBHOOK_SECRET_ID")
+        .ok()
+        .filter(|id| !id.is_empty())
+        .ok_or(Error::BadEnvironmentVariable("WEBHOOK_SECRET_ID", "".into()))?;
+    let http = reqwest::Client::builder()
+        .timeout(ATTEMPT_TIMEOUT)
+        .build()
+        .or(Err(Error::Webhook("failed to build HTTP client")))?;
+    Ok(Some(WebhookNotifier {
+        http,
+        url,
+        secret_id,
+        secrets: SecretCache::new(secrets, load_secret_cache_ttl()?),
+    }))
+}
+
+impl WebhookNotifier {
+    /// Delivers an event.
+    ///
+    /// Retries as described in the [module documentation](self).
+    pub async fn deliver(&self, event: &DomainEvent) -> Result<(), Error> {
+        let body = serde_json::to_string(&WebhookPayload {
+            id: new_delivery_id()?,
+            event_type: event.detail_type(),
+            timestamp: unix_time(),
+            data: event,
+        }).or(Err(Error::Webhook("failed to serialize payload")))?;
+        let secret = self.secrets.get(&self.secret_id).await?;
+        let mut backoff = INITIAL_BACKOFF;
+        for attempt in 1..=MAX_ATTEMPTS {
+            let signature = sign(secret.as_bytes(), unix_time(), &body);
+            let status = match self.http
+                .post(self.url.clone())
+                .header("Content-Type", "application/json")
+                .header(SIGNATURE_HEADER, signature)
+                .body(body.clone())
+                .send()
+                .await
+            {
+                Ok(res) if res.status().is_success() => {
+                    info!("delivered {} in {} attempt(s)", event.detail_type(), attempt);
+                    return Ok(());
+                }
+                Ok(res) => Some(res.status().as_u16()),
+                Err(e) => {
+                    warn!(?e, "posting webhook");
+                    None
+                }
+            };
+            if !is_retryable(status) {
+                error!(?status, "webhook rejected {}", event.detail_type());
+                return Err(Error::Webhook("webhook rejected"));
+            }
+            if attempt < MAX_ATTEMPTS {
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+            }
+        }
+        Err(Error::Webhook("webhook unreachable"))
+    }
+}
+
+/// Delivers an event if a webhook is configured.
+///
+/// A failure is logged and ignored.
+pub async fn notify_webhook(notifier: Option<&WebhookNotifier>, event: &DomainEvent) {
+    if let Some(notifier) = notifier {
+        if let Err(e) = notifier.deliver(event).await {
+            error!("failed to deliver {}: {}", event.detail_type(), e);
+        }
+    }
+}
+
+/// Signs a body at a given Unix time.
+///
+/// Returns the value of the [`SIGNATURE_HEADER`].
+pub fn sign(secret: &[u8], timestamp: i64, body: &str) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+    let tag = hmac::sign(&key, format!("{}.{}", timestamp, body).as_bytes());
+    let signature: String = tag.as_ref()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    format!("t={},v1={}", timestamp, signature)
+}
+
+// returns whether an attempt that ended with a given status code is retried.
+//
+// `None` means that no response was received.
+fn is_retryable(status: Option<u16>) -> bool {
+    match status {
+        None => true,
+        Some(status) => status == 429 || (500..600).contains(&status),
+    }
+}
+
+fn new_delivery_id() -> Result<String, Error> {
+    let mut id = [0u8; 16];
+    SystemRandom::new().fill(&mut id)
+        .or(Err(Error::Webhook("failed to generate delivery ID")))?;
+    Ok(base64url.encode(id))
+}
+
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::domain_events::PasskeyRegistered;
+
+    #[test]
+    fn sign_should_compute_hmac_sha256_of_timestamp_and_body() {
+        assert_eq!(
+            sign(b"secret", 1700000000, r#"{"id":"abc"}"#),
+            "t=1700000000,v1=5ad265e6615b64b835cae994e1526056136c85c5a0d090d4f35b730288b456de",
+        );
+    }
+
+    #[test]
+    fn is_retryable_should_retry_server_errors_only() {
+        assert!(is_retryable(None));
+        assert!(is_retryable(Some(429)));
+        assert!(is_retryable(Some(503)));
+        assert!(!is_retryable(Some(400)));
+        assert!(!is_retryable(Some(410)));
+    }
+
+    #[test]
+    fn webhook_payload_should_carry_event_detail_as_data() {
+        let event = DomainEvent::PasskeyRegistered(PasskeyRegistered {
+            user_handle: "user".into(),
+            credential_id: "abc".into(),
+            tenant_id: None,
+            registration: "passkey",
+        });
+        let payload = serde_json::to_value(WebhookPayload {
+            id: "delivery".into(),
+            event_type: event.detail_type(),
+            timestamp: 1700000000,
+            data: &event,
+        }).unwrap();
+        assert_eq!(payload, serde_json::json!({
+            "id": "delivery",
+            "type": "PasskeyRegistered",
+            "timestamp": 1700000000,
+            "data": {
+                "userHandle": "user",
+                "credentialId": "abc",
+                "registration": "passkey",
+            },
+        }));
+    }
+}
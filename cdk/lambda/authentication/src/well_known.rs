@@ -0,0 +1,218 @@
+//! Association of native apps with the relying party.
+//!
+//! Native apps can use the passkeys of the relying party only if the domain
+//! of the relying party ID associates itself with the apps through the
+//! following files:
+//! - [`ASSET_LINKS_PATH`]: Digital Asset Links for Android apps
+//! - [`APPLE_APP_SITE_ASSOCIATION_PATH`]: associated domains for Apple apps
+//!
+//! The files are generated from the configuration loaded with
+//! [`load_app_association`], so that no separate static hosting is needed.
+
+use serde::Serialize;
+use std::env;
+
+use crate::android::{ApkKeyHash, load_apk_key_hashes};
+use crate::config;
+use crate::error::Error;
+
+/// Path of the Digital Asset Links.
+pub const ASSET_LINKS_PATH: &str = "/.well-known/assetlinks.json";
+
+/// Path of the Apple App Site Association.
+pub const APPLE_APP_SITE_ASSOCIATION_PATH: &str =
+    "/.well-known/apple-app-site-association";
+
+// relations granted to Android apps; sign-in with shared credentials.
+const ASSET_LINKS_RELATIONS: &[&str] = &[
+    "delegate_permission/common.handle_all_urls",
+    "delegate_permission/common.get_login_creds",
+];
+
+/// Native apps associated with the relying party.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct AppAssociation {
+    /// Package names of the Android apps.
+    pub android_package_names: Vec<String>,
+
+    /// Hashes of the certificates that sign the Android apps.
+    pub apk_key_hashes: Vec<ApkKeyHash>,
+
+    /// App IDs of the Apple apps; "<team ID>.<bundle ID>".
+    pub apple_app_ids: Vec<String>,
+}
+
+/// Statement of the Digital Asset Links.
+#[derive(Clone, Debug, Serialize)]
+pub struct AssetLinksStatement {
+    /// Relations granted to the target.
+    pub relation: &'static [&'static str],
+
+    /// Target app.
+    pub target: AssetLinksTarget,
+}
+
+/// Android app in the Digital Asset Links.
+#[derive(Clone, Debug, Serialize)]
+pub struct AssetLinksTarget {
+    /// Always "android_app".
+    pub namespace: &'static str,
+
+    /// Package name.
+    pub package_name: String,
+
+    /// Fingerprints of the signing certificates.
+    pub sha256_cert_fingerprints: Vec<String>,
+}
+
+/// Apple App Site Association.
+#[derive(Clone, Debug, Serialize)]
+pub struct AppleAppSiteAssociation {
+    /// Apps that share web credentials.
+    pub webcredentials: WebCredentials,
+}
+
+/// `webcredentials` of the Apple App Site Association.
+#[derive(Clone, Debug, Serialize)]
+pub struct WebCredentials {
+    /// App IDs.
+    pub apps: Vec<String>,
+}
+
+/// Loads the native apps associated with the relying party.
+///
+/// You can specify to the following environment variables comma-separated
+/// lists:
+/// - `ANDROID_PACKAGE_NAMES`: package names of the Android apps
+/// - `ANDROID_APK_KEY_HASHES`: hashes of the certificates that sign the
+///   Android apps; see [`load_apk_key_hashes`]
+/// - `APPLE_APP_IDS`: app IDs of the Apple apps; e.g.,
+///   "ABCDE12345.com.example.app"
+///
+/// An empty list means that no apps of the platform are associated.
+pub fn load_app_association() -> Result<AppAssociation, Error> {
+    Ok(AppAssociation {
+        android_package_names: load_list("ANDROID_PACKAGE_NAMES")?,
+        apk_key_hashes: load_apk_key_hashes()?,
+        apple_app_ids: load_list("APPLE_APP_IDS")?,
+    })
+}
+
+impl AppAssociation {
+    /// Generates the Digital Asset Links.
+    ///
+    /// Every package is associated with every signing certificate.
+    ///
+    /// Returns `None` if no Android apps are configured.
+    pub fn asset_links(&self) -> Option<Vec<AssetLinksStatement>> {
+        if self.android_package_names.is_empty() || self.apk_key_hashes.is_empty() {
+            return None;
+        }
+        let fingerprints: Vec<String> = self.apk_key_hashes.iter()
+            .map(ApkKeyHash::fingerprint)
+            .collect();
+        Some(self.android_package_names.iter()
+            .map(|package_name| AssetLinksStatement {
+                relation: ASSET_LINKS_RELATIONS,
+                target: AssetLinksTarget {
+                    namespace: "android_app",
+                    package_name: package_name.clone(),
+                    sha256_cert_fingerprints: fingerprints.clone(),
+                },
+            })
+            .collect())
+    }
+
+    /// Generates the Apple App Site Association.
+    ///
+    /// Returns `None` if no Apple apps are configured.
+    pub fn apple_app_site_association(&self) -> Option<AppleAppSiteAssociation> {
+        (!self.apple_app_ids.is_empty()).then(|| AppleAppSiteAssociation {
+            webcredentials: WebCredentials {
+                apps: self.apple_app_ids.clone(),
+            },
+        })
+    }
+}
+
+// loads a comma-separated list from an environment variable.
+//
+// empty if the environment variable is not set.
+fn load_list(name: &'static str) -> Result<Vec<String>, Error> {
+    match config::var(name) {
+        Ok(list) => Ok(parse_list(&list)),
+        Err(env::VarError::NotPresent) => Ok(Vec::new()),
+        Err(env::VarError::NotUnicode(list)) => Err(
+            Error::BadEnvironmentVariable(name, list.to_string_lossy().into()),
+        ),
+    }
+}
+
+fn parse_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash() -> ApkKeyHash {
+        ApkKeyHash::parse("FG3pg8VzBlDY7rmVLzT8ZBagg0LmHb6oigSWsj_PROU").unwrap()
+    }
+
+    #[test]
+    fn asset_links_should_associate_packages_with_fingerprints() {
+        let association = AppAssociation {
+            android_package_names: vec!["com.example.app".into()],
+            apk_key_hashes: vec![hash()],
+            ..Default::default()
+        };
+        let asset_links = serde_json::to_value(association.asset_links().unwrap())
+            .unwrap();
+        assert_eq!(asset_links, serde_json::json!([
+            {
+                "relation": [
+                    "delegate_permission/common.handle_all_urls",
+                    "delegate_permission/common.get_login_creds",
+                ],
+                "target": {
+                    "namespace": "android_app",
+                    "package_name": "com.example.app",
+                    "sha256_cert_fingerprints": [hash().fingerprint()],
+                },
+            },
+        ]));
+    }
+
+    #[test]
+    fn asset_links_should_be_none_without_packages_or_hashes() {
+        assert!(AppAssociation::default().asset_links().is_none());
+        let association = AppAssociation {
+            android_package_names: vec!["com.example.app".into()],
+            ..Default::default()
+        };
+        assert!(association.asset_links().is_none());
+    }
+
+    #[test]
+    fn apple_app_site_association_should_list_web_credentials_apps() {
+        assert!(AppAssociation::default().apple_app_site_association().is_none());
+        let association = AppAssociation {
+            apple_app_ids: parse_list("ABCDE12345.com.example.app, "),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_value(association.apple_app_site_association().unwrap())
+                .unwrap(),
+            serde_json::json!({
+                "webcredentials": {
+                    "apps": ["ABCDE12345.com.example.app"],
+                },
+            }),
+        );
+    }
+}
@@ -0,0 +1,104 @@
+import { RemovalPolicy, aws_dynamodb as dynamodb, aws_iam as iam } from 'aws-cdk-lib';
+import { Construct } from 'constructs';
+
+/** CDK construct that provisions the DynamoDB table for the audit log. */
+export class AuditLog extends Construct {
+    /**
+     * DynamoDB table that stores audit events.
+     *
+     * ## Keys and attributes
+     *
+     * - Partition key: `pk`
+     * - Sort key: `sk`
+     *
+     * Global secondary index `EventDateIndex`:
+     * - Partition key: `eventDate`
+     * - Sort key: `sk`
+     *
+     * ### Audit event
+     *
+     * - `pk`: "user#<user handle>"
+     * - `sk`: "event#<timestamp>#<event ID>"
+     * - `eventId`: unique ID of the event
+     * - `eventType`: "credential_registered", "credential_deleted", or
+     *   "authentication_failed"
+     * - `eventDate`: "<yyyy-mm-dd>" when the event occurred
+     * - `userHandle`: user handle of the user concerned
+     * - `timestamp`: "<yyyy-mm-ddTHH:MM:SS.SSSSSSZ>" when the event occurred
+     * - `credentialId`: (optional) "base64url"-encoded ID of the credential
+     *   concerned
+     * - `sourceIp`: (optional) source IP address of the client
+     * - `userAgent`: (optional) user agent of the client
+     * - `detail`: (optional) additional detail; e.g., the reason of an
+     *   authentication failure
+     * - `syntheticLabel`: (optional) label of synthetic traffic; e.g., the
+     *   name of a red-team scenario
+     */
+    readonly auditTable: dynamodb.TableV2;
+
+    constructor(scope: Construct, id: string) {
+        super(scope, id);
+
+        this.auditTable = new dynamodb.TableV2(this, 'AuditTable', {
+            partitionKey: {
+                name: 'pk',
+                type: dynamodb.AttributeType.STRING,
+            },
+            sortKey: {
+                name: 'sk',
+                type: dynamodb.AttributeType.STRING,
+            },
+            globalSecondaryIndexes: [
+                {
+                    indexName: 'EventDateIndex',
+                    partitionKey: {
+                        name: 'eventDate',
+                        type: dynamodb.AttributeType.STRING,
+                    },
+                    sortKey: {
+                        name: 'sk',
+                        type: dynamodb.AttributeType.STRING,
+                    },
+                    projectionType: dynamodb.ProjectionType.ALL,
+                },
+            ],
+            // every authentication and registration appends events, so
+            // provisioned capacity would throttle bursts of sign-ins
+            billing: dynamodb.Billing.onDemand(),
+            pointInTimeRecovery: true,
+            removalPolicy: RemovalPolicy.RETAIN,
+        });
+    }
+
+    /**
+     * Grants a given principal permission to append events.
+     *
+     * @remarks
+     *
+     * Only `PutItem` is granted so that the principal can neither update nor
+     * delete recorded events through other actions.
+     *
+     * Append-only is *not* enforced by IAM: `PutItem` replaces an existing
+     * item unless the request has a condition, and no IAM condition key can
+     * require one. The Lambda functions never overwrite events, because they
+     * put events with `attribute_not_exists(pk)` under random event IDs, but a
+     * compromised principal could overwrite an event whose key it knows.
+     * Point-in-time recovery of the table restores an overwritten event.
+     */
+    grantAppend(grantee: iam.IGrantable): iam.Grant {
+        return this.auditTable.grant(grantee, 'dynamodb:PutItem');
+    }
+
+    /**
+     * Grants a given principal permission to purge events whose retention
+     * period has elapsed.
+     *
+     * @remarks
+     *
+     * Only the retention job should be granted, because the principal can
+     * delete any event.
+     */
+    grantPurge(grantee: iam.IGrantable): iam.Grant {
+        return this.auditTable.grant(grantee, 'dynamodb:Scan', 'dynamodb:DeleteItem');
+    }
+}
@@ -0,0 +1,101 @@
+import * as path from 'node:path';
+import {
+    Duration,
+    RemovalPolicy,
+    aws_dynamodb as dynamodb,
+    aws_events as events,
+    aws_events_targets as targets,
+    aws_lambda as lambda,
+} from 'aws-cdk-lib';
+import { RustFunction } from 'cargo-lambda-cdk';
+import { Construct } from 'constructs';
+
+import type { Parameters } from './parameters';
+
+/** Props for `AuthenticatorMetadata`. */
+export interface AuthenticatorMetadataProps {
+    /** Parameters in Parameter Store on AWS Systems Manager. */
+    readonly parameters: Parameters;
+}
+
+/**
+ * CDK construct that provisions the metadata of authenticators in the FIDO
+ * Metadata Service.
+ *
+ * @remarks
+ *
+ * A scheduled Lambda function downloads and verifies the BLOB of the FIDO
+ * Metadata Service every day, and stores the entries in a DynamoDB table.
+ * The BLOB is verified with the root certificate in
+ * {@link Parameters.mdsRootCertificateParameter}.
+ */
+export class AuthenticatorMetadata extends Construct {
+    /**
+     * DynamoDB table that stores the entries of the BLOB.
+     *
+     * ## Keys and attributes
+     *
+     * - Partition key: `aaguid`
+     *
+     * ### Authenticator
+     *
+     * - `aaguid`: AAGUID of the authenticator in the hyphenated lowercase
+     *   form
+     * - `description`: (optional) description of the authenticator in English
+     * - `status`: (optional) latest status of the authenticator; e.g.,
+     *   "FIDO_CERTIFIED_L1" or "REVOKED"
+     * - `effectiveDate`: (optional) "<yyyy-mm-dd>" since when `status` has
+     *   been effective
+     * - `blobNumber`: serial number of the BLOB that provided the entry
+     *
+     * ### BLOB
+     *
+     * - `aaguid`: "#blob"
+     * - `blobNumber`: serial number of the BLOB last stored
+     * - `nextUpdate`: "<yyyy-mm-dd>" when the next BLOB is published
+     * - `updatedAt`: "<yyyy-mm-ddTHH:MM:SS.SSSSSSZ>" when the BLOB was stored
+     */
+    readonly metadataTable: dynamodb.TableV2;
+
+    /** Lambda function that refreshes the metadata. */
+    readonly refreshLambda: lambda.IFunction;
+
+    constructor(scope: Construct, id: string, props: AuthenticatorMetadataProps) {
+        super(scope, id);
+
+        const { parameters } = props;
+
+        this.metadataTable = new dynamodb.TableV2(this, 'MetadataTable', {
+            partitionKey: {
+                name: 'aaguid',
+                type: dynamodb.AttributeType.STRING,
+            },
+            billing: dynamodb.Billing.onDemand(),
+            removalPolicy: RemovalPolicy.DESTROY,
+        });
+
+        this.refreshLambda = new RustFunction(this, 'RefreshLambda', {
+            manifestPath: path.join('lambda', 'authentication', 'Cargo.toml'),
+            binaryName: 'mds-refresh',
+            architecture: lambda.Architecture.ARM_64,
+            environment: {
+                METADATA_TABLE_NAME: this.metadataTable.tableName,
+                MDS_ROOT_CERTIFICATE_PARAMETER_PATH: parameters.mdsRootCertificateParameter.parameterName,
+                CONFIG_PARAMETER_PATH: parameters.configParameterPath,
+            },
+            // the BLOB is a few megabytes
+            memorySize: 256,
+            timeout: Duration.minutes(2),
+            tracing: lambda.Tracing.ACTIVE,
+        });
+        parameters.mdsRootCertificateParameter.grantRead(this.refreshLambda);
+        parameters.grantReadConfig(this.refreshLambda);
+        this.metadataTable.grantReadWriteData(this.refreshLambda);
+
+        new events.Rule(this, 'RefreshSchedule', {
+            description: 'Refreshes the FIDO Metadata Service BLOB',
+            schedule: events.Schedule.rate(Duration.days(1)),
+            targets: [new targets.LambdaFunction(this.refreshLambda)],
+        });
+    }
+}
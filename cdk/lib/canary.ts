@@ -0,0 +1,81 @@
+import * as path from 'node:path';
+import {
+    Duration,
+    aws_events as events,
+    aws_events_targets as targets,
+    aws_lambda as lambda,
+} from 'aws-cdk-lib';
+import { RustFunction } from 'cargo-lambda-cdk';
+import { Construct } from 'constructs';
+
+import type { CredentialsApi } from './credentials-api';
+import type { Parameters } from './parameters';
+import type { UserPool } from './user-pool';
+
+/** Props for `Canary`. */
+export interface CanaryProps {
+    /** Credentials API under test. */
+    readonly credentialsApi: CredentialsApi;
+
+    /** Parameters in Parameter Store on AWS Systems Manager. */
+    readonly parameters: Parameters;
+
+    /** User pool under test. */
+    readonly userPool: UserPool;
+
+    /**
+     * Interval between runs.
+     *
+     * @remarks
+     *
+     * 15 minutes by default.
+     */
+    readonly interval?: Duration;
+}
+
+/**
+ * CDK construct that provisions the synthetic canary.
+ *
+ * @remarks
+ *
+ * A scheduled Lambda function registers a new user with a software
+ * authenticator, authenticates the user, and deletes the account through the
+ * Credentials API, and reports whether every step succeeded as CloudWatch
+ * metrics; e.g., `canary_success`.
+ */
+export class Canary extends Construct {
+    /** Lambda function that runs the canary. */
+    readonly canaryLambda: lambda.IFunction;
+
+    constructor(scope: Construct, id: string, props: CanaryProps) {
+        super(scope, id);
+
+        const { credentialsApi, parameters, userPool } = props;
+
+        this.canaryLambda = new RustFunction(this, 'CanaryLambda', {
+            manifestPath: path.join('lambda', 'authentication', 'Cargo.toml'),
+            binaryName: 'canary',
+            bundling: {
+                cargoLambdaFlags: ['--features', 'canary'],
+            },
+            architecture: lambda.Architecture.ARM_64,
+            environment: {
+                CREDENTIALS_API_URL: credentialsApi.internalUrl,
+                USER_POOL_CLIENT_ID: userPool.userPoolClient.userPoolClientId,
+                RP_ORIGIN_PARAMETER_PATH: parameters.rpOriginParameter.parameterName,
+                CONFIG_PARAMETER_PATH: parameters.configParameterPath,
+            },
+            memorySize: 128,
+            timeout: Duration.seconds(30),
+            tracing: lambda.Tracing.ACTIVE,
+        });
+        parameters.rpOriginParameter.grantRead(this.canaryLambda);
+        parameters.grantReadConfig(this.canaryLambda);
+
+        new events.Rule(this, 'CanarySchedule', {
+            description: 'Runs the synthetic canary of registration and authentication',
+            schedule: events.Schedule.rate(props.interval ?? Duration.minutes(15)),
+            targets: [new targets.LambdaFunction(this.canaryLambda)],
+        });
+    }
+}
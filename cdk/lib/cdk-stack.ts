@@ -1,8 +1,12 @@
 import { CfnOutput, Stack, StackProps } from 'aws-cdk-lib';
 import { Construct } from 'constructs';
 
+import { AuditLog } from './audit-log';
+import { AuthenticatorMetadata } from './authenticator-metadata';
+import { Canary } from './canary';
 import { CredentialsApi } from './credentials-api';
 import { Distribution } from './distribution';
+import { DomainEvents } from './domain-events';
 import { Parameters } from './parameters';
 import { SessionStore } from './session-store';
 import { UserPool } from './user-pool';
@@ -24,12 +28,22 @@ export class CdkStack extends Stack {
 
     const parameters = new Parameters(this, 'Parameters');
     const sessionStore = new SessionStore(this, 'SessionStore');
+    const auditLog = new AuditLog(this, 'AuditLog');
+    const domainEvents = new DomainEvents(this, 'DomainEvents');
+    const authenticatorMetadata = new AuthenticatorMetadata(this, 'AuthenticatorMetadata', {
+      parameters,
+    });
     const userPool = new UserPool(this, 'UserPool', {
+      auditLog,
+      domainEvents,
       parameters,
       sessionStore,
     });
     const credentialsApi = new CredentialsApi(this, 'CredentialsApi', {
+      auditLog,
+      authenticatorMetadata,
       basePath: '/auth/credentials/',
+      domainEvents,
       parameters,
       sessionStore,
       userPool,
@@ -40,6 +54,11 @@ export class CdkStack extends Stack {
           : []),
       ],
     });
+    new Canary(this, 'Canary', {
+      credentialsApi,
+      parameters,
+      userPool,
+    });
     const distribution = new Distribution(this, 'Distribution', {
       appBasePath: '/app',
       credentialsApi,
@@ -1,19 +1,45 @@
 import * as path from 'node:path';
-import { CorsHttpMethod, HttpApi, HttpMethod } from '@aws-cdk/aws-apigatewayv2-alpha';
+import {
+    CorsHttpMethod,
+    HttpApi,
+    HttpAuthorizer,
+    HttpAuthorizerType,
+    HttpMethod,
+} from '@aws-cdk/aws-apigatewayv2-alpha';
 import { HttpLambdaIntegration } from '@aws-cdk/aws-apigatewayv2-integrations-alpha';
-import { Duration, aws_lambda as lambda } from 'aws-cdk-lib';
+import {
+    Duration,
+    RemovalPolicy,
+    Stack,
+    aws_events as events,
+    aws_events_targets as targets,
+    aws_iam as iam,
+    aws_lambda as lambda,
+    aws_s3 as s3,
+    aws_secretsmanager as secretsmanager,
+} from 'aws-cdk-lib';
 import { RustFunction } from 'cargo-lambda-cdk';
 import { Construct } from 'constructs';
 
+import type { AuditLog } from './audit-log';
+import type { AuthenticatorMetadata } from './authenticator-metadata';
+import type { DomainEvents } from './domain-events';
 import type { Parameters } from './parameters';
+import { type RiskHookProps, grantRiskHook, riskHookEnvironment } from './risk-hook';
 import type { SessionStore } from './session-store';
 import type { UserPool } from './user-pool';
 
 /** Props for `CredentialsApi`. */
 export interface CredentialsApiProps {
+    /** Audit log. */
+    readonly auditLog: AuditLog;
+
     /** Base path where tht API is to be served. */
     readonly basePath: string;
 
+    /** Domain events. */
+    readonly domainEvents: DomainEvents;
+
     /** Parameters in Parameter Store on AWS Systems Manager. */
     readonly parameters: Parameters;
 
@@ -25,6 +51,112 @@ export interface CredentialsApiProps {
 
     /** Origins allowed to access the API. */
     readonly allowOrigins: string[];
+
+    /**
+     * Recovery links emailed through Amazon SES.
+     *
+     * @remarks
+     *
+     * Email recovery is disabled if omitted.
+     */
+    readonly recoveryEmail?: RecoveryEmailProps;
+
+    /**
+     * Webhook notified of registered and deleted credentials.
+     *
+     * @remarks
+     *
+     * Webhooks are disabled if omitted.
+     */
+    readonly webhook?: WebhookProps;
+
+    /**
+     * Whether the GraphQL facade of the credential management is provisioned.
+     *
+     * @remarks
+     *
+     * Served under `graphql/` of the base path if enabled. Disabled if
+     * omitted.
+     */
+    readonly graphql?: boolean;
+
+    /**
+     * Metadata of authenticators in the FIDO Metadata Service.
+     *
+     * @remarks
+     *
+     * Registered credentials are neither named nor checked against the
+     * statuses of their authenticators if omitted.
+     */
+    readonly authenticatorMetadata?: AuthenticatorMetadata;
+
+    /**
+     * CAPTCHA required to start registration and authentication.
+     *
+     * @remarks
+     *
+     * No CAPTCHA is required if omitted.
+     */
+    readonly captcha?: CaptchaProps;
+
+    /**
+     * Hook that assesses the risk of an authentication with discoverable
+     * credentials.
+     *
+     * @remarks
+     *
+     * Every verified authentication is allowed if omitted.
+     */
+    readonly riskHook?: RiskHookProps;
+}
+
+/** Props for recovery links emailed through Amazon SES. */
+export interface RecoveryEmailProps {
+    /** Email address of the sender verified in SES. */
+    readonly senderAddress: string;
+
+    /**
+     * URL of the page that starts recovery with the token given in the
+     * `token` query parameter.
+     */
+    readonly linkUrl: string;
+}
+
+/** Props for the webhook notified of registered and deleted credentials. */
+export interface WebhookProps {
+    /** URL to which events are posted. */
+    readonly url: string;
+
+    /** Secret in Secrets Manager that signs the requests. */
+    readonly secret: secretsmanager.ISecret;
+}
+
+/** Props for the CAPTCHA required to start registration and authentication. */
+export interface CaptchaProps {
+    /**
+     * Provider of CAPTCHA tokens.
+     *
+     * @remarks
+     *
+     * "aws-waf" requires AWS WAF in front of the API that inserts the header
+     * whose value is the secret into a request with a valid token.
+     */
+    readonly provider: 'turnstile' | 'aws-waf';
+
+    /**
+     * Secret in Secrets Manager; the secret key of Turnstile, or the value of
+     * the header that AWS WAF inserts.
+     */
+    readonly secret: secretsmanager.ISecret;
+
+    /**
+     * Header of the token.
+     *
+     * @remarks
+     *
+     * "CF-Turnstile-Response" or "X-Amzn-Waf-Captcha-Verified" by default.
+     */
+    readonly header?: string;
 }
 
 /** CDK construct that provisions the Credentials API. */
@@ -35,6 +167,63 @@ export class CredentialsApi extends Construct {
     /** Lambda function for discoverable credentials. */
     readonly discoverableLambda: lambda.IFunction;
 
+    /** Lambda function for credential management of authenticated users. */
+    readonly credentialsLambda: lambda.IFunction;
+
+    /**
+     * S3 bucket to which large exports of personal data are uploaded.
+     *
+     * @remarks
+     *
+     * Exports are downloaded via pre-signed URLs, and expire in a day.
+     */
+    readonly exportBucket: s3.IBucket;
+
+    /**
+     * Lambda function that enforces data retention.
+     *
+     * @remarks
+     *
+     * Purges credentials deleted by users once their retention window has
+     * elapsed, and expired or orphaned sessions. Audit events are also purged
+     * if `AUDIT_RETENTION` is configured under the configuration path.
+     */
+    readonly dataRetentionLambda: lambda.IFunction;
+
+    /**
+     * Lambda authorizer that verifies tokens of the user pool.
+     *
+     * @remarks
+     *
+     * Attach it to REST APIs or other APIs that protect endpoints like
+     * credential management, so that they do not have to verify tokens
+     * themselves. Self-issued tokens are also accepted if `TOKEN_ISSUER` and
+     * `TOKEN_JWKS_URL` are configured under the configuration path.
+     */
+    readonly authorizerLambda: lambda.IFunction;
+
+    /** Lambda function for administration. */
+    readonly adminLambda: lambda.IFunction;
+
+    /**
+     * Lambda function that serves the association of native apps.
+     *
+     * @remarks
+     *
+     * Apps are configured with the parameters under the configuration path;
+     * e.g., `ANDROID_PACKAGE_NAMES`, `APPLE_APP_IDS`.
+     */
+    readonly wellKnownLambda: lambda.IFunction;
+
+    /**
+     * Lambda function that serves the GraphQL facade.
+     *
+     * @remarks
+     *
+     * `undefined` unless {@link CredentialsApiProps.graphql} is enabled.
+     */
+    readonly graphqlLambda?: lambda.IFunction;
+
     /** Credentials API. */
     readonly credentialsApi: HttpApi;
 
@@ -43,14 +232,45 @@ export class CredentialsApi extends Construct {
 
         const {
           allowOrigins,
+          auditLog,
+          authenticatorMetadata,
           basePath,
+          captcha,
+          domainEvents,
+          graphql,
           parameters,
+          recoveryEmail,
+          riskHook,
           sessionStore,
           userPool,
+          webhook,
         } = props;
+        const webhookEnvironment = webhook != null ? {
+            WEBHOOK_URL: webhook.url,
+            WEBHOOK_SECRET_ID: webhook.secret.secretArn,
+        } : {};
+        const captchaEnvironment = captcha != null ? {
+            CAPTCHA_PROVIDER: captcha.provider,
+            CAPTCHA_SECRET_ID: captcha.secret.secretArn,
+            ...(captcha.header != null ? { CAPTCHA_HEADER: captcha.header } : {}),
+        } : {};
+        // signs session IDs so that forged ones are rejected early
+        const sessionIdSecret = new secretsmanager.Secret(this, 'SessionIdSecret', {
+            description: 'Key to sign session IDs',
+            generateSecretString: {
+                excludePunctuation: true,
+                passwordLength: 64,
+            },
+        });
+        const sessionIdEnvironment = {
+            SESSION_ID_SECRET_ID: sessionIdSecret.secretArn,
+        };
         const manifestPath = path.join('lambda', 'authentication', 'Cargo.toml');
         const registrationBasePath = `${basePath.replace(/\/$/, '')}/registration/`;
         const discoverableBasePath = `${basePath.replace(/\/$/, '')}/discoverable/`;
+        const credentialsBasePath = `${basePath.replace(/\/$/, '')}/user/`;
+        const adminBasePath = `${basePath.replace(/\/$/, '')}/admin/`;
+        const graphqlBasePath = `${basePath.replace(/\/$/, '')}/graphql/`;
 
         this.registrationLambda = new RustFunction(this, 'RegistrationLambda', {
             manifestPath,
@@ -62,19 +282,58 @@ export class CredentialsApi extends Construct {
                 USER_POOL_ID: userPool.userPool.userPoolId,
                 CREDENTIAL_TABLE_NAME: userPool.credentialTable.tableName,
                 RP_ORIGIN_PARAMETER_PATH: parameters.rpOriginParameter.parameterName,
+                CONFIG_PARAMETER_PATH: parameters.configParameterPath,
+                EVENT_BUS_NAME: domainEvents.eventBus.eventBusName,
+                ...webhookEnvironment,
+                ...captchaEnvironment,
+                ...sessionIdEnvironment,
+                ATTESTATION_CA_LIST_PARAMETER_PATH: parameters.attestationCaListParameter.parameterName,
+                ...(authenticatorMetadata != null ? {
+                    METADATA_TABLE_NAME: authenticatorMetadata.metadataTable.tableName,
+                } : {}),
+                AUDIT_TABLE_NAME: auditLog.auditTable.tableName,
+                // the registration function only pads responses, and never
+                // reads the salt
+                ENUMERATION_SALT_SECRET_ID: userPool.enumerationSaltSecret.secretArn,
+                ...(recoveryEmail != null ? {
+                    RECOVERY_EMAIL_SENDER: recoveryEmail.senderAddress,
+                    RECOVERY_LINK_URL: recoveryEmail.linkUrl,
+                } : {}),
             },
             memorySize: 128,
             timeout: Duration.seconds(5),
+            tracing: lambda.Tracing.ACTIVE,
         });
         parameters.rpOriginParameter.grantRead(this.registrationLambda);
+        parameters.attestationCaListParameter.grantRead(this.registrationLambda);
+        parameters.grantReadConfig(this.registrationLambda);
+        domainEvents.grantPublish(this.registrationLambda);
+        webhook?.secret.grantRead(this.registrationLambda);
+        captcha?.secret.grantRead(this.registrationLambda);
+        sessionIdSecret.grantRead(this.registrationLambda);
         sessionStore.sessionTable.grantReadWriteData(this.registrationLambda);
         userPool.credentialTable.grantReadWriteData(this.registrationLambda);
+        auditLog.grantAppend(this.registrationLambda);
+        authenticatorMetadata?.metadataTable.grantReadData(this.registrationLambda);
         userPool.userPool.grant(
             this.registrationLambda,
             'cognito-idp:AdminCreateUser',
+            'cognito-idp:AdminGetUser',
             'cognito-idp:AdminSetUserPassword',
-            'cognito-idp:ListUsers',
+            'cognito-idp:AdminDeleteUser',
         );
+        if (recoveryEmail != null) {
+            this.registrationLambda.addToRolePolicy(new iam.PolicyStatement({
+                actions: ['ses:SendEmail'],
+                resources: [
+                    Stack.of(this).formatArn({
+                        service: 'ses',
+                        resource: 'identity',
+                        resourceName: '*',
+                    }),
+                ],
+            }));
+        }
 
         this.discoverableLambda = new RustFunction(this, 'DiscoverableLambda', {
             manifestPath,
@@ -84,20 +343,214 @@ export class CredentialsApi extends Construct {
                 BASE_PATH: discoverableBasePath,
                 SESSION_TABLE_NAME: sessionStore.sessionTable.tableName,
                 RP_ORIGIN_PARAMETER_PATH: parameters.rpOriginParameter.parameterName,
+                CONFIG_PARAMETER_PATH: parameters.configParameterPath,
+                EVENT_BUS_NAME: domainEvents.eventBus.eventBusName,
+                AUDIT_TABLE_NAME: auditLog.auditTable.tableName,
+                ...captchaEnvironment,
+                ...riskHookEnvironment(riskHook),
             },
             memorySize: 128,
             timeout: Duration.seconds(5),
+            tracing: lambda.Tracing.ACTIVE,
         });
+        captcha?.secret.grantRead(this.discoverableLambda);
+        grantRiskHook(riskHook, this.discoverableLambda);
         parameters.rpOriginParameter.grantRead(this.discoverableLambda);
+        parameters.grantReadConfig(this.discoverableLambda);
+        domainEvents.grantPublish(this.discoverableLambda);
         sessionStore.sessionTable.grantReadWriteData(this.discoverableLambda);
+        auditLog.grantAppend(this.discoverableLambda);
+
+        this.exportBucket = new s3.Bucket(this, 'ExportBucket', {
+            blockPublicAccess: s3.BlockPublicAccess.BLOCK_ALL,
+            encryption: s3.BucketEncryption.S3_MANAGED,
+            enforceSSL: true,
+            lifecycleRules: [{ expiration: Duration.days(1) }],
+            // exports are transient copies of the other tables
+            removalPolicy: RemovalPolicy.DESTROY,
+            autoDeleteObjects: true,
+        });
+
+        this.credentialsLambda = new RustFunction(this, 'CredentialsLambda', {
+            manifestPath,
+            binaryName: 'credentials',
+            architecture: lambda.Architecture.ARM_64,
+            environment: {
+                BASE_PATH: credentialsBasePath,
+                CREDENTIAL_TABLE_NAME: userPool.credentialTable.tableName,
+                SESSION_TABLE_NAME: sessionStore.sessionTable.tableName,
+                USER_POOL_ID: userPool.userPool.userPoolId,
+                RP_ORIGIN_PARAMETER_PATH: parameters.rpOriginParameter.parameterName,
+                CONFIG_PARAMETER_PATH: parameters.configParameterPath,
+                EVENT_BUS_NAME: domainEvents.eventBus.eventBusName,
+                ...webhookEnvironment,
+                ...sessionIdEnvironment,
+                AUDIT_TABLE_NAME: auditLog.auditTable.tableName,
+                EXPORT_BUCKET_NAME: this.exportBucket.bucketName,
+            },
+            memorySize: 128,
+            // an export reads every audit event and scans the session table
+            timeout: Duration.seconds(15),
+            tracing: lambda.Tracing.ACTIVE,
+        });
+        userPool.credentialTable.grantReadWriteData(this.credentialsLambda);
+        sessionStore.sessionTable.grantReadWriteData(this.credentialsLambda);
+        auditLog.grantAppend(this.credentialsLambda);
+        // exports include the audit events of the user
+        auditLog.auditTable.grantReadData(this.credentialsLambda);
+        this.exportBucket.grantReadWrite(this.credentialsLambda);
+        parameters.rpOriginParameter.grantRead(this.credentialsLambda);
+        parameters.grantReadConfig(this.credentialsLambda);
+        domainEvents.grantPublish(this.credentialsLambda);
+        webhook?.secret.grantRead(this.credentialsLambda);
+        sessionIdSecret.grantRead(this.credentialsLambda);
+        userPool.userPool.grant(
+            this.credentialsLambda,
+            'cognito-idp:AdminDeleteUser',
+            'cognito-idp:AdminGetUser',
+            'cognito-idp:AdminUpdateUserAttributes',
+        );
+
+        this.dataRetentionLambda = new RustFunction(this, 'DataRetentionLambda', {
+            manifestPath,
+            binaryName: 'data-retention',
+            architecture: lambda.Architecture.ARM_64,
+            environment: {
+                CREDENTIAL_TABLE_NAME: userPool.credentialTable.tableName,
+                SESSION_TABLE_NAME: sessionStore.sessionTable.tableName,
+                AUDIT_TABLE_NAME: auditLog.auditTable.tableName,
+                CONFIG_PARAMETER_PATH: parameters.configParameterPath,
+            },
+            memorySize: 128,
+            // scans the whole credential, session, and audit tables
+            timeout: Duration.minutes(15),
+            tracing: lambda.Tracing.ACTIVE,
+        });
+        userPool.credentialTable.grantReadWriteData(this.dataRetentionLambda);
+        sessionStore.sessionTable.grantReadWriteData(this.dataRetentionLambda);
+        auditLog.grantPurge(this.dataRetentionLambda);
+        parameters.grantReadConfig(this.dataRetentionLambda);
+        new events.Rule(this, 'DataRetentionSchedule', {
+            description: 'Purges data that has outlived its retention period',
+            schedule: events.Schedule.rate(Duration.days(1)),
+            targets: [new targets.LambdaFunction(this.dataRetentionLambda)],
+        });
+
+        this.authorizerLambda = new RustFunction(this, 'AuthorizerLambda', {
+            manifestPath,
+            binaryName: 'authorizer',
+            architecture: lambda.Architecture.ARM_64,
+            environment: {
+                USER_POOL_ID: userPool.userPool.userPoolId,
+                USER_POOL_CLIENT_IDS: userPool.userPoolClient.userPoolClientId,
+                CONFIG_PARAMETER_PATH: parameters.configParameterPath,
+            },
+            memorySize: 128,
+            timeout: Duration.seconds(5),
+            tracing: lambda.Tracing.ACTIVE,
+        });
+        parameters.grantReadConfig(this.authorizerLambda);
+
+        this.adminLambda = new RustFunction(this, 'AdminLambda', {
+            manifestPath,
+            binaryName: 'admin',
+            architecture: lambda.Architecture.ARM_64,
+            environment: {
+                BASE_PATH: adminBasePath,
+                AUDIT_TABLE_NAME: auditLog.auditTable.tableName,
+                USER_POOL_ID: userPool.userPool.userPoolId,
+                CREDENTIAL_TABLE_NAME: userPool.credentialTable.tableName,
+                ADMIN_GROUP_NAME: userPool.adminGroupName,
+                CONFIG_PARAMETER_PATH: parameters.configParameterPath,
+                EVENT_BUS_NAME: domainEvents.eventBus.eventBusName,
+                ...webhookEnvironment,
+            },
+            memorySize: 128,
+            timeout: Duration.seconds(5),
+            tracing: lambda.Tracing.ACTIVE,
+        });
+        auditLog.auditTable.grantReadData(this.adminLambda);
+        auditLog.grantAppend(this.adminLambda);
+        userPool.credentialTable.grantReadWriteData(this.adminLambda);
+        userPool.userPool.grant(
+            this.adminLambda,
+            'cognito-idp:ListUsers',
+            'cognito-idp:AdminUserGlobalSignOut',
+        );
+        parameters.grantReadConfig(this.adminLambda);
+        domainEvents.grantPublish(this.adminLambda);
+        webhook?.secret.grantRead(this.adminLambda);
+
+        this.wellKnownLambda = new RustFunction(this, 'WellKnownLambda', {
+            manifestPath,
+            binaryName: 'well-known',
+            architecture: lambda.Architecture.ARM_64,
+            environment: {
+                CONFIG_PARAMETER_PATH: parameters.configParameterPath,
+            },
+            memorySize: 128,
+            timeout: Duration.seconds(5),
+            tracing: lambda.Tracing.ACTIVE,
+        });
+        parameters.grantReadConfig(this.wellKnownLambda);
+
+        if (graphql) {
+            const graphqlLambda = new RustFunction(this, 'GraphqlLambda', {
+                manifestPath,
+                binaryName: 'graphql',
+                bundling: {
+                    cargoLambdaFlags: ['--features', 'graphql'],
+                },
+                architecture: lambda.Architecture.ARM_64,
+                environment: {
+                    BASE_PATH: graphqlBasePath,
+                    CREDENTIAL_TABLE_NAME: userPool.credentialTable.tableName,
+                    SESSION_TABLE_NAME: sessionStore.sessionTable.tableName,
+                    RP_ORIGIN_PARAMETER_PATH: parameters.rpOriginParameter.parameterName,
+                    CONFIG_PARAMETER_PATH: parameters.configParameterPath,
+                    EVENT_BUS_NAME: domainEvents.eventBus.eventBusName,
+                    ...webhookEnvironment,
+                    ...sessionIdEnvironment,
+                    AUDIT_TABLE_NAME: auditLog.auditTable.tableName,
+                },
+                memorySize: 128,
+                timeout: Duration.seconds(5),
+                tracing: lambda.Tracing.ACTIVE,
+            });
+            userPool.credentialTable.grantReadWriteData(graphqlLambda);
+            sessionStore.sessionTable.grantReadWriteData(graphqlLambda);
+            auditLog.grantAppend(graphqlLambda);
+            parameters.rpOriginParameter.grantRead(graphqlLambda);
+            parameters.grantReadConfig(graphqlLambda);
+            domainEvents.grantPublish(graphqlLambda);
+            webhook?.secret.grantRead(graphqlLambda);
+            sessionIdSecret.grantRead(graphqlLambda);
+            this.graphqlLambda = graphqlLambda;
+        }
 
         this.credentialsApi = new HttpApi(this, 'CredentialsApi', {
             description: 'API to manage credentials',
             createDefaultStage: true,
             corsPreflight: {
-                allowHeaders: ['Content-Type'],
-                allowMethods: [CorsHttpMethod.POST],
+                allowHeaders: [
+                    'Authorization',
+                    'Content-Type',
+                    'X-Step-Up-Token',
+                    // correlation ID of a request
+                    'X-Request-Id',
+                    // clients send the token to AWS WAF, which inserts
+                    // another header
+                    ...(captcha?.provider === 'turnstile' ? [captcha.header ?? 'CF-Turnstile-Response'] : []),
+                    ...(captcha?.provider === 'aws-waf' ? ['X-Aws-Waf-Token'] : []),
+                ],
+                allowMethods: [
+                    CorsHttpMethod.GET,
+                    CorsHttpMethod.POST,
+                    CorsHttpMethod.DELETE,
+                ],
                 allowOrigins,
+                // lets clients read the correlation ID of a failing request
+                exposeHeaders: ['X-Request-Id'],
                 maxAge: Duration.days(1),
             },
         });
@@ -106,11 +559,107 @@ export class CredentialsApi extends Construct {
             methods: [HttpMethod.POST],
             integration: new HttpLambdaIntegration('Registration', this.registrationLambda),
         });
+        // health checks for synthetic monitoring require no authorization
+        this.credentialsApi.addRoutes({
+            path: `${registrationBasePath}health`,
+            methods: [HttpMethod.GET],
+            integration: new HttpLambdaIntegration('RegistrationHealth', this.registrationLambda),
+        });
         this.credentialsApi.addRoutes({
             path: `${discoverableBasePath}{proxy+}`,
-            methods: [HttpMethod.POST],
+            // GET serves the JWKS of self-issued tokens
+            methods: [HttpMethod.GET, HttpMethod.POST],
             integration: new HttpLambdaIntegration('Discoverable', this.discoverableLambda),
         });
+        // verifies ID tokens issued by the user pool
+        const userPoolAuthorizer = new HttpAuthorizer(this, 'UserPoolAuthorizer', {
+            httpApi: this.credentialsApi,
+            type: HttpAuthorizerType.JWT,
+            identitySource: ['$request.header.Authorization'],
+            jwtIssuer: `https://cognito-idp.${Stack.of(this).region}.amazonaws.com/${userPool.userPool.userPoolId}`,
+            jwtAudience: [userPool.userPoolClient.userPoolClientId],
+        });
+        const routeAuthorizer = HttpAuthorizer.fromHttpAuthorizerAttributes(
+            this,
+            'UserPoolRouteAuthorizer',
+            {
+                authorizerId: userPoolAuthorizer.authorizerId,
+                authorizerType: HttpAuthorizerType.JWT,
+            },
+        );
+        this.credentialsApi.addRoutes({
+            path: `${credentialsBasePath}{proxy+}`,
+            methods: [HttpMethod.GET, HttpMethod.POST, HttpMethod.DELETE],
+            integration: new HttpLambdaIntegration('Credentials', this.credentialsLambda),
+            authorizer: routeAuthorizer,
+        });
+        // additional passkeys are registered by authenticated users
+        this.credentialsApi.addRoutes({
+            path: `${registrationBasePath}passkeys/{proxy+}`,
+            methods: [HttpMethod.POST],
+            integration: new HttpLambdaIntegration('RegistrationPasskeys', this.registrationLambda),
+            authorizer: routeAuthorizer,
+        });
+        this.credentialsApi.addRoutes({
+            path: `${registrationBasePath}v1/passkeys/{proxy+}`,
+            methods: [HttpMethod.POST],
+            integration: new HttpLambdaIntegration('RegistrationPasskeysV1', this.registrationLambda),
+            authorizer: routeAuthorizer,
+        });
+        // password users upgrade to passkeys while authenticated
+        this.credentialsApi.addRoutes({
+            path: `${registrationBasePath}upgrade/{proxy+}`,
+            methods: [HttpMethod.POST],
+            integration: new HttpLambdaIntegration('RegistrationUpgrade', this.registrationLambda),
+            authorizer: routeAuthorizer,
+        });
+        this.credentialsApi.addRoutes({
+            path: `${registrationBasePath}v1/upgrade/{proxy+}`,
+            methods: [HttpMethod.POST],
+            integration: new HttpLambdaIntegration('RegistrationUpgradeV1', this.registrationLambda),
+            authorizer: routeAuthorizer,
+        });
+        this.credentialsApi.addRoutes({
+            path: `${credentialsBasePath}health`,
+            methods: [HttpMethod.GET],
+            integration: new HttpLambdaIntegration('CredentialsHealth', this.credentialsLambda),
+        });
+        this.credentialsApi.addRoutes({
+            path: `${adminBasePath}health`,
+            methods: [HttpMethod.GET],
+            integration: new HttpLambdaIntegration('AdminHealth', this.adminLambda),
+        });
+        // the admin Lambda checks if the caller belongs to the admin group
+        this.credentialsApi.addRoutes({
+            path: `${adminBasePath}{proxy+}`,
+            methods: [HttpMethod.GET, HttpMethod.DELETE],
+            integration: new HttpLambdaIntegration('Admin', this.adminLambda),
+            authorizer: routeAuthorizer,
+        });
+        if (this.graphqlLambda != null) {
+            this.credentialsApi.addRoutes({
+                path: graphqlBasePath.replace(/\/$/, ''),
+                methods: [HttpMethod.POST],
+                integration: new HttpLambdaIntegration('Graphql', this.graphqlLambda),
+                authorizer: routeAuthorizer,
+            });
+            this.credentialsApi.addRoutes({
+                path: `${graphqlBasePath}health`,
+                methods: [HttpMethod.GET],
+                integration: new HttpLambdaIntegration('GraphqlHealth', this.graphqlLambda),
+            });
+        }
+        // served at the root of the domain
+        for (const file of ['assetlinks.json', 'apple-app-site-association']) {
+            this.credentialsApi.addRoutes({
+                path: `/.well-known/${file}`,
+                methods: [HttpMethod.GET],
+                integration: new HttpLambdaIntegration(
+                    `WellKnown-${file}`,
+                    this.wellKnownLambda,
+                ),
+            });
+        }
     }
 
     /** Base path of the Credentials API not including the trailing slash. */
@@ -58,6 +58,15 @@ export class Distribution extends Construct {
           viewerProtocolPolicy: cloudfront.ViewerProtocolPolicy.HTTPS_ONLY,
           // CORS is unnecessary because the app resides in the same domain
         },
+        // association of native apps with the domain
+        '/.well-known/*': {
+          origin: new origins.HttpOrigin(
+            Fn.parseDomainName(credentialsApi.credentialsApi.apiEndpoint),
+          ),
+          allowedMethods: cloudfront.AllowedMethods.ALLOW_GET_HEAD,
+          cachePolicy: cloudfront.CachePolicy.CACHING_OPTIMIZED,
+          viewerProtocolPolicy: cloudfront.ViewerProtocolPolicy.HTTPS_ONLY,
+        },
       },
       errorResponses: [
         // redirects to the app index whenever access is denied
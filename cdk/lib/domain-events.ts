@@ -0,0 +1,45 @@
+import { aws_events as events, aws_iam as iam } from 'aws-cdk-lib';
+import { Construct } from 'constructs';
+
+/**
+ * CDK construct that provisions the EventBridge event bus for domain events.
+ *
+ * @remarks
+ *
+ * Downstream systems subscribe to the events with rules on the event bus.
+ */
+export class DomainEvents extends Construct {
+    /**
+     * EventBridge event bus that receives domain events.
+     *
+     * ## Events
+     *
+     * Every event has the source "passkey-test.authentication" and one of the
+     * following detail types:
+     *
+     * - "PasskeyRegistered": a passkey has been registered
+     * - "AuthenticationSucceeded": a user has authenticated with a passkey
+     * - "CredentialRevoked": a credential has been deleted or disabled
+     *
+     * The detail has the following attributes:
+     *
+     * - `userHandle`: user handle of the user concerned
+     * - `credentialId`: "base64url"-encoded ID of the credential concerned
+     * - `tenantId`: (optional) ID of the tenant
+     * - `registration`: ("PasskeyRegistered" only) kind of the registration
+     * - `action`: ("CredentialRevoked" only) "deleted" or "disabled"
+     * - `revokedBy`: ("CredentialRevoked" only) "user" or "administrator"
+     */
+    readonly eventBus: events.EventBus;
+
+    constructor(scope: Construct, id: string) {
+        super(scope, id);
+
+        this.eventBus = new events.EventBus(this, 'DomainEventBus');
+    }
+
+    /** Grants a given principal permission to publish events. */
+    grantPublish(grantee: iam.IGrantable): iam.Grant {
+        return this.eventBus.grantPutEventsTo(grantee);
+    }
+}
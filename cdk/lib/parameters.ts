@@ -1,3 +1,4 @@
+import { Stack, aws_iam as iam } from 'aws-cdk-lib';
 import { GhostStringParameter } from 'cdk-ghost-string-parameter';
 import { Construct } from 'constructs';
 
@@ -12,6 +13,32 @@ import { Construct } from 'constructs';
 export class Parameters extends Construct {
   /** Origin (URL) of the relying party. */
   readonly rpOriginParameter: GhostStringParameter;
+  /**
+   * Attestation CA list (JSON) for security key registration.
+   *
+   * @remarks
+   *
+   * Security key registration is disabled unless this parameter exists.
+   */
+  readonly attestationCaListParameter: GhostStringParameter;
+  /**
+   * Root certificate (PEM) of the FIDO Metadata Service.
+   *
+   * @remarks
+   *
+   * The metadata refresh fails unless this parameter exists.
+   */
+  readonly mdsRootCertificateParameter: GhostStringParameter;
+  /**
+   * Path to the configuration parameters.
+   *
+   * @remarks
+   *
+   * Each parameter under this path overrides the environment variable of the
+   * same name given to the Lambda functions; e.g.,
+   * `/passkey-test/config/CHALLENGE_TIMEOUT`.
+   */
+  readonly configParameterPath = '/passkey-test/config/';
 
   constructor(scope: Construct, id: string) {
     super(scope, id);
@@ -19,5 +46,27 @@ export class Parameters extends Construct {
     this.rpOriginParameter = new GhostStringParameter(this, {
       parameterName: '/passkey-test/RP_ORIGIN',
     });
+    this.attestationCaListParameter = new GhostStringParameter(this, {
+      parameterName: '/passkey-test/ATTESTATION_CA_LIST',
+    });
+    this.mdsRootCertificateParameter = new GhostStringParameter(this, {
+      parameterName: '/passkey-test/MDS_ROOT_CERTIFICATE',
+    });
+  }
+
+  /** Grants read access to the configuration parameters. */
+  grantReadConfig(grantee: iam.IGrantable): iam.Grant {
+    const path = this.configParameterPath.replace(/\/$/, '');
+    return iam.Grant.addToPrincipal({
+      grantee,
+      actions: ['ssm:GetParametersByPath'],
+      resourceArns: [
+        Stack.of(this).formatArn({
+          service: 'ssm',
+          resource: 'parameter',
+          resourceName: path.replace(/^\//, ''),
+        }),
+      ],
+    });
   }
 }
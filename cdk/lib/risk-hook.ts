@@ -0,0 +1,59 @@
+import {
+    aws_lambda as lambda,
+    aws_secretsmanager as secretsmanager,
+} from 'aws-cdk-lib';
+
+/**
+ * Props for the hook that assesses the risk of an authentication.
+ *
+ * @remarks
+ *
+ * Either `url` or `function` must be specified.
+ */
+export interface RiskHookProps {
+    /** URL of the HTTP endpoint that assesses the risk. */
+    readonly url?: string;
+
+    /**
+     * Secret in Secrets Manager that signs the requests to `url`.
+     *
+     * @remarks
+     *
+     * Requests are not signed if omitted.
+     */
+    readonly secret?: secretsmanager.ISecret;
+
+    /** Lambda function that assesses the risk. */
+    readonly function?: lambda.IFunction;
+
+    /**
+     * Decision when the hook fails.
+     *
+     * @remarks
+     *
+     * "allow" by default.
+     */
+    readonly onError?: 'allow' | 'step_up' | 'deny';
+}
+
+/** Returns the environment variables that configure a risk hook. */
+export function riskHookEnvironment(riskHook?: RiskHookProps): Record<string, string> {
+    if (riskHook == null) {
+        return {};
+    }
+    if ((riskHook.url == null) === (riskHook.function == null)) {
+        throw new Error('either url or function of the risk hook must be specified');
+    }
+    return {
+        ...(riskHook.url != null ? { RISK_HOOK_URL: riskHook.url } : {}),
+        ...(riskHook.secret != null ? { RISK_HOOK_SECRET_ID: riskHook.secret.secretArn } : {}),
+        ...(riskHook.function != null ? { RISK_HOOK_FUNCTION_NAME: riskHook.function.functionArn } : {}),
+        ...(riskHook.onError != null ? { RISK_HOOK_ON_ERROR: riskHook.onError } : {}),
+    };
+}
+
+/** Grants a Lambda function the permissions to call out a risk hook. */
+export function grantRiskHook(riskHook: RiskHookProps | undefined, grantee: lambda.IFunction) {
+    riskHook?.secret?.grantRead(grantee);
+    riskHook?.function?.grantInvoke(grantee);
+}
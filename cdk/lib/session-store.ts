@@ -12,15 +12,66 @@ export class SessionStore extends Construct {
      * - No sort key
      * - Time to live attribute: `ttl`
      *
+     * If tenants are configured, every `pk` of a tenant is prefixed with
+     * "tenant#<tenant ID>#"; e.g., "tenant#acme#registration#<session ID>".
+     *
      * ### User registration session
      *
      * - `pk`: "registration#<session ID>"
+     *     - "securitykey-registration#<session ID>" for a security key
+     *     - "recovery-registration#<session ID>" for a new passkey of an
+     *       existing user recovering the account
+     *     - "additional-registration#<session ID>" for an additional passkey
+     *       of an authenticated user
      * - `ttl`: 60 seconds after the session was created
      * - `userId`: unique user ID
      * - `userInfo`:
      *     - `username`: unique username
      *     - `displayName`: display name
      * - `state`: serialized internal state
+     * - `dataKey`: (optional) data key encrypted by KMS
+     *     - if present, `userInfo` and `state` are binaries encrypted with
+     *       AES-256-GCM under the data key
+     *     - generated by `SESSION_KMS_KEY_ARN`, or `PII_KMS_KEY_ARN` if only PII
+     *       is protected
+     * - `authenticatorAttachment`: (optional) authenticator attachment
+     *   requested in the creation options; "platform" or "cross-platform"
+     *
+     * ### Result of a finished user registration
+     *
+     * - `pk`: "registration-result#<key hash>"
+     *     - "securitykey-registration-result#<key hash>" for a security key
+     *     - "recovery-registration-result#<key hash>" for account recovery
+     *     - "additional-registration-result#<key hash>" for an additional
+     *       passkey
+     *     - `<key hash>` is the "base64url"-encoded SHA-256 hash of the
+     *       `Idempotency-Key` header, or of the session ID if the header is
+     *       omitted
+     * - `ttl`: an hour after the registration finished
+     * - `sessionId`: session ID of the registration
+     * - `credentialId`: ID of the registered credential
+     *
+     * ### Emailed recovery link
+     *
+     * - `pk`: "recovery-link#<token hash>"
+     *     - `<token hash>` is the "base64url"-encoded SHA-256 hash of the token
+     *       in the link
+     * - `ttl`: 15 minutes after the link was sent by default
+     * - `userHandle`: "base64url"-encoded user handle of the recovering user
+     *
+     * ### Step-up session of an authenticated user
+     *
+     * - `pk`: "stepup#<session ID>"
+     * - `ttl`: 60 seconds after the session was created
+     * - `userHandle`: "base64url"-encoded user handle of the user
+     * - `state`: serialized internal state
+     *
+     * ### Step-up token
+     *
+     * - `pk`: "stepup-token#<token hash>"
+     *     - `<token hash>` is the "base64url"-encoded SHA-256 hash of the token
+     * - `ttl`: 5 minutes after the token was issued
+     * - `userHandle`: "base64url"-encoded user handle of the user
      *
      * ### User authentication session with a user-side discoverable credential
      *
@@ -28,6 +79,45 @@ export class SessionStore extends Construct {
      *     - `<challenge>` is the "base64url"-encoded challenge
      * - `ttl`: 60 seconds after the session was created
      * - `state`: serialized internal state
+     *
+     * ### Refresh token
+     *
+     * - `pk`: "refresh-token#<token hash>"
+     *     - `<token hash>` is the "base64url"-encoded SHA-256 hash of the token
+     * - `ttl`: expiration time of the family of the token
+     * - `userHandle`: "base64url"-encoded user handle of the user
+     * - `familyId`: ID of the family of the token
+     * - `usedAt`: (optional) time when the token was used in seconds since the
+     *   epoch
+     *
+     * ### Refresh token family
+     *
+     * - `pk`: "refresh-family#<family ID>"
+     * - `ttl`: 30 days (`REFRESH_TOKEN_TTL`) after the user authenticated
+     * - `userHandle`: "base64url"-encoded user handle of the user
+     * - `revokedAt`: (optional) time when the family was revoked in seconds
+     *   since the epoch
+     *
+     * ### Latest passkey authentication of a user
+     *
+     * - `pk`: "passkey-authentication#<user handle>"
+     *     - `<user handle>` is the "base64url"-encoded user handle
+     * - `ttl`: 5 minutes after the user authenticated
+     * - `credentialId`: "base64url"-encoded credential ID
+     * - `aaguid`: (optional) AAGUID of the authenticator
+     * - `userVerified`: whether the user was verified
+     * - `authenticatedAt`: time of the authentication in seconds since the
+     *   epoch
+     *
+     * ### Rate limit counter
+     *
+     * - `pk`: "ratelimit#<scope>#<key hash>#<window start>"
+     *     - `<scope>` is "ip" or "username"
+     *     - `<key hash>` is the "base64url"-encoded SHA-256 hash of the key
+     *     - `<window start>` is the start of the window in seconds since the
+     *       epoch
+     * - `ttl`: end of the window
+     * - `count`: number of requests in the window
      */
     readonly sessionTable: dynamodb.TableV2;
 
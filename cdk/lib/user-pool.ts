@@ -5,20 +5,65 @@ import {
   aws_cognito as cognito,
   aws_dynamodb as dynamodb,
   aws_lambda as lambda,
+  aws_secretsmanager as secretsmanager,
 } from 'aws-cdk-lib';
 import { RustFunction } from 'cargo-lambda-cdk';
 import { Construct } from 'constructs';
 
+import type { AuditLog } from './audit-log';
+import type { DomainEvents } from './domain-events';
 import type { Parameters } from './parameters';
+import { type RiskHookProps, grantRiskHook, riskHookEnvironment } from './risk-hook';
 import type { SessionStore } from './session-store';
 
+/** Properties of the legacy user store that users are migrated from. */
+export interface LegacyUserStoreProps {
+  /** URL of the HTTP endpoint of the legacy user store. */
+  readonly url: string;
+
+  /**
+   * Secret in Secrets Manager that signs the requests to `url`.
+   *
+   * @remarks
+   *
+   * Requests are not signed if omitted.
+   */
+  readonly secret?: secretsmanager.ISecret;
+}
+
 /** Properties for `UserPool` */
 export interface UserPoolProps {
+  /** Audit log. */
+  readonly auditLog: AuditLog;
+
+  /** Domain events. */
+  readonly domainEvents: DomainEvents;
+
   /** Parameters in Parameter Store on AWS Systems Manager. */
   readonly parameters: Parameters;
 
   /** Session store. */
   readonly sessionStore: SessionStore;
+
+  /**
+   * Hook that assesses the risk of an authentication.
+   *
+   * @remarks
+   *
+   * Every verified authentication is allowed if omitted.
+   */
+  readonly riskHook?: RiskHookProps;
+
+  /**
+   * Legacy user store that users are lazily migrated from.
+   *
+   * @remarks
+   *
+   * Users are not migrated if omitted. If specified, the user migration
+   * trigger is configured, and the user pool client allows users to sign in
+   * with a password so that they can upgrade to passkeys.
+   */
+  readonly legacyUserStore?: LegacyUserStoreProps;
 }
 
 /**
@@ -31,8 +76,39 @@ export interface UserPoolProps {
  * - Partition key: `pk`
  * - Sort key: `sk`
  *
- * Global secondary index:
- * - Partition key: `credentialId`
+ * Global secondary indexes:
+ * - `CredentialIdIndex`
+ *     - Partition key: `credentialId`
+ * - `UsernameIndex`: resolves a username into the user handle
+ *     - Partition key: `username`
+ *     - Sort key: `sk`
+ *
+ * #### Protection of PII
+ *
+ * If the `PII_KMS_KEY_ARN` and `PII_INDEX_SECRET_ID` environment variables
+ * are configured for the Lambda functions, users and credentials have the
+ * following attributes instead of the plaintext username and display name:
+ * - `username`: "base64url"-encoded HMAC-SHA256 of the normalized username
+ *   keyed with the secret in `PII_INDEX_SECRET_ID`
+ * - `sealedUsername`: normalized username encrypted with AES-256-GCM
+ * - `sealedDisplayName`: (user only) display name encrypted with AES-256-GCM
+ * - `piiDataKey`: data key encrypted by the KMS key in `PII_KMS_KEY_ARN`
+ *
+ * #### User
+ *
+ * Written together with the first credential in a single transaction.
+ *
+ * - `pk`: "user#<user ID>"
+ *     - `<user ID>` is the "base64url"-encoded user handle (unique ID)
+ * - `sk`: "user"
+ * - `username`: normalized username of the user
+ *     - "<tenant ID>/<username>" if tenants are configured
+ *     - index of the username if PII is protected; see below
+ * - `displayName`: display name of the user
+ *     - omitted if PII is protected
+ * - `cognitoSub`: Cognito sub ID
+ * - `createdAt`: "<yyyy-mm-ddTHH:MM:SS.SSSSSSZ>"
+ *     - timestamp when the user was created
  *
  * #### User's public key credential
  *
@@ -41,12 +117,77 @@ export interface UserPoolProps {
  * - `sk`: "credential#<credential ID>"
  *     - `<credential ID>` is the "base64url"-encoded credential ID
  * - `credentialId`: "<credential ID>"
+ * - `username`: normalized username of the user
+ *     - "<tenant ID>/<username>" if tenants are configured
+ *     - index of the username if PII is protected; see below
  * - `credential`: serialized JSON representation of [`Passkey`]
+ *     - or [`SecurityKey`], which is compatible with [`Passkey`]
+ * - `credentialType`: "passkey" or "securityKey"
+ * - `backupEligible`: whether the credential is eligible for backup (BE flag)
+ * - `backupState`: whether the credential is backed up (BS flag)
+ *     - updated on every authentication
+ * - `discoverable`: (optional) whether the credential is client-side
+ *   discoverable (resident) as reported by the `credProps` extension
+ * - `prfEnabled`: (optional) whether the credential supports the `prf`
+ *   extension
  * - `cognitoSub`: Cognito sub ID
  * - `createdAt`: "<yyyy-mm-ddTHH:MM:SS.SSSSSSZ>"
  *     - timestamp when the credential was registered
  * - `updatedAt`: "<yyyy-mm-ddTHH:MM:SS.SSSSSSZ>"
  *     - timestamp when the credential was last updated
+ * - `lastUsedAt`: (optional) "<yyyy-mm-ddTHH:MM:SS.SSSSSSZ>"
+ *     - timestamp when the credential was last used for authentication
+ * - `authCount`: (optional) number of successful authentications with the
+ *   credential since they were counted
+ * - `disabledAt`: (optional) "<yyyy-mm-ddTHH:MM:SS.SSSSSSZ>"
+ *     - timestamp when an administrator disabled the credential; disabled
+ *       credentials cannot be used for authentication
+ * - `deletedAt`: (optional) "<yyyy-mm-ddTHH:MM:SS.SSSSSSZ>"
+ *     - timestamp when the user deleted the credential; the credential is
+ *       also disabled, and can be restored until the retention window
+ *       elapses
+ * - `authenticatorAttachment`: (optional) authenticator attachment reported
+ *   at registration; "platform" or "cross-platform"
+ *     - advisory; the client reports it without a signature
+ * - `aaguid`: (optional) AAGUID of the authenticator reported at
+ *   registration
+ * - `authenticatorName`: (optional) description of the authenticator in the
+ *   FIDO Metadata Service
+ * - `registeredIp`: (optional) source IP address of the client that
+ *   registered the credential
+ * - `registeredUserAgent`: (optional) user agent of the client that
+ *   registered the credential
+ * - `version`: number incremented on every update
+ *     - an update is conditioned on the version read before it so that
+ *       concurrent authentications cannot overwrite each other's sign count
+ *
+ * #### User's recovery codes
+ *
+ * - `pk`: "user#<user ID>"
+ *     - `<user ID>` is the "base64url"-encoded user handle (unique ID)
+ * - `sk`: "recovery-codes"
+ * - `codeHashes`: (optional) string set of the "base64url"-encoded SHA-256
+ *   hashes of the unused recovery codes
+ *     - a used code is removed from the set; the attribute disappears when
+ *       all the codes have been used
+ * - `createdAt`: "<yyyy-mm-ddTHH:MM:SS.SSSSSSZ>"
+ *     - timestamp when the recovery codes were generated
+ *
+ * #### Lockout state of a credential
+ *
+ * Exists only while a credential has failed since the last successful
+ * authentication, and only if the lockout is enabled.
+ *
+ * - `pk`: "user#<user ID>"
+ *     - `<user ID>` is the "base64url"-encoded user handle (unique ID)
+ * - `sk`: "lockout#<credential ID>"
+ *     - `<credential ID>` is the "base64url"-encoded credential ID
+ * - `failures`: number of consecutive failed authentications since the last
+ *   lock
+ * - `locks`: (optional) number of locks since the last successful
+ *   authentication
+ * - `lockedUntil`: (optional) end of the current lock in seconds since the
+ *   epoch
  */
 export class UserPool extends Construct {
   /** User pool. */
@@ -57,11 +198,37 @@ export class UserPool extends Construct {
   readonly credentialTable: dynamodb.TableV2;
   /** Cognito trigger Lambda for the user pool. */
   readonly userPoolTriggerLambda: lambda.IFunction;
+  /**
+   * Cognito trigger Lambda that adds the claims of passkeys to ID tokens.
+   */
+  readonly preTokenGenerationLambda: lambda.IFunction;
+  /**
+   * Cognito trigger Lambda that migrates users from the legacy user store.
+   *
+   * @remarks
+   *
+   * `undefined` unless the legacy user store is specified.
+   */
+  readonly userMigrationLambda?: lambda.IFunction;
+  /**
+   * Secret that salts the dummy credential IDs of unknown users in the
+   * protection mode against user enumeration.
+   */
+  readonly enumerationSaltSecret: secretsmanager.ISecret;
+  /** Name of the group whose members are administrators. */
+  readonly adminGroupName = 'admin';
 
   constructor(scope: Construct, id: string, props: UserPoolProps) {
     super(scope, id);
 
-    const { parameters, sessionStore } = props;
+    const {
+      auditLog,
+      domainEvents,
+      legacyUserStore,
+      parameters,
+      riskHook,
+      sessionStore,
+    } = props;
 
     this.credentialTable = new dynamodb.TableV2(this, 'CredentialTable', {
       partitionKey: {
@@ -81,6 +248,18 @@ export class UserPool extends Construct {
           },
           projectionType: dynamodb.ProjectionType.KEYS_ONLY,
         },
+        {
+          indexName: 'UsernameIndex',
+          partitionKey: {
+            name: 'username',
+            type: dynamodb.AttributeType.STRING,
+          },
+          sortKey: {
+            name: 'sk',
+            type: dynamodb.AttributeType.STRING,
+          },
+          projectionType: dynamodb.ProjectionType.KEYS_ONLY,
+        },
       ],
       billing: dynamodb.Billing.provisioned({
         readCapacity: dynamodb.Capacity.fixed(1),
@@ -91,6 +270,15 @@ export class UserPool extends Construct {
       removalPolicy: RemovalPolicy.RETAIN,
     });
 
+    // required once `ENUMERATION_PROTECTION` is enabled in the parameters
+    this.enumerationSaltSecret = new secretsmanager.Secret(this, 'EnumerationSaltSecret', {
+      description: 'Salt of dummy credential IDs for unknown users',
+      generateSecretString: {
+        excludePunctuation: true,
+        passwordLength: 64,
+      },
+    });
+
     this.userPoolTriggerLambda = new RustFunction(
       this,
       'CognitoTriggerLambda',
@@ -102,14 +290,70 @@ export class UserPool extends Construct {
           CREDENTIAL_TABLE_NAME: this.credentialTable.tableName,
           SESSION_TABLE_NAME: sessionStore.sessionTable.tableName,
           RP_ORIGIN_PARAMETER_PATH: parameters.rpOriginParameter.parameterName,
+          CONFIG_PARAMETER_PATH: parameters.configParameterPath,
+          EVENT_BUS_NAME: domainEvents.eventBus.eventBusName,
+          AUDIT_TABLE_NAME: auditLog.auditTable.tableName,
+          // records authentications for the pre token generation trigger
+          PASSKEY_CLAIMS: 'true',
+          ENUMERATION_SALT_SECRET_ID: this.enumerationSaltSecret.secretArn,
+          ...riskHookEnvironment(riskHook),
         },
         memorySize: 128,
         timeout: Duration.seconds(5),
+        tracing: lambda.Tracing.ACTIVE,
       },
     );
     this.credentialTable.grantReadWriteData(this.userPoolTriggerLambda);
     parameters.rpOriginParameter.grantRead(this.userPoolTriggerLambda);
+    parameters.grantReadConfig(this.userPoolTriggerLambda);
     sessionStore.sessionTable.grantReadWriteData(this.userPoolTriggerLambda);
+    auditLog.grantAppend(this.userPoolTriggerLambda);
+    domainEvents.grantPublish(this.userPoolTriggerLambda);
+    grantRiskHook(riskHook, this.userPoolTriggerLambda);
+    this.enumerationSaltSecret.grantRead(this.userPoolTriggerLambda);
+
+    this.preTokenGenerationLambda = new RustFunction(
+      this,
+      'PreTokenGenerationLambda',
+      {
+        manifestPath: path.join('lambda', 'authentication', 'Cargo.toml'),
+        binaryName: 'pre-token-generation',
+        architecture: lambda.Architecture.ARM_64,
+        environment: {
+          SESSION_TABLE_NAME: sessionStore.sessionTable.tableName,
+          CONFIG_PARAMETER_PATH: parameters.configParameterPath,
+        },
+        memorySize: 128,
+        timeout: Duration.seconds(5),
+        tracing: lambda.Tracing.ACTIVE,
+      },
+    );
+    parameters.grantReadConfig(this.preTokenGenerationLambda);
+    sessionStore.sessionTable.grantReadWriteData(this.preTokenGenerationLambda);
+
+    if (legacyUserStore != null) {
+      this.userMigrationLambda = new RustFunction(
+        this,
+        'UserMigrationLambda',
+        {
+          manifestPath: path.join('lambda', 'authentication', 'Cargo.toml'),
+          binaryName: 'user-migration',
+          architecture: lambda.Architecture.ARM_64,
+          environment: {
+            LEGACY_USER_STORE_URL: legacyUserStore.url,
+            ...(legacyUserStore.secret != null
+              ? { LEGACY_USER_STORE_SECRET_ID: legacyUserStore.secret.secretArn }
+              : {}),
+            CONFIG_PARAMETER_PATH: parameters.configParameterPath,
+          },
+          memorySize: 128,
+          timeout: Duration.seconds(5),
+          tracing: lambda.Tracing.ACTIVE,
+        },
+      );
+      parameters.grantReadConfig(this.userMigrationLambda);
+      legacyUserStore.secret?.grantRead(this.userMigrationLambda);
+    }
 
     this.userPool = new cognito.UserPool(this, 'UserPool', {
       selfSignUpEnabled: false,
@@ -121,6 +365,8 @@ export class UserPool extends Construct {
         defineAuthChallenge: this.userPoolTriggerLambda,
         createAuthChallenge: this.userPoolTriggerLambda,
         verifyAuthChallengeResponse: this.userPoolTriggerLambda,
+        preTokenGeneration: this.preTokenGenerationLambda,
+        userMigration: this.userMigrationLambda,
       },
       // password policy should not be restrictive over character class usage
       // because passwords are randomly generated
@@ -138,6 +384,8 @@ export class UserPool extends Construct {
     this.userPoolClient = this.userPool.addClient('UserPoolClient', {
       authFlows: {
         custom: true,
+        // migrated users sign in with their legacy passwords
+        userPassword: legacyUserStore != null,
       },
       disableOAuth: true,
       preventUserExistenceErrors: true,
@@ -146,5 +394,10 @@ export class UserPool extends Construct {
       idTokenValidity: Duration.minutes(30),
       refreshTokenValidity: Duration.days(30),
     });
+    new cognito.CfnUserPoolGroup(this, 'AdminGroup', {
+      userPoolId: this.userPool.userPoolId,
+      groupName: this.adminGroupName,
+      description: 'Administrators who can access the admin API',
+    });
   }
 }
@@ -78,24 +78,17 @@ pub async fn start_register(
     Json(user_info): Json<NewUserInfo>,
 ) -> Result<impl IntoResponse, WebauthnError> {
     info!("start register");
-    let user_unique_id = {
-        let users_guard = app_state.users.lock().await;
-        users_guard
-            .name_to_id
-            .get(&user_info.username)
-            .copied()
-            .unwrap_or_else(Uuid::new_v4)
-    };
+    let user_unique_id = app_state.store
+        .find_user_id(&user_info.username)
+        .await?
+        .unwrap_or_else(Uuid::new_v4);
     session
         .remove::<(String, String, PasskeyRegistration)>(REG_STATE_KEY)
         .expect("failed to remove registration session");
-    let exclude_credentials = {
-        let users_guard = app_state.users.lock().await;
-        users_guard
-            .keys
-            .get(&user_unique_id)
-            .map(|keys| keys.iter().map(|sk| sk.cred_id().clone()).collect())
-    };
+    let exclude_credentials = app_state.store
+        .passkeys(user_unique_id)
+        .await?
+        .map(|keys| keys.iter().map(|sk| sk.cred_id().clone()).collect());
     let res = match app_state.webauthn.start_passkey_registration(
         user_unique_id,
         &user_info.username,
@@ -140,13 +133,9 @@ pub async fn finish_register(
         .finish_passkey_registration(&reg.public_key_credential, &reg_state)
     {
         Ok(sk) => {
-            let mut users_guard = app_state.users.lock().await;
-            users_guard
-                .keys
-                .entry(user_unique_id)
-                .and_modify(|keys| keys.push(sk.clone()))
-                .or_insert_with(|| vec![sk.clone()]);
-            users_guard.name_to_id.insert(username, user_unique_id);
+            app_state.store
+                .add_passkey(username, user_unique_id, sk)
+                .await?;
             StatusCode::OK
         }
         Err(e) => {
@@ -167,22 +156,19 @@ pub async fn start_authentication(
     session
         .remove::<UserPasskeyAuthentication>(AUTH_STATE_KEY)
         .expect("failed to remove authentication session");
-    let users_guard = app_state.users.lock().await;
-    let user_unique_id = users_guard
-        .name_to_id
-        .get(&user_info.username)
-        .copied()
+    let user_unique_id = app_state.store
+        .find_user_id(&user_info.username)
+        .await?
         .ok_or(WebauthnError::UserNotFound)?;
-    let allow_credentials = users_guard
-        .keys
-        .get(&user_unique_id)
+    let allow_credentials = app_state.store
+        .passkeys(user_unique_id)
+        .await?
         .ok_or(WebauthnError::UserHasNoCredentials)?;
     let res = match app_state
         .webauthn
-        .start_passkey_authentication(allow_credentials)
+        .start_passkey_authentication(&allow_credentials)
     {
         Ok((rcr, auth_state)) => {
-            drop(users_guard);
             session
                 .insert(AUTH_STATE_KEY, (Some(user_unique_id), auth_state))
                 .expect("failed to start authentication session");
@@ -238,29 +224,19 @@ pub async fn finish_authentication_for_anyone(
         .get_user_unique_id()
         .and_then(|id| Uuid::from_slice(id).ok())
         .ok_or(WebauthnError::BadRequest)?;
-    let credentials: Vec<DiscoverableKey> = {
-        let users_guard = app_state.users.lock().await;
-        users_guard
-            .keys
-            .get(&user_unique_id)
-            .map(|keys| keys.iter().map(|sk| sk.into()).collect())
-            .ok_or(WebauthnError::UserHasNoCredentials)?
-    };
+    let credentials: Vec<DiscoverableKey> = app_state.store
+        .passkeys(user_unique_id)
+        .await?
+        .map(|keys| keys.iter().map(|sk| sk.into()).collect())
+        .ok_or(WebauthnError::UserHasNoCredentials)?;
     let body = match app_state
         .webauthn
         .finish_discoverable_authentication(&auth, auth_state, &credentials)
     {
         Ok(auth_result) => {
-            let mut users_guard = app_state.users.lock().await;
-            users_guard
-                .keys
-                .get_mut(&user_unique_id)
-                .map(|keys| {
-                    keys.iter_mut().for_each(|sk| {
-                        sk.update_credential(&auth_result);
-                    })
-                })
-                .ok_or(WebauthnError::UserHasNoCredentials)?;
+            app_state.store
+                .update_passkeys(user_unique_id, &auth_result)
+                .await?;
             "{ \"message\": \"TODO: return username\" }"
         }
         Err(e) => {
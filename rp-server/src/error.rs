@@ -24,6 +24,12 @@ pub enum WebauthnError {
     /// Bad request.
     #[error("Bad request")]
     BadRequest,
+    /// Failure of the store.
+    #[error("Storage Error")]
+    Storage,
+    /// Malformed data in the store.
+    #[error("Corrupt Store")]
+    CorruptStore,
 }
 
 impl IntoResponse for WebauthnError {
@@ -44,6 +50,10 @@ impl IntoResponse for WebauthnError {
                 internal_server_error!("User Not Founde"),
             WebauthnError::BadRequest =>
                 (StatusCode::BAD_REQUEST, "Bad Request"),
+            WebauthnError::Storage =>
+                internal_server_error!("Storage Error"),
+            WebauthnError::CorruptStore =>
+                internal_server_error!("Corrupt Store"),
         }.into_response()
     }
 }
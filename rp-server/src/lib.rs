@@ -4,4 +4,6 @@
 
 pub mod auth;
 pub mod error;
+pub mod metrics;
 pub mod state;
+pub mod store;
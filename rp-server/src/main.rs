@@ -1,12 +1,19 @@
 //! Locally runs the server.
 //!
 //! You have to build the app before running this.
+//!
+//! Users and passkeys are kept in memory unless the `STORE` environment
+//! variable specifies a SQLite database; e.g., `STORE=sqlite:path.db`.
+//!
+//! Metrics are exposed at `/metrics` in the Prometheus text format; see
+//! [`rp_server::metrics`].
 
 use axum::{
     BoxError,
     Extension,
     Router,
     error_handling::HandleErrorLayer,
+    middleware,
     routing::{get, post},
 };
 use cookie::SameSite;
@@ -23,13 +30,16 @@ use rp_server::auth::{
     start_authentication_for_anyone,
     start_register,
 };
+use rp_server::metrics::{serve_metrics, track_requests};
 use rp_server::state::AppState;
+use rp_server::store::UserStore;
 
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
 
-    let app_state = AppState::new();
+    let store = UserStore::from_env().await.expect("failed to open the store");
+    let app_state = AppState::new(store);
 
     let session_store = MemoryStore::default();
     let session_service = ServiceBuilder::new()
@@ -48,10 +58,12 @@ async fn main() {
         .route("/registration/finish", post(finish_register))
         .route("/discoverable/start", post(start_authentication_for_anyone))
         .route("/discoverable/finish", post(finish_authentication_for_anyone))
-        .route("/login-start", post(start_authentication));
+        .route("/login-start", post(start_authentication))
+        .route_layer(middleware::from_fn(track_requests));
 
     let app = Router::new()
         .route("/", get(root))
+        .route("/metrics", get(serve_metrics))
         .nest("/auth", auth_routes)
         .nest_service(
             "/app",
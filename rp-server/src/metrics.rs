@@ -0,0 +1,212 @@
+//! Metrics in the Prometheus text format.
+//!
+//! `/metrics` exposes the following metrics, so that they can be scraped
+//! during load tests:
+//! - `http_requests_total`: number of requests to the authentication API by
+//!   `method`, `route`, and `status`
+//! - `http_request_duration_seconds`: histogram of the latencies of requests
+//!   to the authentication API by `method` and `route`
+//! - `store_operation_duration_seconds`: histogram of the latencies of
+//!   operations on the [`crate::store::UserStore`] by `operation`
+//!
+//! Metrics are kept in memory and reset when the server stops.
+
+use axum::{
+    extract::MatchedPath,
+    http::{Request, header::CONTENT_TYPE},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Content type of the Prometheus text format.
+pub const CONTENT_TYPE_TEXT: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Upper bounds of the histogram buckets in seconds.
+pub const BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+/// Returns the metrics of the server.
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::default)
+}
+
+// histogram of durations.
+#[derive(Clone, Debug)]
+struct Histogram {
+    // number of observations in each bucket; not cumulative.
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: vec![0; BUCKETS.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let secs = duration.as_secs_f64();
+        if let Some(i) = BUCKETS.iter().position(|le| secs <= *le) {
+            self.buckets[i] += 1;
+        }
+        self.sum += secs;
+        self.count += 1;
+    }
+
+    // writes the samples of the histogram with given labels.
+    fn write(&self, out: &mut String, name: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (le, count) in BUCKETS.iter().zip(self.buckets.iter()) {
+            cumulative += count;
+            let _ = writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, le, cumulative);
+        }
+        let _ = writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, self.count);
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, self.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, self.count);
+    }
+}
+
+/// Metrics of the server.
+#[derive(Debug, Default)]
+pub struct Metrics {
+    // (method, route, status) → count
+    requests: Mutex<BTreeMap<(String, String, u16), u64>>,
+    // (method, route) → latencies
+    request_durations: Mutex<BTreeMap<(String, String), Histogram>>,
+    // operation → latencies
+    store_durations: Mutex<BTreeMap<&'static str, Histogram>>,
+}
+
+impl Metrics {
+    /// Records a request.
+    pub fn request(&self, method: &str, route: &str, status: u16, latency: Duration) {
+        *self.requests
+            .lock()
+            .expect("metrics lock poisoned")
+            .entry((method.into(), route.into(), status))
+            .or_default() += 1;
+        self.request_durations
+            .lock()
+            .expect("metrics lock poisoned")
+            .entry((method.into(), route.into()))
+            .or_default()
+            .observe(latency);
+    }
+
+    /// Records the latency of a store operation.
+    pub fn store_latency(&self, operation: &'static str, latency: Duration) {
+        self.store_durations
+            .lock()
+            .expect("metrics lock poisoned")
+            .entry(operation)
+            .or_default()
+            .observe(latency);
+    }
+
+    /// Starts timing a store operation.
+    ///
+    /// The latency is recorded when the returned timer is dropped.
+    pub fn time_store(&'static self, operation: &'static str) -> StoreTimer {
+        StoreTimer {
+            metrics: self,
+            operation,
+            started_at: Instant::now(),
+        }
+    }
+
+    /// Renders the metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP http_requests_total Number of HTTP requests.\n");
+        out.push_str("# TYPE http_requests_total counter\n");
+        for ((method, route, status), count) in self.requests
+            .lock()
+            .expect("metrics lock poisoned")
+            .iter()
+        {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                escape(method),
+                escape(route),
+                status,
+                count,
+            );
+        }
+        out.push_str("# HELP http_request_duration_seconds Latency of HTTP requests.\n");
+        out.push_str("# TYPE http_request_duration_seconds histogram\n");
+        for ((method, route), histogram) in self.request_durations
+            .lock()
+            .expect("metrics lock poisoned")
+            .iter()
+        {
+            let labels = format!("method=\"{}\",route=\"{}\"", escape(method), escape(route));
+            histogram.write(&mut out, "http_request_duration_seconds", &labels);
+        }
+        out.push_str("# HELP store_operation_duration_seconds Latency of store operations.\n");
+        out.push_str("# TYPE store_operation_duration_seconds histogram\n");
+        for (operation, histogram) in self.store_durations
+            .lock()
+            .expect("metrics lock poisoned")
+            .iter()
+        {
+            let labels = format!("operation=\"{}\"", escape(operation));
+            histogram.write(&mut out, "store_operation_duration_seconds", &labels);
+        }
+        out
+    }
+}
+
+/// Timer of a store operation.
+///
+/// Created by [`Metrics::time_store`].
+pub struct StoreTimer {
+    metrics: &'static Metrics,
+    operation: &'static str,
+    started_at: Instant,
+}
+
+impl Drop for StoreTimer {
+    fn drop(&mut self) {
+        self.metrics.store_latency(self.operation, self.started_at.elapsed());
+    }
+}
+
+/// Middleware that records requests.
+///
+/// Must be added with `route_layer`, so that the route is the matched path;
+/// e.g., "/auth/registration/start".
+pub async fn track_requests<B>(request: Request<B>, next: Next<B>) -> Response {
+    let method = request.method().to_string();
+    let route = request.extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", |path| path.as_str())
+        .to_string();
+    let started_at = Instant::now();
+    let res = next.run(request).await;
+    metrics().request(&method, &route, res.status().as_u16(), started_at.elapsed());
+    res
+}
+
+/// Serves the metrics in the Prometheus text format.
+pub async fn serve_metrics() -> impl IntoResponse {
+    ([(CONTENT_TYPE, CONTENT_TYPE_TEXT)], metrics().render())
+}
+
+// escapes a label value.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
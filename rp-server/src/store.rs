@@ -0,0 +1,211 @@
+//! Store of users and passkeys.
+
+use sqlx::{
+    SqlitePool,
+    sqlite::SqliteConnectOptions,
+};
+use std::collections::HashMap;
+use std::str::FromStr;
+use tokio::sync::Mutex;
+use tracing::error;
+use webauthn_rs::prelude::{AuthenticationResult, Passkey, Uuid};
+
+use crate::error::WebauthnError;
+use crate::metrics::metrics;
+
+/// Prefix of the `STORE` environment variable for SQLite.
+pub const SQLITE_PREFIX: &str = "sqlite:";
+
+/// Store of users and passkeys.
+pub enum UserStore {
+    /// In memory; lost when the server stops.
+    Memory(Mutex<Data>),
+    /// SQLite database.
+    Sqlite(SqlitePool),
+}
+
+/// User data in memory.
+#[derive(Default)]
+pub struct Data {
+    /// Map from usernames to IDs.
+    pub name_to_id: HashMap<String, Uuid>,
+    /// Registered keys.
+    pub keys: HashMap<Uuid, Vec<Passkey>>,
+}
+
+impl UserStore {
+    /// Creates an empty store in memory.
+    pub fn memory() -> Self {
+        Self::Memory(Mutex::new(Data::default()))
+    }
+
+    /// Opens a SQLite database; e.g., `sqlite:path.db`.
+    ///
+    /// Creates the database and the tables if they do not exist.
+    pub async fn sqlite(url: &str) -> Result<Self, sqlx::Error> {
+        let options = SqliteConnectOptions::from_str(url)?
+            .create_if_missing(true);
+        let pool = SqlitePool::connect_with(options).await?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS users (username TEXT PRIMARY KEY, user_id TEXT NOT NULL UNIQUE)",
+        )
+            .execute(&pool)
+            .await?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS passkeys (user_id TEXT NOT NULL, credential_id TEXT NOT NULL, passkey TEXT NOT NULL, PRIMARY KEY (user_id, credential_id))",
+        )
+            .execute(&pool)
+            .await?;
+        Ok(Self::Sqlite(pool))
+    }
+
+    /// Opens the store specified by the `STORE` environment variable.
+    ///
+    /// `STORE` may be `memory` or `sqlite:` followed by the path to the
+    /// database file; e.g., `sqlite:path.db`. Defaults to `memory`.
+    pub async fn from_env() -> Result<Self, String> {
+        match std::env::var("STORE").as_deref() {
+            Err(_) | Ok("memory") => Ok(Self::memory()),
+            Ok(url) if url.starts_with(SQLITE_PREFIX) => Self::sqlite(url)
+                .await
+                .map_err(|e| format!("failed to open {}: {}", url, e)),
+            Ok(store) => Err(format!("unsupported STORE: {}", store)),
+        }
+    }
+
+    /// Returns the ID of a user.
+    pub async fn find_user_id(&self, username: &str) -> Result<Option<Uuid>, WebauthnError> {
+        let _timer = metrics().time_store("find_user_id");
+        match self {
+            Self::Memory(data) => Ok(data.lock().await.name_to_id.get(username).copied()),
+            Self::Sqlite(pool) => {
+                let user_id: Option<String> = sqlx::query_scalar(
+                    "SELECT user_id FROM users WHERE username = ?",
+                )
+                    .bind(username)
+                    .fetch_optional(pool)
+                    .await
+                    .map_err(storage_error)?;
+                user_id
+                    .map(|id| Uuid::parse_str(&id).or(Err(WebauthnError::CorruptStore)))
+                    .transpose()
+            }
+        }
+    }
+
+    /// Returns the passkeys of a user.
+    ///
+    /// Returns `None` if the user has no passkeys.
+    pub async fn passkeys(&self, user_id: Uuid) -> Result<Option<Vec<Passkey>>, WebauthnError> {
+        let _timer = metrics().time_store("passkeys");
+        match self {
+            Self::Memory(data) => Ok(data.lock().await.keys.get(&user_id).cloned()),
+            Self::Sqlite(pool) => {
+                let passkeys: Vec<String> = sqlx::query_scalar(
+                    "SELECT passkey FROM passkeys WHERE user_id = ?",
+                )
+                    .bind(user_id.to_string())
+                    .fetch_all(pool)
+                    .await
+                    .map_err(storage_error)?;
+                if passkeys.is_empty() {
+                    return Ok(None);
+                }
+                passkeys.iter()
+                    .map(|p| serde_json::from_str(p).or(Err(WebauthnError::CorruptStore)))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(Some)
+            }
+        }
+    }
+
+    /// Adds a passkey to a user, and creates the user if necessary.
+    pub async fn add_passkey(
+        &self,
+        username: String,
+        user_id: Uuid,
+        passkey: Passkey,
+    ) -> Result<(), WebauthnError> {
+        let _timer = metrics().time_store("add_passkey");
+        match self {
+            Self::Memory(data) => {
+                let mut data = data.lock().await;
+                data.keys.entry(user_id).or_default().push(passkey);
+                data.name_to_id.insert(username, user_id);
+                Ok(())
+            }
+            Self::Sqlite(pool) => {
+                let mut tx = pool.begin().await.map_err(storage_error)?;
+                sqlx::query("INSERT OR REPLACE INTO users (username, user_id) VALUES (?, ?)")
+                    .bind(username)
+                    .bind(user_id.to_string())
+                    .execute(&mut *tx)
+                    .await
+                    .map_err(storage_error)?;
+                insert_passkey(&mut tx, user_id, &passkey, false).await?;
+                tx.commit().await.map_err(storage_error)
+            }
+        }
+    }
+
+    /// Updates the passkeys of a user with an authentication result.
+    pub async fn update_passkeys(
+        &self,
+        user_id: Uuid,
+        auth_result: &AuthenticationResult,
+    ) -> Result<(), WebauthnError> {
+        let _timer = metrics().time_store("update_passkeys");
+        match self {
+            Self::Memory(data) => {
+                data.lock()
+                    .await
+                    .keys
+                    .get_mut(&user_id)
+                    .map(|keys| keys.iter_mut().for_each(|sk| {
+                        sk.update_credential(auth_result);
+                    }))
+                    .ok_or(WebauthnError::UserHasNoCredentials)
+            }
+            Self::Sqlite(pool) => {
+                let mut passkeys = self.passkeys(user_id)
+                    .await?
+                    .ok_or(WebauthnError::UserHasNoCredentials)?;
+                let mut tx = pool.begin().await.map_err(storage_error)?;
+                for passkey in passkeys.iter_mut() {
+                    if passkey.update_credential(auth_result).is_some_and(|b| b) {
+                        insert_passkey(&mut tx, user_id, passkey, true).await?;
+                    }
+                }
+                tx.commit().await.map_err(storage_error)
+            }
+        }
+    }
+}
+
+// inserts or replaces a passkey in a transaction.
+async fn insert_passkey(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    user_id: Uuid,
+    passkey: &Passkey,
+    replace: bool,
+) -> Result<(), WebauthnError> {
+    let statement = if replace {
+        "INSERT OR REPLACE INTO passkeys (user_id, credential_id, passkey) VALUES (?, ?, ?)"
+    } else {
+        "INSERT INTO passkeys (user_id, credential_id, passkey) VALUES (?, ?, ?)"
+    };
+    sqlx::query(statement)
+        .bind(user_id.to_string())
+        .bind(passkey.cred_id().to_string())
+        .bind(serde_json::to_string(passkey).or(Err(WebauthnError::Unknown))?)
+        .execute(&mut **tx)
+        .await
+        .map_err(storage_error)?;
+    Ok(())
+}
+
+// logs a storage error.
+fn storage_error(e: sqlx::Error) -> WebauthnError {
+    error!("storage error: {:?}", e);
+    WebauthnError::Storage
+}